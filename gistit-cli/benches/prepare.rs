@@ -0,0 +1,126 @@
+//! How much of the node handshake `gistit send` hides behind its file work
+//!
+//! Preparing an encrypted send cuts, scans and hashes the file while the node is asked whether it
+//! hosts encrypted gistits. This runs the `gistit` binary against a stand-in node that answers the
+//! handshake right away, then after [`NODE_WAIT`]. Done one after the other, the second run takes
+//! the whole wait longer; overlapped, the file work hides part of it.
+//!
+//! ```shell
+//! cargo bench -p gistit --bench prepare
+//! ```
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use assert_fs::TempDir;
+
+use gistit_proto::ipc::instruction::provide_response::Status;
+use gistit_proto::ipc::instruction::{Kind, ProvideRequest, StatusResponse};
+use gistit_proto::Instruction;
+
+/// Lines of the file a snippet is cut from, about 8 MB. `--range` and `--symbol` allow large ones
+const SOURCE_LINES: usize = 200_000;
+
+/// How long the stand-in node takes to answer the handshake
+const NODE_WAIT: Duration = Duration::from_millis(150);
+
+const RUNS: u32 = 10;
+
+/// Answers the handshake after `wait` milliseconds and provides whatever it's sent, like a node
+/// that hosts encrypted gistits
+fn node(runtime: &Path, wait: Arc<AtomicU64>) {
+    let runtime = runtime.to_path_buf();
+    thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime to build");
+        rt.block_on(async move {
+            let mut bridge = gistit_ipc::server(&runtime).expect("node socket to bind");
+            loop {
+                let instruction = bridge.recv().await.expect("instruction to be received");
+                let response = match instruction.kind {
+                    Some(Kind::StatusRequest(_)) => {
+                        let wait = Duration::from_millis(wait.load(Ordering::Relaxed));
+                        tokio::time::sleep(wait).await;
+                        Instruction::respond_status(StatusResponse::default()).with_hello()
+                    }
+                    Some(Kind::ProvideRequest(ProvideRequest {
+                        gistit: Some(gistit),
+                        ..
+                    })) => Instruction::respond_provide(
+                        Status::Accepted,
+                        gistit.hash.parse().expect("hash to be valid"),
+                    ),
+                    _ => continue,
+                };
+                bridge.connect().await.expect("client socket to be up");
+                bridge.send(response).await.expect("response to be sent");
+            }
+        });
+    });
+}
+
+fn mean(send: &mut Command) -> Duration {
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        let output = send.output().expect("gistit to run");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    started.elapsed() / RUNS
+}
+
+fn main() {
+    let dir = TempDir::new().expect("temp dir to be created");
+    let runtime = dir.path().join("runtime");
+    fs::create_dir(&runtime).expect("runtime dir to be created");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&runtime, fs::Permissions::from_mode(0o700))
+            .expect("runtime dir to be private");
+    }
+    let path = dir.path().join("handlers.rs");
+    let source = (0..SOURCE_LINES).fold(String::new(), |mut source, index| {
+        source.push_str(&format!(
+            "pub fn handler_{}() -> usize {{\n    {}\n}}\n",
+            index, index
+        ));
+        source
+    });
+    fs::write(&path, source).expect("source to be written");
+
+    let wait = Arc::new(AtomicU64::new(0));
+    node(&runtime, Arc::clone(&wait));
+
+    let mut send = Command::new(env!("CARGO_BIN_EXE_gistit"));
+    send.arg(&path)
+        .args(["--symbol", "handler_199999", "--encrypt", "--quiet"])
+        .env("GISTIT_RUNTIME", &runtime)
+        .env("GISTIT_CONFIG", dir.path().join("config"))
+        .env("GISTIT_DATA", dir.path().join("data"))
+        .env("GISTIT_SECRET", "benchmark");
+    // Warm the page cache so both runs read from memory
+    mean(&mut send);
+
+    let answered = mean(&mut send);
+    wait.store(NODE_WAIT.as_millis() as u64, Ordering::Relaxed);
+    let waited = mean(&mut send);
+    let hidden = (answered + NODE_WAIT).saturating_sub(waited);
+
+    println!("node answers at once      {:>8.2?}", answered);
+    println!("node answers after {:>4.0?} {:>8.2?}", NODE_WAIT, waited);
+    println!(
+        "hidden by the file work   {:>8.2?} per send, {:.0}% of the wait",
+        hidden,
+        100.0 * hidden.as_secs_f64() / NODE_WAIT.as_secs_f64()
+    );
+}
@@ -0,0 +1,65 @@
+//! The account module
+//!
+//! Manages the GitHub account linked to gistit (`gistit github ...`).
+use async_trait::async_trait;
+use clap::ArgMatches;
+
+use crate::dispatch::Dispatch;
+use crate::github::Oauth;
+use crate::keychain;
+use crate::{finish, progress, updateln, warnln, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub logout: bool,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            logout: matches!(args.subcommand(), Some(("logout", _))),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    oauth: Oauth,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        if !self.logout {
+            return Err(Error::Argument("missing subcommand", "logout"));
+        }
+
+        progress!("Preparing");
+        let oauth = Oauth::new()?;
+        updateln!("Prepared");
+
+        Ok(Config { oauth })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        if let Some(token) = config.oauth.token() {
+            progress!("Revoking");
+            if let Err(err) = crate::github::revoke(token).await {
+                warnln!("{}, deleting the local token anyway", err);
+            } else {
+                updateln!("Revoked");
+            }
+        } else {
+            warnln!("no github token stored");
+        }
+
+        keychain::delete()?;
+        updateln!("Logged out");
+        finish!("");
+        Ok(())
+    }
+}
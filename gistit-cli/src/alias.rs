@@ -0,0 +1,139 @@
+//! The alias module
+//!
+//! Expands the aliases and default command from the settings before clap sees the command line.
+//! Only the first argument is looked up, and built in commands always win over an alias of the
+//! same name. Sending has no subcommand of its own, so aliases spell it `send`.
+use std::collections::HashSet;
+use std::ffi::OsString;
+
+use gistit_project::settings::Settings;
+
+use crate::{arg, Error, Result};
+
+/// Stands for the top level send action in aliases
+const SEND: &str = "send";
+
+/// Every subcommand name and alias clap knows about
+fn builtins() -> Vec<String> {
+    let app = arg::app();
+    app.get_subcommands()
+        .flat_map(|cmd| std::iter::once(cmd.get_name()).chain(cmd.get_all_aliases()))
+        .chain(std::iter::once("help"))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Expands the command line, `args` includes the binary name
+///
+/// # Errors
+///
+/// Fails if an alias can't be split into arguments, expands into itself, or the default command
+/// is unknown
+pub fn expand(args: Vec<OsString>, settings: &Settings) -> Result<Vec<OsString>> {
+    expand_with(args, settings, &builtins())
+}
+
+fn expand_with(
+    mut args: Vec<OsString>,
+    settings: &Settings,
+    builtins: &[String],
+) -> Result<Vec<OsString>> {
+    let is_known = |name: &str| {
+        name == SEND
+            || builtins.iter().any(|builtin| builtin == name)
+            || settings.alias.contains_key(name)
+    };
+
+    let mut expanded = false;
+    if args.len() == 1 {
+        if let Some(default) = &settings.default_command {
+            if !is_known(default) {
+                return Err(Error::Argument(
+                    "not a gistit command or alias",
+                    "default-command",
+                ));
+            }
+            args.push(default.into());
+            expanded = true;
+        }
+    }
+
+    let mut seen = HashSet::new();
+    while let Some(name) = args
+        .get(1)
+        .and_then(|arg| arg.to_str())
+        .map(ToOwned::to_owned)
+    {
+        // A file named `send` is still sent as usual
+        if name == SEND && expanded {
+            args.remove(1);
+            break;
+        }
+
+        let expansion = match settings.alias.get(&name) {
+            Some(expansion) if !builtins.contains(&name) => expansion,
+            _ => break,
+        };
+        if !seen.insert(name) {
+            return Err(Error::Argument("alias expands into itself", "alias"));
+        }
+
+        let words = shell_words::split(expansion)
+            .map_err(|_| Error::Argument("alias has unbalanced quotes", "alias"))?;
+        args.splice(1..2, words.into_iter().map(OsString::from));
+        expanded = true;
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str], settings: &Settings) -> Result<Vec<String>> {
+        let builtins = vec!["list".to_owned(), "fetch".to_owned()];
+        let args = args.iter().map(OsString::from).collect();
+        Ok(expand_with(args, settings, &builtins)?
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn alias_expand_and_default_command() {
+        let mut settings = Settings::default();
+        settings
+            .alias
+            .insert("s".to_owned(), "send --author 'John Doe'".to_owned());
+        settings
+            .alias
+            .insert("rs".to_owned(), "list --lang rust".to_owned());
+        settings.alias.insert("list".to_owned(), "fetch".to_owned());
+        settings.alias.insert("loop".to_owned(), "loop".to_owned());
+
+        assert_eq!(
+            run(&["gistit", "s", "main.rs"], &settings).unwrap(),
+            vec!["gistit", "--author", "John Doe", "main.rs"]
+        );
+        // Built in commands win, and a plain `send` is a file name
+        assert_eq!(
+            run(&["gistit", "list"], &settings).unwrap(),
+            vec!["gistit", "list"]
+        );
+        assert_eq!(
+            run(&["gistit", "send"], &settings).unwrap(),
+            vec!["gistit", "send"]
+        );
+        assert!(run(&["gistit", "loop"], &settings).is_err());
+
+        assert_eq!(run(&["gistit"], &settings).unwrap(), vec!["gistit"]);
+        settings.default_command = Some("rs".to_owned());
+        assert_eq!(
+            run(&["gistit"], &settings).unwrap(),
+            vec!["gistit", "list", "--lang", "rust"]
+        );
+        settings.default_command = Some("nope".to_owned());
+        assert!(run(&["gistit"], &settings).is_err());
+    }
+}
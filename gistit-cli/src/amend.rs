@@ -0,0 +1,235 @@
+//! The amend module
+//!
+//! `gistit amend <hash>` opens the main file of a gistit we sent in the editor and republishes
+//! it once saved. Gistits sent from our other machines count when history is synced with them.
+//! Gistits are immutable, the amended one gets a hash of its own and points back to the old one
+//! with `previous_hash`, covered by the hash, so whoever fetches it can follow the chain. Other
+//! files, the author, the description and how it's shared are kept as they were.
+use std::fs;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_project::path;
+use gistit_proto::hash::{self, Namespace};
+use gistit_proto::ipc::instruction::provide_response::Status;
+use gistit_proto::{ipc, Gistit, GistitBuilder, GistitHash, Instruction};
+
+use crate::config;
+use crate::dispatch::Dispatch;
+use crate::fetch::{decode_binary, fetch};
+use crate::file::File;
+use crate::fmt::emoji;
+use crate::history::{self, Origin};
+use crate::interrupt;
+use crate::resolve;
+use crate::send::load_to_server;
+use crate::server::web_url;
+use crate::{finish, progress, updateln, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub hash: &'static str,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            hash: args
+                .value_of("HASH")
+                .ok_or(Error::Argument("missing argument", "[HASH]"))?,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    hash: GistitHash,
+    namespace: Option<Namespace>,
+    runtime_path: PathBuf,
+}
+
+/// The amended version of `old`, its main file replaced by `edited`
+///
+/// # Errors
+///
+/// Fails if a binary file is corrupted or the edited file breaks the payload rules
+fn amended(old: &Gistit, edited: String) -> Result<Gistit> {
+    let mut builder = GistitBuilder::new(old.author.clone())
+        .description(old.description.as_deref())
+        .public(old.public)
+        .private(old.private)
+        .burn_after_read(old.burn_after_read)
+        .previous_hash(old.hash.clone());
+
+    let mut edited = Some(edited);
+    for inner in &old.inner {
+        builder = if inner.is_description {
+            builder.description_file(inner.name.clone(), inner.data.clone())
+        } else if inner.is_binary {
+            builder.binary_file(
+                inner.name.clone(),
+                inner.lang.clone(),
+                &decode_binary(old, inner)?,
+            )
+        } else {
+            // The first text file is the main one, the one we edited
+            let data = edited.take().unwrap_or_else(|| inner.data.clone());
+            // Snippets still start where they did, editing may move their end
+            let snippet = inner.snippet.clone().map(|mut snippet| {
+                #[allow(clippy::cast_possible_truncation)]
+                let lines = data.lines().count().max(1) as u32;
+                snippet.end_line = snippet.start_line + lines - 1;
+                snippet
+            });
+            let builder = builder.file(inner.name.clone(), inner.lang.clone(), data);
+            match snippet {
+                Some(snippet) => builder.snippet(snippet),
+                None => builder,
+            }
+        };
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Hosts the gistit with the node if it's running, on the server otherwise. Returns its hash
+/// tagged with where it went
+async fn republish(gistit: &Gistit, config: &Config) -> Result<String> {
+    let mut bridge = gistit_ipc::client(&config.runtime_path)?;
+    if !bridge.alive() {
+        return Ok(Namespace::Server.tag(&load_to_server(gistit).await?));
+    }
+
+    bridge.connect().await?;
+    bridge
+        .send(Instruction::request_provide(gistit.clone(), 0))
+        .await?;
+
+    match interrupt::bounded("gistit node", bridge.recv())
+        .await?
+        .expect_response()?
+    {
+        ipc::instruction::Kind::ProvideResponse(ipc::instruction::ProvideResponse {
+            hash: Some(hash),
+            ..
+        }) => Ok(Namespace::Peer.tag(&hash)),
+        ipc::instruction::Kind::ProvideResponse(refused) => Err(Error::Refused(
+            refused.status(),
+            refused.reason.unwrap_or_default(),
+        )),
+        ipc::instruction::Kind::ErrorResponse(err) => Err(Error::Daemon(err)),
+        _ => Err(Error::Refused(
+            Status::Unspecified,
+            "unexpected response from gistit node".to_owned(),
+        )),
+    }
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let runtime_path = path::runtime()?;
+        let (hash, namespace) = resolve::hash(self.hash, &runtime_path, None).await?;
+        if !history::is_sent(&hash)? {
+            return Err(Error::Argument(
+                "only gistits you sent can be amended, see `gistit list`",
+                "[HASH]",
+            ));
+        }
+        updateln!("Prepared");
+
+        Ok(Config {
+            hash,
+            namespace,
+            runtime_path,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        let old = fetch(&config.hash, &config.runtime_path, config.namespace, None).await?;
+        if old.is_encrypted() {
+            return Err(Error::Argument(
+                "encrypted gistits can't be amended, send a new one",
+                "[HASH]",
+            ));
+        }
+        let main = old
+            .inner
+            .iter()
+            .find(|inner| !inner.is_description && !inner.is_binary)
+            .ok_or(Error::Argument(
+                "gistit has no text file to amend",
+                "[HASH]",
+            ))?;
+
+        progress::clear();
+        let file = File::from_data(&main.data, &main.name)?;
+        config::edit(file.path())?;
+        let edited = fs::read_to_string(file.path())?;
+        if edited == main.data {
+            finish!(format!("{}Nothing changed, not amended", emoji("✋  ", "")));
+            return Ok(());
+        }
+
+        progress!("Amending");
+        let gistit = amended(&old, edited)?;
+        let tagged = republish(&gistit, &config).await?;
+        let on_server = hash::split(&tagged).0 == Some(Namespace::Server);
+        history::record(&gistit, Origin::Sent, on_server);
+        updateln!("Amended");
+
+        finish!(format!(
+            "\n    hash: '{}'\n    amends: '{}'\n    url: '{}'\n\n",
+            style(&tagged).bold(),
+            style(&old.hash).dim(),
+            web_url(&gistit.hash)
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amend_replaces_main_file_only() {
+        let old = GistitBuilder::new("fabricio")
+            .description(Some("prints hello"))
+            .description_file("README.md", "# Hello\n\nPrints hello, politely.".to_owned())
+            .file(
+                "main.rs",
+                "rust",
+                "fn main() { println!(\"hello\"); }".to_owned(),
+            )
+            .file(
+                "Cargo.toml",
+                "toml",
+                "[package]\nname = \"hello\"".to_owned(),
+            )
+            .public(true)
+            .burn_after_read(true)
+            .build()
+            .unwrap();
+
+        let edited = "fn main() { println!(\"hello, world\"); }".to_owned();
+        let new = amended(&old, edited.clone()).unwrap();
+        assert_ne!(new.hash, old.hash);
+        assert_eq!(new.previous_hash(), Some(old.hash.as_str()));
+        assert!(new.is_intact() && new.public && new.burn_after_read);
+        assert_eq!(new.description, old.description);
+        assert_eq!(new.inner[0].data, edited);
+        assert_eq!(new.inner[1], old.inner[1]);
+        assert!(new.inner[2].is_description);
+
+        assert!(amended(&old, "short".to_owned()).is_err());
+    }
+}
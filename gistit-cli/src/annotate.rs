@@ -0,0 +1,467 @@
+//! The annotate module
+//!
+//! `gistit annotate <hash> --line 42 --message "..."` leaves a note on a line of a gistit file,
+//! code review style. Annotations of server hosted gistits are kept by the server next to the
+//! gistit, so everyone fetching it sees them. The server signs them with the GitHub user
+//! annotating. Every annotation seen is also kept in the data directory, which is all there is
+//! for gistits hosted by peers. Previews mark annotated lines and list the notes under the file.
+//!
+//! The server is only asked about gistits known to be there, see [`on_server`], so the hashes of
+//! gistits hosted by peers don't reach it.
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+use reqwest::StatusCode;
+
+use gistit_project::settings::Settings;
+use gistit_project::{file, path, vault};
+use gistit_proto::hash::Namespace;
+use gistit_proto::{Annotation, Annotations, GistitHash};
+
+use crate::dispatch::Dispatch;
+use crate::fmt::{ago, emoji, printable, structured};
+use crate::github::{self, Token};
+use crate::history;
+use crate::http;
+use crate::keychain;
+use crate::param::check;
+use crate::resolve;
+use crate::server::{SERVER_URL_ANNOTATE, SERVER_URL_ANNOTATIONS};
+use crate::{cache, finish, progress, updateln, warnln, Error, Result};
+
+const ANNOTATIONS_FILE: &str = "annotations.json";
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub hash: &'static str,
+    pub line: Option<&'static str>,
+    pub message: Option<&'static str>,
+    pub file: Option<&'static str>,
+    pub author: Option<&'static str>,
+    pub author_setting: Option<&'static str>,
+    pub list: bool,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+        settings: &'static Settings,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            hash: args
+                .value_of("HASH")
+                .ok_or(Error::Argument("missing argument", "[HASH]"))?,
+            line: args.value_of("line"),
+            message: args.value_of("message"),
+            file: args.value_of("file"),
+            author: args.value_of("author"),
+            author_setting: settings.author.as_deref(),
+            list: args.is_present("list"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    hash: GistitHash,
+    namespace: Option<Namespace>,
+    /// `None` when listing
+    annotation: Option<Annotation>,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let (hash, namespace) = resolve::hash(self.hash, &path::runtime()?, None).await?;
+        if self.list {
+            updateln!("Prepared");
+            return Ok(Config {
+                hash,
+                namespace,
+                annotation: None,
+            });
+        }
+
+        let line = check::annotation_line(
+            self.line
+                .ok_or(Error::Argument("missing argument", "--line"))?,
+        )?;
+        let file = file_name(&hash, self.file, line)?;
+        let (author, _) = check::author(self.author, self.author_setting)?;
+        let annotation = Annotation {
+            hash: hash.to_string(),
+            file,
+            line,
+            message: check::annotation_message(
+                self.message
+                    .ok_or(Error::Argument("missing argument", "--message"))?,
+            )?
+            .to_owned(),
+            author,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis())
+                .to_string(),
+        };
+        annotation.validate().map_err(gistit_proto::Error::from)?;
+        updateln!("Prepared");
+
+        Ok(Config {
+            hash,
+            namespace,
+            annotation: Some(annotation),
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        let annotation = match config.annotation {
+            Some(annotation) => annotation,
+            None => {
+                progress!("Listing");
+                let annotations = sync(&config.hash, config.namespace).await?;
+                updateln!("Listed");
+                return print(&annotations);
+            }
+        };
+
+        if on_server(&config.hash, config.namespace) {
+            let token = github::authorized_token().await?;
+            progress!("Annotating");
+            match annotate_on_server(&annotation, &token).await? {
+                Some(annotations) => {
+                    remember(&annotations);
+                    updateln!("Annotated on the server");
+                }
+                None if config.namespace == Some(Namespace::Server) => {
+                    return Err(Error::NotFound("gistit hash not found"));
+                }
+                None => {
+                    remember(std::slice::from_ref(&annotation));
+                    updateln!("Kept locally, the gistit isn't on the server anymore");
+                }
+            }
+        } else {
+            progress!("Annotating");
+            remember(std::slice::from_ref(&annotation));
+            updateln!("Kept locally, only gistits known to be on the server are annotated there");
+        }
+        finish!(format!(
+            "{}Annotated {}:{}",
+            emoji("📌  ", ""),
+            annotation.file,
+            annotation.line
+        ));
+        Ok(())
+    }
+}
+
+/// The file an annotation goes on: the one named, checked against the cached gistit if there
+/// is one, otherwise its main file
+fn file_name(hash: &str, name: Option<&str>, line: u32) -> Result<String> {
+    let gistit = match (cache::load(hash), name) {
+        (Some(gistit), _) => gistit,
+        (None, Some(name)) => return Ok(name.to_owned()),
+        (None, None) => {
+            return Err(Error::Argument(
+                "gistit isn't cached here, fetch it first or name the file",
+                "--file",
+            ))
+        }
+    };
+    let inner = match name {
+        Some(name) => gistit
+            .inner
+            .iter()
+            .find(|inner| inner.name == name)
+            .ok_or(Error::Argument("no such file in the gistit", "--file"))?,
+        None => gistit
+            .inner
+            .iter()
+            .find(|inner| !inner.is_description)
+            .ok_or(Error::Argument(
+                "the gistit has no file to annotate",
+                "--file",
+            ))?,
+    };
+    if inner.is_binary || line as usize > inner.data.lines().count() {
+        return Err(Error::Argument("no such line in the file", "--line"));
+    }
+    Ok(inner.name.clone())
+}
+
+/// Whether the server may be asked about the gistit: its hash says it's hosted there, or the
+/// history knows it is
+fn on_server(hash: &str, namespace: Option<Namespace>) -> bool {
+    match namespace {
+        Some(namespace) => namespace == Namespace::Server,
+        None => history::on_server(hash),
+    }
+}
+
+/// Sends the annotation to the server, signed by the GitHub user of `token`. Answered with every
+/// annotation of the gistit, `None` if the server doesn't have the gistit
+async fn annotate_on_server(
+    annotation: &Annotation,
+    token: &Token,
+) -> Result<Option<Vec<Annotation>>> {
+    let client = http::Client::new();
+    let response = client
+        .send(http::with_payload(
+            client
+                .post(SERVER_URL_ANNOTATE.to_string())
+                .header("authorization", format!("token {}", token.access_token)),
+            annotation,
+        )?)
+        .await?;
+
+    match response.status() {
+        StatusCode::OK => Ok(Some(
+            http::payload_from::<Annotations>(response)
+                .await?
+                .annotations,
+        )),
+        StatusCode::NOT_FOUND => Ok(None),
+        StatusCode::UNAUTHORIZED => Err(Error::OAuth("github rejected the token".to_owned())),
+        StatusCode::FORBIDDEN => Err(Error::Server("the gistit can't take more annotations")),
+        StatusCode::TOO_MANY_REQUESTS => {
+            Err(Error::Server("too many annotations, try again in an hour"))
+        }
+        _ => Err(Error::Server("unexpected response")),
+    }
+}
+
+/// Annotations the server has for the gistit, none if it doesn't have it
+async fn from_server(hash: &str) -> Result<Vec<Annotation>> {
+    let client = http::Client::new();
+    let query = Annotation {
+        hash: hash.to_owned(),
+        ..Annotation::default()
+    };
+    let response = client
+        .send(http::with_payload(
+            client.post(SERVER_URL_ANNOTATIONS.to_string()),
+            &query,
+        )?)
+        .await?;
+
+    match response.status() {
+        StatusCode::OK => Ok(http::payload_from::<Annotations>(response)
+            .await?
+            .annotations),
+        StatusCode::NOT_FOUND => Ok(Vec::new()),
+        _ => Err(Error::Server("unexpected response")),
+    }
+}
+
+/// Keeps the server's annotations of the gistit, if it's known to be there, and returns every
+/// annotation known here
+///
+/// # Errors
+///
+/// Fails if the server can't be reached
+pub async fn sync(hash: &str, namespace: Option<Namespace>) -> Result<Vec<Annotation>> {
+    if on_server(hash, namespace) {
+        remember(&from_server(hash).await?);
+    }
+    Ok(local(hash))
+}
+
+fn annotations_file() -> Result<PathBuf> {
+    Ok(path::data()?.join(ANNOTATIONS_FILE))
+}
+
+/// Reads every annotation kept here, oldest first. Sealed like the history when the vault is
+/// enabled
+///
+/// # Errors
+///
+/// Fails if the annotations file exists but can't be read, unsealed or parsed
+pub fn load() -> Result<Vec<Annotation>> {
+    let file = annotations_file()?;
+    if fs::metadata(&file).is_err() {
+        return Ok(Vec::new());
+    }
+
+    let mut bytes = fs::read(file)?;
+    if vault::is_sealed(&bytes) {
+        let vault = keychain::vault()?.ok_or(Error::Argument(
+            "annotations are sealed but the vault is disabled",
+            "gistit config vault",
+        ))?;
+        bytes = vault.open(&bytes)?;
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+fn save(annotations: &[Annotation]) -> Result<()> {
+    let file = annotations_file()?;
+    let mut bytes = serde_json::to_vec_pretty(annotations)?;
+    if let Some(vault) = keychain::vault()? {
+        bytes = vault.seal(&bytes)?;
+    }
+    file::replace(&file, &bytes)?;
+    Ok(())
+}
+
+/// `annotation` as it's kept, printable once shown. `None` if it breaks the annotation rules
+fn sanitized(annotation: &Annotation) -> Option<Annotation> {
+    let annotation = Annotation {
+        file: printable(&annotation.file),
+        message: printable(&annotation.message),
+        author: printable(&annotation.author),
+        ..annotation.clone()
+    };
+    annotation.validate().ok().map(|()| annotation)
+}
+
+/// Adds the annotations not known yet
+fn merge(known: &mut Vec<Annotation>, annotations: &[Annotation]) -> bool {
+    let before = known.len();
+    for annotation in annotations {
+        if !known.contains(annotation) {
+            known.push(annotation.clone());
+        }
+    }
+    known.len() != before
+}
+
+/// Keeps the annotations that follow the rules here, warning instead of failing
+pub fn remember(annotations: &[Annotation]) {
+    let annotations: Vec<Annotation> = annotations.iter().filter_map(sanitized).collect();
+    let remembered = annotations_file().and_then(|file| {
+        fs::create_dir_all(path::data()?)?;
+        let _lock = file::Lock::acquire(&file)?;
+        let mut known = load()?;
+        if merge(&mut known, &annotations) {
+            save(&known)
+        } else {
+            Ok(())
+        }
+    });
+
+    if let Err(err) = remembered {
+        warnln!("failed to keep annotations: {}", err);
+    }
+}
+
+/// Annotations of the gistit kept here, oldest first. None if they can't be read
+#[must_use]
+pub fn local(hash: &str) -> Vec<Annotation> {
+    load()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|annotation| annotation.hash == hash)
+        .collect()
+}
+
+/// `42 │ message  author, 3d ago`
+#[must_use]
+pub fn format(annotation: &Annotation) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let when = annotation
+        .timestamp
+        .parse::<u64>()
+        .map(|millis| ago(now.saturating_sub(millis / 1000)))
+        .unwrap_or_default();
+
+    format!(
+        "{} {} {}  {}",
+        style(format!("{:>4}", annotation.line)).yellow().bold(),
+        style("│").dim(),
+        annotation.message,
+        style(format!("{}, {}", annotation.author, when)).dim()
+    )
+}
+
+fn print(annotations: &[Annotation]) -> Result<()> {
+    if structured(&annotations)? {
+        return Ok(());
+    }
+    if annotations.is_empty() {
+        finish!("No annotations");
+        return Ok(());
+    }
+
+    progress::clear();
+    let mut files: Vec<&str> = Vec::new();
+    for annotation in annotations {
+        if !files.contains(&annotation.file.as_str()) {
+            files.push(&annotation.file);
+        }
+    }
+    for file in files {
+        println!("{}", style(file).green());
+        let mut lines: Vec<&Annotation> = annotations.iter().filter(|a| a.file == file).collect();
+        lines.sort_by_key(|annotation| annotation.line);
+        for annotation in lines {
+            println!("{}", format(annotation));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotate_merge_skips_known() {
+        let annotation = Annotation {
+            hash: "a".repeat(64),
+            file: "main.rs".to_owned(),
+            line: 3,
+            message: "this can panic".to_owned(),
+            author: "fabricio".to_owned(),
+            timestamp: "1650000000000".to_owned(),
+        };
+        let other = Annotation {
+            line: 7,
+            ..annotation.clone()
+        };
+
+        let mut known = vec![annotation.clone()];
+        assert!(!merge(&mut known, &[annotation.clone()]));
+        assert!(merge(&mut known, &[annotation, other.clone()]));
+        assert_eq!(known.len(), 2);
+        assert_eq!(known[1], other);
+    }
+
+    #[test]
+    fn annotate_sanitized_drops_control_chars() {
+        let annotation = Annotation {
+            hash: "a".repeat(64),
+            file: "main.rs".to_owned(),
+            line: 3,
+            message: "fine\x1b[2J\rgone".to_owned(),
+            author: "fabricio".to_owned(),
+            timestamp: "1650000000000".to_owned(),
+        };
+        let kept = sanitized(&annotation).unwrap();
+        assert_eq!(kept.message, "fine [2J gone");
+        assert!(!kept.message.chars().any(char::is_control));
+
+        assert_eq!(
+            sanitized(&Annotation {
+                line: 0,
+                ..annotation.clone()
+            }),
+            None
+        );
+        assert_eq!(
+            sanitized(&Annotation {
+                hash: "not a hash".to_owned(),
+                ..annotation
+            }),
+            None
+        );
+    }
+}
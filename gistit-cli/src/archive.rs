@@ -0,0 +1,296 @@
+//! The archive module
+//!
+//! Backups are zstd compressed tarballs with the config directory under `config/` and the data
+//! directory under `data/`. Files holding credentials, the node identity and the GitHub token, are
+//! left out unless asked for, and always restored readable by the current user only. A token kept
+//! in the system keyring isn't a file, so it never ends up in a backup. Files are restored by
+//! replacing them, never by writing through a symbolic link.
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use gistit_project::file;
+
+use crate::keychain::{self, TOKEN_FILES};
+use crate::{Error, Result};
+
+pub const CONFIG_DIR: &str = "config";
+pub const DATA_DIR: &str = "data";
+
+/// The daemon identity, see `gistit-daemon`
+pub const NODE_CONFIG_FILE: &str = "node-config";
+
+/// Archive top level directories and where they are on this machine
+pub type Roots<'a> = [(&'static str, &'a Path)];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Files packed or restored
+    pub files: usize,
+    /// Secrets left out when packing
+    pub secrets_left_out: usize,
+    /// Files restored over existing ones, or kept if not forced
+    pub existing: usize,
+}
+
+/// Whether this file holds a credential
+#[must_use]
+pub fn is_secret(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| {
+            name == NODE_CONFIG_FILE || TOKEN_FILES.contains(&name)
+        })
+}
+
+/// Every file under `dir`, sorted so archives of the same data are the same
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        let meta = fs::symlink_metadata(&path)?;
+        if meta.is_dir() {
+            walk(&path, files)?;
+        } else if meta.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Packs every root into `out`
+///
+/// # Errors
+///
+/// Fails if a file can't be read or the archive can't be written
+pub fn pack(out: impl Write, roots: &Roots<'_>, include_secrets: bool) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut builder = tar::Builder::new(zstd::Encoder::new(out, 0)?);
+
+    for (name, dir) in roots {
+        if fs::metadata(dir).is_err() {
+            continue;
+        }
+        let mut files = Vec::new();
+        walk(dir, &mut files)?;
+
+        for (file, relative) in files
+            .iter()
+            .filter_map(|file| Some((file, file.strip_prefix(dir).ok()?)))
+        {
+            if is_secret(file) && !include_secrets {
+                summary.secrets_left_out += 1;
+                continue;
+            }
+            builder.append_path_with_name(file, Path::new(name).join(relative))?;
+            summary.files += 1;
+        }
+    }
+
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(summary)
+}
+
+/// Where an archived file goes, `None` for anything outside the roots or escaping them
+fn target(archived: &Path, roots: &Roots<'_>) -> Option<PathBuf> {
+    let mut components = archived.components();
+    let top = match components.next()? {
+        Component::Normal(top) => top.to_str()?,
+        _ => return None,
+    };
+    let (_, dir) = roots.iter().find(|(name, _)| *name == top)?;
+
+    let rest = components.as_path();
+    let safe = rest
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    (safe && !rest.as_os_str().is_empty()).then(|| dir.join(rest))
+}
+
+/// The first symbolic link on the way from its root to `target`, the root itself may be one
+fn symlink_on_the_way(target: &Path, roots: &Roots<'_>) -> Option<PathBuf> {
+    target
+        .ancestors()
+        .take_while(|path| roots.iter().all(|(_, dir)| path != dir))
+        .filter(|path| {
+            fs::symlink_metadata(path).map_or(false, |meta| meta.file_type().is_symlink())
+        })
+        .last()
+        .map(Path::to_path_buf)
+}
+
+/// Restores the files in `input` under their roots. Existing files are kept unless `force`
+///
+/// # Errors
+///
+/// Fails if the archive is malformed, has files outside the roots or a symbolic link on the way to
+/// one, or a file can't be written
+pub fn unpack(input: impl Read, roots: &Roots<'_>, force: bool) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut archive = tar::Archive::new(zstd::Decoder::new(input)?);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let archived = entry.path()?.into_owned();
+        let target =
+            target(&archived, roots).ok_or(Error::Argument("not a gistit backup", "FILE"))?;
+
+        if let Some(link) = symlink_on_the_way(&target, roots) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "refusing to restore `{}` through the symbolic link `{}`",
+                    archived.display(),
+                    link.display()
+                ),
+            )
+            .into());
+        }
+        if fs::symlink_metadata(&target).is_ok() {
+            summary.existing += 1;
+            if !force {
+                continue;
+            }
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        if is_secret(&target) {
+            keychain::write_private(&target, &contents)?;
+        } else {
+            file::replace(&target, &contents)?;
+        }
+        summary.files += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn archive_roundtrip_secrets() {
+        let temp = TempDir::new().unwrap();
+        let tmp = temp.path();
+        let (config, data) = (tmp.join("config"), tmp.join("data"));
+        fs::create_dir_all(data.join("cache")).unwrap();
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("settings.yaml"), "no-color: true\n").unwrap();
+        fs::write(config.join("node-config"), "identity").unwrap();
+        fs::write(data.join("history.json"), "[]").unwrap();
+        fs::write(data.join("cache").join("abc"), "gistit").unwrap();
+
+        let roots = [(CONFIG_DIR, config.as_path()), (DATA_DIR, data.as_path())];
+        let mut backup = Vec::new();
+        let packed = pack(&mut backup, &roots, false).unwrap();
+        assert_eq!((packed.files, packed.secrets_left_out), (3, 1));
+
+        let restored = tmp.join("restored");
+        let (config, data) = (restored.join("config"), restored.join("data"));
+        let roots = [(CONFIG_DIR, config.as_path()), (DATA_DIR, data.as_path())];
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("settings.yaml"), "no-color: false\n").unwrap();
+
+        let unpacked = unpack(&backup[..], &roots, false).unwrap();
+        assert_eq!((unpacked.files, unpacked.existing), (2, 1));
+        assert_eq!(
+            fs::read_to_string(data.join("cache").join("abc")).unwrap(),
+            "gistit"
+        );
+        assert_eq!(
+            fs::read_to_string(config.join("settings.yaml")).unwrap(),
+            "no-color: false\n"
+        );
+        assert!(fs::metadata(config.join("node-config")).is_err());
+
+        unpack(&backup[..], &roots, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(config.join("settings.yaml")).unwrap(),
+            "no-color: true\n"
+        );
+
+        assert_eq!(target(Path::new("data/../../etc/passwd"), &roots), None);
+        assert_eq!(target(Path::new("/data/history.json"), &roots), None);
+        assert_eq!(target(Path::new("runtime/socket"), &roots), None);
+        assert_eq!(target(Path::new("data"), &roots), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archive_unpack_refuses_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let temp = TempDir::new().unwrap();
+        let tmp = temp.path();
+        let (config, data) = (tmp.join("config"), tmp.join("data"));
+        fs::create_dir_all(data.join("cache")).unwrap();
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("node-config"), "identity").unwrap();
+        fs::write(data.join("cache").join("abc"), "gistit").unwrap();
+
+        let roots = [(CONFIG_DIR, config.as_path()), (DATA_DIR, data.as_path())];
+        let mut backup = Vec::new();
+        pack(&mut backup, &roots, true).unwrap();
+
+        let elsewhere = tmp.join("elsewhere");
+        fs::create_dir_all(&elsewhere).unwrap();
+        let restored = tmp.join("restored");
+        let (config, data) = (restored.join("config"), restored.join("data"));
+        let roots = [(CONFIG_DIR, config.as_path()), (DATA_DIR, data.as_path())];
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&data).unwrap();
+
+        // A directory on the way leads elsewhere
+        symlink(&elsewhere, data.join("cache")).unwrap();
+        assert!(matches!(
+            unpack(&backup[..], &roots, true),
+            Err(Error::IO(_))
+        ));
+        assert!(fs::metadata(elsewhere.join("abc")).is_err());
+        fs::remove_file(data.join("cache")).unwrap();
+
+        // So does the file itself
+        let outside = elsewhere.join("identity");
+        fs::write(&outside, "untouched").unwrap();
+        fs::remove_file(config.join("node-config")).unwrap();
+        symlink(&outside, config.join("node-config")).unwrap();
+        assert!(matches!(
+            unpack(&backup[..], &roots, true),
+            Err(Error::IO(_))
+        ));
+        assert_eq!(fs::read_to_string(&outside).unwrap(), "untouched");
+
+        // An existing secret is made private again when replaced
+        fs::remove_file(config.join("node-config")).unwrap();
+        fs::write(config.join("node-config"), "old").unwrap();
+        fs::set_permissions(
+            config.join("node-config"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        unpack(&backup[..], &roots, true).unwrap();
+        let mode = fs::metadata(config.join("node-config"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            fs::read_to_string(data.join("cache").join("abc")).unwrap(),
+            "gistit"
+        );
+    }
+}
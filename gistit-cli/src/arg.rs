@@ -5,8 +5,6 @@ use clap::{crate_authors, crate_description, crate_version, Arg, ArgGroup, Comma
 #[allow(clippy::too_many_lines)]
 #[must_use]
 pub fn app() -> Command<'static> {
-    let random_name = Box::leak(Box::new(names::Generator::default().next().unwrap()));
-
     Command::new("gistit-cli")
         .version(crate_version!())
         .about(crate_description!())
@@ -22,11 +20,115 @@ pub fn app() -> Command<'static> {
                 .takes_value(true)
                 .value_hint(ValueHint::FilePath)
         )
+        .arg(
+            Arg::new("from-url")
+                .long("from-url")
+                .help("Send a remote file, downloaded from an http(s) url")
+                .long_help(
+                    "Send a remote file, downloaded from an http(s) url.
+The name and language are inferred from the url path, or its `Content-Type` otherwise.
+Handy for sharing raw pastes or CI artifacts.",
+                )
+                .takes_value(true)
+                .value_name("url")
+                .value_hint(ValueHint::Url)
+                .conflicts_with("FILE"),
+        )
+        .arg(
+            Arg::new("from-clipboard")
+                .long("from-clipboard")
+                .help("Send the text in the system clipboard")
+                .long_help(
+                    "Send the text in the system clipboard, read with `xclip`, `xsel`, `wl-paste`,
+`pbpaste` or PowerShell depending on the platform. Binary contents and contents larger than a
+gistit can hold are refused.",
+                )
+                .conflicts_with_all(&["FILE", "from-url", "git-diff", "binary"]),
+        )
+        .arg(
+            Arg::new("git-diff")
+                .long("git-diff")
+                .help("Send changes of the current git repository as a patch, staged ones by default")
+                .long_help(
+                    "Send changes of the current git repository as a `.patch` gistit, staged ones
+unless a revision range like `HEAD~1` or `main..feature` is given. The author defaults to git
+`user.name`. Apply it elsewhere with `git apply`.",
+                )
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .value_name("range")
+                .conflicts_with_all(&["FILE", "from-url", "binary"]),
+        )
+        .arg(
+            Arg::new("stdin-filename")
+                .long("stdin-filename")
+                .takes_value(true)
+                .value_name("name")
+                .help("Name of the file read from stdin, its extension tells the language")
+                .long_help(
+                    "Name of the file read from stdin, e.g. `deploy.sh`. Its extension tells the
+language, highlighted when previewed. Without it stdin is named `stdin` and its language guessed
+from a shebang or modeline on the first line.",
+                )
+                .conflicts_with_all(&["FILE", "from-url", "from-clipboard", "git-diff"]),
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .takes_value(true)
+                .value_name("lang")
+                .help("Language of the file, by name or extension, e.g. `python` or `py`")
+                .long_help(
+                    "Language of the file, by name or extension, e.g. `python` or `py`. Overrides the
+one told by the file extension and is what previews highlight the file as.",
+                )
+                .conflicts_with("binary"),
+        )
+        .arg(
+            Arg::new("range")
+                .long("range")
+                .takes_value(true)
+                .value_name("start:end")
+                .help("Only send a range of lines of the file, e.g. `120:180`, `120:` or `:60`")
+                .long_help(
+                    "Only send a range of lines of the file, e.g. `120:180`, `120:` or `:60`.
+The gistit keeps the file name and the lines it was cut from, shown when previewed. The file
+itself may be larger than a gistit allows, only the snippet has to fit.",
+                )
+                .conflicts_with_all(&["binary", "symbol"]),
+        )
+        .arg(
+            Arg::new("symbol")
+                .long("symbol")
+                .takes_value(true)
+                .value_name("name")
+                .help("Only send the definition of a function, class or type of the file")
+                .long_help(
+                    "Only send the definition of a function, class or type of the file, e.g. `--symbol main`.
+The first line defining the name is found with a regex, not a parser, and the definition ends where
+its braces close, or its indentation does for languages like python. Use `--range` when it guesses
+wrong.",
+                )
+                .conflicts_with("binary"),
+        )
         .arg(
             Arg::new("github")
                 .long("github")
                 .help("Post this gistit to GitHub Gists. Will be prompted to authorize with GitHub OAuth")
         )
+        .arg(
+            Arg::new("github-update")
+                .long("github-update")
+                .help("Update one of your GitHub gists with this gistit instead of posting a new one")
+                .long_help(
+                    "Update one of your GitHub gists with this gistit instead of posting a new one.
+The file with the same name is replaced, or added, other files of the gist are left as they are.
+Takes the gist id or its url.")
+                .takes_value(true)
+                .value_name("gist id")
+                .conflicts_with("github"),
+        )
         .arg(
             Arg::new("description")
                 .long("description")
@@ -34,13 +136,29 @@ pub fn app() -> Command<'static> {
                 .help("With a description")
                 .takes_value(true)
         )
+        .arg(
+            Arg::new("description-file")
+                .long("description-file")
+                .help("With a longer markdown description read from a file, e.g. a README.md")
+                .long_help(
+                    "With a longer markdown description read from a file, e.g. a README.md.
+It's sent along as a file of its own and rendered above the gistit when fetched. Goes well with a
+short `--description`.",
+                )
+                .allow_invalid_utf8(true)
+                .takes_value(true)
+                .value_name("path")
+                .value_hint(ValueHint::FilePath),
+        )
         .arg(
             Arg::new("author")
                 .long("author")
                 .short('a')
-                .help("With author information. Defaults to a random generated name")
+                .help(
+                    "With author information. Defaults to the `author` setting, then git \
+                     `user.name`, then the OS user name",
+                )
                 .takes_value(true)
-                .default_value(random_name)
                 .value_hint(ValueHint::Username),
         )
         .arg(
@@ -56,21 +174,280 @@ This is our best efforts at persisting the hash into the system clipboard after
 ",
                 ),
         )
+        .arg(
+            Arg::new("clipboard-backend")
+                .long("clipboard-backend")
+                .help("Copies with this program, or OSC52, instead of the first one found")
+                .long_help(
+                    "Copies the result hash with this program, or the OSC52 escape sequence, instead of
+the first one found. Implies `--clipboard`. Unlike when picked automatically, a failing program
+doesn't fall back to OSC52.",
+                )
+                .takes_value(true)
+                .value_name("backend")
+                .possible_values(["xclip", "xsel", "wl-copy", "clip.exe", "pbcopy", "osc52"]),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .help("Send this gistit straight to a peer, or to a paste service instead of the server")
+                .long_help(
+                    "Send this gistit straight to a peer, or to a paste service instead of the server.
+Peers need a running gistit node. Nothing is published to the DHT or the server, the gistit lands
+in the peer inbox. See `gistit node --inbox`.
+Supported paste services are 'pastebin', 'hastebin' and 'termbin'. Pastebin needs an api key set
+in `GISTIT_PASTEBIN_KEY`.",
+                )
+                .takes_value(true)
+                .value_name("peer id|service")
+                .conflicts_with_all(&["github", "github-update", "public"]),
+        )
+        .arg(
+            Arg::new("public")
+                .long("public")
+                .help("List this gistit in the public index, browsable with `gistit explore`")
+        )
+        .arg(
+            Arg::new("private")
+                .long("private")
+                .help("Only let this gistit be fetched by its full hash")
+                .long_help(
+                    "Only let this gistit be fetched by its full hash.
+The server and gistit nodes don't resolve short hashes to it and it's never listed. Posted to
+GitHub with `--github` it's a secret gist. Combine with `--encrypt` so only whoever knows the
+secret can read it.",
+                )
+                .conflicts_with("public"),
+        )
+        .arg(
+            Arg::new("binary")
+                .long("binary")
+                .help("Send a binary file as is, it can be saved but not previewed")
+                .requires("FILE")
+                .conflicts_with_all(&["github", "github-update", "from-url"]),
+        )
+        .arg(
+            Arg::new("no-secret-scan")
+                .long("no-secret-scan")
+                .help("Send even if the content looks like it holds secrets")
+                .long_help(
+                    "Send even if the content looks like it holds secrets.
+Before sending, text is checked for things like cloud credentials, private keys and tokens, and
+the send stops at the first sign of one.",
+                )
+                .conflicts_with("redact"),
+        )
+        .arg(
+            Arg::new("redact")
+                .long("redact")
+                .help("Replace what looks like secrets with `<redacted>` instead of stopping")
+                .conflicts_with("binary"),
+        )
+        .arg(
+            Arg::new("expire-after-read")
+                .long("expire-after-read")
+                .help("Delete this gistit after it's fetched once")
+                .long_help(
+                    "Delete this gistit after it's fetched once.
+Both the server and the gistit node hosting it forget it as soon as the first fetch succeeds.",
+                )
+                .conflicts_with_all(&["github", "github-update", "public", "to"]),
+        )
+        .arg(
+            Arg::new("ttl-peers")
+                .long("ttl-peers")
+                .takes_value(true)
+                .value_name("n")
+                .help("Ask this many trusted peers to provide this gistit too")
+                .long_help(
+                    "Ask this many trusted peers to provide this gistit too, so it stays around while
+this node is offline. Needs a running gistit node. Peers in its allowlist are asked, fastest first,
+until enough of them agree. A peer agrees only if it runs with `--reseed` and has this node in its
+own allowlist, see `gistit node --allow-peer`.",
+                )
+                .conflicts_with_all(&["github", "github-update", "to", "expire-after-read"]),
+        )
+        .arg(
+            Arg::new("serve-once")
+                .long("serve-once")
+                .help("Hand the file over plain HTTP instead of sending it")
+                .long_help(
+                    "Hand the file over plain HTTP instead of sending it, for someone without gistit.
+Serves on localhost, or the local network with `--lan`. Prints a url with a random token, answers
+the first download and exits. Nothing is uploaded or provided.",
+                )
+                .conflicts_with_all(&["github", "github-update", "to", "ttl-peers", "expire-after-read"]),
+        )
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .takes_value(true)
+                .value_name("port")
+                .requires("serve-once")
+                .help("Port to serve on with `--serve-once`, a free one by default"),
+        )
+        .arg(
+            Arg::new("lan")
+                .long("lan")
+                .requires("serve-once")
+                .help("Serve on every interface with `--serve-once`, for others on the local network"),
+        )
+        .arg(
+            Arg::new("encrypt")
+                .long("encrypt")
+                .help("Encrypt the file with a secret, asked for by whoever fetches it")
+                .long_help(
+                    "Encrypt the file with a secret before it leaves this machine. Needs a running
+gistit node, peers host the encrypted file without being able to read it. Whoever fetches the
+gistit is asked for the secret. Author and description stay readable.
+The secret is asked for, or read from `GISTIT_SECRET`.",
+                )
+                .conflicts_with_all(&["github", "github-update", "to", "serve-once", "public"]),
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .global(true)
+                .help("Disable colors and emoji in the output")
+                .long_help(
+                    "Disable colors and emoji in the output.
+Also enabled when the `NO_COLOR` environment variable is set to a non-empty value.",
+                ),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .global(true)
+                .conflicts_with("progress-json")
+                .help("Only print warnings, errors and results"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .global(true)
+                .multiple_occurrences(true)
+                .conflicts_with("quiet")
+                .help("Print what gistit is doing, repeat for more (-vv, -vvv)")
+                .long_help(
+                    "Print what gistit is doing, repeat for more (-vv, -vvv).
+Shows the URLs hit, transfer sizes and timings, and the messages exchanged with the gistit node.
+A gistit node started by `gistit node --start` logs at the same level.",
+                ),
+        )
+        .arg(
+            Arg::new("progress-json")
+                .long("progress-json")
+                .global(true)
+                .help("Report progress as JSON lines on stderr")
+                .long_help(
+                    "Report progress as JSON lines on stderr, for tools wrapping gistit.
+Each line is an object with an `event` (status, update, warning, error, finish, done, ...), a
+`message` and, for jobs running side by side, the `task` it belongs to.",
+                ),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["text", "json", "yaml"])
+                .help("Print results as JSON or YAML, for scripts [default: text]")
+                .long_help(
+                    "Print results as JSON or YAML, for scripts [default: text].
+Applies to `gistit node --status` and `gistit node --peers`, other commands print text.
+Progress is left out so only the result reaches stdout.",
+                ),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .global(true)
+                .takes_value(true)
+                .value_name("secs")
+                .help("Give up on network requests and gistit node responses after this long")
+                .long_help(
+                    "Give up on network requests and gistit node responses after this long.
+Bounds server and GitHub requests including retries, and how long we wait for peers to provide a
+gistit, which otherwise defaults to 30 seconds.",
+                ),
+        )
+        .arg(
+            Arg::new("no-retry")
+                .long("no-retry")
+                .global(true)
+                .help("Don't retry failed network requests")
+                .long_help(
+                    "Don't retry failed network requests.
+By default server errors and timeouts are retried up to 3 times with exponential backoff.
+The retry count can also be set with the `GISTIT_HTTP_RETRIES` environment variable.",
+                ),
+        )
+        .arg(
+            Arg::new("wire-format")
+                .long("wire-format")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["proto", "json"])
+                .help("Encoding of gistits sent to and fetched from the server [default: proto]")
+                .long_help(
+                    "Encoding of gistits sent to and fetched from the server [default: proto].
+JSON payloads have the protobuf field names in lower camel case, e.g. `burnAfterRead`.",
+                ),
+        )
+        .arg(
+            Arg::new("insecure-runtime-dir")
+                .long("insecure-runtime-dir")
+                .global(true)
+                .help("Use a runtime directory owned by another user or writable by everyone")
+                .long_help(
+                    "Use a runtime directory owned by another user or writable by everyone.
+The gistit node sockets live in the runtime directory, so by default we refuse it unless it's
+ours and only we can create files in it. Prefer pointing `GISTIT_RUNTIME` to a private directory.",
+                ),
+        )
         .arg(
             Arg::new("list-colorschemes")
                 .long("list-colorschemes")
                 .conflicts_with("FILE")
                 .help("List available colorschemes"),
         )
+        .arg(
+            Arg::new("help-exit-codes")
+                .long("help-exit-codes")
+                .conflicts_with("FILE")
+                .help("List exit codes and what they mean"),
+        )
         .subcommand(
             Command::new("fetch")
                 .alias("f")
                 .about("Fetch a gistit wherever it is")
                 .arg(
                     Arg::new("HASH")
-                        .help("Fetch a gistit via it's hash")
+                        .help("Fetch a gistit via it's hash, or a unique prefix of it like `3fa9c2`. `s:` and `p:` hashes are only looked for on the server and the peer network. Names like `snippet@example.com` are looked up in DNS")
                         .takes_value(true)
-                        .required(true),
+                        .required_unless_present("author"),
+                )
+                .arg(
+                    Arg::new("author")
+                        .long("author")
+                        .takes_value(true)
+                        .value_name("name")
+                        .requires("list")
+                        .conflicts_with_all(&["HASH", "verify-only", "apply", "serve-once", "from-peer", "file-name", "lines"])
+                        .help("Pick public gistits by an author to fetch, with `--list`"),
+                )
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .requires("author")
+                        .help("List the public gistits by `--author` and fetch the ones you pick")
+                        .long_help(
+                            "List the public gistits sent under `--author`, newest first, and fetch the ones you
+pick. More are loaded as you ask for them. Without a terminal to pick in, they are all printed
+instead.",
+                        ),
                 )
                 .arg(
                     Arg::new("save")
@@ -79,7 +456,117 @@ This is our best efforts at persisting the hash into the system clipboard after
                         .long_help(
                             "Save the gistit to local fs after successfully fetching.
 Target directory defaults to 'XDG user directory' on Linux, 'Known Folder' system on Windows,
-and 'Standard Directories' on MacOS.",
+and 'Standard Directories' on MacOS. Every file of the gistit is saved, files sharing a name
+under their whole path.",
+                        ),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .requires("save")
+                        .help("Overwrite the saved file if it already exists"),
+                )
+                .arg(
+                    Arg::new("backup")
+                        .long("backup")
+                        .requires("save")
+                        .conflicts_with("force")
+                        .help("Keep a file that is in the way, moving it to the trash")
+                        .long_help(
+                            "Keep a file that is in the way, moving it to the trash before saving.
+Where there is no freedesktop.org trash the file is renamed to `<name>.bak` instead. Without
+`--force` or `--backup`, saving over a file asks first and fails when it can't ask.",
+                        ),
+                )
+                .arg(
+                    Arg::new("lines")
+                        .long("lines")
+                        .takes_value(true)
+                        .value_name("start:end")
+                        .help("Only preview or save a range of lines, e.g. `10:50`, `10:` or `:50`")
+                )
+                .arg(
+                    Arg::new("p2p-only")
+                        .long("p2p-only")
+                        .help("Only look for the gistit in the peer network, never fall back to the server")
+                        .long_help(
+                            "Only look for the gistit in the peer network, never fall back to the server.
+Requires a running `gistit node`. By default, if no peer provides the hash in time, the gistit is
+fetched from the centralized server instead.",
+                        ),
+                )
+                .arg(
+                    Arg::new("verify-only")
+                        .long("verify-only")
+                        .conflicts_with_all(&["save", "lines", "from-peer"])
+                        .help("Only check the gistit exists and show what it is, without downloading it")
+                        .long_help(
+                            "Only check the gistit exists and show its author, size and age, without downloading
+its contents. Burn after read gistits are not burned by this.",
+                        ),
+                )
+                .arg(
+                    Arg::new("file-name")
+                        .long("file-name")
+                        .takes_value(true)
+                        .value_name("name")
+                        .help("Only preview, save or serve this file of a gistit with several")
+                        .long_help(
+                            "Only preview, save or serve this file of a gistit with several, by its name with or
+without the directory. Otherwise a picker cycles between the files when previewing.",
+                        ),
+                )
+                .arg(
+                    Arg::new("apply")
+                        .long("apply")
+                        .conflicts_with_all(&["save", "verify-only", "lines", "serve-once"])
+                        .help("Apply a patch gistit to the git repository you are in")
+                        .long_help(
+                            "Apply a patch gistit, like one sent with `--git-diff`, to the git repository you
+are in. It's checked to apply cleanly first, then previewed and applied once you confirm. Without
+a terminal to ask it's only applied with `--yes`.",
+                        ),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .requires("apply")
+                        .help("Apply the patch without asking, needed without a terminal"),
+                )
+                .arg(
+                    Arg::new("serve-once")
+                        .long("serve-once")
+                        .conflicts_with_all(&["save", "verify-only"])
+                        .help("Hand the file over plain HTTP once fetched")
+                        .long_help(
+                            "Hand the file over plain HTTP once fetched, for someone without gistit.
+Serves on localhost, or the local network with `--lan`. Prints a url with a random token, answers
+the first download and exits.",
+                        ),
+                )
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .takes_value(true)
+                        .value_name("port")
+                        .requires("serve-once")
+                        .help("Port to serve on with `--serve-once`, a free one by default"),
+                )
+                .arg(
+                    Arg::new("lan")
+                        .long("lan")
+                        .requires("serve-once")
+                        .help("Serve on every interface with `--serve-once`, for others on the local network"),
+                )
+                .arg(
+                    Arg::new("from-peer")
+                        .long("from-peer")
+                        .takes_value(true)
+                        .value_name("peer id")
+                        .help("Fetch straight from a peer you know hosts the gistit")
+                        .long_help(
+                            "Fetch straight from a peer you know hosts the gistit, skipping the lookup
+for providers in the DHT. Requires a running `gistit node`.",
                         ),
                 )
                 .arg(
@@ -93,6 +580,379 @@ Run `gistit --colorschemes` to list available ones.",
                         ),
                 )
         )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare two gistits")
+                .long_about(
+                    "Compare two gistits as a unified diff.
+Files are matched by name, two single file gistits are always compared against each other.")
+                .arg(
+                    Arg::new("OLD")
+                        .help("Hash of the gistit to compare from, or a unique prefix of it")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("NEW")
+                        .help("Hash of the gistit to compare to, or a unique prefix of it")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("unified")
+                        .long("unified")
+                        .short('U')
+                        .takes_value(true)
+                        .value_name("lines")
+                        .help("Lines of context around each change [default: 3]"),
+                )
+        )
+        .subcommand(
+            Command::new("amend")
+                .about("Edit a gistit you sent and send it again")
+                .long_about(
+                    "Edit the main file of a gistit you sent in $VISUAL or $EDITOR and send it again once saved.
+The amended gistit gets a new hash and links back to the old one, which stays as it was. Only
+gistits in your history as sent can be amended.")
+                .arg(
+                    Arg::new("HASH")
+                        .help("Hash of the gistit to amend, or a unique prefix of it")
+                        .takes_value(true)
+                        .required(true),
+                )
+        )
+        .subcommand(
+            Command::new("annotate")
+                .about("Leave a note on a line of a gistit")
+                .long_about(
+                    "Leave a note on a line of a gistit, previews highlight the line and show the note under
+the file. Annotations of server hosted gistits are kept by the server, so everyone fetching the
+gistit sees them. They're signed with your GitHub user, authorized like `--github` is. The ones
+of gistits hosted by peers, or not known to be on the server, are only kept here. Tag the hash
+with `s:` to annotate one sent by someone else on the server.")
+                .arg(
+                    Arg::new("HASH")
+                        .help("Hash of the gistit to annotate, or a unique prefix of it")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("line")
+                        .long("line")
+                        .short('l')
+                        .takes_value(true)
+                        .value_name("number")
+                        .required_unless_present("list")
+                        .help("The line to annotate, 1-based"),
+                )
+                .arg(
+                    Arg::new("message")
+                        .long("message")
+                        .short('m')
+                        .takes_value(true)
+                        .required_unless_present("list")
+                        .help("The note, up to 500 characters"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .takes_value(true)
+                        .value_name("name")
+                        .help("The file the line is in, as named in the gistit [default: the main file]"),
+                )
+                .arg(
+                    Arg::new("author")
+                        .long("author")
+                        .short('a')
+                        .takes_value(true)
+                        .value_hint(ValueHint::Username)
+                        .help("Annotate as this author where it's only kept here. Defaults like sending does"),
+                )
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .conflicts_with_all(&["line", "message", "file", "author"])
+                        .help("List the annotations of the gistit instead"),
+                )
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Republish a gistit as a GitHub gist, or a GitHub gist as a gistit")
+                .arg(
+                    Arg::new("SOURCE")
+                        .help("A gistit hash, a GitHub gist url or a gist id")
+                        .long_help(
+                            "A gistit hash, a GitHub gist url or a gist id.
+Gistit hashes are posted to GitHub Gists, you'll be prompted to authorize with GitHub OAuth.
+Gists are downloaded and sent to gistit. Only the first file of a gist is converted.",
+                        )
+                        .takes_value(true)
+                        .required(true),
+                )
+        )
+        .subcommand(
+            Command::new("github")
+                .about("Manage the linked GitHub account")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("logout")
+                        .about("Revoke and delete the stored GitHub token")
+                )
+        )
+        .subcommand(
+            Command::new("explore")
+                .alias("e")
+                .about("Browse recently sent public gistits")
+                .arg(
+                    Arg::new("colorscheme")
+                        .long("colorscheme")
+                        .takes_value(true)
+                        .help("The colorscheme to apply syntax highlighting")
+                )
+                .arg(
+                    Arg::new("page-size")
+                        .long("page-size")
+                        .takes_value(true)
+                        .value_name("count")
+                        .help("How many gistits to load at a time")
+                        .default_value("20")
+                )
+        )
+        .subcommand(
+            Command::new("search")
+                .about("Search public gistits by description, author, file name or language")
+                .long_about(
+                    "Search public gistits by description, author, file name or language, best match
+first. Pick a result to preview or save it. Results are cached for a few minutes.")
+                .arg(
+                    Arg::new("QUERY")
+                        .required(true)
+                        .help("Words every result has to match")
+                )
+                .arg(
+                    Arg::new("author")
+                        .long("author")
+                        .short('a')
+                        .takes_value(true)
+                        .value_name("name")
+                        .help("Only gistits sent under this author name")
+                )
+                .arg(
+                    Arg::new("lang")
+                        .long("lang")
+                        .takes_value(true)
+                        .value_name("language")
+                        .help("Only gistits with files in this language")
+                )
+                .arg(
+                    Arg::new("colorscheme")
+                        .long("colorscheme")
+                        .takes_value(true)
+                        .help("The colorscheme to apply syntax highlighting")
+                )
+                .arg(
+                    Arg::new("page-size")
+                        .long("page-size")
+                        .takes_value(true)
+                        .value_name("count")
+                        .help("How many results to load at a time")
+                        .default_value("20")
+                )
+                .arg(
+                    Arg::new("no-cache")
+                        .long("no-cache")
+                        .help("Ask the server again instead of using cached results")
+                )
+        )
+        .subcommand(
+            Command::new("list")
+                .alias("ls")
+                .about("List gistits you have sent or fetched, newest first")
+                .arg(
+                    Arg::new("lang")
+                        .long("lang")
+                        .takes_value(true)
+                        .value_name("language")
+                        .help("Only list gistits with files in this language")
+                )
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Show statistics about the gistits you have sent or fetched")
+                .long_about(
+                    "Show statistics about the gistits you have sent or fetched.
+Computed from your local history only, nothing is collected or sent anywhere.")
+        )
+        .subcommand(
+            Command::new("themes")
+                .about("List and preview the colorschemes gistits can be shown with")
+                .arg(
+                    Arg::new("THEME")
+                        .multiple_values(true)
+                        .help("Only these colorschemes")
+                )
+                .arg(
+                    Arg::new("preview")
+                        .long("preview")
+                        .help("Show a sample snippet in each colorscheme, one at a time")
+                )
+        )
+        .subcommand(
+            Command::new("tui")
+                .about("Browse the history, fetch gistits and see the node status in a full screen interface")
+                .long_about(
+                    "Browse the history, fetch gistits and see the node status in a full screen interface.
+Gistits can be searched, previewed, saved and have their hash copied. Only available when gistit is
+built with the `tui` feature.")
+        )
+        .subcommand(
+            Command::new("web")
+                .about("Browse your history and cached gistits in the browser")
+                .long_about(
+                    "Browse your history and cached gistits in the browser.
+Starts a local web server on localhost only, gistits you haven't fetched on this machine are listed without their contents.")
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .takes_value(true)
+                        .help("The port to serve on")
+                        .default_value("7474")
+                )
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Back up your history, cache and settings into a single file")
+                .long_about(
+                    "Back up your history, cache and settings into a single file.
+Restore it on another machine with `gistit import`. Your node identity and github token are left out unless you pass `--include-secrets`.")
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .takes_value(true)
+                        .value_name("file")
+                        .help("Where to write the backup")
+                        .default_value("gistit-backup.tar.zst")
+                )
+                .arg(
+                    Arg::new("include-secrets")
+                        .long("include-secrets")
+                        .help("Also export your node identity and github token")
+                )
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Restore a backup made with `gistit export`")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The backup file")
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Overwrite files that already exist")
+                )
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Read and modify gistit settings")
+                .long_about(
+                    "Read and modify gistit settings.
+Settings live in 'settings.yaml' in the config directory and apply unless overridden by a flag.
+Available keys: author, colorscheme, clipboard, no-color, no-retry, alias, default-command, stdin-limit,
+pre-send, post-fetch, hook-timeout, hook-failure, node-rate-limit, node-ban-duration, node-max-hosted,
+node-max-hosted-bytes
+
+Aliases expand before anything else is parsed, `send` stands for sending a file:
+    alias:
+      s: send --clipboard --author me
+      l: list --lang rust
+    default-command: l
+
+Hooks are shell commands run before sending and after fetching, with the file path in `FILE` and
+the gistit in `GISTIT_HASH`, `GISTIT_NAME`, `GISTIT_LANG`, `GISTIT_AUTHOR` and `GISTIT_DESCRIPTION`:
+    pre-send: ./lint.sh \"$FILE\"
+    post-fetch: open \"$FILE\"
+    hook-timeout: 30
+    hook-failure: warn
+
+The `node-*` keys set the limits of the gistit node, its flags win over them. A running node reads
+them again when they're changed from here, see `gistit node --reload-config`.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Print the value of a setting")
+                        .arg(Arg::new("KEY").required(true))
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Change the value of a setting")
+                        .arg(Arg::new("KEY").required(true))
+                        .arg(Arg::new("VALUE").required(true))
+                )
+                .subcommand(
+                    Command::new("vault")
+                        .about("Seal your history and github token with a passphrase")
+                        .long_about(
+                            "Seal your history and github token with a passphrase, for machines shared with other users.
+The passphrase is asked once per command, or read from `GISTIT_PASSPHRASE`.")
+                        .arg(
+                            Arg::new("ACTION")
+                                .required(true)
+                                .possible_values(["enable", "disable"])
+                        )
+                )
+                .subcommand(
+                    Command::new("edit")
+                        .about("Open the settings file in your editor")
+                )
+                .subcommand(
+                    Command::new("path")
+                        .about("Print the settings file path")
+                )
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check your environment for common problems")
+                .arg(
+                    Arg::new("host")
+                        .long("host")
+                        .help("Local host address the gistit node would listen on")
+                        .takes_value(true)
+                        .value_name("ip")
+                        .default_value("0.0.0.0")
+                        .hide(true),
+                    )
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .help("Local port the gistit node would listen on")
+                        .takes_value(true)
+                        .value_name("port")
+                        .default_value("0"),
+                    )
+        )
+        .subcommand(
+            Command::new("clean")
+                .about("Remove leftover temp files, the node log and old cached gistits")
+                .long_about(
+                    "Remove leftover temp files, the node log and old cached gistits.
+Temp files are only removed once the process that wrote them is gone, an hour after they were last
+written. The node log is kept while the node runs.")
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Only list what would be removed"),
+                )
+                .arg(
+                    Arg::new("older-than")
+                        .long("older-than")
+                        .takes_value(true)
+                        .value_name("days")
+                        .help("Remove cached gistits fetched at least this many days ago [default: 30]"),
+                )
+        )
         .subcommand(
             Command::new("node")
                 .alias("n")
@@ -122,28 +982,370 @@ to peer file sharing.")
                         .help("Display the status of your gistit network node process")
                         // .conflicts_with_all(&["start", "stop"]),
                 )
+                .arg(
+                    Arg::new("watch")
+                        .long("watch")
+                        .requires("status")
+                        .conflicts_with("attach")
+                        .help("Keep the status on screen, refreshed every second")
+                        .long_help(
+                            "Keep the status on screen, refreshed every second in place: peers, listeners,
+hosted gistits and bandwidth. Needs a terminal, ctrl-c to stop.")
+                )
+                .arg(
+                    Arg::new("inbox")
+                        .long("inbox")
+                        .group("daemon_cmd")
+                        .help("List gistits other peers sent straight to this node")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("trusted-peers")
+                        .long("trusted-peers")
+                        .group("daemon_cmd")
+                        .help("List the peers this node serves and refuses")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("allow-peer")
+                        .long("allow-peer")
+                        .group("daemon_cmd")
+                        .help("Only serve gistits to allowed peers")
+                        .long_help(
+                            "Add a peer to the allowlist. Once the allowlist has an entry only the peers
+in it are served, everyone else is ignored.")
+                        .takes_value(true)
+                        .value_name("peer id")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("deny-peer")
+                        .long("deny-peer")
+                        .group("daemon_cmd")
+                        .help("Never serve gistits to this peer")
+                        .takes_value(true)
+                        .value_name("peer id")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("forget-peer")
+                        .long("forget-peer")
+                        .group("daemon_cmd")
+                        .help("Remove a peer from both the allowlist and denylist")
+                        .takes_value(true)
+                        .value_name("peer id")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("peers")
+                        .long("peers")
+                        .group("daemon_cmd")
+                        .help("List the peers this node keeps dialing")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("add-peer")
+                        .long("add-peer")
+                        .group("daemon_cmd")
+                        .help("Keep dialing this peer, also after restarts")
+                        .long_help(
+                            "Add a peer to the address book of the node. It's dialed right away and again
+whenever the connection is lost, backing off after failed dials. The address must end with
+`/p2p/<peer id>`.")
+                        .takes_value(true)
+                        .value_name("multiaddr")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("remove-peer")
+                        .long("remove-peer")
+                        .group("daemon_cmd")
+                        .help("Stop dialing a peer, by address or peer id")
+                        .takes_value(true)
+                        .value_name("multiaddr|peer id")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("audit")
+                        .long("audit")
+                        .group("daemon_cmd")
+                        .help("List the gistits this node served to other peers, most recent first")
+                        .long_help(
+                            "List the gistits this node served to other peers, most recent first. Every
+served request is appended to `audit.log` in the data directory with when, to which peer, which
+hash and how many bytes.")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .long("hash")
+                        .help("Only list requests for this gistit")
+                        .takes_value(true)
+                        .value_name("hash")
+                        .requires("audit"),
+                )
+                .arg(
+                    Arg::new("peer-id")
+                        .long("peer-id")
+                        .help("Only list requests from this peer")
+                        .takes_value(true)
+                        .value_name("peer id")
+                        .requires("audit"),
+                )
+                .arg(
+                    Arg::new("reload-config")
+                        .long("reload-config")
+                        .group("daemon_cmd")
+                        .help("Have the running node read its settings and trusted peers again")
+                        .long_help(
+                            "Have the running node read the `node-*` settings and the trusted peers file
+again, without dropping connections. Rate limits, ban duration and the hosting quota change, the
+ones given as flags when it started keep their value. `gistit config set` does this for the
+`node-*` keys.")
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
                 .arg(
                     Arg::new("attach")
                         .long("attach")
                         .help("Attach this terminal session to the running gistit node log stream. Note: If you use this flag with '--start' hitting `CTRL-C` will exit the background process.")
                         .conflicts_with_all(&["stop"]),
                 )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .requires("attach")
+                        .takes_value(true)
+                        .value_name("duration")
+                        .help("Only show log records younger than this, e.g. '30s', '10m', '2h' or '1d'"),
+                )
+                .arg(
+                    Arg::new("grep")
+                        .long("grep")
+                        .requires("attach")
+                        .takes_value(true)
+                        .value_name("pattern")
+                        .help("Only show log records matching this regular expression"),
+                )
+                .arg(
+                    Arg::new("level")
+                        .long("level")
+                        .requires("attach")
+                        .takes_value(true)
+                        .value_name("level")
+                        .possible_values(&["error", "warn", "info", "debug", "trace"])
+                        .help("Only show log records of this level or more severe"),
+                )
                 .arg(
                     Arg::new("dial")
                         .long("dial")
                         .help("Dials a peer given the background process is running")
                         .takes_value(true)
+                        .multiple_occurrences(true)
                         .value_name("multiaddr")
                         .hide(true)
                         .conflicts_with_all(&["stop"]),
                 )
+                .arg(
+                    Arg::new("bootstrap-list")
+                        .long("bootstrap-list")
+                        .help("Bootstrap from a custom list of peers")
+                        .long_help(
+                            "Bootstrap from a custom list of peers instead of the public libp2p ones.
+Accepts a local path or an http(s) url to a JSON manifest containing an array of multiaddrs
+suffixed with `/p2p/<peer id>`. Defaults to 'bootstrap.json' in the config directory if present.")
+                        .takes_value(true)
+                        .value_name("path|url")
+                        .value_hint(ValueHint::AnyPath)
+                        .conflicts_with_all(&["stop", "status"]),
+                )
+                .arg(
+                    Arg::new("reseed")
+                        .long("reseed")
+                        .help("Provide gistits you fetch from peers too")
+                        .long_help(
+                            "Provide gistits you fetch from peers too, so they stay available after
+whoever sent them goes offline. Only applies when starting the node.")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("provide-dir")
+                        .long("provide-dir")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .value_hint(ValueHint::DirPath)
+                        .help("Provide every snippet in a directory, following changes to it")
+                        .long_help(
+                            "Provide every snippet in a directory, each file as its own gistit, and keep following
+changes to it. Edited files are provided again under their new hash, removed ones stop being
+provided. Files `gistit send` would refuse, for their extension or size, are skipped. Only applies
+when starting the node.")
+                        .conflicts_with_all(&["stop", "status"]),
+                )
+                .arg(
+                    Arg::new("install-service")
+                        .long("install-service")
+                        .help("Start the node at login through the system service manager")
+                        .long_help(
+                            "Start the node at login through the system service manager: a systemd user unit on
+Linux, a launchd agent on macOS or a logon scheduled task on Windows. Starts it right away too.
+The service listens and bootstraps the same way `--start` would.")
+                        .conflicts_with_all(&["start", "stop", "status", "attach", "dial", "uninstall-service"]),
+                )
+                .arg(
+                    Arg::new("uninstall-service")
+                        .long("uninstall-service")
+                        .help("Stop the node service and remove it")
+                        .conflicts_with_all(&["start", "stop", "status", "attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("export-identity")
+                        .long("export-identity")
+                        .group("daemon_cmd")
+                        .help("Bundle the node identity into a passphrase sealed file, to move it to another machine")
+                        .long_help(
+                            "Bundle the node identity into a passphrase sealed file, to move it to another machine
+with '--import-identity'. Holds the keypair, so the node keeps its peer id, the trusted and
+denied peers, the persistent peers and, if the node is running, the gistits it hosts.")
+                        .takes_value(true)
+                        .value_name("file")
+                        .value_hint(ValueHint::FilePath)
+                        .conflicts_with_all(&["attach", "dial"]),
+                )
+                .arg(
+                    Arg::new("import-identity")
+                        .long("import-identity")
+                        .group("daemon_cmd")
+                        .help("Make the identity bundled with '--export-identity' this node's")
+                        .long_help(
+                            "Make the identity bundled with '--export-identity' this node's. The node must be
+stopped. Files replaced are backed up, and the hosted gistits are provided again the next time
+the node starts.")
+                        .takes_value(true)
+                        .value_name("file")
+                        .value_hint(ValueHint::FilePath)
+                        .conflicts_with_all(&["attach", "dial", "export-identity"]),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Replace an existing bundle file, or a different identity when importing"),
+                )
+                .arg(
+                    Arg::new("wait-ready")
+                        .long("wait-ready")
+                        .help("Wait until the node is listening and bootstrapped, 30 seconds at most by default")
+                        .long_help(
+                            "Wait until the node is listening and done bootstrapping before exiting, giving up
+after this many seconds (30 by default). For scripts that start the node and use it right after.")
+                        .takes_value(true)
+                        .min_values(0)
+                        .max_values(1)
+                        .default_missing_value("30")
+                        .value_name("secs")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("no-pex")
+                        .long("no-pex")
+                        .help("Don't trade peer addresses with other gistit nodes")
+                        .long_help(
+                            "Don't trade peer addresses with other gistit nodes. By default connected nodes share
+a sample of the peers they know every couple of minutes, so nodes that can't reach the public
+bootstrap peers still find each other. Only applies when starting the node.")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("keep-history")
+                        .long("keep-history")
+                        .help("Keep providing gistits replaced by `gistit amend`")
+                        .long_help(
+                            "Keep providing gistits replaced by a newer version sent with `gistit amend`. By
+default the node stops providing the old version an hour after the new one is sent. Only applies
+when starting the node.")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("sync")
+                        .long("sync")
+                        .help("Sync the gistit history with your nodes on other machines")
+                        .long_help(
+                            "Sync the gistit history with your nodes on other machines, so `gistit list` shows
+what was sent or fetched on any of them. Nodes started with the same sync secret find each other
+in the network and trade history entries sealed with a key derived from it. Asks for the secret
+unless it's in `GISTIT_SYNC_SECRET`, leave it empty on the first machine to create one. Doesn't
+work with the vault enabled. Only applies when starting the node.")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("randomize-port")
+                        .long("randomize-port")
+                        .help("Listen on a random port instead of the one from the last start")
+                        .long_help(
+                            "Listen on a random port instead of the one from the last start. By default the node
+binds the ports it listened on last time again, so peers that remember its addresses can still
+reach it.")
+                        .requires("start")
+                        .conflicts_with("port"),
+                )
+                .arg(
+                    Arg::new("reseed-cap")
+                        .long("reseed-cap")
+                        .help("Storage for reseeded gistits, least recently used ones are dropped first")
+                        .takes_value(true)
+                        .value_name("bytes")
+                        .requires("reseed"),
+                )
+                .arg(
+                    Arg::new("query-parallelism")
+                        .long("query-parallelism")
+                        .help("Peers a lookup in the network asks at once [default: 5]")
+                        .takes_value(true)
+                        .value_name("peers")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("query-timeout")
+                        .long("query-timeout")
+                        .help("How long a lookup in the network may take [default: 15]")
+                        .long_help(
+                            "How long a lookup in the network may take, e.g. for the peers providing a gistit,
+in seconds [default: 15]. Fetches wait 30 seconds on the node unless `--timeout` says otherwise,
+a longer lookup only helps along with it. Publishing provider records isn't cut short. Only
+applies when starting the node.")
+                        .takes_value(true)
+                        .value_name("secs")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("request-timeout")
+                        .long("request-timeout")
+                        .help("How long a peer may take to send a gistit [default: 10]")
+                        .long_help(
+                            "How long a peer may take to answer a request, e.g. to send a gistit, in seconds
+[default: 10]. Slower peers are given up on and the next provider is asked. Only applies when
+starting the node.")
+                        .takes_value(true)
+                        .value_name("secs")
+                        .requires("start"),
+                )
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .help("Listen on this multiaddr too, can be repeated")
+                        .long_help(
+                            "Listen on this multiaddr too, e.g. `/ip6/::/tcp/4001`. Can be repeated.
+By default the node listens on every ipv4 and ipv6 interface.")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .value_name("multiaddr")
+                        .conflicts_with_all(&["stop", "status"]),
+                )
                 .arg(
                     Arg::new("host")
                         .long("host")
                         .help("Local host address to listen for connection")
                         .takes_value(true)
-                        .value_name("ipv4")
-                        .default_value("0.0.0.0")
+                        .value_name("ip")
                         .hide(true)
                         .conflicts_with_all(&["stop", "status"]),
                     )
@@ -153,7 +1355,6 @@ to peer file sharing.")
                         .help("Local port to listen for connection")
                         .takes_value(true)
                         .value_name("port")
-                        .default_value("0")
                         .hide(true)
                         .conflicts_with_all(&["stop", "status"]),
                     )
@@ -0,0 +1,24 @@
+//! The bridge module
+//!
+//! Requests to the running gistit node made by more than one command. The node may be one started
+//! by another gistit build, so these don't depend on the `p2p` feature.
+use gistit_ipc::{Bridge, Client};
+use gistit_proto::Instruction;
+
+use crate::interrupt;
+use crate::Result;
+
+/// Whether the running node negotiates `capability`, the bridge is left connected to send the
+/// request
+///
+/// # Errors
+///
+/// Fails if the node can't be reached or doesn't answer in time
+pub async fn supports(bridge: &mut Bridge<Client>, capability: &str) -> Result<bool> {
+    bridge.connect().await?;
+    bridge.send(Instruction::hello()).await?;
+    Ok(interrupt::bounded("gistit node", bridge.recv())
+        .await?
+        .negotiate()
+        .supports(capability))
+}
@@ -0,0 +1,134 @@
+//! The cache module
+//!
+//! Gistits are immutable and addressed by their hash, so once fetched they are kept in the data
+//! directory and never requested again. Entries are verified when read, anything that doesn't
+//! match its hash anymore is dropped. `gistit clean` drops the ones fetched long ago. With the
+//! vault enabled entries are sealed like the history.
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use gistit_project::{path, vault};
+#[cfg(feature = "fetch")]
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+
+use crate::{keychain, Error, Result};
+
+const CACHE_DIR: &str = "cache";
+
+/// Returns the cache directory, creating it if needed
+///
+/// # Errors
+///
+/// Fails if the data directory can't be resolved or written to
+pub fn dir() -> Result<PathBuf> {
+    let dir = path::data()?.join(CACHE_DIR);
+    if fs::metadata(&dir).is_err() {
+        fs::create_dir_all(&dir)?;
+    }
+    Ok(dir)
+}
+
+/// Rejects gistits whose content doesn't match the advertised hash, or any of its files their own
+///
+/// # Errors
+///
+/// Fails with [`Error::Integrity`] if a checksum doesn't match
+pub fn verify(gistit: &Gistit) -> Result<()> {
+    if let Some(inner) = gistit.inner.iter().find(|inner| !inner.is_intact()) {
+        return Err(Error::Integrity(format!(
+            "{} ({})",
+            gistit.hash, inner.name
+        )));
+    }
+
+    if gistit.checksum() == gistit.hash {
+        Ok(())
+    } else {
+        Err(Error::Integrity(gistit.hash.clone()))
+    }
+}
+
+/// Reads a cached gistit, `None` if it isn't cached, the entry is corrupted or sealed by a vault
+/// that can't be unlocked
+#[must_use]
+pub fn load(hash: &str) -> Option<Gistit> {
+    let file = dir().ok()?.join(hash);
+    let mut bytes = fs::read(&file).ok()?;
+    if vault::is_sealed(&bytes) {
+        bytes = keychain::vault().ok()??.open(&bytes).ok()?;
+    }
+    let gistit = Gistit::from_bytes(bytes).ok()?;
+
+    if gistit.hash == hash && verify(&gistit).is_ok() {
+        Some(gistit)
+    } else {
+        let _ = fs::remove_file(&file);
+        None
+    }
+}
+
+/// Hashes of the cached gistits, entries are only verified once loaded
+///
+/// # Errors
+///
+/// Fails if the cache directory can't be read
+#[cfg(feature = "fetch")]
+pub fn hashes() -> Result<Vec<String>> {
+    let mut hashes = Vec::new();
+    for entry in fs::read_dir(dir()?)? {
+        if let Some(hash) = entry?.file_name().to_str() {
+            hashes.push(hash.to_owned());
+        }
+    }
+    Ok(hashes)
+}
+
+/// Caches a fetched gistit, sealing it if the vault is enabled
+///
+/// # Errors
+///
+/// Fails if the cache directory can't be written to or the vault can't be unlocked
+#[cfg(feature = "fetch")]
+pub fn store(gistit: &Gistit) -> Result<()> {
+    let mut bytes = gistit.encode_to_vec();
+    if let Some(vault) = keychain::vault()? {
+        bytes = vault.seal(&bytes)?;
+    }
+    fs::write(dir()?.join(&gistit.hash), bytes)?;
+    Ok(())
+}
+
+/// Drops every cached gistit
+///
+/// # Errors
+///
+/// Fails if the cache directory can't be read or an entry can't be removed
+pub fn clear() -> Result<()> {
+    for entry in fs::read_dir(dir()?)? {
+        fs::remove_file(entry?.path())?;
+    }
+    Ok(())
+}
+
+/// Cache entries fetched at least `age` ago
+///
+/// # Errors
+///
+/// Fails if the cache directory can't be read
+pub fn fetched_before(age: Duration) -> Result<Vec<PathBuf>> {
+    let now = SystemTime::now();
+    let mut expired = Vec::new();
+    for entry in fs::read_dir(dir()?)? {
+        let entry = entry?;
+        let fetched = entry.metadata()?.modified()?;
+        if now
+            .duration_since(fetched)
+            .map_or(false, |since| since >= age)
+        {
+            expired.push(entry.path());
+        }
+    }
+    Ok(expired)
+}
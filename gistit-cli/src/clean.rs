@@ -0,0 +1,159 @@
+//! The clean module
+//!
+//! Removes what gistit leaves behind: temp files of processes that crashed, the node log once the
+//! node is stopped and cache entries fetched a while ago. With `--dry-run` they are only listed.
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+#[cfg(feature = "p2p")]
+use gistit_project::path;
+
+use crate::cache;
+use crate::dispatch::Dispatch;
+use crate::fmt::emoji;
+#[cfg(feature = "p2p")]
+use crate::node::LOG_FILE;
+use crate::temp;
+use crate::{cleanln, finish, progress, updateln, warnln, Error, Result};
+
+/// Cache entries fetched longer ago are removed, unless `--older-than` is given
+const DEFAULT_CACHE_DAYS: u64 = 30;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub dry_run: bool,
+    pub older_than: Option<&'static str>,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            dry_run: args.is_present("dry-run"),
+            older_than: args.value_of("older-than"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    cache_age: Duration,
+    #[cfg(feature = "p2p")]
+    runtime_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Leftover {
+    Temp,
+    #[cfg(feature = "p2p")]
+    Log,
+    Cache,
+}
+
+impl Leftover {
+    const fn describe(self) -> &'static str {
+        match self {
+            Self::Temp => "temp file",
+            #[cfg(feature = "p2p")]
+            Self::Log => "node log",
+            Self::Cache => "cached gistit",
+        }
+    }
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let days = self
+            .older_than
+            .map(|days| {
+                days.parse::<u64>()
+                    .map_err(|_| Error::Argument("invalid number of days", "--older-than"))
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_CACHE_DAYS);
+        updateln!("Prepared");
+
+        Ok(Config {
+            cache_age: Duration::from_secs(days.saturating_mul(SECS_PER_DAY)),
+            #[cfg(feature = "p2p")]
+            runtime_path: path::runtime()?,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        progress!("Cleaning");
+        let mut leftovers: Vec<(PathBuf, Leftover)> = temp::stale()
+            .into_iter()
+            .map(|path| (path, Leftover::Temp))
+            .collect();
+
+        // The log of a running node is still being written
+        #[cfg(feature = "p2p")]
+        {
+            let log = config.runtime_path.join(LOG_FILE);
+            if log.is_file() && !gistit_ipc::client(&config.runtime_path)?.alive() {
+                leftovers.push((log, Leftover::Log));
+            }
+        }
+
+        leftovers.extend(
+            cache::fetched_before(config.cache_age)?
+                .into_iter()
+                .map(|path| (path, Leftover::Cache)),
+        );
+
+        let mut freed = 0;
+        let mut removed = Vec::new();
+        for (path, leftover) in leftovers {
+            let size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            if !self.dry_run {
+                if let Err(err) = fs::remove_file(&path) {
+                    warnln!("failed to remove `{}`: {}", path.display(), err);
+                    continue;
+                }
+            }
+            cleanln!(format!(
+                "    {} {}",
+                path.display(),
+                style(format!("{}, {} bytes", leftover.describe(), size)).dim()
+            ));
+            freed += size;
+            removed.push(path);
+        }
+
+        if !self.dry_run {
+            temp::forget(&removed);
+        }
+
+        if removed.is_empty() {
+            finish!(format!("{}Nothing to clean", emoji("✨  ", "")));
+        } else if self.dry_run {
+            finish!(format!(
+                "{}Would remove {} files, {} bytes",
+                emoji("🧹  ", ""),
+                removed.len(),
+                freed
+            ));
+        } else {
+            finish!(format!(
+                "{}Removed {} files, {} bytes",
+                emoji("🧹  ", ""),
+                removed.len(),
+                freed
+            ));
+        }
+        Ok(())
+    }
+}
@@ -11,7 +11,11 @@
 //! credits: this implementation is heavily inspired on
 //! [copypasta](https://docs.rs/copypasta/0.7.1/copypasta/)
 //!
-//! **note** we're not interested in 'paste' functionallity
+//! `--clipboard-backend` skips the search and uses the given program, or OSC52. Otherwise a
+//! program failing to copy falls back to OSC52 too, and the one used is reported to the user.
+//!
+//! Reading goes the same way through the paste counterparts of these binaries, for `gistit send
+//! --from-clipboard`. There's no escape sequence fallback, terminals rarely answer OSC52 queries.
 //!
 //! # Linux/BSD
 //!
@@ -28,7 +32,7 @@
 //!
 //! ## Wayland
 //!
-//! Will look for `wl-copy` binary.
+//! Will look for `wl-copy` binary, and `wl-paste` to read.
 //!
 //! ## Tty (SSH session)
 //!
@@ -43,17 +47,20 @@
 //! # Mac OS
 //!
 //! We check for `pbcopy` binary but it's absence is not a showstopper since we can still try
-//! OSC52 escape sequence.
+//! OSC52 escape sequence. Reading needs `pbpaste`.
 //!
 //! # Windows
 //!
 //! Doesn't make sense to check for `clip.exe` because it's default installation. Anyhow, we're
-//! not using it under this platform. This can change in the future
+//! not using it under this platform. This can change in the future. Reading asks PowerShell's
+//! `Get-Clipboard`, which WSL reaches through `powershell.exe` too.
 use std::env;
 use std::ffi::OsString;
-use std::io::Write;
+use std::fmt;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
+use std::str::FromStr;
 
 use which::which;
 
@@ -77,7 +84,7 @@ pub struct Selected {
 pub struct Binary {
     bin: OsString,
     selected: Selected,
-    program: ClipboardBinProgram,
+    program: Backend,
 }
 
 /// The clipboard that attempts OSC52 escape sequence approach
@@ -95,18 +102,68 @@ enum DisplayKind {
     Wsl,
     SshTty,
     Unknown,
-    #[cfg(target_is = "macos")]
+    #[cfg(target_os = "macos")]
     MacOs,
     #[cfg(target_os = "windows")]
     Windows,
 }
 
+/// How the clipboard is written to, a program or the OSC52 escape sequence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Xclip,
+    Xsel,
+    WlCopy,
+    ClipExe,
+    PbCopy,
+    Osc52,
+}
+
+impl Backend {
+    /// The program, or what `--clipboard-backend` calls it
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Xclip => "xclip",
+            Self::Xsel => "xsel",
+            Self::WlCopy => "wl-copy",
+            Self::ClipExe => "clip.exe",
+            Self::PbCopy => "pbcopy",
+            Self::Osc52 => "osc52",
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = ();
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        [
+            Self::Xclip,
+            Self::Xsel,
+            Self::WlCopy,
+            Self::ClipExe,
+            Self::PbCopy,
+            Self::Osc52,
+        ]
+        .into_iter()
+        .find(|backend| backend.name() == name)
+        .ok_or(())
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 /// Returns the current display server
 fn select_display() -> DisplayKind {
     #[cfg(target_os = "windows")]
     return DisplayKind::Windows;
 
-    #[cfg(target_is = "macos")]
+    #[cfg(target_os = "macos")]
     return DisplayKind::MacOs;
 
     // Linux/BSD only
@@ -207,6 +264,105 @@ impl Clipboard {
     }
 }
 
+/// Copies `content` with `backend`, or the first backend available here. Unless `backend` was
+/// given, a program failing to copy falls back to OSC52. Returns the backend used
+///
+/// # Errors
+///
+/// Fails if no display server was detected, or the given backend is missing or failed
+pub fn copy(content: &str, backend: Option<Backend>) -> Result<Backend> {
+    let selected = Clipboard::new(content).try_into_selected()?;
+    let (provider, used) = selected.clone().into_provider(backend)?;
+    match provider.set_contents() {
+        Ok(()) => Ok(used),
+        Err(_) if backend.is_none() && used != Backend::Osc52 => {
+            EscapeSequence { selected }.set_contents()?;
+            Ok(Backend::Osc52)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// PowerShell arguments printing the clipboard text as is
+#[cfg(not(target_os = "macos"))]
+const GET_CLIPBOARD: &[&str] = &["-NoProfile", "-Command", "Get-Clipboard -Raw"];
+
+/// Reads the text in the system clipboard, at most `limit` bytes
+///
+/// # Errors
+///
+/// Fails if no paste program was found, or the clipboard is empty, larger than `limit` or holds
+/// something else than text
+pub fn paste(limit: usize) -> Result<String> {
+    #[cfg(target_os = "macos")]
+    let (bin, args): (PathBuf, &[&str]) = (which("pbpaste")?, &[]);
+    #[cfg(target_os = "windows")]
+    let (bin, args): (PathBuf, &[&str]) = (PathBuf::from("powershell"), GET_CLIPBOARD);
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let (bin, args) = paste_program(&select_display())?;
+
+    let mut process = Command::new(bin)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut bytes = Vec::new();
+    // One byte past the limit tells contents of exactly `limit` bytes from larger ones
+    process
+        .stdout
+        .take()
+        .expect("to access stdout")
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() > limit {
+        let _ = process.kill();
+    }
+    let status = process.wait()?;
+
+    // Paste programs fail when there's no text to paste, e.g. an image was copied
+    if bytes.len() <= limit && !status.success() {
+        return Err(error::Clipboard::Empty.into());
+    }
+    pasted_text(bytes, limit)
+}
+
+/// The paste program for the display server and its arguments
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn paste_program(display: &DisplayKind) -> Result<(PathBuf, &'static [&'static str])> {
+    match display {
+        DisplayKind::X11 | DisplayKind::SshTty => {
+            if let Ok(bin) = which("xclip") {
+                Ok((bin, &["-o", "-sel", "clip"]))
+            } else if let Ok(bin) = which("xsel") {
+                Ok((bin, &["--clipboard", "--output"]))
+            } else {
+                Err(error::Clipboard::MissingBinary.into())
+            }
+        }
+        DisplayKind::Wayland => Ok((which("wl-paste")?, &["--no-newline", "--type", "text"])),
+        DisplayKind::Wsl => Ok((PathBuf::from("powershell.exe"), GET_CLIPBOARD)),
+        DisplayKind::Unknown => Err(error::Clipboard::UnsupportedPlatform.into()),
+    }
+}
+
+/// Clipboard contents as text. Contents larger than `limit`, binary or blank are refused
+fn pasted_text(bytes: Vec<u8>, limit: usize) -> Result<String> {
+    if bytes.len() > limit {
+        return Err(error::Clipboard::TooLarge.into());
+    }
+    // Text never has NUL bytes, binary data almost always does
+    if bytes.contains(&0) {
+        return Err(error::Clipboard::Binary.into());
+    }
+    let text = String::from_utf8(bytes).map_err(|_| error::Clipboard::Binary)?;
+    if text.trim().is_empty() {
+        return Err(error::Clipboard::Empty.into());
+    }
+    Ok(text)
+}
+
 /// The trait that a ready-to-use clipboard implements
 pub trait Provider {
     /// Attempt to set the contents into the system clipboard
@@ -221,13 +377,13 @@ impl Provider for Binary {
     fn set_contents(&self) -> Result<()> {
         let mut command = Command::new(&self.bin);
         match self.program {
-            ClipboardBinProgram::Xclip => {
+            Backend::Xclip => {
                 command.arg("-sel").arg("clip");
             }
-            ClipboardBinProgram::Xsel => {
+            Backend::Xsel => {
                 command.arg("--clipboard");
             }
-            ClipboardBinProgram::WlCopy | ClipboardBinProgram::ClipExe => (),
+            Backend::WlCopy | Backend::ClipExe | Backend::PbCopy | Backend::Osc52 => (),
         };
         let mut process = command
             .stdin(Stdio::piped())
@@ -241,9 +397,11 @@ impl Provider for Binary {
             .expect("to access stdin")
             .write_all(self.selected.content.as_bytes())?;
 
-        let _status = process.wait()?;
-
-        Ok(())
+        if process.wait()?.success() {
+            Ok(())
+        } else {
+            Err(error::Clipboard::Failed(self.program.name()).into())
+        }
     }
 }
 
@@ -255,38 +413,49 @@ impl Provider for EscapeSequence {
 }
 
 impl Selected {
-    /// Transforms this clipboard into a ready-to-use kind
-    /// First checks for binaries and fallbacks to the ANSI escape sequence approach.
-    #[must_use]
-    pub fn into_provider(self) -> Box<dyn Provider> {
-        match self.try_into_bin() {
-            Ok(bin_clipboard) => {
-                return Box::new(bin_clipboard);
+    /// Transforms this clipboard into a ready-to-use kind, along with the backend it uses.
+    /// `backend` is used if given, otherwise first checks for binaries and fallbacks to the ANSI
+    /// escape sequence approach.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the program of the given `backend` isn't installed
+    pub fn into_provider(self, backend: Option<Backend>) -> Result<(Box<dyn Provider>, Backend)> {
+        match backend {
+            Some(Backend::Osc52) => {
+                Ok((Box::new(EscapeSequence { selected: self }), Backend::Osc52))
             }
-            Err(err) => {
-                println!("{:?}", err);
+            Some(program) => {
+                let bin = which(program.name())
+                    .map_err(|_| error::Clipboard::BackendNotFound(program.name()))?;
+                let binary = Binary {
+                    bin: bin.into_os_string(),
+                    selected: self,
+                    program,
+                };
+                Ok((Box::new(binary), program))
             }
+            None => match self.try_into_bin() {
+                Ok(binary) => {
+                    let program = binary.program;
+                    Ok((Box::new(binary), program))
+                }
+                Err(_) => Ok((Box::new(EscapeSequence { selected: self }), Backend::Osc52)),
+            },
         }
-        Box::new(EscapeSequence { selected: self })
     }
-}
 
-/// Currently supported clipboard programs
-#[non_exhaustive]
-#[derive(Clone, Debug)]
-enum ClipboardBinProgram {
-    Xclip,
-    Xsel,
-    ClipExe,
-    WlCopy,
-    #[cfg(all(target_os = "macos", target_os = "ios"))]
-    PbCopy,
+    /// The clipboard binary that would be used, without touching the clipboard
+    ///
+    /// # Errors
+    ///
+    /// Fails if no supported binary was found for this display server
+    pub fn binary(&self) -> Result<OsString> {
+        self.try_into_bin().map(|binary| binary.bin)
+    }
 }
 
-#[cfg(all(
-    target_family = "unix",
-    not(all(target_os = "macos", target_os = "ios", target_os = "android"))
-))]
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
 impl Selected {
     /// Checks for supported clipboard binaries and attempts to convert the selected clipboard into
     /// the binary implementation.
@@ -299,8 +468,8 @@ impl Selected {
         let (bin, program) = match self.display {
             DisplayKind::X11 => {
                 let mut binaries = [
-                    (which("xclip"), ClipboardBinProgram::Xclip),
-                    (which("xsel"), ClipboardBinProgram::Xsel),
+                    (which("xclip"), Backend::Xclip),
+                    (which("xsel"), Backend::Xsel),
                     // TODO: Add more supported clipboard programs here
                 ]
                 .into_iter();
@@ -313,7 +482,7 @@ impl Selected {
             }
             DisplayKind::Wayland => {
                 let bin = which("wl-copy")?;
-                let program = ClipboardBinProgram::WlCopy;
+                let program = Backend::WlCopy;
                 (bin, program)
             }
             DisplayKind::SshTty => {
@@ -324,8 +493,8 @@ impl Selected {
                 env::var("DISPLAY").map_err(|_| error::Clipboard::DisplayNotSet)?;
 
                 let mut binaries = [
-                    (which("xclip"), ClipboardBinProgram::Xclip),
-                    (which("xsel"), ClipboardBinProgram::Xsel),
+                    (which("xclip"), Backend::Xclip),
+                    (which("xsel"), Backend::Xsel),
                     // TODO: Add more supported clipboard programs here
                 ]
                 .into_iter();
@@ -338,7 +507,7 @@ impl Selected {
             }
             DisplayKind::Wsl => {
                 let bin = PathBuf::from("clip.exe");
-                let program = ClipboardBinProgram::ClipExe;
+                let program = Backend::ClipExe;
                 (bin, program)
             }
             DisplayKind::Unknown => panic!("clipboard feature not supported"),
@@ -351,7 +520,7 @@ impl Selected {
     }
 }
 
-#[cfg(all(target_os = "macos", target_os = "ios"))]
+#[cfg(target_os = "macos")]
 impl Selected {
     /// Checks for supported clipboard binaries and attempts to convert the selected clipboard into
     /// the binary implementation.
@@ -362,13 +531,10 @@ impl Selected {
     /// clipboard binaries.
     fn try_into_bin(&self) -> Result<Binary> {
         let bin = match self.display {
-            DisplayKind::MacOs => which("pbcopy")
-                .ok()
-                .map(|t| t.as_os_str().to_owned())
-                .ok_or(ErrorKind::MissingClipboardBinary)?,
-            DisplayKind::Unknown => panic!("clipboard feature not supported"),
+            DisplayKind::MacOs => which("pbcopy")?.into_os_string(),
+            _ => return Err(error::Clipboard::UnsupportedPlatform.into()),
         };
-        let program = ClipboardBinProgram::PbCopy;
+        let program = Backend::PbCopy;
         Ok(Binary {
             bin,
             program,
@@ -391,6 +557,7 @@ mod tests {
     use std::env;
 
     #[test]
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
     pub fn clipboard_test_selection_order() {
         env::remove_var("DISPLAY");
         env::remove_var("WSL_DISTRO_NAME");
@@ -411,4 +578,26 @@ mod tests {
         let clip3 = Clipboard::new("baz").try_into_selected().unwrap();
         assert_eq!(clip3.display, DisplayKind::Wsl);
     }
+
+    #[test]
+    pub fn clipboard_backend_names() {
+        for name in ["xclip", "xsel", "wl-copy", "clip.exe", "pbcopy", "osc52"] {
+            assert_eq!(name.parse::<Backend>().unwrap().to_string(), name);
+        }
+        assert!("xclip.exe".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    pub fn clipboard_pasted_text_guards() {
+        let text = "fn main() {}\n".repeat(10);
+        assert_eq!(
+            pasted_text(text.clone().into_bytes(), text.len()).unwrap(),
+            text
+        );
+        assert!(pasted_text(text.clone().into_bytes(), text.len() - 1).is_err());
+
+        assert!(pasted_text(b"\x89PNG\r\n\x1a\n\x00\x00".to_vec(), 100).is_err());
+        assert!(pasted_text(vec![0xff, 0xfe, 0x41], 100).is_err());
+        assert!(pasted_text(b" \n\t".to_vec(), 100).is_err());
+    }
 }
@@ -0,0 +1,267 @@
+//! The config module
+//!
+//! Reads and modifies the settings file (`gistit config ...`). Every change is validated by
+//! [`gistit_project::settings`] before gistit is allowed to run with it. A running gistit node
+//! is told to pick up changes to its `node-*` settings.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Confirm;
+
+use gistit_project::exit::ErrorKind;
+use gistit_project::{env, path, settings, vault};
+use gistit_proto::ipc::instruction::ReloadConfigResponse;
+use gistit_proto::{ipc, Instruction};
+
+use crate::dispatch::Dispatch;
+use crate::{cache, history, interrupt, keychain};
+use crate::{errorln, finish, progress, updateln, warnln, Error, Result};
+
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SettingsCommand {
+    Get(&'static str),
+    Set(&'static str, &'static str),
+    Edit,
+    Path,
+    Vault(bool),
+}
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    command: SettingsCommand,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        let command = match args.subcommand() {
+            Some(("get", args)) => SettingsCommand::Get(
+                args.value_of("KEY")
+                    .ok_or(Error::Argument("missing argument", "[KEY]"))?,
+            ),
+            Some(("set", args)) => SettingsCommand::Set(
+                args.value_of("KEY")
+                    .ok_or(Error::Argument("missing argument", "[KEY]"))?,
+                args.value_of("VALUE")
+                    .ok_or(Error::Argument("missing argument", "[VALUE]"))?,
+            ),
+            Some(("edit", _)) => SettingsCommand::Edit,
+            Some(("path", _)) => SettingsCommand::Path,
+            Some(("vault", args)) => {
+                SettingsCommand::Vault(args.value_of("ACTION") == Some("enable"))
+            }
+            _ => {
+                return Err(Error::Argument(
+                    "missing subcommand",
+                    "get|set|edit|path|vault",
+                ))
+            }
+        };
+
+        Ok(Box::new(Self { command }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    settings_path: PathBuf,
+}
+
+/// Has a running gistit node read its `node-*` settings again. Warns instead of failing, the
+/// settings are saved either way
+async fn reload_node() -> Result<()> {
+    let mut bridge = gistit_ipc::client(&path::runtime()?)?;
+    if !bridge.alive() {
+        return Ok(());
+    }
+
+    progress!("Reloading gistit node");
+    match reload_config(&mut bridge).await {
+        Ok(Some(ReloadConfigResponse { error: None })) => updateln!("Reloaded gistit node"),
+        Ok(Some(ReloadConfigResponse { error: Some(err) })) => {
+            warnln!("gistit node kept its previous settings: {}", err);
+        }
+        Ok(None) => {
+            warnln!("the running gistit node can't reload its settings, restart it to apply them")
+        }
+        Err(err) => warnln!("gistit node couldn't be reloaded: {}", err),
+    }
+    Ok(())
+}
+
+/// Asks the running node to read its settings and trusted peers again. `None` if it predates
+/// reloading
+///
+/// # Errors
+///
+/// Fails if the node can't be reached or answers unexpectedly
+pub async fn reload_config(
+    bridge: &mut gistit_ipc::Bridge<gistit_ipc::Client>,
+) -> Result<Option<ReloadConfigResponse>> {
+    bridge.connect().await?;
+    bridge.send(Instruction::hello()).await?;
+    if !interrupt::bounded("gistit node", bridge.recv())
+        .await?
+        .negotiate()
+        .supports(ipc::capability::RELOAD_CONFIG)
+    {
+        return Ok(None);
+    }
+
+    bridge.send(Instruction::request_reload_config()).await?;
+    match interrupt::bounded("gistit node", bridge.recv())
+        .await?
+        .expect_response()?
+    {
+        ipc::instruction::Kind::ReloadConfigResponse(response) => Ok(Some(response)),
+        _ => Err(Error::Server("unexpected response")),
+    }
+}
+
+/// Moves the history and github token in or out of the vault, a new one is created with
+/// `passphrase`. Both are read with the current setup and written back with the new one. Cached
+/// gistits are dropped instead, they're fetched again when needed
+fn toggle_vault(passphrase: Option<&str>) -> Result<()> {
+    let token = keychain::load()?;
+    let _lock = history::lock()?;
+    let entries = history::load()?;
+
+    if let Some(passphrase) = passphrase {
+        keychain::create_vault(passphrase)?;
+    }
+    keychain::delete()?;
+    if passphrase.is_none() {
+        vault::remove()?;
+    }
+
+    if let Some(token) = token {
+        keychain::store(&token)?;
+    }
+    cache::clear()?;
+    history::save(&entries)
+}
+
+/// The user editor command line, `VISUAL` is preferred over `EDITOR` as git does
+pub fn editor() -> Vec<String> {
+    std::env::var(env::VISUAL)
+        .or_else(|_| std::env::var(env::EDITOR))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_owned())
+        .split_whitespace()
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Opens `path` in the user editor and waits for it to exit
+pub fn edit(path: &Path) -> Result<()> {
+    let editor = editor();
+    let status = Command::new(&editor[0])
+        .args(&editor[1..])
+        .arg(path)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Editor(status))
+    }
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        Ok(Config {
+            settings_path: settings::file()?,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        match self.command {
+            SettingsCommand::Get(key) => {
+                if let Some(value) = settings::get(key)? {
+                    println!("{}", value);
+                } else {
+                    warnln!("`{}` is not set", key);
+                }
+            }
+            SettingsCommand::Set(key, value) => {
+                progress!("Saving");
+                settings::set(key, value)?;
+                updateln!("Saved");
+                if key.starts_with("node-") {
+                    reload_node().await?;
+                }
+                finish!("");
+            }
+            SettingsCommand::Vault(enable) => {
+                if enable == vault::enabled() {
+                    warnln!(
+                        "vault is already {}",
+                        if enable { "enabled" } else { "disabled" }
+                    );
+                    return Ok(());
+                }
+
+                // Prompt before the spinner starts
+                let passphrase = if enable {
+                    Some(keychain::passphrase(true)?)
+                } else {
+                    keychain::vault()?;
+                    None
+                };
+
+                progress!("Sealing");
+                toggle_vault(passphrase.as_deref())?;
+                updateln!(if enable {
+                    "Vault enabled"
+                } else {
+                    "Vault disabled"
+                });
+                finish!("");
+            }
+            SettingsCommand::Path => {
+                println!("{}", config.settings_path.to_string_lossy());
+            }
+            SettingsCommand::Edit => {
+                if std::fs::metadata(&config.settings_path).is_err() {
+                    std::fs::write(&config.settings_path, "")?;
+                }
+
+                loop {
+                    edit(&config.settings_path)?;
+                    match settings::load() {
+                        Ok(_) => break,
+                        Err(err) => {
+                            errorln!(err);
+                            let again = Confirm::with_theme(&ColorfulTheme::default())
+                                .with_prompt("Edit again?")
+                                .default(true)
+                                .interact()?;
+                            if !again {
+                                warnln!("settings file is still invalid");
+                                std::process::exit(ErrorKind::Config.code());
+                            }
+                        }
+                    }
+                }
+
+                updateln!("Saved");
+                reload_node().await?;
+                finish!("");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! The convert module
+//!
+//! Republishes a gistit as a GitHub gist, or pulls a GitHub gist down and sends it as a gistit.
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_proto::GistitHash;
+
+use crate::dispatch::Dispatch;
+use crate::fetch::fetch_from_server;
+use crate::file::File;
+use crate::github::{self, Token};
+use crate::param::check;
+use crate::send::{load_to_server, new_gistit};
+use crate::server::web_url;
+use crate::{finish, progress, updateln, warnln, Error, Result};
+
+/// Author used when the gist owner login doesn't fit our author constraints
+const FALLBACK_AUTHOR: &str = "github";
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub source: &'static str,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            source: args
+                .value_of("SOURCE")
+                .ok_or(Error::Argument("missing argument", "[SOURCE]"))?,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub enum Source {
+    /// A gistit hash, converted into a GitHub gist
+    Gistit(GistitHash),
+
+    /// A GitHub gist id, converted into a gistit
+    Gist(&'static str),
+}
+
+#[derive(Debug)]
+pub struct Config {
+    source: Source,
+    github_token: Option<Token>,
+}
+
+/// Tells gistit hashes apart from gist urls and ids.
+/// Gist ids are hex too but never as long as a gistit hash
+fn parse_source(source: &'static str) -> Result<Source> {
+    if let Ok(hash) = check::hash(source) {
+        return Ok(Source::Gistit(hash));
+    }
+
+    check::gist_id(source, "[SOURCE]")
+        .map(Source::Gist)
+        .map_err(|_| {
+            Error::Argument(
+                "expected a gistit hash, a github gist url or a gist id",
+                "[SOURCE]",
+            )
+        })
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let source = parse_source(self.source)?;
+        updateln!("Prepared");
+
+        let github_token = if let Source::Gistit(_) = source {
+            Some(github::authorized_token().await?)
+        } else {
+            None
+        };
+
+        Ok(Config {
+            source,
+            github_token,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        match config.source {
+            Source::Gistit(hash) => {
+                progress!("Fetching");
+                let gistit = fetch_from_server(&hash).await?;
+                updateln!("Fetched");
+
+                progress!("Converting");
+                let token = config.github_token.expect("to be authorized");
+                let gist = github::create_gist(&token, &gistit)
+                    .await?
+                    .ok_or(Error::Server("failed to create github gist"))?;
+                updateln!("Converted");
+
+                finish!(format!(
+                    "\n    hash: '{}'\n    url: '{}'\n    github gist: '{}'\n\n",
+                    style(&hash).bold(),
+                    web_url(&hash),
+                    gist.html_url
+                ));
+            }
+            Source::Gist(id) => {
+                progress!("Fetching gist");
+                let gist = github::get_gist(id).await?;
+                updateln!("Fetched gist");
+
+                progress!("Converting");
+                // NOTE: Currently we support one file
+                if gist.files.len() > 1 {
+                    warnln!("gist has multiple files, only the first one is converted");
+                }
+                let file = gist
+                    .files
+                    .into_values()
+                    .next()
+                    .ok_or(Error::Server("gist has no files"))?;
+
+                if file.truncated {
+                    return Err(Error::Server("gist file is too large"));
+                }
+
+                let author = gist
+                    .owner
+                    .map(|owner| owner.login)
+                    .filter(|login| check::author_name(login).is_ok())
+                    .unwrap_or_else(|| FALLBACK_AUTHOR.to_owned());
+                let description = gist
+                    .description
+                    .filter(|description| check::description(description).is_ok());
+
+                let lang = File::from_data(&file.content, &file.filename)?
+                    .lang()
+                    .to_owned();
+                let gistit = new_gistit(
+                    file.filename,
+                    lang,
+                    file.content,
+                    &author,
+                    description.as_deref(),
+                )?;
+
+                let hash = load_to_server(&gistit).await?;
+                updateln!("Converted");
+
+                finish!(format!(
+                    "\n    hash: '{}'\n    url: '{}'\n    github gist: '{}'\n\n",
+                    style(&hash).bold(),
+                    web_url(&hash),
+                    gist.html_url
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! The diff module
+//!
+//! Compares two gistits in the terminal as a colored unified diff. Files are matched by name, a
+//! pair of single file gistits is always compared even if the file was renamed.
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+use similar::{ChangeTag, TextDiff};
+
+use gistit_project::path;
+use gistit_proto::hash::Namespace;
+use gistit_proto::{Gistit, GistitHash, Inner};
+
+use crate::dispatch::Dispatch;
+use crate::fetch::fetch;
+use crate::resolve;
+use crate::{finish, progress, updateln, Error, Result};
+
+const DEFAULT_CONTEXT: &str = "3";
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub old: &'static str,
+    pub new: &'static str,
+    pub context: &'static str,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            old: args
+                .value_of("OLD")
+                .ok_or(Error::Argument("missing argument", "[OLD]"))?,
+            new: args
+                .value_of("NEW")
+                .ok_or(Error::Argument("missing argument", "[NEW]"))?,
+            context: args.value_of("unified").unwrap_or(DEFAULT_CONTEXT),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    /// Resolved hashes and where they're hosted, if their namespace tells
+    old: (GistitHash, Option<Namespace>),
+    new: (GistitHash, Option<Namespace>),
+    context: usize,
+    runtime_path: PathBuf,
+}
+
+/// Pairs up files with the same name, files present on one side only are paired with `None`
+fn pair_files<'a>(old: &'a Gistit, new: &'a Gistit) -> Vec<(Option<&'a Inner>, Option<&'a Inner>)> {
+    if let ([old], [new]) = (old.inner.as_slice(), new.inner.as_slice()) {
+        return vec![(Some(old), Some(new))];
+    }
+
+    let mut pairs: Vec<_> = old
+        .inner
+        .iter()
+        .map(|old| {
+            let new = new.inner.iter().find(|new| new.name == old.name);
+            (Some(old), new)
+        })
+        .collect();
+
+    pairs.extend(
+        new.inner
+            .iter()
+            .filter(|new| !old.inner.iter().any(|old| old.name == new.name))
+            .map(|new| (None, Some(new))),
+    );
+    pairs
+}
+
+/// Renders a unified diff between two files, `None` if they are equal
+fn unified(old: Option<&Inner>, new: Option<&Inner>, context: usize) -> Option<String> {
+    let old_data = old.map_or("", |inner| &inner.data);
+    let new_data = new.map_or("", |inner| &inner.data);
+    if old_data == new_data {
+        return None;
+    }
+
+    let name = |inner: Option<&Inner>, side: &str| {
+        inner.map_or_else(
+            || "/dev/null".to_owned(),
+            |inner| format!("{}/{}", side, inner.name),
+        )
+    };
+
+    if old.into_iter().chain(new).any(|inner| inner.is_binary) {
+        return Some(format!(
+            "{}\n",
+            style(format!(
+                "Binary files {} and {} differ",
+                name(old, "a"),
+                name(new, "b")
+            ))
+            .bold()
+        ));
+    }
+
+    let mut out = format!(
+        "{}\n{}\n",
+        style(format!("--- {}", name(old, "a"))).bold(),
+        style(format!("+++ {}", name(new, "b"))).bold()
+    );
+
+    let diff = TextDiff::from_lines(old_data, new_data);
+    for hunk in diff.unified_diff().context_radius(context).iter_hunks() {
+        out.push_str(&format!("{}\n", style(hunk.header()).cyan()));
+        for change in hunk.iter_changes() {
+            let line = format!(
+                "{}{}",
+                match change.tag() {
+                    ChangeTag::Delete => "-",
+                    ChangeTag::Insert => "+",
+                    ChangeTag::Equal => " ",
+                },
+                change.value().trim_end_matches('\n')
+            );
+            let line = match change.tag() {
+                ChangeTag::Delete => style(line).red(),
+                ChangeTag::Insert => style(line).green(),
+                ChangeTag::Equal => style(line),
+            };
+            out.push_str(&format!("{}\n", line));
+
+            if change.missing_newline() {
+                out.push_str(&format!(
+                    "{}\n",
+                    style("\\ No newline at end of file").dim()
+                ));
+            }
+        }
+    }
+
+    Some(out)
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let runtime_path = path::runtime()?;
+        let old = resolve::hash(self.old, &runtime_path, None).await?;
+        let new = resolve::hash(self.new, &runtime_path, None).await?;
+        let context = self
+            .context
+            .parse()
+            .map_err(|_| Error::Argument("invalid number of context lines", "--unified"))?;
+        updateln!("Prepared");
+
+        Ok(Config {
+            old,
+            new,
+            context,
+            runtime_path,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        let (ref old, old_namespace) = config.old;
+        let old = fetch(old, &config.runtime_path, old_namespace, None).await?;
+        let (ref new, new_namespace) = config.new;
+        let new = fetch(new, &config.runtime_path, new_namespace, None).await?;
+        progress::clear();
+
+        let diffs: Vec<String> = pair_files(&old, &new)
+            .into_iter()
+            .filter_map(|(old, new)| unified(old, new, config.context))
+            .collect();
+
+        if diffs.is_empty() {
+            finish!(style("no differences").dim());
+        } else {
+            finish!(diffs.join("\n").trim_end());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inner(name: &str, data: &str) -> Inner {
+        Gistit::new_inner(
+            name.to_owned(),
+            "rust".to_owned(),
+            data.len() as u32,
+            data.to_owned(),
+        )
+    }
+
+    #[test]
+    fn diff_pairs_files_by_name() {
+        let old = Gistit {
+            inner: vec![inner("main.rs", "fn main() {}\n"), inner("lib.rs", "")],
+            ..Gistit::default()
+        };
+        let new = Gistit {
+            inner: vec![inner("main.rs", "fn main() {}\n"), inner("mod.rs", "")],
+            ..Gistit::default()
+        };
+
+        let names: Vec<_> = pair_files(&old, &new)
+            .into_iter()
+            .map(|(old, new)| (old.map(|i| i.name.as_str()), new.map(|i| i.name.as_str())))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (Some("main.rs"), Some("main.rs")),
+                (Some("lib.rs"), None),
+                (None, Some("mod.rs")),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_renders_unified() {
+        let old = inner("main.rs", "fn main() {\n    println!(\"foo\");\n}\n");
+        let new = inner("main.rs", "fn main() {\n    println!(\"bar\");\n}\n");
+
+        assert!(unified(Some(&old), Some(&old), 3).is_none());
+
+        let diff = unified(Some(&old), Some(&new), 3).unwrap();
+        assert!(diff.contains("--- a/main.rs\n+++ b/main.rs\n"));
+        assert!(diff.contains("@@ -1,3 +1,3 @@\n"));
+        assert!(diff.contains("-    println!(\"foo\");\n+    println!(\"bar\");\n"));
+    }
+}
@@ -0,0 +1,448 @@
+//! The doctor module
+//!
+//! Runs a series of environment checks and prints what's wrong along with how to fix it. Checks
+//! never abort the run, every problem found is reported at once. They run side by side, the ones
+//! touching the file system or other programs on blocking threads, and each gets
+//! [`CHECK_TIMEOUT`] to tell.
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_project::{env, path};
+use gistit_proto::Instruction;
+
+#[cfg(feature = "clipboard")]
+use crate::clipboard::Clipboard;
+use crate::dispatch::Dispatch;
+use crate::fmt::emoji;
+use crate::http;
+#[cfg(feature = "p2p")]
+use crate::node::DAEMON_BINARY;
+use crate::param::check;
+use crate::server::SERVER_URL_GET;
+use crate::{cleanln, finish, progress, updateln, Error, Result};
+
+const IPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a check may take before it's reported as stuck
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub host: &'static str,
+    pub port: &'static str,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            host: args
+                .value_of("host")
+                .ok_or(Error::Argument("missing argument", "--host"))?,
+            port: args
+                .value_of("port")
+                .ok_or(Error::Argument("missing argument", "--port"))?,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    host: &'static str,
+    port: u16,
+    runtime_path: PathBuf,
+    config_path: PathBuf,
+    data_path: PathBuf,
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Pass(String),
+    /// Something doesn't work but gistit can still be used
+    Warn(String, String),
+    Fail(String, String),
+}
+
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Pass(detail.into()),
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Warn(detail.into(), fix.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Fail(detail.into(), fix.into()),
+        }
+    }
+
+    const fn failed(&self) -> bool {
+        matches!(self.outcome, Outcome::Fail(..))
+    }
+
+    fn stuck(name: &'static str) -> Self {
+        Self::warn(
+            name,
+            format!("took over {}s to tell", CHECK_TIMEOUT.as_secs()),
+            "run `gistit doctor` again, something on this machine is very slow",
+        )
+    }
+
+    fn print(&self) {
+        match &self.outcome {
+            Outcome::Pass(detail) => cleanln!(format!(
+                "    {} {}: {}",
+                style(emoji("✔️ ", "ok")).green(),
+                self.name,
+                style(detail).dim()
+            )),
+            Outcome::Warn(detail, fix) => cleanln!(format!(
+                "    {} {}: {}\n         {} {}",
+                style(emoji("⚠️ ", "!!")).yellow(),
+                self.name,
+                detail,
+                style("fix:").bold(),
+                fix
+            )),
+            Outcome::Fail(detail, fix) => cleanln!(format!(
+                "    {} {}: {}\n         {} {}",
+                style(emoji("❌", "xx")).red(),
+                self.name,
+                detail,
+                style("fix:").bold(),
+                fix
+            )),
+        }
+    }
+}
+
+fn check_directory(name: &'static str, dir: &Path, var: &str) -> Check {
+    let shown = dir.to_string_lossy();
+    if std::fs::metadata(dir).is_err() {
+        Check::fail(
+            name,
+            format!("'{}' does not exist", shown),
+            format!("create it, or point `{}` to an existing directory", var),
+        )
+    } else if path::writable(dir) {
+        Check::pass(name, shown)
+    } else {
+        Check::fail(
+            name,
+            format!("'{}' is not writable", shown),
+            format!("fix its permissions, or point `{}` elsewhere", var),
+        )
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn check_clipboard() -> Check {
+    let name = "clipboard";
+    match Clipboard::new("").try_into_selected() {
+        Ok(selected) => match selected.binary() {
+            Ok(bin) => Check::pass(name, bin.to_string_lossy()),
+            Err(err) => Check::warn(
+                name,
+                format!("{}, falling back to terminal escape sequences", err),
+                "install `xclip` or `xsel` (X11), `wl-clipboard` (Wayland) or `pbcopy` (macOS)",
+            ),
+        },
+        Err(err) => Check::warn(
+            name,
+            err.to_string(),
+            "no display server detected, `--clipboard` won't work in this session",
+        ),
+    }
+}
+
+#[cfg(feature = "p2p")]
+fn check_daemon_binary() -> Check {
+    let name = "daemon";
+    match which::which(DAEMON_BINARY) {
+        Ok(bin) => Check::pass(name, bin.to_string_lossy()),
+        Err(_) => Check::fail(
+            name,
+            format!("`{}` not found in PATH", DAEMON_BINARY),
+            format!(
+                "install `{}` alongside gistit, or add its directory to PATH",
+                DAEMON_BINARY
+            ),
+        ),
+    }
+}
+
+/// Runs a check that blocks, on the file system or waiting on another program, off the runtime
+/// threads. Gives up on it after `timeout`
+async fn blocking(
+    name: &'static str,
+    timeout: Duration,
+    check: impl FnOnce() -> Check + Send + 'static,
+) -> Check {
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(check)).await {
+        Ok(Ok(check)) => check,
+        Ok(Err(err)) => Check::fail(
+            name,
+            format!("the check itself failed ({})", err),
+            "run `gistit doctor` again",
+        ),
+        Err(_) => Check::stuck(name),
+    }
+}
+
+async fn check_server() -> Check {
+    let name = "server";
+    let client = http::Client::new();
+    // Any response means it's reachable, we don't care about the status code here
+    match tokio::time::timeout(
+        CHECK_TIMEOUT,
+        client.send(client.get(SERVER_URL_GET.clone())),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Check::pass(name, SERVER_URL_GET.as_str()),
+        Ok(Err(err)) => Check::fail(
+            name,
+            format!("unreachable ({})", err),
+            "check your connection, proxy settings and `GISTIT_SERVER_URL`",
+        ),
+        Err(_) => Check::stuck(name),
+    }
+}
+
+fn check_port(host: &str, port: u16, daemon_running: bool) -> Check {
+    let name = "port";
+    let address = match host.parse() {
+        Ok(ip) => SocketAddr::new(ip, port),
+        Err(_) => {
+            return Check::fail(
+                name,
+                format!("'{}' is not an IP address", host),
+                "start the node with an IPv4 or IPv6 `--host`",
+            )
+        }
+    };
+    match TcpListener::bind(address) {
+        Ok(_) => Check::pass(name, format!("{} is free", address)),
+        // Our own node is the one holding it
+        Err(_) if daemon_running && port != 0 => {
+            Check::pass(name, format!("{} is in use by gistit node", address))
+        }
+        Err(err) => Check::fail(
+            name,
+            format!("can't listen on {} ({})", address, err),
+            "stop the program using it, or start the node with another `--port`",
+        ),
+    }
+}
+
+/// Returns the check and whether the daemon is running
+async fn check_ipc(runtime_path: &Path) -> (Check, bool) {
+    let name = "ipc";
+    let mut bridge = match gistit_ipc::client(runtime_path) {
+        Ok(bridge) => bridge,
+        Err(err) => {
+            return (
+                Check::fail(
+                    name,
+                    format!("can't bind socket ({})", err),
+                    "make sure the runtime directory is writable",
+                ),
+                false,
+            )
+        }
+    };
+
+    if !bridge.alive() {
+        let socket = gistit_ipc::server_socket(runtime_path);
+        let check = if std::fs::metadata(&socket).is_ok() {
+            Check::warn(
+                name,
+                "found a stale socket from a node that didn't shut down cleanly",
+                format!(
+                    "run `gistit node --start` to replace it, or remove '{}'",
+                    socket.to_string_lossy()
+                ),
+            )
+        } else {
+            Check::pass(name, "gistit node is not running")
+        };
+        return (check, false);
+    }
+
+    let handshake = async {
+        bridge.connect().await?;
+        bridge.send(Instruction::hello()).await?;
+        Ok::<_, Error>(bridge.recv().await?.negotiate())
+    };
+
+    let check = match tokio::time::timeout(IPC_TIMEOUT, handshake).await {
+        Ok(Ok(negotiated)) if negotiated.is_outdated() => Check::warn(
+            name,
+            format!("gistit node speaks protocol v{}", negotiated.version),
+            "restart it with `gistit node --stop` then `gistit node --start`",
+        ),
+        Ok(Ok(negotiated)) => Check::pass(
+            name,
+            format!("gistit node responding, protocol v{}", negotiated.version),
+        ),
+        Ok(Err(err)) => Check::fail(
+            name,
+            format!("gistit node is not responding ({})", err),
+            "restart it with `gistit node --stop` then `gistit node --start`",
+        ),
+        Err(_) => Check::fail(
+            name,
+            "gistit node is not responding",
+            "restart it with `gistit node --stop` then `gistit node --start`",
+        ),
+    };
+
+    (check, true)
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let (host, port) = check::host_port(self.host, self.port)?;
+        updateln!("Prepared");
+
+        Ok(Config {
+            host,
+            // Already validated
+            port: port.parse().expect("to be a valid port"),
+            runtime_path: path::runtime()?,
+            config_path: path::config()?,
+            data_path: path::data()?,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        progress!("Running checks");
+        let directory = |name, dir: &PathBuf, var| {
+            let dir = dir.clone();
+            blocking(name, CHECK_TIMEOUT, move || {
+                check_directory(name, &dir, var)
+            })
+        };
+        let (config_dir, data_dir, runtime_dir, server, (ipc, daemon_running)) = tokio::join!(
+            directory(
+                "config directory",
+                &config.config_path,
+                env::GISTIT_CONFIG_VAR
+            ),
+            directory("data directory", &config.data_path, env::GISTIT_DATA_VAR),
+            directory(
+                "runtime directory",
+                &config.runtime_path,
+                env::GISTIT_RUNTIME_VAR
+            ),
+            check_server(),
+            check_ipc(&config.runtime_path),
+        );
+        let (host, port) = (config.host, config.port);
+        let checks = [
+            config_dir,
+            data_dir,
+            runtime_dir,
+            #[cfg(feature = "clipboard")]
+            blocking("clipboard", CHECK_TIMEOUT, check_clipboard).await,
+            #[cfg(feature = "p2p")]
+            blocking("daemon", CHECK_TIMEOUT, check_daemon_binary).await,
+            server,
+            blocking("port", CHECK_TIMEOUT, move || {
+                check_port(host, port, daemon_running)
+            })
+            .await,
+            ipc,
+        ];
+        updateln!("Checked");
+
+        cleanln!("");
+        for check in &checks {
+            check.print();
+        }
+
+        let failures = checks.iter().filter(|check| check.failed()).count();
+        if failures > 0 {
+            finish!(format!(
+                "\n    {} {} found\n",
+                style(failures).red().bold(),
+                if failures == 1 { "problem" } else { "problems" }
+            ));
+            std::process::exit(1);
+        }
+
+        finish!(format!(
+            "\n    {}\n",
+            style("everything looks good").green()
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn doctor_check_directory() {
+        let tmp = TempDir::new().unwrap();
+        assert!(!check_directory("data directory", tmp.path(), env::GISTIT_DATA_VAR).failed());
+
+        let missing = tmp.child("missing");
+        assert!(check_directory("data directory", missing.path(), env::GISTIT_DATA_VAR).failed());
+    }
+
+    #[test]
+    fn doctor_check_port() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        assert!(check_port("127.0.0.1", port, false).failed());
+        // Held by our own node
+        assert!(!check_port("127.0.0.1", port, true).failed());
+
+        drop(taken);
+        assert!(!check_port("127.0.0.1", port, false).failed());
+
+        assert!(check_port("localhost.", port, false).failed());
+    }
+
+    #[tokio::test]
+    async fn doctor_gives_up_on_slow_checks() {
+        let check = blocking("slow", Duration::from_millis(50), || {
+            std::thread::sleep(Duration::from_millis(500));
+            Check::pass("slow", "done")
+        })
+        .await;
+        assert!(matches!(check.outcome, Outcome::Warn(..)));
+
+        let check = blocking("fast", CHECK_TIMEOUT, || Check::pass("fast", "done")).await;
+        assert!(matches!(check.outcome, Outcome::Pass(..)));
+    }
+}
@@ -0,0 +1,261 @@
+//! The encrypt module
+//!
+//! `gistit send --encrypt` seals the files of a gistit with a secret before anyone else sees
+//! them. The secret is stretched with argon2 into a ChaCha20-Poly1305 key, and the files, names
+//! and languages included, become a single opaque binary file. Author, description and
+//! timestamp stay readable, the hash covers the sealed file so nodes check it like any other.
+//! Fetching an encrypted gistit asks for the secret, unless `GISTIT_SECRET` holds it.
+//!
+//! The sealed file is held to [`validate::FILE_SIZE`] like any other. Binary files are base64
+//! inside it, so they can be about 37KB at most once encrypted, text files about 50KB.
+use argon2::Argon2;
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Password;
+#[cfg(feature = "send")]
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use gistit_proto::payload::gistit::Encryption;
+use gistit_proto::prost::Message;
+use gistit_proto::Gistit;
+#[cfg(feature = "fetch")]
+use gistit_proto::Inner;
+#[cfg(feature = "send")]
+use gistit_proto::{validate, GistitBuilder};
+
+use gistit_project::env;
+
+#[cfg(feature = "send")]
+use crate::file::BINARY_LANG;
+use crate::{Error, Result};
+
+/// The only scheme we know, stored with every gistit we encrypt
+pub const SCHEME: &str = "argon2id-chacha20poly1305";
+
+/// Name of the file holding the sealed ones
+#[cfg(feature = "send")]
+pub const SEALED_FILE: &str = "gistit.sealed";
+
+#[cfg(feature = "send")]
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// The secret a gistit is encrypted with, wiped from memory once dropped
+#[derive(Clone)]
+pub struct Secret(Zeroizing<String>);
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secret").finish_non_exhaustive()
+    }
+}
+
+/// Reads the secret from `GISTIT_SECRET` or asks for it, twice if `confirm`
+///
+/// # Errors
+///
+/// Fails if the prompt can't be shown or the secret is empty
+pub fn secret(confirm: bool) -> Result<Secret> {
+    if let Some(secret) = std::env::var(env::GISTIT_SECRET)
+        .ok()
+        .filter(|secret| !secret.is_empty())
+    {
+        return Ok(Secret(Zeroizing::new(secret)));
+    }
+
+    let theme = ColorfulTheme::default();
+    let mut prompt = Password::with_theme(&theme);
+    prompt.with_prompt("Gistit secret");
+    if confirm {
+        prompt.with_confirmation("Repeat secret", "secrets don't match");
+    }
+    let secret = Zeroizing::new(prompt.interact()?);
+    if secret.is_empty() {
+        return Err(Error::Encryption("secret can't be empty"));
+    }
+    Ok(Secret(secret))
+}
+
+/// The cipher of `secret` with `salt`. The cipher wipes its copy of the key once dropped
+fn derive(secret: &Secret, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+    let mut key = Zeroizing::new([0_u8; KEY_LEN]);
+    Argon2::default()
+        .hash_password_into(secret.0.as_bytes(), salt, key.as_mut())
+        .map_err(|_| Error::Encryption("failed to derive a key from the secret"))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(key.as_ref())))
+}
+
+/// What's authenticated along with the sealed file, so the scheme, nonce and salt can't be
+/// swapped without opening failing
+fn associated_data(encryption: &Encryption) -> Vec<u8> {
+    encryption.encode_to_vec()
+}
+
+/// The encrypted version of `gistit`, with a fresh salt and nonce. It's built again, so it gets
+/// a hash of its own. The files are encrypted in place, only the sealed copy outlives this
+///
+/// # Errors
+///
+/// Fails if encryption fails or the sealed file breaks the payload rules, e.g. it's too large
+#[cfg(feature = "send")]
+pub fn seal(gistit: Gistit, secret: &Secret) -> Result<Gistit> {
+    let mut salt = [0_u8; SALT_LEN];
+    let mut nonce = [0_u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let encryption = Encryption {
+        scheme: SCHEME.to_owned(),
+        nonce: base64::encode(nonce),
+        salt: base64::encode(salt),
+    };
+
+    let Gistit {
+        author,
+        description,
+        timestamp,
+        inner,
+        languages,
+        public,
+        private,
+        burn_after_read,
+        ..
+    } = gistit;
+    let mut sealed = Gistit {
+        inner,
+        languages,
+        ..Gistit::default()
+    }
+    .encode_to_vec();
+    derive(secret, &salt)?
+        .encrypt_in_place(
+            Nonce::from_slice(&nonce),
+            &associated_data(&encryption),
+            &mut sealed,
+        )
+        .map_err(|_| Error::Encryption("failed to encrypt the gistit"))?;
+    if sealed.len() > *validate::FILE_SIZE.end() {
+        return Err(Error::Encryption(
+            "too large to encrypt, about 50KB of text or 37KB of binary files fit",
+        ));
+    }
+
+    Ok(GistitBuilder::new(author)
+        .description(description.as_deref())
+        .timestamp(timestamp)
+        .binary_file(SEALED_FILE, BINARY_LANG, &sealed)
+        .public(public)
+        .private(private)
+        .burn_after_read(burn_after_read)
+        .encryption(encryption)
+        .build()?)
+}
+
+/// The decrypted version of an encrypted `gistit`, under the same hash. Files are checked
+/// against their own hashes, the gistit hash only holds for the sealed file
+///
+/// # Errors
+///
+/// Fails if the scheme is unknown, the secret is wrong or the sealed file was tampered with
+#[cfg(feature = "fetch")]
+pub fn open(gistit: &Gistit, secret: &Secret) -> Result<Gistit> {
+    let corrupted = || Error::Encryption("encrypted gistit is corrupted");
+    let encryption = gistit
+        .encryption
+        .as_ref()
+        .ok_or(Error::Encryption("gistit is not encrypted"))?;
+    if encryption.scheme != SCHEME {
+        return Err(Error::Encryption(
+            "gistit is encrypted with a scheme this version doesn't know, try updating",
+        ));
+    }
+    let nonce = base64::decode(&encryption.nonce).map_err(|_| corrupted())?;
+    let salt = base64::decode(&encryption.salt).map_err(|_| corrupted())?;
+    let mut files = gistit
+        .inner
+        .first()
+        .and_then(|inner| base64::decode(&inner.data).ok())
+        .ok_or_else(corrupted)?;
+    if nonce.len() != NONCE_LEN {
+        return Err(corrupted());
+    }
+
+    derive(secret, &salt)?
+        .decrypt_in_place(
+            Nonce::from_slice(&nonce),
+            &associated_data(encryption),
+            &mut files,
+        )
+        .map_err(|_| Error::Encryption("wrong secret, or the gistit was tampered with"))?;
+    let files = Gistit::decode(files.as_slice()).map_err(|_| corrupted())?;
+    if files.inner.is_empty() || !files.inner.iter().all(Inner::is_intact) {
+        return Err(corrupted());
+    }
+
+    Ok(Gistit {
+        inner: files.inner,
+        languages: files.languages,
+        encryption: None,
+        ..gistit.clone()
+    })
+}
+
+#[cfg(all(test, feature = "send", feature = "fetch"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_seal_and_open() {
+        let gistit = GistitBuilder::new("fabricio")
+            .description(Some("prints hello"))
+            .file(
+                "main.rs",
+                "rust",
+                "fn main() { println!(\"hello\"); }".to_owned(),
+            )
+            .build()
+            .unwrap();
+
+        let secret = Secret(Zeroizing::new("correct horse battery staple".to_owned()));
+        let sealed = seal(gistit.clone(), &secret).unwrap();
+        assert!(sealed.is_encrypted() && sealed.is_intact());
+        assert!(sealed.validate().is_ok());
+        assert_eq!(sealed.inner.len(), 1);
+        assert_eq!(sealed.inner[0].name, SEALED_FILE);
+        assert!(!sealed.inner[0].data.contains("hello"));
+        assert_eq!(sealed.description, gistit.description);
+
+        let opened = open(&sealed, &secret).unwrap();
+        assert_eq!(opened.hash, sealed.hash);
+        assert_eq!(opened.inner, gistit.inner);
+        assert!(!opened.is_encrypted());
+
+        let wrong = Secret(Zeroizing::new("wrong".to_owned()));
+        assert!(matches!(open(&sealed, &wrong), Err(Error::Encryption(_))));
+        let mut tampered = sealed;
+        tampered.encryption.as_mut().unwrap().scheme = "rot13".to_owned();
+        assert!(matches!(
+            open(&tampered, &secret),
+            Err(Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn encrypt_binary_size_limit() {
+        let secret = Secret(Zeroizing::new("correct horse battery staple".to_owned()));
+        let binary = |len| {
+            GistitBuilder::new("fabricio")
+                .binary_file("image.png", BINARY_LANG, &vec![7_u8; len])
+                .build()
+                .unwrap()
+        };
+        assert!(seal(binary(36_000), &secret).is_ok());
+        // Fits as a plain gistit, not once sealed
+        assert!(matches!(
+            seal(binary(40_000), &secret),
+            Err(Error::Encryption(_))
+        ));
+    }
+}
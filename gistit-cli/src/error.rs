@@ -1,5 +1,10 @@
 use console::style;
 
+use gistit_project::exit::ErrorKind;
+use gistit_proto::ipc::instruction::error_response::Code;
+use gistit_proto::ipc::instruction::provide_response::Status;
+use gistit_proto::ipc::instruction::ErrorResponse;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("{0}")]
@@ -20,6 +25,9 @@ pub enum Error {
     #[error("{0}")]
     JsonParse(#[from] serde_json::Error),
 
+    #[error("{0}")]
+    Yaml(#[from] serde_yaml::Error),
+
     #[error("{0}")]
     Ipc(#[from] gistit_ipc::Error),
 
@@ -29,6 +37,7 @@ pub enum Error {
     #[error("{0}")]
     Proto(#[from] gistit_proto::Error),
 
+    #[cfg(feature = "fetch")]
     #[error("{0}")]
     Tui(#[from] bat::error::Error),
 
@@ -38,6 +47,15 @@ pub enum Error {
     #[error("{0}")]
     Server(&'static str),
 
+    #[error("{0}")]
+    NotFound(&'static str),
+
+    #[error("{0} timed out")]
+    Timeout(&'static str),
+
+    #[error("github rate limit exceeded, try again in {0}")]
+    RateLimited(String),
+
     /// (Reason, Param)
     #[error("{}", fmt_subcat("PARAM", .0, .1))]
     Argument(&'static str, &'static str),
@@ -48,10 +66,105 @@ pub enum Error {
     #[error("{0}")]
     OAuth(String),
 
+    /// A paste service refused the gistit
+    #[error("{0}")]
+    Uplink(String),
+
+    #[error("{0}")]
+    Service(String),
+
+    /// `git apply` refused a patch, with its reason
+    #[error("patch doesn't apply: {0}")]
+    Patch(String),
+
+    /// The user editor exited with a failure, with its exit status
+    #[error("editor exited with {0}, set `VISUAL` or `EDITOR` to change it")]
+    Editor(std::process::ExitStatus),
+
+    /// A `pre-send` or `post-fetch` hook failed, see `hook-failure`
+    #[error("{0}")]
+    Hook(String),
+
+    /// The node couldn't fulfill a request
+    #[error("{}", fmt_daemon(.0))]
+    Daemon(ErrorResponse),
+
+    /// The node refused to host a gistit, with why
+    #[error("{}", fmt_refused(*.0, .1))]
+    Refused(Status, String),
+
+    #[error("integrity check failed, content doesn't match hash '{0}'")]
+    Integrity(String),
+
+    /// Sealing or opening an encrypted gistit failed, usually a wrong secret
+    #[error("{0}")]
+    Encryption(&'static str),
+
+    /// A command or flag this build left out, with the feature bringing it back
+    #[error("gistit was built without {0} support, rebuild it with `--features {0}`")]
+    Feature(&'static str),
+
+    /// A hash prefix matching several gistits, with the ones it matched
+    #[error("{}", fmt_ambiguous(.0, .1))]
+    Ambiguous(String, Vec<String>),
+
+    /// A name published in DNS pointing to several gistits, with the ones it points to
+    #[error("{}", fmt_ambiguous_name(.0, .1))]
+    AmbiguousName(String, Vec<String>),
+
     #[error("unknown error")]
     Unknown,
 }
 
+impl Error {
+    /// Category of the error, which decides the process exit code
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            // Prompts report Ctrl-C as an interrupted read
+            Self::IO(err) if err.kind() == std::io::ErrorKind::Interrupted => {
+                ErrorKind::Interrupted
+            }
+            Self::IO(_) | Self::Editor(_) => ErrorKind::Io,
+            Self::Request(_)
+            | Self::Server(_)
+            | Self::Uplink(_)
+            | Self::Timeout(_)
+            | Self::RateLimited(_) => ErrorKind::Network,
+            Self::Utf8(_)
+            | Self::UrlParse(_)
+            | Self::Argument(..)
+            | Self::Colorscheme(_)
+            | Self::Patch(_)
+            | Self::Hook(_)
+            | Self::Feature(_)
+            | Self::Ambiguous(..)
+            | Self::AmbiguousName(..) => ErrorKind::Argument,
+            Self::Ipc(_) | Self::Service(_) => ErrorKind::Daemon,
+            Self::Daemon(err) => match err.code() {
+                Code::Timeout => ErrorKind::Network,
+                Code::NoProviders => ErrorKind::NotFound,
+                Code::InvalidHash | Code::InvalidGistit => ErrorKind::Argument,
+                Code::QuotaExceeded | Code::Unsupported | Code::Unknown => ErrorKind::Daemon,
+            },
+            Self::Refused(Status::TooLarge, _) => ErrorKind::Argument,
+            Self::Refused(..) => ErrorKind::Daemon,
+            Self::Project(err) => err.kind(),
+            Self::NotFound(_) => ErrorKind::NotFound,
+            Self::Integrity(_) => ErrorKind::Integrity,
+            Self::OAuth(_) | Self::Encryption(_) => ErrorKind::Auth,
+            Self::Clipboard(_)
+            | Self::JsonParse(_)
+            | Self::Yaml(_)
+            | Self::Proto(_)
+            | Self::Other(_)
+            | Self::Unknown => ErrorKind::Other,
+            #[cfg(feature = "fetch")]
+            Self::Tui(_) => ErrorKind::Other,
+        }
+    }
+}
+
 fn fmt_suggest(cause: &'static str, suggest: String) -> String {
     format!(
         r#"{}
@@ -63,6 +176,67 @@ Did you mean: '{}'?
     )
 }
 
+fn fmt_candidates(head: String, candidates: &[String], hint: &str) -> String {
+    let mut out = head;
+    for hash in candidates {
+        out.push_str(&format!("\n    {}", style(hash).blue()));
+    }
+    out.push_str(hint);
+    out
+}
+
+fn fmt_ambiguous(prefix: &str, candidates: &[String]) -> String {
+    fmt_candidates(
+        format!("hash prefix '{}' is ambiguous, it matches:\n", prefix),
+        candidates,
+        "\n\nType more of the hash to pick one",
+    )
+}
+
+fn fmt_ambiguous_name(name: &str, candidates: &[String]) -> String {
+    fmt_candidates(
+        format!("name '{}' points to several gistits:\n", name),
+        candidates,
+        "\n\nFetch one of them by hash",
+    )
+}
+
+fn fmt_daemon(err: &ErrorResponse) -> String {
+    let reason = match err.code() {
+        Code::Timeout => "peers didn't answer in time, try again or check your connection",
+        Code::NoProviders => "no peer provides this gistit",
+        Code::QuotaExceeded => "gistit node can't host more gistits, restart it to free room",
+        Code::InvalidHash => "gistit node refused the hash as invalid",
+        Code::InvalidGistit => "gistit node refused the gistit as invalid",
+        Code::Unsupported => "gistit node is outdated, update and restart it",
+        Code::Unknown => "gistit node failed, check gistit-daemon logs",
+    };
+    match err.message {
+        Some(ref message) => format!("{} ({})", reason, message),
+        None => reason.to_owned(),
+    }
+}
+
+fn fmt_refused(status: Status, reason: &str) -> String {
+    let cause = match status {
+        Status::QuotaExceeded => {
+            "gistit node hosts as much as it may, raise its `--max-hosted` or `--max-hosted-bytes`"
+        }
+        Status::TooLarge => "gistit is too large for the gistit node to host",
+        Status::StoreFailed => {
+            "gistit node's record store is full, restart it or stop providing some gistits"
+        }
+        Status::Unspecified | Status::Accepted | Status::Duplicate => {
+            "failed to provide gistit, check gistit-daemon logs"
+        }
+    };
+    if reason.is_empty() {
+        cause.to_owned()
+    } else {
+        format!("{} ({})", cause, reason)
+    }
+}
+
 fn fmt_subcat(subcat: &'static str, cause: &'static str, param: &'static str) -> String {
     format!(
         r#"{}
@@ -84,6 +258,16 @@ pub enum Clipboard {
     MissingBinary,
     #[error("the environment variable `DISPLAY` is not set")]
     DisplayNotSet,
+    #[error("the clipboard holds no text")]
+    Empty,
+    #[error("the clipboard contents look binary, save them to a file and send it with `--binary`")]
+    Binary,
+    #[error("the clipboard contents are larger than the largest gistit we can send")]
+    TooLarge,
+    #[error("clipboard backend `{0}` is not installed")]
+    BackendNotFound(&'static str),
+    #[error("`{0}` failed to copy")]
+    Failed(&'static str),
 }
 
 impl From<String> for Error {
@@ -91,3 +275,45 @@ impl From<String> for Error {
         Self::Unknown
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn daemon(code: Code) -> Error {
+        Error::Daemon(ErrorResponse {
+            code: code as i32,
+            message: None,
+        })
+    }
+
+    #[test]
+    fn error_kinds() {
+        let interrupted = std::io::Error::new(std::io::ErrorKind::Interrupted, "read interrupted");
+        assert_eq!(Error::IO(interrupted).kind(), ErrorKind::Interrupted);
+        assert_eq!(
+            Error::IO(std::io::ErrorKind::NotFound.into()).kind(),
+            ErrorKind::Io
+        );
+        assert_eq!(Error::NotFound("gistit").kind(), ErrorKind::NotFound);
+        assert_eq!(Error::Argument("bad", "--to").kind(), ErrorKind::Argument);
+        assert_eq!(
+            Error::Integrity("a".repeat(64)).kind(),
+            ErrorKind::Integrity
+        );
+        assert_eq!(Error::Timeout("request").kind(), ErrorKind::Network);
+
+        assert_eq!(daemon(Code::NoProviders).kind(), ErrorKind::NotFound);
+        assert_eq!(daemon(Code::Timeout).kind(), ErrorKind::Network);
+        assert_eq!(daemon(Code::InvalidHash).kind(), ErrorKind::Argument);
+        assert_eq!(daemon(Code::Unknown).kind(), ErrorKind::Daemon);
+        assert_eq!(
+            Error::Refused(Status::TooLarge, String::new()).kind(),
+            ErrorKind::Argument
+        );
+        assert_eq!(
+            Error::Refused(Status::QuotaExceeded, String::new()).kind(),
+            ErrorKind::Daemon
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! The explore module
+//!
+//! Browse the public index of gistits hosted by the server. Only gistits sent with `--public` are
+//! listed here. The same index, narrowed to one author, backs `gistit fetch --author`.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Select;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use gistit_proto::GistitHash;
+
+use gistit_project::settings::Settings;
+
+use crate::dispatch::Dispatch;
+use crate::fetch::{fetch_from_server, preview};
+use crate::fmt::{ago, bytes};
+use crate::http;
+use crate::param::check;
+use crate::server::{SERVER_URL_LIST, SERVER_URL_LIST_AUTHOR};
+use crate::{progress, updateln, Error, Result};
+
+const DEFAULT_PAGE_SIZE: &str = "20";
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub colorscheme: &'static str,
+    pub page_size: &'static str,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+        settings: &'static Settings,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            colorscheme: args
+                .value_of("colorscheme")
+                .or(settings.colorscheme.as_deref())
+                .unwrap_or("Monokai Extended Origin"),
+            page_size: args.value_of("page-size").unwrap_or(DEFAULT_PAGE_SIZE),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    colorscheme: &'static str,
+    page_size: u32,
+}
+
+/// A public gistit entry, without the file contents
+#[derive(Debug, Clone, Deserialize)]
+pub struct Listed {
+    pub hash: String,
+    pub author: String,
+    pub description: Option<String>,
+    pub timestamp: String,
+    pub name: String,
+    pub lang: String,
+    pub size: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    pub gistits: Vec<Listed>,
+    /// Cursor of the following page, `None` on the last one
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PageQuery<'a> {
+    limit: u32,
+    cursor: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<&'a str>,
+}
+
+impl std::fmt::Display for Listed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let age = self.timestamp.parse::<u64>().map_or_else(
+            |_| self.timestamp.clone(),
+            |millis| ago(now.saturating_sub(millis / 1000)),
+        );
+        write!(
+            f,
+            "{} {} | {} | {}, {}, {} | {}",
+            style(self.hash.get(..8).unwrap_or(&self.hash)).dim(),
+            style(&self.name).green(),
+            style(&self.author).blue().bold(),
+            self.lang,
+            bytes(u64::from(self.size)),
+            style(age).dim(),
+            style(self.description.as_deref().unwrap_or("")).italic()
+        )
+    }
+}
+
+/// Requests one page of the public index, only gistits by `author` if given
+///
+/// # Errors
+///
+/// Fails if the server is unreachable or responds unexpectedly
+pub async fn list_page(limit: u32, cursor: Option<&str>, author: Option<&str>) -> Result<Page> {
+    let url = if author.is_some() {
+        SERVER_URL_LIST_AUTHOR.to_string()
+    } else {
+        SERVER_URL_LIST.to_string()
+    };
+    let client = http::Client::new();
+    let response = client
+        .send(client.post(url).json(&PageQuery {
+            limit,
+            cursor,
+            author,
+        }))
+        .await?;
+
+    match response.status() {
+        StatusCode::OK => Ok(response.json().await?),
+        _ => Err(Error::Server("unexpected response")),
+    }
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let colorscheme = check::colorscheme(self.colorscheme)?;
+        let page_size = check::page_size(self.page_size)?;
+        updateln!("Prepared");
+
+        Ok(Config {
+            colorscheme,
+            page_size,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        progress!("Exploring");
+        let first = list_page(config.page_size, None, None).await?;
+        let mut listed = first.gistits;
+        let mut next = first.next;
+        updateln!("Explored");
+        progress::clear();
+
+        if listed.is_empty() {
+            println!("No public gistits yet");
+            return Ok(());
+        }
+
+        let mut cursor = 0;
+        loop {
+            let mut items: Vec<String> = listed.iter().map(ToString::to_string).collect();
+            if next.is_some() {
+                items.push(style("(load more)").dim().italic().to_string());
+            }
+
+            let selection = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Public gistits (enter to preview, esc to quit)")
+                .items(&items)
+                .default(cursor)
+                .max_length(config.page_size as usize)
+                .interact_opt()?;
+
+            match selection {
+                Some(index) if index == listed.len() => {
+                    let page = list_page(config.page_size, next.as_deref(), None).await?;
+                    cursor = listed.len();
+                    listed.extend(page.gistits);
+                    next = page.next;
+                }
+                Some(index) => {
+                    let hash = GistitHash::parse(&listed[index].hash)
+                        .map_err(|_| Error::Server("unexpected response"))?;
+                    let gistit = fetch_from_server(&hash).await?;
+
+                    preview(&gistit, config.colorscheme, None)?;
+                    cursor = index;
+                }
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explore_author_page() {
+        let query = |author| {
+            serde_json::to_value(PageQuery {
+                limit: 20,
+                cursor: Some("next"),
+                author,
+            })
+            .unwrap()
+        };
+        assert_eq!(
+            query(Some("fabricio")),
+            serde_json::json!({ "limit": 20, "cursor": "next", "author": "fabricio" })
+        );
+        // The whole index takes no `author`
+        assert_eq!(
+            query(None),
+            serde_json::json!({ "limit": 20, "cursor": "next" })
+        );
+
+        let page: Page = serde_json::from_value(serde_json::json!({
+            "gistits": [{
+                "hash": "a".repeat(64),
+                "author": "fabricio",
+                "description": null,
+                "timestamp": "0",
+                "name": "main.rs",
+                "lang": "rust",
+                "size": 12,
+            }],
+            "next": null,
+        }))
+        .unwrap();
+        assert_eq!(page.gistits[0].author, "fabricio");
+        assert!(page.next.is_none());
+
+        let shown = console::strip_ansi_codes(&page.gistits[0].to_string()).into_owned();
+        assert!(shown.starts_with("aaaaaaaa main.rs | fabricio | rust, 12 B, "));
+        assert!(shown.ends_with(" ago | "));
+    }
+}
@@ -0,0 +1,97 @@
+//! The export module
+//!
+//! Backs up the history, cache and settings into a single archive to move them to another
+//! machine with `gistit import`. See [`crate::archive`] for what goes in it.
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_project::path;
+
+use crate::archive::{self, CONFIG_DIR, DATA_DIR};
+use crate::dispatch::Dispatch;
+use crate::{finish, progress, updateln, warnln, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub output: &'static str,
+    pub include_secrets: bool,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            output: args
+                .value_of("output")
+                .ok_or(Error::Argument("missing argument", "--output"))?,
+            include_secrets: args.is_present("include-secrets"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    output: PathBuf,
+    config_path: PathBuf,
+    data_path: PathBuf,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let output = PathBuf::from(self.output);
+        if fs::metadata(&output).map_or(false, |meta| meta.is_dir()) {
+            return Err(Error::Argument("output is a directory", "--output"));
+        }
+        updateln!("Prepared");
+
+        Ok(Config {
+            output,
+            config_path: path::config()?,
+            data_path: path::data()?,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        progress!("Exporting");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if self.include_secrets {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&config.output)?;
+
+        let roots = [
+            (CONFIG_DIR, config.config_path.as_path()),
+            (DATA_DIR, config.data_path.as_path()),
+        ];
+        let summary = archive::pack(file, &roots, self.include_secrets)?;
+        updateln!("Exported");
+
+        if self.include_secrets {
+            warnln!("the backup holds your node identity and github token, keep it private");
+        } else if summary.secrets_left_out > 0 {
+            warnln!(
+                "{} files with credentials left out, use '--include-secrets' to export them",
+                summary.secrets_left_out
+            );
+        }
+
+        finish!(format!(
+            "\n    {} files exported to: '{}'\n\n",
+            style(summary.files).bold(),
+            style(config.output.display()).bold()
+        ));
+        Ok(())
+    }
+}
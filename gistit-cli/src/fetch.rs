@@ -1,168 +1,1229 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
+use bat::line_range::{LineRange, LineRanges};
 use clap::ArgMatches;
-use console::style;
+use console::{style, Term};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, MultiSelect, Select};
 use reqwest::StatusCode;
 use serde::Serialize;
 
+use gistit_proto::hash::Namespace;
 use gistit_proto::ipc::{self, Instruction};
 use gistit_proto::payload::Gistit;
-use gistit_proto::prost::Message;
+use gistit_proto::{GistitHash, Inner, Visibility};
 
 use gistit_project::path;
+use gistit_project::settings::Settings;
 
+use crate::annotate;
+use crate::cache::{self, verify};
 use crate::dispatch::Dispatch;
-use crate::file::File;
+use crate::encrypt;
+use crate::explore::{self, Listed};
+use crate::file::{self, name_from_path, File, Overwrite};
+use crate::fmt::{ago, colors_enabled, emoji};
+use crate::git;
+use crate::history::{self, Origin};
+use crate::hook::Hook;
+use crate::http;
+use crate::interrupt;
 use crate::param::check;
-use crate::server::SERVER_URL_GET;
-use crate::{errorln, finish, interruptln, progress, updateln, warnln, Error, Result};
+use crate::resolve;
+use crate::serve::{serve_once, Download, Listen};
+use crate::server::{SERVER_URL_GET, SERVER_URL_HEAD};
+use crate::sniff;
+use crate::syntax;
+use crate::{cleanln, finish, progress, updateln, warnln, Error, Result};
+
+/// How long we wait for the daemon to find the gistit in the DHT before giving up, unless
+/// `--timeout` is given
+const DHT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Gistits listed at a time by `--author`
+const AUTHOR_PAGE_SIZE: u32 = 20;
 
 #[derive(Debug, Clone)]
 pub struct Action {
-    pub hash: &'static str,
+    pub hash: Option<&'static str>,
+    pub author: Option<&'static str>,
     pub colorscheme: &'static str,
     pub save: bool,
+    pub force: bool,
+    pub backup: bool,
+    pub p2p_only: bool,
+    pub from_peer: Option<&'static str>,
+    pub lines: Option<&'static str>,
+    pub verify_only: bool,
+    pub serve_once: bool,
+    pub port: Option<&'static str>,
+    pub lan: bool,
+    pub apply: bool,
+    pub yes: bool,
+    pub file_name: Option<&'static str>,
+    pub post_fetch: Option<Hook>,
 }
 
 impl Action {
     pub fn from_args(
         args: &'static ArgMatches,
+        settings: &'static Settings,
     ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
         Ok(Box::new(Self {
-            hash: args
-                .value_of("HASH")
-                .ok_or(Error::Argument("missing arugment", "--hash"))?,
+            hash: args.value_of("HASH"),
+            author: args.value_of("author"),
             colorscheme: args
                 .value_of("colorscheme")
+                .or(settings.colorscheme.as_deref())
                 .unwrap_or("Monokai Extended Origin"), // This is the most decent looking
             save: args.is_present("save"),
+            force: args.is_present("force"),
+            backup: args.is_present("backup"),
+            p2p_only: args.is_present("p2p-only"),
+            from_peer: args.value_of("from-peer"),
+            lines: args.value_of("lines"),
+            verify_only: args.is_present("verify-only"),
+            serve_once: args.is_present("serve-once"),
+            port: args.value_of("port"),
+            lan: args.is_present("lan"),
+            apply: args.is_present("apply"),
+            yes: args.is_present("yes"),
+            file_name: args.value_of("file-name"),
+            post_fetch: Hook::post_fetch(settings),
         }))
     }
 }
 
 #[derive(Debug, Serialize)]
 pub struct Config {
-    hash: &'static str,
+    /// `None` when listing by author
+    hash: Option<GistitHash>,
+    author: Option<&'static str>,
     colorscheme: &'static str,
     save: bool,
+    force: bool,
+    backup: bool,
+    /// Where to look for the gistit, everywhere if `None`
+    namespace: Option<Namespace>,
+    from_peer: Option<&'static str>,
+    lines: Option<(usize, usize)>,
+    /// Where to serve the file with `--serve-once`
+    serve_once: Option<Listen>,
     runtime_path: PathBuf,
     config_path: PathBuf,
     data_path: PathBuf,
 }
 
-impl TryFrom<&Config> for Gistit {
-    type Error = Error;
-
-    fn try_from(value: &Config) -> std::result::Result<Self, Self::Error> {
-        Ok(Self {
-            hash: value.hash.to_owned(),
-            ..Self::default()
-        })
-    }
-}
-
 #[async_trait]
 impl Dispatch for Action {
     type InnerData = Config;
 
     async fn prepare(&self) -> Result<Self::InnerData> {
         progress!("Preparing");
-        let hash = check::hash(self.hash)?;
+        let runtime_path = path::runtime()?;
+        let p2p_only = self.p2p_only.then(|| Namespace::Peer);
+        let ((hash, namespace), author) = match (self.hash, self.author) {
+            (_, Some(author)) => ((None, p2p_only), Some(check::author_name(author)?)),
+            (Some(hash), None) => {
+                let (hash, namespace) = resolve::hash(hash, &runtime_path, p2p_only).await?;
+                ((Some(hash), namespace), None)
+            }
+            (None, None) => return Err(Error::Argument("missing argument", "[HASH]")),
+        };
+        if self.from_peer.is_some() && namespace == Some(Namespace::Server) {
+            return Err(Error::Argument(
+                "server hosted gistits can't be fetched from a peer",
+                "--from-peer",
+            ));
+        }
         let colorscheme = check::colorscheme(self.colorscheme)?;
+        let lines = self.lines.map(check::line_range).transpose()?;
+        let from_peer = self
+            .from_peer
+            .map(|peer_id| check::peer_id(peer_id, "--from-peer"))
+            .transpose()?;
+        let serve_once = if self.serve_once {
+            Some(Listen {
+                port: check::port_number(self.port.unwrap_or("0"))?,
+                lan: self.lan,
+            })
+        } else {
+            None
+        };
         updateln!("Prepared");
 
         Ok(Config {
             hash,
+            author,
             colorscheme,
             save: self.save,
-            runtime_path: path::runtime()?,
+            force: self.force,
+            backup: self.backup,
+            namespace,
+            from_peer,
+            lines,
+            serve_once,
+            runtime_path,
             config_path: path::config()?,
             data_path: path::data()?,
         })
     }
 
     async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
-        progress!("Fetching");
-        let mut bridge = gistit_ipc::client(&config.runtime_path)?;
+        if let Some(author) = config.author {
+            return fetch_by_author(author, self.save, self.post_fetch.as_ref(), &config).await;
+        }
+        let hash = config
+            .hash
+            .as_ref()
+            .expect("to have a hash unless listing by author");
+        if self.verify_only {
+            let metadata = fetch_metadata(hash, &config.runtime_path, config.namespace).await?;
+            format_metadata(&metadata);
+            finish!(format!("{}Exists", emoji("✅  ", "")));
+            return Ok(());
+        }
 
-        if bridge.alive() {
-            warnln!("gistit-daemon running, looking in the DHT");
-            bridge.connect_blocking()?;
-            bridge
-                .send(Instruction::request_fetch(self.hash.to_owned()))
-                .await?;
+        let gistit = fetch(
+            hash,
+            &config.runtime_path,
+            config.namespace,
+            config.from_peer,
+        )
+        .await?;
+        // Cached as fetched, only decrypted for this invocation
+        let gistit = if gistit.is_encrypted() {
+            let gistit = encrypt::open(&gistit, &encrypt::secret(false)?)?;
+            updateln!("Decrypted");
+            gistit
+        } else {
+            gistit
+        };
+        let gistit = match self.file_name {
+            Some(name) => only_file(gistit, name)?,
+            None => gistit,
+        };
 
-            if let ipc::instruction::Kind::FetchResponse(ipc::instruction::FetchResponse {
-                gistit: Some(gistit),
-            }) = bridge.recv().await?.expect_response()?
-            {
-                preview_or_save(&gistit, self.save, &config)?;
-            } else {
-                interruptln!();
-                errorln!("gistit hash not found");
+        if let Some(listen) = config.serve_once {
+            let inner = gistit.inner.first().ok_or(Error::Argument(
+                "the gistit has no file to serve",
+                "--serve-once",
+            ))?;
+            let download = Download {
+                name: name_from_path(Path::new(&inner.name)),
+                data: contents(&gistit, inner, config.lines)?,
+                binary: inner.is_binary,
+            };
+            return serve_once(download, listen).await;
+        }
+        if self.apply {
+            return apply(&gistit, config.colorscheme, self.yes);
+        }
+        if !self.save {
+            // Previews show them, they're kept here for the next time too
+            if let Err(err) = annotate::sync(&gistit.hash, config.namespace).await {
+                warnln!("failed to fetch annotations: {}", err);
             }
+        }
+        let saved = preview_or_save(&gistit, self.save, &config)?;
+        match &self.post_fetch {
+            Some(hook) => post_fetch(hook, &gistit, &saved).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs the `post-fetch` hook on each file of `gistit`, where they were saved or on temp copies if
+/// they weren't
+async fn post_fetch(hook: &Hook, gistit: &Gistit, saved: &[PathBuf]) -> Result<()> {
+    for (index, inner) in gistit.inner.iter().enumerate() {
+        if let Some(file_path) = saved.get(index) {
+            hook.run(file_path, gistit, index).await?;
+        } else {
+            let file = File::from_data(&inner.data, &inner.name)?;
+            hook.run(file.path(), gistit, index).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the public gistits of `author` to pick from, a page at a time, then fetches the picked
+/// ones. Without a terminal to pick in, the list is printed instead
+async fn fetch_by_author(
+    author: &str,
+    save: bool,
+    hook: Option<&Hook>,
+    config: &Config,
+) -> Result<()> {
+    progress!("Listing");
+    let first = explore::list_page(AUTHOR_PAGE_SIZE, None, Some(author)).await?;
+    let mut listed: Vec<Listed> = first.gistits;
+    let mut next = first.next;
+    updateln!("Listed");
+
+    if listed.is_empty() {
+        finish!(format!("No public gistits by '{}'", author));
+        return Ok(());
+    }
+    if !atty::is(atty::Stream::Stdin) || !Term::stderr().is_term() {
+        // Everything, not just the first page, when it's read by something else
+        while let Some(cursor) = next {
+            let page = explore::list_page(AUTHOR_PAGE_SIZE, Some(&cursor), Some(author)).await?;
+            listed.extend(page.gistits);
+            next = page.next;
+        }
+        progress::clear();
+        for entry in &listed {
+            println!("{}", entry);
+        }
+        return Ok(());
+    }
+    progress::clear();
+
+    let mut picked = vec![false; listed.len()];
+    let chosen = loop {
+        let mut items: Vec<String> = listed.iter().map(ToString::to_string).collect();
+        let mut defaults = picked.clone();
+        if next.is_some() {
+            items.push(style("(load more)").dim().italic().to_string());
+            defaults.push(false);
+        }
+
+        let selection = MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Public gistits by '{}' (space to pick, enter to fetch)",
+                author
+            ))
+            .items(&items)
+            .defaults(&defaults)
+            .max_length(AUTHOR_PAGE_SIZE as usize)
+            .interact_opt()?;
+        let selection = match selection {
+            Some(selection) => selection,
+            None => return Ok(()),
+        };
+
+        if selection.contains(&listed.len()) {
+            let page = explore::list_page(AUTHOR_PAGE_SIZE, next.as_deref(), Some(author)).await?;
+            picked = (0..listed.len())
+                .map(|index| selection.contains(&index))
+                .collect();
+            picked.resize(listed.len() + page.gistits.len(), false);
+            listed.extend(page.gistits);
+            next = page.next;
+        } else {
+            break selection;
+        }
+    };
+
+    for index in chosen {
+        let hash = GistitHash::parse(&listed[index].hash)
+            .map_err(|_| Error::Server("unexpected response"))?;
+        let gistit = fetch(&hash, &config.runtime_path, config.namespace, None).await?;
+        let saved = preview_or_save(&gistit, save, config)?;
+        if let Some(hook) = hook {
+            post_fetch(hook, &gistit, &saved).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies a patch gistit to the repository we are in. It's checked to apply cleanly first, then
+/// previewed and confirmed unless `yes`. Without a terminal to confirm it needs `yes`
+fn apply(gistit: &Gistit, colorscheme: &str, yes: bool) -> Result<()> {
+    // NOTE: Currently we support one file
+    let inner = match gistit.inner.first() {
+        Some(inner) if !inner.is_binary && inner.lang == "diff" => inner,
+        _ => {
+            return Err(Error::Argument(
+                "only patch gistits can be applied",
+                "--apply",
+            ))
+        }
+    };
+    let interactive = atty::is(atty::Stream::Stdin) && Term::stderr().is_term();
+    if !yes && !interactive {
+        return Err(Error::Argument(
+            "no terminal to confirm applying the patch, use '--yes' to apply it anyway",
+            "--yes",
+        ));
+    }
+
+    progress!("Checking patch");
+    let root = git::toplevel()?;
+    let stat = git::check(&root, &inner.data)?;
+    updateln!("Patch applies cleanly to '{}'", root.to_string_lossy());
+    cleanln!(format!("\n{}", stat));
+
+    if !yes {
+        preview(gistit, colorscheme, None)?;
+        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Apply this patch?")
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            finish!(format!("{}Not applied", emoji("✋  ", "")));
+            return Ok(());
+        }
+    }
+
+    progress!("Applying patch");
+    git::apply(&root, &inner.data)?;
+    updateln!("Applied, undo with `git apply -R`");
+    finish!(format!("{}Applied", emoji("🩹  ", "")));
+    Ok(())
+}
+
+/// Waits on the node's answer about `hash`. Datagrams carry no request id, so gistits answering
+/// an earlier request, e.g. one that timed out, are skipped
+async fn recv_for(
+    bridge: &mut gistit_ipc::Bridge<gistit_ipc::Client>,
+    hash: &GistitHash,
+) -> Result<ipc::instruction::Kind> {
+    loop {
+        match bridge.recv().await?.expect_response()? {
+            // Answering another request
+            ipc::instruction::Kind::FetchResponse(ipc::instruction::FetchResponse {
+                gistit: Some(gistit),
+            })
+            | ipc::instruction::Kind::MetadataResponse(ipc::instruction::MetadataResponse {
+                gistit: Some(gistit),
+            }) if *hash != gistit.hash => (),
+            response => return Ok(response),
+        }
+    }
+}
+
+/// Fetches a gistit from the local cache, the peer network if `gistit node` is running, or
+/// the server, in that order. Fetched gistits are cached. With `from_peer` only that peer is
+/// asked instead of looking up who provides the gistit, with a `namespace` only where it says
+///
+/// # Errors
+///
+/// Fails if the gistit can't be found anywhere, or in the peer network only if no peer provides it
+pub async fn fetch(
+    hash: &GistitHash,
+    runtime_path: &Path,
+    namespace: Option<Namespace>,
+    from_peer: Option<&str>,
+) -> Result<Gistit> {
+    let p2p_only = namespace == Some(Namespace::Peer);
+    progress!("Fetching");
+    let task = progress::task(hash.short(), "looking in cache");
+    if let Some(gistit) = cache::load(hash) {
+        task.done("Fetched from cache");
+        return Ok(gistit);
+    }
+
+    let mut bridge = gistit_ipc::client(runtime_path)?;
+
+    // Server hosted gistits aren't looked for in the DHT
+    let daemon_alive = namespace != Some(Namespace::Server) && bridge.alive();
+
+    if from_peer.is_some() && !daemon_alive {
+        return Err(Error::Argument(
+            "fetching from a peer needs a running gistit node, start it with `gistit node --start`",
+            "--from-peer",
+        ));
+    }
+
+    // Why the node didn't get it, if it said so
+    let mut failure = None;
+    let from_peers = if daemon_alive {
+        let wait = interrupt::timeout().unwrap_or(DHT_FETCH_TIMEOUT);
+        let instruction = if let Some(peer_id) = from_peer {
+            task.status(format!("asking {}", peer_id));
+            Instruction::request_fetch_from_peer(hash.clone(), peer_id.to_owned())
         } else {
-            let gistit: Gistit = (&config).try_into()?;
-
-            let response = reqwest::Client::new()
-                .post(SERVER_URL_GET.to_string())
-                .header("content-type", "application/x-protobuf")
-                .body(gistit.encode_to_vec())
-                .send()
-                .await?;
-            updateln!("Fetched");
-
-            match response.status() {
-                StatusCode::OK => {
-                    let gistit = Gistit::from_bytes(response.bytes().await?)?;
-                    preview_or_save(&gistit, self.save, &config)?;
+            task.status("looking in the DHT");
+            Instruction::request_fetch(hash.clone()).with_timeout(wait)
+        };
+        bridge.connect().await?;
+        bridge.send(instruction).await?;
+
+        match tokio::time::timeout(wait, recv_for(&mut bridge, hash)).await {
+            Ok(response) => match response? {
+                ipc::instruction::Kind::FetchResponse(ipc::instruction::FetchResponse {
+                    gistit,
+                }) => gistit,
+                ipc::instruction::Kind::ErrorResponse(err) => {
+                    failure = Some(Error::Daemon(err));
+                    None
                 }
-                StatusCode::NOT_FOUND => {
-                    return Err(Error::Server("gistit hash not found"));
+                _ => None,
+            },
+            Err(_) => None,
+        }
+    } else if p2p_only {
+        return Err(Error::Argument(
+            "gistit-daemon is not running",
+            "--p2p-only",
+        ));
+    } else {
+        None
+    };
+
+    let (gistit, on_server) = if let Some(gistit) = from_peers {
+        verify(&gistit)?;
+        task.done("Fetched from peers");
+        (gistit, false)
+    } else if p2p_only {
+        return Err(failure.unwrap_or(Error::NotFound("gistit hash not found in the DHT")));
+    } else {
+        match failure {
+            Some(err) => task.warn(format!("{}, falling back to the server", err)),
+            None if daemon_alive => {
+                task.warn("no peers provided this gistit, falling back to the server");
+            }
+            None => (),
+        }
+        task.status("asking the server");
+        let gistit = fetch_from_server(hash).await?;
+        task.done("Fetched from server");
+        (gistit, true)
+    };
+
+    // Not being able to cache shouldn't fail the fetch
+    if let Err(err) = cache::store(&gistit) {
+        warnln!("failed to cache gistit: {}", err);
+    }
+    history::record(&gistit, Origin::Fetched, on_server);
+
+    Ok(gistit)
+}
+
+/// Looks a gistit up in the local cache, the peer network if `gistit node` is running, or the
+/// server, in that order, or only where `namespace` says. Only its metadata is transferred, file
+/// contents come back empty
+///
+/// # Errors
+///
+/// Fails if the gistit can't be found anywhere, or in the peer network only if no peer has it
+pub async fn fetch_metadata(
+    hash: &GistitHash,
+    runtime_path: &Path,
+    namespace: Option<Namespace>,
+) -> Result<Gistit> {
+    let p2p_only = namespace == Some(Namespace::Peer);
+    progress!("Verifying");
+    let task = progress::task(hash.short(), "looking in cache");
+    if let Some(gistit) = cache::load(hash) {
+        task.done("Found in cache");
+        return Ok(gistit.metadata());
+    }
+
+    let mut bridge = gistit_ipc::client(runtime_path)?;
+    let daemon_alive = namespace != Some(Namespace::Server) && bridge.alive();
+
+    let mut failure = None;
+    if daemon_alive {
+        task.status("looking in the DHT");
+        let wait = interrupt::timeout().unwrap_or(DHT_FETCH_TIMEOUT);
+        bridge.connect().await?;
+        bridge
+            .send(Instruction::request_metadata(hash.clone()).with_timeout(wait))
+            .await?;
+
+        if let Ok(response) = tokio::time::timeout(wait, recv_for(&mut bridge, hash)).await {
+            match response? {
+                ipc::instruction::Kind::MetadataResponse(ipc::instruction::MetadataResponse {
+                    gistit: Some(gistit),
+                }) => {
+                    task.done("Found in the peer network");
+                    return Ok(gistit);
                 }
-                _ => return Err(Error::Server("unexpected response")),
+                ipc::instruction::Kind::ErrorResponse(err) => failure = Some(Error::Daemon(err)),
+                _ => (),
             }
         }
+    } else if p2p_only {
+        return Err(Error::Argument(
+            "gistit-daemon is not running",
+            "--p2p-only",
+        ));
+    }
+
+    if p2p_only {
+        return Err(failure.unwrap_or(Error::NotFound("gistit hash not found in the DHT")));
+    }
+    match failure {
+        Some(err) => task.warn(format!("{}, falling back to the server", err)),
+        None if daemon_alive => task.warn("no peers know this gistit, falling back to the server"),
+        None => (),
+    }
+    task.status("asking the server");
+    let gistit = fetch_metadata_from_server(hash).await?;
+    task.done("Found on the server");
+    Ok(gistit)
+}
+
+/// Asks the server for a gistit without its file contents. Unlike a fetch, this never burns a
+/// burn after read gistit
+///
+/// # Errors
+///
+/// Fails if the hash is not found, the server responds unexpectedly or with another gistit
+pub async fn fetch_metadata_from_server(hash: &GistitHash) -> Result<Gistit> {
+    let client = http::Client::new();
+    let response = client
+        .send(http::with_gistit(
+            client.post(SERVER_URL_HEAD.to_string()),
+            &Gistit {
+                hash: hash.to_string(),
+                ..Gistit::default()
+            },
+        )?)
+        .await?;
+
+    match response.status() {
+        StatusCode::OK => {
+            let gistit = http::gistit_from(response).await?;
+            requested(hash, &gistit)?;
+            Ok(gistit)
+        }
+        StatusCode::NOT_FOUND => Err(Error::NotFound("gistit hash not found")),
+        _ => Err(Error::Server("unexpected response")),
+    }
+}
+
+/// Prints what a gistit is, its contents can't be checked against the hash without fetching it
+fn format_metadata(gistit: &Gistit) {
+    let size: u32 = gistit.inner.iter().map(|inner| inner.size).sum();
+    let files = gistit
+        .inner
+        .iter()
+        .map(|inner| inner.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let age = gistit
+        .timestamp
+        .parse::<u64>()
+        .ok()
+        .zip(SystemTime::now().duration_since(UNIX_EPOCH).ok())
+        .map_or_else(
+            || gistit.timestamp.clone(),
+            |(timestamp, now)| ago(now.as_secs().saturating_sub(timestamp / 1000)),
+        );
 
+    let files = match gistit.encryption {
+        Some(ref encryption) => format!("encrypted with {}", encryption.scheme),
+        None => files,
+    };
+
+    let amends = gistit.previous_hash().map_or_else(String::new, |previous| {
+        format!("\n    amends: '{}'", style(previous).dim())
+    });
+
+    cleanln!(format!(
+        "\n    hash: '{}'{}\n    file: {}, {} bytes\n    by {}, {}, {}{}\n",
+        style(&gistit.hash).bold(),
+        amends,
+        files,
+        size,
+        style(&gistit.author).blue().bold(),
+        age,
+        gistit.visibility(),
+        gistit
+            .description
+            .as_ref()
+            .map_or_else(String::new, |description| format!(
+                "\n    {}",
+                style(description).italic()
+            ))
+    ));
+}
+
+/// Fetches the gistit under `hash` from the server
+///
+/// # Errors
+///
+/// Fails if the hash is not found, the server responds unexpectedly or with a gistit that isn't
+/// the one under `hash`
+pub async fn fetch_from_server(hash: &GistitHash) -> Result<Gistit> {
+    let client = http::Client::new();
+    let response = client
+        .send(http::with_gistit(
+            client.post(SERVER_URL_GET.to_string()),
+            &Gistit {
+                hash: hash.to_string(),
+                ..Gistit::default()
+            },
+        )?)
+        .await?;
+
+    match response.status() {
+        StatusCode::OK => {
+            let gistit = http::gistit_from(response).await?;
+            requested(hash, &gistit)?;
+            verify(&gistit)?;
+            Ok(gistit)
+        }
+        StatusCode::NOT_FOUND => Err(Error::NotFound("gistit hash not found")),
+        _ => Err(Error::Server("unexpected response")),
+    }
+}
+
+/// Checks the server answered with the gistit under `hash`. [`verify`] only checks it against
+/// the hash it claims, any other gistit would pass
+fn requested(hash: &GistitHash, gistit: &Gistit) -> Result<()> {
+    if gistit.hash == hash.as_str() {
         Ok(())
+    } else {
+        Err(Error::Integrity(format!("{} (got {})", hash, gistit.hash)))
     }
 }
 
-pub fn preview_or_save(gistit: &Gistit, save: bool, config: &Config) -> Result<()> {
-    // NOTE: Currently we support one file
-    let inner = gistit.inner.first().expect("to have at least one file");
-    let mut file = File::from_data(&inner.data, &inner.name)?;
-    let save_location = &config.data_path;
+/// The gistit with only the file named `name`, either its full name or just the file name if no
+/// other file has it too
+fn only_file(mut gistit: Gistit, name: &str) -> Result<Gistit> {
+    let index = file_index(&gistit, name)?;
+    gistit.inner = vec![gistit.inner.swap_remove(index)];
+    Ok(gistit)
+}
+
+fn file_index(gistit: &Gistit, name: &str) -> Result<usize> {
+    if let Some(index) = gistit.inner.iter().position(|inner| inner.name == name) {
+        return Ok(index);
+    }
+
+    let mut named = gistit
+        .inner
+        .iter()
+        .enumerate()
+        .filter(|(_, inner)| name_from_path(Path::new(&inner.name)) == name)
+        .map(|(index, _)| index);
+    match (named.next(), named.next()) {
+        (Some(index), None) => Ok(index),
+        (Some(_), Some(_)) => Err(Error::Argument(
+            "several files have this name, pick one by its full name",
+            "--file-name",
+        )),
+        (None, _) => Err(Error::Argument(
+            "no such file in the gistit, `--verify-only` lists them",
+            "--file-name",
+        )),
+    }
+}
 
-    if save {
-        let file_path = save_location.join(file.name());
-        file.save_as(&file_path)?;
+/// Previews the gistit, or saves its files and returns where
+pub fn preview_or_save(gistit: &Gistit, save: bool, config: &Config) -> Result<Vec<PathBuf>> {
+    // Binary gistits can't be previewed
+    if save || gistit.inner.iter().all(|inner| inner.is_binary) {
+        let overwrite = overwrite(gistit, config)?;
+        let saved = save_to(gistit, &config.data_path, config.lines, overwrite)?;
 
-        warnln!("gistit saved at: `{}`", file_path.to_string_lossy());
-        finish!("💾  Saved");
+        for (file_path, backup) in &saved {
+            if let Some(backup) = backup {
+                warnln!("existing file moved to: `{}`", backup.to_string_lossy());
+            }
+            warnln!("gistit saved at: `{}`", file_path.to_string_lossy());
+        }
+        finish!(format!("{}Saved", emoji("💾  ", "")));
+        Ok(saved.into_iter().map(|(file_path, _)| file_path).collect())
     } else {
-        finish!("👀  Preview");
-        let mut header_string = style(&inner.name).green().to_string();
-        header_string.push_str(&format!(" | {}", style(&gistit.author).blue().bold()));
+        finish!(format!("{}Preview", emoji("👀  ", "")));
+        preview(gistit, config.colorscheme, config.lines)?;
+        Ok(Vec::new())
+    }
+}
+
+/// How to treat a file already at the save path. Without `--force` or `--backup` we ask, if
+/// there is someone to ask
+fn overwrite(gistit: &Gistit, config: &Config) -> Result<Overwrite> {
+    if config.force {
+        return Ok(Overwrite::Replace);
+    }
+    if config.backup {
+        return Ok(Overwrite::Backup);
+    }
 
-        if let Some(ref description) = gistit.description {
-            header_string.push_str(&format!(" | {}", style(description).italic()));
+    let existing: Vec<String> = save_paths(gistit, &config.data_path)
+        .into_iter()
+        .filter(|file_path| file_path.exists())
+        .map(|file_path| format!("`{}`", file_path.to_string_lossy()))
+        .collect();
+    if !existing.is_empty() && atty::is(atty::Stream::Stdin) && Term::stderr().is_term() {
+        let replace = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(if existing.len() == 1 {
+                format!("{} already exists, overwrite it?", existing[0])
+            } else {
+                format!("{} already exist, overwrite them?", existing.join(", "))
+            })
+            .default(false)
+            .interact()?;
+        if replace {
+            return Ok(Overwrite::Replace);
         }
+    }
+    Ok(Overwrite::Refuse)
+}
+
+/// Names the gistit files are shown and saved under: their file name, or their whole path if
+/// another file has the same file name
+fn file_names(gistit: &Gistit) -> Vec<String> {
+    let names: Vec<String> = gistit
+        .inner
+        .iter()
+        .map(|inner| name_from_path(Path::new(&inner.name)))
+        .collect();
+    gistit
+        .inner
+        .iter()
+        .zip(&names)
+        .map(|(inner, name)| {
+            if names.iter().filter(|other| *other == name).count() > 1 {
+                relative_name(&inner.name)
+            } else {
+                name.clone()
+            }
+        })
+        .collect()
+}
+
+/// The path of a file without its root or `..`, so it stays inside wherever it's saved
+fn relative_name(name: &str) -> String {
+    Path::new(name)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Where each gistit file is saved inside `dir`
+#[must_use]
+pub fn save_paths(gistit: &Gistit, dir: &Path) -> Vec<PathBuf> {
+    file_names(gistit)
+        .into_iter()
+        .map(|name| dir.join(name))
+        .collect()
+}
+
+/// Writes the gistit files into `dir`, optionally only a range of lines. Binary files are written
+/// byte for byte. Returns where each was saved and, if a file was there already and backed up,
+/// where that went
+///
+/// # Errors
+///
+/// Fails if a binary gistit is sliced by lines, is corrupted, a file is in the way and `overwrite`
+/// refuses, or a file can't be written. Nothing is written if a file is refused
+pub fn save_to(
+    gistit: &Gistit,
+    dir: &Path,
+    lines: Option<(usize, usize)>,
+    overwrite: Overwrite,
+) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+    let file_paths = save_paths(gistit, dir);
+    if overwrite == Overwrite::Refuse && file_paths.iter().any(|file_path| file_path.exists()) {
+        return Err(file::already_exists());
+    }
+
+    gistit
+        .inner
+        .iter()
+        .zip(file_paths)
+        .map(|(inner, file_path)| {
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let backup = file::save(&file_path, &contents(gistit, inner, lines)?, overwrite)?;
+            Ok((file_path, backup))
+        })
+        .collect()
+}
+
+/// What a gistit file holds, optionally only a range of lines. Binary files are decoded
+///
+/// # Errors
+///
+/// Fails if a binary file is sliced by lines or is corrupted
+pub fn contents(gistit: &Gistit, inner: &Inner, lines: Option<(usize, usize)>) -> Result<Vec<u8>> {
+    if inner.is_binary {
+        if lines.is_some() {
+            return Err(Error::Argument(
+                "binary gistits can't be sliced by lines",
+                "--lines",
+            ));
+        }
+        return decode_binary(gistit, inner);
+    }
+
+    Ok(lines
+        .map_or_else(
+            || inner.data.clone(),
+            |range| slice_lines(&inner.data, range),
+        )
+        .into_bytes())
+}
+
+/// Raw bytes of a binary file, the base64 encoding is covered by the integrity checks but a
+/// broken one is caught here
+///
+/// # Errors
+///
+/// Fails with [`Error::Integrity`] if the data isn't valid base64 or doesn't match the size
+pub fn decode_binary(gistit: &Gistit, inner: &Inner) -> Result<Vec<u8>> {
+    match base64::decode(&inner.data) {
+        Ok(bytes) if bytes.len() == inner.size as usize => Ok(bytes),
+        _ => Err(Error::Integrity(format!(
+            "{} ({})",
+            gistit.hash, inner.name
+        ))),
+    }
+}
+
+/// Raw bytes of a file bat can't show, binary or full of control characters, `None` for text
+///
+/// # Errors
+///
+/// Fails with [`Error::Integrity`] if a binary file is corrupted
+pub fn unprintable(gistit: &Gistit, inner: &Inner) -> Result<Option<Vec<u8>>> {
+    if inner.is_binary {
+        return decode_binary(gistit, inner).map(Some);
+    }
+    Ok((!sniff::is_text(inner.data.as_bytes())).then(|| inner.data.clone().into_bytes()))
+}
+
+/// Keeps only the lines within an inclusive, 1-based range
+fn slice_lines(data: &str, (start, end): (usize, usize)) -> String {
+    data.split_inclusive('\n')
+        .skip(start - 1)
+        .take(end - start + 1)
+        .collect()
+}
+
+/// Renders the gistit inside the terminal using `bat`, optionally only a range of lines.
+/// Annotated lines are highlighted and their notes listed under each file.
+/// A markdown description goes first. Gistits with several files get a tab line listing them
+/// and, with someone at the terminal, a picker to cycle between them. Otherwise every file is
+/// printed in turn
+///
+/// # Errors
+///
+/// Fails if the temporary file can't be created or `bat` fails to print
+pub fn preview(gistit: &Gistit, colorscheme: &str, lines: Option<(usize, usize)>) -> Result<()> {
+    let mut gistit = gistit.clone();
+    if let Some(description) = take_description(&mut gistit) {
+        preview_description(&description, colorscheme)?;
+    }
+    let gistit = &gistit;
+
+    let count = gistit.inner.len();
+    if count < 2 || !atty::is(atty::Stream::Stdin) || !Term::stderr().is_term() {
+        for index in 0..count {
+            preview_file(gistit, index, colorscheme, lines)?;
+        }
+        return Ok(());
+    }
+
+    let names: Vec<&str> = gistit
+        .inner
+        .iter()
+        .map(|inner| inner.name.as_str())
+        .collect();
+    let mut current = 0;
+    loop {
+        preview_file(gistit, current, colorscheme, lines)?;
+
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Show file (esc to quit)")
+            .items(&names)
+            .default((current + 1) % count)
+            .interact_opt()?;
+        match selection {
+            Some(index) => current = index,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Takes the markdown description out of the files. A gistit left with only its description,
+/// e.g. picked with `--file-name`, keeps it as a regular file
+fn take_description(gistit: &mut Gistit) -> Option<Inner> {
+    let index = gistit.inner.iter().position(|inner| inner.is_description)?;
+    (gistit.inner.len() > 1).then(|| gistit.inner.remove(index))
+}
+
+/// Renders a markdown description, highlighted by `bat` and never paged so the files follow it
+fn preview_description(inner: &Inner, colorscheme: &str) -> Result<()> {
+    let file = File::from_data(&inner.data, &inner.name)?;
+    let input = bat::Input::from_reader(&*file)
+        .name(&inner.name)
+        .title(format!(
+            "{} | {}",
+            style(&inner.name).green(),
+            style("description").dim()
+        ));
+
+    bat::PrettyPrinter::new()
+        .header(true)
+        .grid(true)
+        .input(input)
+        .language("Markdown")
+        .line_numbers(false)
+        .theme(colorscheme)
+        .use_italics(true)
+        .colored_output(colors_enabled())
+        .paging_mode(bat::PagingMode::Never)
+        .print()?;
+
+    Ok(())
+}
+
+/// Names of every file of the gistit, the one at `current` highlighted
+fn tabs(gistit: &Gistit, current: usize) -> String {
+    file_names(gistit)
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| {
+            if index == current {
+                style(format!("[{}]", name)).green().bold().to_string()
+            } else {
+                style(format!(" {} ", name)).dim().to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Renders the file at `index` of the gistit
+fn preview_file(
+    gistit: &Gistit,
+    index: usize,
+    colorscheme: &str,
+    lines: Option<(usize, usize)>,
+) -> Result<()> {
+    let inner = &gistit.inner[index];
+    if gistit.inner.len() > 1 {
+        progress::line(format!(
+            "{} {}",
+            style(format!("{}/{}", index + 1, gistit.inner.len())).dim(),
+            tabs(gistit, index)
+        ));
+    }
+    if let Some(data) = unprintable(gistit, inner)? {
+        progress::line(format!(
+            "{} {}\n{}",
+            style(&inner.name).green(),
+            style(format!(
+                "{}. Save it with `gistit fetch --save`",
+                sniff::summary(&data)
+            ))
+            .dim(),
+            sniff::hexdump(&data, sniff::HEXDUMP_LINES)
+        ));
+        return Ok(());
+    }
+    let file = File::from_data(&inner.data, &inner.name)?;
 
-        let input = bat::Input::from_reader(&*file)
-            .name(&inner.name)
-            .title(header_string);
+    let mut header_string = style(&inner.name).green().to_string();
+    if let Some(ref snippet) = inner.snippet {
+        let lines = format!("lines {}-{}", snippet.start_line, snippet.end_line);
+        header_string.push_str(&format!(
+            " {}",
+            style(match snippet.symbol {
+                Some(ref symbol) => format!("{}, {}", symbol, lines),
+                None => lines,
+            })
+            .dim()
+        ));
+    }
+    header_string.push_str(&format!(" | {}", style(&gistit.author).blue().bold()));
+
+    if let Some(ref description) = gistit.description {
+        header_string.push_str(&format!(" | {}", style(description).italic()));
+    }
+    if let Some(previous) = gistit.previous_hash() {
+        header_string.push_str(&format!(
+            " | {}",
+            style(format!("amends {}", previous.get(..8).unwrap_or(previous))).dim()
+        ));
+    }
+    // Most gistits are unlisted, only the others say so
+    if gistit.visibility() != Visibility::Unlisted {
+        header_string.push_str(&format!(" | {}", style(gistit.visibility()).dim()));
+    }
+
+    let input = bat::Input::from_reader(&*file)
+        .name(&inner.name)
+        .title(header_string);
+
+    let mut printer = bat::PrettyPrinter::new();
+    printer
+        .header(true)
+        .grid(true)
+        .input(input)
+        .line_numbers(true)
+        .theme(colorscheme)
+        .use_italics(true)
+        .colored_output(colors_enabled())
+        .paging_mode(bat::PagingMode::QuitIfOneScreen);
 
-        bat::PrettyPrinter::new()
-            .header(true)
-            .grid(true)
-            .input(input)
-            .line_numbers(true)
-            .theme(config.colorscheme)
-            .use_italics(true)
-            .paging_mode(bat::PagingMode::QuitIfOneScreen)
-            .print()?;
+    // The name may not tell, stdin for instance, so the language sent along is what's highlighted
+    let language = syntax::token(&inner.lang);
+    if let Some(ref language) = language {
+        printer.language(language);
     }
+    if let Some((start, end)) = lines {
+        printer.line_ranges(LineRanges::from(vec![LineRange::new(start, end)]));
+    }
+    let (start, end) = lines.unwrap_or((1, usize::MAX));
+    let mut annotations: Vec<_> = annotate::local(&gistit.hash)
+        .into_iter()
+        .filter(|annotation| annotation.file == inner.name)
+        .filter(|annotation| (start..=end).contains(&(annotation.line as usize)))
+        .collect();
+    annotations.sort_by_key(|annotation| annotation.line);
+    for annotation in &annotations {
+        printer.highlight(annotation.line as usize);
+    }
+
+    printer.print()?;
+
+    if !annotations.is_empty() {
+        progress::line(
+            annotations
+                .iter()
+                .map(annotate::format)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gistit_of(names: &[&str]) -> Gistit {
+        Gistit {
+            hash: "a".repeat(64),
+            inner: names
+                .iter()
+                .map(|name| {
+                    Gistit::new_inner((*name).to_owned(), "text".to_owned(), 0, (*name).to_owned())
+                })
+                .collect(),
+            ..Gistit::default()
+        }
+    }
+
+    fn action(hash: Option<&'static str>, author: Option<&'static str>) -> Action {
+        Action {
+            hash,
+            author,
+            colorscheme: "Monokai Extended Origin",
+            save: false,
+            force: false,
+            backup: false,
+            p2p_only: false,
+            from_peer: None,
+            lines: None,
+            verify_only: false,
+            serve_once: false,
+            port: None,
+            lan: false,
+            apply: false,
+            yes: false,
+            file_name: None,
+            post_fetch: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_by_author_needs_no_hash() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        std::env::set_var(gistit_project::env::GISTIT_RUNTIME_VAR, tmp.path());
+        std::env::set_var(gistit_project::env::GISTIT_CONFIG_VAR, tmp.path());
+        std::env::set_var(gistit_project::env::GISTIT_DATA_VAR, tmp.path());
+
+        let config = action(None, Some("fabricio")).prepare().await.unwrap();
+        assert_eq!(config.runtime_path, tmp.path());
+        assert_eq!(config.author, Some("fabricio"));
+        assert!(config.hash.is_none());
+        // Listing wins over the hash, which isn't looked up
+        let config = action(Some("not-a-hash"), Some("fabricio"))
+            .prepare()
+            .await
+            .unwrap();
+        assert!(config.hash.is_none());
+
+        assert!(action(None, Some("fa")).prepare().await.is_err());
+        assert!(matches!(
+            action(None, None).prepare().await,
+            Err(Error::Argument(_, "[HASH]"))
+        ));
+    }
+
+    #[test]
+    fn fetch_only_the_requested_gistit() {
+        let mut gistit = gistit_of(&["main.rs"]);
+        gistit.hash = gistit.checksum();
+        let hash = GistitHash::parse(&gistit.hash).unwrap();
+        assert!(requested(&hash, &gistit).is_ok());
+
+        // Intact, just not the one asked for
+        let mut other = gistit_of(&["lib.rs"]);
+        other.hash = other.checksum();
+        assert!(verify(&other).is_ok());
+        assert!(matches!(requested(&hash, &other), Err(Error::Integrity(_))));
+    }
+
+    #[test]
+    fn fetch_applies_only_patches() {
+        for gistit in [gistit_of(&[]), gistit_of(&["main.rs"])] {
+            assert!(matches!(
+                apply(&gistit, "Monokai Extended Origin", true),
+                Err(Error::Argument(_, "--apply"))
+            ));
+        }
+    }
+
+    #[test]
+    fn fetch_colliding_file_names() {
+        let gistit = gistit_of(&["src/main.rs", "/tests/../main.rs", "lib.rs"]);
+        assert_eq!(
+            file_names(&gistit),
+            vec!["src/main.rs", "tests/main.rs", "lib.rs"]
+        );
+
+        assert!(only_file(gistit.clone(), "main.rs").is_err());
+        assert!(only_file(gistit.clone(), "nope.rs").is_err());
+        let picked = only_file(gistit.clone(), "src/main.rs").unwrap();
+        assert_eq!(picked.inner[0].name, "src/main.rs");
+        let picked = only_file(gistit, "lib.rs").unwrap();
+        assert_eq!(picked.inner.len(), 1);
+        assert_eq!(picked.inner[0].name, "lib.rs");
+    }
+
+    #[test]
+    fn fetch_saves_every_file() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let gistit = gistit_of(&["a/main.rs", "b/main.rs", "lib.rs"]);
+
+        let saved = save_to(&gistit, tmp.path(), None, Overwrite::Refuse).unwrap();
+        let paths: Vec<PathBuf> = saved.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(paths, save_paths(&gistit, tmp.path()));
+        assert_eq!(paths[0], tmp.path().join("a/main.rs"));
+        for (path, inner) in paths.iter().zip(&gistit.inner) {
+            assert_eq!(fs::read_to_string(path).unwrap(), inner.data);
+        }
+
+        // One file in the way and nothing is written
+        fs::remove_file(&paths[1]).unwrap();
+        assert!(save_to(&gistit, tmp.path(), None, Overwrite::Refuse).is_err());
+        assert!(!paths[1].exists());
+
+        let saved = save_to(&gistit, tmp.path(), None, Overwrite::Backup).unwrap();
+        assert!(saved[0].1.is_some());
+        assert!(saved[1].1.is_none());
+    }
+}
@@ -2,592 +2,38 @@
 //!
 //! Here we define file structures and methods. It is implemented using [`tokio`] so we don't block
 //! progress output during the process.
+//!
+//! Contents are read once into a buffer of the file size, which is moved into the payload
+//! instead of copied.
 
 use std::env::temp_dir;
 use std::ffi::OsStr;
-use std::fs::{self, write};
-use std::io::{BufReader, Read, Seek, Write};
+use std::fs;
+use std::io::{Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::str;
 
-use phf::{phf_map, Map};
 use rand::{distributions::Alphanumeric, Rng};
+use url::Url;
 
-use gistit_project::var::GISTIT_MAX_SIZE;
+use crate::temp;
+use crate::{Error, Result};
 
-use crate::Result;
+pub use gistit_project::lang::EXTENSION_TO_LANG_MAPPING;
 
-/// Supported file extensions
-/// This is a compile time built hashmap to check incomming file extensions against.
-/// Follows the extensions supported by currently UI syntax highlighting lib:
-/// [`react-syntax-highlighter`](https://gist.github.com/ppisarczyk/43962d06686722d26d176fad46879d41)
-///
-/// `Bat` does autodetection so this doesn't affect it.
-///
-/// Filled with [Programming languages](https://gist.github.com/ppisarczyk/43962d06686722d26d176fad46879d41)
-/// and some google help.
-pub const EXTENSION_TO_LANG_MAPPING: Map<&'static str, &'static str> = phf_map! {
-    "abap" => "abap",
-    "as" => "actionscript",
-    "ada" => "ada",
-    "adb" => "ada",
-    "ads" => "ada",
-    "agda" => "agda",
-    "als" => "al",
-    "g4" => "antlr4",
-    "apacheconf" => "apacheconf",
-    "vhost" => "apacheconf",
-    "apl" => "apl",
-    "dyalog" => "apl",
-    "applescript" => "applescript",
-    "scpt" => "applescript",
-    "ino" => "arduino",
-    "asciidoc" => "asciidoc",
-    "adoc" => "asciidoc",
-    "asc" => "asciidoc",
-    "asm" => "asm6502",
-    "a51" => "asm6502",
-    "nasm" => "asm6502",
-    "asp" => "aspnet",
-    "asax" => "aspnet",
-    "ascx" => "aspnet",
-    "ashx" => "aspnet",
-    "asmx" => "aspnet",
-    "aspx" => "aspnet",
-    "axd" => "aspnet",
-    "md" => "markdown",
-    "ts" => "typescript",
-    "rs" => "rust",
-    "toml" => "toml",
-    "ahk" => "autohotkey",
-    "ahkl" => "autohotkey",
-    "au3" => "autoit",
-    "sh" => "bash",
-    "bash" => "bash",
-    "bats" => "bash",
-    "cgi" => "bash",
-    "command" => "bash",
-    "fcgi" => "bash",
-    "ksh" => "bash",
-    "sh.in" => "bash",
-    "tmux" => "bash",
-    "tool" => "bash",
-    "zsh" => "bash",
-    "vb" => "basic",
-    "bas" => "basic",
-    "cls" => "basic",
-    "frm" => "basic",
-    "frx" => "basic",
-    "vba" => "basic",
-    "vbhtml" => "basic",
-    "vbs" => "basic",
-    "bat" => "batch",
-    "cmd" => "batch",
-    "bb" => "bbcode",
-    "bison" => "bison",
-    "b" => "brainfuck",
-    "bf" => "brainfuck",
-    "brs" => "brightscript",
-    "bro" => "bro",
-    "bsl" => "bsl",
-    "c" => "c",
-    "cats" => "c",
-    "idc" => "c",
-    "w" => "c",
-    "cil" => "cil",
-    "clike" => "clike",
-    "clj" => "clojure",
-    "boot" => "clojure",
-    "cl2" => "clojure",
-    "cljc" => "clojure",
-    "cljs" => "clojure",
-    "cljs.hl" => "clojure",
-    "cljscm" => "clojure",
-    "cljx" => "clojure",
-    "hic" => "clojure",
-    "cmake" => "cmake",
-    "cmake.in" => "cmake",
-    "coffee" => "coffeescript",
-    "_coffee" => "coffeescript",
-    "cjsx" => "coffeescript",
-    "cson" => "coffeescript",
-    "iced" => "coffeescript",
-    "conc" => "concurnas",
-    "cpp" => "cpp",
-    "c++" => "cpp",
-    "cc" => "cpp",
-    "cp" => "cpp",
-    "cxx" => "cpp",
-    "h" => "cpp",
-    "h++" => "cpp",
-    "hh" => "cpp",
-    "hpp" => "cpp",
-    "hxx" => "cpp",
-    "inc" => "cpp",
-    "inl" => "cpp",
-    "ipp" => "cpp",
-    "tcc" => "cpp",
-    "tpp" => "cpp",
-    "cr" => "crystal",
-    "csx" => "csharp",
-    "cshtml" => "csharp",
-    "csp" => "csp",
-    "css" => "css",
-    "cypher" => "cypher",
-    "d" => "d",
-    "di" => "d",
-    "dart" => "dart",
-    "dax" => "dax",
-    "dhall" => "dhall",
-    "diff" => "diff",
-    "patch" => "diff",
-    "mustache" => "django",
-    "jinja" => "django",
-    "zone" => "dnsZoneFile",
-    "arpa" => "dnsZoneFile",
-    "dockerfile" => "docker",
-    "ebnf" => "ebnf",
-    "editorconfig" => "editorconfig",
-    "e" => "eiffel",
-    "ejs" => "ejs",
-    "ex" => "elixir",
-    "exs" => "elixir",
-    "elm" => "elm",
-    "erb" => "erb",
-    "erb.deface" => "erb",
-    "erl" => "erlang",
-    "es" => "erlang",
-    "escript" => "erlang",
-    "hrl" => "erlang",
-    "xrl" => "erlang",
-    "yrl" => "erlang",
-    "etlua" => "etlua",
-    "xls" => "excelFormula",
-    "xlsx" => "excelFormula",
-    "factor" => "factor",
-    "rules" => "firestoreSecurityRules",
-    "flow" => "flow",
-    "f90" => "fortran",
-    "f" => "fortran",
-    "f03" => "fortran",
-    "f08" => "fortran",
-    "f77" => "fortran",
-    "f95" => "fortran",
-    "for" => "fortran",
-    "fpp" => "fortran",
-    "fs" => "fsharp",
-    "fsi" => "fsharp",
-    "fsx" => "fsharp",
-    "ftl" => "ftl",
-    "g" => "gcode",
-    "gco" => "gcode",
-    "gcode" => "gcode",
-    "gd" => "gdscript",
-    "gedcom" => "gedcom",
-    "feature" => "gherkin",
-    "git" => "git",
-    "glsl" => "glsl",
-    "fp" => "glsl",
-    "frag" => "glsl",
-    "frg" => "glsl",
-    "fsh" => "glsl",
-    "fshader" => "glsl",
-    "geo" => "glsl",
-    "geom" => "glsl",
-    "glslv" => "glsl",
-    "gshader" => "glsl",
-    "shader" => "glsl",
-    "vert" => "glsl",
-    "vrx" => "glsl",
-    "vsh" => "glsl",
-    "vshader" => "glsl",
-    "gml" => "gml",
-    "go" => "go",
-    "graphql" => "graphql",
-    "groovy" => "groovy",
-    "grt" => "groovy",
-    "gtpl" => "groovy",
-    "gvy" => "groovy",
-    "haml" => "haml",
-    "haml.deface" => "haml",
-    "handlebars" => "handlebars",
-    "hbs" => "handlebars",
-    "hs" => "haskell",
-    "hsc" => "haskell",
-    "hx" => "haxe",
-    "hxsl" => "haxe",
-    "hcl" => "hcl",
-    "tf" => "hcl",
-    "hlsl" => "hlsl",
-    "fx" => "hlsl",
-    "fxh" => "hlsl",
-    "hlsli" => "hlsl",
-    "hpkp" => "hpkp",
-    "hsts" => "hsts",
-    "http" => "http",
-    "ico" => "icon",
-    "ni" => "inform7",
-    "i7x" => "inform7",
-    "ini" => "ini",
-    "cfg" => "ini",
-    "prefs" => "ini",
-    "pro" => "ini",
-    "io" => "io",
-    "j" => "j",
-    "java" => "java",
-    "js" => "javascript",
-    "_js" => "javascript",
-    "bones" => "javascript",
-    "es6" => "javascript",
-    "gs" => "javascript",
-    "jake" => "javascript",
-    "jsb" => "javascript",
-    "jscad" => "javascript",
-    "jsfl" => "javascript",
-    "jsm" => "javascript",
-    "jss" => "javascript",
-    "njs" => "javascript",
-    "pac" => "javascript",
-    "sjs" => "javascript",
-    "ssjs" => "javascript",
-    "sublime-build" => "javascript",
-    "sublime-commands" => "javascript",
-    "sublime-completions" => "javascript",
-    "sublime-keymap" => "javascript",
-    "sublime-macro" => "javascript",
-    "sublime-menu" => "javascript",
-    "sublime-mousemap" => "javascript",
-    "sublime-project" => "javascript",
-    "sublime-settings" => "javascript",
-    "sublime-theme" => "javascript",
-    "sublime-workspace" => "javascript",
-    "sublime_metrics" => "javascript",
-    "sublime_session" => "javascript",
-    "xsjs" => "javascript",
-    "xsjslib" => "javascript",
-    "jolie" => "jolie",
-    "jq" => "jq",
-    "json" => "json",
-    "geojson" => "json",
-    "lock" => "json",
-    "topojson" => "json",
-    "json5" => "json5",
-    "jsonp" => "jsonp",
-    "jsx" => "jsx",
-    "jl" => "julia",
-    "keyman" => "keyman",
-    "kt" => "kotlin",
-    "ktm" => "kotlin",
-    "kts" => "kotlin",
-    "latex" => "latex",
-    "latte" => "latte",
-    "less" => "less",
-    "ly" => "lilypond",
-    "ily" => "lilypond",
-    "liquid" => "liquid",
-    "nl" => "lisp",
-    "lisp" => "lisp",
-    "lsp" => "lisp",
-    "ls" => "livescript",
-    "_ls" => "livescript",
-    "ll" => "llvm",
-    "lol" => "lolcode",
-    "lua" => "lua",
-    "nse" => "lua",
-    "pd_lua" => "lua",
-    "rbxs" => "lua",
-    "wlua" => "lua",
-    "mak" => "makefile",
-    "mk" => "makefile",
-    "mkfile" => "makefile",
-    "matlap" => "matlab",
-    "m" => "matlab",
-    "mel" => "mel",
-    "mizar" => "mizar",
-    "monkey" => "monkey",
-    "moon" => "moonscript",
-    "n1ql" => "n1ql",
-    "n4js" => "n4js",
-    "nand2tetrisHdl" => "nand2tetrisHdl",
-    "naniscript" => "naniscript",
-    "neon" => "neon",
-    "nginxconf" => "nginx",
-    "nim" => "nim",
-    "nimrod" => "nim",
-    "nix" => "nix",
-    "nsi" => "nsis",
-    "nsh" => "nsis",
-    "mm" => "objectivec",
-    "ml" => "ocaml",
-    "eliom" => "ocaml",
-    "eliomi" => "ocaml",
-    "ml4" => "ocaml",
-    "mli" => "ocaml",
-    "mll" => "ocaml",
-    "mly" => "ocaml",
-    "opencl" => "opencl",
-    "cl" => "opencl",
-    "oz" => "oz",
-    "parigp" => "parigp",
-    "parser" => "parser",
-    "pas" => "pascal",
-    "dfm" => "pascal",
-    "dpr" => "pascal",
-    "ipr" => "pascal",
-    "pcaxis" => "pcaxis",
-    "peoplecode" => "peoplecode",
-    "pl" => "perl",
-    "al" => "perl",
-    "perl" => "perl",
-    "ph" => "perl",
-    "plx" => "perl",
-    "pm" => "perl",
-    "pod" => "perl",
-    "psgi" => "perl",
-    "6pl" => "perl",
-    "6pm" => "perl",
-    "nqd" => "perl",
-    "p6" => "perl",
-    "p6l" => "perl",
-    "p6m" => "perl",
-    "pm6" => "perl",
-    "php" => "php",
-    "pls" => "plsql",
-    "pck" => "plsql",
-    "pkb" => "plsql",
-    "pks" => "plsql",
-    "plb" => "plsql",
-    "plsql" => "plsql",
-    "powerquery" => "powerquery",
-    "ps1" => "powershell",
-    "psd1" => "powershell",
-    "psm1" => "powershell",
-    "pde" => "processing",
-    "prolog" => "prolog",
-    "yap" => "prolog",
-    "properties" => "properties",
-    "proto" => "protobuf",
-    "pug" => "pug",
-    "pp" => "puppet",
-    "pd" => "pure",
-    "pb" => "purebasic",
-    "pbi" => "purebasic",
-    "purs" => "purescript",
-    "py" => "python",
-    "bzl" => "python",
-    "gyp" => "python",
-    "lmi" => "python",
-    "pyde" => "python",
-    "pyp" => "python",
-    "pyt" => "python",
-    "pyw" => "python",
-    "rpy" => "python",
-    "tac" => "python",
-    "wsgi" => "python",
-    "xpy" => "python",
-    "q" => "q",
-    "qml" => "qml",
-    "qbs" => "qml",
-    "qore" => "qore",
-    "r" => "r",
-    "rd" => "r",
-    "rsx" => "r",
-    "rkt" => "racket",
-    "rktd" => "racket",
-    "rktl" => "racket",
-    "scrbl" => "racket",
-    "re" => "reason",
-    "regex" => "regex",
-    "renpy" => "renpy",
-    "rst" => "rest",
-    "rest" => "rest",
-    "rest.txt" => "rest",
-    "rst.txt" => "rest",
-    "rip" => "rip",
-    "roboconf" => "roboconf",
-    "robotframework" => "robotframework",
-    "rb" => "ruby",
-    "builder" => "ruby",
-    "gemspec" => "ruby",
-    "god" => "ruby",
-    "irbrc" => "ruby",
-    "jbuilder" => "ruby",
-    "mspec" => "ruby",
-    "pluginspec" => "ruby",
-    "podspec" => "ruby",
-    "rabl" => "ruby",
-    "rake" => "ruby",
-    "rbuild" => "ruby",
-    "rbw" => "ruby",
-    "rbx" => "ruby",
-    "ru" => "ruby",
-    "ruby" => "ruby",
-    "thor" => "ruby",
-    "watchr" => "ruby",
-    "sas" => "sas",
-    "sass" => "sass",
-    "sbt" => "scala",
-    "scala" => "scala",
-    "sc" => "scala",
-    "scm" => "scheme",
-    "sld" => "scheme",
-    "sls" => "scheme",
-    "sps" => "scheme",
-    "ss" => "scheme",
-    "scss" => "scss",
-    "sh-session" => "shellSession",
-    "smali" => "smali",
-    "st" => "smalltalk",
-    "cs" => "smalltalk",
-    "tpl" => "smarty",
-    "sml" => "sml",
-    "sol" => "solidity",
-    "soy" => "soy",
-    "sparql" => "sparql",
-    "rq" => "sparql",
-    "splunk" => "splunkSpl",
-    "sqf" => "sqf",
-    "hqf" => "sqf",
-    "sql" => "sql",
-    "cql" => "sql",
-    "ddl" => "sql",
-    "prc" => "sql",
-    "tab" => "sql",
-    "udf" => "sql",
-    "viw" => "sql",
-    "stan" => "stan",
-    "styl" => "stylus",
-    "swift" => "swift",
-    "t4cs" => "t4Cs",
-    "t4" => "t4Vb",
-    "tap" => "tap",
-    "tcl" => "tcl",
-    "adp" => "tcl",
-    "tm" => "tcl",
-    "textile" => "textile",
-    "tsx" => "tsx",
-    "tt2" => "tt2",
-    "ttl" => "turtle",
-    "twig" => "twig",
-    "typoscript" => "typoscript",
-    "uc" => "unrealscript",
-    "vala" => "vala",
-    "vapi" => "vala",
-    "vbnet" => "vbnet",
-    "velocity" => "velocity",
-    "v" => "verilog",
-    "veo" => "verilog",
-    "vhdl" => "vhdl",
-    "vhd" => "vhdl",
-    "vhf" => "vhdl",
-    "vhi" => "vhdl",
-    "vho" => "vhdl",
-    "vhs" => "vhdl",
-    "vht" => "vhdl",
-    "vhw" => "vhdl",
-    "vim" => "vim",
-    "warpscript" => "warpscript",
-    "wasm" => "wasm",
-    "wiki" => "wiki",
-    "xeora" => "xeora",
-    "xml" => "xmlDoc",
-    "ant" => "xmlDoc",
-    "axml" => "xmlDoc",
-    "ccxml" => "xmlDoc",
-    "clixml" => "xmlDoc",
-    "cproject" => "xmlDoc",
-    "csl" => "xmlDoc",
-    "csproj" => "xmlDoc",
-    "ct" => "xmlDoc",
-    "dita" => "xmlDoc",
-    "ditamap" => "xmlDoc",
-    "ditaval" => "xmlDoc",
-    "dll.config" => "xmlDoc",
-    "dotsettings" => "xmlDoc",
-    "filters" => "xmlDoc",
-    "fsproj" => "xmlDoc",
-    "fxml" => "xmlDoc",
-    "glade" => "xmlDoc",
-    "grxml" => "xmlDoc",
-    "iml" => "xmlDoc",
-    "ivy" => "xmlDoc",
-    "jelly" => "xmlDoc",
-    "jsproj" => "xmlDoc",
-    "kml" => "xmlDoc",
-    "launch" => "xmlDoc",
-    "mdpolicy" => "xmlDoc",
-    "mod" => "xmlDoc",
-    "mxml" => "xmlDoc",
-    "nproj" => "xmlDoc",
-    "nuspec" => "xmlDoc",
-    "odd" => "xmlDoc",
-    "osm" => "xmlDoc",
-    "plist" => "xmlDoc",
-    "props" => "xmlDoc",
-    "ps1xml" => "xmlDoc",
-    "psc1" => "xmlDoc",
-    "pt" => "xmlDoc",
-    "rdf" => "xmlDoc",
-    "rss" => "xmlDoc",
-    "scxml" => "xmlDoc",
-    "srdf" => "xmlDoc",
-    "storyboard" => "xmlDoc",
-    "stTheme" => "xmlDoc",
-    "sublime-snippet" => "xmlDoc",
-    "targets" => "xmlDoc",
-    "tmCommand" => "xmlDoc",
-    "tml" => "xmlDoc",
-    "tmLanguage" => "xmlDoc",
-    "tmPreferences" => "xmlDoc",
-    "tmSnippet" => "xmlDoc",
-    "tmTheme" => "xmlDoc",
-    "ui" => "xmlDoc",
-    "urdf" => "xmlDoc",
-    "ux" => "xmlDoc",
-    "vbproj" => "xmlDoc",
-    "vcxproj" => "xmlDoc",
-    "vssettings" => "xmlDoc",
-    "vxml" => "xmlDoc",
-    "wsdl" => "xmlDoc",
-    "wsf" => "xmlDoc",
-    "wxi" => "xmlDoc",
-    "wxl" => "xmlDoc",
-    "wxs" => "xmlDoc",
-    "x3d" => "xmlDoc",
-    "xacro" => "xmlDoc",
-    "xaml" => "xmlDoc",
-    "xib" => "xmlDoc",
-    "xlf" => "xmlDoc",
-    "xliff" => "xmlDoc",
-    "xmi" => "xmlDoc",
-    "xml.dist" => "xmlDoc",
-    "xproj" => "xmlDoc",
-    "xsd" => "xmlDoc",
-    "xul" => "xmlDoc",
-    "zcml" => "xmlDoc",
-    "xojo_code" => "xojo",
-    "xojo_menu" => "xojo",
-    "xojo_report" => "xojo",
-    "xojo_script" => "xojo",
-    "xojo_toolbar" => "xojo",
-    "xojo_window" => "xojo",
-    "xquery" => "xquery",
-    "xq" => "xquery",
-    "xql" => "xquery",
-    "xqm" => "xquery",
-    "xqy" => "xquery",
-    "yaml" => "yaml",
-    "yml" => "yaml",
-    "reek" => "yaml",
-    "rviz" => "yaml",
-    "yaml-tmlanguage" => "yaml",
-    "sublime-syntax" => "yaml",
-    "syntax" => "yaml",
-    "yang" => "yang",
-    "zig" => "zig",
-    "txt" => "text",
-    "" => "text",
-};
+/// Language of files sent with `--binary`
+pub const BINARY_LANG: &str = "binary";
+
+/// What to do when saving over a file that already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Leave it and fail
+    Refuse,
+    /// Replace it
+    Replace,
+    /// Move it out of the way first, see [`backup`]
+    Backup,
+}
 
 #[derive(Debug)]
 pub struct File {
@@ -618,6 +64,272 @@ pub fn name_from_path(path: &Path) -> String {
         .to_string()
 }
 
+/// Names a downloaded file after the url path, falling back to its `Content-Type` for the
+/// extension so we still get a language when the url doesn't tell
+#[must_use]
+pub fn name_from_url(url: &Url, content_type: Option<&str>) -> String {
+    let segment = url
+        .path_segments()
+        .and_then(Iterator::last)
+        .filter(|segment| !segment.is_empty());
+
+    let known_extension = segment
+        .map(Path::new)
+        .and_then(Path::extension)
+        .and_then(OsStr::to_str)
+        .map_or(false, |ext| EXTENSION_TO_LANG_MAPPING.contains_key(ext));
+
+    if known_extension {
+        return segment.unwrap_or_default().to_owned();
+    }
+
+    let stem = segment.unwrap_or("remote");
+    content_type
+        .and_then(|mime| mime.split(';').next())
+        .and_then(extension_from_mime)
+        .map_or_else(|| stem.to_owned(), |ext| format!("{}.{}", stem, ext))
+}
+
+fn extension_from_mime(mime: &str) -> Option<&'static str> {
+    let ext = match mime.trim() {
+        "text/x-rust" => "rs",
+        "text/x-python" | "application/x-python-code" => "py",
+        "text/javascript" | "application/javascript" => "js",
+        "application/typescript" => "ts",
+        "application/json" => "json",
+        "text/markdown" => "md",
+        "text/html" => "html",
+        "text/css" => "css",
+        "text/x-c" => "c",
+        "text/x-c++" => "cpp",
+        "text/x-go" => "go",
+        "text/x-java" | "text/x-java-source" => "java",
+        "text/x-shellscript" | "application/x-sh" => "sh",
+        "text/yaml" | "application/x-yaml" => "yaml",
+        "text/xml" | "application/xml" => "xml",
+        "application/toml" => "toml",
+        _ => return None,
+    };
+    Some(ext)
+}
+
+fn random_suffix() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect()
+}
+
+/// Saves `contents` to `path` without ever leaving it half written
+///
+/// They are written to a temporary file next to `path` which is then moved over it. A file that is
+/// replaced keeps its permissions. Returns where the existing file went with [`Overwrite::Backup`]
+///
+/// # Errors
+///
+/// Fails with [`Error::Argument`] if the file exists and `overwrite` refuses, or with
+/// [`std::io::Error`]
+pub fn save(path: &Path, contents: &[u8], overwrite: Overwrite) -> Result<Option<PathBuf>> {
+    let existing = fs::symlink_metadata(path).ok();
+    if existing.is_some() && overwrite == Overwrite::Refuse {
+        return Err(already_exists());
+    }
+
+    let temp = path.with_file_name(format!(
+        ".{}.gistit-{}",
+        name_from_path(path),
+        random_suffix()
+    ));
+    let saved = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| {
+            if let Ok(metadata) = fs::metadata(path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .map_err(Error::from)
+        .and_then(|()| match overwrite {
+            Overwrite::Refuse => place(&temp, path).map(|()| None),
+            Overwrite::Backup if existing.is_some() => {
+                let backed_up = backup(path)?;
+                fs::rename(&temp, path)?;
+                Ok(Some(backed_up))
+            }
+            Overwrite::Backup | Overwrite::Replace => {
+                fs::rename(&temp, path)?;
+                Ok(None)
+            }
+        });
+
+    if saved.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    saved
+}
+
+/// The error saving over an existing file without `--force` or `--backup`
+#[must_use]
+pub const fn already_exists() -> Error {
+    Error::Argument(
+        "file already exists, overwrite it with `--force` or keep a copy with `--backup`",
+        "--save",
+    )
+}
+
+/// Moves `temp` to `path` unless something got there first. Linking fails if `path` exists,
+/// where a rename would replace it; file systems without hard links fall back to checking first
+fn place(temp: &Path, path: &Path) -> Result<()> {
+    match fs::hard_link(temp, path) {
+        Ok(()) => {
+            fs::remove_file(temp)?;
+            Ok(())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Err(already_exists()),
+        Err(_) if fs::symlink_metadata(path).is_err() => Ok(fs::rename(temp, path)?),
+        Err(_) => Err(already_exists()),
+    }
+}
+
+/// Moves a file out of the way, to the trash on freedesktop systems
+///
+/// Where there is no trash, or it is on another file system, the file is kept next to itself as
+/// `<name>.bak`, replacing an older backup. Returns where it went
+///
+/// # Errors
+///
+/// Fails with [`std::io::Error`]
+pub fn backup(path: &Path) -> Result<PathBuf> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if let Some(trash) = trash::dir() {
+        if let Some(trashed) = trash::put(path, &trash)? {
+            return Ok(trashed);
+        }
+    }
+
+    let backup = path.with_file_name(format!("{}.bak", name_from_path(path)));
+    fs::rename(path, &backup)?;
+    Ok(backup)
+}
+
+/// The freedesktop.org trash, see <https://specifications.freedesktop.org/trash-spec/trashspec-latest.html>
+#[cfg(all(unix, not(target_os = "macos")))]
+mod trash {
+    use std::fs::{self, DirBuilder, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::DirBuilderExt;
+    use std::path::{Path, PathBuf};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use gistit_project::path;
+
+    use super::name_from_path;
+    use crate::stats::civil_from_days;
+    use crate::Result;
+
+    /// The home trash, `$XDG_DATA_HOME/Trash`
+    pub fn dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| Some(path::home().ok()?.join(".local").join("share")))
+            .map(|data| data.join("Trash"))
+    }
+
+    /// `YYYY-MM-DDThh:mm:ss` in UTC
+    pub(super) fn timestamp(time: SystemTime) -> String {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        #[allow(clippy::cast_possible_wrap)]
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let secs = secs % 86_400;
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    }
+
+    /// Percent encodes a path for the `Path` key, keeping `/`
+    pub(super) fn encode(path: &Path) -> String {
+        path.as_os_str()
+            .as_bytes()
+            .iter()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                    char::from(*byte).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    /// Moves `path` into `trash`, `None` if it can't be moved there with a rename
+    pub fn put(path: &Path, trash: &Path) -> Result<Option<PathBuf>> {
+        let (files, info) = (trash.join("files"), trash.join("info"));
+        // The trash is only for its owner to look into
+        let mut builder = DirBuilder::new();
+        builder.recursive(true).mode(0o700);
+        builder.create(&files)?;
+        builder.create(&info)?;
+
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let original = fs::canonicalize(parent)?.join(path.file_name().unwrap_or_default());
+        let name = name_from_path(path);
+
+        for attempt in 0_u32.. {
+            let candidate = if attempt == 0 {
+                name.clone()
+            } else {
+                format!("{}.{}", name, attempt)
+            };
+            let trashed = files.join(&candidate);
+            let info_file = info.join(format!("{}.trashinfo", candidate));
+
+            // Creating the info file first reserves the name
+            let mut file = match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info_file)
+            {
+                Ok(file) if fs::symlink_metadata(&trashed).is_err() => file,
+                Ok(_) => {
+                    fs::remove_file(&info_file)?;
+                    continue;
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            };
+            write!(
+                file,
+                "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                encode(&original),
+                timestamp(SystemTime::now())
+            )?;
+
+            if fs::rename(path, &trashed).is_ok() {
+                return Ok(Some(trashed));
+            }
+            fs::remove_file(&info_file)?;
+            return Ok(None);
+        }
+        Ok(None)
+    }
+}
+
 impl File {
     /// Create file from a given path
     ///
@@ -645,14 +357,8 @@ impl File {
         let data = data.as_ref();
 
         let (handler, path) = {
-            let rng_string: String = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(8)
-                .map(char::from)
-                .collect();
-
-            let mut rng_name = "gistit-".to_owned();
-            rng_name.push_str(&rng_string);
+            let mut rng_name = temp::PREFIX.to_owned();
+            rng_name.push_str(&random_suffix());
             rng_name.push_str(name);
 
             let path = temp_dir().join(&rng_name);
@@ -663,6 +369,7 @@ impl File {
                 .truncate(true)
                 .open(&path)?;
 
+            temp::register(&path);
             handler.write_all(data.as_bytes())?;
             handler.rewind()?;
 
@@ -699,27 +406,37 @@ impl File {
         self.size
     }
 
-    /// Reads the file using [`BufReader`] and returns contents as string
+    /// Reads the contents as a string, from the start whatever was read before
     ///
     /// # Errors
     ///
-    /// Fails if can't read the file
+    /// Fails if can't read the file, or with [`Error::Utf8`] if it isn't valid utf8
     pub fn read(&self) -> Result<String> {
-        let mut buf = String::with_capacity(GISTIT_MAX_SIZE);
-        let mut reader = BufReader::new(&**self);
-        reader.read_to_string(&mut buf)?;
+        String::from_utf8(self.read_bytes()?).map_err(|err| err.utf8_error().into())
+    }
+
+    /// Reads the raw contents, for binary files, from the start whatever was read before
+    ///
+    /// # Errors
+    ///
+    /// Fails if can't read the file
+    pub fn read_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.size);
+        let mut handler = &self.handler;
+        handler.rewind()?;
+        handler.read_to_end(&mut buf)?;
 
         Ok(buf)
     }
 
-    /// Reads the contents and save the file to given path
+    /// Reads the contents and saves them to the given path, see [`save`]
     ///
     /// # Errors
     ///
-    /// Fails with [`std::io::Error`]
-    pub fn save_as(&mut self, path: &Path) -> Result<()> {
+    /// Fails if the file exists and `overwrite` refuses, or with [`std::io::Error`]
+    pub fn save_as(&mut self, path: &Path, overwrite: Overwrite) -> Result<Option<PathBuf>> {
         let content = self.read()?;
-        Ok(write(path, content)?)
+        save(path, content.as_bytes(), overwrite)
     }
 }
 
@@ -747,6 +464,27 @@ mod tests {
         assert_eq!(n6, "😁");
     }
 
+    #[test]
+    fn file_name_from_url() {
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        let n1 = name_from_url(&url("https://example.com/src/main.rs"), Some("text/plain"));
+        let n2 = name_from_url(
+            &url("https://pastebin.com/raw/abc123"),
+            Some("text/x-python"),
+        );
+        let n3 = name_from_url(
+            &url("https://example.com/"),
+            Some("application/json; charset=utf-8"),
+        );
+        let n4 = name_from_url(&url("https://example.com/artifact"), None);
+
+        assert_eq!(n1, "main.rs");
+        assert_eq!(n2, "abc123.py");
+        assert_eq!(n3, "remote.json");
+        assert_eq!(n4, "artifact");
+    }
+
     #[test]
     fn file_spawn_random_and_write() {
         let data: String = rand::thread_rng()
@@ -793,6 +531,18 @@ mod tests {
         assert_eq!(content, data);
     }
 
+    #[test]
+    fn file_structure_read_binary() {
+        let data = [0x7f_u8, b'E', b'L', b'F', 0xff, 0x00, 0xfe];
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let input_file = tmp.child("a.out");
+        input_file.write_binary(&data).unwrap();
+
+        assert!(File::from_path(&input_file).unwrap().read().is_err());
+        let file = File::from_path(&input_file).unwrap();
+        assert_eq!(file.read_bytes().unwrap(), data);
+    }
+
     #[test]
     fn file_structure_save_as() {
         let data: String = rand::thread_rng()
@@ -807,7 +557,8 @@ mod tests {
         tmp_file.write_binary(data.as_bytes()).unwrap();
 
         let mut file = File::from_path(&tmp_file).unwrap();
-        file.save_as(&tmp.join("bar.txt")).unwrap();
+        file.save_as(&tmp.join("bar.txt"), Overwrite::Refuse)
+            .unwrap();
         tmp.assert(predicates::path::exists());
 
         let other = tmp.child("bar.txt");
@@ -816,6 +567,81 @@ mod tests {
         assert_eq!(data.as_bytes(), other);
     }
 
+    #[test]
+    fn file_save_overwrite_and_backup() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let target = tmp.child("main.rs");
+
+        assert_eq!(save(&target, b"one", Overwrite::Refuse).unwrap(), None);
+        assert!(matches!(
+            save(&target, b"two", Overwrite::Refuse),
+            Err(Error::Argument(..))
+        ));
+        target.assert("one");
+
+        save(&target, b"two", Overwrite::Replace).unwrap();
+        target.assert("two");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+            save(&target, b"three", Overwrite::Replace).unwrap();
+            target.assert("three");
+            let mode = fs::metadata(&target).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o750);
+        }
+
+        // No temporary file left behind
+        let names: Vec<String> = fs::read_dir(&tmp)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with('.'))
+            .collect();
+        assert!(names.is_empty());
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn file_backup_to_trash() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let trash = tmp.join("Trash");
+        let target = tmp.child("my file.rs");
+        target.write_str("old").unwrap();
+
+        let trashed = trash::put(&target, &trash).unwrap().unwrap();
+        assert_eq!(trashed, trash.join("files").join("my file.rs"));
+        for dir in [trash.join("files"), trash.join("info")] {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        assert_eq!(fs::read_to_string(&trashed).unwrap(), "old");
+        target.assert(predicates::path::missing());
+
+        let info = fs::read_to_string(trash.join("info").join("my file.rs.trashinfo")).unwrap();
+        let original = fs::canonicalize(tmp.path()).unwrap().join("my file.rs");
+        assert!(info.starts_with(&format!(
+            "[Trash Info]\nPath={}\nDeletionDate=",
+            trash::encode(&original)
+        )));
+        assert!(info.contains("my%20file.rs"));
+
+        // Same name trashed twice
+        target.write_str("older").unwrap();
+        assert_eq!(
+            trash::put(&target, &trash).unwrap().unwrap(),
+            trash.join("files").join("my file.rs.1")
+        );
+
+        let epoch = std::time::UNIX_EPOCH;
+        assert_eq!(trash::timestamp(epoch), "1970-01-01T00:00:00");
+        assert_eq!(
+            trash::timestamp(epoch + std::time::Duration::from_secs(1_646_092_861)),
+            "2022-03-01T00:01:01"
+        );
+    }
+
     #[test]
     fn file_structure_extension_to_lang_mapping() {
         let tmp = assert_fs::TempDir::new().unwrap();
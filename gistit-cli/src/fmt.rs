@@ -1,138 +1,209 @@
-use std::sync::{Arc, Mutex};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
-use indicatif::{ProgressBar, ProgressStyle};
+use console::Emoji;
+#[cfg(any(feature = "fetch", feature = "p2p"))]
+use serde::Serialize;
+
+use gistit_project::env;
+
+use crate::{Error, Result};
+
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// How results are printed (`--format`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Format {
+    Text,
+    Json,
+    Yaml,
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            _ => Err(Error::Argument("unknown output format", "--format")),
+        }
+    }
+}
+
+pub fn set_format(format: Format) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn format() -> Format {
+    match FORMAT.load(Ordering::Relaxed) {
+        1 => Format::Json,
+        2 => Format::Yaml,
+        _ => Format::Text,
+    }
+}
+
+/// Prints `value` to stdout as JSON or YAML, if `--format` asked for it. Returns `false` for
+/// text, which is left to the caller
+#[cfg(any(feature = "fetch", feature = "p2p"))]
+pub fn structured(value: &impl Serialize) -> Result<bool> {
+    match format() {
+        Format::Text => return Ok(false),
+        Format::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        Format::Yaml => print!("{}", serde_yaml::to_string(value)?),
+    }
+    Ok(true)
+}
+
+/// Disables colors and emoji for the rest of the program (`--no-color`)
+pub fn disable_colors() {
+    NO_COLOR.store(true, Ordering::Relaxed);
+    console::set_colors_enabled(false);
+    console::set_colors_enabled_stderr(false);
+}
+
+/// Honors the `NO_COLOR` convention, see <https://no-color.org>
+#[must_use]
+pub fn no_color_env() -> bool {
+    std::env::var_os(env::NO_COLOR).map_or(false, |value| !value.is_empty())
+}
+
+#[must_use]
+pub fn colors_enabled() -> bool {
+    !NO_COLOR.load(Ordering::Relaxed)
+}
+
+/// Like [`Emoji`] but always falls back when colors are disabled
+#[must_use]
+pub fn emoji(emoji: &'static str, fallback: &'static str) -> Emoji<'static, 'static> {
+    if colors_enabled() {
+        Emoji(emoji, fallback)
+    } else {
+        Emoji(fallback, fallback)
+    }
+}
+
+/// How long ago, roughly, `secs` seconds were
+#[cfg(any(feature = "fetch", feature = "p2p"))]
+#[must_use]
+pub fn ago(secs: u64) -> String {
+    format!("{} ago", span(secs))
+}
+
+/// Roughly how long `secs` seconds are, in the largest unit that fits
+#[must_use]
+pub fn span(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Human readable byte count
+#[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+#[must_use]
+pub fn bytes(count: u64) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let count = count as f64;
+    match count {
+        c if c < 1024.0 => format!("{} B", c),
+        c if c < 1024.0 * 1024.0 => format!("{:.1} KiB", c / 1024.0),
+        c if c < 1024.0 * 1024.0 * 1024.0 => format!("{:.1} MiB", c / (1024.0 * 1024.0)),
+        c => format!("{:.1} GiB", c / (1024.0 * 1024.0 * 1024.0)),
+    }
+}
+
+/// `text` from someone else, safe to print: control characters, which could rewrite the terminal
+/// around it, become spaces
+#[cfg(feature = "fetch")]
+#[must_use]
+pub fn printable(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
 
 #[macro_export]
 macro_rules! errorln {
     ($err:expr) => {{
-        use console::style;
-
-        eprintln!(
-            "{}: {}",
-            style("error").red().bold(),
-            $err
-        );
+        crate::progress::error(&$err);
     }};
 
     ($msg:literal, $($rest:expr),* $(,)*) => {{
-        use console::style;
-
-        let msg = format!($msg, $($rest,)*);
-        println!("{}: {}",
-            style("error").red().bold(),
-            msg
-        );
+        crate::progress::error(format!($msg, $($rest,)*));
     }};
 }
 
 #[macro_export]
 macro_rules! warnln {
     ($warn:expr) => {{
-        use console::style;
-        use crate::fmt::PROGRESS;
+        crate::progress::warn(&$warn);
+    }};
 
-        PROGRESS.println(format!( "{}: {}",
-            style("warning").yellow().bold(),
-            $warn
-        ));
+    ($msg:literal, $($rest:expr),* $(,)*) => {{
+        crate::progress::warn(format!($msg, $($rest,)*));
+    }};
+}
+
+#[macro_export]
+macro_rules! verboseln {
+    ($msg:expr) => {{
+        crate::progress::verbose(&$msg);
     }};
 
     ($msg:literal, $($rest:expr),* $(,)*) => {{
-        use console::style;
-        use crate::fmt::PROGRESS;
-
-        let msg = format!($msg, $($rest,)*);
-        PROGRESS.println(format!("{}: {}",
-            style("warning").yellow().bold(),
-            msg
-        ));
+        if crate::progress::verbose_enabled() {
+            crate::progress::verbose(format!($msg, $($rest,)*));
+        }
     }};
 }
 
 #[macro_export]
 macro_rules! progress {
     ($msg:expr) => {{
-        use crate::fmt::{PROGRESS, STATUS};
-        let mut status = STATUS.lock().unwrap();
-        PROGRESS.set_message($msg);
-        *status = Box::leak(Box::new($msg));
+        crate::progress::status(&$msg);
     }};
 
     ($msg:literal, $($rest:expr),* $(,)*) => {{
-        use crate::fmt::{PROGRESS, STATUS};
-        let mut status = STATUS.lock().unwrap();
-        let msg = format!($msg, $($rest,)*);
-        PROGRESS.set_message(msg.clone());
-        *status = Box::leak(Box::new(msg));
+        crate::progress::status(format!($msg, $($rest,)*));
     }};
 }
 
 #[macro_export]
 macro_rules! updateln {
     ($msg:expr) => {{
-        use console::{style, Emoji};
-        use crate::fmt::PROGRESS;
-        PROGRESS.println(format!("{} {}", style(Emoji("✔️ ", "> ")).green(), $msg));
+        crate::progress::update(&$msg);
     }};
 
     ($msg:literal, $($rest:expr),* $(,)*) => {{
-        use crate::fmt::PROGRESS;
-        use console::{style, Emoji};
-        let msg = format!($msg, $($rest,)*);
-        PROGRESS.println(format!("{} {}", style(Emoji("✔️ ", "> ")).green(), msg));
+        crate::progress::update(format!($msg, $($rest,)*));
     }};
 }
 
 #[macro_export]
 macro_rules! finish {
     ($msg:expr) => {{
-        use crate::fmt::PROGRESS;
-
-        PROGRESS.println(format!("{}", $msg));
-        PROGRESS.finish_and_clear();
+        crate::progress::finish(&$msg);
     }};
 }
 
 #[macro_export]
 macro_rules! cleanln {
     ($msg:expr) => {{
-        use crate::fmt::PROGRESS;
-
-        PROGRESS.println(format!("{}", $msg));
+        crate::progress::line(&$msg);
     }};
 }
 
 #[macro_export]
 macro_rules! interruptln {
     () => {{
-        use crate::fmt::{PROGRESS, STATUS};
-        use console::{style, Emoji};
-        let status = STATUS.lock().unwrap();
-
-        PROGRESS.println(format!("{} {}", style(Emoji("❌", "x ")).red(), status));
-        PROGRESS.finish_and_clear();
+        crate::progress::interrupt();
     }};
 }
-
-lazy_static::lazy_static! {
-    pub static ref PROGRESS: ProgressBar = {
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&[
-                "◜",
-                "◠",
-                "◝",
-                "◞",
-                "◡",
-                "◟",
-                "✔️",
-            ])
-            .template("{spinner:.blue}  {msg}"),
-        );
-        pb.enable_steady_tick(100);
-        pb
-    };
-
-    pub static ref STATUS: Arc<Mutex<&'static str>> = Arc::new(Mutex::new(""));
-}
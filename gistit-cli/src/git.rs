@@ -0,0 +1,212 @@
+//! The git module
+//!
+//! `--git-diff` sends changes of the repository we are in as a `.patch` gistit and `fetch
+//! --apply` applies one. We shell out to `git`, so whatever the user configured it with applies,
+//! and read the author from it too.
+#[cfg(feature = "fetch")]
+use std::io::Write;
+#[cfg(feature = "fetch")]
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+use std::process::Command;
+#[cfg(feature = "fetch")]
+use std::process::{Output, Stdio};
+
+#[cfg(any(feature = "send", feature = "fetch"))]
+use crate::{Error, Result};
+
+/// What to diff
+#[cfg(feature = "send")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Range<'a> {
+    /// Changes staged for the next commit
+    Staged,
+
+    /// Anything `git diff` takes, e.g. `HEAD~1` or `main..feature`
+    Revisions(&'a str),
+}
+
+#[cfg(feature = "send")]
+impl<'a> Range<'a> {
+    /// Without a revision range staged changes are sent
+    #[must_use]
+    pub const fn new(range: Option<&'a str>) -> Self {
+        match range {
+            Some(range) => Self::Revisions(range),
+            None => Self::Staged,
+        }
+    }
+
+    /// Name of the patch file, e.g. `HEAD_1.patch`
+    #[must_use]
+    pub fn file_name(&self) -> String {
+        let stem = match self {
+            Self::Staged => "staged".to_owned(),
+            Self::Revisions(range) => range
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect(),
+        };
+        format!("{}.patch", stem)
+    }
+}
+
+/// `git config user.name`, if set
+#[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+#[must_use]
+pub fn user_name() -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "user.name"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// The patch for `range`, in the repository of the working directory
+///
+/// # Errors
+///
+/// Fails if git isn't installed, we aren't in a repository, the range is invalid or there are no
+/// changes in it
+#[cfg(feature = "send")]
+pub fn diff(range: Range) -> Result<String> {
+    let mut command = Command::new("git");
+    command.args(["diff", "--no-color", "--no-ext-diff"]);
+    match range {
+        Range::Staged => {
+            command.arg("--staged");
+        }
+        // Would be read as an option otherwise
+        Range::Revisions(range) if range.starts_with('-') => {
+            return Err(Error::Argument("invalid revision range", "--git-diff"));
+        }
+        Range::Revisions(range) => {
+            command.arg(range);
+        }
+    }
+    // Everything before is a revision, never a path
+    command.arg("--");
+
+    let output = command
+        .output()
+        .map_err(|_| Error::Argument("git is not installed", "--git-diff"))?;
+    if !output.status.success() {
+        return Err(Error::Argument(
+            "not a git repository or invalid revision range",
+            "--git-diff",
+        ));
+    }
+
+    let patch = String::from_utf8(output.stdout)
+        .map_err(|_| Error::Argument("the diff is not UTF-8 text", "--git-diff"))?;
+    if patch.trim().is_empty() {
+        return Err(Error::Argument(
+            match range {
+                Range::Staged => "nothing staged, pass a revision range to send committed changes",
+                Range::Revisions(_) => "no changes in this revision range",
+            },
+            "--git-diff",
+        ));
+    }
+    Ok(patch)
+}
+
+/// The root of the repository of the working directory
+///
+/// # Errors
+///
+/// Fails if git isn't installed or we aren't in a repository
+#[cfg(feature = "fetch")]
+pub fn toplevel() -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .map_err(|_| Error::Argument("git is not installed", "--apply"))?;
+    if !output.status.success() {
+        return Err(Error::Argument("not a git repository", "--apply"));
+    }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+    ))
+}
+
+/// Runs `git apply` at `root` with `patch` on stdin. From a subdirectory git would skip files
+/// outside of it
+#[cfg(feature = "fetch")]
+fn apply_with(root: &Path, args: &[&str], patch: &str) -> Result<Output> {
+    let mut child = Command::new("git")
+        .arg("apply")
+        .args(args)
+        .current_dir(root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin to be piped")
+        .write_all(patch.as_bytes())?;
+    Ok(child.wait_with_output()?)
+}
+
+/// Checks `patch` applies cleanly at `root` without touching anything, returning what it would
+/// change as `git apply --stat --summary` puts it
+///
+/// # Errors
+///
+/// Fails with git's reason if it doesn't apply
+#[cfg(feature = "fetch")]
+pub fn check(root: &Path, patch: &str) -> Result<String> {
+    let output = apply_with(root, &["--check"], patch)?;
+    if !output.status.success() {
+        return Err(Error::Patch(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    let output = apply_with(root, &["--stat", "--summary"], patch)?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Applies `patch` to the working tree at `root`
+///
+/// # Errors
+///
+/// Fails with git's reason if it doesn't apply, nothing is changed then
+#[cfg(feature = "fetch")]
+pub fn apply(root: &Path, patch: &str) -> Result<()> {
+    let output = apply_with(root, &[], patch)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::Patch(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "send"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_patch_file_name() {
+        assert_eq!(Range::new(None).file_name(), "staged.patch");
+        assert_eq!(Range::new(Some("HEAD~1")).file_name(), "HEAD_1.patch");
+        assert_eq!(
+            Range::new(Some("main..feature/x")).file_name(),
+            "main..feature_x.patch"
+        );
+        assert!(diff(Range::new(Some("--output=/tmp/x"))).is_err());
+    }
+}
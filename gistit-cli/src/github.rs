@@ -1,21 +1,33 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::fs;
-use std::io::Write;
-use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rand::{distributions::Alphanumeric, Rng};
-use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
+use reqwest::{RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use console::style;
+use gistit_proto::Gistit;
+
+use crate::fmt::span;
+use crate::http;
+use crate::keychain::{self, Storage};
 use crate::patch::webbrowser::{self, BrowserOptions};
-use crate::server::SERVER_URL_TOKEN;
-use crate::{Error, Result};
+use crate::server::{SERVER_URL_REVOKE, SERVER_URL_TOKEN};
+use crate::{progress, updateln, warnln, Error, Result};
 
 pub const GITHUB_OAUTH_CLIENT_ID: &str = "265cd618948a2e58042e";
 pub const GITHUB_OAUTH_BASE_URL: &str = "https://github.com/login/oauth/authorize";
 pub const GITHUB_GISTS_API_URL: &str = "https://api.github.com/gists";
+pub const GITHUB_RATE_LIMIT_API_URL: &str = "https://api.github.com/rate_limit";
+
+/// The only scope gistit needs
+pub const GITHUB_REQUIRED_SCOPE: &str = "gist";
+
+/// Longest we wait for a rate limit to reset before giving up
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
 
 #[derive(Clone, Debug, Serialize)]
 pub struct Oauth {
@@ -47,23 +59,37 @@ pub struct CreateResponse {
     pub comments_url: String,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct GistOwner {
+    pub login: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GistFile {
+    pub filename: String,
+    pub content: String,
+    pub truncated: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GetResponse {
+    pub id: String,
+    pub html_url: String,
+    pub description: Option<String>,
+    pub owner: Option<GistOwner>,
+    pub files: BTreeMap<String, GistFile>,
+}
+
 impl Oauth {
-    /// Looks for token in project config dir and initializes state.
-    /// Will not fail if token file is missing.
+    /// Looks for a stored token and initializes state.
+    /// Will not fail if there is no token.
     ///
     /// # Errors
     ///
-    /// Fails if cannot read token file
+    /// Fails if the stored token can't be read
     pub fn new() -> Result<Self> {
-        let config = gistit_project::path::config()?;
-        let token_path = config.join("github");
         let state = unguessable_state();
-
-        let token = if fs::metadata(&token_path).is_ok() {
-            Some(serde_json::from_str(&fs::read_to_string(&token_path)?)?)
-        } else {
-            None
-        };
+        let token = keychain::load()?;
 
         Ok(Self { state, token })
     }
@@ -103,19 +129,18 @@ impl Oauth {
     /// # Errors
     ///
     /// Fails after 3 retries
-    pub async fn poll_token(&mut self) -> Result<()> {
+    pub async fn poll_token(&mut self) -> Result<Storage> {
         let mut retry = 0_usize;
         let token: Token = loop {
-            let response = reqwest::Client::new()
-                .post(SERVER_URL_TOKEN.to_string())
-                .json(self)
-                .send()
+            let client = http::Client::new();
+            let response = client
+                .send(client.post(SERVER_URL_TOKEN.to_string()).json(self))
                 .await?;
 
             match response.status() {
                 StatusCode::NOT_FOUND => {
                     if retry < 7 {
-                        thread::sleep(Duration::from_secs(3));
+                        tokio::time::sleep(Duration::from_secs(3)).await;
                         retry += 1;
                     } else {
                         return Err(Error::OAuth("could not authorize".to_owned()));
@@ -128,12 +153,12 @@ impl Oauth {
             }
         };
 
-        let config = gistit_project::path::config()?;
-        fs::File::create(config.join("github"))?.write_all(&serde_json::to_vec(&token)?)?;
+        check_scopes(&token).await?;
+        let storage = keychain::store(&token)?;
 
         self.token = Some(token);
 
-        Ok(())
+        Ok(storage)
     }
 
     #[must_use]
@@ -150,3 +175,350 @@ pub fn unguessable_state() -> String {
         .map(char::from)
         .collect()
 }
+
+/// Loads the stored GitHub token, walking the user through OAuth if there is none
+///
+/// # Errors
+///
+/// Fails if the authorization can't be completed
+pub async fn authorized_token() -> Result<Token> {
+    progress!("Authorizing");
+    let mut oauth = Oauth::new()?;
+
+    if oauth.token().is_none() {
+        if let Err(url) = oauth.authorize() {
+            warnln!(
+                "failed to open your web browser. \n\nAuthorize manually: '{}'",
+                style(url).cyan()
+            );
+        }
+        let storage = oauth.poll_token().await?;
+        warnln!("storing github token at: '{}'", storage);
+    }
+    updateln!("Authorized");
+
+    oauth
+        .token
+        .ok_or_else(|| Error::OAuth("could not authorize".to_owned()))
+}
+
+/// Seconds until the rate limit GitHub answered with `status` and `headers` resets, `None` if it
+/// wasn't rate limited. `now` is in seconds since the epoch
+fn rate_limit_reset(status: StatusCode, headers: &HeaderMap, now: u64) -> Option<u64> {
+    if !matches!(
+        status,
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+    ) {
+        return None;
+    }
+    let header = |name: &str| -> Option<u64> {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    };
+
+    // Secondary rate limits say how long to wait, the primary one when it resets
+    if let Some(secs) = header("retry-after") {
+        return Some(secs);
+    }
+    if header("x-ratelimit-remaining") != Some(0) {
+        return None;
+    }
+    Some(header("x-ratelimit-reset").map_or(0, |reset| reset.saturating_sub(now)))
+}
+
+/// [`rate_limit_reset`] for a response received now
+fn reset_of(response: &Response) -> Option<u64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    rate_limit_reset(response.status(), response.headers(), now)
+}
+
+/// Sends a request to the GitHub api. Rate limits resetting within [`MAX_RATE_LIMIT_WAIT`] are
+/// waited out like server errors are retried, all of it bounded by `--timeout`
+///
+/// # Errors
+///
+/// Fails with [`Error::RateLimited`] saying when the rate limit resets, or if the request can't
+/// be sent
+async fn send(client: &http::Client, request: RequestBuilder) -> Result<Response> {
+    let response = client
+        .send_waiting(request, |response| {
+            let secs = reset_of(response)?;
+            (Duration::from_secs(secs) <= MAX_RATE_LIMIT_WAIT).then(|| {
+                warnln!("github rate limit exceeded, retrying in {}", span(secs));
+                // A second past the reset, our clock may be behind
+                Duration::from_secs(secs + 1)
+            })
+        })
+        .await?;
+
+    reset_of(&response).map_or(Ok(response), |secs| Err(Error::RateLimited(span(secs))))
+}
+
+/// Tells why GitHub refused to post or update a gist, nothing was `done`
+fn warn_refused(status: StatusCode, done: &str) {
+    match status {
+        StatusCode::UNAUTHORIZED => {
+            warnln!(
+                "your github token is expired or revoked, nothing was {}",
+                done
+            );
+        }
+        StatusCode::FORBIDDEN => warnln!(
+            "your github token isn't allowed to write gists, nothing was {}",
+            done
+        ),
+        StatusCode::UNPROCESSABLE_ENTITY => {
+            warnln!("github rejected the gist, nothing was {}", done);
+        }
+        _ => warnln!(
+            "got a invalid response from github, nothing was {}. status {}",
+            done,
+            status
+        ),
+    }
+}
+
+/// The gist files a gistit is posted as
+///
+/// # Errors
+///
+/// Fails if the gistit is binary, GitHub Gists only take text
+fn gist_body(gistit: &Gistit, arg: &'static str) -> Result<serde_json::Value> {
+    // NOTE: Currently we only support one file
+    let inner = gistit.inner.first().expect("to have at least one file");
+    if inner.is_binary {
+        return Err(Error::Argument(
+            "binary gistits can't be posted to GitHub Gists",
+            arg,
+        ));
+    }
+    Ok(serde_json::json!({
+        "description": gistit.description.as_deref().unwrap_or(""),
+        "files": {
+            &inner.name: {
+                "content": inner.data
+            }
+        }
+    }))
+}
+
+/// Posts a gistit to GitHub Gists. Returns `None` if GitHub refused it
+///
+/// # Errors
+///
+/// Fails if the request can't be sent or GitHub stays rate limited
+pub async fn create_gist(token: &Token, gistit: &Gistit) -> Result<Option<CreateResponse>> {
+    let mut body = gist_body(gistit, "--github")?;
+    // Private gistits only go by their full hash, secret gists by their id
+    body["public"] = serde_json::Value::Bool(!gistit.private);
+
+    let client = http::Client::new();
+    let response = send(
+        &client,
+        client
+            .post(GITHUB_GISTS_API_URL)
+            .header("user-agent", "gistit")
+            .header("authorization", format!("token {}", token.access_token))
+            .header("accept", "application/vnd.github.v3+json")
+            .json(&body),
+    )
+    .await?;
+
+    match response.status() {
+        StatusCode::CREATED => Ok(Some(response.json().await?)),
+        status => {
+            warn_refused(status, "posted");
+            Ok(None)
+        }
+    }
+}
+
+/// Replaces the contents of a gist of ours with a gistit. Its other files are left as they are.
+/// Returns `None` if GitHub refused it
+///
+/// # Errors
+///
+/// Fails if the gist doesn't exist, the request can't be sent or GitHub stays rate limited
+pub async fn update_gist(
+    token: &Token,
+    id: &str,
+    gistit: &Gistit,
+) -> Result<Option<CreateResponse>> {
+    let body = gist_body(gistit, "--github-update")?;
+
+    let client = http::Client::new();
+    let response = send(
+        &client,
+        client
+            .patch(format!("{}/{}", GITHUB_GISTS_API_URL, id))
+            .header("user-agent", "gistit")
+            .header("authorization", format!("token {}", token.access_token))
+            .header("accept", "application/vnd.github.v3+json")
+            .json(&body),
+    )
+    .await?;
+
+    match response.status() {
+        StatusCode::OK => Ok(Some(response.json().await?)),
+        StatusCode::NOT_FOUND => Err(Error::NotFound(
+            "github gist not found, or it isn't yours to update",
+        )),
+        status => {
+            warn_refused(status, "updated");
+            Ok(None)
+        }
+    }
+}
+
+/// Downloads a public gist
+///
+/// # Errors
+///
+/// Fails if the gist doesn't exist, GitHub stays rate limited or responds unexpectedly
+pub async fn get_gist(id: &str) -> Result<GetResponse> {
+    let client = http::Client::new();
+    let response = send(
+        &client,
+        client
+            .get(format!("{}/{}", GITHUB_GISTS_API_URL, id))
+            .header("user-agent", "gistit")
+            .header("accept", "application/vnd.github.v3+json"),
+    )
+    .await?;
+
+    match response.status() {
+        StatusCode::OK => Ok(response.json().await?),
+        StatusCode::NOT_FOUND => Err(Error::NotFound("github gist not found")),
+        _ => Err(Error::Server("unexpected response from github")),
+    }
+}
+
+/// Makes sure the token grants the `gist` scope, warning if it grants more than that
+///
+/// # Errors
+///
+/// Fails if the token is rejected or lacks the `gist` scope
+pub async fn check_scopes(token: &Token) -> Result<()> {
+    let client = http::Client::new();
+    // Doesn't count against the rate limit and still reports the token scopes
+    let response = client
+        .send(
+            client
+                .get(GITHUB_RATE_LIMIT_API_URL)
+                .header("user-agent", "gistit")
+                .header("authorization", format!("token {}", token.access_token)),
+        )
+        .await?;
+
+    if response.status() == StatusCode::UNAUTHORIZED {
+        return Err(Error::OAuth("github rejected the token".to_owned()));
+    }
+
+    let scopes: Vec<String> = response
+        .headers()
+        .get("x-oauth-scopes")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or(&token.scope)
+        .split(',')
+        .map(str::trim)
+        .filter(|scope| !scope.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+
+    if !scopes.iter().any(|scope| scope == GITHUB_REQUIRED_SCOPE) {
+        return Err(Error::OAuth(format!(
+            "token is missing the `{}` scope",
+            GITHUB_REQUIRED_SCOPE
+        )));
+    }
+
+    let extra: Vec<&str> = scopes
+        .iter()
+        .map(String::as_str)
+        .filter(|scope| *scope != GITHUB_REQUIRED_SCOPE)
+        .collect();
+    if !extra.is_empty() {
+        warnln!(
+            "github token grants more than needed ({}), consider revoking it",
+            extra.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Asks the server to revoke the token with GitHub
+///
+/// # Errors
+///
+/// Fails if the server can't be reached or refuses to revoke
+pub async fn revoke(token: &Token) -> Result<()> {
+    let client = http::Client::new();
+    let response = client
+        .send(
+            client
+                .post(SERVER_URL_REVOKE.to_string())
+                .json(&serde_json::json!({ "access_token": token.access_token })),
+        )
+        .await?;
+
+    match response.status() {
+        StatusCode::OK | StatusCode::NOT_FOUND => Ok(()),
+        _ => Err(Error::Server("failed to revoke github token")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn github_rate_limit_reset() {
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, HeaderValue::from_str(value).unwrap());
+            }
+            headers
+        };
+        let now = 1_000;
+
+        let exhausted = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1030"),
+        ]);
+        assert_eq!(
+            rate_limit_reset(StatusCode::FORBIDDEN, &exhausted, now),
+            Some(30)
+        );
+        // Reset already, our clock is ahead
+        assert_eq!(
+            rate_limit_reset(StatusCode::FORBIDDEN, &exhausted, 2_000),
+            Some(0)
+        );
+        assert_eq!(rate_limit_reset(StatusCode::OK, &exhausted, now), None);
+
+        let secondary = headers(&[("retry-after", "5")]);
+        assert_eq!(
+            rate_limit_reset(StatusCode::TOO_MANY_REQUESTS, &secondary, now),
+            Some(5)
+        );
+
+        // Forbidden for other reasons, like a token without the gist scope
+        let left = headers(&[
+            ("x-ratelimit-remaining", "12"),
+            ("x-ratelimit-reset", "1030"),
+        ]);
+        assert_eq!(rate_limit_reset(StatusCode::FORBIDDEN, &left, now), None);
+        assert_eq!(
+            rate_limit_reset(StatusCode::FORBIDDEN, &HeaderMap::new(), now),
+            None
+        );
+    }
+}
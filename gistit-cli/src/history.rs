@@ -0,0 +1,301 @@
+//! The history module
+//!
+//! Every gistit sent or fetched is recorded in the data directory, without its contents, so it
+//! can be listed later with `gistit list`. Recording is best effort and never fails a command.
+//! With the vault enabled the history file is sealed with the passphrase.
+//!
+//! The gistit node writes it too when syncing history, see `gistit node --sync`. Both replace the
+//! file at once and hold its lock while updating it.
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use gistit_project::{file, path, vault};
+#[cfg(any(feature = "send", feature = "fetch"))]
+use gistit_proto::Gistit;
+
+use crate::keychain;
+#[cfg(any(feature = "send", feature = "fetch"))]
+use crate::warnln;
+use crate::{Error, Result};
+
+const HISTORY_FILE: &str = "history.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    Sent,
+    Fetched,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Language {
+    pub lang: String,
+    pub bytes: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub hash: String,
+    pub origin: Origin,
+    pub author: String,
+    pub description: Option<String>,
+    pub timestamp: String,
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub languages: Vec<Language>,
+    /// Sent to or fetched from the server, so asking it about the gistit tells it nothing new
+    #[serde(default)]
+    pub on_server: bool,
+}
+
+impl Entry {
+    #[cfg(any(feature = "send", feature = "fetch"))]
+    #[must_use]
+    pub fn new(gistit: &Gistit, origin: Origin) -> Self {
+        Self {
+            hash: gistit.hash.clone(),
+            origin,
+            author: gistit.author.clone(),
+            description: gistit.description.clone(),
+            timestamp: gistit.timestamp.clone(),
+            files: gistit
+                .inner
+                .iter()
+                .map(|inner| inner.name.clone())
+                .collect(),
+            languages: gistit
+                .language_stats()
+                .into_iter()
+                .map(|language| Language {
+                    lang: language.lang,
+                    bytes: language.bytes,
+                })
+                .collect(),
+            on_server: false,
+        }
+    }
+
+    /// Whether any file is in `lang`
+    #[must_use]
+    pub fn has_lang(&self, lang: &str) -> bool {
+        self.languages
+            .iter()
+            .any(|language| language.lang.eq_ignore_ascii_case(lang))
+    }
+
+    /// Whether the hash, author, description, a file name or a language contains `query`,
+    /// ignoring case
+    #[cfg(feature = "tui")]
+    #[must_use]
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        let contains = |text: &str| text.to_lowercase().contains(&query);
+
+        contains(&self.hash)
+            || contains(&self.author)
+            || self.description.as_deref().map_or(false, contains)
+            || self.files.iter().any(|file| contains(file))
+            || self
+                .languages
+                .iter()
+                .any(|language| contains(&language.lang))
+    }
+
+    /// Language shares as percentages, e.g. `rust 70%, toml 30%`
+    #[must_use]
+    pub fn composition(&self) -> String {
+        let total: u64 = self
+            .languages
+            .iter()
+            .map(|language| u64::from(language.bytes))
+            .sum();
+
+        self.languages
+            .iter()
+            .map(|language| {
+                let share = if total == 0 {
+                    100 / self.languages.len() as u64
+                } else {
+                    u64::from(language.bytes) * 100 / total
+                };
+                format!("{} {}%", language.lang, share)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn history_file() -> Result<PathBuf> {
+    Ok(path::data()?.join(HISTORY_FILE))
+}
+
+/// Keeps others from updating the history until dropped, hold it from [`load`] to [`save`]
+///
+/// # Errors
+///
+/// Fails if the lock file can't be created
+pub fn lock() -> Result<file::Lock> {
+    let file = history_file()?;
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(file::Lock::acquire(&file)?)
+}
+
+/// Reads the history, oldest first
+///
+/// # Errors
+///
+/// Fails if the history file exists but can't be read, unsealed or parsed
+pub fn load() -> Result<Vec<Entry>> {
+    let file = history_file()?;
+    if fs::metadata(&file).is_err() {
+        return Ok(Vec::new());
+    }
+
+    let mut bytes = fs::read(file)?;
+    if vault::is_sealed(&bytes) {
+        let vault = keychain::vault()?.ok_or(Error::Argument(
+            "history is sealed but the vault is disabled",
+            "gistit config vault",
+        ))?;
+        bytes = vault.open(&bytes)?;
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Overwrites the history, sealing it if the vault is enabled
+///
+/// # Errors
+///
+/// Fails if the history file can't be written
+pub fn save(entries: &[Entry]) -> Result<()> {
+    let file = history_file()?;
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut bytes = serde_json::to_vec_pretty(entries)?;
+    if let Some(vault) = keychain::vault()? {
+        bytes = vault.seal(&bytes)?;
+    }
+    file::replace(&file, &bytes)?;
+    Ok(())
+}
+
+/// Adds the entry to the history, moving it to the top if the hash is already there. A gistit
+/// sent by us stays sent when fetched again, `gistit amend` relies on it
+#[cfg(any(feature = "send", feature = "fetch"))]
+fn push(entries: &mut Vec<Entry>, mut entry: Entry) {
+    if entries
+        .iter()
+        .any(|known| known.hash == entry.hash && known.origin == Origin::Sent)
+    {
+        entry.origin = Origin::Sent;
+    }
+    entry.on_server |= entries
+        .iter()
+        .any(|known| known.hash == entry.hash && known.on_server);
+    entries.retain(|known| known.hash != entry.hash);
+    entries.push(entry);
+}
+
+/// Whether the gistit was sent by us, from here or from a machine syncing history with this one
+///
+/// # Errors
+///
+/// Fails if the history can't be read
+#[cfg(all(feature = "send", feature = "fetch"))]
+pub fn is_sent(hash: &str) -> Result<bool> {
+    Ok(load()?
+        .iter()
+        .any(|entry| entry.hash == hash && entry.origin == Origin::Sent))
+}
+
+/// Whether the gistit is known to be on the server, see [`Entry::on_server`]. Not if the history
+/// can't be read
+#[cfg(feature = "fetch")]
+#[must_use]
+pub fn on_server(hash: &str) -> bool {
+    load()
+        .unwrap_or_default()
+        .iter()
+        .any(|entry| entry.hash == hash && entry.on_server)
+}
+
+/// Records a gistit in the history, `on_server` if it went to or came from the server. Warns
+/// instead of failing
+#[cfg(any(feature = "send", feature = "fetch"))]
+pub fn record(gistit: &Gistit, origin: Origin, on_server: bool) {
+    let recorded = lock().and_then(|_lock| {
+        let mut entries = load()?;
+        push(
+            &mut entries,
+            Entry {
+                on_server,
+                ..Entry::new(gistit, origin)
+            },
+        );
+        save(&entries)
+    });
+
+    if let Err(err) = recorded {
+        warnln!("failed to record gistit in history: {}", err);
+    }
+}
+
+#[cfg(all(test, any(feature = "send", feature = "fetch")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_entry_language_composition() {
+        let gistit = Gistit::new(
+            "foo".to_owned(),
+            "Matthew McCaunaghey".to_owned(),
+            None,
+            String::new(),
+            vec![
+                Gistit::new_inner("main.rs".to_owned(), "rust".to_owned(), 75, String::new()),
+                Gistit::new_inner(
+                    "Cargo.toml".to_owned(),
+                    "toml".to_owned(),
+                    25,
+                    String::new(),
+                ),
+            ],
+        );
+
+        let entry = Entry::new(&gistit, Origin::Sent);
+        assert_eq!(entry.files, vec!["main.rs", "Cargo.toml"]);
+        assert_eq!(entry.composition(), "rust 75%, toml 25%");
+        assert!(entry.has_lang("Rust"));
+        assert!(!entry.has_lang("python"));
+        #[cfg(feature = "tui")]
+        {
+            assert!(entry.matches("CARGO"));
+            assert!(entry.matches("mccaun"));
+            assert!(entry.matches(""));
+            assert!(!entry.matches("python"));
+        }
+
+        let mut entries = vec![
+            entry.clone(),
+            Entry::new(&Gistit::default(), Origin::Fetched),
+        ];
+        entries[0].on_server = true;
+        push(
+            &mut entries,
+            Entry {
+                origin: Origin::Fetched,
+                ..entry
+            },
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].hash, "foo");
+        assert_eq!(entries[1].origin, Origin::Sent);
+        assert!(entries[1].on_server);
+    }
+}
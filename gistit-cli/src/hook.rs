@@ -0,0 +1,224 @@
+//! The hook module
+//!
+//! Shell commands set in the settings run at points of the send and fetch pipelines: `pre-send`
+//! right before a gistit is sent, e.g. to lint it, and `post-fetch` once a gistit is fetched,
+//! e.g. to open it. They learn about the gistit from environment variables, `FILE` being the path
+//! of one of its files on disk. `post-fetch` runs once for each file of the gistit. A hook that fails or runs past `hook-timeout` aborts the command, or only
+//! warns with `hook-failure: warn`.
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use gistit_project::settings::{HookFailure, Settings};
+use gistit_proto::Gistit;
+
+use crate::{progress, warnln, Error, Result};
+
+/// How long hooks run unless `hook-timeout` says otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct Hook {
+    /// The setting it comes from
+    name: &'static str,
+    command: &'static str,
+    timeout: Duration,
+    failure: HookFailure,
+}
+
+impl Hook {
+    fn from_settings(
+        name: &'static str,
+        command: Option<&'static str>,
+        settings: &Settings,
+    ) -> Option<Self> {
+        let command = command.filter(|command| !command.trim().is_empty())?;
+        Some(Self {
+            name,
+            command,
+            timeout: settings
+                .hook_timeout
+                .map_or(DEFAULT_TIMEOUT, Duration::from_secs),
+            failure: settings.hook_failure,
+        })
+    }
+
+    /// The `pre-send` hook, if set
+    #[cfg(feature = "send")]
+    #[must_use]
+    pub fn pre_send(settings: &'static Settings) -> Option<Self> {
+        Self::from_settings("pre-send", settings.pre_send.as_deref(), settings)
+    }
+
+    /// The `post-fetch` hook, if set
+    #[cfg(feature = "fetch")]
+    #[must_use]
+    pub fn post_fetch(settings: &'static Settings) -> Option<Self> {
+        Self::from_settings("post-fetch", settings.post_fetch.as_deref(), settings)
+    }
+
+    /// Runs the hook for the file at `index` of `gistit`, which is at `file`. It shares our
+    /// terminal, so it can print or prompt
+    ///
+    /// # Errors
+    ///
+    /// Fails if the hook can't be started, exits with an error or times out, unless failures
+    /// only warn
+    pub async fn run(&self, file: &Path, gistit: &Gistit, index: usize) -> Result<()> {
+        progress::clear();
+        match self.exec(file, gistit, index).await {
+            Err(err) if self.failure == HookFailure::Warn => {
+                warnln!("{}", err);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    async fn exec(&self, file: &Path, gistit: &Gistit, index: usize) -> Result<()> {
+        let mut child = shell(self.command)
+            .envs(env(self.name, file, gistit, index))
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| Error::Hook(format!("{} hook failed to start: {}", self.name, err)))?;
+
+        match tokio::time::timeout(self.timeout, child.wait()).await {
+            Ok(status) => {
+                let status = status?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(Error::Hook(format!(
+                        "{} hook `{}` exited with {}",
+                        self.name, self.command, status
+                    )))
+                }
+            }
+            Err(_) => {
+                let _ = child.kill().await;
+                Err(Error::Hook(format!(
+                    "{} hook `{}` timed out after {}s",
+                    self.name,
+                    self.command,
+                    self.timeout.as_secs()
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// What hooks know about the gistit and its file at `index`
+fn env(
+    name: &'static str,
+    file: &Path,
+    gistit: &Gistit,
+    index: usize,
+) -> Vec<(&'static str, String)> {
+    let inner = gistit.inner.get(index);
+    vec![
+        ("GISTIT_HOOK", name.to_owned()),
+        ("FILE", file.to_string_lossy().into_owned()),
+        ("GISTIT_HASH", gistit.hash.clone()),
+        (
+            "GISTIT_NAME",
+            inner.map(|inner| inner.name.clone()).unwrap_or_default(),
+        ),
+        (
+            "GISTIT_LANG",
+            inner.map(|inner| inner.lang.clone()).unwrap_or_default(),
+        ),
+        ("GISTIT_AUTHOR", gistit.author.clone()),
+        (
+            "GISTIT_DESCRIPTION",
+            gistit.description.clone().unwrap_or_default(),
+        ),
+    ]
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn hook(command: &'static str, timeout: Duration, failure: HookFailure) -> Hook {
+        Hook {
+            name: "pre-send",
+            command,
+            timeout,
+            failure,
+        }
+    }
+
+    #[tokio::test]
+    async fn hook_env_exit_status_and_timeout() {
+        let gistit = Gistit {
+            hash: "a".repeat(64),
+            author: "fabricio".to_owned(),
+            ..Gistit::default()
+        };
+        let file = Path::new("/tmp/main.rs");
+
+        let check = r#"test "$FILE" = /tmp/main.rs && test "$GISTIT_AUTHOR" = fabricio && test "$GISTIT_HOOK" = pre-send"#;
+        hook(check, DEFAULT_TIMEOUT, HookFailure::Abort)
+            .run(file, &gistit, 0)
+            .await
+            .unwrap();
+
+        let failing = hook("exit 3", DEFAULT_TIMEOUT, HookFailure::Abort);
+        assert!(matches!(
+            failing.run(file, &gistit, 0).await,
+            Err(Error::Hook(_))
+        ));
+        hook("exit 3", DEFAULT_TIMEOUT, HookFailure::Warn)
+            .run(file, &gistit, 0)
+            .await
+            .unwrap();
+
+        let slow = hook("sleep 5", Duration::from_millis(100), HookFailure::Abort);
+        let err = slow.run(file, &gistit, 0).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[test]
+    fn hook_env_describes_each_file() {
+        let gistit = gistit_proto::GistitBuilder::new("fabricio")
+            .file(
+                "main.rs",
+                "rust",
+                "fn main() {\n    println!(\"hello\");\n}".to_owned(),
+            )
+            .file(
+                "notes.md",
+                "markdown",
+                "# Notes on the main function".to_owned(),
+            )
+            .build()
+            .unwrap();
+        let value = |index, var| {
+            env("post-fetch", Path::new("/tmp/notes.md"), &gistit, index)
+                .into_iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value)
+                .unwrap()
+        };
+
+        assert_eq!(value(0, "GISTIT_NAME"), "main.rs");
+        assert_eq!(value(1, "GISTIT_NAME"), "notes.md");
+        assert_eq!(value(1, "GISTIT_LANG"), "markdown");
+        assert_eq!(value(1, "FILE"), "/tmp/notes.md");
+    }
+}
@@ -0,0 +1,385 @@
+//! The http module
+//!
+//! Every request to the gistit server and GitHub goes through [`Client`], so transient failures
+//! (5xx responses, timeouts, dropped connections) are retried with exponential backoff and jitter
+//! instead of bubbling up as fatal errors. Requests that aren't idempotent, like the POST creating
+//! a gistit, may already have gone through when they fail that way. Those are only retried when
+//! they never reached the server.
+//!
+//! Gistits are asked from the server compressed, with zstd or deflate, and decoded here before
+//! anyone else sees them. `--verbose` shows how much was transferred and how long it took.
+#[cfg(any(feature = "send", feature = "fetch"))]
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(any(feature = "send", feature = "fetch"))]
+use flate2::read::ZlibDecoder;
+use rand::Rng;
+#[cfg(any(feature = "send", feature = "fetch"))]
+use reqwest::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{IntoUrl, RequestBuilder, Response};
+
+use gistit_project::env;
+#[cfg(any(feature = "send", feature = "fetch"))]
+use gistit_proto::wire::Payload;
+#[cfg(any(feature = "send", feature = "fetch"))]
+use gistit_proto::Gistit;
+use gistit_proto::WireFormat;
+
+#[cfg(any(feature = "send", feature = "fetch"))]
+use crate::fmt::bytes;
+use crate::interrupt;
+#[cfg(any(feature = "send", feature = "fetch"))]
+use crate::Error;
+use crate::{verboseln, warnln, Result};
+
+/// Retries after the first attempt, overridable with `GISTIT_HTTP_RETRIES`
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// Compressions we decode, best first
+#[cfg(any(feature = "send", feature = "fetch"))]
+const ACCEPTED_ENCODINGS: &str = "zstd, deflate";
+
+/// Bodies decoding to more than this are refused, a gistit is nowhere near it
+#[cfg(any(feature = "send", feature = "fetch"))]
+const MAX_DECODED_SIZE: u64 = 64 * 1024 * 1024;
+
+static NO_RETRY: AtomicBool = AtomicBool::new(false);
+
+/// Disable retries for every client created from now on (`--no-retry`)
+pub fn disable_retries() {
+    NO_RETRY.store(true, Ordering::Relaxed);
+}
+
+static JSON_WIRE_FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// How gistits are sent to and asked from the server from now on (`--wire-format`)
+pub fn set_wire_format(format: WireFormat) {
+    JSON_WIRE_FORMAT.store(format == WireFormat::Json, Ordering::Relaxed);
+}
+
+#[cfg(any(feature = "send", feature = "fetch"))]
+#[must_use]
+pub fn wire_format() -> WireFormat {
+    if JSON_WIRE_FORMAT.load(Ordering::Relaxed) {
+        WireFormat::Json
+    } else {
+        WireFormat::Proto
+    }
+}
+
+/// Attaches `gistit` to a request in the [`wire_format`], asking for the response in it too
+///
+/// # Errors
+///
+/// Fails if the gistit can't be encoded
+#[cfg(any(feature = "send", feature = "fetch"))]
+pub fn with_gistit(request: RequestBuilder, gistit: &Gistit) -> Result<RequestBuilder> {
+    with_payload(request, gistit)
+}
+
+/// Like [`with_gistit`], for any payload the server takes, annotations for instance
+///
+/// # Errors
+///
+/// Fails if the payload can't be encoded
+#[cfg(any(feature = "send", feature = "fetch"))]
+pub fn with_payload<T: Payload>(request: RequestBuilder, payload: &T) -> Result<RequestBuilder> {
+    let format = wire_format();
+    Ok(request
+        .header(CONTENT_TYPE, format.content_type())
+        .header(ACCEPT, format.content_type())
+        .header(ACCEPT_ENCODING, ACCEPTED_ENCODINGS)
+        .body(format.encode_payload(payload)?))
+}
+
+/// Reads a gistit from a response in whichever format the server answered with. Servers
+/// predating negotiation don't say, and only speak protobuf
+///
+/// # Errors
+///
+/// Fails if the body can't be read or isn't a gistit
+#[cfg(any(feature = "send", feature = "fetch"))]
+pub async fn gistit_from(response: Response) -> Result<Gistit> {
+    payload_from(response).await
+}
+
+/// Like [`gistit_from`], for any payload the server answers with
+///
+/// # Errors
+///
+/// Fails if the body can't be read, decompressed or isn't a `T`
+#[cfg(any(feature = "send", feature = "fetch"))]
+pub async fn payload_from<T: Payload>(response: Response) -> Result<T> {
+    let format = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(WireFormat::from_content_type)
+        .unwrap_or(WireFormat::Proto);
+    let encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let started = Instant::now();
+    let body = response.bytes().await?;
+    let decoded = decode(encoding.as_deref(), &body)?;
+    verboseln!(
+        "received {} in {}ms, {} decoded ({})",
+        bytes(body.len() as u64),
+        started.elapsed().as_millis(),
+        bytes(decoded.len() as u64),
+        encoding.as_deref().unwrap_or("uncompressed")
+    );
+    Ok(format.decode_payload(&decoded)?)
+}
+
+/// Decompresses a body sent with `Content-Encoding: encoding`
+///
+/// # Errors
+///
+/// Fails on encodings we didn't ask for, corrupt bodies or ones decoding past [`MAX_DECODED_SIZE`]
+#[cfg(any(feature = "send", feature = "fetch"))]
+fn decode(encoding: Option<&str>, body: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let read = match encoding.map(str::trim) {
+        None | Some("" | "identity") => return Ok(body.to_vec()),
+        Some("zstd") => zstd::stream::read::Decoder::new(body)?
+            .take(MAX_DECODED_SIZE + 1)
+            .read_to_end(&mut decoded)?,
+        Some("deflate") => ZlibDecoder::new(body)
+            .take(MAX_DECODED_SIZE + 1)
+            .read_to_end(&mut decoded)?,
+        Some(_) => return Err(Error::Server("response compressed in an unknown way")),
+    };
+    if read as u64 > MAX_DECODED_SIZE {
+        return Err(Error::Server("response too large once decompressed"));
+    }
+    Ok(decoded)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        let max_retries = if NO_RETRY.load(Ordering::Relaxed) {
+            0
+        } else {
+            std::env::var(env::GISTIT_HTTP_RETRIES)
+                .ok()
+                .and_then(|retries| retries.parse().ok())
+                .unwrap_or(DEFAULT_MAX_RETRIES)
+        };
+
+        Self {
+            max_retries,
+            base_delay: BASE_DELAY,
+            max_delay: MAX_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential delay for a given attempt plus up to 50% random jitter
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let jitter = rand::thread_rng().gen_range(0..=exp.as_millis() as u64 / 2);
+
+        exp + Duration::from_millis(jitter)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    policy: RetryPolicy,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            inner: reqwest::Client::new(),
+            policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.inner.get(url)
+    }
+
+    pub fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.inner.post(url)
+    }
+
+    pub fn patch(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.inner.patch(url)
+    }
+
+    /// Sends the request, retrying on server errors and transient network failures, only failed
+    /// connections for requests that aren't idempotent. Retries included, it's bounded by
+    /// `--timeout`
+    ///
+    /// # Errors
+    ///
+    /// Fails with the last [`reqwest::Error`] once retries are exhausted, or on timeout
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.send_waiting(request, |_| None).await
+    }
+
+    /// Like [`Self::send`], also waiting out responses `wait` says how long to wait for, like
+    /// rate limits. Those count as retries, the last one is returned as is
+    ///
+    /// # Errors
+    ///
+    /// Fails with the last [`reqwest::Error`] once retries are exhausted, or on timeout
+    pub async fn send_waiting<W>(&self, request: RequestBuilder, wait: W) -> Result<Response>
+    where
+        W: Fn(&Response) -> Option<Duration> + Send + Sync,
+    {
+        let started = Instant::now();
+        let response = interrupt::bounded("request", self.send_with_retries(request, wait)).await?;
+        verboseln!(
+            "{} {} in {}ms",
+            response.url(),
+            response.status(),
+            started.elapsed().as_millis()
+        );
+        Ok(response)
+    }
+
+    async fn send_with_retries<W>(&self, request: RequestBuilder, wait: W) -> Result<Response>
+    where
+        W: Fn(&Response) -> Option<Duration> + Send + Sync,
+    {
+        let request = request.build()?;
+        let idempotent = request.method().is_idempotent();
+        let mut attempt = 0;
+
+        loop {
+            // Streaming bodies can't be replayed, those are sent only once
+            let current = match request.try_clone() {
+                Some(current) => current,
+                None => return Ok(self.inner.execute(request).await?),
+            };
+            let exhausted = attempt >= self.policy.max_retries;
+
+            let delay = match self.inner.execute(current).await {
+                Ok(response) if response.status().is_server_error() && idempotent && !exhausted => {
+                    warnln!("server responded {}, retrying", response.status());
+                    self.policy.delay(attempt)
+                }
+                Ok(response) if !exhausted => match wait(&response) {
+                    Some(delay) => delay,
+                    None => return Ok(response),
+                },
+                Ok(response) => return Ok(response),
+                Err(err) if is_transient(&err, idempotent) && !exhausted => {
+                    warnln!("request failed ({}), retrying", err);
+                    self.policy.delay(attempt)
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether sending again may go through. Only a failed connection is sure not to have reached the
+/// server
+fn is_transient(err: &reqwest::Error, idempotent: bool) -> bool {
+    err.is_connect() || (idempotent && (err.is_timeout() || err.is_request()))
+}
+
+#[cfg(all(test, any(feature = "send", feature = "fetch")))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn http_decode_compressed_bodies() {
+        let body = b"fn main() {\n    println!(\"hello\");\n}\n".repeat(100);
+
+        let zstd = zstd::stream::encode_all(&body[..], 3).unwrap();
+        assert!(zstd.len() < body.len());
+        assert_eq!(decode(Some("zstd"), &zstd).unwrap(), body);
+
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(&body).unwrap();
+        let deflate = deflate.finish().unwrap();
+        assert_eq!(decode(Some("deflate"), &deflate).unwrap(), body);
+
+        assert_eq!(decode(None, &body).unwrap(), body);
+        assert_eq!(decode(Some("identity"), &body).unwrap(), body);
+        assert!(decode(Some("br"), &body).is_err());
+        assert!(decode(Some("zstd"), &body).is_err());
+    }
+
+    #[tokio::test]
+    async fn http_retry_only_idempotent_requests() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&hits);
+        std::thread::spawn(move || {
+            for stream in server.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).unwrap();
+                counted.fetch_add(1, Ordering::SeqCst);
+                stream
+                    .write_all(
+                        b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .unwrap();
+            }
+        });
+        let client = Client {
+            inner: reqwest::Client::new(),
+            policy: RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            },
+        };
+
+        let response = client.send(client.get(&url)).await.unwrap();
+        assert!(response.status().is_server_error());
+        assert_eq!(hits.swap(0, Ordering::SeqCst), 3);
+
+        let response = client.send(client.post(&url).body("gistit")).await.unwrap();
+        assert!(response.status().is_server_error());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+}
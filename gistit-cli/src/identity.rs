@@ -0,0 +1,366 @@
+//! The identity module
+//!
+//! `gistit node --export-identity <file>` bundles what makes a gistit node itself, to move it to
+//! another machine with `--import-identity`: its keypair, so the peer id stays the same and links
+//! and allowlists of other peers keep working, its trusted and denied peers, the address book of
+//! persistent peers and the gistits it hosts. Bundles are sealed with a passphrase of their own,
+//! see [`vault::seal_with_passphrase`]. Imported gistits are provided again the next time the
+//! node starts.
+use std::fs;
+use std::path::Path;
+
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Password;
+use serde::{Deserialize, Serialize};
+
+use gistit_project::vault;
+use gistit_proto::Gistit;
+
+use crate::archive::NODE_CONFIG_FILE;
+use crate::file;
+use crate::keychain;
+use crate::{Error, Result};
+
+/// Trusted and denied peers, in the config directory, see `gistit-daemon`
+const TRUST_FILE: &str = "trusted-peers.json";
+
+/// Persistent peers, in the data directory, see `gistit-daemon`
+const PEERS_FILE: &str = "peers.json";
+
+/// Hosted gistits of an imported identity, in the data directory until the node provides them
+const PENDING_FILE: &str = "hosted-import.json";
+
+/// Bumped when bundles change in a way older builds can't read
+const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bundle {
+    version: u32,
+    pub peer_id: String,
+    node_config: String,
+    trusted_peers: Option<String>,
+    peers: Option<String>,
+    #[serde(default)]
+    pub hosted: Vec<Gistit>,
+}
+
+/// The daemon's `node-config`, only what we need of it
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NodeKey {
+    identity: NodeIdentity,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NodeIdentity {
+    #[serde(rename = "PeerID")]
+    peer_id: String,
+    priv_key: String,
+}
+
+/// Libp2p's protobuf encoding of an ed25519 keypair, the only kind the node makes: key type 1,
+/// then the 32 bytes of the secret key followed by the public key
+const KEYPAIR_PREFIX: [u8; 4] = [0x08, 0x01, 0x12, 0x40];
+
+/// The encoded public key alone, which the peer id holds as an identity multihash (code 0, 36
+/// bytes long)
+const PEER_ID_PREFIX: [u8; 6] = [0x00, 0x24, 0x08, 0x01, 0x12, 0x20];
+
+/// The peer id of the keypair in a node config, if it's a keypair and the config holds that peer
+/// id. The node refuses to start otherwise
+fn peer_id(node_config: &str) -> Option<String> {
+    let key: NodeKey = serde_json::from_str(node_config).ok()?;
+    let encoded = base64::decode(&key.identity.priv_key).ok()?;
+    if encoded.len() != KEYPAIR_PREFIX.len() + 64 || !encoded.starts_with(&KEYPAIR_PREFIX) {
+        return None;
+    }
+    let (secret, public) = encoded[KEYPAIR_PREFIX.len()..].split_at(32);
+    let derived =
+        ed25519_dalek::PublicKey::from(&ed25519_dalek::SecretKey::from_bytes(secret).ok()?);
+    if derived.as_bytes()[..] != *public {
+        return None;
+    }
+
+    let peer_id = bs58::encode([&PEER_ID_PREFIX[..], derived.as_bytes()].concat()).into_string();
+    if peer_id == key.identity.peer_id {
+        Some(peer_id)
+    } else {
+        None
+    }
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    if fs::metadata(path).is_err() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+/// Reads the passphrase of a bundle from `GISTIT_PASSPHRASE` or asks for it, twice if `confirm`
+///
+/// # Errors
+///
+/// Fails if the prompt can't be shown
+pub fn passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = vault::passphrase_from_env() {
+        return Ok(passphrase);
+    }
+
+    let theme = ColorfulTheme::default();
+    let mut prompt = Password::with_theme(&theme);
+    prompt.with_prompt("Bundle passphrase");
+    if confirm {
+        prompt.with_confirmation("Repeat passphrase", "passphrases don't match");
+    }
+    Ok(prompt.interact()?)
+}
+
+impl Bundle {
+    /// Bundles the identity of the node using these directories along with `hosted`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the node has no identity yet or a file can't be read
+    pub fn new(config_path: &Path, data_path: &Path, hosted: Vec<Gistit>) -> Result<Self> {
+        let node_config = fs::read_to_string(config_path.join(NODE_CONFIG_FILE)).map_err(|_| {
+            Error::Argument(
+                "the gistit node has no identity yet, start it once first",
+                "--export-identity",
+            )
+        })?;
+
+        let peer_id = peer_id(&node_config).ok_or(Error::Argument(
+            "the gistit node identity is malformed, its peer id doesn't match its keypair",
+            "--export-identity",
+        ))?;
+
+        Ok(Self {
+            version: BUNDLE_VERSION,
+            peer_id,
+            node_config,
+            trusted_peers: read_optional(&config_path.join(TRUST_FILE))?,
+            peers: read_optional(&data_path.join(PEERS_FILE))?,
+            hosted,
+        })
+    }
+
+    /// The bundle sealed with `passphrase`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the passphrase is empty or encryption fails
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>> {
+        Ok(vault::seal_with_passphrase(
+            passphrase,
+            &serde_json::to_vec(self)?,
+        )?)
+    }
+
+    /// Opens a bundle sealed with [`Self::seal`]
+    ///
+    /// # Errors
+    ///
+    /// Fails if the passphrase is wrong, or the bundle is malformed or from a newer build
+    pub fn open(sealed: &[u8], passphrase: &str) -> Result<Self> {
+        let bundle: Self =
+            serde_json::from_slice(&vault::open_with_passphrase(passphrase, sealed)?)?;
+        if bundle.version > BUNDLE_VERSION {
+            return Err(Error::Argument(
+                "bundle was exported by a newer gistit, update to import it",
+                "--import-identity",
+            ));
+        }
+        if peer_id(&bundle.node_config).as_deref() != Some(bundle.peer_id.as_str()) {
+            return Err(Error::Argument(
+                "bundle is malformed, its peer id doesn't match its keypair",
+                "--import-identity",
+            ));
+        }
+        Ok(bundle)
+    }
+
+    /// Writes the identity where the node looks for it. A different identity already there is
+    /// only replaced if `force`, every file replaced is backed up. Hosted gistits wait in the data
+    /// directory for the node to start
+    ///
+    /// # Errors
+    ///
+    /// Fails if another identity is there and not `force`, or a file can't be written
+    pub fn restore(&self, config_path: &Path, data_path: &Path, force: bool) -> Result<()> {
+        let node_config = config_path.join(NODE_CONFIG_FILE);
+        if let Some(current) = read_optional(&node_config)? {
+            if !force && peer_id(&current).as_deref() != Some(self.peer_id.as_str()) {
+                return Err(Error::Argument(
+                    "this node has another identity, use '--force' to replace it",
+                    "--force",
+                ));
+            }
+        }
+
+        fs::create_dir_all(config_path)?;
+        fs::create_dir_all(data_path)?;
+        replace(&node_config, &self.node_config, true)?;
+        if let Some(trusted_peers) = &self.trusted_peers {
+            replace(&config_path.join(TRUST_FILE), trusted_peers, false)?;
+        }
+        if let Some(peers) = &self.peers {
+            replace(&data_path.join(PEERS_FILE), peers, false)?;
+        }
+        if !self.hosted.is_empty() {
+            // Private gistits are in there in the clear
+            gistit_project::file::replace_private(
+                &data_path.join(PENDING_FILE),
+                &serde_json::to_vec(&self.hosted)?,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `contents` to `path`, backing up what was there unless it's the same
+fn replace(path: &Path, contents: &str, secret: bool) -> Result<()> {
+    match read_optional(path)? {
+        Some(current) if current == contents => return Ok(()),
+        Some(_) => {
+            file::backup(path)?;
+        }
+        None => (),
+    }
+
+    if secret {
+        keychain::write_private(path, contents.as_bytes())
+    } else {
+        Ok(fs::write(path, contents)?)
+    }
+}
+
+/// Hosted gistits of an imported identity the node didn't provide yet
+///
+/// # Errors
+///
+/// Fails if they can't be read
+pub fn pending(data_path: &Path) -> Result<Vec<Gistit>> {
+    let path = data_path.join(PENDING_FILE);
+    if fs::metadata(&path).is_err() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Forgets the hosted gistits of an imported identity once the node provides them
+///
+/// # Errors
+///
+/// Fails if they can't be removed
+pub fn clear_pending(data_path: &Path) -> Result<()> {
+    let path = data_path.join(PENDING_FILE);
+    if fs::metadata(&path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    /// Keypairs made by the node and their peer ids
+    const OLD: (&str, &str) = (
+        "12D3KooWJ1TsijH7H5F74hfAD5XishQz3sxrmAtVY37GtNd9CqYf",
+        "CAESQAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8gebVWLo/mVPlAeLES6KmLp5AfhTrmlb7X4OORC60ElmQ=",
+    );
+    const NEW: (&str, &str) = (
+        "12D3KooWC4T1AXU2s2YBgGJ2FeaYVtsKoHZWJeubnWe9SnuSE7Zb",
+        "CAESQEJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCIVL40Zt5HSRFMkLhXy6rbLfP+ntqXtMAl5YOBpiB2xI=",
+    );
+
+    fn node_config((peer_id, priv_key): (&str, &str)) -> String {
+        format!(
+            r#"{{"Identity":{{"PeerID":"{}","PrivKey":"{}"}}}}"#,
+            peer_id, priv_key
+        )
+    }
+
+    #[test]
+    fn identity_peer_id_from_keypair() {
+        assert_eq!(peer_id(&node_config(OLD)).as_deref(), Some(OLD.0));
+        assert_eq!(peer_id(&node_config(NEW)).as_deref(), Some(NEW.0));
+        // Someone else's peer id next to our keypair
+        assert_eq!(peer_id(&node_config((NEW.0, OLD.1))), None);
+        assert_eq!(peer_id(&node_config((OLD.0, "c2VjcmV0"))), None);
+
+        // A public key that isn't the secret key's
+        let mut encoded = base64::decode(OLD.1).unwrap();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        assert_eq!(
+            peer_id(&node_config((OLD.0, &base64::encode(encoded)))),
+            None
+        );
+    }
+
+    #[test]
+    fn identity_bundle_roundtrip() {
+        let tmp = TempDir::new().unwrap();
+        let (config, data) = (tmp.path().join("config"), tmp.path().join("data"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&data).unwrap();
+        fs::write(config.join(NODE_CONFIG_FILE), node_config(OLD)).unwrap();
+        fs::write(config.join(TRUST_FILE), r#"{"allowed":[]}"#).unwrap();
+
+        let hosted = vec![Gistit {
+            hash: "a".repeat(64),
+            ..Gistit::default()
+        }];
+        let bundle = Bundle::new(&config, &data, hosted.clone()).unwrap();
+        assert_eq!(bundle.peer_id, OLD.0);
+        assert_eq!(bundle.peers, None);
+
+        let sealed = bundle.seal("hunter2").unwrap();
+        assert!(Bundle::open(&sealed, "hunter3").is_err());
+        let bundle = Bundle::open(&sealed, "hunter2").unwrap();
+
+        // A bundle claiming another peer id than its keypair's
+        let forged = Bundle {
+            peer_id: NEW.0.to_owned(),
+            ..Bundle::open(&sealed, "hunter2").unwrap()
+        };
+        assert!(Bundle::open(&forged.seal("hunter2").unwrap(), "hunter2").is_err());
+
+        let other = tmp.path().join("other");
+        let (config, data) = (other.join("config"), other.join("data"));
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join(NODE_CONFIG_FILE), node_config(NEW)).unwrap();
+        assert!(bundle.restore(&config, &data, false).is_err());
+        assert!(pending(&data).unwrap().is_empty());
+
+        bundle.restore(&config, &data, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(config.join(NODE_CONFIG_FILE)).unwrap(),
+            node_config(OLD)
+        );
+        assert_eq!(
+            fs::read_to_string(config.join(TRUST_FILE)).unwrap(),
+            r#"{"allowed":[]}"#
+        );
+        assert_eq!(pending(&data).unwrap(), hosted);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(data.join(PENDING_FILE))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // Importing the same identity again needs no force
+        bundle.restore(&config, &data, false).unwrap();
+
+        clear_pending(&data).unwrap();
+        assert!(pending(&data).unwrap().is_empty());
+    }
+}
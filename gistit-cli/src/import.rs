@@ -0,0 +1,82 @@
+//! The import module
+//!
+//! Restores a backup made with `gistit export`. Files already on this machine win unless
+//! `--force` is given, gistits are addressed by hash so the cache never conflicts.
+use std::fs::File;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use gistit_project::path;
+
+use crate::archive::{self, CONFIG_DIR, DATA_DIR};
+use crate::dispatch::Dispatch;
+use crate::{finish, progress, updateln, warnln, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub file: &'static str,
+    pub force: bool,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            file: args
+                .value_of("FILE")
+                .ok_or(Error::Argument("missing argument", "FILE"))?,
+            force: args.is_present("force"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    file: File,
+    config_path: PathBuf,
+    data_path: PathBuf,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let file = File::open(self.file)?;
+        updateln!("Prepared");
+
+        Ok(Config {
+            file,
+            config_path: path::config()?,
+            data_path: path::data()?,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        progress!("Importing");
+        let roots = [
+            (CONFIG_DIR, config.config_path.as_path()),
+            (DATA_DIR, config.data_path.as_path()),
+        ];
+        let summary = archive::unpack(config.file, &roots, self.force)?;
+        updateln!("Imported");
+
+        if summary.existing > 0 && !self.force {
+            warnln!(
+                "{} files already exist and were kept, use '--force' to overwrite them",
+                summary.existing
+            );
+        }
+
+        finish!(format!(
+            "\n    {} files imported\n\n",
+            style(summary.files).bold()
+        ));
+        Ok(())
+    }
+}
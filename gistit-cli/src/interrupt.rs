@@ -0,0 +1,72 @@
+//! The interrupt module
+//!
+//! Network and ipc waits are bounded by the global `--timeout`, and Ctrl-C aborts whatever is in
+//! flight leaving the terminal as it was before we started.
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use gistit_project::exit::ErrorKind;
+
+use crate::temp;
+use crate::{errorln, interruptln, Error, Result};
+
+/// Seconds, zero means no timeout
+static TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+/// Bounds every network and ipc wait from now on (`--timeout`)
+pub fn set_timeout(timeout: Duration) {
+    TIMEOUT.store(timeout.as_secs(), Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn timeout() -> Option<Duration> {
+    match TIMEOUT.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Awaits `future`, giving up once `--timeout` elapses
+///
+/// # Errors
+///
+/// Fails with [`Error::Timeout`] naming `what` was being waited on, or with the future's error
+pub async fn bounded<T, E>(
+    what: &'static str,
+    future: impl Future<Output = std::result::Result<T, E>>,
+) -> Result<T>
+where
+    E: Into<Error>,
+{
+    match timeout() {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| Error::Timeout(what))?
+            .map_err(Into::into),
+        None => future.await.map_err(Into::into),
+    }
+}
+
+/// Puts the terminal back the way we found it, prompts hide the cursor while they run and
+/// `gistit tui` takes the whole screen
+pub fn restore_terminal() {
+    #[cfg(feature = "tui")]
+    crate::tui::restore();
+    let _ = console::Term::stderr().show_cursor();
+    let _ = console::Term::stdout().show_cursor();
+}
+
+/// Exits cleanly on Ctrl-C. This runs apart from the main task so it also works while it's
+/// blocked on a prompt or printing a preview
+pub fn handle_ctrl_c() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            restore_terminal();
+            temp::remove_own();
+            interruptln!();
+            errorln!("interrupted");
+            std::process::exit(ErrorKind::Interrupted.code());
+        }
+    });
+}
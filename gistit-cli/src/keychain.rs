@@ -0,0 +1,250 @@
+//! The keychain module
+//!
+//! The GitHub token lives in the OS keyring when one is available. Otherwise it is encrypted into
+//! the config directory with a key kept apart in the data directory, both readable only by the
+//! current user. With the vault enabled it is sealed with the passphrase instead, and never
+//! handed to the keyring.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Password;
+use lazy_static::lazy_static;
+use rand::RngCore;
+
+use gistit_project::vault::{self, Vault};
+use gistit_project::{file, path};
+
+use crate::github::Token;
+use crate::{progress, Error, Result};
+
+const KEYRING_SERVICE: &str = "gistit";
+const KEYRING_USER: &str = "github";
+
+/// Plaintext token written by older versions, migrated on first load
+const LEGACY_TOKEN_FILE: &str = "github";
+const TOKEN_FILE: &str = "github.enc";
+const KEY_FILE: &str = "github.key";
+const VAULT_TOKEN_FILE: &str = "github.vault";
+
+/// Names of every file the token may be kept in
+pub const TOKEN_FILES: [&str; 4] = [LEGACY_TOKEN_FILE, TOKEN_FILE, KEY_FILE, VAULT_TOKEN_FILE];
+
+const NONCE_LEN: usize = 12;
+
+/// Where the token ended up
+#[derive(Debug, Clone)]
+pub enum Storage {
+    Keyring,
+    File(PathBuf),
+}
+
+impl std::fmt::Display for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Keyring => write!(f, "system keyring"),
+            Self::File(path) => write!(f, "{}", path.to_string_lossy()),
+        }
+    }
+}
+
+fn keyring() -> keyring::Entry {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+}
+
+lazy_static! {
+    /// Unlocked once per invocation
+    static ref VAULT: Mutex<Option<Vault>> = Mutex::new(None);
+}
+
+/// Reads a passphrase from `GISTIT_PASSPHRASE` or asks for it
+///
+/// # Errors
+///
+/// Fails if the prompt can't be shown
+pub fn passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = vault::passphrase_from_env() {
+        return Ok(passphrase);
+    }
+
+    // The spinner would be drawn over the prompt
+    progress::clear();
+    let theme = ColorfulTheme::default();
+    let mut prompt = Password::with_theme(&theme);
+    prompt.with_prompt("Vault passphrase");
+    if confirm {
+        prompt.with_confirmation("Repeat passphrase", "passphrases don't match");
+    }
+    Ok(prompt.interact()?)
+}
+
+/// The unlocked vault, `None` unless the user enabled it
+///
+/// # Errors
+///
+/// Fails if the passphrase is wrong
+pub fn vault() -> Result<Option<Vault>> {
+    if !vault::enabled() {
+        return Ok(None);
+    }
+
+    let mut unlocked = VAULT.lock().expect("vault lock to not be poisoned");
+    if let Some(ref vault) = *unlocked {
+        return Ok(Some(vault.clone()));
+    }
+
+    let vault = Vault::unlock(&passphrase(false)?)?;
+    *unlocked = Some(vault.clone());
+    Ok(Some(vault))
+}
+
+/// Creates a new vault and keeps it unlocked for the rest of this invocation
+///
+/// # Errors
+///
+/// Fails if the vault file can't be written
+pub fn create_vault(passphrase: &str) -> Result<()> {
+    let vault = Vault::create(passphrase)?;
+    *VAULT.lock().expect("vault lock to not be poisoned") = Some(vault);
+    Ok(())
+}
+
+/// Loads the stored token, if any, migrating plaintext tokens from older versions
+///
+/// # Errors
+///
+/// Fails if the stored token can't be read or decrypted
+pub fn load() -> Result<Option<Token>> {
+    if let Some(vault) = vault()? {
+        let sealed = path::config()?.join(VAULT_TOKEN_FILE);
+        if fs::metadata(&sealed).is_ok() {
+            return Ok(Some(serde_json::from_slice(
+                &vault.open(&fs::read(sealed)?)?,
+            )?));
+        }
+    }
+
+    if let Ok(secret) = keyring().get_password() {
+        return Ok(Some(serde_json::from_str(&secret)?));
+    }
+
+    let config = path::config()?;
+    let legacy = config.join(LEGACY_TOKEN_FILE);
+    if fs::metadata(&legacy).is_ok() {
+        let token: Token = serde_json::from_str(&fs::read_to_string(&legacy)?)?;
+        store(&token)?;
+        fs::remove_file(&legacy)?;
+        return Ok(Some(token));
+    }
+
+    let encrypted = config.join(TOKEN_FILE);
+    if fs::metadata(&encrypted).is_ok() {
+        let secret = decrypt(&fs::read_to_string(&encrypted)?, &secret_key()?)?;
+        return Ok(Some(serde_json::from_slice(&secret)?));
+    }
+
+    Ok(None)
+}
+
+/// Stores the token in the keyring, falling back to an encrypted file
+///
+/// # Errors
+///
+/// Fails if neither the keyring nor the fallback file can be written
+pub fn store(token: &Token) -> Result<Storage> {
+    let secret = serde_json::to_string(token)?;
+    if let Some(vault) = vault()? {
+        let sealed = path::config()?.join(VAULT_TOKEN_FILE);
+        write_private(&sealed, &vault.seal(secret.as_bytes())?)?;
+        return Ok(Storage::File(sealed));
+    }
+
+    if keyring().set_password(&secret).is_ok() {
+        return Ok(Storage::Keyring);
+    }
+
+    let encrypted = path::config()?.join(TOKEN_FILE);
+    write_private(
+        &encrypted,
+        encrypt(secret.as_bytes(), &secret_key()?)?.as_bytes(),
+    )?;
+    Ok(Storage::File(encrypted))
+}
+
+/// Deletes the token from every place it could be stored
+///
+/// # Errors
+///
+/// Fails if a stored file can't be removed
+pub fn delete() -> Result<()> {
+    // Missing entries and keyring-less systems are fine here
+    keyring().delete_password().ok();
+
+    let config = path::config()?;
+    for file in [
+        config.join(LEGACY_TOKEN_FILE),
+        config.join(TOKEN_FILE),
+        config.join(VAULT_TOKEN_FILE),
+        path::data()?.join(KEY_FILE),
+    ] {
+        if fs::metadata(&file).is_ok() {
+            fs::remove_file(&file)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The fallback encryption key, generated on first use
+fn secret_key() -> Result<Vec<u8>> {
+    let key_path = path::data()?.join(KEY_FILE);
+    if fs::metadata(&key_path).is_ok() {
+        return Ok(fs::read(&key_path)?);
+    }
+
+    let mut key = vec![0_u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    write_private(&key_path, &key)?;
+
+    Ok(key)
+}
+
+fn encrypt(plaintext: &[u8], key: &[u8]) -> Result<String> {
+    let mut nonce = [0_u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| Error::OAuth("failed to encrypt github token".to_owned()))?,
+    );
+
+    Ok(base64::encode(sealed))
+}
+
+fn decrypt(sealed: &str, key: &[u8]) -> Result<Vec<u8>> {
+    let invalid =
+        || Error::OAuth("stored github token is corrupted, run `gistit github logout`".to_owned());
+    let sealed = base64::decode(sealed.trim()).map_err(|_| invalid())?;
+    if sealed.len() < NONCE_LEN || key.len() != 32 {
+        return Err(invalid());
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| invalid())
+}
+
+/// Writes a file only the current user can read, see [`file::replace_private`]
+///
+/// # Errors
+///
+/// Fails if the file can't be written
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    Ok(file::replace_private(path, contents)?)
+}
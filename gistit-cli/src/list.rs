@@ -0,0 +1,75 @@
+//! The list module
+//!
+//! Prints the local history of sent and fetched gistits, newest first, along with the languages
+//! each one is made of.
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use crate::dispatch::Dispatch;
+use crate::history::{self, Entry, Origin};
+use crate::Result;
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub lang: Option<&'static str>,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            lang: args.value_of("lang"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    entries: Vec<Entry>,
+}
+
+fn format_entry(entry: &Entry) -> String {
+    let origin = match entry.origin {
+        Origin::Sent => style("sent   ").green(),
+        Origin::Fetched => style("fetched").blue(),
+    };
+
+    format!(
+        "{} {} {} | {} | {}",
+        style(entry.hash.get(..8).unwrap_or(&entry.hash)).dim(),
+        origin,
+        style(entry.files.join(", ")).bold(),
+        style(entry.composition()).cyan(),
+        style(entry.description.as_deref().unwrap_or("")).italic()
+    )
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        let entries = history::load()?
+            .into_iter()
+            .rev()
+            .filter(|entry| self.lang.map_or(true, |lang| entry.has_lang(lang)))
+            .collect();
+
+        Ok(Config { entries })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        if config.entries.is_empty() {
+            println!("{}", style("no gistits in history").dim());
+            return Ok(());
+        }
+
+        for entry in &config.entries {
+            println!("{}", format_entry(entry));
+        }
+
+        Ok(())
+    }
+}
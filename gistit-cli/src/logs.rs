@@ -0,0 +1,137 @@
+//! The logs module
+//!
+//! Filters for `gistit node --attach`. The node logs through `env_logger`, one record per line
+//! headed by `[<timestamp> <level> <module>]`, so we filter on our side while following its log
+//! file. Lines without a header, e.g. the rest of a multi line error, belong to the record before
+//! them.
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use console::strip_ansi_codes;
+use regex::Regex;
+
+use crate::stats::days_from_civil;
+
+/// Most severe first, so `<=` reads as "at least as severe as"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(level: &str) -> std::result::Result<Self, Self::Err> {
+        match level.to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    /// Unix timestamp in seconds, older records are left out
+    since: Option<u64>,
+    level: Option<Level>,
+    grep: Option<Regex>,
+    /// Whether the last record matched, its headerless lines follow it
+    matching: bool,
+}
+
+impl Filter {
+    #[must_use]
+    pub fn new(since: Option<Duration>, level: Option<Level>, grep: Option<Regex>) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Self {
+            since: since.map(|since| now.saturating_sub(since.as_secs())),
+            level,
+            grep,
+            matching: true,
+        }
+    }
+
+    /// Whether `line` should be shown. Lines must be fed in order
+    pub fn matches(&mut self, line: &str) -> bool {
+        let plain = strip_ansi_codes(line);
+        if let Some((timestamp, level)) = header(&plain) {
+            self.matching = self.since.map_or(true, |since| timestamp >= since)
+                && self.level.map_or(true, |wanted| level <= wanted)
+                && self
+                    .grep
+                    .as_ref()
+                    .map_or(true, |grep| grep.is_match(&plain));
+        }
+        self.matching
+    }
+}
+
+/// Timestamp and level of a record's first line
+fn header(line: &str) -> Option<(u64, Level)> {
+    let (header, _) = line.strip_prefix('[')?.split_once(']')?;
+    let mut parts = header.split_whitespace();
+    let timestamp = timestamp(parts.next()?)?;
+    let level = parts.next()?.parse().ok()?;
+    Some((timestamp, level))
+}
+
+/// Seconds since the unix epoch of `2022-04-01T12:34:56Z`, as `env_logger` writes it. Fractions
+/// of a second are dropped
+fn timestamp(rfc3339: &str) -> Option<u64> {
+    let (date, time) = rfc3339.strip_suffix('Z')?.split_once('T')?;
+    let time = time.split('.').next()?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hours, minutes, seconds) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    u64::try_from(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_filter_records() {
+        assert_eq!(timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(timestamp("2022-03-01T00:00:00Z"), Some(1_646_092_800));
+        assert_eq!(timestamp("2022-02-28T23:59:59.123Z"), Some(1_646_092_799));
+        assert_eq!(timestamp("yesterday"), None);
+
+        let mut filter = Filter {
+            since: Some(1_646_092_800),
+            level: Some(Level::Warn),
+            grep: Some(Regex::new("peer").unwrap()),
+            matching: true,
+        };
+        // Too old
+        assert!(!filter.matches("[2022-02-28T23:59:59Z WARN  gistit_daemon::node] peer left"));
+        // Not severe enough
+        assert!(!filter.matches("[2022-03-01T00:00:00Z INFO  gistit_daemon::node] peer joined"));
+        assert!(!filter.matches("[2022-03-01T00:00:01Z ERROR gistit_daemon::node] bad gistit"));
+        // Colored, as the node writes it, and followed by a line of the same record
+        assert!(filter.matches(
+            "\u{1b}[2m[\u{1b}[0m2022-03-01T00:00:02Z \u{1b}[31mERROR\u{1b}[0m gistit_daemon::node\u{1b}[2m]\u{1b}[0m peer dial failed"
+        ));
+        assert!(filter.matches("    caused by: connection refused"));
+        assert!(!filter.matches("[2022-03-01T00:00:03Z DEBUG gistit_daemon::node] peer"));
+        assert!(!filter.matches("    more details"));
+
+        let mut filter = Filter::new(None, None, None);
+        assert!(filter.matches("not a record"));
+        assert!(filter.matches("[2022-03-01T00:00:03Z TRACE gistit_daemon] anything"));
+    }
+}
@@ -21,19 +21,90 @@
     )
 )]
 
+use std::ffi::OsString;
+
+use gistit_project::exit::ErrorKind;
+use gistit_project::settings::{self, Settings};
+
+mod account;
+mod alias;
+#[cfg(all(feature = "send", feature = "fetch"))]
+mod amend;
+#[cfg(feature = "fetch")]
+mod annotate;
+mod archive;
 mod arg;
+#[cfg(any(feature = "send", feature = "p2p"))]
+mod bridge;
+mod cache;
+mod clean;
+mod config;
+#[cfg(all(feature = "send", feature = "fetch"))]
+mod convert;
+#[cfg(feature = "fetch")]
+mod diff;
 mod dispatch;
+mod doctor;
+#[cfg(any(feature = "send", feature = "fetch"))]
+mod encrypt;
+#[cfg(feature = "fetch")]
+mod explore;
+mod export;
+#[cfg(feature = "fetch")]
 mod fetch;
 mod fmt;
+mod git;
+mod history;
+#[cfg(any(feature = "send", feature = "fetch"))]
+mod hook;
+mod http;
+#[cfg(feature = "p2p")]
+mod identity;
+mod import;
+mod interrupt;
+mod list;
+#[cfg(feature = "p2p")]
+mod logs;
+#[cfg(feature = "p2p")]
 mod node;
 mod param;
+mod progress;
+#[cfg(feature = "fetch")]
+mod resolve;
+#[cfg(feature = "send")]
+mod scan;
+#[cfg(feature = "fetch")]
+mod search;
+#[cfg(feature = "send")]
 mod send;
+#[cfg(any(feature = "send", feature = "fetch"))]
+mod serve;
+#[cfg(feature = "p2p")]
+mod service;
+#[cfg(feature = "fetch")]
+mod sniff;
+#[cfg(feature = "send")]
+mod snippet;
+mod stats;
+#[cfg(feature = "send")]
 mod stdin;
+#[cfg(any(feature = "send", feature = "fetch"))]
+mod syntax;
+mod temp;
+#[cfg(feature = "fetch")]
+mod themes;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "send")]
+mod uplink;
+mod web;
 
+#[cfg(feature = "clipboard")]
 pub mod clipboard;
 pub mod error;
 pub mod file;
 pub mod github;
+pub mod keychain;
 pub mod patch;
 pub mod server;
 
@@ -41,19 +112,32 @@ pub use error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    if let Err(err) = run().await {
+async fn main() {
+    interrupt::handle_ctrl_c();
+
+    let result = run().await;
+    temp::remove_own();
+
+    if let Err(err) = result {
+        interrupt::restore_terminal();
         interruptln!();
         errorln!(err);
+        std::process::exit(err.kind().code());
     };
-
-    Ok(())
 }
 
 #[allow(clippy::single_match_else)]
 async fn run() -> Result<()> {
-    let matches = Box::leak(Box::new(arg::app().get_matches()));
     gistit_project::path::init()?;
+    let loaded = settings::load();
+
+    // Broken settings are reported below, unless running `gistit config` to fix them
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let argv = match &loaded {
+        Ok(settings) => alias::expand(argv, settings)?,
+        Err(_) => argv,
+    };
+    let matches = Box::leak(Box::new(arg::app().get_matches_from(argv)));
 
     let (cmd, args) = if let Some((cmd, args)) = matches.subcommand() {
         (cmd, Some(args))
@@ -61,28 +145,216 @@ async fn run() -> Result<()> {
         ("", None)
     };
 
+    // Settings can't stop `gistit config` from fixing them
+    let settings: &'static Settings = if cmd == "config" {
+        Box::leak(Box::new(Settings::default()))
+    } else {
+        Box::leak(Box::new(loaded?))
+    };
+
+    if fmt::no_color_env()
+        || settings.no_color
+        || matches.is_present("no-color")
+        || args.map_or(false, |args| args.is_present("no-color"))
+    {
+        fmt::disable_colors();
+    }
+
+    if let Some(format) = args
+        .and_then(|args| args.value_of("format"))
+        .or_else(|| matches.value_of("format"))
+    {
+        fmt::set_format(format.parse()?);
+    }
+
+    if matches.is_present("progress-json")
+        || args.map_or(false, |args| args.is_present("progress-json"))
+    {
+        progress::set_mode(progress::Mode::Json);
+    } else if matches.is_present("quiet")
+        || args.map_or(false, |args| args.is_present("quiet"))
+        // Only the result goes to stdout
+        || fmt::format() != fmt::Format::Text
+    {
+        progress::set_mode(progress::Mode::Quiet);
+    }
+
+    let verbosity = matches
+        .occurrences_of("verbose")
+        .max(args.map_or(0, |args| args.occurrences_of("verbose")));
+    if verbosity > 0 {
+        progress::set_verbosity(u8::try_from(verbosity).unwrap_or(u8::MAX));
+    }
+
+    if settings.no_retry
+        || matches.is_present("no-retry")
+        || args.map_or(false, |args| args.is_present("no-retry"))
+    {
+        http::disable_retries();
+    }
+
+    if let Some(format) = args
+        .and_then(|args| args.value_of("wire-format"))
+        .or_else(|| matches.value_of("wire-format"))
+    {
+        http::set_wire_format(format.parse()?);
+    }
+
+    if matches.is_present("insecure-runtime-dir")
+        || args.map_or(false, |args| args.is_present("insecure-runtime-dir"))
+    {
+        gistit_ipc::allow_insecure_runtime_dir();
+    }
+
+    if let Some(timeout) = args
+        .and_then(|args| args.value_of("timeout"))
+        .or_else(|| matches.value_of("timeout"))
+    {
+        interrupt::set_timeout(param::check::timeout(timeout)?);
+    }
+
     if matches.is_present("list-colorschemes") {
         list_bat_colorschemes();
         std::process::exit(0);
     }
 
+    if matches.is_present("help-exit-codes") {
+        list_exit_codes();
+        std::process::exit(0);
+    }
+
     match (cmd, args) {
+        #[cfg(feature = "fetch")]
         ("fetch", Some(args)) => {
-            let action = fetch::Action::from_args(args)?;
+            let action = fetch::Action::from_args(args, settings)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(all(feature = "send", feature = "fetch"))]
+        ("amend", Some(args)) => {
+            let action = amend::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(feature = "fetch")]
+        ("annotate", Some(args)) => {
+            let action = annotate::Action::from_args(args, settings)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(feature = "fetch")]
+        ("diff", Some(args)) => {
+            let action = diff::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(all(feature = "send", feature = "fetch"))]
+        ("convert", Some(args)) => {
+            let action = convert::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("github", Some(args)) => {
+            let action = account::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(feature = "fetch")]
+        ("explore", Some(args)) => {
+            let action = explore::Action::from_args(args, settings)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(feature = "fetch")]
+        ("search", Some(args)) => {
+            let action = search::Action::from_args(args, settings)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("list", Some(args)) => {
+            let action = list::Action::from_args(args)?;
             let payload = action.prepare().await?;
             action.dispatch(payload).await?;
         }
+        ("config", Some(args)) => {
+            let action = config::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("doctor", Some(args)) => {
+            let action = doctor::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("clean", Some(args)) => {
+            let action = clean::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("stats", Some(args)) => {
+            let action = stats::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(feature = "fetch")]
+        ("themes", Some(args)) => {
+            let action = themes::Action::from_args(args, settings)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("web", Some(args)) => {
+            let action = web::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("export", Some(args)) => {
+            let action = export::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        ("import", Some(args)) => {
+            let action = import::Action::from_args(args)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(feature = "p2p")]
         ("node", Some(args)) => {
-            let action = node::Action::from_args(args)?;
+            let action = node::Action::from_args(args, settings)?;
+            let payload = action.prepare().await?;
+            action.dispatch(payload).await?;
+        }
+        #[cfg(feature = "tui")]
+        ("tui", Some(args)) => {
+            let action = tui::Action::from_args(args)?;
             let payload = action.prepare().await?;
             action.dispatch(payload).await?;
         }
+        #[cfg(not(feature = "tui"))]
+        ("tui", Some(_)) => return Err(Error::Feature("tui")),
+        #[cfg(not(feature = "fetch"))]
+        (
+            "fetch" | "amend" | "annotate" | "diff" | "convert" | "explore" | "search" | "themes",
+            Some(_),
+        ) => return Err(Error::Feature("fetch")),
+        #[cfg(all(feature = "fetch", not(feature = "send")))]
+        ("amend" | "convert", Some(_)) => return Err(Error::Feature("send")),
+        #[cfg(not(feature = "p2p"))]
+        ("node", Some(_)) => return Err(Error::Feature("p2p")),
+        #[cfg(not(feature = "send"))]
+        _ => return Err(Error::Feature("send")),
+        #[cfg(feature = "send")]
         _ => {
-            let default_action = if matches.is_present("FILE") {
-                send::Action::from_args(matches, None)?
+            let default_action = if matches.is_present("FILE")
+                || matches.is_present("from-url")
+                || matches.is_present("from-clipboard")
+                || matches.is_present("git-diff")
+            {
+                send::Action::from_args(matches, None, settings)?
             } else {
-                let stdin = stdin::read_to_end();
-                send::Action::from_args(matches, Some(stdin))?
+                let stdin =
+                    stdin::read_to_end(settings.stdin_limit.unwrap_or(stdin::READ_LIMIT_BYTES))
+                        .await?;
+                send::Action::from_args(matches, Some(stdin), settings)?
             };
 
             let payload = default_action.prepare().await?;
@@ -93,6 +365,15 @@ async fn run() -> Result<()> {
     Ok(())
 }
 
+fn list_exit_codes() {
+    println!("{}", console::style("Exit codes: \n").green().bold());
+    println!("    0  success");
+    for kind in ErrorKind::ALL {
+        println!("    {:<2} {}", kind.code(), kind.describe());
+    }
+    println!();
+}
+
 fn list_bat_colorschemes() {
     println!(
         "{}",
@@ -101,6 +382,10 @@ fn list_bat_colorschemes() {
     for c in param::SUPPORTED_COLORSCHEMES {
         println!("    {}", c);
     }
+    println!(
+        "\nPreview them with '{}'",
+        console::style("gistit themes --preview").bold()
+    );
     println!(
         r#"
 This application uses '{}' to view gistits inside your terminal.
@@ -3,19 +3,47 @@ use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use clap::ArgMatches;
-use console::style;
+use console::{style, Term};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Password;
+use serde::Deserialize;
 
-use gistit_project::path;
-use gistit_proto::{ipc, Instruction};
+use gistit_project::exit::ErrorKind;
+use gistit_project::settings::Settings;
+use gistit_project::{env, path, vault};
+use gistit_proto::ipc::instruction::persistent_peer_request::Change;
+use gistit_proto::ipc::instruction::trust_peer_request::Trust;
+use gistit_proto::{ipc, GistitHash, Instruction};
 
 use crate::arg::app;
+use crate::bridge::supports;
+use crate::config;
 use crate::dispatch::Dispatch;
+use crate::fmt::{self, ago, bytes, Format};
+use crate::http;
+use crate::identity;
+use crate::interrupt;
+use crate::keychain;
+use crate::logs;
 use crate::param::check;
-use crate::{cleanln, errorln, finish, interruptln, progress, updateln, Error, Result};
+use crate::service::{self, Service};
+use crate::{cleanln, errorln, finish, interruptln, progress, updateln, warnln, Error, Result};
+
+const BOOTSTRAP_LIST_FILE: &str = "bootstrap.json";
+pub const LOG_FILE: &str = "gistit.log";
+
+/// How often `--wait-ready` asks the node whether it's ready
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often `--status --watch` redraws
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The daemon executable, resolved from `PATH`
+pub const DAEMON_BINARY: &str = "gistit-daemon";
 
 #[derive(Debug, Clone)]
 #[allow(clippy::struct_excessive_bools)]
@@ -23,29 +51,96 @@ pub struct Action {
     pub start: bool,
     pub stop: bool,
     pub status: bool,
+    pub watch: bool,
     pub attach: bool,
+    pub since: Option<&'static str>,
+    pub grep: Option<&'static str>,
+    pub level: Option<&'static str>,
+    pub inbox: bool,
+    pub trusted_peers: bool,
+    pub allow_peer: Option<&'static str>,
+    pub deny_peer: Option<&'static str>,
+    pub forget_peer: Option<&'static str>,
+    pub peers: bool,
+    pub add_peer: Option<&'static str>,
+    pub remove_peer: Option<&'static str>,
+    pub audit: bool,
+    pub audit_hash: Option<&'static str>,
+    pub audit_peer_id: Option<&'static str>,
+    pub reload_config: bool,
+    pub bootstrap_list: Option<&'static str>,
+    pub reseed: bool,
+    pub reseed_cap: Option<&'static str>,
+    pub query_parallelism: Option<&'static str>,
+    pub query_timeout: Option<&'static str>,
+    pub request_timeout: Option<&'static str>,
+    pub provide_dir: Option<&'static str>,
+    pub no_pex: bool,
+    pub keep_history: bool,
+    pub sync: bool,
+    pub randomize_port: bool,
+    pub author_setting: Option<&'static str>,
+    pub listen: Vec<&'static str>,
+    pub wait_ready: Option<&'static str>,
+    pub install_service: bool,
+    pub uninstall_service: bool,
+    pub export_identity: Option<&'static str>,
+    pub import_identity: Option<&'static str>,
+    pub force: bool,
     // Hidden args
-    dial: Option<&'static str>,
-    host: &'static str,
-    port: &'static str,
+    dial: Vec<&'static str>,
+    host: Option<&'static str>,
+    port: Option<&'static str>,
 }
 
 impl Action {
     pub fn from_args(
         args: &'static ArgMatches,
+        settings: &'static Settings,
     ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
         Ok(Box::new(Self {
             start: args.is_present("start"),
             stop: args.is_present("stop"),
             status: args.is_present("status"),
+            watch: args.is_present("watch"),
             attach: args.is_present("attach"),
-            dial: args.value_of("dial"),
-            host: args
-                .value_of("host")
-                .ok_or(Error::Argument("missing argument", "--host"))?,
-            port: args
-                .value_of("port")
-                .ok_or(Error::Argument("missing argument", "--host"))?,
+            since: args.value_of("since"),
+            grep: args.value_of("grep"),
+            level: args.value_of("level"),
+            inbox: args.is_present("inbox"),
+            trusted_peers: args.is_present("trusted-peers"),
+            allow_peer: args.value_of("allow-peer"),
+            deny_peer: args.value_of("deny-peer"),
+            forget_peer: args.value_of("forget-peer"),
+            peers: args.is_present("peers"),
+            add_peer: args.value_of("add-peer"),
+            remove_peer: args.value_of("remove-peer"),
+            audit: args.is_present("audit"),
+            audit_hash: args.value_of("hash"),
+            audit_peer_id: args.value_of("peer-id"),
+            reload_config: args.is_present("reload-config"),
+            bootstrap_list: args.value_of("bootstrap-list"),
+            reseed: args.is_present("reseed"),
+            reseed_cap: args.value_of("reseed-cap"),
+            query_parallelism: args.value_of("query-parallelism"),
+            query_timeout: args.value_of("query-timeout"),
+            request_timeout: args.value_of("request-timeout"),
+            provide_dir: args.value_of("provide-dir"),
+            no_pex: args.is_present("no-pex"),
+            keep_history: args.is_present("keep-history"),
+            sync: args.is_present("sync"),
+            randomize_port: args.is_present("randomize-port"),
+            author_setting: settings.author.as_deref(),
+            dial: args.values_of("dial").into_iter().flatten().collect(),
+            listen: args.values_of("listen").into_iter().flatten().collect(),
+            wait_ready: args.value_of("wait-ready"),
+            install_service: args.is_present("install-service"),
+            uninstall_service: args.is_present("uninstall-service"),
+            export_identity: args.value_of("export-identity"),
+            import_identity: args.value_of("import-identity"),
+            force: args.is_present("force"),
+            host: args.value_of("host"),
+            port: args.value_of("port"),
         }))
     }
 }
@@ -53,16 +148,51 @@ impl Action {
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum ProcessCommand {
     Start,
+    /// Polls the node until it's listening and bootstrapped, for at most this long
+    WaitReady(Duration),
     Status,
+    /// Redraws the status in place until interrupted
+    WatchStatus,
     Stop,
     Attach,
+    Inbox,
+    /// `None` only lists trusted peers
+    Trust(Option<(Trust, &'static str)>),
+    /// `None` only lists persistent peers
+    Peers(Option<(Change, &'static str)>),
+    /// Served requests, filtered by hash and peer id
+    Audit(Option<GistitHash>, Option<&'static str>),
+    ReloadConfig,
     Dial(&'static str),
+    InstallService,
+    UninstallService,
+    /// Bundle file, whether to replace it
+    ExportIdentity(&'static str, bool),
+    /// Bundle file, whether to replace a different identity
+    ImportIdentity(&'static str, bool),
 }
 
 pub struct Config {
     commands: Vec<ProcessCommand>,
-    host: &'static str,
-    port: &'static str,
+    bootstrap_peers: Vec<String>,
+    reseed: bool,
+    reseed_cap: Option<u64>,
+    query_parallelism: Option<u32>,
+    query_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    /// Directory the node provides and the author of its gistits
+    provide_dir: Option<(PathBuf, String)>,
+    no_pex: bool,
+    keep_history: bool,
+    /// Handed to the node in the environment, not in its arguments
+    sync_secret: Option<String>,
+    randomize_port: bool,
+    host: Option<&'static str>,
+    /// `None` binds the port from the last start again
+    port: Option<&'static str>,
+    listen: Vec<&'static str>,
+    /// Which log records `--attach` shows
+    log_filter: logs::Filter,
     runtime_path: PathBuf,
     config_path: PathBuf,
 }
@@ -74,34 +204,66 @@ impl Dispatch for Action {
     async fn prepare(&self) -> Result<Self::InnerData> {
         progress!("Preparing");
         let mut commands: Vec<ProcessCommand> = Vec::new();
-        match (self.start, self.stop, self.status, self.attach, self.dial) {
+        let wait_ready = self
+            .wait_ready
+            .map(|secs| {
+                check::timeout(secs).map_err(|_| {
+                    Error::Argument(
+                        "timeout must be a positive number of seconds",
+                        "--wait-ready",
+                    )
+                })
+            })
+            .transpose()?;
+
+        match (
+            self.start,
+            self.stop,
+            self.status,
+            self.attach,
+            !self.dial.is_empty(),
+        ) {
             // Matching:
             // - start
             // - start [attach]
             // - start [dial]
             // - start [attach] [dial]
-            (true, false, false, attach, dial) => {
+            (true, false, false, attach, _) => {
                 commands.push(ProcessCommand::Start);
 
-                if let Some(addr) = dial {
-                    commands.push(ProcessCommand::Dial(addr));
+                if let Some(timeout) = wait_ready {
+                    commands.push(ProcessCommand::WaitReady(timeout));
                 }
 
+                commands.extend(self.dial.iter().copied().map(ProcessCommand::Dial));
+
                 if attach {
                     commands.push(ProcessCommand::Attach);
                 }
             }
             // Matching:
+            // - status watch
+            // - status watch [dial]
+            (false, false, true, false, _) if self.watch => {
+                if !Term::stdout().is_term() {
+                    return Err(Error::Argument("--watch needs a terminal", "--watch"));
+                }
+                if fmt::format() != Format::Text {
+                    return Err(Error::Argument("--watch only prints text", "--format"));
+                }
+                // Dial first, watching only ends with ctrl-c
+                commands.extend(self.dial.iter().copied().map(ProcessCommand::Dial));
+                commands.push(ProcessCommand::WatchStatus);
+            }
+            // Matching:
             // - status
             // - status [attach]
             // - status [dial]
             // - status [attach] [dial]
-            (false, false, true, attach, dial) => {
+            (false, false, true, attach, _) => {
                 commands.push(ProcessCommand::Status);
 
-                if let Some(addr) = dial {
-                    commands.push(ProcessCommand::Dial(addr));
-                }
+                commands.extend(self.dial.iter().copied().map(ProcessCommand::Dial));
 
                 if attach {
                     commands.push(ProcessCommand::Attach);
@@ -110,18 +272,16 @@ impl Dispatch for Action {
             // Matching:
             // - attach
             // - attach [dial]
-            (false, false, false, true, dial) => {
+            (false, false, false, true, _) => {
                 commands.push(ProcessCommand::Attach);
 
-                if let Some(addr) = dial {
-                    commands.push(ProcessCommand::Dial(addr));
-                }
+                commands.extend(self.dial.iter().copied().map(ProcessCommand::Dial));
             }
             // Matching:
             // - dial
             // - dial [attach]
-            (false, false, false, attach, Some(addr)) => {
-                commands.push(ProcessCommand::Dial(addr));
+            (false, false, false, attach, true) => {
+                commands.extend(self.dial.iter().copied().map(ProcessCommand::Dial));
 
                 if attach {
                     commands.push(ProcessCommand::Attach);
@@ -129,21 +289,199 @@ impl Dispatch for Action {
             }
             // Matching:
             // - stop
-            (false, true, false, false, None) => commands.push(ProcessCommand::Stop),
+            (false, true, false, false, false) => commands.push(ProcessCommand::Stop),
+            // Matching:
+            // - install-service
+            // - uninstall-service
+            (false, false, false, false, false) if self.install_service => {
+                commands.push(ProcessCommand::InstallService);
+            }
+            (false, false, false, false, false) if self.uninstall_service => {
+                commands.push(ProcessCommand::UninstallService);
+            }
+            // Matching:
+            // - export-identity [force]
+            // - import-identity [force]
+            (false, false, false, false, false) if self.export_identity.is_some() => {
+                commands.push(ProcessCommand::ExportIdentity(
+                    self.export_identity.unwrap_or_default(),
+                    self.force,
+                ));
+            }
+            (false, false, false, false, false) if self.import_identity.is_some() => {
+                commands.push(ProcessCommand::ImportIdentity(
+                    self.import_identity.unwrap_or_default(),
+                    self.force,
+                ));
+            }
+            // Matching:
+            // - inbox
+            (false, false, false, false, false) if self.inbox => {
+                commands.push(ProcessCommand::Inbox);
+            }
+            // Matching:
+            // - trusted-peers
+            // - allow-peer | deny-peer | forget-peer
+            (false, false, false, false, false) if self.trusted_peers => {
+                commands.push(ProcessCommand::Trust(None));
+            }
+            (false, false, false, false, false)
+                if self
+                    .allow_peer
+                    .or(self.deny_peer)
+                    .or(self.forget_peer)
+                    .is_some() =>
+            {
+                let (trust, peer_id, arg) =
+                    match (self.allow_peer, self.deny_peer, self.forget_peer) {
+                        (Some(peer_id), _, _) => (Trust::Allow, peer_id, "--allow-peer"),
+                        (_, Some(peer_id), _) => (Trust::Deny, peer_id, "--deny-peer"),
+                        (_, _, Some(peer_id)) => (Trust::Forget, peer_id, "--forget-peer"),
+                        _ => unreachable!(),
+                    };
+                check::peer_id(peer_id, arg)?;
+                commands.push(ProcessCommand::Trust(Some((trust, peer_id))));
+            }
+            // Matching:
+            // - peers
+            // - add-peer | remove-peer
+            (false, false, false, false, false) if self.peers => {
+                commands.push(ProcessCommand::Peers(None));
+            }
+            (false, false, false, false, false) if self.add_peer.is_some() => {
+                let address = check::peer_addr(self.add_peer.unwrap_or_default())?;
+                commands.push(ProcessCommand::Peers(Some((Change::Add, address))));
+            }
+            (false, false, false, false, false) if self.remove_peer.is_some() => {
+                let address = self.remove_peer.unwrap_or_default();
+                commands.push(ProcessCommand::Peers(Some((Change::Remove, address))));
+            }
+            // Matching:
+            // - audit [hash] [peer-id]
+            (false, false, false, false, false) if self.audit => {
+                let hash = self.audit_hash.map(check::hash).transpose()?;
+                let peer_id = self
+                    .audit_peer_id
+                    .map(|peer_id| check::peer_id(peer_id, "--peer-id"))
+                    .transpose()?;
+                commands.push(ProcessCommand::Audit(hash, peer_id));
+            }
+            // Matching:
+            // - reload-config
+            (false, false, false, false, false) if self.reload_config => {
+                commands.push(ProcessCommand::ReloadConfig);
+            }
             // No match. Clap should not let this branch happen
             (_, _, _, _, _) => {
                 app().print_help()?;
-                std::process::exit(1);
+                std::process::exit(ErrorKind::Argument.code());
             }
         };
 
-        let (host, port) = check::host_port(self.host, self.port)?;
+        let host = self.host.map(check::host).transpose()?;
+        let port = self.port.map(check::port).transpose()?;
+        let listen = self
+            .listen
+            .iter()
+            .copied()
+            .map(check::listen_addr)
+            .collect::<Result<_>>()?;
+        let config_path = path::config()?;
+        let bootstrap_peers = if let Some(list) = self.bootstrap_list {
+            load_bootstrap_list(list).await?
+        } else if fs::metadata(config_path.join(BOOTSTRAP_LIST_FILE)).is_ok() {
+            load_bootstrap_list(&config_path.join(BOOTSTRAP_LIST_FILE).to_string_lossy()).await?
+        } else {
+            Vec::new()
+        };
+
+        let reseed_cap = self
+            .reseed_cap
+            .map(|cap| {
+                cap.parse()
+                    .map_err(|_| Error::Argument("invalid number of bytes", "--reseed-cap"))
+            })
+            .transpose()?;
+        let query_parallelism = self
+            .query_parallelism
+            .map(check::query_parallelism)
+            .transpose()?;
+        let query_timeout = self
+            .query_timeout
+            .map(|secs| {
+                check::timeout(secs).map_err(|_| {
+                    Error::Argument(
+                        "timeout must be a positive number of seconds",
+                        "--query-timeout",
+                    )
+                })
+            })
+            .transpose()?;
+        let request_timeout = self
+            .request_timeout
+            .map(|secs| {
+                check::timeout(secs).map_err(|_| {
+                    Error::Argument(
+                        "timeout must be a positive number of seconds",
+                        "--request-timeout",
+                    )
+                })
+            })
+            .transpose()?;
+
+        // The daemon doesn't run from our working directory
+        let provide_dir = self
+            .provide_dir
+            .map(|dir| -> Result<(PathBuf, String)> {
+                let dir = fs::canonicalize(dir)?;
+                if !dir.is_dir() {
+                    return Err(Error::Argument("not a directory", "--provide-dir"));
+                }
+                let (author, _) = check::author(None, self.author_setting)?;
+                Ok((dir, author))
+            })
+            .transpose()?;
+
+        let sync_secret = if self.sync {
+            // The node can't open the history sealed by the vault
+            if vault::enabled() {
+                return Err(Error::Argument(
+                    "history sealed by the vault can't be synced",
+                    "--sync",
+                ));
+            }
+            progress::clear();
+            Some(sync_secret()?)
+        } else {
+            None
+        };
+
+        let log_filter = logs::Filter::new(
+            self.since.map(check::since).transpose()?,
+            // Validated by clap
+            self.level.and_then(|level| level.parse().ok()),
+            self.grep.map(check::grep).transpose()?,
+        );
+
         let config = Config {
             commands,
+            bootstrap_peers,
+            reseed: self.reseed,
+            reseed_cap,
+            query_parallelism,
+            query_timeout,
+            request_timeout,
+            provide_dir,
+            no_pex: self.no_pex,
+            keep_history: self.keep_history,
+            sync_secret,
+            randomize_port: self.randomize_port,
             host,
             port,
+            listen,
+            log_filter,
             runtime_path: path::runtime()?,
-            config_path: path::config()?,
+            config_path,
         };
         updateln!("Prepared");
 
@@ -151,20 +489,22 @@ impl Dispatch for Action {
     }
 
     #[allow(clippy::too_many_lines)]
-    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+    async fn dispatch(&self, mut config: Self::InnerData) -> Result<()> {
         let mut bridge = gistit_ipc::client(&config.runtime_path)?;
 
         for command in &config.commands {
             match command {
                 ProcessCommand::Start => {
                     if bridge.alive() {
-                        bridge.connect_blocking()?;
+                        bridge.connect().await?;
                         bridge.send(Instruction::request_status()).await?;
 
                         if let ipc::instruction::Kind::StatusResponse(response) =
-                            bridge.recv().await?.expect_response()?
+                            interrupt::bounded("gistit node", bridge.recv())
+                                .await?
+                                .expect_response()?
                         {
-                            format_daemon_status(&response);
+                            format_daemon_status(&response)?;
                         }
 
                         continue;
@@ -172,72 +512,300 @@ impl Dispatch for Action {
 
                     progress!("Starting gistit node");
                     let pid = {
-                        let stdout = fs::File::create(config.runtime_path.join("gistit.log"))?;
-                        // FIXME: Fix this before release
-                        let daemon = "gistit-daemon";
-
-                        Command::new(daemon)
-                            .args(&["--host", config.host])
-                            .args(&["--port", config.port])
-                            .args(&["--runtime-path", &*config.runtime_path.to_string_lossy()])
-                            .args(&["--config-path", &*config.config_path.to_string_lossy()])
-                            .arg("--bootstrap")
-                            .stderr(stdout)
-                            .stdout(Stdio::null())
-                            .spawn()?
-                            .id()
+                        let stdout = fs::File::create(config.runtime_path.join(LOG_FILE))?;
+                        let mut daemon = Command::new(DAEMON_BINARY);
+                        daemon.args(daemon_args(&config));
+                        if let Some(secret) = &config.sync_secret {
+                            daemon.env(env::GISTIT_SYNC_SECRET, secret);
+                        }
+                        daemon.stderr(stdout).stdout(Stdio::null());
+                        detach(&mut daemon);
+                        daemon.spawn()?.id()
                     };
 
                     updateln!("Gistit node started, pid: {}", style(pid).blue());
-                    bridge.connect_blocking()?;
+                    bridge.connect().await?;
                     bridge.send(Instruction::request_status()).await?;
 
                     if let ipc::instruction::Kind::StatusResponse(
                         ipc::instruction::StatusResponse { peer_id, .. },
-                    ) = bridge.recv().await?.expect_response()?
+                    ) = interrupt::bounded("gistit node", bridge.recv())
+                        .await?
+                        .expect_response()?
                     {
                         cleanln!(format!("\n    peer id: '{}'\n\n", style(peer_id).bold()));
                     }
+                    provide_imported(&mut bridge).await?;
+                }
+
+                ProcessCommand::WaitReady(timeout) => {
+                    progress!("Waiting for gistit node to be ready");
+                    let deadline = Instant::now() + *timeout;
+                    loop {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        bridge.connect().await?;
+                        bridge.send(Instruction::request_ping()).await?;
+
+                        // Nodes predating health checks never answer
+                        let response = tokio::time::timeout(remaining, bridge.recv())
+                            .await
+                            .map_err(|_| Error::Timeout("gistit node readiness"))??;
+                        if let ipc::instruction::Kind::PongResponse(pong) =
+                            response.expect_response()?
+                        {
+                            if pong.is_ready() {
+                                break;
+                            }
+                            if let Some(err) = pong.bootstrap_error {
+                                return Err(Error::Service(format!(
+                                    "gistit node failed to bootstrap: {}",
+                                    err
+                                )));
+                            }
+                        }
+
+                        if Instant::now() + READY_POLL_INTERVAL >= deadline {
+                            return Err(Error::Timeout("gistit node readiness"));
+                        }
+                        tokio::time::sleep(READY_POLL_INTERVAL).await;
+                    }
+                    updateln!("Gistit node ready");
+                }
+
+                ProcessCommand::InstallService => {
+                    progress!("Installing service");
+                    let installed = service::install(&Service {
+                        program: which::which(DAEMON_BINARY)?,
+                        args: daemon_args(&config),
+                        log: config.runtime_path.join(LOG_FILE),
+                    })?;
+                    updateln!("Installed service");
+                    finish!(format!(
+                        "\n    service: '{}' {}\n\n",
+                        style(installed.display()).bold(),
+                        style("(starts at login)").italic().dim()
+                    ));
+                }
+
+                ProcessCommand::UninstallService => {
+                    progress!("Uninstalling service");
+                    match service::uninstall()? {
+                        Some(removed) => {
+                            updateln!("Uninstalled service");
+                            finish!(format!("\n    removed: '{}'\n\n", removed.display()));
+                        }
+                        None => warnln!("gistit node service is not installed"),
+                    }
+                }
+
+                ProcessCommand::ExportIdentity(file, force) => {
+                    export_identity(&mut bridge, &config, Path::new(file), *force).await?;
+                }
+
+                ProcessCommand::ImportIdentity(file, force) => {
+                    import_identity(&bridge, &config, Path::new(file), *force)?;
                 }
 
                 ProcessCommand::Stop => {
                     progress!("Stopping");
                     if bridge.alive() {
-                        fs::remove_file(config.runtime_path.join("gistit.log"))?;
+                        fs::remove_file(config.runtime_path.join(LOG_FILE))?;
 
-                        bridge.connect_blocking()?;
+                        bridge.connect().await?;
                         bridge.send(Instruction::request_shutdown()).await?;
                         updateln!("Stopped");
                         finish!("");
                     } else {
                         interruptln!();
                         errorln!("gistit node is not running");
-                        std::process::exit(1);
+                        std::process::exit(ErrorKind::Daemon.code());
                     }
                 }
 
                 ProcessCommand::Status => {
                     progress!("Requesting status");
                     if bridge.alive() {
-                        bridge.connect_blocking()?;
-                        bridge.send(Instruction::request_status()).await?;
+                        bridge.connect().await?;
+                        bridge.send(Instruction::hello()).await?;
+
+                        let response = interrupt::bounded("gistit node", bridge.recv()).await?;
+                        let negotiated = response.negotiate();
+                        if negotiated.is_outdated() {
+                            warnln!(
+                                "gistit node is outdated (protocol v{}), consider restarting it",
+                                negotiated.version
+                            );
+                        }
 
                         if let ipc::instruction::Kind::StatusResponse(response) =
-                            bridge.recv().await?.expect_response()?
+                            response.expect_response()?
+                        {
+                            format_daemon_status(&response)?;
+                        }
+                    } else {
+                        interruptln!();
+                        errorln!("gistit node is not running");
+                        std::process::exit(ErrorKind::Daemon.code());
+                    }
+                }
+
+                ProcessCommand::WatchStatus => {
+                    progress!("Requesting status");
+                    if bridge.alive() {
+                        watch_status(&mut bridge).await?;
+                    } else {
+                        interruptln!();
+                        errorln!("gistit node is not running");
+                        std::process::exit(ErrorKind::Daemon.code());
+                    }
+                }
+
+                ProcessCommand::Inbox => {
+                    progress!("Requesting inbox");
+                    if bridge.alive() {
+                        bridge.connect().await?;
+                        // A page at a time, a full inbox doesn't fit an instruction
+                        let mut entries = Vec::new();
+                        let mut offset = Some(0);
+                        while let Some(page) = offset {
+                            bridge.send(Instruction::request_inbox(page)).await?;
+                            offset = match interrupt::bounded("gistit node", bridge.recv())
+                                .await?
+                                .expect_response()?
+                            {
+                                ipc::instruction::Kind::InboxResponse(response) => {
+                                    entries.extend(response.entries);
+                                    response.next
+                                }
+                                _ => None,
+                            };
+                        }
+                        format_inbox(&entries);
+                    } else {
+                        interruptln!();
+                        errorln!("gistit node is not running");
+                        std::process::exit(ErrorKind::Daemon.code());
+                    }
+                }
+
+                ProcessCommand::Trust(change) => {
+                    progress!("Requesting trusted peers");
+                    if bridge.alive() {
+                        let (peer_id, trust) = change
+                            .map_or((None, Trust::Allow), |(trust, id)| {
+                                (Some(id.to_owned()), trust)
+                            });
+                        bridge.connect().await?;
+                        bridge
+                            .send(Instruction::request_trust_peer(peer_id, trust))
+                            .await?;
+
+                        if let ipc::instruction::Kind::TrustPeerResponse(response) =
+                            interrupt::bounded("gistit node", bridge.recv())
+                                .await?
+                                .expect_response()?
+                        {
+                            if let Some(err) = response.error {
+                                interruptln!();
+                                errorln!(err);
+                                std::process::exit(ErrorKind::Daemon.code());
+                            }
+                            format_trusted_peers(&response);
+                        }
+                    } else {
+                        interruptln!();
+                        errorln!("gistit node is not running");
+                        std::process::exit(ErrorKind::Daemon.code());
+                    }
+                }
+
+                ProcessCommand::Peers(change) => {
+                    progress!("Requesting persistent peers");
+                    if bridge.alive() {
+                        let (address, change) = change
+                            .map_or((None, Change::Add), |(change, address)| {
+                                (Some(address.to_owned()), change)
+                            });
+                        if !supports(&mut bridge, ipc::capability::PERSISTENT_PEERS).await? {
+                            warnln!("the running gistit node can't keep persistent peers, update and restart it");
+                            continue;
+                        }
+                        bridge
+                            .send(Instruction::request_persistent_peer(address, change))
+                            .await?;
+
+                        if let ipc::instruction::Kind::PersistentPeerResponse(response) =
+                            interrupt::bounded("gistit node", bridge.recv())
+                                .await?
+                                .expect_response()?
                         {
-                            format_daemon_status(&response);
+                            if let Some(err) = response.error {
+                                return Err(Error::Service(err));
+                            }
+                            format_persistent_peers(&response)?;
+                        }
+                    } else {
+                        return Err(Error::Service("gistit node is not running".to_owned()));
+                    }
+                }
+
+                ProcessCommand::Audit(hash, peer_id) => {
+                    progress!("Requesting audit log");
+                    if bridge.alive() {
+                        if !supports(&mut bridge, ipc::capability::AUDIT).await? {
+                            warnln!("the running gistit node doesn't log what it serves, update and restart it");
+                            continue;
+                        }
+                        bridge
+                            .send(Instruction::request_audit(
+                                hash.clone(),
+                                peer_id.map(ToOwned::to_owned),
+                            ))
+                            .await?;
+
+                        if let ipc::instruction::Kind::AuditResponse(response) =
+                            interrupt::bounded("gistit node", bridge.recv())
+                                .await?
+                                .expect_response()?
+                        {
+                            if let Some(err) = response.error {
+                                return Err(Error::Service(err));
+                            }
+                            format_audit(&response);
+                        }
+                    } else {
+                        return Err(Error::Service("gistit node is not running".to_owned()));
+                    }
+                }
+
+                ProcessCommand::ReloadConfig => {
+                    progress!("Reloading config");
+                    if bridge.alive() {
+                        match config::reload_config(&mut bridge).await? {
+                            Some(ipc::instruction::ReloadConfigResponse { error: Some(err) }) => {
+                                interruptln!();
+                                errorln!(err);
+                                std::process::exit(ErrorKind::Daemon.code());
+                            }
+                            Some(_) => {
+                                updateln!("Reloaded config");
+                                finish!("");
+                            }
+                            None => warnln!(
+                                "the running gistit node can't reload its config, update and restart it"
+                            ),
                         }
                     } else {
                         interruptln!();
                         errorln!("gistit node is not running");
-                        std::process::exit(1);
+                        std::process::exit(ErrorKind::Daemon.code());
                     }
                 }
 
                 ProcessCommand::Dial(addr) => {
                     progress!("Dialing");
                     if bridge.alive() {
-                        bridge.connect_blocking()?;
+                        bridge.connect().await?;
                         bridge
                             .send(Instruction::request_dial((*addr).to_string()))
                             .await?;
@@ -245,7 +813,7 @@ impl Dispatch for Action {
                     } else {
                         interruptln!();
                         errorln!("gistit node is not running");
-                        std::process::exit(1);
+                        std::process::exit(ErrorKind::Daemon.code());
                     }
                 }
 
@@ -256,6 +824,7 @@ impl Dispatch for Action {
                             .commands
                             .iter()
                             .any(|cmd| *cmd == ProcessCommand::Start),
+                        &mut config.log_filter,
                     )?;
                 }
             };
@@ -265,31 +834,606 @@ impl Dispatch for Action {
     }
 }
 
-fn format_daemon_status(response: &ipc::instruction::StatusResponse) {
+fn format_inbox(entries: &[ipc::instruction::InboxEntry]) {
+    updateln!("Inbox");
+    if entries.is_empty() {
+        cleanln!(format!("\n    {}\n", style("no gistits received").dim()));
+        return;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    for entry in entries {
+        let gistit = entry.gistit.clone().unwrap_or_default();
+        let name = gistit
+            .inner
+            .first()
+            .map_or_else(String::new, |inner| inner.name.clone());
+
+        cleanln!(format!(
+            "\n    hash: '{}'\n    file: {}, by {}\n    from: {}, {}",
+            style(&gistit.hash).bold(),
+            name,
+            gistit.author,
+            style(&entry.peer_id).dim(),
+            ago(now.saturating_sub(entry.received_at))
+        ));
+    }
+    cleanln!(format!(
+        "\n    {}\n",
+        style("fetch them with `gistit fetch <hash>`").dim()
+    ));
+}
+
+fn format_trusted_peers(response: &ipc::instruction::TrustPeerResponse) {
+    updateln!("Trusted peers");
+    let list = |peers: &[String]| {
+        if peers.is_empty() {
+            format!("    {}", style("none").dim())
+        } else {
+            peers
+                .iter()
+                .map(|peer| format!("    {}", peer))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    };
+
+    cleanln!(format!(
+        "\n    {}\n{}\n\n    {}\n{}\n",
+        style("allowed").bold(),
+        list(&response.allowed),
+        style("denied").bold(),
+        list(&response.denied)
+    ));
+    if response.allowed.is_empty() {
+        cleanln!(format!(
+            "    {}\n",
+            style("allowlist is empty, every peer not denied is served").dim()
+        ));
+    }
+}
+
+fn format_persistent_peers(response: &ipc::instruction::PersistentPeerResponse) -> Result<()> {
+    if fmt::structured(&response.peers)? {
+        return Ok(());
+    }
+    updateln!("Persistent peers");
+    if response.peers.is_empty() {
+        cleanln!(format!(
+            "\n    {}\n",
+            style("none, add one with `gistit node --add-peer <multiaddr>`").dim()
+        ));
+        return Ok(());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    for peer in &response.peers {
+        let state = if peer.connected {
+            style("connected".to_owned()).green()
+        } else if let Some(last_connected) = peer.last_connected {
+            style(format!(
+                "last connected {}",
+                ago(now.saturating_sub(last_connected))
+            ))
+            .dim()
+        } else {
+            style("never connected".to_owned()).dim()
+        };
+        let failures = if peer.failures > 0 {
+            format!(", {} failed dials", peer.failures)
+        } else {
+            String::new()
+        };
+        cleanln!(format!("\n    {}\n    {}{}", peer.address, state, failures));
+    }
+    cleanln!("");
+    Ok(())
+}
+
+fn format_audit(response: &ipc::instruction::AuditResponse) {
+    updateln!("Audit log");
+    if response.entries.is_empty() {
+        cleanln!(format!("\n    {}\n", style("nothing served yet").dim()));
+        return;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    for entry in &response.entries {
+        cleanln!(format!(
+            "\n    hash: '{}'\n    to: {}, {}, {}",
+            style(&entry.hash).bold(),
+            style(&entry.peer_id).dim(),
+            bytes(entry.bytes),
+            ago(now.saturating_sub(entry.timestamp))
+        ));
+    }
+    let shown = response.entries.len() as u64;
+    if shown < response.total {
+        cleanln!(format!(
+            "\n    {}",
+            style(format!(
+                "showing the last {} of {}, the full log is audit.log in the data directory",
+                shown, response.total
+            ))
+            .dim()
+        ));
+    }
+    cleanln!("");
+}
+
+fn format_daemon_status(response: &ipc::instruction::StatusResponse) -> Result<()> {
+    if fmt::structured(response)? {
+        return Ok(());
+    }
     let ipc::instruction::StatusResponse {
         peer_id,
         peer_count,
         pending_connections,
         hosting,
+        bootstrap_peers,
+        rate_limited,
+        denied_connections,
+        banned_peers,
+        listeners,
+        relays,
+        reseeding,
+        bytes_received,
+        bytes_sent,
+        ipc_queue_peak,
+        ipc_stalls,
+        bootstrapped,
+        bootstrap_error,
     } = response;
 
+    let bootstrap = bootstrap_peers
+        .iter()
+        .map(|peer| format!("\n        {}", style(peer).dim()))
+        .collect::<String>();
+    let bootstrap_state = match (bootstrapped, bootstrap_error) {
+        (true, _) => style("done".to_owned()).green(),
+        (false, Some(err)) => style(format!("failed, {}", err)).red(),
+        (false, None) => style("in progress".to_owned()).yellow(),
+    };
+    let listening = listeners
+        .iter()
+        .map(|addr| format!("\n        {}", style(addr).dim()))
+        .collect::<String>();
+
     updateln!("Running status");
     finish!(format!(
         r#"
     peer id: '{}'
     hosting: {} gistit
+    reseeding: {} gistit
     peers: {}
     pending connections: {}
+    bootstrap: {}
+    bootstrap peers: {}{}
+    rate limited requests: {}
+    denied connections: {}
+    banned peers: {}
+    relays: {}
+    received: {}
+    sent: {}
+    ipc queue peak: {} ({} stalled)
+    listening on: {}{}
         "#,
         style(peer_id).bold(),
         hosting,
+        reseeding,
         style(peer_count).blue(),
         pending_connections,
+        bootstrap_state,
+        bootstrap_peers.len(),
+        bootstrap,
+        rate_limited,
+        denied_connections,
+        banned_peers,
+        relays,
+        bytes(*bytes_received),
+        bytes(*bytes_sent),
+        ipc_queue_peak,
+        ipc_stalls,
+        listeners.len(),
+        listening,
     ));
+    Ok(())
+}
+
+/// Bundles the node identity into `file`, along with the gistits the node hosts if it's running
+async fn export_identity(
+    bridge: &mut gistit_ipc::Bridge<gistit_ipc::Client>,
+    config: &Config,
+    file: &Path,
+    force: bool,
+) -> Result<()> {
+    if fs::metadata(file).is_ok() && !force {
+        return Err(Error::Argument(
+            "file exists, use '--force' to replace it",
+            "--export-identity",
+        ));
+    }
+
+    progress!("Exporting identity");
+    let hosted = if bridge.alive() {
+        bridge.connect().await?;
+        bridge.send(Instruction::hello()).await?;
+        if interrupt::bounded("gistit node", bridge.recv())
+            .await?
+            .negotiate()
+            .supports(ipc::capability::HOSTED)
+        {
+            // A page at a time, all of them don't fit an instruction
+            let mut hosted = Vec::new();
+            let mut offset = Some(0);
+            while let Some(page) = offset {
+                bridge.send(Instruction::request_hosted(page)).await?;
+                offset = match interrupt::bounded("gistit node", bridge.recv())
+                    .await?
+                    .expect_response()?
+                {
+                    ipc::instruction::Kind::HostedResponse(response) => {
+                        hosted.extend(response.gistits);
+                        response.next
+                    }
+                    _ => None,
+                };
+            }
+            hosted
+        } else {
+            warnln!("the running gistit node can't list what it hosts, update and restart it to export hosted gistits");
+            Vec::new()
+        }
+    } else {
+        warnln!("gistit node is not running, hosted gistits are left out");
+        Vec::new()
+    };
+
+    let bundle = identity::Bundle::new(&config.config_path, &path::data()?, hosted)?;
+    updateln!("Bundled identity");
+    let sealed = bundle.seal(&identity::passphrase(true)?)?;
+    keychain::write_private(file, &sealed)?;
+
+    finish!(format!(
+        "\n    peer id: '{}'\n    hosted: {} gistit\n    bundle: '{}'\n\n",
+        style(&bundle.peer_id).bold(),
+        bundle.hosted.len(),
+        file.display()
+    ));
+    Ok(())
+}
+
+/// Makes the identity bundled in `file` this node's. The running node keeps the old one, so it
+/// must be stopped first
+fn import_identity(
+    bridge: &gistit_ipc::Bridge<gistit_ipc::Client>,
+    config: &Config,
+    file: &Path,
+    force: bool,
+) -> Result<()> {
+    if bridge.alive() {
+        return Err(Error::Argument(
+            "stop the gistit node first, it runs with the current identity",
+            "--import-identity",
+        ));
+    }
+
+    progress!("Importing identity");
+    let sealed = fs::read(file)?;
+    let bundle = identity::Bundle::open(&sealed, &identity::passphrase(false)?)?;
+    bundle.restore(&config.config_path, &path::data()?, force)?;
+    updateln!("Imported identity");
+    if !bundle.hosted.is_empty() {
+        updateln!(
+            "{} hosted gistits are provided when the node starts",
+            bundle.hosted.len()
+        );
+    }
+
+    finish!(format!(
+        "\n    peer id: '{}'\n\n",
+        style(&bundle.peer_id).bold()
+    ));
+    Ok(())
+}
+
+/// Provides the hosted gistits of an imported identity, once the node started with it
+async fn provide_imported(bridge: &mut gistit_ipc::Bridge<gistit_ipc::Client>) -> Result<()> {
+    let data_path = path::data()?;
+    let gistits = identity::pending(&data_path)?;
+    if gistits.is_empty() {
+        return Ok(());
+    }
+
+    progress!("Providing imported gistits");
+    bridge.connect().await?;
+    let mut provided = 0;
+    for request in Instruction::request_provide_many_batches(gistits, gistit_ipc::READBUF_SIZE) {
+        bridge.send(request).await?;
+        if let ipc::instruction::Kind::ProvideManyResponse(response) =
+            interrupt::bounded("gistit node", bridge.recv())
+                .await?
+                .expect_response()?
+        {
+            provided += response
+                .results
+                .iter()
+                .filter(|result| result.provided)
+                .count();
+            for result in response.results.iter().filter(|result| !result.provided) {
+                warnln!(
+                    "failed to provide {}: {}",
+                    result.hash,
+                    result.error.as_deref().unwrap_or("unknown error")
+                );
+            }
+        }
+    }
+    updateln!("Provided {} imported gistits", provided);
+    identity::clear_pending(&data_path)
 }
 
-fn attach_to_log(runtime_path: &Path, linked: bool) -> Result<()> {
-    let log_path = runtime_path.join("gistit.log");
+/// Shows the cursor again when dropped, however watching ends
+struct HiddenCursor(Term);
+
+impl HiddenCursor {
+    fn hide(term: &Term) -> Result<Self> {
+        term.hide_cursor()?;
+        Ok(Self(term.clone()))
+    }
+}
+
+impl Drop for HiddenCursor {
+    fn drop(&mut self) {
+        let _ = self.0.show_cursor();
+    }
+}
+
+/// Terminal rows `text` takes up `width` columns wide, lines longer than that wrap
+fn rows(text: &str, width: usize) -> usize {
+    let width = width.max(1);
+    text.lines()
+        .map(|line| (console::measure_text_width(line) + width - 1) / width)
+        .map(|rows| rows.max(1))
+        .sum()
+}
+
+/// Asks for the status every [`WATCH_INTERVAL`] and redraws it over the previous one, with
+/// transfer rates since the last refresh. Returns once the node stops
+async fn watch_status(bridge: &mut gistit_ipc::Bridge<gistit_ipc::Client>) -> Result<()> {
+    // The spinner would be drawn over the dashboard
+    crate::progress::clear();
+    let term = Term::stdout();
+    let cursor = HiddenCursor::hide(&term)?;
+
+    let mut refresh = tokio::time::interval(WATCH_INTERVAL);
+    let mut previous: Option<(Instant, u64, u64)> = None;
+    let mut drawn = 0;
+    while bridge.alive() {
+        refresh.tick().await;
+        bridge.connect().await?;
+        bridge.send(Instruction::request_status()).await?;
+        let response = match interrupt::bounded("gistit node", bridge.recv())
+            .await?
+            .expect_response()?
+        {
+            ipc::instruction::Kind::StatusResponse(response) => response,
+            _ => continue,
+        };
+
+        let now = Instant::now();
+        let rates = previous.map_or_else(String::new, |(then, received, sent)| {
+            let secs = now.duration_since(then).as_secs_f64().max(f64::EPSILON);
+            #[allow(
+                clippy::cast_possible_truncation,
+                clippy::cast_sign_loss,
+                clippy::cast_precision_loss
+            )]
+            let rate = |delta: u64| bytes((delta as f64 / secs) as u64);
+            format!(
+                "    {} {}/s in, {}/s out\n",
+                style("rate:").dim(),
+                rate(response.bytes_received.saturating_sub(received)),
+                rate(response.bytes_sent.saturating_sub(sent))
+            )
+        });
+        previous = Some((now, response.bytes_received, response.bytes_sent));
+
+        let listening = response
+            .listeners
+            .iter()
+            .map(|addr| format!("\n        {}", style(addr).dim()))
+            .collect::<String>();
+        let bootstrap_failed = response
+            .bootstrap_error
+            .as_ref()
+            .map_or_else(String::new, |err| {
+                format!(
+                    "    {}\n",
+                    style(format!("bootstrap failed, {}", err)).red()
+                )
+            });
+        let dashboard = format!(
+            "\n    peer id: '{}'\n    peers: {} ({} pending)\n{}    hosting: {} gistit, reseeding {}\n    \
+             received: {}\n    sent: {}\n{}    listening on: {}{}\n\n    {}\n",
+            style(&response.peer_id).bold(),
+            style(response.peer_count).blue(),
+            response.pending_connections,
+            bootstrap_failed,
+            response.hosting,
+            response.reseeding,
+            bytes(response.bytes_received),
+            bytes(response.bytes_sent),
+            rates,
+            response.listeners.len(),
+            listening,
+            style("refreshing every second, ctrl-c to stop").italic().dim()
+        );
+
+        term.clear_last_lines(drawn)?;
+        term.write_str(&dashboard)?;
+        drawn = rows(&dashboard, usize::from(term.size().1));
+    }
+
+    drop(cursor);
+    warnln!("gistit node stopped");
+    Ok(())
+}
+
+/// A bootstrap manifest, either a plain array of multiaddrs or an object with a `peers` key
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BootstrapManifest {
+    List(Vec<String>),
+    Object { peers: Vec<String> },
+}
+
+/// Keeps the node running once we exit. Unix children already outlive their parent
+#[cfg(unix)]
+const fn detach(_: &mut Command) {}
+
+/// Keeps the node running once we exit, without a console window, and out of reach of the
+/// Ctrl-C of the console it was started from
+#[cfg(windows)]
+fn detach(daemon: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+    daemon.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
+
+/// Reads the history sync secret from `GISTIT_SYNC_SECRET` or asks for it. Without one, on the
+/// first machine, a new secret is created
+fn sync_secret() -> Result<String> {
+    let secret = match std::env::var(env::GISTIT_SYNC_SECRET) {
+        Ok(secret) => secret,
+        Err(_) => Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("Sync secret of your other machines, leave empty to create one")
+            .allow_empty_password(true)
+            .interact()?,
+    };
+    if secret.is_empty() {
+        let secret = vault::new_sync_secret();
+        warnln!(
+            "created a sync secret, start the node on your other machines with it: {}",
+            secret
+        );
+        return Ok(secret);
+    }
+
+    vault::check_sync_secret(&secret).map_err(|_| {
+        Error::Argument("not a sync secret, leave it empty to create one", "--sync")
+    })?;
+    Ok(secret)
+}
+
+/// Arguments the daemon is started with, be it by us or a service manager
+fn daemon_args(config: &Config) -> Vec<String> {
+    let mut args = vec![
+        "--runtime-path".to_owned(),
+        config.runtime_path.to_string_lossy().into_owned(),
+        "--config-path".to_owned(),
+        config.config_path.to_string_lossy().into_owned(),
+        "--bootstrap".to_owned(),
+    ];
+
+    // Both ipv4 and ipv6 unless told otherwise
+    if let Some(host) = config.host {
+        args.extend(["--host".to_owned(), host.to_owned()]);
+    }
+    if let Some(port) = config.port {
+        args.extend(["--port".to_owned(), port.to_owned()]);
+    }
+    if config.randomize_port {
+        args.push("--randomize-port".to_owned());
+    }
+    for addr in &config.listen {
+        args.extend(["--listen".to_owned(), (*addr).to_owned()]);
+    }
+    for peer in &config.bootstrap_peers {
+        args.extend(["--bootstrap-peer".to_owned(), peer.clone()]);
+    }
+
+    if config.reseed {
+        args.push("--reseed".to_owned());
+    }
+    if let Some(cap) = config.reseed_cap {
+        args.extend(["--reseed-cap".to_owned(), cap.to_string()]);
+    }
+    if let Some(parallelism) = config.query_parallelism {
+        args.extend(["--query-parallelism".to_owned(), parallelism.to_string()]);
+    }
+    if let Some(timeout) = config.query_timeout {
+        args.extend(["--query-timeout".to_owned(), timeout.as_secs().to_string()]);
+    }
+    if let Some(timeout) = config.request_timeout {
+        args.extend([
+            "--request-timeout".to_owned(),
+            timeout.as_secs().to_string(),
+        ]);
+    }
+    if let Some((dir, author)) = &config.provide_dir {
+        args.extend([
+            "--provide-dir".to_owned(),
+            dir.to_string_lossy().into_owned(),
+            "--provide-author".to_owned(),
+            author.clone(),
+        ]);
+    }
+    if config.no_pex {
+        args.push("--no-pex".to_owned());
+    }
+    if config.keep_history {
+        args.push("--keep-history".to_owned());
+    }
+    if config.sync_secret.is_some() {
+        args.push("--sync".to_owned());
+    }
+    if gistit_ipc::insecure_runtime_dir_allowed() {
+        args.push("--insecure-runtime-dir".to_owned());
+    }
+    // Logs as much as we do
+    if progress::verbose_enabled() {
+        args.push(format!("-{}", "v".repeat(progress::verbosity().into())));
+    }
+    args
+}
+
+/// Loads bootstrap peers multiaddrs from a local file or remote JSON manifest
+async fn load_bootstrap_list(location: &str) -> Result<Vec<String>> {
+    let content = if location.starts_with("http://") || location.starts_with("https://") {
+        let client = http::Client::new();
+        let response = client.send(client.get(location)).await?;
+
+        if !response.status().is_success() {
+            return Err(Error::Argument(
+                "failed to download bootstrap list",
+                "--bootstrap-list",
+            ));
+        }
+        response.text().await?
+    } else {
+        fs::read_to_string(location)?
+    };
+
+    let peers = match serde_json::from_str(&content)? {
+        BootstrapManifest::List(peers) | BootstrapManifest::Object { peers } => peers,
+    };
+
+    peers.into_iter().map(check::bootstrap_peer).collect()
+}
+
+fn attach_to_log(runtime_path: &Path, linked: bool, filter: &mut logs::Filter) -> Result<()> {
+    let log_path = runtime_path.join(LOG_FILE);
 
     if let Ok(log) = fs::File::open(&log_path) {
         let mut reader = BufReader::new(&log);
@@ -306,10 +1450,13 @@ fn attach_to_log(runtime_path: &Path, linked: bool) -> Result<()> {
 
         loop {
             let bytes = reader.read_line(&mut buf)?;
-            if bytes > 0 {
-                cleanln!(buf);
+            // Filters need whole lines, wait for the node to finish writing this one
+            if buf.ends_with('\n') {
+                if filter.matches(&buf) {
+                    cleanln!(buf);
+                }
                 buf = String::new();
-            } else {
+            } else if bytes == 0 {
                 sleep(Duration::from_millis(500));
             }
         }
@@ -320,3 +1467,19 @@ fn attach_to_log(runtime_path: &Path, linked: bool) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_rows_count_wrapped_lines() {
+        let dashboard = "\n    peers: 3\n    listening on: 1\n\n";
+        assert_eq!(rows(dashboard, 80), 4);
+        // Wraps onto a second row, styling takes no room
+        assert_eq!(rows("\n    peers: 3\n", 8), 3);
+        assert_eq!(rows(&format!("{}\n", style("12345678").bold()), 8), 1);
+        assert_eq!(rows("123456789\n", 4), 3);
+        assert_eq!(rows("x\n", 0), 1);
+    }
+}
@@ -1,4 +1,6 @@
+#[cfg(feature = "fetch")]
 use lazy_static::lazy_static;
+#[cfg(feature = "fetch")]
 use ngrammatic::{Corpus, CorpusBuilder, Pad};
 
 pub const SUPPORTED_COLORSCHEMES: [&str; 24] = [
@@ -28,6 +30,7 @@ pub const SUPPORTED_COLORSCHEMES: [&str; 24] = [
     "zenburn",
 ];
 
+#[cfg(feature = "fetch")]
 lazy_static! {
     static ref FUZZY_MATCH: Corpus = SUPPORTED_COLORSCHEMES.iter().fold(
         CorpusBuilder::new().arity(2).pad_full(Pad::Auto).finish(),
@@ -39,26 +42,56 @@ lazy_static! {
 }
 
 pub mod check {
+    #[cfg(feature = "fetch")]
     use super::{FUZZY_MATCH, SUPPORTED_COLORSCHEMES};
 
+    #[cfg(feature = "send")]
     use std::ffi::OsStr;
+    #[cfg(feature = "send")]
     use std::fs;
-    use std::net::Ipv4Addr;
+    use std::net::IpAddr;
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
     use std::ops::RangeInclusive;
+    use std::time::Duration;
 
+    #[cfg(feature = "p2p")]
+    use regex::Regex;
+    #[cfg(feature = "send")]
+    use url::Url;
+
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    use gistit_proto::validate;
+    use gistit_proto::GistitHash;
+
+    #[cfg(feature = "send")]
+    use crate::file::name_from_path;
+    #[cfg(any(feature = "send", feature = "fetch"))]
     use crate::file::EXTENSION_TO_LANG_MAPPING;
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    use crate::git;
     use crate::{Error, Result};
 
-    const ALLOWED_FILE_SIZE_RANGE: RangeInclusive<u64> = 20..=50_000;
+    #[cfg(feature = "fetch")]
+    const ALLOWED_PAGE_SIZE_RANGE: RangeInclusive<u32> = 1..=50;
+
+    #[cfg(feature = "fetch")]
+    const ALLOWED_SEARCH_QUERY_LENGTH_RANGE: RangeInclusive<usize> = 1..=100;
+
+    #[cfg(feature = "send")]
+    const ALLOWED_REPLICAS_RANGE: RangeInclusive<u32> = 1..=20;
 
-    const ALLOWED_DESCRIPTION_CHAR_LENGHT_RANGE: RangeInclusive<usize> = 10..=100;
+    #[cfg(feature = "p2p")]
+    const ALLOWED_QUERY_PARALLELISM_RANGE: RangeInclusive<u32> = 1..=20;
 
-    const ALLOWED_AUTHOR_CHAR_LENGTH_RANGE: RangeInclusive<usize> = 3..=30;
+    #[cfg(feature = "send")]
+    const SNIPPET_SOURCE_MAX_SIZE: u64 = 10_000_000;
 
-    const GISTIT_HASH_CHAR_LENGTH: usize = 64;
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    const ALLOWED_PEER_ID_CHAR_LENGTH_RANGE: RangeInclusive<usize> = 46..=52;
 
+    #[cfg(feature = "send")]
     pub fn description(description: &str) -> Result<&str> {
-        if ALLOWED_DESCRIPTION_CHAR_LENGHT_RANGE.contains(&description.len()) {
+        if validate::description(description).is_ok() {
             Ok(description)
         } else {
             Err(Error::Argument(
@@ -68,8 +101,9 @@ pub mod check {
         }
     }
 
-    pub fn author(author: &str) -> Result<&str> {
-        if ALLOWED_AUTHOR_CHAR_LENGTH_RANGE.contains(&author.len()) {
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    pub fn author_name(author: &str) -> Result<&str> {
+        if validate::author(author).is_ok() {
             Ok(author)
         } else {
             Err(Error::Argument(
@@ -79,8 +113,89 @@ pub mod check {
         }
     }
 
+    /// Where the author of a gistit came from
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum AuthorSource {
+        Flag,
+        Settings,
+        Git,
+        OsUser,
+        Random,
+    }
+
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    impl std::fmt::Display for AuthorSource {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(match self {
+                Self::Flag => "--author",
+                Self::Settings => "the author setting",
+                Self::Git => "git user.name",
+                Self::OsUser => "the OS user name",
+                Self::Random => "a random name",
+            })
+        }
+    }
+
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    fn os_user_name() -> Option<String> {
+        ["USER", "USERNAME", "LOGNAME"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok())
+    }
+
+    /// Resolves the author: `--author`, then the `author` setting, then git `user.name`, then
+    /// the OS user name. Given ones must be valid, guessed ones are skipped if they aren't. A
+    /// random name is the last resort
+    /// A way to guess the author, and where the guess comes from
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    pub(super) type AuthorGuess = (fn() -> Option<String>, AuthorSource);
+
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    pub fn author(flag: Option<&str>, setting: Option<&str>) -> Result<(String, AuthorSource)> {
+        resolve_author(
+            flag,
+            setting,
+            [
+                (git::user_name as fn() -> Option<String>, AuthorSource::Git),
+                (os_user_name, AuthorSource::OsUser),
+            ],
+        )
+    }
+
+    /// [`author`], guessing with `guessed` in order
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    pub(super) fn resolve_author(
+        flag: Option<&str>,
+        setting: Option<&str>,
+        guessed: [AuthorGuess; 2],
+    ) -> Result<(String, AuthorSource)> {
+        if let Some(author) = flag {
+            return Ok((author_name(author)?.to_owned(), AuthorSource::Flag));
+        }
+        if let Some(author) = setting {
+            return Ok((author_name(author)?.to_owned(), AuthorSource::Settings));
+        }
+
+        Ok(guessed
+            .into_iter()
+            .find_map(|(guess, source)| {
+                guess()
+                    .filter(|author| author_name(author).is_ok())
+                    .map(|author| (author, source))
+            })
+            .unwrap_or_else(|| {
+                let random = names::Generator::default()
+                    .next()
+                    .unwrap_or_else(|| "anonymous".to_owned());
+                (random, AuthorSource::Random)
+            }))
+    }
+
+    #[cfg(feature = "send")]
     pub fn metadata(attr: &fs::Metadata) -> Result<()> {
-        let size_allowed = ALLOWED_FILE_SIZE_RANGE.contains(&attr.len());
+        let size_allowed =
+            usize::try_from(attr.len()).map_or(false, |len| validate::size(len).is_ok());
 
         if size_allowed {
             Ok(())
@@ -89,6 +204,40 @@ pub mod check {
         }
     }
 
+    /// Files a snippet is cut from can be larger than a gistit, within reason
+    #[cfg(feature = "send")]
+    pub fn snippet_source(attr: &fs::Metadata) -> Result<()> {
+        if attr.len() <= SNIPPET_SOURCE_MAX_SIZE {
+            Ok(())
+        } else {
+            Err(Error::Argument(
+                "file is too large to cut a snippet from",
+                "[FILE]",
+            ))
+        }
+    }
+
+    #[cfg(feature = "send")]
+    pub fn remote_url(url: &str) -> Result<Url> {
+        Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or(Error::Argument("expected an http(s) url", "--from-url"))
+    }
+
+    #[cfg(feature = "send")]
+    pub fn remote_size(len: usize) -> Result<()> {
+        if validate::size(len).is_ok() {
+            Ok(())
+        } else {
+            Err(Error::Argument(
+                "remote file size not allowed",
+                "--from-url",
+            ))
+        }
+    }
+
+    #[cfg(feature = "send")]
     pub fn extension(ext: Option<&OsStr>) -> Result<()> {
         let ext = ext
             .and_then(OsStr::to_str)
@@ -101,6 +250,23 @@ pub mod check {
         }
     }
 
+    /// A language by name, e.g. `python`, or by one of its extensions, e.g. `py`
+    #[cfg(any(feature = "send", feature = "fetch"))]
+    pub fn lang(value: &str) -> Result<&'static str> {
+        let lowercase = value.to_lowercase();
+        EXTENSION_TO_LANG_MAPPING
+            .get(lowercase.as_str())
+            .copied()
+            .or_else(|| {
+                EXTENSION_TO_LANG_MAPPING
+                    .values()
+                    .find(|lang| **lang == lowercase)
+                    .copied()
+            })
+            .ok_or(Error::Argument("unknown language", "--lang"))
+    }
+
+    #[cfg(feature = "fetch")]
     pub fn colorscheme(colorscheme: &str) -> Result<&str> {
         if SUPPORTED_COLORSCHEMES.contains(&colorscheme) {
             Ok(colorscheme)
@@ -115,21 +281,345 @@ pub mod check {
         }
     }
 
-    pub const fn hash(hash: &str) -> Result<&str> {
-        if hash.len() == GISTIT_HASH_CHAR_LENGTH {
-            Ok(hash)
+    /// Name and contents of a markdown description, text within the file size limits
+    #[cfg(feature = "send")]
+    pub fn description_file(path: &OsStr) -> Result<(String, String)> {
+        let path = std::path::Path::new(path);
+        let data = fs::read(path).map_err(|_| {
+            Error::Argument("can't read the description file", "--description-file")
+        })?;
+        let data = String::from_utf8(data).map_err(|_| {
+            Error::Argument("the description file must be text", "--description-file")
+        })?;
+        if validate::size(data.len()).is_err() {
+            return Err(Error::Argument(
+                "description file size not allowed",
+                "--description-file",
+            ));
+        }
+        Ok((name_from_path(path), data))
+    }
+
+    /// A full hash, without its namespace if it has one
+    pub fn hash(hash: &str) -> Result<GistitHash> {
+        GistitHash::parse(hash)
+            .map_err(|_| Error::Argument("invalid gistit hash format.", "--hash"))
+    }
+
+    /// A GitHub gist id, or the id out of a gist url like `https://gist.github.com/<user>/<id>`
+    #[cfg(feature = "send")]
+    pub fn gist_id<'a>(gist: &'a str, arg: &'static str) -> Result<&'a str> {
+        gist.trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or(Error::Argument(
+                "expected a github gist url or a gist id",
+                arg,
+            ))
+    }
+
+    /// Parses `start:end`, `start:` or `:end` into an inclusive, 1-based line range
+    #[cfg(any(feature = "send", feature = "fetch"))]
+    pub fn line_range(range: &str) -> Result<(usize, usize)> {
+        let parsed = range.split_once(':').and_then(|(start, end)| {
+            let start = if start.is_empty() {
+                Ok(1)
+            } else {
+                start.parse()
+            };
+            let end = if end.is_empty() {
+                Ok(usize::MAX)
+            } else {
+                end.parse()
+            };
+            start.ok().zip(end.ok())
+        });
+
+        match parsed {
+            Some((start, end)) if start >= 1 && start <= end => Ok((start, end)),
+            _ => Err(Error::Argument(
+                "invalid line range, expected `start:end`, `start:` or `:end`",
+                "--lines",
+            )),
+        }
+    }
+
+    /// A 1-based line to annotate
+    #[cfg(feature = "fetch")]
+    pub fn annotation_line(line: &str) -> Result<u32> {
+        line.parse()
+            .ok()
+            .filter(|line| *line >= 1)
+            .ok_or(Error::Argument("invalid line, lines start at 1", "--line"))
+    }
+
+    #[cfg(feature = "fetch")]
+    pub fn annotation_message(message: &str) -> Result<&str> {
+        if validate::ANNOTATION_LENGTH.contains(&message.len()) {
+            Ok(message)
         } else {
-            Err(Error::Argument("invalid gistit hash format.", "--hash"))
+            Err(Error::Argument(
+                "invalid annotation character length.",
+                "--message",
+            ))
         }
     }
 
-    pub fn host_port<'a, 'b>(host: &'a str, port: &'b str) -> Result<(&'a str, &'b str)> {
-        let _host: Ipv4Addr = host
+    #[cfg(feature = "fetch")]
+    pub fn search_query(query: &str) -> Result<&str> {
+        let query = query.trim();
+        if ALLOWED_SEARCH_QUERY_LENGTH_RANGE.contains(&query.len()) {
+            Ok(query)
+        } else {
+            Err(Error::Argument(
+                "invalid search query character length.",
+                "[QUERY]",
+            ))
+        }
+    }
+
+    #[cfg(feature = "fetch")]
+    pub fn page_size(page_size: &str) -> Result<u32> {
+        page_size
+            .parse()
+            .ok()
+            .filter(|size| ALLOWED_PAGE_SIZE_RANGE.contains(size))
+            .ok_or(Error::Argument("invalid page size", "--page-size"))
+    }
+
+    #[cfg(feature = "send")]
+    pub fn replicas(replicas: &str) -> Result<u32> {
+        replicas
             .parse()
-            .map_err(|_| Error::Argument("invalid host", "--host"))?;
-        let _port: u16 = port
+            .ok()
+            .filter(|replicas| ALLOWED_REPLICAS_RANGE.contains(replicas))
+            .ok_or(Error::Argument(
+                "peer count must be between 1 and 20",
+                "--ttl-peers",
+            ))
+    }
+
+    #[cfg(feature = "p2p")]
+    pub fn query_parallelism(parallelism: &str) -> Result<u32> {
+        parallelism
             .parse()
-            .map_err(|_| Error::Argument("invalid port", "--port"))?;
-        Ok((host, port))
+            .ok()
+            .filter(|parallelism| ALLOWED_QUERY_PARALLELISM_RANGE.contains(parallelism))
+            .ok_or(Error::Argument(
+                "query parallelism must be between 1 and 20",
+                "--query-parallelism",
+            ))
+    }
+
+    pub fn timeout(secs: &str) -> Result<Duration> {
+        secs.parse()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .ok_or(Error::Argument(
+                "timeout must be a positive number of seconds",
+                "--timeout",
+            ))
+    }
+
+    /// A number of seconds, minutes, hours or days, e.g. `10m`. Seconds without a unit
+    #[cfg(feature = "p2p")]
+    pub fn since(since: &str) -> Result<Duration> {
+        let (count, unit) = since
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or((since, "s"), |at| since.split_at(at));
+        let secs = match unit {
+            "s" => Some(1),
+            "m" => Some(60),
+            "h" => Some(3_600),
+            "d" => Some(86_400),
+            _ => None,
+        };
+        count
+            .parse::<u64>()
+            .ok()
+            .zip(secs)
+            .and_then(|(count, secs)| count.checked_mul(secs))
+            .map(Duration::from_secs)
+            .ok_or(Error::Argument(
+                "expected a duration like '30s', '10m', '2h' or '1d'",
+                "--since",
+            ))
+    }
+
+    #[cfg(feature = "p2p")]
+    pub fn grep(pattern: &str) -> Result<Regex> {
+        Regex::new(pattern).map_err(|_| Error::Argument("invalid regular expression", "--grep"))
+    }
+
+    /// A full hash or a unique prefix of one, at least 4 characters long
+    #[cfg(feature = "fetch")]
+    pub fn hash_prefix(prefix: &str) -> Result<&str> {
+        if validate::hash_prefix(prefix).is_ok() {
+            Ok(prefix)
+        } else {
+            Err(Error::Argument(
+                "invalid gistit hash, expected at least 4 hexadecimal characters of one",
+                "--hash",
+            ))
+        }
+    }
+
+    #[cfg(feature = "p2p")]
+    pub fn bootstrap_peer(addr: String) -> Result<String> {
+        if addr.starts_with('/') && addr.contains("/p2p/") {
+            Ok(addr)
+        } else {
+            Err(Error::Argument(
+                "bootstrap peers must be multiaddrs ending with `/p2p/<peer id>`",
+                "--bootstrap-list",
+            ))
+        }
+    }
+
+    /// Base58 encoded, as printed by `gistit node --status`
+    #[cfg(any(feature = "send", feature = "fetch", feature = "p2p"))]
+    pub fn peer_id<'a>(peer_id: &'a str, arg: &'static str) -> Result<&'a str> {
+        const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+        if ALLOWED_PEER_ID_CHAR_LENGTH_RANGE.contains(&peer_id.len())
+            && peer_id.chars().all(|c| BASE58.contains(c))
+        {
+            Ok(peer_id)
+        } else {
+            Err(Error::Argument("invalid peer id", arg))
+        }
+    }
+
+    /// An ipv4 or ipv6 address, `::` listens on every ipv6 interface
+    pub fn host(host: &str) -> Result<&str> {
+        let _host: IpAddr = host.parse().map_err(|_| {
+            Error::Argument("invalid host, expected an ipv4 or ipv6 address", "--host")
+        })?;
+        Ok(host)
+    }
+
+    pub fn port(port: &str) -> Result<&str> {
+        port_number(port)?;
+        Ok(port)
+    }
+
+    pub fn port_number(port: &str) -> Result<u16> {
+        port.parse()
+            .map_err(|_| Error::Argument("invalid port", "--port"))
+    }
+
+    pub fn host_port<'a, 'b>(host: &'a str, port: &'b str) -> Result<(&'a str, &'b str)> {
+        Ok((self::host(host)?, self::port(port)?))
+    }
+
+    /// A multiaddr the node keeps dialing, it must name the peer, e.g.
+    /// `/ip4/1.2.3.4/tcp/4001/p2p/<peer id>`
+    #[cfg(feature = "p2p")]
+    pub fn peer_addr(addr: &str) -> Result<&str> {
+        match addr.rsplit_once("/p2p/") {
+            Some((transport, peer_id)) if transport.starts_with('/') => {
+                self::peer_id(peer_id, "--add-peer")?;
+                Ok(addr)
+            }
+            _ => Err(Error::Argument(
+                "persistent peer addresses must end with /p2p/<peer id>",
+                "--add-peer",
+            )),
+        }
+    }
+
+    /// A multiaddr to listen on, e.g. `/ip6/::/tcp/4001`
+    #[cfg(feature = "p2p")]
+    pub fn listen_addr(addr: &str) -> Result<&str> {
+        if addr.starts_with("/ip4/") || addr.starts_with("/ip6/") || addr.contains("/p2p-circuit") {
+            Ok(addr)
+        } else {
+            Err(Error::Argument(
+                "listen addresses must be ip4, ip6 or circuit multiaddrs",
+                "--listen",
+            ))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "send"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn param_gist_id() {
+        let id = "aa5a315d61ae9438b18d";
+        assert_eq!(check::gist_id(id, "--github-update").unwrap(), id);
+        assert_eq!(
+            check::gist_id(
+                "https://gist.github.com/fabricio7p/aa5a315d61ae9438b18d/",
+                "--github-update"
+            )
+            .unwrap(),
+            id
+        );
+
+        for invalid in [
+            "",
+            "https://gist.github.com/",
+            "not-a-gist",
+            "../etc/passwd",
+        ] {
+            assert!(check::gist_id(invalid, "--github-update").is_err());
+        }
+    }
+    #[test]
+    fn param_author() {
+        use check::{resolve_author, AuthorSource};
+
+        fn git() -> Option<String> {
+            Some("Fabricio".to_owned())
+        }
+        fn too_short() -> Option<String> {
+            Some("fa".to_owned())
+        }
+        fn nobody() -> Option<String> {
+            None
+        }
+        let guessed = [
+            (git as fn() -> Option<String>, AuthorSource::Git),
+            (nobody, AuthorSource::OsUser),
+        ];
+
+        assert_eq!(
+            resolve_author(Some("flagged"), Some("setting"), guessed).unwrap(),
+            ("flagged".to_owned(), AuthorSource::Flag)
+        );
+        assert_eq!(
+            resolve_author(None, Some("setting"), guessed).unwrap(),
+            ("setting".to_owned(), AuthorSource::Settings)
+        );
+        // Given ones must be valid
+        assert!(resolve_author(Some("fa"), None, guessed).is_err());
+        assert!(resolve_author(None, Some("fa"), guessed).is_err());
+        assert_eq!(
+            resolve_author(None, None, guessed).unwrap(),
+            ("Fabricio".to_owned(), AuthorSource::Git)
+        );
+
+        // Invalid guesses are skipped
+        let guessed = [
+            (too_short as fn() -> Option<String>, AuthorSource::Git),
+            (git, AuthorSource::OsUser),
+        ];
+        assert_eq!(
+            resolve_author(None, None, guessed).unwrap().1,
+            AuthorSource::OsUser
+        );
+        let guessed = [
+            (nobody as fn() -> Option<String>, AuthorSource::Git),
+            (too_short, AuthorSource::OsUser),
+        ];
+        assert_eq!(
+            resolve_author(None, None, guessed).unwrap().1,
+            AuthorSource::Random
+        );
     }
 }
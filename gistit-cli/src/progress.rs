@@ -0,0 +1,360 @@
+//! The progress module
+//!
+//! Everything gistit reports while working goes through here, the output macros are thin
+//! wrappers around it. A spinner shows the current step, and jobs that may run side by side get a
+//! [`Task`] spinner of their own. `--quiet` keeps only warnings, errors and results, while
+//! `--progress-json` turns every report into a JSON line on stderr for tools wrapping gistit.
+//! `-v` adds details such as the URLs hit, transfer sizes and timings, along with the logs of our
+//! libraries, e.g. the messages exchanged with the gistit node. `-vv` and `-vvv` log more, see
+//! [`verbosity::filter`].
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use console::style;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use gistit_project::verbosity;
+
+use crate::fmt::{colors_enabled, emoji};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+    Human,
+    Quiet,
+    Json,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Human as u8);
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+lazy_static! {
+    static ref MULTI: MultiProgress = MultiProgress::new();
+    static ref MAIN: ProgressBar = MULTI.add(spinner());
+    /// Last step set with `progress!`, reported again if we get interrupted
+    static ref STATUS: Mutex<String> = Mutex::new(String::new());
+    /// Warnings kept instead of printed, see [`capture_warnings`]
+    static ref CAPTURED: Mutex<Option<Vec<String>>> = Mutex::new(None);
+}
+
+/// Selects how progress is reported, must be called before anything is printed
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+    if mode != Mode::Human {
+        MULTI.set_draw_target(ProgressDrawTarget::hidden());
+    }
+}
+
+/// Reports details from now on, more the higher `verbosity` is (`-v` given that many times).
+/// Log records of our libraries are reported as details too
+pub fn set_verbosity(verbosity: u8) {
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+    if verbosity > 0 && log::set_logger(&Logger).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+#[must_use]
+pub fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+#[must_use]
+pub fn verbose_enabled() -> bool {
+    verbosity() > 0
+}
+
+/// Reports log records as details, filtered like the node's log at the same verbosity
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        verbosity::level(verbosity::filter(verbosity()), metadata.target())
+            .parse::<log::LevelFilter>()
+            .map_or(false, |level| metadata.level() <= level)
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            verbose(format!(
+                "{:<5} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[must_use]
+pub fn mode() -> Mode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => Mode::Quiet,
+        2 => Mode::Json,
+        _ => Mode::Human,
+    }
+}
+
+fn spinner() -> ProgressBar {
+    let pb = ProgressBar::new_spinner();
+    let ticks: &[&str] = if colors_enabled() {
+        &["◜", "◠", "◝", "◞", "◡", "◟", "✔️"]
+    } else {
+        &["-", "\\", "|", "/", ">"]
+    };
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .tick_strings(ticks)
+            .template("{spinner:.blue}  {msg}")
+            .expect("spinner template to be valid"),
+    );
+    pb.enable_steady_tick(TICK_INTERVAL);
+    pb
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task: Option<&'a str>,
+    message: String,
+}
+
+fn emit(event: &str, task: Option<&str>, message: impl Display) {
+    if let Some(line) = json_line(event, task, message) {
+        eprintln!("{}", line);
+    }
+}
+
+/// An event as reported with `--progress-json`, colors and surrounding whitespace left out
+fn json_line(event: &str, task: Option<&str>, message: impl Display) -> Option<String> {
+    let message = console::strip_ansi_codes(&message.to_string())
+        .trim()
+        .to_owned();
+    serde_json::to_string(&Event {
+        event,
+        task,
+        message,
+    })
+    .ok()
+}
+
+fn println(line: String) {
+    MULTI.println(line).ok();
+}
+
+/// Sets the current step
+pub fn status(msg: impl Display) {
+    let msg = msg.to_string();
+    match mode() {
+        Mode::Human => MAIN.set_message(msg.clone()),
+        Mode::Json => emit("status", None, &msg),
+        Mode::Quiet => (),
+    }
+    *STATUS.lock().expect("status lock to not be poisoned") = msg;
+}
+
+/// Reports a finished step
+pub fn update(msg: impl Display) {
+    match mode() {
+        Mode::Human => println(format!("{} {}", style(emoji("✔️ ", "> ")).green(), msg)),
+        Mode::Json => emit("update", None, msg),
+        Mode::Quiet => (),
+    }
+}
+
+/// Reports a detail, only with `-v`
+pub fn verbose(msg: impl Display) {
+    if !verbose_enabled() {
+        return;
+    }
+    match mode() {
+        Mode::Human => println(style(msg).dim().to_string()),
+        Mode::Json => emit("verbose", None, msg),
+        Mode::Quiet => (),
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Keeps warnings from now on instead of printing them, for screens the terminal is drawn by
+/// someone else. They are taken with [`captured_warnings`]
+pub fn capture_warnings() {
+    if let Ok(mut captured) = CAPTURED.lock() {
+        captured.get_or_insert_with(Vec::new);
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Warnings kept since the last call, oldest first
+#[must_use]
+pub fn captured_warnings() -> Vec<String> {
+    CAPTURED
+        .lock()
+        .ok()
+        .and_then(|mut captured| captured.as_mut().map(std::mem::take))
+        .unwrap_or_default()
+}
+
+pub fn warn(msg: impl Display) {
+    if let Ok(mut captured) = CAPTURED.lock() {
+        if let Some(captured) = captured.as_mut() {
+            captured.push(msg.to_string());
+            return;
+        }
+    }
+    match mode() {
+        Mode::Human => println(format!("{}: {}", style("warning").yellow().bold(), msg)),
+        Mode::Json => emit("warning", None, msg),
+        Mode::Quiet => eprintln!("{}: {}", style("warning").yellow().bold(), msg),
+    }
+}
+
+pub fn error(msg: impl Display) {
+    match mode() {
+        Mode::Json => emit("error", None, msg),
+        Mode::Human | Mode::Quiet => eprintln!("{}: {}", style("error").red().bold(), msg),
+    }
+}
+
+/// Prints a line above the spinners
+pub fn line(msg: impl Display) {
+    match mode() {
+        Mode::Human => println(msg.to_string()),
+        Mode::Json => emit("line", None, msg),
+        Mode::Quiet => (),
+    }
+}
+
+/// Prints the outcome of the command and stops the spinners
+pub fn finish(msg: impl Display) {
+    match mode() {
+        Mode::Human => {
+            println(msg.to_string());
+            clear();
+        }
+        Mode::Json => emit("finish", None, msg),
+        Mode::Quiet => {
+            let msg = msg.to_string();
+            if !msg.trim().is_empty() {
+                println!("{}", msg.trim());
+            }
+        }
+    }
+}
+
+/// Reports the current step as failed and stops the spinners
+pub fn interrupt() {
+    let status = STATUS
+        .lock()
+        .expect("status lock to not be poisoned")
+        .clone();
+    match mode() {
+        Mode::Human => {
+            println(format!("{} {}", style(emoji("❌", "x ")).red(), status));
+            clear();
+        }
+        Mode::Json => emit("interrupted", None, status),
+        Mode::Quiet => (),
+    }
+}
+
+/// Stops the spinners without printing anything, before handing the terminal to something else
+pub fn clear() {
+    MAIN.finish_and_clear();
+    MULTI.clear().ok();
+}
+
+/// A job with its own spinner, so several can report at once. The spinner goes away when the
+/// task is dropped
+#[cfg(any(feature = "send", feature = "fetch"))]
+#[derive(Debug)]
+pub struct Task {
+    name: String,
+    bar: Option<ProgressBar>,
+}
+
+/// Starts a task named `name`, shown next to its messages
+#[cfg(any(feature = "send", feature = "fetch"))]
+pub fn task(name: impl Into<String>, msg: impl Display) -> Task {
+    let name = name.into();
+    let bar = (mode() == Mode::Human).then(|| MULTI.add(spinner()));
+    let task = Task { name, bar };
+    task.status(msg);
+    task
+}
+
+#[cfg(any(feature = "send", feature = "fetch"))]
+impl Task {
+    pub fn status(&self, msg: impl Display) {
+        match (&self.bar, mode()) {
+            (Some(bar), _) => bar.set_message(format!("{} {}", style(&self.name).dim(), msg)),
+            (None, Mode::Json) => emit("status", Some(&self.name), msg),
+            (None, _) => (),
+        }
+    }
+
+    #[cfg(feature = "fetch")]
+    pub fn warn(&self, msg: impl Display) {
+        match mode() {
+            Mode::Json => emit("warning", Some(&self.name), msg),
+            _ => warn(format!("{} {}", self.name, msg)),
+        }
+    }
+
+    /// Reports the task as finished
+    pub fn done(self, msg: impl Display) {
+        match mode() {
+            Mode::Human => println(format!(
+                "{} {} {}",
+                style(emoji("✔️ ", "> ")).green(),
+                msg,
+                style(&self.name).dim()
+            )),
+            Mode::Json => emit("done", Some(&self.name), msg),
+            Mode::Quiet => (),
+        }
+    }
+}
+
+#[cfg(any(feature = "send", feature = "fetch"))]
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+            MULTI.remove(&bar);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_json_lines() {
+        let line = json_line(
+            "update",
+            None,
+            style("  Fetched\n").green().force_styling(true),
+        );
+        assert_eq!(
+            line.as_deref(),
+            Some(r#"{"event":"update","message":"Fetched"}"#)
+        );
+
+        let line = json_line("done", Some("3fa9c2"), "Fetched from peers");
+        assert_eq!(
+            line.as_deref(),
+            Some(r#"{"event":"done","task":"3fa9c2","message":"Fetched from peers"}"#)
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! The resolve module
+//!
+//! Gistits can be referred to by a unique prefix of their hash, like git short SHAs. Prefixes are
+//! looked up where `fetch` looks for gistits and in the same order: the local cache and history,
+//! the gistit node if it's running, then the server. The first place knowing any match settles
+//! it, several matches there are an error listing them. Namespaced hashes, see
+//! [`gistit_proto::hash`], are only looked for where they're hosted.
+//!
+//! Names like `snippet@example.com` stand for the hash their owner publishes in DNS, see
+//! [`gistit_project::dns`].
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use gistit_project::dns;
+use gistit_proto::hash::{self, Namespace};
+use gistit_proto::ipc::{self, capability, Instruction};
+use gistit_proto::GistitHash;
+
+use crate::cache;
+use crate::history;
+use crate::http;
+use crate::param::check;
+use crate::server::SERVER_URL_RESOLVE;
+use crate::{verboseln, Error, Result};
+
+/// The node answers from memory, taking longer means it's stuck
+const NODE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+struct Query<'a> {
+    prefix: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resolved {
+    hashes: Vec<String>,
+}
+
+/// The full hash `prefix` stands for and where it's hosted, if its namespace or `namespace`
+/// tell. Full hashes are returned as is, nothing is looked up. `prefix` can also be a name
+/// published in DNS
+///
+/// # Errors
+///
+/// Fails if `prefix` isn't a valid prefix, matches no gistit or matches several, or its namespace
+/// isn't `namespace`. Names fail if their lookup does or they don't point to exactly one gistit
+pub async fn hash(
+    prefix: &str,
+    runtime_path: &Path,
+    namespace: Option<Namespace>,
+) -> Result<(GistitHash, Option<Namespace>)> {
+    let published;
+    let prefix = match dns::Name::parse(prefix) {
+        Some(name) => {
+            published = from_dns(&name).await?;
+            published.as_str()
+        }
+        None => prefix,
+    };
+    let (tagged, prefix) = hash::split(prefix);
+    let namespace =
+        match (tagged, namespace) {
+            (Some(tagged), Some(namespace)) if tagged != namespace => return Err(Error::Argument(
+                "the hash namespace says it's hosted elsewhere, drop `--p2p-only` or the prefix",
+                "[HASH]",
+            )),
+            (tagged, namespace) => tagged.or(namespace),
+        };
+    let prefix = check::hash_prefix(prefix)?.to_ascii_lowercase();
+    if let Ok(hash) = GistitHash::parse(&prefix) {
+        return Ok((hash, namespace));
+    }
+
+    let mut hashes = local(&prefix);
+    if hashes.is_empty() && namespace != Some(Namespace::Server) {
+        hashes = from_node(&prefix, runtime_path).await?;
+    }
+    if hashes.is_empty() && namespace != Some(Namespace::Peer) {
+        hashes = from_server(&prefix).await?;
+    }
+    Ok((pick(prefix, hashes)?, namespace))
+}
+
+fn pick(prefix: String, hashes: BTreeSet<GistitHash>) -> Result<GistitHash> {
+    match hashes.len() {
+        0 => Err(Error::NotFound("no gistit hash starts with this prefix")),
+        1 => Ok(hashes.into_iter().next().expect("to have one hash")),
+        _ => Err(Error::Ambiguous(
+            prefix,
+            hashes.into_iter().map(String::from).collect(),
+        )),
+    }
+}
+
+/// Full hashes among `hashes` starting with `prefix`, whoever answered is not trusted to filter
+fn matching(prefix: &str, hashes: impl IntoIterator<Item = String>) -> BTreeSet<GistitHash> {
+    hashes
+        .into_iter()
+        .filter_map(|hash| GistitHash::try_from(hash).ok())
+        .filter(|hash| hash.starts_with(prefix))
+        .collect()
+}
+
+/// Cached gistits and the ones we sent or fetched before
+fn local(prefix: &str) -> BTreeSet<GistitHash> {
+    let cached = cache::hashes().unwrap_or_default();
+    let recorded = history::load()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|entry| entry.hash);
+    matching(prefix, cached.into_iter().chain(recorded))
+}
+
+/// Gistits the node provides or received. Nodes predating prefix resolution are skipped
+async fn from_node(prefix: &str, runtime_path: &Path) -> Result<BTreeSet<GistitHash>> {
+    let mut bridge = gistit_ipc::client(runtime_path)?;
+    if !bridge.alive() {
+        return Ok(BTreeSet::new());
+    }
+    bridge.connect().await?;
+
+    let ask = async {
+        bridge.send(Instruction::hello()).await?;
+        if !bridge
+            .recv()
+            .await?
+            .negotiate()
+            .supports(capability::RESOLVE)
+        {
+            return Ok(Vec::new());
+        }
+        bridge
+            .send(Instruction::request_resolve(prefix.to_owned()))
+            .await?;
+        match bridge.recv().await?.expect_response()? {
+            ipc::instruction::Kind::ResolveResponse(ipc::instruction::ResolveResponse {
+                hashes,
+            }) => Ok::<_, Error>(hashes),
+            _ => Ok(Vec::new()),
+        }
+    };
+    match tokio::time::timeout(NODE_TIMEOUT, ask).await {
+        Ok(hashes) => Ok(matching(prefix, hashes?)),
+        Err(_) => Ok(BTreeSet::new()),
+    }
+}
+
+/// The hash published under `name`
+async fn from_dns(name: &dns::Name) -> Result<String> {
+    let mut hashes = dns::lookup(name).await?.hashes;
+    match hashes.len() {
+        0 => Err(Error::NotFound("no gistit is published under this name")),
+        1 => {
+            let hash = hashes.remove(0);
+            verboseln!("{} resolved to {}", name, hash);
+            Ok(hash)
+        }
+        _ => Err(Error::AmbiguousName(name.to_string(), hashes)),
+    }
+}
+
+async fn from_server(prefix: &str) -> Result<BTreeSet<GistitHash>> {
+    let client = http::Client::new();
+    let response = client
+        .send(
+            client
+                .post(SERVER_URL_RESOLVE.to_string())
+                .json(&Query { prefix }),
+        )
+        .await?;
+
+    match response.status() {
+        StatusCode::OK => Ok(matching(prefix, response.json::<Resolved>().await?.hashes)),
+        _ => Err(Error::Server("unexpected response")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_prefix_matches() {
+        let first = format!("3fa9c2{}", "0".repeat(58));
+        let second = format!("3fa9d0{}", "0".repeat(58));
+        let hashes = vec![
+            first.clone(),
+            second.clone(),
+            first.clone(),
+            // Not full hashes
+            "3fa9c2".to_owned(),
+            "history.json".to_owned(),
+        ];
+
+        let matched = matching("3fa9c", hashes.clone());
+        assert_eq!(pick("3fa9c".to_owned(), matched).unwrap(), first);
+
+        let matched = matching("3fa9", hashes.clone());
+        assert!(matches!(
+            pick("3fa9".to_owned(), matched),
+            Err(Error::Ambiguous(_, candidates)) if candidates == vec![first, second]
+        ));
+
+        let matched = matching("ffff", hashes);
+        assert!(matches!(
+            pick("ffff".to_owned(), matched),
+            Err(Error::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,410 @@
+//! The search module
+//!
+//! `gistit search <query>` asks the server for public gistits matching the query by description,
+//! author, file name or language, best match first. Results show the start of the file and can
+//! be previewed or saved right from the list. Pages of results are cached in the data directory
+//! for a few minutes, so paging back and forth doesn't hit the server again. Results are written
+//! by whoever sent the gistits, their control characters are printed as spaces.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::{style, Term};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Select;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use gistit_project::path;
+use gistit_project::settings::Settings;
+use gistit_proto::payload::inner_hash;
+use gistit_proto::GistitHash;
+
+use crate::dispatch::Dispatch;
+use crate::fetch::{fetch_from_server, preview, save_to};
+use crate::file::Overwrite;
+use crate::fmt::{printable, structured};
+use crate::http;
+use crate::param::check;
+use crate::server::SERVER_URL_SEARCH;
+use crate::{finish, progress, updateln, warnln, Error, Result};
+
+const DEFAULT_PAGE_SIZE: &str = "20";
+
+const SEARCH_CACHE_DIR: &str = "search";
+
+/// How long a cached page of results is used
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Preview characters shown next to each result in the interactive list
+const INLINE_PREVIEW_WIDTH: usize = 60;
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub query: &'static str,
+    pub author: Option<&'static str>,
+    pub lang: Option<&'static str>,
+    pub colorscheme: &'static str,
+    pub page_size: &'static str,
+    pub no_cache: bool,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+        settings: &'static Settings,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            query: args
+                .value_of("QUERY")
+                .ok_or(Error::Argument("missing argument", "[QUERY]"))?,
+            author: args.value_of("author"),
+            lang: args.value_of("lang"),
+            colorscheme: args
+                .value_of("colorscheme")
+                .or(settings.colorscheme.as_deref())
+                .unwrap_or("Monokai Extended Origin"),
+            page_size: args.value_of("page-size").unwrap_or(DEFAULT_PAGE_SIZE),
+            no_cache: args.is_present("no-cache"),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    query: &'static str,
+    author: Option<&'static str>,
+    lang: Option<&'static str>,
+    colorscheme: &'static str,
+    page_size: u32,
+    no_cache: bool,
+}
+
+/// A public gistit matching the query, without the file contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Found {
+    pub hash: String,
+    pub author: String,
+    pub description: Option<String>,
+    pub timestamp: String,
+    pub name: String,
+    pub lang: String,
+    pub size: u32,
+    /// Higher is a better match
+    pub score: u32,
+    /// The first lines of the file, empty for binary files
+    #[serde(default)]
+    pub preview: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page {
+    pub results: Vec<Found>,
+    /// Cursor of the following page, `None` on the last one
+    pub next: Option<String>,
+    /// Results across every page
+    #[serde(default)]
+    pub total: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchQuery<'a> {
+    query: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<&'a str>,
+    limit: u32,
+    cursor: Option<&'a str>,
+}
+
+impl std::fmt::Display for Found {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inline: String = self
+            .preview
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .chars()
+            .take(INLINE_PREVIEW_WIDTH)
+            .collect();
+        write!(
+            f,
+            "{} {} | {} | {} {}",
+            style(printable(self.hash.get(..8).unwrap_or(&self.hash))).dim(),
+            style(printable(&self.name)).green(),
+            style(printable(&self.author)).blue().bold(),
+            style(printable(self.description.as_deref().unwrap_or(""))).italic(),
+            style(printable(&inline)).dim()
+        )
+    }
+}
+
+/// Where a page of results is cached in `dir`, named after everything that went into the request
+fn cache_file(dir: &Path, query: &SearchQuery<'_>) -> Result<PathBuf> {
+    if fs::metadata(dir).is_err() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(dir.join(inner_hash(serde_json::to_vec(query)?)))
+}
+
+/// The page cached at `file` if it's younger than `ttl`
+fn cached(file: &Path, ttl: Duration) -> Option<Page> {
+    let age = fs::metadata(file).ok()?.modified().ok()?.elapsed().ok()?;
+    if age >= ttl {
+        return None;
+    }
+    serde_json::from_slice(&fs::read(file).ok()?).ok()
+}
+
+/// Drops the cached pages older than `ttl`, so the cache doesn't grow with every query
+fn prune(dir: &Path, ttl: Duration) {
+    let expired = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(std::result::Result::ok)
+        .filter(|entry| {
+            entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .map_or(false, |age| age >= ttl)
+        });
+    for entry in expired {
+        let _ = fs::remove_file(entry.path());
+    }
+}
+
+/// Requests one page of results, from the cache unless `no_cache`
+///
+/// # Errors
+///
+/// Fails if the server is unreachable or refuses the query
+async fn search_page(config: &Config, cursor: Option<&str>) -> Result<Page> {
+    let query = SearchQuery {
+        query: config.query,
+        author: config.author,
+        lang: config.lang,
+        limit: config.page_size,
+        cursor,
+    };
+    let file = cache_file(&path::data()?.join(SEARCH_CACHE_DIR), &query)?;
+    if !config.no_cache {
+        if let Some(page) = cached(&file, CACHE_TTL) {
+            return Ok(page);
+        }
+    }
+
+    let client = http::Client::new();
+    let response = client
+        .send(client.post(SERVER_URL_SEARCH.to_string()).json(&query))
+        .await?;
+    let page: Page = match response.status() {
+        StatusCode::OK => response.json().await?,
+        StatusCode::BAD_REQUEST => return Err(Error::Server("search query refused")),
+        _ => return Err(Error::Server("unexpected response")),
+    };
+
+    if let Some(dir) = file.parent() {
+        prune(dir, CACHE_TTL);
+    }
+    if let Err(err) = serde_json::to_vec(&page)
+        .map_err(Error::from)
+        .and_then(|bytes| Ok(fs::write(&file, bytes)?))
+    {
+        warnln!("failed to cache search results: {}", err);
+    }
+    Ok(page)
+}
+
+/// Every result with its preview, for pipes and scripts
+fn print(results: &[Found]) {
+    for (rank, found) in results.iter().enumerate() {
+        println!("{:>3}. {}", rank + 1, found);
+        for line in found.preview.lines() {
+            println!("       {}", style(printable(line)).dim());
+        }
+    }
+}
+
+/// Previews or saves a result
+async fn quick_action(found: &Found, config: &Config) -> Result<()> {
+    let action = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{} (esc to go back)", found.name))
+        .items(&["Preview", "Save"])
+        .default(0)
+        .interact_opt()?;
+    if action.is_none() {
+        return Ok(());
+    }
+
+    let hash = GistitHash::parse(&found.hash).map_err(|_| Error::Server("unexpected response"))?;
+    let gistit = fetch_from_server(&hash).await?;
+    if action == Some(0) {
+        preview(&gistit, config.colorscheme, None)?;
+    } else {
+        for (file_path, backup) in save_to(&gistit, &path::data()?, None, Overwrite::Backup)? {
+            if let Some(backup) = backup {
+                warnln!("existing file moved to: `{}`", backup.to_string_lossy());
+            }
+            warnln!("gistit saved at: `{}`", file_path.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let query = check::search_query(self.query)?;
+        let author = self.author.map(check::author_name).transpose()?;
+        let lang = self.lang.map(check::lang).transpose()?;
+        let colorscheme = check::colorscheme(self.colorscheme)?;
+        let page_size = check::page_size(self.page_size)?;
+        updateln!("Prepared");
+
+        Ok(Config {
+            query,
+            author,
+            lang,
+            colorscheme,
+            page_size,
+            no_cache: self.no_cache,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        progress!("Searching");
+        let first = search_page(&config, None).await?;
+        let mut results = first.results;
+        let mut next = first.next;
+        updateln!("Found {} gistits", first.total);
+
+        if structured(&results)? {
+            return Ok(());
+        }
+        if results.is_empty() {
+            finish!("No gistits match");
+            return Ok(());
+        }
+        progress::clear();
+
+        if !atty::is(atty::Stream::Stdin) || !Term::stdout().is_term() {
+            print(&results);
+            return Ok(());
+        }
+
+        let mut cursor = 0;
+        loop {
+            let mut items: Vec<String> = results.iter().map(ToString::to_string).collect();
+            if next.is_some() {
+                items.push(style("(load more)").dim().italic().to_string());
+            }
+
+            let selection = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Results (enter to preview or save, esc to quit)")
+                .items(&items)
+                .default(cursor)
+                .max_length(config.page_size as usize)
+                .interact_opt()?;
+
+            match selection {
+                Some(index) if index == results.len() => {
+                    let page = search_page(&config, next.as_deref()).await?;
+                    cursor = results.len();
+                    results.extend(page.results);
+                    next = page.next;
+                }
+                Some(index) => {
+                    quick_action(&results[index], &config).await?;
+                    cursor = index;
+                }
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn search_cache_per_query_and_expiry() {
+        let dir = TempDir::new().unwrap();
+        let query = SearchQuery {
+            query: "tokio runtime",
+            author: None,
+            lang: Some("rust"),
+            limit: 20,
+            cursor: None,
+        };
+        let file = cache_file(dir.path(), &query).unwrap();
+        assert_eq!(file, cache_file(dir.path(), &query).unwrap());
+        assert_ne!(
+            file,
+            cache_file(
+                dir.path(),
+                &SearchQuery {
+                    cursor: Some("20"),
+                    ..query
+                }
+            )
+            .unwrap()
+        );
+
+        let page = Page {
+            results: vec![Found {
+                hash: "a".repeat(64),
+                author: "fabricio".to_owned(),
+                description: Some("Spawns a runtime".to_owned()),
+                timestamp: "1650000000000".to_owned(),
+                name: "main.rs".to_owned(),
+                lang: "rust".to_owned(),
+                size: 42,
+                score: 5,
+                preview: "fn main() {\n    tokio::spawn(run());".to_owned(),
+            }],
+            next: None,
+            total: 1,
+        };
+        fs::write(&file, serde_json::to_vec(&page).unwrap()).unwrap();
+        let hit = cached(&file, CACHE_TTL).unwrap();
+        assert_eq!(hit.results[0].hash, page.results[0].hash);
+        assert!(cached(&file, Duration::ZERO).is_none());
+
+        prune(dir.path(), Duration::ZERO);
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn search_results_print_without_control_characters() {
+        let found = Found {
+            hash: "a".repeat(64),
+            author: "fabricio".to_owned(),
+            description: Some("Clears \x1b[2Jthe screen".to_owned()),
+            timestamp: "1650000000000".to_owned(),
+            name: "main\x07.rs".to_owned(),
+            lang: "rust".to_owned(),
+            size: 42,
+            score: 5,
+            preview: "\x1b]0;title\x07fn main() {}".to_owned(),
+        };
+        // Styles bring escapes of their own, where colors are on
+        let shown = found.to_string();
+        assert!(!shown.contains('\x07'));
+        assert!(shown.contains("Clears  [2Jthe screen"));
+        assert!(shown.contains(" ]0;title fn main() {}"));
+    }
+}
@@ -1,196 +1,719 @@
 use std::ffi::OsStr;
 use std::fs;
+use std::io::Seek;
 use std::path::{Path, PathBuf};
 use std::str;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use clap::ArgMatches;
 use console::style;
+use reqwest::header::CONTENT_TYPE;
 use reqwest::StatusCode;
+use url::Url;
 
-use gistit_proto::payload::{hash, Gistit};
-use gistit_proto::prost::Message;
-use gistit_proto::{ipc, Instruction};
+use gistit_proto::hash::Namespace;
+use gistit_proto::ipc::instruction::provide_response::Status;
+use gistit_proto::payload::Gistit;
+use gistit_proto::{ipc, validate, GistitBuilder, Instruction};
 
 use gistit_project::path;
+use gistit_project::settings::Settings;
 
-use crate::clipboard::Clipboard;
+use crate::bridge;
+#[cfg(feature = "clipboard")]
+use crate::clipboard;
 use crate::dispatch::Dispatch;
-use crate::file::File;
-use crate::github::{self, CreateResponse, GITHUB_GISTS_API_URL};
-use crate::param::check;
-use crate::server::SERVER_URL_LOAD;
+use crate::encrypt;
+use crate::file::{name_from_path, name_from_url, File, BINARY_LANG};
+use crate::git;
+use crate::github;
+use crate::history::{self, Origin};
+use crate::hook::Hook;
+use crate::http;
+use crate::interrupt;
+use crate::param::check::{self, AuthorSource};
+use crate::progress::Task;
+use crate::scan;
+use crate::serve::{serve_once, Download, Listen};
+use crate::server::{web_url, SERVER_URL_LOAD};
+use crate::snippet;
+use crate::syntax;
+use crate::uplink::{self, Uplink};
 use crate::{errorln, finish, interruptln, progress, updateln, warnln, Error, Result};
 
 #[derive(Debug, Clone)]
 pub struct Action {
     pub file_path: Option<&'static OsStr>,
+    pub from_url: Option<&'static str>,
+    pub from_clipboard: bool,
+    pub git_diff: bool,
+    pub git_range: Option<&'static str>,
     pub maybe_stdin: Option<String>,
     pub description: Option<&'static str>,
-    pub author: &'static str,
+    pub description_file: Option<&'static OsStr>,
+    pub author: Option<&'static str>,
+    pub author_setting: Option<&'static str>,
     pub clipboard: bool,
+    pub clipboard_backend: Option<&'static str>,
     pub github: bool,
+    pub github_update: Option<&'static str>,
+    pub public: bool,
+    pub private: bool,
+    pub expire_after_read: bool,
+    pub binary: bool,
+    pub to: Option<&'static str>,
+    pub ttl_peers: Option<&'static str>,
+    pub serve_once: bool,
+    pub port: Option<&'static str>,
+    pub lan: bool,
+    pub secret_scan: bool,
+    pub redact: bool,
+    pub encrypt: bool,
+    pub range: Option<&'static str>,
+    pub symbol: Option<&'static str>,
+    pub stdin_filename: Option<&'static str>,
+    pub lang: Option<&'static str>,
+    pub pre_send: Option<Hook>,
 }
 
 impl Action {
     pub fn from_args(
         args: &'static ArgMatches,
         maybe_stdin: Option<String>,
+        settings: &'static Settings,
     ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
         Ok(Box::new(Self {
             file_path: args.value_of_os("FILE"),
+            from_url: args.value_of("from-url"),
+            from_clipboard: args.is_present("from-clipboard"),
+            git_diff: args.is_present("git-diff"),
+            git_range: args.value_of("git-diff"),
             maybe_stdin,
             description: args.value_of("description"),
-            author: args
-                .value_of("author")
-                .ok_or(Error::Argument("missing argument", "--author"))?,
-            clipboard: args.is_present("clipboard"),
+            description_file: args.value_of_os("description-file"),
+            author: args.value_of("author"),
+            author_setting: settings.author.as_deref(),
+            clipboard: args.is_present("clipboard")
+                || args.is_present("clipboard-backend")
+                || settings.clipboard,
+            clipboard_backend: args.value_of("clipboard-backend"),
             github: args.is_present("github"),
+            github_update: args.value_of("github-update"),
+            public: args.is_present("public"),
+            private: args.is_present("private"),
+            expire_after_read: args.is_present("expire-after-read"),
+            binary: args.is_present("binary"),
+            to: args.value_of("to"),
+            ttl_peers: args.value_of("ttl-peers"),
+            serve_once: args.is_present("serve-once"),
+            port: args.value_of("port"),
+            lan: args.is_present("lan"),
+            secret_scan: !args.is_present("no-secret-scan"),
+            redact: args.is_present("redact"),
+            encrypt: args.is_present("encrypt"),
+            range: args.value_of("range"),
+            symbol: args.value_of("symbol"),
+            stdin_filename: args.value_of("stdin-filename"),
+            lang: args.value_of("lang"),
+            pre_send: Hook::pre_send(settings),
         }))
     }
 }
 
 #[derive(Debug)]
 pub struct Config {
+    /// What the gistit was built from, served as is by `--serve-once`
     file: File,
-    author: &'static str,
-    description: Option<&'static str>,
+    gistit: Gistit,
     clipboard: bool,
+    /// `None` picks the first one available. Validated by clap
+    clipboard_backend: Option<&'static str>,
     github_token: Option<github::Token>,
+    /// Gist to update instead of posting a new one
+    github_update: Option<&'static str>,
+    binary: bool,
+    to: Option<Destination>,
+    replicas: u32,
+    /// Where and under which file name to serve the file with `--serve-once`, instead of sending it
+    serve_once: Option<(Listen, String)>,
     runtime_path: PathBuf,
 }
 
-impl TryFrom<Config> for Gistit {
-    type Error = Error;
-
-    #[allow(clippy::cast_possible_truncation)]
-    fn try_from(value: Config) -> std::result::Result<Self, Self::Error> {
-        let data = value.file.read()?;
-        let hash = hash(value.author, value.description, &data);
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Check your system time")
-            .as_millis()
-            .to_string();
-
-        let inner = Self::new_inner(
-            value.file.name(),
-            value.file.lang().to_owned(),
-            value.file.size() as u32,
-            data,
-        );
-
-        let gistit = Self::new(
-            hash,
-            value.author.to_owned(),
-            value.description.map(ToOwned::to_owned),
-            now,
-            vec![inner],
-        );
-
-        Ok(gistit)
+/// Where `--to` sends the gistit
+#[derive(Debug)]
+enum Destination {
+    Peer(&'static str),
+    Uplink(Box<dyn Uplink>),
+}
+
+/// Part of the file to send, see [`snippet`]
+#[derive(Debug, Clone, Copy)]
+enum Cut {
+    Whole,
+    Range((usize, usize)),
+    Symbol(&'static str),
+}
+
+/// The file and what's done with it before it's sent: cutting, scanning, hashing and encoding.
+/// Large files take a while, so it runs on a blocking thread while GitHub authorizes or the node
+/// is asked about encryption, reporting on a [`Task`] of its own
+struct Contents {
+    file: File,
+    name: String,
+    cut: Cut,
+    binary: bool,
+    /// Given with `--lang`, told by the extension or guessed from the contents otherwise
+    lang: Option<&'static str>,
+    /// Scan for secrets, redacting them if `Some(true)`
+    scan: Option<bool>,
+}
+
+impl Contents {
+    /// Adds the file to `builder` and builds the gistit, keeping the file as it was added
+    fn build(self, builder: GistitBuilder, task: Task) -> Result<(File, Gistit)> {
+        if self.binary {
+            let mut file = self.file;
+            let gistit = builder
+                .binary_file(file.name(), BINARY_LANG, &file.read_bytes()?)
+                .build()?;
+            file.rewind()?;
+            task.done("Read");
+            return Ok((file, gistit));
+        }
+
+        let (file, snippet) = match self.cut {
+            Cut::Whole => (self.file, None),
+            Cut::Range(range) => {
+                let (data, snippet) = snippet::lines(&self.file.read()?, range)?;
+                (File::from_data(data, &self.name)?, Some(snippet))
+            }
+            Cut::Symbol(symbol) => {
+                task.status(format!("looking for '{}'", symbol));
+                let (data, snippet) =
+                    snippet::symbol(&self.file.read()?, self.file.lang(), symbol)?;
+                (File::from_data(data, &self.name)?, Some(snippet))
+            }
+        };
+        let mut file = match self.scan {
+            Some(redact) => {
+                task.status("scanning for secrets");
+                scan_secrets(file, &self.name, redact)?
+            }
+            None => file,
+        };
+
+        let data = file.read()?;
+        let lang = match self.lang {
+            Some(lang) => lang.to_owned(),
+            // Stdin and clipboard contents have no extension to go by
+            None if file.lang() == "text" => syntax::detect(&data).unwrap_or("text").to_owned(),
+            None => file.lang().to_owned(),
+        };
+        task.status("hashing");
+        let done = match (&snippet, self.cut) {
+            (Some(snippet), Cut::Symbol(symbol)) => format!(
+                "Found '{}' on lines {} to {}",
+                symbol, snippet.start_line, snippet.end_line
+            ),
+            _ => "Read".to_owned(),
+        };
+        // Snippets and contents without a file of their own go by the name they were given
+        let builder = match snippet {
+            Some(snippet) => builder.file(self.name, lang, data).snippet(snippet),
+            None => builder.file(self.name, lang, data),
+        };
+        let gistit = builder.build()?;
+        file.rewind()?;
+        task.done(done);
+        Ok((file, gistit))
+    }
+}
+
+/// Builds a single file gistit payload timestamped now
+///
+/// # Errors
+///
+/// Fails if the payload breaks the rules in [`gistit_proto::validate`]
+#[cfg(feature = "fetch")]
+pub fn new_gistit(
+    name: String,
+    lang: String,
+    data: String,
+    author: &str,
+    description: Option<&str>,
+) -> Result<Gistit> {
+    Ok(GistitBuilder::new(author)
+        .description(description)
+        .file(name, lang, data)
+        .build()?)
+}
+
+/// Uploads a gistit to the server, returning the hash it was stored under
+///
+/// # Errors
+///
+/// Fails if the server rejects the payload or responds unexpectedly
+pub async fn load_to_server(gistit: &Gistit) -> Result<String> {
+    let client = http::Client::new();
+    let response = client
+        .send(http::with_gistit(
+            client.post(SERVER_URL_LOAD.to_string()),
+            gistit,
+        )?)
+        .await?;
+
+    match response.status() {
+        StatusCode::OK => Ok(http::gistit_from(response).await?.hash),
+        StatusCode::UNPROCESSABLE_ENTITY | StatusCode::BAD_REQUEST => {
+            Err(Error::Server("invalid gistit payload"))
+        }
+        _ => Err(Error::Server("invalid server response")),
+    }
+}
+
+/// Downloads a remote file, giving up as soon as it grows past the gistit size limit
+async fn download(url: &Url) -> Result<(String, String)> {
+    let too_large = || Error::Argument("remote file is too large", "--from-url");
+
+    let client = http::Client::new();
+    let mut response = client.send(client.get(url.clone())).await?;
+    if !response.status().is_success() {
+        return Err(Error::Server("failed to download remote file"));
+    }
+    if response
+        .content_length()
+        .map_or(false, |len| len > *validate::FILE_SIZE.end() as u64)
+    {
+        return Err(too_large());
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned);
+
+    // `Content-Length` may be missing or lying
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        data.extend_from_slice(&chunk);
+        if data.len() > *validate::FILE_SIZE.end() {
+            return Err(too_large());
+        }
+    }
+    check::remote_size(data.len())?;
+
+    let data = String::from_utf8(data)
+        .map_err(|_| Error::Argument("remote file is not valid utf8 text", "--from-url"))?;
+
+    Ok((name_from_url(url, content_type.as_deref()), data))
+}
+
+/// Stops at likely secrets in the content, or with `--redact` replaces them in a copy
+fn scan_secrets(file: File, name: &str, redact: bool) -> Result<File> {
+    let data = file.read()?;
+    let findings = scan::scan(&data);
+    if findings.is_empty() {
+        return Ok(file);
+    }
+
+    for finding in &findings {
+        warnln!("possible {} on line {}", finding.rule, finding.line);
+    }
+    if redact {
+        updateln!("Redacted {} possible secrets", findings.len());
+        File::from_data(scan::redact(&data, &findings), name)
+    } else {
+        Err(Error::Argument(
+            "content looks like it holds secrets, redact them with `--redact` or send anyway with `--no-secret-scan`",
+            "--no-secret-scan",
+        ))
+    }
+}
+
+/// Encrypted gistits are only hosted by the node, and nodes predating encryption would strip it
+async fn check_encryption_support(runtime_path: &Path) -> Result<()> {
+    let mut bridge = gistit_ipc::client(runtime_path)?;
+    if !bridge.alive() {
+        return Err(Error::Argument(
+            "encrypted gistits are hosted by peers, start a gistit node with `gistit node --start`",
+            "--encrypt",
+        ));
+    }
+    if bridge::supports(&mut bridge, ipc::capability::ENCRYPTION).await? {
+        Ok(())
+    } else {
+        Err(Error::Argument(
+            "the running gistit node can't host encrypted gistits, update and restart it",
+            "--encrypt",
+        ))
     }
 }
 
+/// The text in the system clipboard, for `--from-clipboard`
+#[cfg(feature = "clipboard")]
+fn paste() -> Result<String> {
+    clipboard::paste(*validate::FILE_SIZE.end())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn paste() -> Result<String> {
+    Err(Error::Feature("clipboard"))
+}
+
+/// Copies `content` if asked to, returning the note shown next to it. It was sent already, so
+/// failing to copy is only reported
+#[cfg(feature = "clipboard")]
+fn copy_note(copy: bool, content: &str, backend: Option<&str>) -> String {
+    if !copy {
+        return String::new();
+    }
+    let backend = backend.and_then(|backend| backend.parse().ok());
+    let note = match clipboard::copy(content, backend) {
+        Ok(backend) => format!("(copied via {})", backend),
+        Err(err) => format!("(not copied to the clipboard, {})", err),
+    };
+    style(note).italic().dim().to_string()
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_note(copy: bool, _: &str, _: Option<&str>) -> String {
+    if !copy {
+        return String::new();
+    }
+    style(format!("(not copied, {})", Error::Feature("clipboard")))
+        .italic()
+        .dim()
+        .to_string()
+}
+
 #[async_trait]
 impl Dispatch for Action {
     type InnerData = Config;
 
     async fn prepare(&self) -> Result<Self::InnerData> {
         progress!("Preparing");
+        // Temp files are named randomly, the name the file is served as is kept here
+        let mut name = "stdin".to_owned();
         let file = if let Some(file_ostr) = self.file_path {
             let path = Path::new(file_ostr);
             let attr = fs::metadata(&path)?;
             let maybe_extension = path.extension();
 
-            check::metadata(&attr)?;
-            check::extension(maybe_extension)?;
+            // Only the snippet has to fit in a gistit
+            if self.range.is_some() || self.symbol.is_some() {
+                check::snippet_source(&attr)?;
+            } else {
+                check::metadata(&attr)?;
+            }
+            // Binary files aren't mapped to a language
+            if !self.binary {
+                check::extension(maybe_extension)?;
+            }
 
-            File::from_path(path)?
+            let file = File::from_path(path)?;
+            name = file.name();
+            file
+        } else if let Some(url) = self.from_url {
+            let url = check::remote_url(url)?;
+            progress!("Downloading");
+            let (remote_name, data) = download(&url).await?;
+            updateln!("Downloaded");
+
+            name = remote_name;
+            File::from_data(data, &name)?
+        } else if self.from_clipboard {
+            progress!("Reading clipboard");
+            let text = paste()?;
+            updateln!("Read clipboard");
+
+            name = "clipboard".to_owned();
+            File::from_data(text, &name)?
+        } else if self.git_diff {
+            let range = git::Range::new(self.git_range);
+            progress!("Diffing");
+            let patch = git::diff(range)?;
+            updateln!("Diffed");
+
+            name = range.file_name();
+            File::from_data(patch, &name)?
         } else if let Some(ref stdin) = self.maybe_stdin {
-            File::from_data(stdin, "stdin")?
+            if let Some(stdin_filename) = self.stdin_filename {
+                // A path would do too, only its last component names the file
+                name = name_from_path(Path::new(stdin_filename));
+            }
+            File::from_data(stdin, &name)?
         } else {
             return Err(Error::Argument("missing file input", "[FILE]/[STDIN]"));
         };
+        let lang = self.lang.map(check::lang).transpose()?;
+        let cut = if let Some(range) = self.range {
+            Cut::Range(check::line_range(range).map_err(|_| {
+                Error::Argument(
+                    "invalid line range, expected `start:end`, `start:` or `:end`",
+                    "--range",
+                )
+            })?)
+        } else if let Some(symbol) = self.symbol {
+            Cut::Symbol(symbol)
+        } else {
+            Cut::Whole
+        };
 
-        let author = check::author(self.author)?;
+        // Patches are credited to whoever git says made them
+        let git_author = if self.git_diff && self.author.is_none() {
+            git::user_name().filter(|author| check::author_name(author).is_ok())
+        } else {
+            None
+        };
+        let (author, source) = match git_author {
+            Some(author) => (author, AuthorSource::Git),
+            None => check::author(self.author, self.author_setting)?,
+        };
+        if source != AuthorSource::Flag {
+            updateln!("Sending as '{}', from {}", author, source);
+        }
         let description = if let Some(value) = self.description {
             Some(check::description(value)?)
         } else {
             None
         };
-        updateln!("Prepared");
-
-        let github_token = if self.github {
-            progress!("Authorizing");
-            let mut oauth = github::Oauth::new()?;
-
-            if oauth.token().is_none() {
-                if let Err(url) = oauth.authorize() {
-                    warnln!(
-                        "failed to open your web browser. \n\nAuthorize manually: '{}'",
-                        style(url).cyan()
-                    );
-                }
-                oauth.poll_token().await?;
-                warnln!(
-                    "storing github token at: '{}'",
-                    gistit_project::path::config()?.to_string_lossy()
-                );
-            }
-            updateln!("Authorized");
-            oauth.token
+        let description_file = self
+            .description_file
+            .map(check::description_file)
+            .transpose()?;
+
+        let to = match self.to.map(|to| (to, uplink::from_name(to))) {
+            Some((_, Some(uplink))) => Some(Destination::Uplink(uplink)),
+            Some((peer_id, None)) => Some(Destination::Peer(check::peer_id(peer_id, "--to")?)),
+            None => None,
+        };
+        let replicas = self
+            .ttl_peers
+            .map(check::replicas)
+            .transpose()?
+            .unwrap_or_default();
+        let serve_once = if self.serve_once {
+            let listen = Listen {
+                port: check::port_number(self.port.unwrap_or("0"))?,
+                lan: self.lan,
+            };
+            Some((listen, name.clone()))
         } else {
             None
         };
+        let runtime_path = path::runtime()?;
+
+        let builder = GistitBuilder::new(author)
+            .description(description)
+            .public(self.public)
+            .private(self.private)
+            .burn_after_read(self.expire_after_read);
+        let builder = match description_file {
+            Some((name, data)) => builder.description_file(name, data),
+            None => builder,
+        };
+        let contents = Contents {
+            file,
+            name,
+            cut,
+            binary: self.binary,
+            lang,
+            // Binary files are sent as is, there's no text to look at
+            scan: if self.secret_scan && !self.binary {
+                Some(self.redact)
+            } else {
+                None
+            },
+        };
+        // Its own spinner, the main one tells how GitHub or the node are doing meanwhile
+        let task = progress::task(contents.name.clone(), "reading");
+        let building = tokio::task::spawn_blocking(move || contents.build(builder, task));
+        let github_update = self
+            .github_update
+            .map(|gist| check::gist_id(gist, "--github-update"))
+            .transpose()?;
+        let github_token = async {
+            if self.github || github_update.is_some() {
+                github::authorized_token().await.map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        let encryption_support = async {
+            if self.encrypt {
+                check_encryption_support(&runtime_path).await
+            } else {
+                Ok(())
+            }
+        };
+        let ((file, gistit), github_token, ()) = tokio::try_join!(
+            async { building.await.map_err(std::io::Error::from)? },
+            github_token,
+            encryption_support
+        )?;
+
+        // Asked for once nothing else prints, the prompt needs the terminal
+        let gistit = if self.encrypt {
+            encrypt::seal(gistit, &encrypt::secret(true)?)?
+        } else {
+            gistit
+        };
+        updateln!("Prepared");
 
         Ok(Config {
             file,
-            description,
-            author,
+            gistit,
             clipboard: self.clipboard,
+            clipboard_backend: self.clipboard_backend,
             github_token,
-            runtime_path: path::runtime()?,
+            github_update,
+            binary: self.binary,
+            to,
+            replicas,
+            serve_once,
+            runtime_path,
         })
     }
 
     #[allow(clippy::too_many_lines)]
-    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
-        let clipboard = config.clipboard;
+    async fn dispatch(&self, mut config: Self::InnerData) -> Result<()> {
+        let (clipboard, clipboard_backend) = (config.clipboard, config.clipboard_backend);
+        let to = config.to.take();
+        let replicas = config.replicas;
+
+        if let Some(hook) = &self.pre_send {
+            hook.run(config.file.path(), &config.gistit, 0).await?;
+        }
+
+        if let Some((listen, name)) = config.serve_once.take() {
+            let download = Download {
+                name,
+                data: config.file.read_bytes()?,
+                binary: config.binary,
+            };
+            return serve_once(download, listen).await;
+        }
 
         let mut bridge = gistit_ipc::client(&config.runtime_path)?;
-        if bridge.alive() {
+        if replicas > 0 && !bridge.alive() {
+            return Err(Error::Argument(
+                "replicating needs a running gistit node, start it with `gistit node --start`",
+                "--ttl-peers",
+            ));
+        }
+        // Older nodes would provide it and drop the replicas without a word
+        if replicas > 0 && !bridge::supports(&mut bridge, ipc::capability::REPLICATION).await? {
+            return Err(Error::Argument(
+                "the running gistit node can't replicate, update and restart it",
+                "--ttl-peers",
+            ));
+        }
+
+        if let Some(Destination::Uplink(uplink)) = to {
+            progress!("Sending to {}", uplink.name());
+            let gistit = config.gistit;
+            let url = uplink.upload(&gistit).await?;
+
+            let clipboard_msg = copy_note(clipboard, url.as_str(), clipboard_backend);
+
+            updateln!("Sent to {}", uplink.name());
+            finish!(format!(
+                "\n    url: '{}' {}\n\n",
+                style(url).bold(),
+                clipboard_msg
+            ));
+        } else if let Some(Destination::Peer(peer_id)) = to {
+            if !bridge.alive() {
+                return Err(Error::Argument(
+                    "sending to a peer needs a running gistit node, start it with `gistit node --start`",
+                    "--to",
+                ));
+            }
+
+            progress!("Sending to peer");
+            let gistit = config.gistit;
+            let hash = Namespace::Peer.tag(&gistit.hash);
+
+            bridge.connect().await?;
+            bridge
+                .send(Instruction::request_send_to_peer(
+                    peer_id.to_owned(),
+                    gistit.clone(),
+                ))
+                .await?;
+
+            if let ipc::instruction::Kind::SendToPeerResponse(response) =
+                interrupt::bounded("gistit node", bridge.recv())
+                    .await?
+                    .expect_response()?
+            {
+                if response.delivered {
+                    history::record(&gistit, Origin::Sent, false);
+                    updateln!("Sent to peer");
+                    finish!(format!(
+                        "\n    hash: '{}'\n    peer: '{}'\n\n",
+                        style(hash).bold(),
+                        style(peer_id).dim()
+                    ));
+                } else {
+                    interruptln!();
+                    errorln!(
+                        "failed to send to peer: {}",
+                        response.error.unwrap_or_else(|| "unknown error".to_owned())
+                    );
+                }
+            }
+        } else if bridge.alive() {
             // Daemon is running, hosting with p2p
             progress!("Hosting");
-            let gistit: Gistit = config.try_into()?;
+            let gistit = config.gistit;
 
-            bridge.connect_blocking()?;
-            bridge.send(Instruction::request_provide(gistit)).await?;
+            bridge.connect().await?;
+            bridge
+                .send(Instruction::request_provide(gistit.clone(), replicas))
+                .await?;
+
+            let response = interrupt::bounded("gistit node", bridge.recv())
+                .await?
+                .expect_response()?;
+            if let ipc::instruction::Kind::ErrorResponse(err) = response {
+                interruptln!();
+                return Err(Error::Daemon(err));
+            }
 
             if let ipc::instruction::Kind::ProvideResponse(ipc::instruction::ProvideResponse {
                 hash: Some(hash),
-            }) = bridge.recv().await?.expect_response()?
+                status,
+                ..
+            }) = response
             {
-                if clipboard {
-                    Clipboard::new(&hash)
-                        .try_into_selected()?
-                        .into_provider()
-                        .set_contents()?;
-                }
+                history::record(&gistit, Origin::Sent, false);
+                let hash = Namespace::Peer.tag(&hash);
+                let clipboard_msg = copy_note(clipboard, &hash, clipboard_backend);
 
-                let clipboard_msg = if self.clipboard {
-                    style("(copied to clipboard)").italic().dim().to_string()
+                if status == Status::Duplicate as i32 {
+                    updateln!("Hosted already");
                 } else {
-                    "".to_string()
-                };
-
-                updateln!("Hosted");
+                    updateln!("Hosted");
+                }
+                if replicas > 0 {
+                    updateln!(
+                        "Asking {} trusted peers to provide it too, see the gistit node logs",
+                        replicas
+                    );
+                }
                 finish!(format!(
                     "\n    hash: '{}' {}\n\n",
                     style(hash).bold(),
                     style(clipboard_msg).italic().dim()
                 ));
+            } else if let ipc::instruction::Kind::ProvideResponse(refused) = response {
+                interruptln!();
+                return Err(Error::Refused(
+                    refused.status(),
+                    refused.reason.unwrap_or_default(),
+                ));
             } else {
                 interruptln!();
                 errorln!("failed to provide gistit, check gistit-daemon logs");
@@ -198,96 +721,38 @@ impl Dispatch for Action {
         } else {
             progress!("Sending");
             let maybe_github_token = config.github_token.as_ref().map(Clone::clone);
-            let gistit: Gistit = config.try_into()?;
+            let gistit = config.gistit;
 
-            let maybe_gist = if let Some(token) = maybe_github_token {
+            let maybe_gist = match (maybe_github_token, config.github_update) {
+                (Some(token), Some(id)) => github::update_gist(&token, id, &gistit)
+                    .await?
+                    .map(|gist| gist.url),
                 // Github flag was provided, sending to Github Gists
-                // NOTE: Currently we only support one file
-                let inner = gistit.inner.first().expect("to have at least one file");
-                let name = &inner.name;
-                let description = gistit.description.as_deref().unwrap_or("");
-
-                let response = reqwest::Client::new()
-                    .post(GITHUB_GISTS_API_URL)
-                    .header("user-agent", "gistit")
-                    .header("authorization", format!("token {}", token.access_token))
-                    .header("accept", "application/vnd.github.v3+json")
-                    .json(&serde_json::json!({
-                        "description": description,
-                        "public": true,
-                        "files": {
-                            name: {
-                                "content": inner.data
-                            }
-                        }
-                    }))
-                    .send()
-                    .await?;
-
-                match response.status() {
-                    StatusCode::CREATED => {
-                        let data: CreateResponse = response.json().await?;
-                        Some(data.url)
-                    }
-                    StatusCode::FORBIDDEN | StatusCode::UNPROCESSABLE_ENTITY => {
-                        warnln!(
-                            "your github token is expired, nothing was posted. status {}",
-                            response.status()
-                        );
-                        None
-                    }
-                    _ => {
-                        warnln!("got a invalid response from github, nothing was posted");
-                        None
-                    }
-                }
-            } else {
-                None
+                (Some(token), None) => github::create_gist(&token, &gistit)
+                    .await?
+                    .map(|gist| gist.url),
+                (None, _) => None,
             };
 
-            let response = reqwest::Client::new()
-                .post(SERVER_URL_LOAD.to_string())
-                .header("content-type", "application/x-protobuf")
-                .body(gistit.encode_to_vec())
-                .send()
-                .await?;
-
-            match response.status() {
-                StatusCode::OK => {
-                    let server_hash = Gistit::from_bytes(response.bytes().await?)?.hash;
-
-                    if clipboard {
-                        Clipboard::new(&server_hash)
-                            .try_into_selected()?
-                            .into_provider()
-                            .set_contents()?;
-                    }
-                    updateln!("Sent");
-
-                    let clipboard_msg = if self.clipboard {
-                        style("(copied to clipboard)").italic().dim().to_string()
-                    } else {
-                        "".to_string()
-                    };
-
-                    let gist = maybe_gist.map_or_else(
-                        || "".to_string(),
-                        |gist_url| format!("github gist: '{}'\n", gist_url),
-                    );
-
-                    finish!(format!(
-                        "\n    hash: '{}' {} \n    url: 'https://gistit.vercel.app/h/{}' \n    {}\n\n",
-                        style(&server_hash).bold(),
-                        clipboard_msg,
-                        style(&server_hash).bold(),
-                        gist
-                    ));
-                }
-                StatusCode::UNPROCESSABLE_ENTITY | StatusCode::BAD_REQUEST => {
-                    return Err(Error::Server("invalid gistit payload"));
-                }
-                _ => return Err(Error::Server("invalid server response")),
-            }
+            let server_hash = load_to_server(&gistit).await?;
+            history::record(&gistit, Origin::Sent, true);
+            let hash = Namespace::Server.tag(&server_hash);
+
+            let clipboard_msg = copy_note(clipboard, &hash, clipboard_backend);
+            updateln!("Sent");
+
+            let gist = maybe_gist.map_or_else(
+                || "".to_string(),
+                |gist_url| format!("github gist: '{}'\n", gist_url),
+            );
+
+            finish!(format!(
+                "\n    hash: '{}' {} \n    url: '{}' \n    {}\n\n",
+                style(&hash).bold(),
+                clipboard_msg,
+                web_url(&server_hash),
+                gist
+            ));
         };
         Ok(())
     }
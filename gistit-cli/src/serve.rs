@@ -0,0 +1,242 @@
+//! The serve module
+//!
+//! `--serve-once` hands a file to someone without gistit over plain HTTP. We listen on localhost,
+//! or on every interface with `--lan`, answer the first download and exit. The url carries a
+//! random token, so others on the network can't guess it and browsers asking for a favicon don't
+//! count as the download. Clients are answered side by side, each within [`ANSWER_TIMEOUT`], so a
+//! stalled one doesn't hold up the others.
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+use console::style;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use crate::interrupt;
+use crate::web::request_line;
+use crate::{finish, updateln, warnln, Error, Result};
+
+const TOKEN_LENGTH: usize = 16;
+
+/// How long a client may take to send its request and read the answer
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait before accepting again when it failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A file to hand over
+#[derive(Debug)]
+pub struct Download {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub binary: bool,
+}
+
+/// Where to serve a [`Download`]
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Listen {
+    pub port: u16,
+    /// Every interface instead of localhost only, `--lan`
+    pub lan: bool,
+}
+
+/// Address others on the local network reach us at, the one of the interface the default route
+/// goes through. Connecting an udp socket sends nothing
+fn lan_address() -> IpAddr {
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80))?;
+            socket.local_addr()
+        })
+        .map_or(IpAddr::V4(Ipv4Addr::LOCALHOST), |addr| addr.ip())
+}
+
+/// Percent encodes a file name for the url path
+fn encode(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Serves `download` until it's downloaded once, or `--timeout` elapses
+///
+/// # Errors
+///
+/// Fails if the port can't be bound or nobody downloads it in time
+pub async fn serve_once(download: Download, listen: Listen) -> Result<()> {
+    let (bind, shown) = if listen.lan {
+        (Ipv4Addr::UNSPECIFIED, lan_address())
+    } else {
+        (Ipv4Addr::LOCALHOST, IpAddr::V4(Ipv4Addr::LOCALHOST))
+    };
+    let listener = TcpListener::bind((bind, listen.port)).await?;
+    let token: Arc<str> = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect::<String>()
+        .into();
+    let address = SocketAddr::new(shown, listener.local_addr()?.port());
+
+    finish!(format!(
+        "\n    download: '{}' {}\n\n",
+        style(format!(
+            "http://{}/{}/{}",
+            address,
+            token,
+            encode(&download.name)
+        ))
+        .bold(),
+        style("(served once, ctrl-c to stop)").italic().dim()
+    ));
+
+    let download = Arc::new(download);
+    let (answered, mut downloads) = mpsc::unbounded_channel();
+    interrupt::bounded("a download", async {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            warnln!("failed to accept a connection: {}", err);
+                            // Out of file descriptors, say, give the others time to finish
+                            tokio::time::sleep(ACCEPT_BACKOFF).await;
+                            continue;
+                        }
+                    };
+                    let (token, download, answered) =
+                        (Arc::clone(&token), Arc::clone(&download), answered.clone());
+                    tokio::spawn(async move {
+                        let result = tokio::time::timeout(
+                            ANSWER_TIMEOUT,
+                            answer(stream, &token, &download),
+                        )
+                        .await
+                        .unwrap_or(Err(Error::Timeout("answering a request")));
+                        let _ = answered.send((peer, result));
+                    });
+                }
+                Some((peer, result)) = downloads.recv() => match result {
+                    Ok(true) => {
+                        updateln!("Downloaded by {}", peer.ip());
+                        return Ok::<_, Error>(());
+                    }
+                    Ok(false) => (),
+                    Err(err) => warnln!("failed to answer {}: {}", peer.ip(), err),
+                },
+            }
+        }
+    })
+    .await
+}
+
+/// Answers a request, `true` once the file was sent
+async fn answer(mut stream: TcpStream, token: &str, download: &Download) -> Result<bool> {
+    let (method, path) = request_line(&mut stream).await?;
+    let authorized = path.split('/').nth(1) == Some(token);
+
+    let (head, body, downloaded) = match (method.as_str(), authorized) {
+        ("GET" | "HEAD", true) => {
+            let content_type = if download.binary {
+                "application/octet-stream"
+            } else {
+                "text/plain; charset=utf-8"
+            };
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                 Content-Disposition: attachment; filename=\"{}\"\r\nConnection: close\r\n\r\n",
+                content_type,
+                download.data.len(),
+                download.name.replace(['"', '\\'], "_")
+            );
+            // Some clients look before they download
+            let downloaded = method == "GET";
+            (
+                head,
+                if downloaded { &download.data[..] } else { &[] },
+                downloaded,
+            )
+        }
+        ("GET" | "HEAD", false) => (
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+            &[][..],
+            false,
+        ),
+        _ => (
+            "HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_owned(),
+            &[][..],
+            false,
+        ),
+    };
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await?;
+    Ok(downloaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn serve_encode_name() {
+        assert_eq!(encode("main.rs"), "main.rs");
+        assert_eq!(encode("my file+1.rs"), "my%20file%2B1.rs");
+    }
+
+    #[tokio::test]
+    async fn serve_answers_only_the_token() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let download = Download {
+            name: "main.rs".to_owned(),
+            data: b"fn main() {}".to_vec(),
+            binary: false,
+        };
+
+        for (request, downloaded, expected) in [
+            ("GET /favicon.ico HTTP/1.1\r\n\r\n", false, "HTTP/1.1 404"),
+            (
+                "HEAD /token/main.rs HTTP/1.1\r\n\r\n",
+                false,
+                "HTTP/1.1 200",
+            ),
+            (
+                "POST /token/main.rs HTTP/1.1\r\n\r\n",
+                false,
+                "HTTP/1.1 405",
+            ),
+            ("GET /token/main.rs HTTP/1.1\r\n\r\n", true, "HTTP/1.1 200"),
+        ] {
+            let client = tokio::spawn(async move {
+                let mut stream = TcpStream::connect(address).await.unwrap();
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            });
+            let (stream, _) = listener.accept().await.unwrap();
+            assert_eq!(
+                answer(stream, "token", &download).await.unwrap(),
+                downloaded
+            );
+
+            let response = client.await.unwrap();
+            assert!(response.starts_with(expected));
+            assert_eq!(response.ends_with("fn main() {}"), downloaded);
+        }
+    }
+}
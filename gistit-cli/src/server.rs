@@ -8,8 +8,24 @@ lazy_static! {
 }
 
 const SERVER_SUBPATH_GET: &str = "get";
+const SERVER_SUBPATH_HEAD: &str = "head";
 const SERVER_SUBPATH_LOAD: &str = "load";
 const SERVER_SUBPATH_TOKEN: &str = "token";
+const SERVER_SUBPATH_LIST: &str = "list";
+const SERVER_SUBPATH_LIST_AUTHOR: &str = "listAuthor";
+const SERVER_SUBPATH_REVOKE: &str = "revoke";
+const SERVER_SUBPATH_RESOLVE: &str = "resolve";
+const SERVER_SUBPATH_ANNOTATE: &str = "annotate";
+const SERVER_SUBPATH_ANNOTATIONS: &str = "annotations";
+const SERVER_SUBPATH_SEARCH: &str = "search";
+
+const WEB_URL_BASE: &str = "https://gistit.vercel.app/h/";
+
+/// Where a gistit can be viewed in the browser
+#[must_use]
+pub fn web_url(hash: &str) -> String {
+    format!("{}{}", WEB_URL_BASE, hash)
+}
 
 lazy_static! {
     pub static ref SERVER_URL_GET: Url = Url::parse(
@@ -19,6 +35,13 @@ lazy_static! {
     .expect("invalid `GISTIT_SERVER_URL` variable")
     .join(SERVER_SUBPATH_GET)
     .unwrap();
+    pub static ref SERVER_URL_HEAD: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_HEAD)
+    .unwrap();
     pub static ref SERVER_URL_LOAD: Url = Url::parse(
         &std::env::var(env::GISTIT_SERVER_URL)
             .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
@@ -33,4 +56,53 @@ lazy_static! {
     .expect("invalid `GISTIT_SERVER_URL` variable")
     .join(SERVER_SUBPATH_TOKEN)
     .unwrap();
+    pub static ref SERVER_URL_LIST: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_LIST)
+    .unwrap();
+    pub static ref SERVER_URL_LIST_AUTHOR: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_LIST_AUTHOR)
+    .unwrap();
+    pub static ref SERVER_URL_REVOKE: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_REVOKE)
+    .unwrap();
+    pub static ref SERVER_URL_RESOLVE: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_RESOLVE)
+    .unwrap();
+    pub static ref SERVER_URL_ANNOTATE: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_ANNOTATE)
+    .unwrap();
+    pub static ref SERVER_URL_ANNOTATIONS: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_ANNOTATIONS)
+    .unwrap();
+    pub static ref SERVER_URL_SEARCH: Url = Url::parse(
+        &std::env::var(env::GISTIT_SERVER_URL)
+            .unwrap_or_else(|_| var::GISTIT_SERVER_URL_BASE.to_owned())
+    )
+    .expect("invalid `GISTIT_SERVER_URL` variable")
+    .join(SERVER_SUBPATH_SEARCH)
+    .unwrap();
 }
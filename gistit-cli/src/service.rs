@@ -0,0 +1,421 @@
+//! The service module
+//!
+//! Registers the gistit node with the platform service manager so it starts at login: a systemd
+//! user unit on Linux, a launchd agent on macOS and a logon scheduled task on Windows. The daemon
+//! runs under the manager with the same arguments `gistit node --start` spawns it with. Windows
+//! can't run it as a real service, the task starts a small wrapper script instead.
+use std::path::PathBuf;
+
+use crate::Result;
+
+/// Name of the unit, agent or task
+pub const SERVICE_NAME: &str = "gistit-daemon";
+
+/// How to run the daemon
+#[derive(Debug, Clone)]
+pub struct Service {
+    /// The daemon binary, as an absolute path
+    pub program: PathBuf,
+
+    /// Arguments the daemon is started with
+    pub args: Vec<String>,
+
+    /// Where the daemon output goes
+    pub log: PathBuf,
+}
+
+/// Installs and starts the service, returns where it was installed
+///
+/// # Errors
+///
+/// Fails on unsupported platforms, or if the service file can't be written or the service manager
+/// refuses it
+pub fn install(service: &Service) -> Result<PathBuf> {
+    backend::install(service)
+}
+
+/// Stops and removes the service. `None` if it wasn't installed
+///
+/// # Errors
+///
+/// Fails on unsupported platforms, or if the service manager or file system refuse
+pub fn uninstall() -> Result<Option<PathBuf>> {
+    backend::uninstall()
+}
+
+/// Runs a service manager command, failing with its output if it doesn't succeed
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = std::process::Command::new(program).args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(crate::Error::Service(format!(
+            "`{} {}` failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use gistit_project::path;
+
+    use super::{run, Service, SERVICE_NAME};
+    use crate::Result;
+
+    fn unit_file() -> Result<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME").map_or_else(
+            || path::home().map(|home| home.join(".config")),
+            |dir| Ok(dir.into()),
+        )?;
+        Ok(config
+            .join("systemd")
+            .join("user")
+            .join(format!("{}.service", SERVICE_NAME)))
+    }
+
+    /// Quotes a word for `ExecStart`, where `%` and `$` are expanded by systemd
+    fn quote(word: &str) -> String {
+        let escaped = word.replace('%', "%%").replace('$', "$$");
+        if word.is_empty() || word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+            format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            escaped
+        }
+    }
+
+    /// The unit file running `service`
+    pub(super) fn unit(service: &Service) -> String {
+        let exec = std::iter::once(service.program.to_string_lossy().into_owned())
+            .chain(service.args.iter().cloned())
+            .map(|word| quote(&word))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        format!(
+            "[Unit]
+Description=Gistit p2p node
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+ExecStart={}
+Restart=on-failure
+StandardError=append:{}
+
+[Install]
+WantedBy=default.target
+",
+            exec,
+            service.log.display()
+        )
+    }
+
+    pub fn install(service: &Service) -> Result<PathBuf> {
+        let file = unit_file()?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, unit(service))?;
+
+        let name = format!("{}.service", SERVICE_NAME);
+        run("systemctl", &["--user", "daemon-reload"])?;
+        run("systemctl", &["--user", "enable", "--now", &name])?;
+        Ok(file)
+    }
+
+    pub fn uninstall() -> Result<Option<PathBuf>> {
+        let file = unit_file()?;
+        if fs::metadata(&file).is_err() {
+            return Ok(None);
+        }
+
+        let name = format!("{}.service", SERVICE_NAME);
+        run("systemctl", &["--user", "disable", "--now", &name])?;
+        fs::remove_file(&file)?;
+        run("systemctl", &["--user", "daemon-reload"])?;
+        Ok(Some(file))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod backend {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use gistit_project::path;
+
+    use super::{run, Service};
+    use crate::Result;
+
+    const LABEL: &str = "io.demfabris.gistit";
+
+    fn plist_file() -> Result<PathBuf> {
+        Ok(path::home()?
+            .join("Library")
+            .join("LaunchAgents")
+            .join(format!("{}.plist", LABEL)))
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    /// The agent file running `service`
+    fn plist(service: &Service) -> String {
+        let args: String = std::iter::once(service.program.to_string_lossy().into_owned())
+            .chain(service.args.iter().cloned())
+            .map(|arg| format!("        <string>{}</string>\n", escape(&arg)))
+            .collect();
+        let log = escape(&service.log.to_string_lossy());
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+"#,
+            LABEL, args, log
+        )
+    }
+
+    pub fn install(service: &Service) -> Result<PathBuf> {
+        let file = plist_file()?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Reloads an agent installed before
+        if fs::metadata(&file).is_ok() {
+            let _ = run("launchctl", &["unload", &file.to_string_lossy()]);
+        }
+        fs::write(&file, plist(service))?;
+
+        run("launchctl", &["load", "-w", &file.to_string_lossy()])?;
+        Ok(file)
+    }
+
+    pub fn uninstall() -> Result<Option<PathBuf>> {
+        let file = plist_file()?;
+        if fs::metadata(&file).is_err() {
+            return Ok(None);
+        }
+
+        run("launchctl", &["unload", "-w", &file.to_string_lossy()])?;
+        fs::remove_file(&file)?;
+        Ok(Some(file))
+    }
+}
+
+#[cfg(windows)]
+mod backend {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use gistit_project::path;
+
+    use super::{run, Service, SERVICE_NAME};
+    use crate::{Error, Result};
+
+    fn wrapper_file() -> Result<PathBuf> {
+        Ok(path::config()?.join(format!("{}.cmd", SERVICE_NAME)))
+    }
+
+    /// Quotes a path for cmd. Paths can't hold quotes, and cmd takes everything else between
+    /// them as is but `%`
+    fn quote_path(path: &Path) -> String {
+        format!("\"{}\"", path.to_string_lossy().replace('%', "%%"))
+    }
+
+    /// Quotes an argument the way programs split their command line, then escapes every cmd
+    /// special character in it, quotes included, so cmd passes it through untouched
+    pub(super) fn quote(word: &str) -> Result<String> {
+        if word.contains(&['\r', '\n'][..]) {
+            return Err(Error::Service(format!(
+                "can't pass {:?} to the service, it has a line break",
+                word
+            )));
+        }
+
+        let mut quoted = String::from('"');
+        let mut backslashes = 0;
+        for c in word.chars() {
+            if c == '\\' {
+                backslashes += 1;
+            } else {
+                // Backslashes before a quote are escapes, as is one more for the quote itself
+                if c == '"' {
+                    quoted.extend(std::iter::repeat('\\').take(backslashes + 1));
+                }
+                backslashes = 0;
+            }
+            quoted.push(c);
+        }
+        // Or the closing quote would be escaped
+        quoted.extend(std::iter::repeat('\\').take(backslashes));
+        quoted.push('"');
+
+        let mut escaped = String::with_capacity(quoted.len());
+        for c in quoted.chars() {
+            match c {
+                '%' => escaped.push_str("%%"),
+                '^' | '&' | '|' | '<' | '>' | '(' | ')' | '!' | '"' => {
+                    escaped.push('^');
+                    escaped.push(c);
+                }
+                _ => escaped.push(c),
+            }
+        }
+        Ok(escaped)
+    }
+
+    /// The batch file the task runs to start `service`
+    pub(super) fn wrapper(service: &Service) -> Result<String> {
+        let command = std::iter::once(Ok(quote_path(&service.program)))
+            .chain(service.args.iter().map(|word| quote(word)))
+            .collect::<Result<Vec<_>>>()?
+            .join(" ");
+        Ok(format!(
+            "@echo off\r\n{} 2>> {}\r\n",
+            command,
+            quote_path(&service.log)
+        ))
+    }
+
+    pub fn install(service: &Service) -> Result<PathBuf> {
+        // Task commands are limited to 261 characters, the wrapper has no such limit
+        let file = wrapper_file()?;
+        fs::write(&file, wrapper(service)?)?;
+
+        let task = format!("\"{}\"", file.display());
+        run(
+            "schtasks",
+            &[
+                "/Create",
+                "/F",
+                "/SC",
+                "ONLOGON",
+                "/RL",
+                "LIMITED",
+                "/TN",
+                SERVICE_NAME,
+                "/TR",
+                &task,
+            ],
+        )?;
+        run("schtasks", &["/Run", "/TN", SERVICE_NAME])?;
+        Ok(file)
+    }
+
+    pub fn uninstall() -> Result<Option<PathBuf>> {
+        let file = wrapper_file()?;
+        if fs::metadata(&file).is_err() {
+            return Ok(None);
+        }
+
+        run("schtasks", &["/Delete", "/F", "/TN", SERVICE_NAME])?;
+        fs::remove_file(&file)?;
+        Ok(Some(file))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod backend {
+    use std::path::PathBuf;
+
+    use super::Service;
+    use crate::{Error, Result};
+
+    fn unsupported() -> Error {
+        Error::Service("services are only supported on linux, macos and windows".to_owned())
+    }
+
+    pub fn install(_service: &Service) -> Result<PathBuf> {
+        Err(unsupported())
+    }
+
+    pub fn uninstall() -> Result<Option<PathBuf>> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_systemd_unit() {
+        let unit = backend::unit(&Service {
+            program: PathBuf::from("/usr/local/bin/gistit-daemon"),
+            args: vec![
+                "--runtime-path".to_owned(),
+                "/run/user/1000".to_owned(),
+                "--config-path".to_owned(),
+                "/home/me/My Config".to_owned(),
+                "--listen".to_owned(),
+                "/ip4/0.0.0.0/tcp/4001$%".to_owned(),
+            ],
+            log: PathBuf::from("/run/user/1000/gistit.log"),
+        });
+
+        assert!(unit.contains(
+            "ExecStart=/usr/local/bin/gistit-daemon --runtime-path /run/user/1000 \
+             --config-path \"/home/me/My Config\" --listen /ip4/0.0.0.0/tcp/4001$$%%\n"
+        ));
+        assert!(unit.contains("StandardError=append:/run/user/1000/gistit.log\n"));
+        assert!(unit.ends_with("WantedBy=default.target\n"));
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_windows_wrapper() {
+        assert_eq!(backend::quote("plain").unwrap(), "^\"plain^\"");
+        assert_eq!(
+            backend::quote(r#"a "b" & c% d\"#).unwrap(),
+            r#"^"a \^"b\^" ^& c%% d\\^""#
+        );
+        assert!(backend::quote("two\r\nlines").is_err());
+
+        let wrapper = backend::wrapper(&Service {
+            program: PathBuf::from(r"C:\Program Files\gistit\gistit-daemon.exe"),
+            args: vec![
+                "--config-path".to_owned(),
+                r"C:\Users\me\50% (x)".to_owned(),
+            ],
+            log: PathBuf::from(r"C:\Temp\gistit.log"),
+        })
+        .unwrap();
+        assert_eq!(
+            wrapper,
+            "@echo off\r\n\"C:\\Program Files\\gistit\\gistit-daemon.exe\" \
+             ^\"--config-path^\" ^\"C:\\Users\\me\\50%% ^(x^)^\" 2>> \"C:\\Temp\\gistit.log\"\r\n"
+        );
+    }
+}
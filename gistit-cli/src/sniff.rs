@@ -0,0 +1,293 @@
+//! The sniff module
+//!
+//! Binary gistits, and text ones full of control characters, come out of bat as garbage. Their
+//! previews summarize them instead: the file type going by the first bytes, the dimensions of
+//! images and a hexdump of the head of the file.
+use std::fmt::Write;
+
+use crate::fmt::bytes;
+
+/// Bytes a file type starts with, the ones further in are given with their offset
+struct Magic {
+    parts: &'static [(usize, &'static [u8])],
+    mime: &'static str,
+}
+
+const MAGIC: &[Magic] = &[
+    Magic {
+        parts: &[(0, b"\x89PNG\r\n\x1a\n")],
+        mime: "image/png",
+    },
+    Magic {
+        parts: &[(0, b"\xff\xd8\xff")],
+        mime: "image/jpeg",
+    },
+    Magic {
+        parts: &[(0, b"GIF87a")],
+        mime: "image/gif",
+    },
+    Magic {
+        parts: &[(0, b"GIF89a")],
+        mime: "image/gif",
+    },
+    Magic {
+        parts: &[(0, b"RIFF"), (8, b"WEBP")],
+        mime: "image/webp",
+    },
+    Magic {
+        parts: &[(0, b"BM")],
+        mime: "image/bmp",
+    },
+    Magic {
+        parts: &[(0, b"\x00\x00\x01\x00")],
+        mime: "image/x-icon",
+    },
+    Magic {
+        parts: &[(0, b"%PDF-")],
+        mime: "application/pdf",
+    },
+    Magic {
+        parts: &[(0, b"PK\x03\x04")],
+        mime: "application/zip",
+    },
+    Magic {
+        parts: &[(0, b"\x1f\x8b")],
+        mime: "application/gzip",
+    },
+    Magic {
+        parts: &[(0, b"\x28\xb5\x2f\xfd")],
+        mime: "application/zstd",
+    },
+    Magic {
+        parts: &[(0, b"BZh")],
+        mime: "application/x-bzip2",
+    },
+    Magic {
+        parts: &[(0, b"\xfd7zXZ\x00")],
+        mime: "application/x-xz",
+    },
+    Magic {
+        parts: &[(0, b"7z\xbc\xaf\x27\x1c")],
+        mime: "application/x-7z-compressed",
+    },
+    Magic {
+        parts: &[(257, b"ustar")],
+        mime: "application/x-tar",
+    },
+    Magic {
+        parts: &[(0, b"\x7fELF")],
+        mime: "application/x-elf",
+    },
+    Magic {
+        parts: &[(0, b"\xcf\xfa\xed\xfe")],
+        mime: "application/x-mach-binary",
+    },
+    Magic {
+        parts: &[(0, b"MZ")],
+        mime: "application/vnd.microsoft.portable-executable",
+    },
+    Magic {
+        parts: &[(0, b"\x00asm")],
+        mime: "application/wasm",
+    },
+    Magic {
+        parts: &[(0, b"SQLite format 3\x00")],
+        mime: "application/vnd.sqlite3",
+    },
+    Magic {
+        parts: &[(0, b"ID3")],
+        mime: "audio/mpeg",
+    },
+    Magic {
+        parts: &[(0, b"OggS")],
+        mime: "audio/ogg",
+    },
+    Magic {
+        parts: &[(0, b"fLaC")],
+        mime: "audio/flac",
+    },
+    Magic {
+        parts: &[(0, b"RIFF"), (8, b"WAVE")],
+        mime: "audio/wav",
+    },
+    Magic {
+        parts: &[(4, b"ftyp")],
+        mime: "video/mp4",
+    },
+];
+
+/// Bytes per hexdump line
+const HEXDUMP_WIDTH: usize = 16;
+
+/// Hexdump lines shown in previews
+pub const HEXDUMP_LINES: usize = 8;
+
+/// The MIME type of `data`, `text/plain` for text, otherwise going by its first bytes and
+/// `application/octet-stream` if they don't tell
+#[must_use]
+pub fn mime(data: &[u8]) -> &'static str {
+    // Some magic bytes are plain letters, `BM` for bitmaps
+    if is_text(data) {
+        return "text/plain";
+    }
+    MAGIC
+        .iter()
+        .find(|magic| {
+            magic
+                .parts
+                .iter()
+                .all(|(offset, bytes)| data.get(*offset..offset + bytes.len()) == Some(*bytes))
+        })
+        .map_or("application/octet-stream", |magic| magic.mime)
+}
+
+/// Whether bat can show `data`: UTF-8 without control characters other than whitespace and the
+/// escapes of colored output
+#[must_use]
+pub fn is_text(data: &[u8]) -> bool {
+    std::str::from_utf8(data).map_or(false, |text| {
+        !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+    })
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from(u16::from_be_bytes(
+        data.get(at..at + 2)?.try_into().ok()?,
+    )))
+}
+
+fn le16(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from(u16::from_le_bytes(
+        data.get(at..at + 2)?.try_into().ok()?,
+    )))
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le32(data: &[u8], at: usize) -> Option<i32> {
+    Some(i32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Width and height of png, gif, bmp and jpeg images
+#[must_use]
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match mime(data) {
+        "image/png" if data.get(12..16) == Some(&b"IHDR"[..]) => {
+            Some((be32(data, 16)?, be32(data, 20)?))
+        }
+        "image/gif" => Some((le16(data, 6)?, le16(data, 8)?)),
+        // Top down bitmaps have a negative height
+        "image/bmp" => Some((
+            le32(data, 18)?.unsigned_abs(),
+            le32(data, 22)?.unsigned_abs(),
+        )),
+        "image/jpeg" => jpeg_dimensions(data),
+        _ => None,
+    }
+}
+
+/// Walks the jpeg segments up to the start of frame, which holds the dimensions
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *data.get(at)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(at + 1)?;
+        match marker {
+            // Padding
+            0xff => at += 1,
+            // Start of frame, except huffman and arithmetic coding tables
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                return Some((be16(data, at + 7)?, be16(data, at + 5)?));
+            }
+            _ => at += 2 + usize::try_from(be16(data, at + 2)?).ok()?,
+        }
+    }
+}
+
+/// One line about what `data` is, e.g. `image/png, 640x480, 12.3 KiB`
+#[must_use]
+pub fn summary(data: &[u8]) -> String {
+    let mut summary = mime(data).to_owned();
+    if let Some((width, height)) = dimensions(data) {
+        let _ = write!(summary, ", {}x{}", width, height);
+    }
+    let _ = write!(summary, ", {}", bytes(data.len() as u64));
+    summary
+}
+
+/// The first `lines` lines of an `xxd` like hexdump of `data`
+#[must_use]
+pub fn hexdump(data: &[u8], lines: usize) -> String {
+    let mut out = String::new();
+    for (index, chunk) in data.chunks(HEXDUMP_WIDTH).take(lines).enumerate() {
+        let _ = write!(out, "{:08x} ", index * HEXDUMP_WIDTH);
+        for column in 0..HEXDUMP_WIDTH {
+            if column % 8 == 0 {
+                out.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => {
+                    let _ = write!(out, "{:02x} ", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        let printable: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    char::from(byte)
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(out, " |{}|", printable);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_mime_dimensions_and_hexdump() {
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend_from_slice(&640_u32.to_be_bytes());
+        png.extend_from_slice(&480_u32.to_be_bytes());
+        assert_eq!(mime(&png), "image/png");
+        assert_eq!(dimensions(&png), Some((640, 480)));
+        assert_eq!(summary(&png), "image/png, 640x480, 24 B");
+
+        let gif = b"GIF89a\x20\x00\x10\x00\x80";
+        assert_eq!(dimensions(gif), Some((32, 16)));
+
+        // SOI, an APP0 segment and a baseline start of frame
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04\x00\x00\xff\xc0\x00\x11\x08\x00\x78\x00\xa0";
+        assert_eq!(mime(jpeg), "image/jpeg");
+        assert_eq!(dimensions(jpeg), Some((160, 120)));
+
+        assert_eq!(mime(b"RIFF\x00\x00\x00\x00WAVE"), "audio/wav");
+        assert_eq!(mime(b"fn main() {}\n"), "text/plain");
+        assert_eq!(mime(b"\x00\x01\x02"), "application/octet-stream");
+        assert_eq!(dimensions(b"\x00\x01\x02"), None);
+
+        assert!(is_text("tabs\tand \x1b[1mcolors\x1b[0m\n".as_bytes()));
+        assert!(!is_text(b"nul\x00byte"));
+        assert!(!is_text(b"\xff\xfe"));
+
+        let dump = hexdump(b"hello, world\x00\x01\x02\x03!", 1);
+        assert_eq!(
+            dump,
+            "00000000  68 65 6c 6c 6f 2c 20 77  6f 72 6c 64 00 01 02 03  |hello, world....|\n"
+        );
+        assert_eq!(hexdump(&[0; 64], 2).lines().count(), 2);
+    }
+}
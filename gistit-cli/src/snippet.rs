@@ -0,0 +1,317 @@
+//! The snippet module
+//!
+//! `gistit send --range` and `--symbol` send part of a file instead of all of it. The file keeps
+//! its name and records the lines it was cut from, shown when previewed. Symbols are found with
+//! the definition keywords of the file's language, not a parser: the first line defining the name
+//! starts the snippet, braces or indentation tell where it ends.
+use regex::Regex;
+
+use gistit_proto::payload::gistit::Snippet;
+
+use crate::{Error, Result};
+
+/// Languages whose blocks end where indentation does
+const INDENTED: &[&str] = &["python", "coffeescript", "nim", "yaml", "haskell", "fsharp"];
+
+/// How many lines after the definition its opening brace may be found on
+const BRACE_LOOKAHEAD: usize = 3;
+
+/// Definition keywords of languages we don't know better
+const ALL_KEYWORDS: &str = r"fn|def|defp|function|func(?:\s*\([^)]*\))?|sub|proc|class|struct|enum|trait|impl(?:<[^>]*>)?|interface|type|module|mod|macro_rules!|object";
+
+/// Words starting a statement, a C like signature starting with one is a call
+const STATEMENTS: &[&str] = &[
+    "return", "if", "else", "while", "for", "switch", "case", "new", "throw", "delete", "sizeof",
+    "await", "yield", "do", "goto",
+];
+
+/// Lines `start` to `end` of `data`, inclusive and 1-based. `end` past the last line stops there
+///
+/// # Errors
+///
+/// Fails if the file has fewer than `start` lines
+pub fn lines(data: &str, (start, end): (usize, usize)) -> Result<(String, Snippet)> {
+    let count = data.lines().count();
+    if start > count {
+        return Err(Error::Argument(
+            "line range starts past the end of the file",
+            "--range",
+        ));
+    }
+    let end = end.min(count);
+    Ok((cut(data, start, end), snippet(start, end, None)))
+}
+
+/// The definition of `name`, found as described in the module docs
+///
+/// # Errors
+///
+/// Fails if nothing defines `name`, or its end can't be told
+pub fn symbol(data: &str, lang: &str, name: &str) -> Result<(String, Snippet)> {
+    let lines: Vec<&str> = data.lines().collect();
+    let definition = Definition::new(lang, name);
+    let start = lines
+        .iter()
+        .position(|line| definition.is_match(line))
+        .ok_or(Error::Argument("symbol not found in the file", "--symbol"))?;
+
+    let end = if INDENTED.contains(&lang) {
+        Some(indented_end(&lines, start))
+    } else if let Some(opening) = lines
+        .iter()
+        .skip(start)
+        .take(BRACE_LOOKAHEAD + 1)
+        .position(|line| line.contains('{'))
+    {
+        braced_end(&lines, start + opening)
+    } else if lines[start].trim_end().ends_with(';') {
+        Some(start)
+    } else {
+        Some(indented_end(&lines, start))
+    }
+    .ok_or(Error::Argument(
+        "couldn't tell where the symbol ends, send a `--range` instead",
+        "--symbol",
+    ))?;
+
+    Ok((
+        cut(data, start + 1, end + 1),
+        snippet(start + 1, end + 1, Some(name)),
+    ))
+}
+
+/// Keywords starting a definition in `lang`, and whether it has C like function signatures
+fn keywords(lang: &str) -> (&'static str, bool) {
+    match lang {
+        "rust" => (
+            r"fn|struct|enum|union|trait|type|mod|macro_rules!|impl(?:<[^>]*>)?|const|static",
+            false,
+        ),
+        "python" | "gdscript" | "renpy" => ("def|class", false),
+        "go" => (r"func(?:\s*\([^)]*\))?|type", false),
+        "javascript" | "jsx" | "typescript" | "tsx" | "flow" => {
+            (r"function\*?|class|interface|type|enum|namespace", false)
+        }
+        "ruby" | "crystal" => ("def|class|module", false),
+        "elixir" => ("def|defp|defmacro|defmodule", false),
+        "perl" => ("sub", false),
+        "php" => ("function|class|interface|trait|enum", false),
+        "kotlin" => ("fun|class|interface|object", false),
+        "scala" => ("def|class|object|trait", false),
+        "swift" => ("func|class|struct|enum|protocol|extension", false),
+        "nim" => ("proc|func|method|iterator|template|macro|type", false),
+        "lua" => ("function", false),
+        "julia" => ("function|struct|module|macro", false),
+        "zig" => ("fn", false),
+        "c" | "cpp" | "objectivec" | "arduino" | "glsl" | "hlsl" | "opencl" | "d" | "clike" => {
+            ("struct|class|enum|union|namespace", true)
+        }
+        "java" | "csharp" | "dart" | "groovy" | "vala" | "processing" => {
+            ("class|interface|enum|struct|record", true)
+        }
+        _ => (ALL_KEYWORDS, true),
+    }
+}
+
+/// Matches a line defining `name`: a definition keyword of the language followed by it, or a C
+/// like function signature in languages having them
+struct Definition {
+    keyword: Regex,
+    signature: Option<Regex>,
+}
+
+impl Definition {
+    fn new(lang: &str, name: &str) -> Self {
+        let name = regex::escape(name);
+        let (keywords, c_like) = keywords(lang);
+        let keyword = Regex::new(&format!(
+            r"^\s*(?:(?:[\w:]+(?:\([^)]*\))?|@\w+)\s+)*?(?:{keywords})\s+\*?{name}\b"
+        ))
+        .expect("definition pattern to compile");
+        // A return type, maybe qualifiers, then the name and its parameters. Declarations and
+        // calls end with `;`
+        let signature = c_like.then(|| {
+            Regex::new(&format!(
+                r"^\s*(?:[\w:<>,\*&\[\]]+\s+)+[\*&]*(?:\w+::)*{name}\s*\([^;]*$"
+            ))
+            .expect("signature pattern to compile")
+        });
+        Self { keyword, signature }
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        let first = line.split_whitespace().next().unwrap_or_default();
+        self.keyword.is_match(line)
+            || self
+                .signature
+                .iter()
+                .any(|signature| signature.is_match(line) && !STATEMENTS.contains(&first))
+    }
+}
+
+/// Index of the line closing the brace opened on line `opening`
+fn braced_end(lines: &[&str], opening: usize) -> Option<usize> {
+    let mut depth = 0_i64;
+    for (index, line) in lines.iter().enumerate().skip(opening) {
+        for c in line.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => (),
+            }
+        }
+        if depth <= 0 {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Index of the last line indented deeper than line `start`, a closing `end` or bracket at the
+/// same indentation included
+fn indented_end(lines: &[&str], start: usize) -> usize {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let base = indent(lines[start]);
+
+    let mut end = start;
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if indent(line) > base {
+            end = index;
+            continue;
+        }
+        let closing = line.trim_start();
+        if indent(line) == base
+            && (closing.starts_with("end") || closing.starts_with(['}', ')', ']']))
+        {
+            end = index;
+        }
+        break;
+    }
+    end
+}
+
+fn cut(data: &str, start: usize, end: usize) -> String {
+    data.split_inclusive('\n')
+        .skip(start - 1)
+        .take(end - start + 1)
+        .collect()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn snippet(start: usize, end: usize, symbol: Option<&str>) -> Snippet {
+    Snippet {
+        start_line: start as u32,
+        end_line: end as u32,
+        symbol: symbol.map(ToOwned::to_owned),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUST: &str = "use std::io;
+
+/// Says hello
+pub fn hello(name: &str) -> String {
+    if name.is_empty() {
+        return \"hello\".to_owned();
+    }
+    format!(\"hello {}\", name)
+}
+
+fn main() {
+    println!(\"{}\", hello(\"world\"));
+}
+";
+
+    const PYTHON: &str = "import sys
+
+class Greeter:
+    def hello(self, name):
+        if not name:
+            return 'hello'
+        return 'hello ' + name
+
+    def bye(self):
+        return 'bye'
+
+print(Greeter().hello(sys.argv[1]))
+";
+
+    const CALLS: &str = "fn greet(name: &str) -> String {
+    if hello(name) {
+        return hello(name)
+    }
+    hello(name)
+}
+";
+
+    const C: &str = "#include <stdio.h>
+
+int main(void) {
+    if (hello(1)) {
+        return hello(2);
+    }
+    return 0;
+}
+
+static const char *hello(int times) {
+    return \"hello\";
+}
+";
+
+    #[test]
+    fn snippet_by_symbol_and_range() {
+        let (data, snippet) = symbol(RUST, "rust", "hello").unwrap();
+        assert!(data.starts_with("pub fn hello"));
+        assert!(data.ends_with("}\n"));
+        assert_eq!((snippet.start_line, snippet.end_line), (4, 9));
+        assert_eq!(snippet.symbol.as_deref(), Some("hello"));
+
+        let (data, snippet) = symbol(PYTHON, "python", "hello").unwrap();
+        assert!(data.trim_start().starts_with("def hello"));
+        assert!(data.trim_end().ends_with("return 'hello ' + name"));
+        assert_eq!((snippet.start_line, snippet.end_line), (4, 7));
+
+        let (_, snippet) = symbol(PYTHON, "python", "Greeter").unwrap();
+        assert_eq!((snippet.start_line, snippet.end_line), (3, 10));
+
+        assert!(symbol(RUST, "rust", "goodbye").is_err());
+        // Calls aren't definitions
+        let (_, snippet) = symbol(RUST, "rust", "main").unwrap();
+        assert_eq!(snippet.start_line, 11);
+
+        let (data, snippet) = symbol(C, "c", "hello").unwrap();
+        assert!(data.starts_with("static const char *hello"));
+        assert_eq!((snippet.start_line, snippet.end_line), (10, 12));
+
+        let (data, snippet) = lines(RUST, (11, 100)).unwrap();
+        assert_eq!(
+            data,
+            "fn main() {\n    println!(\"{}\", hello(\"world\"));\n}\n"
+        );
+        assert_eq!((snippet.start_line, snippet.end_line), (11, 13));
+        assert!(snippet.symbol.is_none());
+        assert!(lines(RUST, (14, 20)).is_err());
+    }
+
+    #[test]
+    fn snippet_symbol_skips_calls() {
+        for lang in ["rust", "go", "python", "text"] {
+            assert!(symbol(CALLS, lang, "hello").is_err(), "{}", lang);
+        }
+        assert!(symbol(
+            "    if (hello(1)) {\n        return hello(2);\n    }\n",
+            "c",
+            "hello"
+        )
+        .is_err());
+        assert!(symbol("    return hello(name,\n        times);\n", "java", "hello").is_err());
+        assert!(symbol("print(Greeter().hello(sys.argv[1]))\n", "python", "hello").is_err());
+        assert!(symbol("x = hello(1)\n", "text", "hello").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! The stats module
+//!
+//! Aggregates the local history into usage statistics rendered as terminal bar charts. Nothing is
+//! collected or sent anywhere, these are computed from `history.json` on demand.
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+
+use crate::dispatch::Dispatch;
+use crate::fmt::colors_enabled;
+use crate::history::{self, Entry, Origin};
+use crate::Result;
+
+const BAR_WIDTH: u64 = 30;
+const TOP_LANGUAGES: usize = 10;
+const MS_PER_DAY: i64 = 86_400_000;
+
+#[derive(Debug, Clone)]
+pub struct Action;
+
+impl Action {
+    pub fn from_args(
+        _args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    stats: Stats,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: u64,
+    pub fetched: u64,
+    /// Gistits with files in each language, most used first
+    pub languages: Vec<(String, u64)>,
+    /// Gistits per `YYYY-MM`, oldest first
+    pub months: Vec<(String, u64)>,
+    /// Average gistit size in bytes
+    pub average_size: u64,
+}
+
+impl Stats {
+    #[must_use]
+    pub fn from_entries(entries: &[Entry]) -> Self {
+        let mut stats = Self::default();
+        let mut languages: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut months: BTreeMap<String, u64> = BTreeMap::new();
+        let mut total_size = 0;
+
+        for entry in entries {
+            match entry.origin {
+                Origin::Sent => stats.sent += 1,
+                Origin::Fetched => stats.fetched += 1,
+            }
+            for language in &entry.languages {
+                *languages.entry(&language.lang).or_default() += 1;
+                total_size += u64::from(language.bytes);
+            }
+            if let Some(month) = month_of(&entry.timestamp) {
+                *months.entry(month).or_default() += 1;
+            }
+        }
+
+        stats.languages = languages
+            .into_iter()
+            .map(|(lang, count)| (lang.to_owned(), count))
+            .collect();
+        // Stable, so ties stay in alphabetical order
+        stats.languages.sort_by(|a, b| b.1.cmp(&a.1));
+        stats.months = months.into_iter().collect();
+        if !entries.is_empty() {
+            stats.average_size = total_size / entries.len() as u64;
+        }
+        stats
+    }
+}
+
+/// `YYYY-MM` of a timestamp in milliseconds since the unix epoch
+fn month_of(timestamp: &str) -> Option<String> {
+    let days = timestamp.parse::<i64>().ok()?.div_euclid(MS_PER_DAY);
+    let (year, month, _) = civil_from_days(days);
+    Some(format!("{:04}-{:02}", year, month))
+}
+
+/// Year, month and day of a day count since the unix epoch, in the proleptic gregorian calendar
+pub const fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Days since the unix epoch of a date in the proleptic gregorian calendar, the inverse of
+/// [`civil_from_days`]
+#[cfg(feature = "p2p")]
+pub const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn bar(value: u64, max: u64) -> String {
+    let width = if max == 0 { 0 } else { value * BAR_WIDTH / max };
+    let fill = if colors_enabled() { "█" } else { "#" };
+    // Anything counted gets at least a sliver
+    fill.repeat(width.max(u64::from(value > 0)) as usize)
+}
+
+fn chart(title: &str, rows: &[(String, u64)]) {
+    println!("{}", style(title).bold());
+    let max = rows.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    for (label, count) in rows {
+        println!(
+            "  {:<width$}  {} {}",
+            label,
+            style(bar(*count, max)).blue(),
+            style(count).dim(),
+            width = label_width
+        );
+    }
+    println!();
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        Ok(Config {
+            stats: Stats::from_entries(&history::load()?),
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        let stats = config.stats;
+        if stats.sent + stats.fetched == 0 {
+            println!("{}", style("no gistits in history").dim());
+            return Ok(());
+        }
+
+        println!(
+            "{} sent, {} fetched, {} bytes on average\n",
+            style(stats.sent).green().bold(),
+            style(stats.fetched).blue().bold(),
+            style(stats.average_size).bold()
+        );
+
+        let languages: Vec<_> = stats.languages.into_iter().take(TOP_LANGUAGES).collect();
+        chart("By language", &languages);
+        chart("By month", &stats.months);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Language;
+
+    fn entry(origin: Origin, timestamp: &str, languages: &[(&str, u32)]) -> Entry {
+        Entry {
+            hash: String::new(),
+            origin,
+            author: String::new(),
+            description: None,
+            timestamp: timestamp.to_owned(),
+            files: Vec::new(),
+            on_server: false,
+            languages: languages
+                .iter()
+                .map(|(lang, bytes)| Language {
+                    lang: (*lang).to_owned(),
+                    bytes: *bytes,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn stats_aggregate_history() {
+        assert_eq!(month_of("0").as_deref(), Some("1970-01"));
+        // 2022-02-28T23:59:59Z and 2022-03-01T00:00:00Z
+        assert_eq!(month_of("1646092799000").as_deref(), Some("2022-02"));
+        assert_eq!(month_of("1646092800000").as_deref(), Some("2022-03"));
+        assert_eq!(month_of("not a timestamp"), None);
+
+        let stats = Stats::from_entries(&[
+            entry(
+                Origin::Sent,
+                "1646092799000",
+                &[("rust", 100), ("toml", 20)],
+            ),
+            entry(Origin::Fetched, "1646092800000", &[("rust", 30)]),
+            entry(Origin::Sent, "1646092800001", &[("python", 0)]),
+        ]);
+        assert_eq!((stats.sent, stats.fetched), (2, 1));
+        assert_eq!(stats.average_size, 50);
+        assert_eq!(
+            stats.languages,
+            vec![
+                ("rust".to_owned(), 2),
+                ("python".to_owned(), 1),
+                ("toml".to_owned(), 1)
+            ]
+        );
+        assert_eq!(
+            stats.months,
+            vec![("2022-02".to_owned(), 1), ("2022-03".to_owned(), 2)]
+        );
+        assert_eq!(bar(0, 10), "");
+    }
+}
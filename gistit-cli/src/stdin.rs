@@ -1,25 +1,78 @@
-use console::{style, Emoji};
-use std::io::{stdin, BufRead};
-
-const READ_LIMIT_BYTES: usize = 50_000;
-
-pub fn read_to_end() -> String {
-    let mut buf = String::new();
-    let mut threshold = READ_LIMIT_BYTES;
-    let stdin = stdin();
-    let mut handle = stdin.lock();
-    println!(
-        "{} Reading stdin {}",
-        Emoji("📝", ">"),
-        style("(Ctrl+D to end)").dim().italic()
-    );
-
-    while let Ok(read) = handle.read_line(&mut buf) {
-        if threshold == 0 || read == 0 {
-            break;
-        }
-        threshold -= read;
+//! The stdin module
+//!
+//! Reads piped or typed input as raw bytes until it ends. Binary input and input over the size
+//! limit are refused with an error, never mangled or silently truncated.
+use console::style;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use gistit_proto::validate;
+
+use crate::fmt::emoji;
+use crate::{Error, Result};
+
+/// Max bytes read unless the `stdin-limit` setting says otherwise, the largest file we can send
+pub const READ_LIMIT_BYTES: usize = *validate::FILE_SIZE.end();
+
+/// Reads stdin to the end, at most `limit` bytes
+///
+/// # Errors
+///
+/// Fails if stdin can't be read, is larger than `limit` or isn't text
+pub async fn read_to_end(limit: usize) -> Result<String> {
+    if atty::is(atty::Stream::Stdin) {
+        println!(
+            "{} Reading stdin {}",
+            emoji("📝", ">"),
+            style("(Ctrl+D to end)").dim().italic()
+        );
+    }
+
+    read_from(tokio::io::stdin(), limit).await
+}
+
+async fn read_from(reader: impl AsyncRead + Unpin, limit: usize) -> Result<String> {
+    let mut buf = Vec::new();
+    // One byte past the limit tells input of exactly `limit` bytes from larger input
+    reader.take(limit as u64 + 1).read_to_end(&mut buf).await?;
+
+    if buf.len() > limit {
+        return Err(Error::Argument(
+            "stdin input is too large, raise the `stdin-limit` setting",
+            "[STDIN]",
+        ));
     }
+    if is_binary(&buf) {
+        return Err(binary());
+    }
+    String::from_utf8(buf).map_err(|_| binary())
+}
 
-    buf
+/// Text never has NUL bytes, binary files almost always do
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0)
+}
+
+const fn binary() -> Error {
+    Error::Argument(
+        "stdin input looks binary, save it to a file and send it with `--binary`",
+        "[STDIN]",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stdin_limit_and_binary() {
+        let text = "fn main() {}\n".repeat(10);
+        assert_eq!(read_from(text.as_bytes(), text.len()).await.unwrap(), text);
+        assert!(read_from(text.as_bytes(), text.len() - 1).await.is_err());
+
+        assert!(read_from(&b"\x7fELF\x02\x01\x01\x00"[..], 100)
+            .await
+            .is_err());
+        assert!(read_from(&[0xff, 0xfe, 0x41][..], 100).await.is_err());
+        assert_eq!(read_from(&b""[..], 100).await.unwrap(), "");
+    }
 }
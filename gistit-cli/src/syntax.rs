@@ -0,0 +1,62 @@
+//! The syntax module
+//!
+//! Gistit languages are named after the web highlighter, bat names its syntaxes its own way.
+//! This bridges the two: guessing the language of text without a file name to go by, stdin for
+//! instance, and telling bat which syntax a language is highlighted with.
+use lazy_static::lazy_static;
+use syntect::parsing::SyntaxSet;
+
+use crate::file::EXTENSION_TO_LANG_MAPPING;
+
+lazy_static! {
+    /// Syntect's default syntaxes, bat ships them too
+    static ref SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
+
+/// The language of `data` going by its first line: a shebang, `<?php`, an editor modeline
+#[cfg(feature = "send")]
+#[must_use]
+pub fn detect(data: &str) -> Option<&'static str> {
+    let first_line = data.lines().next()?;
+    SYNTAXES
+        .find_syntax_by_first_line(first_line)?
+        .file_extensions
+        .iter()
+        .find_map(|ext| EXTENSION_TO_LANG_MAPPING.get(ext.as_str()).copied())
+}
+
+/// What to hand bat as the language of a `lang` file, `None` if it wouldn't know it. Either the
+/// language itself or one of its extensions
+#[cfg(feature = "fetch")]
+#[must_use]
+pub fn token(lang: &str) -> Option<String> {
+    if SYNTAXES.find_syntax_by_token(lang).is_some() {
+        return Some(lang.to_owned());
+    }
+    EXTENSION_TO_LANG_MAPPING
+        .entries()
+        .filter(|(_, mapped)| **mapped == lang)
+        .map(|(ext, _)| *ext)
+        .find(|ext| SYNTAXES.find_syntax_by_extension(ext).is_some())
+        .map(ToOwned::to_owned)
+}
+
+#[cfg(all(test, feature = "send", feature = "fetch"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_detect_and_token() {
+        assert_eq!(
+            detect("#!/usr/bin/env python3\nprint('hi')"),
+            Some("python")
+        );
+        assert_eq!(detect("#!/bin/bash\necho hi"), Some("bash"));
+        assert_eq!(detect("hello there"), None);
+        assert_eq!(detect(""), None);
+
+        assert_eq!(token("rust").as_deref(), Some("rust"));
+        assert!(token("bash").is_some());
+        assert_eq!(token("not a language"), None);
+    }
+}
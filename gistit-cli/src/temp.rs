@@ -0,0 +1,211 @@
+//! The temp module
+//!
+//! Files we write to the OS temp directory, e.g. to preview a gistit with `bat`, are named
+//! `gistit-*` and listed in a registry in the runtime directory next to the process that wrote
+//! them. A process removes its own when it exits, `gistit clean` removes the ones a crash left
+//! behind. Only files of ours in the temp directory are ever removed, whatever the registry says.
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use lazy_static::lazy_static;
+
+use gistit_project::{file, path};
+
+const REGISTRY_FILE: &str = "temp-files";
+
+/// Prefix of every temp file we write
+pub const PREFIX: &str = "gistit-";
+
+/// Temp files of other processes younger than this may still be in use
+pub const STALE_AFTER: Duration = Duration::from_secs(3600);
+
+lazy_static! {
+    static ref CREATED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+fn registry() -> Option<PathBuf> {
+    path::runtime()
+        .ok()
+        .map(|runtime| runtime.join(REGISTRY_FILE))
+}
+
+/// Whether `path` is named like our temp files and sits right in `temp_dir`
+fn is_ours(path: &Path, temp_dir: &Path) -> bool {
+    path.parent() == Some(temp_dir)
+        && path
+            .file_name()
+            .map_or(false, |name| name.to_string_lossy().starts_with(PREFIX))
+}
+
+/// `(pid, path)` of every registered temp file in `temp_dir`, anything else in the registry is
+/// ignored
+fn entries(registry: &Path, temp_dir: &Path) -> Vec<(u32, PathBuf)> {
+    fs::read_to_string(registry)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (pid, path) = line.split_once(' ')?;
+            Some((pid.parse().ok()?, PathBuf::from(path)))
+        })
+        .filter(|(_, path)| is_ours(path, temp_dir))
+        .collect()
+}
+
+fn rewrite(registry: &Path, entries: &[(u32, PathBuf)]) {
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|(pid, path)| format!("{} {}\n", pid, path.display()))
+        .collect();
+    let _ = file::replace(registry, lines.concat().as_bytes());
+}
+
+/// Keeps only the registry entries `keep` agrees with, holding its lock so registrations of
+/// other processes in the meantime aren't lost
+fn retain(keep: impl Fn(u32, &Path) -> bool) {
+    if let Some(registry) = registry() {
+        let _lock = file::Lock::acquire(&registry);
+        let mut entries = entries(&registry, &std::env::temp_dir());
+        entries.retain(|(pid, path)| keep(*pid, path));
+        rewrite(&registry, &entries);
+    }
+}
+
+/// Keeps track of a temp file we just wrote. Failing to register it only means `gistit clean`
+/// has to find it by its name
+pub fn register(path: &Path) {
+    if let Ok(mut created) = CREATED.lock() {
+        created.push(path.to_path_buf());
+    }
+    if let Some(registry) = registry() {
+        let _lock = file::Lock::acquire(&registry);
+        let _ = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(registry)
+            .and_then(|mut file| writeln!(file, "{} {}", std::process::id(), path.display()));
+    }
+}
+
+/// Removes the temp files this process wrote, called on the way out
+pub fn remove_own() {
+    let created = match CREATED.lock() {
+        Ok(mut created) => std::mem::take(&mut *created),
+        Err(_) => return,
+    };
+    if created.is_empty() {
+        return;
+    }
+    for path in &created {
+        let _ = fs::remove_file(path);
+    }
+    let pid = std::process::id();
+    retain(|owner, _| owner != pid);
+}
+
+/// Temp files other processes left behind: registered or named like ours, and untouched for
+/// [`STALE_AFTER`]
+#[must_use]
+pub fn stale() -> Vec<PathBuf> {
+    let pid = std::process::id();
+    let temp_dir = std::env::temp_dir();
+    let registered = registry()
+        .map(|registry| entries(&registry, &temp_dir))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(owner, _)| *owner != pid)
+        .map(|(_, path)| path);
+    let unregistered = fs::read_dir(&temp_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_ours(path, &temp_dir));
+
+    let own = CREATED
+        .lock()
+        .map(|created| created.clone())
+        .unwrap_or_default();
+    registered
+        .chain(unregistered)
+        .filter(|path| !own.contains(path) && is_stale(path, SystemTime::now()))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn is_stale(path: &Path, now: SystemTime) -> bool {
+    fs::symlink_metadata(path)
+        .ok()
+        .filter(fs::Metadata::is_file)
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|modified| now.duration_since(modified).ok())
+        .map_or(false, |age| age >= STALE_AFTER)
+}
+
+/// Drops removed, or otherwise gone, temp files from the registry
+pub fn forget(removed: &[PathBuf]) {
+    retain(|_, path| !removed.iter().any(|removed| removed == path) && path.exists());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn temp_registry_and_staleness() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let registry = tmp.child(REGISTRY_FILE);
+        let file = tmp.child("gistit-abcdefghmain.rs");
+        file.write_str("fn main() {}").unwrap();
+        let gone = tmp.join("gistit-gone");
+
+        registry
+            .write_str(&format!(
+                "1 {}\nnot an entry\n2 {}\n",
+                file.display(),
+                gone.display()
+            ))
+            .unwrap();
+        assert_eq!(
+            entries(&registry, tmp.path()),
+            vec![(1, file.to_path_buf()), (2, gone)]
+        );
+
+        rewrite(&registry, &[(3, file.to_path_buf())]);
+        assert_eq!(
+            entries(&registry, tmp.path()),
+            vec![(3, file.to_path_buf())]
+        );
+
+        let now = SystemTime::now();
+        assert!(!is_stale(&file, now));
+        assert!(is_stale(&file, now + STALE_AFTER));
+        assert!(!is_stale(tmp.path(), now + STALE_AFTER));
+        assert!(!is_stale(Path::new("/gone"), now + STALE_AFTER));
+    }
+
+    #[test]
+    fn temp_registry_ignores_files_not_ours() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let registry = tmp.child(REGISTRY_FILE);
+        let ours = tmp.join("gistit-abcdefghmain.rs");
+        let lines = [
+            format!("1 {}", ours.display()),
+            format!("1 {}", tmp.join("main.rs").display()),
+            format!("1 {}", tmp.join("nested").join("gistit-main.rs").display()),
+            format!("1 {}", tmp.join("..").join("gistit-main.rs").display()),
+            "1 /etc/passwd".to_owned(),
+            "1 gistit-relative".to_owned(),
+        ];
+        registry.write_str(&lines.join("\n")).unwrap();
+
+        assert_eq!(entries(&registry, tmp.path()), vec![(1, ours.clone())]);
+        assert!(is_ours(&ours, tmp.path()));
+        assert!(!is_ours(tmp.path(), tmp.path()));
+    }
+}
@@ -0,0 +1,175 @@
+//! The themes module
+//!
+//! Lists the colorschemes gistits can be previewed with, and renders a sample snippet in each of
+//! them so one can be picked for `--colorscheme` or the `colorscheme` setting without trial and
+//! error. In a terminal the gallery shows one colorscheme at a time.
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::{style, Key, Term};
+
+use gistit_project::settings::Settings;
+
+use crate::dispatch::Dispatch;
+use crate::fmt::colors_enabled;
+use crate::param::{check, SUPPORTED_COLORSCHEMES};
+use crate::{progress, Result};
+
+const SAMPLE_NAME: &str = "sample.rs";
+
+/// Short, but touches most scopes a colorscheme styles
+const SAMPLE: &str = r#"use std::collections::HashMap;
+
+/// Counts how many times each word shows up
+fn count_words(text: &str) -> HashMap<&str, usize> {
+    let mut counts = HashMap::new();
+    for word in text.split_whitespace() {
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn main() {
+    // Share it with `gistit main.rs`
+    let counts = count_words("the quick brown fox jumps over the lazy dog");
+    println!("{:?} {}", counts.get("the"), 0x2a_u8 as f32 * 1.5);
+}
+"#;
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub preview: bool,
+    pub themes: Vec<&'static str>,
+    pub current: Option<&'static str>,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+        settings: &'static Settings,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            preview: args.is_present("preview"),
+            themes: args.values_of("THEME").into_iter().flatten().collect(),
+            current: settings.colorscheme.as_deref(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    themes: Vec<&'static str>,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        let themes = if self.themes.is_empty() {
+            SUPPORTED_COLORSCHEMES.to_vec()
+        } else {
+            self.themes
+                .iter()
+                .copied()
+                .map(check::colorscheme)
+                .collect::<Result<_>>()?
+        };
+        Ok(Config { themes })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        if !self.preview {
+            list(&config.themes, self.current);
+            return Ok(());
+        }
+
+        let term = Term::stdout();
+        let paged = term.is_term() && progress::mode() == progress::Mode::Human;
+        let total = config.themes.len();
+        for (index, theme) in config.themes.iter().enumerate() {
+            render(theme)?;
+
+            if paged && index + 1 < total {
+                term.write_line(&format!(
+                    "{}",
+                    style(format!(
+                        "{}/{}  any key for the next colorscheme, q to quit",
+                        index + 1,
+                        total
+                    ))
+                    .dim()
+                ))?;
+                let key = term.read_key()?;
+                term.clear_last_lines(1)?;
+                if matches!(key, Key::Char('q') | Key::Escape) {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints colorscheme names, marking the one from the settings
+fn list(themes: &[&str], current: Option<&str>) {
+    println!("{}", style("Supported colorschemes: \n").green().bold());
+    for theme in themes {
+        if Some(*theme) == current {
+            println!("    {} {}", theme, style("(current)").dim());
+        } else {
+            println!("    {}", theme);
+        }
+    }
+    println!(
+        "\n    Preview them with '{}'\n",
+        style("gistit themes --preview").bold()
+    );
+}
+
+fn render(theme: &str) -> Result<()> {
+    let input = bat::Input::from_bytes(SAMPLE.as_bytes())
+        .name(SAMPLE_NAME)
+        .title(style(theme).green().bold().to_string());
+
+    let mut printer = bat::PrettyPrinter::new();
+    printer
+        .header(true)
+        .grid(true)
+        .input(input)
+        .line_numbers(true)
+        .theme(theme)
+        .use_italics(true)
+        .colored_output(colors_enabled())
+        .paging_mode(bat::PagingMode::Never);
+    printer.print()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(themes: Vec<&'static str>) -> Action {
+        Action {
+            preview: false,
+            themes,
+            current: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn themes_picks_and_checks_colorschemes() {
+        let config = action(Vec::new()).prepare().await.unwrap();
+        assert_eq!(config.themes, SUPPORTED_COLORSCHEMES.to_vec());
+
+        let picked = vec![SUPPORTED_COLORSCHEMES[1], SUPPORTED_COLORSCHEMES[0]];
+        let config = action(picked.clone()).prepare().await.unwrap();
+        assert_eq!(config.themes, picked);
+
+        assert!(action(vec![SUPPORTED_COLORSCHEMES[0], "not-a-theme"])
+            .prepare()
+            .await
+            .is_err());
+    }
+}
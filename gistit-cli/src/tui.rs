@@ -0,0 +1,629 @@
+//! The tui module
+//!
+//! `gistit tui` is a full screen interface over the same pieces the other commands use: the
+//! history, the fetch chain through the cache, the peers and the server, and the gistit node
+//! status. Gistits can be searched, fetched, previewed, saved and have their hash copied without
+//! leaving it. Only built with the `tui` feature.
+use std::io::{self, Stdout};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::Term;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use gistit_project::path;
+use gistit_proto::ipc::{self, Instruction};
+use gistit_proto::{Gistit, GistitHash};
+
+use crate::clipboard;
+use crate::dispatch::Dispatch;
+use crate::fetch;
+use crate::file::Overwrite;
+use crate::history::{self, Entry, Origin};
+use crate::param::check;
+use crate::sniff;
+use crate::{progress, Error, Result};
+
+/// How long we wait for a key before drawing again
+const TICK: Duration = Duration::from_millis(250);
+
+/// How long the gistit node gets to answer a status request
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Lines the preview scrolls by
+const SCROLL_STEP: u16 = 10;
+
+/// Whether the terminal is in raw mode on the alternate screen, see [`restore`]
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+const HELP: &str =
+    "↑↓ move  enter preview  pgup/pgdn scroll  s save  c copy hash  / search  f fetch  r refresh  q quit";
+
+#[derive(Debug, Clone)]
+pub struct Action;
+
+impl Action {
+    pub fn from_args(
+        _args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    entries: Vec<Entry>,
+    runtime_path: PathBuf,
+    data_path: PathBuf,
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        if !Term::stdout().is_term() {
+            return Err(Error::Argument("gistit tui needs a terminal", "tui"));
+        }
+
+        Ok(Config {
+            entries: newest_first(history::load()?),
+            runtime_path: path::runtime()?,
+            data_path: path::data()?,
+        })
+    }
+
+    async fn dispatch(&self, mut config: Self::InnerData) -> Result<()> {
+        // Progress would be drawn over the interface, warnings are shown in the status line
+        progress::set_mode(progress::Mode::Quiet);
+        progress::capture_warnings();
+
+        let mut app = App::new(std::mem::take(&mut config.entries));
+        app.node = node_status(&config.runtime_path).await;
+
+        let mut screen = Screen::enter()?;
+        loop {
+            screen.terminal.draw(|frame| draw(frame, &mut app))?;
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+
+            let job = app.on_key(key);
+            if app.quit {
+                return Ok(());
+            }
+            if let Some(job) = job {
+                app.message = job.to_string();
+                screen.terminal.draw(|frame| draw(frame, &mut app))?;
+
+                if let Err(err) = app.run(&job, &config).await {
+                    app.message = format!("error: {}", err);
+                } else if let Some(warning) = progress::captured_warnings().pop() {
+                    app.message = format!("warning: {}", warning);
+                }
+            }
+        }
+    }
+}
+
+/// Leaves raw mode and the alternate screen if the interface is up. Exiting without unwinding,
+/// e.g. on a signal, skips [`Screen`]'s drop and calls this instead
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, crossterm::cursor::Show);
+    }
+}
+
+/// Raw mode on the alternate screen, restored however the interface exits
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        Ok(Self {
+            terminal: Terminal::new(CrosstermBackend::new(stdout))?,
+        })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Browse,
+    Search,
+    Fetch,
+}
+
+/// Work that waits on the network or the disk, run once the screen says so
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Job {
+    Preview(GistitHash),
+    Save(GistitHash),
+    Fetch(GistitHash),
+    Refresh,
+}
+
+impl std::fmt::Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Preview(hash) | Self::Fetch(hash) => write!(f, "fetching {}...", hash.short()),
+            Self::Save(hash) => write!(f, "saving {}...", hash.short()),
+            Self::Refresh => write!(f, "refreshing..."),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct App {
+    /// History, newest first
+    entries: Vec<Entry>,
+    /// Entries matching the search
+    shown: Vec<usize>,
+    list: ListState,
+
+    mode: Mode,
+    search: String,
+    hash: String,
+
+    preview: Option<Gistit>,
+    scroll: u16,
+
+    node: Option<ipc::instruction::StatusResponse>,
+    message: String,
+    quit: bool,
+}
+
+impl App {
+    fn new(entries: Vec<Entry>) -> Self {
+        let mut app = Self {
+            entries,
+            shown: Vec::new(),
+            list: ListState::default(),
+            mode: Mode::Browse,
+            search: String::new(),
+            hash: String::new(),
+            preview: None,
+            scroll: 0,
+            node: None,
+            message: String::new(),
+            quit: false,
+        };
+        app.filter();
+        app
+    }
+
+    /// Shows the entries matching the search, selecting the first one
+    fn filter(&mut self) {
+        self.shown = (0..self.entries.len())
+            .filter(|index| self.entries[*index].matches(&self.search))
+            .collect();
+        let first = if self.shown.is_empty() { None } else { Some(0) };
+        self.list.select(first);
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        let index = self.shown.get(self.list.selected()?)?;
+        self.entries.get(*index)
+    }
+
+    /// Hash of the selected entry, unless the history holds a malformed one
+    fn selected_hash(&self) -> Option<GistitHash> {
+        GistitHash::parse(&self.selected()?.hash).ok()
+    }
+
+    fn select(&mut self, hash: &str) {
+        self.list.select(
+            self.shown
+                .iter()
+                .position(|index| self.entries[*index].hash == hash),
+        );
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        if self.shown.is_empty() {
+            return;
+        }
+        let last = self.shown.len() - 1;
+        let current = self.list.selected().unwrap_or(0);
+        let next = if delta < 0 {
+            current.saturating_sub(delta.unsigned_abs())
+        } else {
+            current.saturating_add(delta.unsigned_abs()).min(last)
+        };
+        self.list.select(Some(next));
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Option<Job> {
+        self.message.clear();
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return None;
+        }
+
+        match (self.mode, key.code) {
+            (_, KeyCode::Down) | (Mode::Browse, KeyCode::Char('j')) => self.move_by(1),
+            (_, KeyCode::Up) | (Mode::Browse, KeyCode::Char('k')) => self.move_by(-1),
+            (_, KeyCode::PageDown) => self.scroll = self.scroll.saturating_add(SCROLL_STEP),
+            (_, KeyCode::PageUp) => self.scroll = self.scroll.saturating_sub(SCROLL_STEP),
+
+            (Mode::Browse, KeyCode::Char('q') | KeyCode::Esc) => self.quit = true,
+            (Mode::Browse, KeyCode::Enter | KeyCode::Char('p')) => {
+                return self.selected_hash().map(Job::Preview);
+            }
+            (Mode::Browse, KeyCode::Char('s')) => {
+                return self.selected_hash().map(Job::Save);
+            }
+            (Mode::Browse, KeyCode::Char('c')) => self.copy_hash(),
+            (Mode::Browse, KeyCode::Char('/')) => self.mode = Mode::Search,
+            (Mode::Browse, KeyCode::Char('f')) => {
+                self.hash.clear();
+                self.mode = Mode::Fetch;
+            }
+            (Mode::Browse, KeyCode::Char('r')) => return Some(Job::Refresh),
+
+            (Mode::Search, KeyCode::Char(c)) => {
+                self.search.push(c);
+                self.filter();
+            }
+            (Mode::Search, KeyCode::Backspace) => {
+                self.search.pop();
+                self.filter();
+            }
+            (Mode::Search, KeyCode::Enter) | (Mode::Fetch, KeyCode::Esc) => {
+                self.mode = Mode::Browse;
+            }
+            (Mode::Search, KeyCode::Esc) => {
+                self.search.clear();
+                self.filter();
+                self.mode = Mode::Browse;
+            }
+
+            (Mode::Fetch, KeyCode::Char(c)) => self.hash.push(c),
+            (Mode::Fetch, KeyCode::Backspace) => {
+                self.hash.pop();
+            }
+            (Mode::Fetch, KeyCode::Enter) => {
+                self.mode = Mode::Browse;
+                let hash = self.hash.trim().to_owned();
+                match check::hash(&hash) {
+                    Ok(hash) => return Some(Job::Fetch(hash)),
+                    Err(err) => self.message = format!("error: {}", err),
+                }
+            }
+            _ => (),
+        }
+        None
+    }
+
+    fn copy_hash(&mut self) {
+        let hash = match self.selected() {
+            Some(entry) => entry.hash.clone(),
+            None => return,
+        };
+        self.message = match clipboard::copy(&hash, None) {
+            Ok(backend) => format!("copied {} via {}", short(&hash), backend),
+            Err(err) => format!("error: {}", err),
+        };
+    }
+
+    async fn run(&mut self, job: &Job, config: &Config) -> Result<()> {
+        match job {
+            Job::Preview(hash) => {
+                self.preview = Some(fetch::fetch(hash, &config.runtime_path, None, None).await?);
+                self.scroll = 0;
+                self.message.clear();
+            }
+            Job::Save(hash) => {
+                let gistit = fetch::fetch(hash, &config.runtime_path, None, None).await?;
+                let saved = fetch::save_to(&gistit, &config.data_path, None, Overwrite::Backup)?;
+                self.message = saved
+                    .iter()
+                    .map(|(file_path, backup)| {
+                        backup.as_ref().map_or_else(
+                            || format!("saved at `{}`", file_path.display()),
+                            |backup| {
+                                format!(
+                                    "saved at `{}`, the old file moved to `{}`",
+                                    file_path.display(),
+                                    backup.display()
+                                )
+                            },
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+            }
+            Job::Fetch(hash) => {
+                let gistit = fetch::fetch(hash, &config.runtime_path, None, None).await?;
+                self.reload()?;
+                self.search.clear();
+                self.filter();
+                self.select(hash);
+                self.preview = Some(gistit);
+                self.scroll = 0;
+                self.message.clear();
+            }
+            Job::Refresh => {
+                self.reload()?;
+                self.node = node_status(&config.runtime_path).await;
+                self.message.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the history again, keeping the selection if it's still shown
+    fn reload(&mut self) -> Result<()> {
+        let selected = self.selected().map(|entry| entry.hash.clone());
+        self.entries = newest_first(history::load()?);
+        self.filter();
+        if let Some(hash) = selected {
+            self.select(&hash);
+        }
+        Ok(())
+    }
+}
+
+fn newest_first(mut entries: Vec<Entry>) -> Vec<Entry> {
+    entries.reverse();
+    entries
+}
+
+fn short(hash: &str) -> &str {
+    hash.get(..8).unwrap_or(hash)
+}
+
+/// The gistit node status, `None` if it isn't running or doesn't answer
+async fn node_status(runtime_path: &Path) -> Option<ipc::instruction::StatusResponse> {
+    let mut bridge = gistit_ipc::client(runtime_path).ok()?;
+    if !bridge.alive() {
+        return None;
+    }
+    bridge.connect().await.ok()?;
+    bridge.send(Instruction::request_status()).await.ok()?;
+
+    match tokio::time::timeout(STATUS_TIMEOUT, bridge.recv())
+        .await
+        .ok()?
+        .ok()?
+        .expect_response()
+        .ok()?
+    {
+        ipc::instruction::Kind::StatusResponse(status) => Some(status),
+        _ => None,
+    }
+}
+
+fn draw<B: Backend>(frame: &mut Frame<'_, B>, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .split(rows[0]);
+    let right = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(5), Constraint::Min(3)])
+        .split(columns[1]);
+
+    draw_history(frame, app, columns[0]);
+    draw_node(frame, app, right[0]);
+    draw_preview(frame, app, right[1]);
+    draw_input(frame, app, rows[1]);
+
+    let footer = if app.message.is_empty() {
+        Span::styled(HELP, Style::default().add_modifier(Modifier::DIM))
+    } else {
+        Span::styled(app.message.as_str(), Style::default().fg(Color::Yellow))
+    };
+    frame.render_widget(Paragraph::new(footer), rows[2]);
+}
+
+fn draw_history<B: Backend>(frame: &mut Frame<'_, B>, app: &mut App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .shown
+        .iter()
+        .map(|index| {
+            let entry = &app.entries[*index];
+            let origin = match entry.origin {
+                Origin::Sent => Span::styled("sent    ", Style::default().fg(Color::Green)),
+                Origin::Fetched => Span::styled("fetched ", Style::default().fg(Color::Blue)),
+            };
+            ListItem::new(Spans::from(vec![
+                Span::styled(
+                    format!("{} ", short(&entry.hash)),
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+                origin,
+                Span::raw(entry.files.join(", ")),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" History ({}) ", app.shown.len())),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, area, &mut app.list);
+}
+
+fn draw_node<B: Backend>(frame: &mut Frame<'_, B>, app: &App, area: Rect) {
+    let lines = app.node.as_ref().map_or_else(
+        || {
+            vec![Spans::from(Span::styled(
+                "not running, start it with `gistit node --start`",
+                Style::default().add_modifier(Modifier::DIM),
+            ))]
+        },
+        |status| {
+            vec![
+                Spans::from(vec![
+                    Span::raw("peer id "),
+                    Span::styled(
+                        status.peer_id.as_str(),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                ]),
+                Spans::from(format!(
+                    "peers {}  hosting {}  reseeding {}",
+                    status.peer_count, status.hosting, status.reseeding
+                )),
+                Spans::from(format!(
+                    "bootstrap peers {}  relays {}",
+                    status.bootstrap_peers.len(),
+                    status.relays
+                )),
+            ]
+        },
+    );
+
+    let node = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Node "));
+    frame.render_widget(node, area);
+}
+
+fn draw_preview<B: Backend>(frame: &mut Frame<'_, B>, app: &App, area: Rect) {
+    // NOTE: Currently we support one file
+    let inner = app
+        .preview
+        .as_ref()
+        .and_then(|gistit| Some((gistit, gistit.inner.first()?)));
+
+    let (title, body) = match inner {
+        Some((gistit, inner)) => {
+            let mut title = format!(" {} | {} ", inner.name, gistit.author);
+            if let Some(ref description) = gistit.description {
+                title.push_str(&format!("| {} ", description));
+            }
+            let body = match fetch::unprintable(gistit, inner) {
+                Ok(Some(data)) => format!(
+                    "{}. Save it with `s`\n\n{}",
+                    sniff::summary(&data),
+                    sniff::hexdump(&data, sniff::HEXDUMP_LINES)
+                ),
+                Ok(None) => inner.data.replace('\t', "    "),
+                Err(err) => err.to_string(),
+            };
+            (title, body)
+        }
+        None => (
+            " Preview ".to_owned(),
+            "select a gistit and press enter".to_owned(),
+        ),
+    };
+
+    let preview = Paragraph::new(body)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .scroll((app.scroll, 0));
+    frame.render_widget(preview, area);
+}
+
+fn draw_input<B: Backend>(frame: &mut Frame<'_, B>, app: &App, area: Rect) {
+    let (title, text) = match app.mode {
+        Mode::Browse if app.search.is_empty() => (" Search (/) or fetch (f) ", ""),
+        Mode::Browse | Mode::Search => (" Search ", app.search.as_str()),
+        Mode::Fetch => (" Fetch hash ", app.hash.as_str()),
+    };
+
+    let input = Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_widget(input, area);
+
+    if app.mode != Mode::Browse {
+        #[allow(clippy::cast_possible_truncation)]
+        let width = text.chars().count() as u16;
+        frame.set_cursor(
+            area.x + 1 + width.min(area.width.saturating_sub(3)),
+            area.y + 1,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: &str, file: &str) -> Entry {
+        Entry {
+            hash: hash.to_owned(),
+            origin: Origin::Sent,
+            author: "someone".to_owned(),
+            description: None,
+            timestamp: String::new(),
+            files: vec![file.to_owned()],
+            languages: Vec::new(),
+            on_server: false,
+        }
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Option<Job> {
+        app.on_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn tui_search_and_keys() {
+        let mut app = App::new(vec![
+            entry("a".repeat(64).as_str(), "main.rs"),
+            entry("b".repeat(64).as_str(), "index.js"),
+            entry("c".repeat(64).as_str(), "lib.rs"),
+        ]);
+        assert_eq!(app.list.selected(), Some(0));
+
+        press(&mut app, KeyCode::Char('/'));
+        for c in ".rs".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        assert_eq!(app.shown, vec![0, 2]);
+        press(&mut app, KeyCode::Enter);
+
+        press(&mut app, KeyCode::Char('j'));
+        press(&mut app, KeyCode::Char('j'));
+        assert_eq!(
+            press(&mut app, KeyCode::Enter),
+            GistitHash::parse(&"c".repeat(64)).ok().map(Job::Preview)
+        );
+
+        press(&mut app, KeyCode::Char('f'));
+        press(&mut app, KeyCode::Char('x'));
+        assert_eq!(press(&mut app, KeyCode::Enter), None);
+        assert!(app.message.starts_with("error"));
+
+        press(&mut app, KeyCode::Char('q'));
+        assert!(app.quit);
+    }
+}
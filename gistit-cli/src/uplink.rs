@@ -0,0 +1,199 @@
+//! The uplink module
+//!
+//! Alternative paste services a gistit can be sent to with `--to <service>`, instead of the
+//! gistit server. Each service only gets the first file and hands back the url it was posted at.
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+use gistit_project::env;
+use gistit_proto::Gistit;
+
+use crate::http;
+use crate::{Error, Result};
+
+#[async_trait]
+pub trait Uplink: Debug + Send + Sync {
+    /// Service name, as given to `--to`
+    fn name(&self) -> &'static str;
+
+    /// Posts the gistit, returning where it can be found
+    async fn upload(&self, gistit: &Gistit) -> Result<Url>;
+}
+
+/// Selects the uplink for a service name, `None` if it isn't a known service
+#[must_use]
+pub fn from_name(name: &str) -> Option<Box<dyn Uplink>> {
+    match name {
+        "pastebin" => Some(Box::new(Pastebin)),
+        "hastebin" => Some(Box::new(Hastebin)),
+        "termbin" => Some(Box::new(Termbin)),
+        _ => None,
+    }
+}
+
+fn first_file(gistit: &Gistit) -> Result<&str> {
+    let inner = gistit
+        .inner
+        .first()
+        .ok_or(Error::Argument("missing file input", "[FILE]/[STDIN]"))?;
+
+    if inner.is_binary {
+        return Err(Error::Argument(
+            "binary files can't be sent to paste services",
+            "--to",
+        ));
+    }
+    Ok(&inner.data)
+}
+
+#[derive(Debug)]
+pub struct Pastebin;
+
+const PASTEBIN_API: &str = "https://pastebin.com/api/api_post.php";
+
+#[async_trait]
+impl Uplink for Pastebin {
+    fn name(&self) -> &'static str {
+        "pastebin"
+    }
+
+    async fn upload(&self, gistit: &Gistit) -> Result<Url> {
+        let key = std::env::var(env::GISTIT_PASTEBIN_KEY).map_err(|_| {
+            Error::Uplink(format!(
+                "pastebin needs an api key, get one at https://pastebin.com/doc_api and set `{}`",
+                env::GISTIT_PASTEBIN_KEY
+            ))
+        })?;
+        let name = gistit.inner.first().map_or("", |inner| inner.name.as_str());
+
+        let client = http::Client::new();
+        let response = client
+            .send(client.post(PASTEBIN_API).form(&[
+                ("api_dev_key", key.as_str()),
+                ("api_option", "paste"),
+                ("api_paste_code", first_file(gistit)?),
+                ("api_paste_name", name),
+                // Unlisted
+                ("api_paste_private", "1"),
+            ]))
+            .await?;
+
+        let status = response.status();
+        let body = response.text().await?;
+        match status {
+            // Errors are reported with a 200 too, the body is the url on success
+            StatusCode::OK => Url::parse(body.trim())
+                .map_err(|_| Error::Uplink(format!("pastebin: {}", body.trim()))),
+            _ => Err(Error::Uplink(format!("pastebin: {}", body.trim()))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Hastebin;
+
+const HASTEBIN_URL: &str = "https://hastebin.com";
+
+#[derive(Deserialize)]
+struct HastebinDocument {
+    key: String,
+}
+
+#[async_trait]
+impl Uplink for Hastebin {
+    fn name(&self) -> &'static str {
+        "hastebin"
+    }
+
+    async fn upload(&self, gistit: &Gistit) -> Result<Url> {
+        let base = Url::parse(HASTEBIN_URL)?;
+        let client = http::Client::new();
+        let response = client
+            .send(
+                client
+                    .post(base.join("documents")?)
+                    .body(first_file(gistit)?.to_owned()),
+            )
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let document: HastebinDocument = response.json().await?;
+                Ok(base.join(&document.key)?)
+            }
+            status => Err(Error::Uplink(format!("hastebin responded with {}", status))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Termbin;
+
+const TERMBIN_ADDRESS: &str = "termbin.com:9999";
+
+#[async_trait]
+impl Uplink for Termbin {
+    fn name(&self) -> &'static str {
+        "termbin"
+    }
+
+    /// Termbin is plain netcat, the url is sent back once we close our side
+    async fn upload(&self, gistit: &Gistit) -> Result<Url> {
+        let mut stream = TcpStream::connect(TERMBIN_ADDRESS).await?;
+        stream.write_all(first_file(gistit)?.as_bytes()).await?;
+        stream.shutdown().await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+
+        // Null terminated
+        let response = response.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        Url::parse(response).map_err(|_| Error::Uplink(format!("termbin: {}", response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gistit(data: &str, is_binary: bool) -> Gistit {
+        let mut inner = Gistit::new_inner(
+            "main.rs".to_owned(),
+            "rust".to_owned(),
+            data.len() as u32,
+            data.to_owned(),
+        );
+        inner.is_binary = is_binary;
+        Gistit {
+            inner: vec![inner],
+            ..Gistit::default()
+        }
+    }
+
+    #[test]
+    fn uplink_from_name() {
+        for name in ["pastebin", "hastebin", "termbin"] {
+            assert_eq!(from_name(name).unwrap().name(), name);
+        }
+        assert!(from_name("gist").is_none());
+    }
+
+    #[test]
+    fn uplink_only_text_files() {
+        assert_eq!(
+            first_file(&gistit("fn main() {}", false)).unwrap(),
+            "fn main() {}"
+        );
+        assert!(matches!(
+            first_file(&gistit("AAEC", true)),
+            Err(Error::Argument(_, "--to"))
+        ));
+        assert!(first_file(&Gistit::default()).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! The web module
+//!
+//! Serves the local history and cached gistits to the browser, for browsing snippets without the
+//! hosted web app. It only binds to localhost and answers one request at a time, each within
+//! [`REQUEST_TIMEOUT`], nothing leaves the machine. Requests naming another host are refused, so a
+//! page whose domain was rebound to localhost can't read them. Gistits that were never fetched
+//! here aren't cached, so only their history entry is shown.
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::ArgMatches;
+use console::style;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use gistit_proto::Gistit;
+
+use crate::dispatch::Dispatch;
+use crate::history::{self, Origin};
+use crate::param::check;
+use crate::{cache, finish, progress, warnln, Error, Result};
+
+const THEME: &str = "base16-ocean.dark";
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// How long a client may take to send its request and read the answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const STYLE: &str = "body { font-family: sans-serif; background: #1c1f26; color: #c0c5ce; \
+margin: 2em auto; max-width: 60em; } a { color: #8fa1b3; } pre { padding: 1em; overflow: auto; } \
+table { border-collapse: collapse; width: 100%; } td { padding: 0.3em 0.6em; } \
+.dim { color: #65737e; }";
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub port: &'static str,
+}
+
+impl Action {
+    pub fn from_args(
+        args: &'static ArgMatches,
+    ) -> Result<Box<dyn Dispatch<InnerData = Config> + Send + Sync + 'static>> {
+        Ok(Box::new(Self {
+            port: args
+                .value_of("port")
+                .ok_or(Error::Argument("missing argument", "--port"))?,
+        }))
+    }
+}
+
+pub struct Config {
+    address: SocketAddr,
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("address", &self.address)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl Dispatch for Action {
+    type InnerData = Config;
+
+    async fn prepare(&self) -> Result<Self::InnerData> {
+        progress!("Preparing");
+        let port = check::port_number(self.port)?;
+
+        let theme = ThemeSet::load_defaults()
+            .themes
+            .remove(THEME)
+            .expect("default theme to exist");
+
+        Ok(Config {
+            address: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port),
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme,
+        })
+    }
+
+    async fn dispatch(&self, config: Self::InnerData) -> Result<()> {
+        let listener = TcpListener::bind(config.address).await?;
+        finish!(format!(
+            "\n    serving at: '{}' {}\n\n",
+            style(format!("http://{}", listener.local_addr()?)).bold(),
+            style("(ctrl-c to stop)").italic().dim()
+        ));
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let served = tokio::time::timeout(REQUEST_TIMEOUT, serve(stream, &config))
+                .await
+                .unwrap_or(Err(Error::Timeout("answering a request")));
+            if let Err(err) = served {
+                warnln!("failed to answer request: {}", err);
+            }
+        }
+    }
+}
+
+/// Reads the request head, returns its method and path, empty if missing
+///
+/// # Errors
+///
+/// Fails if the connection can't be read from
+#[cfg(any(feature = "send", feature = "fetch"))]
+pub async fn request_line(stream: &mut TcpStream) -> Result<(String, String)> {
+    let head = read_head(stream).await?;
+    Ok(method_and_path(&head))
+}
+
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0_u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+fn method_and_path(head: &str) -> (String, String) {
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    (
+        request_line.next().unwrap_or("").to_owned(),
+        request_line.next().unwrap_or("").to_owned(),
+    )
+}
+
+/// Value of the `Host` header, if any
+fn host(head: &str) -> Option<&str> {
+    head.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("host")
+            .then(|| value.trim())
+    })
+}
+
+/// Whether a `Host` header names this machine, with or without a port
+fn is_local_host(host: &str) -> bool {
+    let name = if host.starts_with('[') {
+        host.split_once(']').map_or(host, |(name, _)| &name[1..])
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+    name.eq_ignore_ascii_case("localhost") || name == "127.0.0.1" || name == "::1"
+}
+
+async fn serve(mut stream: TcpStream, config: &Config) -> Result<()> {
+    let head = read_head(&mut stream).await?;
+    let (method, path) = method_and_path(&head);
+    let (status, body) = match (method.as_str(), path.as_str()) {
+        _ if !matches!(host(&head), Some(host) if is_local_host(host)) => (
+            "403 Forbidden",
+            page("Forbidden", "<p>Open it from localhost</p>"),
+        ),
+        ("GET", "/") => ("200 OK", index()),
+        ("GET", path) => path
+            .strip_prefix("/h/")
+            // Hashes are looked up as cache file names
+            .filter(|hash| is_hash(hash))
+            .map_or_else(
+                || ("404 Not Found", page("Not found", "<p>Nothing here</p>")),
+                |hash| ("200 OK", gistit_page(hash, config)),
+            ),
+        _ => ("405 Method Not Allowed", page("Not allowed", "")),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn is_hash(hash: &str) -> bool {
+    check::hash(hash).is_ok()
+}
+
+/// Escapes text to be placed inside html
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{} - gistit</title>\
+         <style>{}</style></head><body><h2><a href=\"/\">gistit</a> / {}</h2>{}</body></html>",
+        escape(title),
+        STYLE,
+        escape(title),
+        body
+    )
+}
+
+fn index() -> String {
+    let entries = match history::load() {
+        Ok(entries) => entries,
+        Err(err) => {
+            return page(
+                "History",
+                &format!(
+                    "<p>failed to load history: {}</p>",
+                    escape(&err.to_string())
+                ),
+            )
+        }
+    };
+    if entries.is_empty() {
+        return page("History", "<p class=\"dim\">no gistits in history</p>");
+    }
+
+    let mut rows = String::new();
+    for entry in entries.iter().rev() {
+        let origin = match entry.origin {
+            Origin::Sent => "sent",
+            Origin::Fetched => "fetched",
+        };
+        let _ = write!(
+            rows,
+            "<tr><td><a href=\"/h/{hash}\">{short}</a></td><td class=\"dim\">{}</td><td>{}</td>\
+             <td class=\"dim\">{}</td><td>{}</td></tr>",
+            origin,
+            escape(&entry.files.join(", ")),
+            escape(&entry.composition()),
+            escape(entry.description.as_deref().unwrap_or("")),
+            hash = escape(&entry.hash),
+            short = escape(entry.hash.get(..8).unwrap_or(&entry.hash)),
+        );
+    }
+
+    page("History", &format!("<table>{}</table>", rows))
+}
+
+fn gistit_page(hash: &str, config: &Config) -> String {
+    let gistit = match cache::load(hash) {
+        Some(gistit) => gistit,
+        None => {
+            return page(
+                hash,
+                &format!(
+                    "<p>not cached here, fetch it with <code>gistit fetch {}</code></p>",
+                    escape(hash)
+                ),
+            )
+        }
+    };
+
+    page(hash, &render(&gistit, config))
+}
+
+fn render(gistit: &Gistit, config: &Config) -> String {
+    let mut body = format!(
+        "<p>{} <span class=\"dim\">{}</span></p>",
+        escape(&gistit.author),
+        escape(gistit.description.as_deref().unwrap_or(""))
+    );
+
+    for inner in &gistit.inner {
+        if inner.is_binary {
+            let _ = write!(
+                body,
+                "<h3>{}</h3><p class=\"dim\">binary, {} bytes</p>",
+                escape(&inner.name),
+                inner.size
+            );
+            continue;
+        }
+
+        let syntaxes = &config.syntaxes;
+        let syntax = Path::new(&inner.name)
+            .extension()
+            .and_then(|ext| syntaxes.find_syntax_by_extension(&ext.to_string_lossy()))
+            .or_else(|| syntaxes.find_syntax_by_token(&inner.lang))
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+
+        let _ = write!(
+            body,
+            "<h3>{}</h3>{}",
+            escape(&inner.name),
+            highlighted_html_for_string(&inner.data, syntaxes, syntax, &config.theme)
+        );
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn web_escape_html() {
+        assert_eq!(
+            escape("<a href=\"x\">Tom & 'Jerry'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn web_only_local_hosts() {
+        let head = |host: &str| format!("GET / HTTP/1.1\r\nAccept: */*\r\nHOST: {}\r\n\r\n", host);
+        let local =
+            |host: &str| matches!(super::host(&head(host)), Some(host) if is_local_host(host));
+
+        assert!(local("localhost:8080"));
+        assert!(local("127.0.0.1:8080"));
+        assert!(local("[::1]:8080"));
+        assert!(local("LOCALHOST"));
+
+        assert!(!local("evil.example.com:8080"));
+        assert!(!local("localhost.evil.example.com"));
+        assert!(!local("127.0.0.1.nip.io"));
+        assert_eq!(super::host("GET / HTTP/1.0\r\n\r\n"), None);
+    }
+}
@@ -0,0 +1,93 @@
+//! Addresses given by other peers
+//!
+//! Provider records and peer exchange carry addresses chosen by remote peers. Dialing whatever
+//! they say would have the node probe the user's own network, so only addresses reachable over
+//! the internet are dialed from them, see [`is_global`].
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+
+const fn is_global_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Shared address space of carrier-grade NATs, 100.64.0.0/10
+        || (first == 100 && (second & 0b1100_0000) == 64)
+        // "This network", 0.0.0.0/8
+        || first == 0)
+}
+
+const fn is_global_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4() {
+        // Mapped and compatible addresses, `::1` aside
+        if !ip.is_loopback() {
+            return is_global_v4(v4);
+        }
+    }
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // Documentation, 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0xdb8))
+}
+
+/// Whether `address` is reachable over the internet. Host names are, but `localhost`
+#[must_use]
+pub fn is_global(address: &Multiaddr) -> bool {
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => is_global_v4(ip),
+        Some(Protocol::Ip6(ip)) => is_global_v6(ip),
+        Some(
+            Protocol::Dns(host)
+            | Protocol::Dns4(host)
+            | Protocol::Dns6(host)
+            | Protocol::Dnsaddr(host),
+        ) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            host != "localhost" && !host.ends_with(".localhost")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_global_only() {
+        let global = |address: &str| is_global(&address.parse().unwrap());
+
+        assert!(global("/ip4/203.0.114.7/tcp/4001"));
+        assert!(global("/ip6/2a00:1450::1/tcp/4001"));
+        assert!(global("/dns4/bootstrap.libp2p.io/tcp/443/wss"));
+        assert!(global("/ip4/1.2.3.4/tcp/4001/p2p-circuit"));
+
+        assert!(!global("/ip4/127.0.0.1/tcp/4001"));
+        assert!(!global("/ip4/192.168.1.5/tcp/4001"));
+        assert!(!global("/ip4/10.0.0.1/tcp/4001"));
+        assert!(!global("/ip4/172.16.3.1/tcp/4001"));
+        assert!(!global("/ip4/169.254.1.1/tcp/4001"));
+        assert!(!global("/ip4/100.64.0.1/tcp/4001"));
+        assert!(!global("/ip4/0.0.0.0/tcp/4001"));
+        assert!(!global("/ip4/203.0.113.7/tcp/4001"));
+        assert!(!global("/ip6/::1/tcp/4001"));
+        assert!(!global("/ip6/fd00::1/tcp/4001"));
+        assert!(!global("/ip6/fe80::1/tcp/4001"));
+        assert!(!global("/ip6/::ffff:192.168.1.5/tcp/4001"));
+        assert!(!global("/dns4/localhost/tcp/4001"));
+        assert!(!global("/dns/printer.localhost./tcp/4001"));
+        assert!(!global("/memory/1234"));
+    }
+}
@@ -0,0 +1,284 @@
+//! Audit log
+//!
+//! Every gistit we serve to another peer is appended to a log in the data directory, one JSON
+//! object per line, so `gistit node --audit` can tell who fetched what. We only ever append to
+//! it, trimming or removing it is up to the user.
+//!
+//! Served chunks are gathered in memory and written every [`FLUSH_INTERVAL`] on a blocking thread,
+//! the chunks a peer fetched of a gistit in that time making one entry. Queries keep what they read
+//! and only read what was appended since.
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2p::core::PeerId;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+pub const AUDIT_FILE: &str = "audit.log";
+
+/// Most entries answered to a query, so the response fits the ipc socket buffer
+pub const MAX_ENTRIES: usize = 200;
+
+/// How often what was served is written to the log
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub peer_id: String,
+    pub hash: String,
+    pub bytes: u64,
+}
+
+/// The entries read from the log so far and where we stopped reading it
+#[derive(Debug, Default)]
+struct Tail {
+    entries: Vec<Entry>,
+    offset: u64,
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    /// Served since the last flush, by peer and hash
+    pending: HashMap<(String, String), Entry>,
+    tail: Tail,
+    /// Held while writing, so a query waits for a flush under way
+    writing: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    #[must_use]
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            pending: HashMap::new(),
+            tail: Tail::default(),
+            writing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Notes a served chunk, written with the next [`flush`](Self::flush)
+    pub fn record(&mut self, peer_id: &PeerId, hash: &str, bytes: usize) {
+        let (peer_id, hash) = (peer_id.to_string(), hash.to_owned());
+        self.pending
+            .entry((peer_id.clone(), hash.clone()))
+            .or_insert_with(|| Entry {
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs()),
+                peer_id,
+                hash,
+                bytes: 0,
+            })
+            .bytes += bytes as u64;
+    }
+
+    fn take_pending(&mut self) -> Result<Vec<u8>> {
+        let mut pending: Vec<Entry> = self.pending.drain().map(|(_, entry)| entry).collect();
+        pending.sort_by_key(|entry| entry.timestamp);
+        pending.iter().try_fold(Vec::new(), |mut lines, entry| {
+            serde_json::to_writer(&mut lines, entry)?;
+            lines.push(b'\n');
+            Ok(lines)
+        })
+    }
+
+    /// Appends what was served since the last flush on a blocking thread. Failures are logged
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        match self.take_pending() {
+            Ok(lines) => {
+                let (path, writing) = (self.path.clone(), Arc::clone(&self.writing));
+                tokio::task::spawn_blocking(move || {
+                    let _writing = writing.lock().unwrap_or_else(PoisonError::into_inner);
+                    if let Err(err) = append(&path, &lines) {
+                        error!("Failed to write the audit log: {}", err);
+                    }
+                });
+            }
+            Err(err) => error!("Failed to write the audit log: {}", err),
+        }
+    }
+
+    /// Appends what was served since the last flush right away, e.g. before exiting
+    pub fn write_pending(&mut self) -> Result<()> {
+        let lines = self.take_pending()?;
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let _writing = self.writing.lock().unwrap_or_else(PoisonError::into_inner);
+        append(&self.path, &lines)
+    }
+
+    /// Entries matching both filters, most recent first and at most [`MAX_ENTRIES`], along with
+    /// how many matched. Lines that don't parse, e.g. a torn last write, are skipped
+    pub fn query(
+        &mut self,
+        hash: Option<&str>,
+        peer_id: Option<&str>,
+    ) -> Result<(Vec<Entry>, u64)> {
+        self.write_pending()?;
+        {
+            let writing = Arc::clone(&self.writing);
+            let _writing = writing.lock().unwrap_or_else(PoisonError::into_inner);
+            self.read_appended()?;
+        }
+
+        let mut total = 0;
+        let mut matching = Vec::new();
+        for entry in self
+            .tail
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| hash.map_or(true, |hash| entry.hash == hash))
+            .filter(|entry| peer_id.map_or(true, |peer_id| entry.peer_id == peer_id))
+        {
+            total += 1;
+            if matching.len() < MAX_ENTRIES {
+                matching.push(entry.clone());
+            }
+        }
+        Ok((matching, total))
+    }
+
+    /// Reads the lines appended since the last query. A log that got shorter was trimmed, it's read
+    /// again from the start
+    fn read_appended(&mut self) -> Result<()> {
+        let mut file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.tail = Tail::default();
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if file.metadata()?.len() < self.tail.offset {
+            self.tail = Tail::default();
+        }
+        file.seek(SeekFrom::Start(self.tail.offset))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+
+        // A line still being written is read next time
+        let complete = appended
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |last| last + 1);
+        self.tail.entries.extend(
+            appended[..complete]
+                .split(|byte| *byte == b'\n')
+                .filter_map(|line| serde_json::from_slice::<Entry>(line).ok()),
+        );
+        self.tail.offset += complete as u64;
+        Ok(())
+    }
+}
+
+fn append(path: &Path, lines: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    // A torn last line is ended first, so it doesn't take the next one with it
+    let mut last = [b'\n'];
+    if file.metadata()?.len() > 0 {
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+    }
+    let torn = if last[0] == b'\n' {
+        &[][..]
+    } else {
+        &b"\n"[..]
+    };
+    // A single write, so concurrent writers can't interleave
+    file.write_all(&[torn, lines].concat())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn audit_log_appends_and_filters() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(AUDIT_FILE);
+        let mut log = AuditLog::new(&path);
+        assert_eq!(log.query(None, None).unwrap(), (Vec::new(), 0));
+
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        log.record(&alice, "foo", 10);
+        log.record(&bob, "foo", 20);
+        // Chunks of the same gistit make one entry
+        log.record(&bob, "foo", 5);
+        assert_eq!(log.query(None, None).unwrap().1, 2);
+
+        log.record(&alice, "bar", 30);
+        // Torn write, the next entry still makes it
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"timestamp\":")
+            .unwrap();
+
+        let (entries, total) = log.query(None, None).unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(entries[0].hash, "bar");
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+
+        let (entries, total) = log.query(Some("foo"), None).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(
+            entries.iter().map(|entry| entry.bytes).sum::<u64>(),
+            10 + 20 + 5
+        );
+
+        let alice = alice.to_string();
+        let (entries, _) = log.query(Some("foo"), Some(&alice)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].bytes, 10);
+
+        // Another reader sees the same log
+        assert_eq!(AuditLog::new(&path).query(None, None).unwrap().1, 3);
+
+        // Trimmed by the user
+        fs::write(&path, "").unwrap();
+        assert_eq!(log.query(None, None).unwrap(), (Vec::new(), 0));
+    }
+
+    #[tokio::test]
+    async fn audit_log_flushes_in_the_background() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(AUDIT_FILE);
+        let mut log = AuditLog::new(&path);
+
+        log.record(&PeerId::random(), "foo", 10);
+        log.flush();
+        assert!(log.pending.is_empty());
+        for _ in 0..100 {
+            if matches!(fs::metadata(&path), Ok(meta) if meta.len() > 0) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(log.query(None, None).unwrap().1, 1);
+    }
+}
@@ -0,0 +1,121 @@
+//! Batched provides
+//!
+//! A `ProvideManyRequest` starts providing every gistit at once, and is answered a single time
+//! once each provider record is published or failed, with one result per gistit in request order.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use libp2p::kad::QueryId;
+
+use gistit_proto::ipc::instruction::ProvideResult;
+
+#[derive(Debug)]
+struct Batch<Id> {
+    results: Vec<ProvideResult>,
+
+    /// Queries still running and the result they fill in
+    pending: HashMap<Id, usize>,
+}
+
+#[derive(Debug)]
+pub struct ProvideBatches<Id = QueryId> {
+    batches: Vec<Batch<Id>>,
+}
+
+impl<Id> Default for ProvideBatches<Id> {
+    fn default() -> Self {
+        Self {
+            batches: Vec::new(),
+        }
+    }
+}
+
+impl<Id: Hash + Eq> ProvideBatches<Id> {
+    /// Tracks a batch whose `pending` queries fill in `results`. Returns the results right away
+    /// if nothing is pending, e.g. every provide failed to start
+    pub fn start(
+        &mut self,
+        results: Vec<ProvideResult>,
+        pending: HashMap<Id, usize>,
+    ) -> Option<Vec<ProvideResult>> {
+        if pending.is_empty() {
+            return Some(results);
+        }
+        self.batches.push(Batch { results, pending });
+        None
+    }
+
+    #[must_use]
+    pub fn contains(&self, id: &Id) -> bool {
+        self.batches
+            .iter()
+            .any(|batch| batch.pending.contains_key(id))
+    }
+
+    /// Records how a query ended, `error` being why it failed. Returns the batch results once it
+    /// was the last one pending
+    pub fn complete(&mut self, id: &Id, error: Option<String>) -> Option<Vec<ProvideResult>> {
+        let position = self
+            .batches
+            .iter()
+            .position(|batch| batch.pending.contains_key(id))?;
+        let batch = &mut self.batches[position];
+
+        if let Some(index) = batch.pending.remove(id) {
+            let result = &mut batch.results[index];
+            result.provided = error.is_none();
+            result.error = error;
+        }
+
+        if batch.pending.is_empty() {
+            Some(self.batches.swap_remove(position).results)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(hash: &str) -> ProvideResult {
+        ProvideResult {
+            hash: hash.to_owned(),
+            ..ProvideResult::default()
+        }
+    }
+
+    #[test]
+    fn batch_answered_once_complete() {
+        let mut batches = ProvideBatches::default();
+        let failed_to_start = ProvideResult {
+            error: Some("store full".to_owned()),
+            ..result("c")
+        };
+
+        let results = vec![result("a"), result("b"), failed_to_start.clone()];
+        assert!(batches
+            .start(results, HashMap::from([(1, 0), (2, 1)]))
+            .is_none());
+        assert!(batches.contains(&2));
+        assert!(!batches.contains(&3));
+
+        assert!(batches.complete(&2, Some("timeout".to_owned())).is_none());
+        assert!(batches.complete(&3, None).is_none());
+
+        let results = batches.complete(&1, None).unwrap();
+        assert!(results[0].provided);
+        assert_eq!(results[1].error.as_deref(), Some("timeout"));
+        assert_eq!(results[2], failed_to_start);
+        assert!(!batches.contains(&1));
+
+        assert_eq!(
+            batches
+                .start(vec![result("d")], HashMap::new())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -1,35 +1,36 @@
-use std::io;
-use std::iter::once;
-use std::str::{self, FromStr};
-use std::time::Duration;
+use std::io::{self, Read};
+use std::str;
 
-use gistit_project::var;
-use gistit_proto::bytes::BytesMut;
+use gistit_proto::bytes::{BufMut, BytesMut};
 
-use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::core::upgrade::{read_length_prefixed, read_varint, write_length_prefixed};
 use libp2p::core::ProtocolName;
-use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use libp2p::{autonat, Multiaddr, NetworkBehaviour};
+use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{autonat, NetworkBehaviour};
 
 use libp2p::autonat::{Behaviour as Autonat, Event as AutonatEvent};
-use libp2p::core::PeerId;
+use libp2p::core::{Multiaddr, PeerId};
 use libp2p::identify::{Identify, IdentifyConfig, IdentifyEvent};
 use libp2p::kad::record::store::MemoryStore;
-use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent};
+use libp2p::kad::{Kademlia, KademliaEvent, KademliaStoreInserts};
 use libp2p::ping::{Behaviour as PingBehaviour, Config as PingConfig, Event as PingEvent, Ping};
 use libp2p::relay::v2::client::{self, Client, Event as ClientEvent};
 use libp2p::relay::v2::relay::{self, Event as RelayEvent, Relay};
 use libp2p::request_response::{
-    ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig,
-    RequestResponseEvent,
+    ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseEvent,
 };
+use libp2p::swarm::toggle::Toggle;
 
 use async_trait::async_trait;
+use log::warn;
 
 use gistit_proto::prost::Message;
-use gistit_proto::Gistit;
+use gistit_proto::{validate, Gistit};
 
+use crate::bootstrap::split_peer;
 use crate::config::Config;
+use crate::pex::{PexCodec, PexProtocol};
+use crate::sync::{SyncCodec, SyncProtocol};
 use crate::Result;
 
 pub const BOOTNODES: [&str; 4] = [
@@ -51,6 +52,10 @@ pub struct Behaviour {
     pub autonat: Autonat,
     pub ping: Ping,
     pub client: Client,
+    /// Peer exchange, disabled with `--no-pex`
+    pub pex: Toggle<RequestResponse<PexCodec>>,
+    /// History sync, enabled with `--sync`
+    pub sync: Toggle<RequestResponse<SyncCodec>>,
 }
 
 impl Behaviour {
@@ -58,27 +63,29 @@ impl Behaviour {
         config: &Config,
     ) -> Result<(Self, client::transport::ClientTransport)> {
         let request_response = RequestResponse::new(
-            ExchangeCodec,
-            once((ExchangeProtocol, ProtocolSupport::Full)),
-            RequestResponseConfig::default(),
+            ExchangeCodec::new(config.limits.max_message_size),
+            ExchangeProtocol::SUPPORTED
+                .into_iter()
+                .map(|protocol| (protocol, ProtocolSupport::Full)),
+            config.queries.request_response(),
         );
 
         let kademlia = {
-            let mut cfg = KademliaConfig::default();
-            cfg.set_query_timeout(Duration::from_secs(5 * 60));
             let store = MemoryStore::new(config.peer_id);
+            let mut cfg = config.queries.kademlia();
+            // Records other peers publish through us are stored by the node, so it knows which
+            // providers to check, see `health`
+            cfg.set_record_filtering(KademliaStoreInserts::FilterBoth);
             let mut behaviour = Kademlia::with_config(config.peer_id, store, cfg);
 
-            let bootaddr = Multiaddr::from_str(BOOTADDR)?;
-            if config.bootstrap {
-                for peer in BOOTNODES {
-                    behaviour.add_address(
-                        &PeerId::from_str(peer).expect("peer id to be valid"),
-                        bootaddr.clone(),
-                    );
+            if !config.bootstrap_peers.is_empty() {
+                for (peer_id, address) in config.bootstrap_peers.iter().filter_map(split_peer) {
+                    behaviour.add_address(&peer_id, address);
                 }
 
-                behaviour.bootstrap().expect("to bootstrap");
+                if let Err(err) = behaviour.bootstrap() {
+                    warn!("Failed to bootstrap: {:?}", err);
+                }
             }
             behaviour
         };
@@ -101,14 +108,8 @@ impl Behaviour {
                 PeerId::from(config.keypair.public()),
                 autonat::Config::default(),
             );
-            if config.bootstrap {
-                for peer in BOOTNODES {
-                    let bootaddr = Multiaddr::from_str(BOOTADDR)?;
-                    behaviour.add_server(
-                        PeerId::from_str(peer).expect("peer id to be valid"),
-                        Some(bootaddr),
-                    );
-                }
+            for (peer_id, address) in config.bootstrap_peers.iter().filter_map(split_peer) {
+                behaviour.add_server(peer_id, Some(address));
             }
 
             behaviour
@@ -116,6 +117,29 @@ impl Behaviour {
 
         let ping = PingBehaviour::new(PingConfig::new().with_keep_alive(true));
 
+        let pex = config
+            .pex_interval
+            .map(|_| {
+                RequestResponse::new(
+                    PexCodec,
+                    std::iter::once((PexProtocol, ProtocolSupport::Full)),
+                    config.queries.request_response(),
+                )
+            })
+            .into();
+
+        let sync = config
+            .history_sync
+            .as_ref()
+            .map(|_| {
+                RequestResponse::new(
+                    SyncCodec,
+                    std::iter::once((SyncProtocol, ProtocolSupport::Full)),
+                    config.queries.request_response(),
+                )
+            })
+            .into();
+
         Ok((
             Self {
                 request_response,
@@ -125,6 +149,8 @@ impl Behaviour {
                 autonat,
                 ping,
                 client,
+                pex,
+                sync,
             },
             client_transport,
         ))
@@ -140,6 +166,8 @@ pub enum Event {
     Autonat(AutonatEvent),
     Ping(PingEvent),
     Client(ClientEvent),
+    Pex(RequestResponseEvent<Vec<Multiaddr>, Vec<Multiaddr>>),
+    Sync(RequestResponseEvent<Vec<u8>, Vec<u8>>),
 }
 
 impl From<RequestResponseEvent<Request, Response>> for Event {
@@ -184,27 +212,133 @@ impl From<ClientEvent> for Event {
     }
 }
 
+impl From<RequestResponseEvent<Vec<Multiaddr>, Vec<Multiaddr>>> for Event {
+    fn from(event: RequestResponseEvent<Vec<Multiaddr>, Vec<Multiaddr>>) -> Self {
+        Self::Pex(event)
+    }
+}
+
+impl From<RequestResponseEvent<Vec<u8>, Vec<u8>>> for Event {
+    fn from(event: RequestResponseEvent<Vec<u8>, Vec<u8>>) -> Self {
+        Self::Sync(event)
+    }
+}
+
+const TAG_FETCH: u8 = 0;
+const TAG_PUSH: u8 = 1;
+const TAG_FETCH_CHUNK: u8 = 2;
+const TAG_REPLICATE: u8 = 3;
+const TAG_GISTIT: u8 = 0;
+const TAG_PUSHED: u8 = 1;
+const TAG_CHUNK: u8 = 2;
+const TAG_TOO_LARGE: u8 = 3;
+const TAG_RATE_LIMITED: u8 = 4;
+
+/// Compression level of v4 messages, zstd's default
+const COMPRESSION_LEVEL: i32 = 0;
+
+/// Supported exchange protocols, newest first so it is preferred when dialing
 #[derive(Debug, Clone)]
-pub struct ExchangeProtocol;
+pub enum ExchangeProtocol {
+    /// Fetch by hash only
+    V1,
+
+    /// Tagged messages, adds pushing gistits to a peer
+    V2,
+
+    /// Adds fetching in chunks, so interrupted transfers can resume
+    V3,
+
+    /// Messages are zstd compressed, and refused with [`Response::TooLarge`] past the size limit
+    V4,
+
+    /// Adds asking a peer to provide a gistit too
+    V5,
+
+    /// Adds refusing requests with [`Response::RateLimited`] when a peer asks too often
+    V6,
+}
+
+impl ExchangeProtocol {
+    pub const SUPPORTED: [Self; 6] = [Self::V6, Self::V5, Self::V4, Self::V3, Self::V2, Self::V1];
+}
 
 impl ProtocolName for ExchangeProtocol {
     fn protocol_name(&self) -> &[u8] {
-        b"/gistit/1"
+        match self {
+            Self::V1 => b"/gistit/1",
+            Self::V2 => b"/gistit/2",
+            Self::V3 => b"/gistit/3",
+            Self::V4 => b"/gistit/4",
+            Self::V5 => b"/gistit/5",
+            Self::V6 => b"/gistit/6",
+        }
     }
 }
 
 #[derive(Clone)]
-pub struct ExchangeCodec;
+pub struct ExchangeCodec {
+    /// Largest message taken from a peer, once decompressed
+    max_size: usize,
+}
+
+impl ExchangeCodec {
+    #[must_use]
+    pub const fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    const fn max_size_u32(&self) -> u32 {
+        self.max_size as u32
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Request(pub Vec<u8>);
+pub enum Request {
+    /// Ask for the gistit with this hash
+    Fetch(Vec<u8>),
+
+    /// Hand a gistit over to the peer inbox
+    Push(Gistit),
+
+    /// Ask for one chunk of the encoded gistit with this hash. Peers older than v3 get a plain
+    /// fetch instead
+    FetchChunk { hash: Vec<u8>, index: u32 },
+
+    /// Ask the peer to provide a gistit too, answered with [`Response::Pushed`]. Only v5
+    Replicate(Gistit),
+
+    /// Never sent, stands for a request over our size limit so it can be refused
+    TooLarge { max_size: u32 },
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Response(pub Gistit);
+pub enum Response {
+    Gistit(Gistit),
+
+    /// Whether a pushed or replicated gistit was accepted
+    Pushed(bool),
+
+    /// Chunk `index` out of `total` of an encoded gistit
+    Chunk {
+        index: u32,
+        total: u32,
+        data: Vec<u8>,
+    },
+
+    /// The request, or the response to it, was over the size limit of whoever refused it
+    TooLarge {
+        max_size: u32,
+    },
+
+    /// The peer asked too often, it may ask again later. Only v6
+    RateLimited,
+}
 
 impl std::fmt::Display for Response {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.0)
+        write!(f, "{:?}", self)
     }
 }
 
@@ -214,6 +348,115 @@ impl std::error::Error for Response {
     }
 }
 
+fn decode_gistit(bytes: &[u8]) -> io::Result<Gistit> {
+    Gistit::decode(bytes).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+/// Encodes a gistit, prefixed by a tag unless talking v1
+fn encode_gistit(tag: Option<u8>, gistit: &Gistit) -> io::Result<BytesMut> {
+    let mut buf = BytesMut::with_capacity(gistit.encoded_len() + 1);
+    if let Some(tag) = tag {
+        buf.put_u8(tag);
+    }
+    gistit
+        .encode(&mut buf)
+        .map_err(|_| io::ErrorKind::InvalidInput)?;
+    Ok(buf)
+}
+
+fn decode_request(bytes: &[u8]) -> io::Result<Request> {
+    match bytes.split_first() {
+        Some((&TAG_FETCH, hash)) if !hash.is_empty() => {
+            log::debug!("Read request {:?}", std::str::from_utf8(hash));
+            Ok(Request::Fetch(hash.to_vec()))
+        }
+        Some((&TAG_PUSH, gistit)) => {
+            log::debug!("Read push request {:?} bytes", gistit.len());
+            Ok(Request::Push(decode_gistit(gistit)?))
+        }
+        Some((&TAG_REPLICATE, gistit)) => {
+            log::debug!("Read replicate request {:?} bytes", gistit.len());
+            Ok(Request::Replicate(decode_gistit(gistit)?))
+        }
+        Some((&TAG_FETCH_CHUNK, rest)) if rest.len() > 4 => {
+            let (index, hash) = rest.split_at(4);
+            let index = u32::from_be_bytes(index.try_into().expect("4 bytes"));
+            log::debug!(
+                "Read chunk {} request {:?}",
+                index,
+                std::str::from_utf8(hash)
+            );
+            Ok(Request::FetchChunk {
+                hash: hash.to_vec(),
+                index,
+            })
+        }
+        Some(_) => Err(io::ErrorKind::InvalidData.into()),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+fn decode_response(bytes: &[u8]) -> io::Result<Response> {
+    match bytes.split_first() {
+        Some((&TAG_GISTIT, gistit)) if !gistit.is_empty() => {
+            let gistit = decode_gistit(gistit)?;
+            log::debug!("Read response: {:?}", gistit);
+            Ok(Response::Gistit(gistit))
+        }
+        Some((&TAG_PUSHED, [accepted])) => Ok(Response::Pushed(*accepted != 0)),
+        Some((&TAG_CHUNK, rest)) if rest.len() >= 8 => {
+            let (header, data) = rest.split_at(8);
+            let (index, total) = header.split_at(4);
+            Ok(Response::Chunk {
+                index: u32::from_be_bytes(index.try_into().expect("4 bytes")),
+                total: u32::from_be_bytes(total.try_into().expect("4 bytes")),
+                data: data.to_vec(),
+            })
+        }
+        Some((&TAG_TOO_LARGE, max_size)) if max_size.len() == 4 => Ok(Response::TooLarge {
+            max_size: u32::from_be_bytes(max_size.try_into().expect("4 bytes")),
+        }),
+        Some((&TAG_RATE_LIMITED, [])) => Ok(Response::RateLimited),
+        Some(_) => Err(io::ErrorKind::InvalidData.into()),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+/// Compresses a tagged message
+fn compress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::bulk::compress(bytes, COMPRESSION_LEVEL)
+}
+
+/// Decompresses a tagged message, `None` if it is larger than `max_size`. Stops reading right
+/// past the limit, so small messages can't blow up in memory
+fn decompress(compressed: &[u8], max_size: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    zstd::stream::read::Decoder::new(compressed)?
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+    if bytes.len() > max_size {
+        return Ok(None);
+    }
+    Ok(Some(bytes))
+}
+
+/// Reads a compressed message, `None` if it is larger than `max_size` before or after
+/// decompressing. The rest of a refused message is left unread
+async fn read_compressed<T: AsyncRead + Unpin + Send>(
+    io: &mut T,
+    max_size: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let len = read_varint(io).await?;
+    if len > max_size {
+        return Ok(None);
+    }
+
+    let mut compressed = vec![0; len];
+    io.read_exact(&mut compressed).await?;
+    decompress(&compressed, max_size)
+}
+
 #[async_trait]
 impl RequestResponseCodec for ExchangeCodec {
     type Protocol = ExchangeProtocol;
@@ -222,65 +465,214 @@ impl RequestResponseCodec for ExchangeCodec {
 
     async fn read_request<T: Send + Unpin + AsyncRead>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Request> {
-        let hash = read_length_prefixed(io, var::GISTIT_HASH_LENGTH).await?;
-        log::debug!("Read request {:?}", std::str::from_utf8(&hash).unwrap());
-
-        if hash.is_empty() {
-            Err(io::ErrorKind::UnexpectedEof.into())
-        } else {
-            Ok(Request(hash))
+        match protocol {
+            ExchangeProtocol::V1 => {
+                let hash = read_length_prefixed(io, validate::HASH_LENGTH).await?;
+                log::debug!("Read request {:?}", std::str::from_utf8(&hash));
+
+                if hash.is_empty() {
+                    Err(io::ErrorKind::UnexpectedEof.into())
+                } else {
+                    Ok(Request::Fetch(hash))
+                }
+            }
+            ExchangeProtocol::V2 | ExchangeProtocol::V3 => {
+                decode_request(&read_length_prefixed(io, self.max_size).await?)
+            }
+            ExchangeProtocol::V4 | ExchangeProtocol::V5 | ExchangeProtocol::V6 => {
+                match read_compressed(io, self.max_size).await? {
+                    Some(bytes) => decode_request(&bytes),
+                    None => {
+                        log::warn!("Refusing request over {} bytes", self.max_size);
+                        Ok(Request::TooLarge {
+                            max_size: self.max_size_u32(),
+                        })
+                    }
+                }
+            }
         }
     }
 
     async fn read_response<T: Send + Unpin + AsyncRead>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Response> {
-        let bytes = read_length_prefixed(io, var::GISTIT_MAX_SIZE).await?;
-        let gistit = Gistit::decode(&*bytes).map_err(|_| io::ErrorKind::InvalidInput)?;
-        log::debug!("Read response: {:?}", gistit);
-
-        if bytes.is_empty() {
-            Err(io::ErrorKind::UnexpectedEof.into())
-        } else {
-            Ok(Response(gistit))
+        match protocol {
+            ExchangeProtocol::V1 => {
+                let bytes = read_length_prefixed(io, validate::ENCODED_SIZE).await?;
+                let gistit = decode_gistit(&bytes)?;
+                log::debug!("Read response: {:?}", gistit);
+
+                if bytes.is_empty() {
+                    Err(io::ErrorKind::UnexpectedEof.into())
+                } else {
+                    Ok(Response::Gistit(gistit))
+                }
+            }
+            ExchangeProtocol::V2 | ExchangeProtocol::V3 => {
+                decode_response(&read_length_prefixed(io, self.max_size).await?)
+            }
+            ExchangeProtocol::V4 | ExchangeProtocol::V5 | ExchangeProtocol::V6 => {
+                match read_compressed(io, self.max_size).await? {
+                    Some(bytes) => decode_response(&bytes),
+                    // Handled like a refusal from the peer
+                    None => Ok(Response::TooLarge {
+                        max_size: self.max_size_u32(),
+                    }),
+                }
+            }
         }
     }
 
     async fn write_request<T: Send + Unpin + AsyncWrite>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
-        Request(req): Self::Request,
+        request: Self::Request,
     ) -> io::Result<()> {
-        log::debug!("Write request {:?}", std::str::from_utf8(&req).unwrap());
-        write_length_prefixed(io, req).await?;
+        let buf = match (protocol, request) {
+            (_, Request::TooLarge { .. }) => return Err(io::ErrorKind::InvalidInput.into()),
+            (ExchangeProtocol::V5 | ExchangeProtocol::V6, Request::Replicate(gistit)) => {
+                let buf = encode_gistit(Some(TAG_REPLICATE), &gistit)?;
+                log::debug!("Write replicate request {:?} bytes", buf.len());
+                buf.to_vec()
+            }
+            // Peers older than v5 don't replicate
+            (_, Request::Replicate(_)) => return Err(io::ErrorKind::Unsupported.into()),
+            (ExchangeProtocol::V1, Request::Fetch(hash) | Request::FetchChunk { hash, .. }) => {
+                log::debug!("Write request {:?}", std::str::from_utf8(&hash));
+                hash
+            }
+            // Peers speaking v1 can't take pushes
+            (ExchangeProtocol::V1, Request::Push(_)) => {
+                return Err(io::ErrorKind::Unsupported.into())
+            }
+            (_, Request::Fetch(hash))
+            | (ExchangeProtocol::V2, Request::FetchChunk { hash, .. }) => {
+                log::debug!("Write request {:?}", std::str::from_utf8(&hash));
+                let mut buf = vec![TAG_FETCH];
+                buf.extend(hash);
+                buf
+            }
+            (_, Request::FetchChunk { hash, index }) => {
+                log::debug!(
+                    "Write chunk {} request {:?}",
+                    index,
+                    std::str::from_utf8(&hash)
+                );
+                let mut buf = vec![TAG_FETCH_CHUNK];
+                buf.extend(index.to_be_bytes());
+                buf.extend(hash);
+                buf
+            }
+            (_, Request::Push(gistit)) => {
+                let buf = encode_gistit(Some(TAG_PUSH), &gistit)?;
+                log::debug!("Write push request {:?} bytes", buf.len());
+                buf.to_vec()
+            }
+        };
+
+        let buf = match protocol {
+            ExchangeProtocol::V4 | ExchangeProtocol::V5 | ExchangeProtocol::V6 => compress(&buf)?,
+            _ => buf,
+        };
+        write_length_prefixed(io, buf).await?;
         io.close().await?;
         Ok(())
     }
 
     async fn write_response<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
-        Response(gistit): Self::Response,
+        response: Self::Response,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let mut buf = BytesMut::with_capacity(var::GISTIT_MAX_SIZE);
-        gistit
-            .encode(&mut buf)
-            .map_err(|_| io::ErrorKind::InvalidInput)?;
+        let buf = match (protocol, response) {
+            (ExchangeProtocol::V1, Response::Gistit(gistit)) => encode_gistit(None, &gistit)?,
+            (ExchangeProtocol::V1, Response::Pushed(_))
+            | (ExchangeProtocol::V1 | ExchangeProtocol::V2, Response::Chunk { .. })
+            | (
+                ExchangeProtocol::V1 | ExchangeProtocol::V2 | ExchangeProtocol::V3,
+                Response::TooLarge { .. },
+            )
+            | (
+                ExchangeProtocol::V1
+                | ExchangeProtocol::V2
+                | ExchangeProtocol::V3
+                | ExchangeProtocol::V4
+                | ExchangeProtocol::V5,
+                Response::RateLimited,
+            ) => return Err(io::ErrorKind::Unsupported.into()),
+            (_, Response::Gistit(gistit)) => encode_gistit(Some(TAG_GISTIT), &gistit)?,
+            (_, Response::Pushed(accepted)) => {
+                BytesMut::from(&[TAG_PUSHED, u8::from(accepted)][..])
+            }
+            (_, Response::Chunk { index, total, data }) => {
+                let mut buf = BytesMut::with_capacity(9 + data.len());
+                buf.put_u8(TAG_CHUNK);
+                buf.put_u32(index);
+                buf.put_u32(total);
+                buf.extend_from_slice(&data);
+                buf
+            }
+            (_, Response::TooLarge { max_size }) => {
+                let mut buf = BytesMut::with_capacity(5);
+                buf.put_u8(TAG_TOO_LARGE);
+                buf.put_u32(max_size);
+                buf
+            }
+            (_, Response::RateLimited) => BytesMut::from(&[TAG_RATE_LIMITED][..]),
+        };
         log::debug!("Write response {:?} bytes", buf.len());
 
+        let buf = match protocol {
+            ExchangeProtocol::V4 | ExchangeProtocol::V5 | ExchangeProtocol::V6 => compress(&buf)?,
+            _ => buf.to_vec(),
+        };
         write_length_prefixed(io, buf).await?;
         io.close().await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_compression_size_limit() {
+        let request = [&[TAG_FETCH_CHUNK][..], &7_u32.to_be_bytes(), b"abc"].concat();
+        let compressed = compress(&request).unwrap();
+        let decompressed = decompress(&compressed, 64).unwrap().unwrap();
+        assert_eq!(
+            decode_request(&decompressed).unwrap(),
+            Request::FetchChunk {
+                hash: b"abc".to_vec(),
+                index: 7
+            }
+        );
+
+        // Highly compressible, small on the wire but over the limit once decompressed
+        let bomb = compress(&vec![0_u8; 10_000]).unwrap();
+        assert!(bomb.len() < 100);
+        assert_eq!(decompress(&bomb, 1_000).unwrap(), None);
+        assert!(decompress(b"not zstd", 1_000).is_err());
+
+        assert_eq!(
+            decode_response(&[TAG_TOO_LARGE, 0, 0, 4, 0]).unwrap(),
+            Response::TooLarge { max_size: 1024 }
+        );
+        assert_eq!(
+            decode_response(&[TAG_RATE_LIMITED]).unwrap(),
+            Response::RateLimited
+        );
+    }
+}
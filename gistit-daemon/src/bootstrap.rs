@@ -0,0 +1,130 @@
+//! Bootstrap peers health tracking
+//!
+//! Bootstrap peers are dialed a few at a time, healthiest first. Whenever one of them fails or
+//! disconnects the next candidate is rotated in.
+use libp2p::core::{Multiaddr, PeerId};
+use libp2p::multiaddr::Protocol;
+use log::warn;
+
+/// How many bootstrap peers we try to keep connected at once
+pub const BOOTSTRAP_TARGET: usize = 4;
+
+/// Peers that failed this many times are not dialed again
+pub const MAX_FAILURES: u32 = 5;
+
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+    pub failures: u32,
+    pub connected: bool,
+    dialing: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Bootstrap {
+    peers: Vec<Peer>,
+}
+
+/// Splits the trailing `/p2p/<peer id>` off a multiaddr
+#[must_use]
+pub fn split_peer(address: &Multiaddr) -> Option<(PeerId, Multiaddr)> {
+    let mut transport = address.clone();
+    match transport.pop() {
+        Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash)
+            .ok()
+            .map(|peer_id| (peer_id, transport)),
+        _ => None,
+    }
+}
+
+impl Bootstrap {
+    #[must_use]
+    pub fn new(addresses: impl IntoIterator<Item = Multiaddr>) -> Self {
+        let peers = addresses
+            .into_iter()
+            .filter_map(|address| {
+                if let Some((peer_id, _)) = split_peer(&address) {
+                    Some(Peer {
+                        peer_id,
+                        address,
+                        failures: 0,
+                        connected: false,
+                        dialing: false,
+                    })
+                } else {
+                    warn!("Ignoring bootstrap address without peer id: {}", address);
+                    None
+                }
+            })
+            .collect();
+
+        Self { peers }
+    }
+
+    /// Picks the next peers to dial so we reach [`BOOTSTRAP_TARGET`] live connections,
+    /// preferring the ones that failed the least
+    pub fn next_to_dial(&mut self) -> Vec<Multiaddr> {
+        let busy = self
+            .peers
+            .iter()
+            .filter(|peer| peer.connected || peer.dialing)
+            .count();
+        let wanted = BOOTSTRAP_TARGET.saturating_sub(busy);
+
+        let mut candidates: Vec<&mut Peer> = self
+            .peers
+            .iter_mut()
+            .filter(|peer| !peer.connected && !peer.dialing && peer.failures < MAX_FAILURES)
+            .collect();
+        candidates.sort_by_key(|peer| peer.failures);
+
+        candidates
+            .into_iter()
+            .take(wanted)
+            .map(|peer| {
+                peer.dialing = true;
+                peer.address.clone()
+            })
+            .collect()
+    }
+
+    /// Returns `true` if this peer is a bootstrap peer
+    pub fn on_connected(&mut self, peer_id: &PeerId) -> bool {
+        self.update(peer_id, |peer| {
+            peer.connected = true;
+            peer.dialing = false;
+        })
+    }
+
+    /// Returns `true` if this peer is a bootstrap peer
+    pub fn on_failure(&mut self, peer_id: &PeerId) -> bool {
+        self.update(peer_id, |peer| {
+            peer.failures += 1;
+            peer.dialing = false;
+        })
+    }
+
+    /// Returns `true` if this peer is a bootstrap peer
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) -> bool {
+        self.update(peer_id, |peer| peer.connected = false)
+    }
+
+    /// Addresses of bootstrap peers we are currently connected to
+    #[must_use]
+    pub fn connected(&self) -> Vec<String> {
+        self.peers
+            .iter()
+            .filter(|peer| peer.connected)
+            .map(|peer| peer.address.to_string())
+            .collect()
+    }
+
+    fn update(&mut self, peer_id: &PeerId, f: impl FnOnce(&mut Peer)) -> bool {
+        self.peers
+            .iter_mut()
+            .find(|peer| peer.peer_id == *peer_id)
+            .map(f)
+            .is_some()
+    }
+}
@@ -0,0 +1,146 @@
+//! Burn after read
+//!
+//! A burn after read gistit is forgotten once a peer got all of it, not when its response is
+//! queued: a response that never makes it out leaves the gistit to be fetched again. The first peer
+//! asking for it claims it, others are refused until that peer's fetch fails altogether.
+//! Chunked fetches burn once every chunk was delivered to the claiming peer.
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use libp2p::core::PeerId;
+use libp2p::kad::record::Key;
+use libp2p::request_response::RequestId;
+
+/// A response on its way out, with the chunk it holds if chunked
+#[derive(Debug)]
+struct Sending<P> {
+    peer: P,
+    key: Key,
+    chunk: Option<(u32, u32)>,
+}
+
+#[derive(Debug)]
+struct Claim<P> {
+    peer: P,
+    /// Chunks delivered so far
+    delivered: HashSet<u32>,
+}
+
+#[derive(Debug)]
+pub struct Burns<I = RequestId, P = PeerId> {
+    sending: HashMap<I, Sending<P>>,
+    claims: HashMap<Key, Claim<P>>,
+}
+
+impl<I, P> Default for Burns<I, P> {
+    fn default() -> Self {
+        Self {
+            sending: HashMap::new(),
+            claims: HashMap::new(),
+        }
+    }
+}
+
+impl<I: Eq + Hash, P: Eq + Clone> Burns<I, P> {
+    /// Whether `peer` may be sent the gistit under `key`, claiming it if nobody did
+    pub fn claim(&mut self, peer: &P, key: &Key) -> bool {
+        let claim = self.claims.entry(key.clone()).or_insert_with(|| Claim {
+            peer: peer.clone(),
+            delivered: HashSet::new(),
+        });
+        claim.peer == *peer
+    }
+
+    /// Notes the response `id` holds the gistit, or its chunk `index` of `total`
+    pub fn sending(&mut self, id: I, peer: P, key: Key, chunk: Option<(u32, u32)>) {
+        self.sending.insert(id, Sending { peer, key, chunk });
+    }
+
+    /// The response `id` was delivered. Returns the gistit to burn if its peer now has all of it
+    pub fn sent(&mut self, id: &I) -> Option<Key> {
+        let Sending { peer, key, chunk } = self.sending.remove(id)?;
+        let claim = self
+            .claims
+            .get_mut(&key)
+            .filter(|claim| claim.peer == peer)?;
+        let complete = match chunk {
+            Some((index, total)) => {
+                claim.delivered.insert(index);
+                claim.delivered.len() >= total as usize
+            }
+            None => true,
+        };
+        if complete {
+            self.claims.remove(&key);
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    /// The response `id` didn't make it out. If its peer has nothing else coming and got nothing
+    /// yet, the gistit is up for grabs again
+    pub fn failed(&mut self, id: &I) {
+        let Sending { peer, key, .. } = match self.sending.remove(id) {
+            Some(sending) => sending,
+            None => return,
+        };
+        let pending = self
+            .sending
+            .values()
+            .any(|sending| sending.peer == peer && sending.key == key);
+        let untouched = matches!(
+            self.claims.get(&key),
+            Some(claim) if claim.peer == peer && claim.delivered.is_empty()
+        );
+        if !pending && untouched {
+            self.claims.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burns_once_delivered() {
+        let mut burns: Burns<u64, &str> = Burns::default();
+        let key = Key::from(b"burn".to_vec());
+
+        assert!(burns.claim(&"alice", &key));
+        assert!(!burns.claim(&"bob", &key));
+        burns.sending(1, "alice", key.clone(), None);
+        // Queued, not burned yet
+        assert!(!burns.claim(&"bob", &key));
+
+        // Never made it out, someone else may have it
+        burns.failed(&1);
+        assert!(burns.claim(&"bob", &key));
+        burns.sending(2, "bob", key.clone(), None);
+        assert_eq!(burns.sent(&2), Some(key));
+        assert_eq!(burns.sent(&2), None);
+    }
+
+    #[test]
+    fn burns_chunks_once_all_delivered() {
+        let mut burns: Burns<u64, &str> = Burns::default();
+        let key = Key::from(b"chunked".to_vec());
+
+        assert!(burns.claim(&"alice", &key));
+        // Fetched in parallel, the last chunk first
+        burns.sending(1, "alice", key.clone(), Some((2, 3)));
+        burns.sending(2, "alice", key.clone(), Some((0, 3)));
+        burns.sending(3, "alice", key.clone(), Some((1, 3)));
+        assert_eq!(burns.sent(&1), None);
+        assert_eq!(burns.sent(&2), None);
+
+        // Part of it was delivered, it stays claimed
+        burns.failed(&3);
+        assert!(!burns.claim(&"bob", &key));
+        assert!(burns.claim(&"alice", &key));
+        burns.sending(4, "alice", key.clone(), Some((1, 3)));
+        assert_eq!(burns.sent(&4), Some(key.clone()));
+        assert!(burns.claim(&"bob", &key));
+    }
+}
@@ -1,17 +1,31 @@
 use std::fmt::Debug;
 use std::fs;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 use libp2p::core::{Multiaddr, PeerId};
 use libp2p::identity::{self, ed25519, Keypair};
-use libp2p::multiaddr::multiaddr;
+use libp2p::multiaddr::{multiaddr, Protocol};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use zeroize::{Zeroize, Zeroizing};
 
+use gistit_project::settings::{self, Settings, SETTINGS_FILE};
+use gistit_proto::validate;
+
+use crate::audit::AUDIT_FILE;
+use crate::behaviour::{BOOTADDR, BOOTNODES};
+use crate::limit::Limits;
+use crate::listen::LISTEN_FILE;
+use crate::peers::PEERS_FILE;
+use crate::query::Queries;
+use crate::reload::Overrides;
+use crate::supersede::SUPERSEDED_FILE;
+use crate::sync::{HistorySync, HISTORY_FILE};
+use crate::trust::TRUST_FILE;
 use crate::{Error, Result};
 
 pub struct Config {
@@ -19,8 +33,42 @@ pub struct Config {
     pub keypair: Keypair,
     pub runtime_path: PathBuf,
     pub config_path: PathBuf,
-    pub multiaddr: Multiaddr,
-    pub bootstrap: bool,
+    /// Every address we listen on, ipv4 and ipv6 alike
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Whether tcp addresses without a port take the one we listened on last time, see
+    /// [`crate::listen`]
+    pub rebind_port: bool,
+    /// Where the addresses we listened on are persisted
+    pub listen_file: PathBuf,
+    /// Bootstrap peers, `/p2p/<peer id>` suffixed
+    pub bootstrap_peers: Vec<Multiaddr>,
+    pub limits: Limits,
+    /// Limits given as flags, the others come from the settings file, see [`crate::reload`]
+    pub overrides: Overrides,
+    /// Where the `node-*` settings are read from
+    pub settings_file: PathBuf,
+    pub queries: Queries,
+    /// Where trusted and denied peers are persisted
+    pub trust_file: PathBuf,
+    /// Where peers we keep dialing are persisted
+    pub peers_file: PathBuf,
+    /// Where gistits we serve are logged
+    pub audit_file: PathBuf,
+    /// Where superseded gistits due to stop being provided are persisted
+    pub superseded_file: PathBuf,
+    /// Storage cap for reseeded gistits, `None` if we don't reseed
+    pub reseed_cap: Option<u64>,
+    /// Directory whose files we provide, see [`crate::watch`]
+    pub provide_dir: Option<PathBuf>,
+    pub provide_author: String,
+    /// How often we trade peers with connected nodes, `None` if we don't, see [`crate::pex`]
+    pub pex_interval: Option<Duration>,
+    /// How long gistits replaced by a newer version are still provided, `None` to keep them,
+    /// see [`crate::supersede`]
+    pub supersede_grace: Option<Duration>,
+    /// History synced with the nodes sharing our passphrase, `None` unless `--sync`, see
+    /// [`crate::sync`]
+    pub history_sync: Option<HistorySync>,
 }
 
 impl Debug for Config {
@@ -28,29 +76,93 @@ impl Debug for Config {
         write!(
             f,
             "{:?} {:?} {:?} {:?}",
-            self.peer_id, self.runtime_path, self.config_path, self.multiaddr,
+            self.peer_id, self.runtime_path, self.config_path, self.listen_addrs,
         )
     }
 }
 
 impl Config {
+    #[allow(clippy::too_many_arguments)]
     pub fn from_args(
         runtime_path: Option<PathBuf>,
         config_path: Option<PathBuf>,
+        data_path: Option<PathBuf>,
         config_file: Option<PathBuf>,
-        host: Option<Ipv4Addr>,
+        hosts: Vec<IpAddr>,
         port: Option<u16>,
+        randomize_port: bool,
+        listen: Vec<String>,
         bootstrap: bool,
+        bootstrap_peers: Vec<String>,
+        limits: Limits,
+        overrides: Overrides,
+        queries: Queries,
+        trust_file: Option<PathBuf>,
+        reseed_cap: Option<u64>,
+        provide_dir: Option<PathBuf>,
+        provide_author: Option<String>,
+        pex_interval: Option<Duration>,
+        supersede_grace: Option<Duration>,
+        sync: bool,
     ) -> Result<Self> {
-        gistit_project::path::init()?;
+        // The user's directories are only needed for the ones not given
+        if runtime_path.is_none() || config_path.is_none() || data_path.is_none() {
+            gistit_project::path::init()?;
+        }
+
+        if let Some(dir) = &provide_dir {
+            if !dir.is_dir() {
+                return Err(Error::Parse("--provide-dir is not a directory"));
+            }
+        }
+        queries.validate()?;
+        if pex_interval == Some(Duration::ZERO) {
+            return Err(Error::Parse("--pex-interval must be at least a second"));
+        }
+        if let Some(author) = &provide_author {
+            validate::author(author)
+                .map_err(|_| Error::Parse("--provide-author must be 3 to 30 characters long"))?;
+        }
+        let provide_author = provide_author
+            .or_else(|| {
+                ["USER", "USERNAME", "LOGNAME"]
+                    .iter()
+                    .filter_map(|var| std::env::var(var).ok())
+                    .find(|user| validate::author(user).is_ok())
+            })
+            .unwrap_or_else(|| "anonymous".to_owned());
 
-        let host = host.unwrap_or_else(|| Ipv4Addr::new(0, 0, 0, 0));
-        let port = port.unwrap_or(0_u16);
-        let multiaddr = multiaddr!(Ip4(host), Tcp(port));
+        if port.is_some() && randomize_port {
+            return Err(Error::Parse("--port can't be used with --randomize-port"));
+        }
+        let rebind_port = port.is_none() && !randomize_port;
+        let listen_addrs = listen_addrs(hosts, port.unwrap_or(0_u16), &listen)?;
 
-        let runtime_path = runtime_path.unwrap_or(gistit_project::path::runtime()?);
-        let config_path = config_path.unwrap_or(gistit_project::path::config()?);
+        let runtime_path = runtime_path.map_or_else(gistit_project::path::runtime, Ok)?;
+        let config_path = config_path.map_or_else(gistit_project::path::config, Ok)?;
+        let data_path = data_path.map_or_else(gistit_project::path::data, Ok)?;
         let node_config = config_file.unwrap_or_else(|| config_path.join("node-config"));
+        let trust_file = trust_file.unwrap_or_else(|| config_path.join(TRUST_FILE));
+        let settings_file = config_path.join(SETTINGS_FILE);
+        // A broken settings file is only the CLI's problem, don't refuse to start over it
+        let settings = settings::load_from(&settings_file).unwrap_or_else(|err| {
+            warn!("Ignoring the settings file, using the defaults: {}", err);
+            Settings::default()
+        });
+        let limits = overrides.apply(limits, &settings);
+        let peers_file = data_path.join(PEERS_FILE);
+        let listen_file = data_path.join(LISTEN_FILE);
+        let audit_file = data_path.join(AUDIT_FILE);
+        let superseded_file = data_path.join(SUPERSEDED_FILE);
+        let history_sync = if sync {
+            let secret = Zeroizing::new(
+                std::env::var(gistit_project::env::GISTIT_SYNC_SECRET)
+                    .map_err(|_| Error::Parse("--sync needs a secret in GISTIT_SYNC_SECRET"))?,
+            );
+            Some(HistorySync::new(&secret, data_path.join(HISTORY_FILE))?)
+        } else {
+            None
+        };
 
         let (peer_id, keypair) = if fs::metadata(&node_config).is_ok() {
             debug!("Using existing node config file");
@@ -81,17 +193,75 @@ impl Config {
         };
         info!("{:?}", peer_id);
 
+        let bootstrap_peers = if bootstrap_peers.is_empty() && bootstrap {
+            let bootaddr = Multiaddr::from_str(BOOTADDR)?;
+            BOOTNODES
+                .iter()
+                .map(|peer| {
+                    let peer_id = PeerId::from_str(peer).expect("peer id to be valid");
+                    bootaddr.clone().with(Protocol::P2p(peer_id.into()))
+                })
+                .collect()
+        } else {
+            bootstrap_peers
+                .iter()
+                .map(|addr| addr.parse())
+                .collect::<std::result::Result<_, _>>()?
+        };
+
         Ok(Self {
             peer_id,
             keypair,
             runtime_path,
             config_path,
-            multiaddr,
-            bootstrap,
+            listen_addrs,
+            rebind_port,
+            listen_file,
+            bootstrap_peers,
+            limits,
+            overrides,
+            settings_file,
+            queries,
+            trust_file,
+            peers_file,
+            audit_file,
+            superseded_file,
+            reseed_cap,
+            provide_dir,
+            provide_author,
+            pex_interval,
+            supersede_grace,
+            history_sync,
         })
     }
 }
 
+/// Tcp multiaddrs for each host, both ipv4 and ipv6 wildcards if none is given, followed by the
+/// extra `listen` multiaddrs
+fn listen_addrs(hosts: Vec<IpAddr>, port: u16, listen: &[String]) -> Result<Vec<Multiaddr>> {
+    let hosts = if hosts.is_empty() {
+        vec![
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        ]
+    } else {
+        hosts
+    };
+
+    let mut addrs: Vec<Multiaddr> = hosts
+        .into_iter()
+        .map(|host| match host {
+            IpAddr::V4(ip) => multiaddr!(Ip4(ip), Tcp(port)),
+            IpAddr::V6(ip) => multiaddr!(Ip6(ip), Tcp(port)),
+        })
+        .collect();
+
+    for addr in listen {
+        addrs.push(addr.parse()?);
+    }
+    Ok(addrs)
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct NodeKey {
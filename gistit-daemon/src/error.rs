@@ -1,3 +1,5 @@
+use gistit_project::exit::ErrorKind;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("i/o error, {0}")]
@@ -33,4 +35,25 @@ pub enum Error {
 
     #[error("parse error, {0}")]
     Parse(&'static str),
+
+    #[error("history sync error, {0}")]
+    Sync(&'static str),
+}
+
+impl Error {
+    /// Category of the error, which decides the process exit code
+    #[must_use]
+    pub const fn kind(&self) -> ErrorKind {
+        match self {
+            Self::IO(_) => ErrorKind::Io,
+            Self::Ipc(_) => ErrorKind::Daemon,
+            Self::Multiaddr(_) | Self::Parse(_) => ErrorKind::Argument,
+            Self::Json(_) | Self::Identity(_) | Self::Base64(_) | Self::Sync(_) => {
+                ErrorKind::Config
+            }
+            Self::Project(err) => err.kind(),
+            Self::Transport(_) | Self::Dial(_) => ErrorKind::Network,
+            Self::Proto(_) | Self::Codec(_) => ErrorKind::Other,
+        }
+    }
 }
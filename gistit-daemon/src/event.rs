@@ -1,16 +1,27 @@
 use std::str;
 
 use libp2p::identify::{IdentifyEvent, IdentifyInfo};
-use libp2p::kad::record::Key;
-use libp2p::kad::{GetProvidersError, GetProvidersOk, KademliaEvent, QueryResult};
-use libp2p::multiaddr::Protocol;
-use libp2p::request_response::{RequestResponseEvent, RequestResponseMessage};
+use libp2p::kad::store::RecordStore;
+use libp2p::kad::{
+    BootstrapOk, GetProvidersError, GetProvidersOk, GetRecordError, GetRecordOk, InboundRequest,
+    KademliaEvent, QueryResult,
+};
+use libp2p::relay::v2::client::Event as ClientEvent;
+use libp2p::request_response::{RequestId, RequestResponseEvent, RequestResponseMessage};
+use libp2p::{Multiaddr, PeerId};
 
-use gistit_proto::Instruction;
-use log::{debug, error, info};
+use gistit_proto::ipc::instruction::error_response::Code;
+use gistit_proto::ipc::instruction::provide_response::Status;
+use gistit_proto::prost::Message;
+use gistit_proto::{validate, Gistit, GistitHash, Instruction};
+use log::{debug, error, info, warn};
 
 use crate::behaviour::{Request, Response};
-use crate::node::Node;
+use crate::latency::PARALLEL_REQUESTS;
+use crate::limit::Verdict;
+use crate::node::{gistit_key, metadata_key, Node};
+use crate::partial::{chunk, Progress};
+use crate::pex::PEX_PROTOCOL;
 use crate::Result;
 
 pub async fn handle_request_response(
@@ -18,80 +29,524 @@ pub async fn handle_request_response(
     event: RequestResponseEvent<Request, Response>,
 ) -> Result<()> {
     match event {
-        RequestResponseEvent::Message { message, .. } => match message {
+        RequestResponseEvent::Message { peer, message } => match message {
             RequestResponseMessage::Request {
-                request, channel, ..
+                request_id,
+                request,
+                channel,
             } => {
-                let key = Key::new(&request.0);
-                info!("Request response 'Message::Request' for {:?}", key);
-                let file = node
-                    .to_provide
-                    .get(&key)
-                    .expect("to be providing {key}")
-                    .clone();
+                if !within_rate_limit(node, peer) {
+                    let refused = node
+                        .swarm
+                        .behaviour_mut()
+                        .request_response
+                        .send_response(channel, Response::RateLimited);
+                    if refused.is_err() {
+                        debug!("Request from {:?} closed before we refused it", peer);
+                    }
+                    return Ok(());
+                }
+
+                if !node.trust.is_allowed(&peer) {
+                    warn!("Ignoring request from untrusted peer {:?}", peer);
+                    return Ok(());
+                }
+
+                let mut burned = None;
+                // Hash and bytes, for the audit log
+                let mut served = None;
+                let response = match request {
+                    Request::Fetch(hash) => {
+                        let key = gistit_key(&String::from_utf8_lossy(&hash));
+                        info!("Request response 'Message::Request' for {:?}", key);
+                        if let Some(file) = node.to_provide.get(&key) {
+                            if file.burn_after_read && !node.burns.claim(&peer, &key) {
+                                warn!(
+                                    "Not sending {:?} to {:?}, it burns for another peer",
+                                    key, peer
+                                );
+                                return Ok(());
+                            }
+                            node.reseed.touch(&key);
+                            if file.burn_after_read {
+                                burned = Some((key, None));
+                            }
+                            served = Some((file.hash.clone(), file.encoded_len()));
+                            Response::Gistit(file)
+                        } else {
+                            warn!("Peer {:?} requested {:?} which we don't provide", peer, key);
+                            return Ok(());
+                        }
+                    }
+                    Request::FetchChunk { hash, index } => {
+                        let key = gistit_key(&String::from_utf8_lossy(&hash));
+                        info!(
+                            "Request response 'Message::Request' for chunk {} of {:?}",
+                            index, key
+                        );
+                        let gistit = if let Some(gistit) = node.to_provide.get(&key) {
+                            gistit
+                        } else {
+                            warn!("Peer {:?} requested {:?} which we don't provide", peer, key);
+                            return Ok(());
+                        };
+                        if gistit.burn_after_read && !node.burns.claim(&peer, &key) {
+                            warn!(
+                                "Not sending {:?} to {:?}, it burns for another peer",
+                                key, peer
+                            );
+                            return Ok(());
+                        }
+                        node.reseed.touch(&key);
+
+                        let bytes = gistit.encode_to_vec();
+                        if let Some((total, data)) = chunk(&bytes, index) {
+                            if gistit.burn_after_read {
+                                burned = Some((key, Some((index, total))));
+                            }
+                            served = Some((gistit.hash.clone(), data.len()));
+                            Response::Chunk {
+                                index,
+                                total,
+                                data: data.to_vec(),
+                            }
+                        } else {
+                            warn!(
+                                "Peer {:?} requested chunk {} of {:?} out of range",
+                                peer, index, key
+                            );
+                            return Ok(());
+                        }
+                    }
+                    Request::Push(gistit) => {
+                        info!("Peer {:?} pushed gistit {}", peer, gistit.hash);
+                        // Fetched from the inbox over the bridge, which takes no larger ones
+                        let accepted = if !gistit.is_intact() {
+                            error!("Refusing corrupted gistit pushed by {:?}", peer);
+                            false
+                        } else if gistit.encoded_len() > validate::ENCODED_SIZE {
+                            warn!("Refusing oversized gistit pushed by {:?}", peer);
+                            false
+                        } else {
+                            node.inbox.push(peer, gistit);
+                            true
+                        };
+                        Response::Pushed(accepted)
+                    }
+                    Request::Replicate(gistit) => {
+                        info!("Peer {:?} asked us to replicate {}", peer, gistit.hash);
+                        let accepted = if !node.trust.is_listed(&peer) {
+                            warn!("Not replicating for {:?}, not in the allowlist", peer);
+                            false
+                        } else if !gistit.is_intact() {
+                            error!("Refusing corrupted gistit to replicate from {:?}", peer);
+                            false
+                        } else {
+                            node.reseed_fetched(&gistit)
+                        };
+                        Response::Pushed(accepted)
+                    }
+                    Request::TooLarge { max_size } => {
+                        warn!("Peer {:?} sent a request over {} bytes", peer, max_size);
+                        Response::TooLarge { max_size }
+                    }
+                };
 
                 node.swarm
                     .behaviour_mut()
                     .request_response
-                    .send_response(channel, Response(file))?;
+                    .send_response(channel, response)?;
+
+                if let Some((hash, bytes)) = served {
+                    node.audit.record(&peer, &hash, bytes);
+                }
+                // Burned once delivered, see `RequestResponseEvent::ResponseSent`
+                if let Some((key, chunk)) = burned {
+                    node.burns.sending(request_id, peer, key, chunk);
+                }
             }
             RequestResponseMessage::Response {
                 request_id,
-                response,
+                response: Response::Pushed(accepted),
             } => {
-                info!("Request response 'Message::Response'");
-                let gistit = response.0;
-                let key = Key::new(&gistit.hash.as_bytes());
+                if node.replications.contains(&request_id) {
+                    info!(
+                        "Replication acknowledged by {:?}, accepted: {}",
+                        peer, accepted
+                    );
+                    let step = node.replications.answered(&request_id, accepted);
+                    node.replicate(step);
+                    return Ok(());
+                }
 
-                if node.pending_receive_file.remove(&key) {
-                    node.bridge.connect_blocking()?;
+                info!("Push acknowledged, accepted: {}", accepted);
+                if node.pending_push.remove(&request_id) {
+                    let error = (!accepted).then(|| "peer refused the gistit".to_owned());
+                    node.bridge.connect().await?;
+                    node.bridge
+                        .send(Instruction::respond_send_to_peer(accepted, error))
+                        .await?;
+                }
+            }
+            RequestResponseMessage::Response {
+                request_id,
+                response: Response::Gistit(gistit),
+            } => {
+                info!("Request response 'Message::Response'");
+                node.latency.answered(&request_id);
+                receive_gistit(node, request_id, gistit).await?;
+            }
+            RequestResponseMessage::Response {
+                request_id,
+                response: Response::Chunk { index, total, data },
+            } => {
+                debug!("Received chunk {} of {} from {:?}", index, total, peer);
+                node.latency.answered(&request_id);
+                receive_chunk(node, peer, request_id, (index, total, &data)).await?;
+            }
+            RequestResponseMessage::Response {
+                request_id,
+                response: refusal @ (Response::TooLarge { .. } | Response::RateLimited),
+            } => {
+                let reason = if let Response::TooLarge { max_size } = refusal {
+                    warn!(
+                        "Message to or from {:?} over {} bytes refused",
+                        peer, max_size
+                    );
+                    format!("peer refused a gistit over {} bytes", max_size)
+                } else {
+                    warn!("Request to {:?} refused, we asked too often", peer);
+                    "peer is rate limiting us, try again later".to_owned()
+                };
+                if node.replications.contains(&request_id) {
+                    let step = node.replications.answered(&request_id, false);
+                    node.replicate(step);
+                    return Ok(());
+                }
+                if node.pending_push.remove(&request_id) {
+                    node.bridge.connect().await?;
                     node.bridge
-                        .send(Instruction::respond_fetch(Some(gistit)))
+                        .send(Instruction::respond_send_to_peer(false, Some(reason)))
                         .await?;
+                    return Ok(());
                 }
-                node.pending_request_file.remove(&request_id);
+                fail_request(node, request_id).await?;
             }
         },
         RequestResponseEvent::OutboundFailure {
             request_id, error, ..
         } => {
             error!("Request response outbound failure {:?}", error);
-            node.pending_request_file.remove(&request_id);
-            node.bridge.connect_blocking()?;
-            node.bridge.send(Instruction::respond_fetch(None)).await?;
+            if node.replications.contains(&request_id) {
+                let step = node.replications.answered(&request_id, false);
+                node.replicate(step);
+                return Ok(());
+            }
+            if node.pending_push.remove(&request_id) {
+                node.bridge.connect().await?;
+                node.bridge
+                    .send(Instruction::respond_send_to_peer(
+                        false,
+                        Some(format!("{:?}", error)),
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            fail_request(node, request_id).await?;
         }
-        RequestResponseEvent::InboundFailure { error, .. } => {
+        RequestResponseEvent::InboundFailure {
+            request_id, error, ..
+        } => {
             error!("Request response inbound failure {:?}", error);
+            node.burns.failed(&request_id);
         }
-        RequestResponseEvent::ResponseSent { .. } => (),
+        RequestResponseEvent::ResponseSent { request_id, .. } => {
+            if let Some(key) = node.burns.sent(&request_id) {
+                node.burn(&key);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn receive_gistit(node: &mut Node, request_id: RequestId, gistit: Gistit) -> Result<()> {
+    let key = match node.pending_request_file.get(&request_id) {
+        Some((key, _)) => key.clone(),
+        // Late answers for a gistit we already got
+        None => return Ok(()),
+    };
+    // An intact gistit is only consistent with itself, the provider may have sent another one
+    if gistit_key(&gistit.hash) != key {
+        error!(
+            "Discarding gistit {}, it isn't the one requested",
+            gistit.hash
+        );
+        fail_request(node, request_id).await?;
+        return Ok(());
     }
+    if !gistit.is_intact() {
+        error!("Discarding corrupted gistit, content doesn't match hash");
+        fail_request(node, request_id).await?;
+        return Ok(());
+    }
+
+    node.reseed_fetched(&gistit);
+
+    if node.pending_receive_file.remove(&key) {
+        node.bridge.connect().await?;
+        node.bridge
+            .send(Instruction::respond_fetch(Some(gistit)))
+            .await?;
+    }
+    node.pending_request_file.remove(&request_id);
+    node.partials.remove(&key);
+
+    // Whatever the slower providers answer now is ignored
+    let latency = &mut node.latency;
+    node.pending_request_file
+        .retain(|request_id, (pending, _)| {
+            let cancel = *pending == key;
+            if cancel {
+                latency.forget(request_id);
+            }
+            !cancel
+        });
+    node.fallback_providers.remove(&key);
     Ok(())
 }
 
+/// Stores a chunk of a gistit being fetched, then asks the same peer for another one nobody was
+/// asked for until the gistit is complete. Peers left with nothing to fetch are kept to ask
+/// first if another one fails
+async fn receive_chunk(
+    node: &mut Node,
+    peer: PeerId,
+    request_id: RequestId,
+    (index, total, data): (u32, u32, &[u8]),
+) -> Result<()> {
+    let key = match node.pending_request_file.get(&request_id) {
+        // Late answers for a gistit we already got
+        Some((key, _)) if node.pending_receive_file.contains(key) => key.clone(),
+        _ => {
+            node.pending_request_file.remove(&request_id);
+            return Ok(());
+        }
+    };
+
+    match node.partials.append(&key, index, total, data) {
+        // Another provider may have been ahead with this one, this peer still works
+        Ok(progress @ (Progress::Stored | Progress::Stale)) => {
+            if progress == Progress::Stale {
+                debug!("Chunk {} of {:?} was already there", index, key);
+            }
+            node.pending_request_file.remove(&request_id);
+            if !node.request_chunk(peer, key.clone()) {
+                node.fallback_providers
+                    .entry(key)
+                    .or_default()
+                    .insert(0, peer);
+            }
+        }
+        Ok(Progress::Done(bytes)) => match Gistit::decode(&*bytes) {
+            Ok(gistit) => receive_gistit(node, request_id, gistit).await?,
+            Err(err) => {
+                error!("Discarding gistit assembled from chunks: {}", err);
+                fail_request(node, request_id).await?;
+            }
+        },
+        Err(err) => {
+            error!("Failed to store chunk {} of {:?}: {}", index, key, err);
+            fail_request(node, request_id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether to serve a request from `peer`, banning it if it keeps going over the rate limit
+fn within_rate_limit(node: &mut Node, peer: PeerId) -> bool {
+    match node.limiter.check(&peer) {
+        Verdict::Allow => true,
+        Verdict::Refuse => {
+            warn!("Peer {:?} exceeded the request rate limit", peer);
+            false
+        }
+        Verdict::Ban => {
+            warn!(
+                "Peer {:?} kept exceeding the request rate limit, banning",
+                peer
+            );
+            node.limiter.ban(peer);
+            node.swarm.ban_peer_id(peer);
+            false
+        }
+    }
+}
+
+/// Drops a failed file request. Responds with nothing once every provider for that key failed
+async fn fail_request(node: &mut Node, request_id: RequestId) -> Result<()> {
+    node.latency.forget(&request_id);
+    if let Some((key, index)) = node.pending_request_file.remove(&request_id) {
+        // Whoever asks next gets it
+        node.partials.release(&key, index);
+        let waiting_others = node.pending_request_file.values().any(|(k, _)| *k == key);
+        if waiting_others || !node.pending_receive_file.contains(&key) {
+            return Ok(());
+        }
+
+        let fallback = node
+            .fallback_providers
+            .get_mut(&key)
+            .map_or_else(Vec::new, |peers| {
+                let batch = peers.len().min(PARALLEL_REQUESTS);
+                peers.drain(..batch).collect()
+            });
+        if !fallback.is_empty() {
+            info!(
+                "Every provider asked failed, trying {} more",
+                fallback.len()
+            );
+            for peer in fallback {
+                if !node.request_chunk(peer, key.clone()) {
+                    node.fallback_providers
+                        .entry(key.clone())
+                        .or_default()
+                        .push(peer);
+                }
+            }
+            return Ok(());
+        }
+
+        node.fallback_providers.remove(&key);
+        node.partials.abandon(&key);
+        if node.pending_receive_file.remove(&key) {
+            node.bridge.connect().await?;
+            node.bridge
+                .send(Instruction::respond_error(
+                    Code::NoProviders,
+                    Some("every provider failed to send it".to_owned()),
+                ))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// What a query was for, in logs
+const fn query_kind(result: &QueryResult) -> &'static str {
+    match result {
+        QueryResult::Bootstrap(_) => "bootstrap",
+        QueryResult::GetClosestPeers(_) => "get closest peers",
+        QueryResult::GetProviders(_) => "get providers",
+        QueryResult::StartProviding(_) => "start providing",
+        QueryResult::RepublishProvider(_) => "republish provider",
+        QueryResult::GetRecord(_) => "get record",
+        QueryResult::PutRecord(_) => "put record",
+        QueryResult::RepublishRecord(_) => "republish record",
+    }
+}
+
 pub async fn handle_kademlia(node: &mut Node, event: KademliaEvent) -> Result<()> {
+    if let KademliaEvent::OutboundQueryCompleted { id, result, stats } = &event {
+        debug!(
+            "Kademlia {} query {:?} took {:?}, {} requests to peers, {} failed",
+            query_kind(result),
+            id,
+            stats.duration().unwrap_or_default(),
+            stats.num_requests(),
+            stats.num_failures()
+        );
+    }
+
     match event {
+        KademliaEvent::OutboundQueryCompleted { id, result, .. }
+            if node
+                .history_sync
+                .as_mut()
+                .map_or(false, |sync| sync.finished(&id)) =>
+        {
+            handle_sync_query(node, result);
+            Ok(())
+        }
+        KademliaEvent::OutboundQueryCompleted {
+            id,
+            result: QueryResult::StartProviding(maybe_provided),
+            ..
+        } if node.pending_provide_dir.remove(&id) => {
+            // Nobody is waiting on directory provides, peers asking us directly still get it
+            if let Err(err) = maybe_provided {
+                error!("Kademlia provide from directory failed: {:?}", err);
+            }
+            Ok(())
+        }
+        KademliaEvent::OutboundQueryCompleted {
+            id,
+            result: QueryResult::StartProviding(maybe_provided),
+            ..
+        } if node.pending_reseed.remove(&id) => {
+            // Nobody is waiting on reseeds
+            if let Err(err) = maybe_provided {
+                error!("Kademlia reseed failed: {:?}", err);
+                let key = node.to_provide.key_of(err.key());
+                node.to_provide.remove(&key);
+                node.reseed.remove(&key);
+            }
+            Ok(())
+        }
+        KademliaEvent::OutboundQueryCompleted {
+            id,
+            result: QueryResult::StartProviding(maybe_provided),
+            ..
+        } if node.provide_batches.contains(&id) => {
+            let error = maybe_provided.err().map(|err| {
+                error!("Kademlia start providing failed: {:?}", err);
+                let key = node.to_provide.key_of(err.key());
+                node.to_provide.remove(&key);
+                "failed to publish the provider record".to_owned()
+            });
+
+            if let Some(results) = node.provide_batches.complete(&id, error) {
+                node.bridge.connect().await?;
+                node.bridge
+                    .send(Instruction::respond_provide_many(results))
+                    .await?;
+            }
+            Ok(())
+        }
         KademliaEvent::OutboundQueryCompleted {
             id,
             result: QueryResult::StartProviding(maybe_provided),
             ..
         } => {
             node.pending_start_providing.remove(&id);
-            node.bridge.connect_blocking()?;
+            node.bridge.connect().await?;
 
             match maybe_provided {
                 Ok(provider) => {
                     info!("Kademlia start providing: {:?}", provider);
-                    let hash = str::from_utf8(&provider.key.to_vec())
-                        .expect("hash format to be valid utf8")
-                        .to_owned();
-                    node.bridge
-                        .send(Instruction::respond_provide(Some(hash)))
-                        .await?;
+                    let key = node.to_provide.key_of(&provider.key);
+                    match str::from_utf8(&key.to_vec())
+                        .ok()
+                        .and_then(|key| GistitHash::parse(key).ok())
+                    {
+                        Some(hash) => {
+                            node.bridge
+                                .send(Instruction::respond_provide(Status::Accepted, hash))
+                                .await?;
+                        }
+                        None => error!("Provided {:?}, which isn't a gistit hash", provider.key),
+                    }
                 }
                 Err(provider) => {
                     error!("Kademlia start providing failed: {:?}", provider);
-                    node.to_provide.remove(provider.key());
-                    node.bridge.send(Instruction::respond_provide(None)).await?;
+                    let key = node.to_provide.key_of(provider.key());
+                    node.to_provide.remove(&key);
+                    // Timing out is the only way publishing provider records fails
+                    node.bridge
+                        .send(Instruction::respond_error(
+                            Code::Timeout,
+                            Some("failed to publish the provider record".to_owned()),
+                        ))
+                        .await?;
                 }
             }
             Ok(())
@@ -102,35 +557,156 @@ pub async fn handle_kademlia(node: &mut Node, event: KademliaEvent) -> Result<()
             ..
         } => {
             info!("Kademlia get providers: {:?}", maybe_providers);
-            node.pending_get_providers.remove(&id);
-            let mut failed = false;
-
-            match maybe_providers {
-                Ok(GetProvidersOk { key, providers, .. }) => {
-                    // Finding zero providers is also an error
-                    if providers.is_empty() {
-                        failed = true;
-                    } else {
-                        node.to_request.push((key, providers));
-                    }
+            // Either abandoned or not a fetch of ours
+            let key = match node.pending_get_providers.remove(&id) {
+                Some(key) => key,
+                None => return Ok(()),
+            };
+            let cut = node.lookups.completed(&id);
+            // Gistits are looked up under both their public and private provider key
+            let siblings = node.pending_lookups_for(&key);
+
+            let failure = match maybe_providers {
+                Ok(GetProvidersOk { providers, .. }) if !providers.is_empty() => {
+                    node.abandon_lookups(&siblings);
+                    node.to_request.push((key, providers));
+                    None
+                }
+                // The other lookup may still find it
+                _ if !siblings.is_empty() => None,
+                // Finding zero providers is also an error
+                Ok(_) if cut => {
+                    error!("No providers for {:?} in time", key);
+                    Some(Code::Timeout)
                 }
-                Err(GetProvidersError::Timeout { key, .. }) => {
-                    failed = true;
+                Ok(_) => Some(Code::NoProviders),
+                Err(GetProvidersError::Timeout { .. }) => {
                     error!("No providers for {:?}", key);
+                    Some(Code::Timeout)
                 }
-            }
+            };
 
-            if failed {
-                node.bridge.connect_blocking()?;
-                node.bridge.send(Instruction::respond_fetch(None)).await?;
+            if let Some(code) = failure {
+                node.bridge.connect().await?;
+                node.bridge
+                    .send(Instruction::respond_error(code, None))
+                    .await?;
             }
 
             Ok(())
         }
+        KademliaEvent::OutboundQueryCompleted {
+            id,
+            result: QueryResult::GetRecord(maybe_records),
+            ..
+        } if node.pending_get_metadata.remove(&id) => {
+            let cut = node.lookups.completed(&id);
+            // Anyone can put a record under any key, and without the file contents nothing in it
+            // can be checked against the hash. Records naming another gistit are skipped, what's
+            // left is passed on as the publisher claims it
+            let response = match maybe_records {
+                Ok(GetRecordOk { records, .. }) => {
+                    Instruction::respond_metadata(records.into_iter().find_map(|peer_record| {
+                        Gistit::decode(&*peer_record.record.value)
+                            .ok()
+                            .filter(|gistit| {
+                                metadata_key(gistit.hash.as_bytes()) == peer_record.record.key
+                            })
+                            .map(|gistit| gistit.metadata())
+                    }))
+                }
+                Err(GetRecordError::NotFound { .. }) if cut => {
+                    error!("No metadata record in time");
+                    Instruction::respond_error(Code::Timeout, None)
+                }
+                Err(GetRecordError::NotFound { .. }) => {
+                    info!("No metadata record");
+                    Instruction::respond_metadata(None)
+                }
+                Err(err) => {
+                    error!("Metadata record lookup failed: {:?}", err);
+                    Instruction::respond_error(Code::Timeout, Some(format!("{:?}", err)))
+                }
+            };
+
+            node.bridge.connect().await?;
+            node.bridge.send(response).await?;
+            Ok(())
+        }
+        KademliaEvent::OutboundQueryCompleted {
+            result: QueryResult::PutRecord(Err(err)),
+            ..
+        } => {
+            warn!("Kademlia put record failed: {:?}", err);
+            Ok(())
+        }
+        KademliaEvent::InboundRequest {
+            request:
+                InboundRequest::AddProvider {
+                    record: Some(record),
+                },
+        } => {
+            node.store_provider(record);
+            Ok(())
+        }
+        KademliaEvent::InboundRequest {
+            request:
+                InboundRequest::PutRecord {
+                    record: Some(record),
+                    ..
+                },
+        } => {
+            // Stored as kademlia would, only provider records are of interest to us
+            if let Err(err) = node.swarm.behaviour_mut().kademlia.store_mut().put(record) {
+                warn!("Failed to store record: {:?}", err);
+            }
+            Ok(())
+        }
+        KademliaEvent::OutboundQueryCompleted {
+            result: QueryResult::Bootstrap(maybe_bootstrapped),
+            ..
+        } => {
+            match maybe_bootstrapped {
+                Ok(BootstrapOk {
+                    num_remaining: 0, ..
+                }) => {
+                    info!("Kademlia bootstrapped");
+                    node.bootstrapped = true;
+                    node.bootstrap_error = None;
+                    // Other nodes of the group can be found now
+                    node.sync_history();
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    error!("Kademlia bootstrap failed: {:?}", err);
+                    node.bootstrap_error = Some(format!("{:?}", err));
+                }
+            }
+            Ok(())
+        }
         _ => Ok(()),
     }
 }
 
+/// Syncs with the other nodes providing our sync group record, nobody waits on these queries
+fn handle_sync_query(node: &mut Node, result: QueryResult) {
+    match result {
+        QueryResult::GetProviders(Ok(GetProvidersOk { providers, .. })) => {
+            let local = *node.swarm.local_peer_id();
+            for peer_id in providers.into_iter().filter(|peer_id| *peer_id != local) {
+                node.sync_with(peer_id);
+            }
+        }
+        QueryResult::GetProviders(Err(err)) => {
+            debug!("No other node of the sync group found: {:?}", err);
+        }
+        QueryResult::StartProviding(Err(err)) => {
+            warn!("Failed to provide the sync group record: {:?}", err);
+        }
+        _ => (),
+    }
+}
+
 pub fn handle_identify(node: &mut Node, event: IdentifyEvent) -> Result<()> {
     if let IdentifyEvent::Received {
         peer_id,
@@ -138,11 +714,19 @@ pub fn handle_identify(node: &mut Node, event: IdentifyEvent) -> Result<()> {
             IdentifyInfo {
                 listen_addrs,
                 protocols,
+                observed_addr,
                 ..
             },
     } = event
     {
         debug!("Identify: {:?}, protocols: {:?}", listen_addrs, protocols);
+        let supports_pex = protocols.iter().any(|p| p.as_bytes() == PEX_PROTOCOL);
+        if node.pex.as_mut().map_or(false, |pex| {
+            pex.identified(peer_id, &listen_addrs, &observed_addr, supports_pex)
+        }) {
+            node.exchange_peers(peer_id);
+        }
+
         if protocols.iter().any(|p| p.as_bytes() == KADEMLIA_PROTO) {
             for addr in &listen_addrs {
                 node.swarm
@@ -153,27 +737,138 @@ pub fn handle_identify(node: &mut Node, event: IdentifyEvent) -> Result<()> {
         }
 
         if protocols.iter().any(|p| p.as_bytes() == RELAY_HOP_PROTO) {
-            for addr in listen_addrs {
-                // Don't attempt to relay over the relay
-                if addr.iter().any(|t| matches!(t, Protocol::P2pCircuit)) {
-                    continue;
+            for addr in &listen_addrs {
+                if let Some(circuit) = node.relays.candidate(&peer_id, addr) {
+                    info!("Reserving a slot on relay {:?}", circuit);
+                    let listener = node.swarm.listen_on(circuit.clone())?;
+                    node.relays.insert(peer_id, circuit, listener);
                 }
+            }
+        }
 
-                let peer = addr
-                    .with(Protocol::P2p(peer_id.into()))
-                    .with(Protocol::P2pCircuit);
+        // The address the peer saw us at may be a new external one
+        node.remember_addresses();
+    }
+    Ok(())
+}
 
-                if node.relays.contains(&peer) {
-                    continue;
+pub fn handle_pex(node: &mut Node, event: RequestResponseEvent<Vec<Multiaddr>, Vec<Multiaddr>>) {
+    match event {
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                },
+        } => {
+            if !within_rate_limit(node, peer) {
+                // Nothing to learn from us this time
+                if let Some(pex) = node.swarm.behaviour_mut().pex.as_mut() {
+                    let _ = pex.send_response(channel, Vec::new());
                 }
+                return;
+            }
+            if !node.trust.is_allowed(&peer) {
+                warn!("Ignoring peer exchange from untrusted peer {:?}", peer);
+                return;
+            }
 
-                info!("Listening on relay {:?}", peer);
-                node.relays.insert(peer.clone());
-                node.swarm.listen_on(peer)?;
+            let trust = &node.trust;
+            let sample = node
+                .pex
+                .as_ref()
+                .map(|pex| pex.sample(&peer, |other| trust.is_allowed(other)))
+                .unwrap_or_default();
+            if let Some(pex) = node.swarm.behaviour_mut().pex.as_mut() {
+                if pex.send_response(channel, sample).is_err() {
+                    debug!("Peer exchange with {:?} closed before we answered", peer);
+                }
             }
+            node.learn_peers(&peer, request);
+        }
+        RequestResponseEvent::Message {
+            peer,
+            message: RequestResponseMessage::Response { response, .. },
+        } => node.learn_peers(&peer, response),
+        RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+            debug!("Peer exchange with {:?} failed: {:?}", peer, error);
         }
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            debug!("Peer exchange from {:?} failed: {:?}", peer, error);
+        }
+        RequestResponseEvent::ResponseSent { .. } => (),
+    }
+}
+
+pub fn handle_sync(node: &mut Node, event: RequestResponseEvent<Vec<u8>, Vec<u8>>) {
+    match event {
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                },
+        } => {
+            if !within_rate_limit(node, peer) {
+                // Same as a history we don't merge
+                if let Some(sync) = node.swarm.behaviour_mut().sync.as_mut() {
+                    let _ = sync.send_response(channel, Vec::new());
+                }
+                return;
+            }
+            if !node.trust.is_allowed(&peer) {
+                warn!("Ignoring history sync from untrusted peer {:?}", peer);
+                return;
+            }
+
+            // Only nodes of our group get our history back, answered once it's merged
+            if let Some(sync) = &node.history_sync {
+                sync.answer(peer, channel, request);
+            }
+        }
+        RequestResponseEvent::Message {
+            peer,
+            message: RequestResponseMessage::Response { response, .. },
+        } => {
+            if let Some(sync) = node.history_sync.as_ref().filter(|_| !response.is_empty()) {
+                sync.merge_answer(peer, response);
+            }
+        }
+        RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+            debug!("History sync with {:?} failed: {:?}", peer, error);
+        }
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            debug!("History sync from {:?} failed: {:?}", peer, error);
+        }
+        RequestResponseEvent::ResponseSent { .. } => (),
+    }
+}
+
+pub fn handle_relay_client(node: &mut Node, event: ClientEvent) {
+    match event {
+        ClientEvent::ReservationReqAccepted { relay_peer_id, .. } => {
+            if node.relays.on_reserved(&relay_peer_id) {
+                info!("Reservation accepted by relay {:?}", relay_peer_id);
+            }
+        }
+        ClientEvent::ReservationReqFailed {
+            relay_peer_id,
+            error,
+            ..
+        } => {
+            warn!(
+                "Reservation refused by relay {:?}: {:?}",
+                relay_peer_id, error
+            );
+            for listener in node.relays.on_failed(&relay_peer_id) {
+                node.swarm.remove_listener(listener);
+            }
+        }
+        ClientEvent::InboundCircuitEstablished { src_peer_id, .. } => {
+            info!("Relayed connection from {:?}", src_peer_id);
+        }
+        ev => debug!("Relay client: {:?}", ev),
     }
-    Ok(())
 }
 
 const KADEMLIA_PROTO: &[u8] = b"/ipfs/kad/1.0.0";
@@ -0,0 +1,212 @@
+//! Provider health
+//!
+//! Kademlia keeps the provider records other peers publish through us until they expire, long
+//! after a provider went away, and hands them to whoever looks the gistit up. Every
+//! [`CHECK_INTERVAL`] we dial up to [`MAX_CHECKS`] of the providers of the records we hold that
+//! we aren't connected to, the ones checked longest ago first, and drop their records once they
+//! failed [`MAX_FAILURES`] checks in a row. Only the global addresses of a record are dialed,
+//! see [`crate::address`].
+//!
+//! Records from elsewhere may still name dead providers, so fetches don't wait on one either: a
+//! provider that hasn't answered in a third of `--request-timeout` is still waited on, but the
+//! next one is asked too.
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use libp2p::kad::record::Key;
+use libp2p::kad::ProviderRecord;
+use libp2p::{Multiaddr, PeerId};
+
+use crate::address;
+
+/// How often the providers of the records we hold are checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Most providers dialed per check
+pub const MAX_CHECKS: usize = 32;
+
+/// Providers failing this many checks in a row are dropped
+pub const MAX_FAILURES: u32 = 2;
+
+/// How long a provider may take to answer before the next one is asked too, out of the time a
+/// request may take
+#[must_use]
+pub fn provider_timeout(request_timeout: Duration) -> Duration {
+    request_timeout / 3
+}
+
+#[derive(Debug, Default)]
+struct Provider {
+    /// Keys of the records we hold for it
+    keys: HashSet<Key>,
+    /// Where it said it listens
+    addresses: Vec<Multiaddr>,
+    /// Failed checks in a row
+    failures: u32,
+    /// Dialed by the running check
+    checking: bool,
+    /// The check it was last dialed by
+    checked: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ProviderHealth {
+    providers: HashMap<PeerId, Provider>,
+    /// Checks so far
+    checks: u64,
+}
+
+impl ProviderHealth {
+    /// Remembers a provider record we store
+    pub fn track(&mut self, record: &ProviderRecord) {
+        let provider = self.providers.entry(record.provider).or_default();
+        provider.keys.insert(record.key.clone());
+        let addresses: Vec<Multiaddr> = record
+            .addresses
+            .iter()
+            .filter(|address| address::is_global(address))
+            .cloned()
+            .collect();
+        if !addresses.is_empty() {
+            provider.addresses = addresses;
+        }
+    }
+
+    /// Forgets records that are no longer `stored`, kademlia expires them on its own
+    pub fn retain(&mut self, mut stored: impl FnMut(&Key, &PeerId) -> bool) {
+        for (peer, provider) in &mut self.providers {
+            provider.keys.retain(|key| stored(key, peer));
+        }
+        self.providers
+            .retain(|_, provider| !provider.keys.is_empty());
+    }
+
+    /// Up to `max` providers to dial and where, the ones checked longest ago. The `connected`
+    /// ones are alive already
+    pub fn due(
+        &mut self,
+        connected: impl Fn(&PeerId) -> bool,
+        max: usize,
+    ) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        self.checks += 1;
+        let mut candidates = Vec::new();
+        for (peer, provider) in &mut self.providers {
+            if connected(peer) {
+                provider.failures = 0;
+            } else if !provider.checking {
+                candidates.push((provider.checked, *peer));
+            }
+        }
+        candidates.sort_unstable();
+
+        let mut due = Vec::new();
+        for (_, peer) in candidates.into_iter().take(max) {
+            if let Some(provider) = self.providers.get_mut(&peer) {
+                provider.checking = true;
+                provider.checked = self.checks;
+                due.push((peer, provider.addresses.clone()));
+            }
+        }
+        due
+    }
+
+    pub fn alive(&mut self, peer: &PeerId) {
+        if let Some(provider) = self.providers.get_mut(peer) {
+            provider.failures = 0;
+            provider.checking = false;
+        }
+    }
+
+    /// A check of `peer` failed. Returns the keys whose records to drop once it failed
+    /// [`MAX_FAILURES`] in a row. Failures of dials other than ours don't count
+    pub fn failed(&mut self, peer: &PeerId) -> Vec<Key> {
+        match self.providers.get_mut(peer) {
+            Some(provider) if provider.checking => {
+                provider.checking = false;
+                provider.failures += 1;
+                if provider.failures < MAX_FAILURES {
+                    return Vec::new();
+                }
+            }
+            _ => return Vec::new(),
+        }
+        self.providers
+            .remove(peer)
+            .map(|provider| provider.keys.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.providers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_drops_providers_failing_in_a_row() {
+        let (gone, flaky, online) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let address: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+        let private: Multiaddr = "/ip4/192.168.1.5/tcp/4001".parse().unwrap();
+        let (first, second) = (Key::new(&"first"), Key::new(&"second"));
+
+        let mut health = ProviderHealth::default();
+        health.track(&ProviderRecord::new(
+            first.clone(),
+            gone,
+            vec![address.clone(), private.clone()],
+        ));
+        health.track(&ProviderRecord::new(second.clone(), gone, Vec::new()));
+        // Private addresses are never dialed on a record's word
+        health.track(&ProviderRecord::new(first.clone(), flaky, vec![private]));
+        health.track(&ProviderRecord::new(first.clone(), online, Vec::new()));
+
+        let mut due = health.due(|peer| *peer == online, MAX_CHECKS);
+        due.sort_by_key(|(peer, _)| *peer == flaky);
+        assert_eq!(due, vec![(gone, vec![address]), (flaky, Vec::new())]);
+        // Still being checked
+        assert!(health.due(|peer| *peer == online, MAX_CHECKS).is_empty());
+
+        assert!(health.failed(&gone).is_empty());
+        health.alive(&flaky);
+        // Dials we didn't make for a check
+        assert!(health.failed(&online).is_empty());
+        assert!(health.failed(&flaky).is_empty());
+
+        assert_eq!(health.due(|peer| *peer == online, MAX_CHECKS).len(), 2);
+        let mut dropped = health.failed(&gone);
+        dropped.sort_by_key(|key| key.to_vec());
+        assert_eq!(dropped, vec![first, second.clone()]);
+        assert!(health.failed(&flaky).is_empty());
+        assert_eq!(health.len(), 2);
+
+        // Expired records are forgotten
+        health.retain(|_, peer| *peer != online);
+        assert_eq!(health.len(), 1);
+        health.retain(|key, _| *key == second);
+        assert_eq!(health.len(), 0);
+    }
+
+    #[test]
+    fn health_checks_a_few_at_a_time() {
+        let mut health = ProviderHealth::default();
+        let peers: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+        for peer in &peers {
+            health.track(&ProviderRecord::new(Key::new(&"key"), *peer, Vec::new()));
+        }
+
+        let first = health.due(|_| false, 2);
+        assert_eq!(first.len(), 2);
+        for (peer, _) in &first {
+            health.alive(peer);
+        }
+        // The one left out goes first next time
+        let second = health.due(|_| false, 2);
+        assert_eq!(second.len(), 2);
+        assert!(first.iter().all(|(peer, _)| *peer != second[0].0));
+        assert!(first.iter().any(|(peer, _)| *peer == second[1].0));
+    }
+}
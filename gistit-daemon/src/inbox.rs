@@ -0,0 +1,127 @@
+//! Gistits pushed straight to us by other peers
+//!
+//! Kept in memory only, the oldest entries are dropped once the inbox is full.
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libp2p::core::PeerId;
+
+use gistit_proto::{ipc, Gistit};
+
+/// Max gistits kept in the inbox
+const INBOX_CAPACITY: usize = 100;
+
+#[derive(Debug)]
+pub struct Entry {
+    pub peer_id: PeerId,
+    pub gistit: Gistit,
+    pub received_at: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Inbox {
+    /// Most recent first
+    entries: VecDeque<Entry>,
+}
+
+impl Inbox {
+    pub fn push(&mut self, peer_id: PeerId, gistit: Gistit) {
+        self.entries
+            .retain(|entry| entry.gistit.hash != gistit.hash);
+        self.entries.push_front(Entry {
+            peer_id,
+            gistit,
+            received_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        });
+        self.entries.truncate(INBOX_CAPACITY);
+    }
+
+    #[must_use]
+    pub fn get(&self, hash: &str) -> Option<&Gistit> {
+        self.entries
+            .iter()
+            .find(|entry| entry.gistit.hash == hash)
+            .map(|entry| &entry.gistit)
+    }
+
+    /// Hashes of the received gistits starting with `prefix`, private ones only go by their full
+    /// hash
+    pub fn hashes_starting_with<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.gistit.private)
+            .map(|entry| entry.gistit.hash.as_str())
+            .filter(move |hash| hash.starts_with(prefix))
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Entries past the first `offset` ones, without their file contents
+    #[must_use]
+    pub fn summary(&self, offset: usize) -> Vec<ipc::instruction::InboxEntry> {
+        self.entries
+            .iter()
+            .skip(offset)
+            .map(|entry| ipc::instruction::InboxEntry {
+                peer_id: entry.peer_id.to_string(),
+                gistit: Some(entry.gistit.metadata()),
+                received_at: entry.received_at,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gistit(hash: &str) -> Gistit {
+        Gistit {
+            hash: hash.to_owned(),
+            inner: vec![Gistit::new_inner(
+                "main.rs".to_owned(),
+                "rust".to_owned(),
+                12,
+                "fn main() {}".to_owned(),
+            )],
+            ..Gistit::default()
+        }
+    }
+
+    #[test]
+    fn inbox_replaces_and_summarizes() {
+        let mut inbox = Inbox::default();
+        let peer_id = PeerId::random();
+
+        inbox.push(peer_id, gistit("foo"));
+        inbox.push(peer_id, gistit("bar"));
+        inbox.push(peer_id, gistit("foo"));
+        assert_eq!(inbox.len(), 2);
+        assert_eq!(inbox.get("foo").unwrap().inner[0].data, "fn main() {}");
+        assert_eq!(
+            inbox.hashes_starting_with("fo").collect::<Vec<_>>(),
+            vec!["foo"]
+        );
+
+        let summary = inbox.summary(0);
+        assert_eq!(summary[0].gistit.as_ref().unwrap().hash, "foo");
+        assert!(summary[0].gistit.as_ref().unwrap().inner[0].data.is_empty());
+        assert_eq!(inbox.summary(1)[0].gistit.as_ref().unwrap().hash, "bar");
+        assert!(inbox.summary(2).is_empty());
+
+        // Only fetched by the full hash
+        let mut private = gistit("fob");
+        private.private = true;
+        inbox.push(peer_id, private);
+        assert_eq!(
+            inbox.hashes_starting_with("fo").collect::<Vec<_>>(),
+            vec!["foo"]
+        );
+        assert!(inbox.get("fob").is_some());
+    }
+}
@@ -0,0 +1,118 @@
+//! Peer latency
+//!
+//! Round trips are measured on every answered request and every ping, and smoothed per peer.
+//! When several peers provide a gistit, the fastest known ones are asked first and the others are
+//! kept as a fallback, for when they fail or take too long to answer.
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use libp2p::request_response::RequestId;
+use libp2p::PeerId;
+use log::debug;
+
+/// How many providers are asked for a gistit at once
+pub const PARALLEL_REQUESTS: usize = 3;
+
+#[derive(Debug, Default)]
+pub struct Latency {
+    /// Smoothed round trip time per peer
+    rtt: HashMap<PeerId, Duration>,
+
+    /// Requests waiting for an answer and when they were sent
+    in_flight: HashMap<RequestId, (PeerId, Instant)>,
+
+    /// Requests in flight already reported by [`Self::overdue`]
+    overdue: HashSet<RequestId>,
+}
+
+impl Latency {
+    /// Adds a round trip sample, older samples weight 70%
+    pub fn record(&mut self, peer: PeerId, sample: Duration) {
+        let rtt = self
+            .rtt
+            .get(&peer)
+            .map_or(sample, |rtt| (*rtt * 7 + sample * 3) / 10);
+        self.rtt.insert(peer, rtt);
+    }
+
+    #[must_use]
+    pub fn get(&self, peer: &PeerId) -> Option<Duration> {
+        self.rtt.get(peer).copied()
+    }
+
+    pub fn sent(&mut self, request_id: RequestId, peer: PeerId) {
+        self.in_flight.insert(request_id, (peer, Instant::now()));
+    }
+
+    /// Records how long the request took to be answered
+    pub fn answered(&mut self, request_id: &RequestId) {
+        self.overdue.remove(request_id);
+        if let Some((peer, sent)) = self.in_flight.remove(request_id) {
+            debug!(
+                "Request {:?} answered by {:?} in {:?}",
+                request_id,
+                peer,
+                sent.elapsed()
+            );
+            self.record(peer, sent.elapsed());
+        }
+    }
+
+    /// Stops timing a request that failed or no longer matters
+    pub fn forget(&mut self, request_id: &RequestId) {
+        self.overdue.remove(request_id);
+        if let Some((peer, sent)) = self.in_flight.remove(request_id) {
+            debug!(
+                "Request {:?} to {:?} given up after {:?}",
+                request_id,
+                peer,
+                sent.elapsed()
+            );
+        }
+    }
+
+    /// Requests sent over `timeout` ago and still unanswered, each one is reported once
+    pub fn overdue(&mut self, timeout: Duration) -> Vec<(RequestId, PeerId)> {
+        let overdue: Vec<(RequestId, PeerId)> = self
+            .in_flight
+            .iter()
+            .filter(|(request_id, (_, sent))| {
+                sent.elapsed() >= timeout && !self.overdue.contains(request_id)
+            })
+            .map(|(request_id, (peer, _))| (*request_id, *peer))
+            .collect();
+        self.overdue
+            .extend(overdue.iter().map(|(request_id, _)| *request_id));
+        overdue
+    }
+
+    /// Orders peers fastest first, peers we never heard back from go last
+    #[must_use]
+    pub fn rank(&self, peers: impl IntoIterator<Item = PeerId>) -> Vec<PeerId> {
+        let mut peers: Vec<PeerId> = peers.into_iter().collect();
+        peers.sort_by_key(|peer| self.get(peer).unwrap_or(Duration::MAX));
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_rank_fastest_first() {
+        let (slow, fast, unknown) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut latency = Latency::default();
+
+        latency.record(slow, Duration::from_millis(300));
+        latency.record(fast, Duration::from_millis(500));
+        latency.record(fast, Duration::from_millis(100));
+        latency.record(fast, Duration::from_millis(100));
+        assert_eq!(latency.get(&fast), Some(Duration::from_millis(296)));
+
+        assert_eq!(
+            latency.rank([unknown, slow, fast]),
+            vec![fast, slow, unknown]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//
+//   ________.__          __  .__  __
+//  /  _____/|__| _______/  |_|__|/  |_
+// /   \  ___|  |/  ___/\   __\  \   __\
+// \    \_\  \  |\___ \  |  | |  ||  |
+//  \______  /__/____  > |__| |__||__|
+//         \/        \/
+//
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![cfg_attr(
+    test,
+    allow(
+        unused,
+        clippy::all,
+        clippy::pedantic,
+        clippy::nursery,
+        clippy::dbg_macro,
+        clippy::unwrap_used,
+        clippy::missing_docs_in_private_items,
+    )
+)]
+// Exported for the binary and `gistit-testkit`, not meant as a public api
+#![allow(
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::must_use_candidate
+)]
+//! The gistit p2p node. The `gistit-daemon` binary parses its arguments into a [`Config`] and
+//! runs a [`Node`] with it
+
+mod address;
+mod audit;
+mod batch;
+mod behaviour;
+mod bootstrap;
+mod burn;
+mod config;
+mod error;
+mod event;
+mod health;
+mod inbox;
+mod latency;
+mod limit;
+mod listen;
+mod node;
+mod partial;
+mod peers;
+mod pex;
+mod query;
+mod quota;
+mod relay;
+mod reload;
+mod replicate;
+mod reseed;
+mod resolve;
+mod store;
+mod supersede;
+mod sync;
+mod trust;
+mod watch;
+
+pub use config::Config;
+pub use limit::Limits;
+pub use node::Node;
+pub use pex::DEFAULT_PEX_INTERVAL;
+pub use query::Queries;
+pub use reload::Overrides;
+pub use reseed::DEFAULT_RESEED_CAP;
+pub use supersede::DEFAULT_SUPERSEDE_GRACE;
+
+pub type Error = crate::error::Error;
+pub type Result<T> = std::result::Result<T, Error>;
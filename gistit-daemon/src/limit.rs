@@ -0,0 +1,229 @@
+//! Peer limits
+//!
+//! Inbound requests are rate limited per peer with a token bucket. Requests over the limit are
+//! refused, and peers that keep going over it are banned for a while, and unbanned once the ban
+//! expires.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::core::PeerId;
+use libp2p::swarm::ConnectionLimits;
+
+use gistit_proto::validate;
+
+use crate::quota::{Quota, DEFAULT_MAX_HOSTED, DEFAULT_MAX_HOSTED_BYTES};
+
+/// Time it takes for an empty bucket to refill
+const REFILL_PERIOD: Duration = Duration::from_secs(60);
+
+/// Requests refused before a peer is banned, forgiven once its bucket is full again
+const STRIKES: u32 = 10;
+
+/// User configurable limits
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Max established connections, incoming and outgoing
+    pub max_connections: u32,
+
+    /// Max established connections with a single peer
+    pub max_connections_per_peer: u32,
+
+    /// Inbound requests allowed per peer each minute
+    pub requests_per_minute: u32,
+
+    /// How long misbehaving peers stay banned
+    pub ban_duration: Duration,
+
+    /// Largest message taken from a peer, in bytes
+    pub max_message_size: usize,
+
+    /// Gistits we provide on purpose, reseeded ones aside
+    pub max_hosted: usize,
+
+    /// Bytes the gistits we provide on purpose may take
+    pub max_hosted_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_connections: 128,
+            max_connections_per_peer: 2,
+            requests_per_minute: 60,
+            ban_duration: Duration::from_secs(10 * 60),
+            // The largest gistit, once encoded
+            max_message_size: validate::ENCODED_SIZE,
+            max_hosted: DEFAULT_MAX_HOSTED,
+            max_hosted_bytes: DEFAULT_MAX_HOSTED_BYTES,
+        }
+    }
+}
+
+impl Limits {
+    #[must_use]
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits::default()
+            .with_max_established(Some(self.max_connections))
+            .with_max_established_per_peer(Some(self.max_connections_per_peer))
+    }
+
+    #[must_use]
+    pub const fn quota(&self) -> Quota {
+        Quota::new(self.max_hosted, self.max_hosted_bytes)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// Requests refused since the bucket was last full
+    strikes: u32,
+}
+
+/// What to do with a peer request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+
+    /// Over the limit, refuse it
+    Refuse,
+
+    /// Over the limit once too often, ban the peer
+    Ban,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: Limits,
+    buckets: HashMap<PeerId, Bucket>,
+    bans: HashMap<PeerId, Instant>,
+
+    /// Requests dropped for exceeding the rate limit
+    pub rate_limited: u32,
+
+    /// Connections refused for exceeding connection limits
+    pub denied_connections: u32,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub const fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Buckets and bans in place are kept, they refill and expire as they would have. New bans
+    /// last the new duration
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Takes a token from this peer bucket, refusing the request if it ran out
+    pub fn check(&mut self, peer_id: &PeerId) -> Verdict {
+        let capacity = f64::from(self.limits.requests_per_minute);
+        let now = Instant::now();
+
+        let bucket = self.buckets.entry(*peer_id).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+            strikes: 0,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens =
+            capacity.min(bucket.tokens + elapsed * capacity / REFILL_PERIOD.as_secs_f64());
+        bucket.last_refill = now;
+        if bucket.tokens >= capacity {
+            bucket.strikes = 0;
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Verdict::Allow
+        } else {
+            self.rate_limited += 1;
+            bucket.strikes += 1;
+            if bucket.strikes >= STRIKES {
+                Verdict::Ban
+            } else {
+                Verdict::Refuse
+            }
+        }
+    }
+
+    pub fn ban(&mut self, peer_id: PeerId) {
+        self.buckets.remove(&peer_id);
+        self.bans
+            .insert(peer_id, Instant::now() + self.limits.ban_duration);
+    }
+
+    /// Removes and returns peers whose ban expired
+    pub fn expired_bans(&mut self) -> Vec<PeerId> {
+        let now = Instant::now();
+        let expired: Vec<PeerId> = self
+            .bans
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+
+        for peer_id in &expired {
+            self.bans.remove(peer_id);
+        }
+
+        expired
+    }
+
+    /// Drops buckets idle for long enough to be full again, they'd be recreated as such anyway
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        self.buckets
+            .retain(|_, bucket| now.duration_since(bucket.last_refill) < REFILL_PERIOD);
+    }
+
+    #[must_use]
+    pub fn banned_count(&self) -> usize {
+        self.bans.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_exhausts_and_bans() {
+        let mut limiter = RateLimiter::new(Limits {
+            requests_per_minute: 3,
+            ban_duration: Duration::from_secs(0),
+            ..Limits::default()
+        });
+        let peer_id = PeerId::random();
+
+        assert_eq!(limiter.check(&peer_id), Verdict::Allow);
+        assert_eq!(limiter.check(&peer_id), Verdict::Allow);
+        assert_eq!(limiter.check(&peer_id), Verdict::Allow);
+        assert_eq!(limiter.check(&peer_id), Verdict::Refuse);
+        assert_eq!(limiter.rate_limited, 1);
+
+        // Only peers that keep at it are banned
+        for _ in 2..STRIKES {
+            assert_eq!(limiter.check(&peer_id), Verdict::Refuse);
+        }
+        assert_eq!(limiter.check(&peer_id), Verdict::Ban);
+        assert_eq!(limiter.rate_limited, STRIKES);
+
+        limiter.ban(peer_id);
+        assert_eq!(limiter.banned_count(), 1);
+        assert_eq!(limiter.expired_bans(), vec![peer_id]);
+        assert_eq!(limiter.banned_count(), 0);
+    }
+}
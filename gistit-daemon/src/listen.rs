@@ -0,0 +1,275 @@
+//! Listen addresses
+//!
+//! Peers remember the addresses we listen on, picking a new port on every start would break
+//! their address books. The addresses we bound and the external addresses we learned are kept in
+//! the data directory, and the next start binds the same tcp ports again unless `--port` or
+//! `--randomize-port` is given. Relay circuits are left out, they are the relay's to hand out.
+//! A file that can't be read is started over, it only costs peers a new port.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use libp2p::core::Multiaddr;
+use libp2p::multiaddr::Protocol;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use gistit_project::file;
+
+use crate::relay::is_circuit;
+use crate::Result;
+
+pub const LISTEN_FILE: &str = "listen.json";
+
+/// The on disk format
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ListenFile {
+    #[serde(default)]
+    listen: Vec<String>,
+    #[serde(default)]
+    external: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ListenBook {
+    listen: Vec<Multiaddr>,
+    external: Vec<Multiaddr>,
+    path: PathBuf,
+    /// Bumped with every change, saving in the background skips writes a newer one overtook
+    generation: u64,
+    written: Arc<Mutex<u64>>,
+}
+
+/// The tcp port of a plain `/ip4/../tcp/..` or `/ip6/../tcp/..` address, and whether it's ipv6
+fn tcp_port(address: &Multiaddr) -> Option<(bool, u16)> {
+    let mut protocols = address.iter();
+    let ipv6 = match protocols.next()? {
+        Protocol::Ip4(_) => false,
+        Protocol::Ip6(_) => true,
+        _ => return None,
+    };
+    match (protocols.next()?, protocols.next()) {
+        (Protocol::Tcp(port), None) => Some((ipv6, port)),
+        _ => None,
+    }
+}
+
+fn read(path: &Path) -> Result<ListenFile> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    file::replace(path, contents)?;
+    Ok(())
+}
+
+fn parse(addresses: Vec<String>) -> Vec<Multiaddr> {
+    // A stale or hand edited entry shouldn't keep the node from starting
+    addresses
+        .into_iter()
+        .filter_map(|address| address.parse().ok())
+        .collect()
+}
+
+impl ListenBook {
+    /// Loads the addresses from `path`, none if the file doesn't exist or can't be read
+    #[must_use]
+    pub fn load(path: &Path) -> Self {
+        let file = if fs::metadata(path).is_ok() {
+            read(path).unwrap_or_else(|err| {
+                warn!("Starting the listen addresses over: {}", err);
+                ListenFile::default()
+            })
+        } else {
+            ListenFile::default()
+        };
+
+        Self {
+            listen: parse(file.listen),
+            external: parse(file.external),
+            path: path.to_path_buf(),
+            generation: 0,
+            written: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    fn contents(&self) -> Result<Vec<u8>> {
+        let file = ListenFile {
+            listen: self.listen.iter().map(ToString::to_string).collect(),
+            external: self.external.iter().map(ToString::to_string).collect(),
+        };
+        Ok(serde_json::to_vec_pretty(&file)?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        write(&self.path, &self.contents()?)
+    }
+
+    /// Saves on a blocking thread, so the event loop doesn't wait on the disk. Failures are only
+    /// logged
+    pub fn save_in_background(&self) {
+        let contents = match self.contents() {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!("Failed to save listen addresses: {:?}", err);
+                return;
+            }
+        };
+        let (path, generation, written) = (
+            self.path.clone(),
+            self.generation,
+            Arc::clone(&self.written),
+        );
+        tokio::task::spawn_blocking(move || {
+            let mut written = written.lock().unwrap_or_else(PoisonError::into_inner);
+            if *written > generation {
+                return;
+            }
+            match write(&path, &contents) {
+                Ok(()) => *written = generation,
+                Err(err) => warn!("Failed to save listen addresses: {:?}", err),
+            }
+        });
+    }
+
+    /// `address` on the port we listened on last time, for tcp addresses with port 0. `None` if
+    /// there's nothing to rebind
+    #[must_use]
+    pub fn rebind(&self, address: &Multiaddr) -> Option<Multiaddr> {
+        let ipv6 = match tcp_port(address)? {
+            (ipv6, 0) => ipv6,
+            _ => return None,
+        };
+        let port = self
+            .listen
+            .iter()
+            .filter_map(tcp_port)
+            .find_map(|(was_ipv6, port)| (was_ipv6 == ipv6 && port != 0).then(|| port))?;
+
+        Some(
+            address
+                .iter()
+                .map(|protocol| match protocol {
+                    Protocol::Tcp(_) => Protocol::Tcp(port),
+                    protocol => protocol,
+                })
+                .collect(),
+        )
+    }
+
+    /// External addresses learned last time on the port of one of the `rebound` addresses, still
+    /// good now that we listen there again
+    pub fn external_on<'a>(
+        &'a self,
+        rebound: &'a [Multiaddr],
+    ) -> impl Iterator<Item = &'a Multiaddr> {
+        let ports: Vec<u16> = rebound
+            .iter()
+            .filter_map(|address| tcp_port(address).map(|(_, port)| port))
+            .collect();
+        self.external.iter().filter(move |address| {
+            tcp_port(address).map_or(false, |(_, port)| ports.contains(&port))
+        })
+    }
+
+    /// Replaces the addresses with the ones we have now. Returns `true` if they changed
+    pub fn update<'a>(
+        &mut self,
+        listen: impl Iterator<Item = &'a Multiaddr>,
+        external: impl Iterator<Item = &'a Multiaddr>,
+    ) -> bool {
+        let listen: Vec<Multiaddr> = listen.filter(|a| !is_circuit(a)).cloned().collect();
+        let external: Vec<Multiaddr> = external.filter(|a| !is_circuit(a)).cloned().collect();
+        // Nothing bound yet, keep what we had for the next start
+        if listen.is_empty() || (listen == self.listen && external == self.external) {
+            return false;
+        }
+        self.listen = listen;
+        self.external = external;
+        self.generation += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn listen_book_rebinds_persisted_ports() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(LISTEN_FILE);
+        let mut book = ListenBook::load(&path);
+
+        let any_v4: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse().unwrap();
+        let any_v6: Multiaddr = "/ip6/::/tcp/0".parse().unwrap();
+        assert_eq!(book.rebind(&any_v4), None);
+
+        let bound: Multiaddr = "/ip4/192.168.1.5/tcp/40123".parse().unwrap();
+        let circuit: Multiaddr = "/ip4/1.2.3.4/tcp/4001/p2p-circuit".parse().unwrap();
+        let external: Multiaddr = "/ip4/203.0.113.7/tcp/40123".parse().unwrap();
+        assert!(!book.update([].iter(), [&external].into_iter()));
+        assert!(book.update([&bound, &circuit].into_iter(), [&external].into_iter()));
+        assert!(!book.update([&bound].into_iter(), [&external].into_iter()));
+        book.save().unwrap();
+
+        let book = ListenBook::load(&path);
+        assert_eq!(
+            book.rebind(&any_v4),
+            Some("/ip4/0.0.0.0/tcp/40123".parse().unwrap())
+        );
+        // Only ipv4 was bound
+        assert_eq!(book.rebind(&any_v6), None);
+        // Explicit ports and other transports are left alone
+        assert_eq!(book.rebind(&"/ip4/0.0.0.0/tcp/4001".parse().unwrap()), None);
+        assert_eq!(book.rebind(&"/ip4/0.0.0.0/tcp/0/ws".parse().unwrap()), None);
+
+        let rebound = [book.rebind(&any_v4).unwrap()];
+        assert_eq!(book.external_on(&rebound).collect::<Vec<_>>(), [&external]);
+        assert_eq!(book.external_on(&[any_v4]).count(), 0);
+    }
+
+    #[test]
+    fn listen_book_starts_over_from_a_broken_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(LISTEN_FILE);
+        fs::write(&path, r#"{"listen": ["/ip4/0.0.0.0/tcp/"#).unwrap();
+
+        let mut book = ListenBook::load(&path);
+        assert_eq!(book.rebind(&"/ip4/0.0.0.0/tcp/0".parse().unwrap()), None);
+
+        let bound: Multiaddr = "/ip4/192.168.1.5/tcp/40123".parse().unwrap();
+        assert!(book.update([&bound].into_iter(), [].iter()));
+        book.save().unwrap();
+        assert_eq!(ListenBook::load(&path).listen, [bound]);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn listen_book_saves_in_the_background() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(LISTEN_FILE);
+        let mut book = ListenBook::load(&path);
+
+        let first: Multiaddr = "/ip4/192.168.1.5/tcp/40123".parse().unwrap();
+        let second: Multiaddr = "/ip4/192.168.1.5/tcp/40124".parse().unwrap();
+        assert!(book.update([&first].into_iter(), [].iter()));
+        book.save_in_background();
+        assert!(book.update([&second].into_iter(), [].iter()));
+        book.save_in_background();
+
+        // Whichever ran last, the newer addresses are the ones kept
+        for _ in 0..100 {
+            if *book.written.lock().unwrap() == book.generation {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(ListenBook::load(&path).listen, [second]);
+    }
+}
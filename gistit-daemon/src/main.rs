@@ -20,25 +20,22 @@
     )
 )]
 
-mod behaviour;
-mod config;
-mod error;
-mod event;
-mod node;
-
-pub type Error = crate::error::Error;
-pub type Result<T> = std::result::Result<T, Error>;
-
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::Parser;
 
-use config::Config;
-use node::Node;
+use gistit_project::verbosity;
+
+use gistit_daemon::{
+    Config, Limits, Node, Overrides, Queries, Result, DEFAULT_PEX_INTERVAL, DEFAULT_RESEED_CAP,
+    DEFAULT_SUPERSEDE_GRACE,
+};
 
 /// Gistit p2p node
-#[derive(Parser, PartialEq, Debug)]
+#[derive(Parser, PartialEq, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     #[clap(long)]
@@ -49,50 +46,228 @@ struct Args {
     /// Override config directory
     config_path: Option<PathBuf>,
 
+    #[clap(long)]
+    /// Override data directory
+    data_path: Option<PathBuf>,
+
     #[clap(long)]
     /// IPFS config file to extract key material
     config_file: Option<PathBuf>,
 
     #[clap(long)]
-    /// Address to listen for connections
-    host: Option<Ipv4Addr>,
+    /// Addresses to listen for connections, ipv4 or ipv6 [default: 0.0.0.0 and ::]
+    host: Vec<IpAddr>,
 
     #[clap(long)]
-    /// Port to listen for connections
+    /// Port to listen for connections [default: the one from the last start, if still free]
     port: Option<u16>,
 
     #[clap(long)]
-    /// Dial these addresses on start
+    /// Listen on a random port instead of the one from the last start
+    randomize_port: bool,
+
+    #[clap(long)]
+    /// Dial these addresses on start, the ones ending with `/p2p/<peer id>` are remembered and
+    /// dialed again whenever we lose them. Names like `node@example.com` stand for the addresses
+    /// published in their DNS record
     dial: Vec<String>,
 
     #[clap(long)]
-    /// Listen to these addresses, useful for relays
+    /// Listen to these multiaddrs too, useful for relays
     listen: Vec<String>,
 
     #[clap(long)]
     /// Bootstrap this node
     bootstrap: bool,
+
+    #[clap(long)]
+    /// Bootstrap from these peers instead of the public libp2p ones, `/p2p/<peer id>` suffixed
+    bootstrap_peer: Vec<String>,
+
+    #[clap(long)]
+    /// Max established connections [default: 128]
+    max_connections: Option<u32>,
+
+    #[clap(long)]
+    /// Max established connections with a single peer [default: 2]
+    max_connections_per_peer: Option<u32>,
+
+    #[clap(long)]
+    /// Inbound requests allowed per peer each minute before banning it, `node-rate-limit` in the
+    /// settings [default: 60]
+    rate_limit: Option<u32>,
+
+    #[clap(long)]
+    /// How long misbehaving peers stay banned, in seconds, `node-ban-duration` in the settings
+    /// [default: 600]
+    ban_duration: Option<u64>,
+
+    #[clap(long)]
+    /// Largest message taken from a peer, in bytes. Bigger ones are refused [default: 149720]
+    max_message_size: Option<usize>,
+
+    #[clap(long)]
+    /// Max gistits provided from the client or `--provide-dir`, more are refused,
+    /// `node-max-hosted` in the settings [default: 1000]
+    max_hosted: Option<usize>,
+
+    #[clap(long)]
+    /// Bytes the gistits provided from the client or `--provide-dir` may take, more are refused,
+    /// `node-max-hosted-bytes` in the settings [default: 50000000]
+    max_hosted_bytes: Option<u64>,
+
+    #[clap(long)]
+    /// Peers a kademlia query, e.g. looking for the providers of a gistit, asks at once, up to
+    /// 20 [default: 5]
+    query_parallelism: Option<NonZeroUsize>,
+
+    #[clap(long)]
+    /// How long looking up the providers or metadata of a gistit may take, in seconds. Providing
+    /// and bootstrapping aren't cut short [default: 15]
+    query_timeout: Option<u64>,
+
+    #[clap(long)]
+    /// How long a peer may take to answer a request, e.g. for a gistit, in seconds [default: 10]
+    request_timeout: Option<u64>,
+
+    #[clap(long)]
+    /// Allowlist and denylist of peers we serve [default: <config-path>/trusted-peers.json]
+    trusted_peers: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Provide gistits fetched from other peers too
+    reseed: bool,
+
+    #[clap(long, default_value_t = DEFAULT_RESEED_CAP)]
+    /// Storage for reseeded gistits, in bytes
+    reseed_cap: u64,
+
+    #[clap(long)]
+    /// Provide every snippet in this directory, following changes to it
+    provide_dir: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Author of the gistits provided from `--provide-dir` [default: the OS user name]
+    provide_author: Option<String>,
+
+    #[clap(long)]
+    /// Don't trade peer addresses with connected gistit nodes
+    no_pex: bool,
+
+    #[clap(long)]
+    /// How often we trade peer addresses with connected gistit nodes, in seconds [default: 120]
+    pex_interval: Option<u64>,
+
+    #[clap(long)]
+    /// Keep providing gistits after a newer version of them is sent with `gistit amend`
+    keep_history: bool,
+
+    #[clap(long)]
+    /// How long gistits replaced by a newer version are still provided, in seconds
+    /// [default: 3600]
+    supersede_grace: Option<u64>,
+
+    #[clap(long)]
+    /// Sync the gistit history with the nodes started with the same sync secret in
+    /// `GISTIT_SYNC_SECRET`, e.g. on your other machines. Create one with `gistit node --start
+    /// --sync`
+    sync: bool,
+
+    #[clap(long)]
+    /// Use a runtime directory owned by another user or writable by everyone
+    insecure_runtime_dir: bool,
+
+    #[clap(short, long, parse(from_occurrences))]
+    /// Log more, repeat for even more (-vv, -vvv). `RUST_LOG` takes precedence
+    verbose: u8,
 }
 
-async fn run() -> Result<()> {
+/// Builds the node from the arguments, ready to run
+async fn setup(args: Args) -> Result<Node> {
     let Args {
         runtime_path,
         config_path,
+        data_path,
         config_file,
         host,
         port,
+        randomize_port,
         bootstrap,
+        bootstrap_peer,
         dial,
         listen,
-    } = Args::parse();
+        max_connections,
+        max_connections_per_peer,
+        rate_limit,
+        ban_duration,
+        max_message_size,
+        max_hosted,
+        max_hosted_bytes,
+        query_parallelism,
+        query_timeout,
+        request_timeout,
+        trusted_peers,
+        reseed,
+        reseed_cap,
+        provide_dir,
+        provide_author,
+        no_pex,
+        pex_interval,
+        keep_history,
+        supersede_grace,
+        sync,
+        insecure_runtime_dir,
+        verbose: _,
+    } = args;
+
+    if insecure_runtime_dir {
+        gistit_ipc::allow_insecure_runtime_dir();
+    }
+
+    let defaults = Limits::default();
+    // The others may be set in the settings file as well, and reloaded
+    let limits = Limits {
+        max_connections: max_connections.unwrap_or(defaults.max_connections),
+        max_connections_per_peer: max_connections_per_peer
+            .unwrap_or(defaults.max_connections_per_peer),
+        max_message_size: max_message_size.unwrap_or(defaults.max_message_size),
+        ..defaults
+    };
+    let overrides = Overrides {
+        requests_per_minute: rate_limit,
+        ban_duration: ban_duration.map(Duration::from_secs),
+        max_hosted,
+        max_hosted_bytes,
+    };
+    let defaults = Queries::default();
+    let queries = Queries {
+        parallelism: query_parallelism.unwrap_or(defaults.parallelism),
+        query_timeout: query_timeout.map_or(defaults.query_timeout, Duration::from_secs),
+        request_timeout: request_timeout.map_or(defaults.request_timeout, Duration::from_secs),
+    };
 
     let config = Config::from_args(
         runtime_path,
         config_path,
+        data_path,
         config_file,
         host,
         port,
+        randomize_port,
+        listen,
         bootstrap,
+        bootstrap_peer,
+        limits,
+        overrides,
+        queries,
+        trusted_peers,
+        reseed.then(|| reseed_cap),
+        provide_dir,
+        provide_author,
+        (!no_pex).then(|| pex_interval.map_or(DEFAULT_PEX_INTERVAL, Duration::from_secs)),
+        (!keep_history)
+            .then(|| supersede_grace.map_or(DEFAULT_SUPERSEDE_GRACE, Duration::from_secs)),
+        sync,
     )?;
     log::debug!("Running config: {:?}", config);
 
@@ -102,22 +277,29 @@ async fn run() -> Result<()> {
         node.dial_on_init(&addr)?;
     }
 
-    for addr in listen {
-        node.listen_on_init(&addr)?;
-    }
-
-    node.run().await?;
-
-    Ok(())
+    Ok(node)
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+    let args = Args::parse();
+    let filter = verbosity::filter(args.verbose);
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(filter))
         .write_style(env_logger::WriteStyle::Always)
         .init();
 
-    while let Err(err) = run().await {
-        log::error!("{:?}", err);
+    // Runtime failures restart the node, but a bad setup won't fix itself by retrying
+    loop {
+        let mut node = match setup(args.clone()).await {
+            Ok(node) => node,
+            Err(err) => {
+                log::error!("{:?}", err);
+                std::process::exit(err.kind().code());
+            }
+        };
+
+        if let Err(err) = node.run().await {
+            log::error!("{:?}", err);
+        }
     }
 }
@@ -1,33 +1,100 @@
 //! The network module
 #![allow(clippy::missing_errors_doc)]
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::io;
+use std::path::PathBuf;
 use std::string::ToString;
+use std::sync::Arc;
 use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
 
 use either::Either;
 use log::{debug, error, info, warn};
 
 use gistit_ipc::{Bridge, Server};
-use gistit_proto::{ipc, Gistit, Instruction};
+use gistit_project::dns::Name;
+use gistit_project::path::RuntimeLock;
+use gistit_project::settings;
+use gistit_proto::ipc::instruction::error_response::Code;
+use gistit_proto::ipc::instruction::persistent_peer_request::Change;
+use gistit_proto::ipc::instruction::provide_response::Status;
+use gistit_proto::ipc::instruction::trust_peer_request::Trust;
+use gistit_proto::prost::Message;
+use gistit_proto::{ipc, validate, Gistit, GistitHash, Instruction};
 
+use libp2p::bandwidth::BandwidthSinks;
+use libp2p::core::connection::PendingConnectionError;
 use libp2p::core::either::EitherError;
 use libp2p::core::{self, Multiaddr, PeerId};
 use libp2p::futures::future::poll_fn;
 use libp2p::futures::StreamExt;
 use libp2p::multiaddr::Protocol;
-use libp2p::swarm::{ProtocolsHandlerUpgrErr, SwarmBuilder, SwarmEvent};
-use libp2p::{dns, mplex, noise, tcp, websocket, yamux, Swarm, Transport};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{AddressScore, ProtocolsHandlerUpgrErr, SwarmBuilder, SwarmEvent};
+use libp2p::{dns, mplex, noise, tcp, websocket, yamux, Swarm, Transport, TransportExt};
 
-use libp2p::kad::{record::Key, QueryId};
-use libp2p::ping::Failure;
+use libp2p::kad::store::RecordStore;
+use libp2p::kad::{record::Key, ProviderRecord, QueryId, Quorum, Record};
+use libp2p::ping::{Event as PingEvent, Failure, Success};
 use libp2p::request_response::RequestId;
 
+use crate::audit::{AuditLog, FLUSH_INTERVAL};
+use crate::batch::ProvideBatches;
 use crate::behaviour::{Behaviour, Event, Request};
+use crate::bootstrap::{split_peer, Bootstrap};
+use crate::burn::Burns;
 use crate::config::Config;
-use crate::event::{handle_identify, handle_kademlia, handle_request_response};
-use crate::Result;
+use crate::event::{
+    handle_identify, handle_kademlia, handle_pex, handle_relay_client, handle_request_response,
+    handle_sync,
+};
+use crate::health::{self, ProviderHealth, CHECK_INTERVAL, MAX_CHECKS};
+use crate::inbox::Inbox;
+use crate::latency::{Latency, PARALLEL_REQUESTS};
+use crate::limit::RateLimiter;
+use crate::listen::ListenBook;
+use crate::partial::Partials;
+use crate::peers::{PeerBook, REDIAL_INTERVAL};
+use crate::pex::{self, PeerExchange, DEFAULT_PEX_INTERVAL, PEX_TARGET};
+use crate::query::{Lookups, LOOKUP_CHECK_INTERVAL};
+use crate::quota::{Quota, Refusal};
+use crate::relay::{is_circuit, Relays};
+use crate::reload::Overrides;
+use crate::replicate::{Replications, Step};
+use crate::reseed::{size_of, Reseed};
+use crate::resolve::{Resolved, Resolver};
+use crate::store::{provider_key, Store};
+use crate::supersede::{Superseded, RETIRE_INTERVAL};
+use crate::sync::{HistorySync, Synced, SYNC_INTERVAL};
+use crate::trust::TrustList;
+use crate::watch::{ProvideDir, RESCAN_INTERVAL};
+use crate::{Error, Result};
+
+/// How often expired bans are lifted
+const UNBAN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often we look for providers slow to answer a fetch
+const SLOW_PROVIDER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long after a new circuit address we publish provider records again. Relays reserved
+/// meanwhile are picked up by the same reprovide
+const REPROVIDE_DELAY: Duration = Duration::from_secs(10);
+
+/// Most hashes answered to a prefix, enough to tell the user it's ambiguous
+const MAX_RESOLVED: usize = 20;
+
+/// Key of a gistit, the one we store it under and its provider records are published under
+#[must_use]
+pub fn gistit_key(hash: &str) -> Key {
+    Key::new(&hash.as_bytes())
+}
+
+/// Key of the record holding a gistit metadata, apart from its provider records
+#[must_use]
+pub fn metadata_key(hash: &[u8]) -> Key {
+    Key::new(&[b"meta/", hash].concat())
+}
 
 /// The main event loop
 pub struct Node {
@@ -36,38 +103,154 @@ pub struct Node {
 
     pub pending_dial: HashSet<PeerId>,
 
-    /// Pending kademlia queries to get providers
-    pub pending_get_providers: HashSet<QueryId>,
+    /// Pending kademlia queries to get providers, and the key of the gistit they look for
+    pub pending_get_providers: HashMap<QueryId, Key>,
 
     pub pending_start_providing: HashSet<QueryId>,
-    pub to_provide: HashMap<Key, Gistit>,
+    pub to_provide: Store,
+
+    /// When to publish provider records again, after our circuit addresses changed
+    pub reprovide_at: Option<Instant>,
+
+    /// Caps what we provide on purpose, see `--max-hosted`
+    pub quota: Quota,
+
+    /// Provides requested together, answered once all of them complete
+    pub provide_batches: ProvideBatches,
+
+    /// Pending kademlia queries for gistit metadata records
+    pub pending_get_metadata: HashSet<QueryId>,
 
-    pub pending_request_file: HashSet<RequestId>,
+    /// Deadlines of the provider and metadata lookups above, see `--query-timeout` and
+    /// [`Node::lookup_deadline`]
+    pub lookups: Lookups,
+    pub query_timeout: Duration,
+    /// How long a provider may take to answer before the next one is asked too
+    pub provider_timeout: Duration,
+
+    /// Outbound file requests, the key and chunk they are requesting
+    pub pending_request_file: HashMap<RequestId, (Key, u32)>,
 
     /// Stack of request file (`key`) events
     pub to_request: Vec<(Key, HashSet<PeerId>)>,
     pub pending_receive_file: HashSet<Key>,
 
-    /// Addresses that can be used as relay
-    pub relays: HashSet<Multiaddr>,
+    /// Chunks received so far of the gistits being fetched, kept across restarts
+    pub partials: Partials,
+
+    /// Providers not asked yet, fastest first. Asked once the ones asked all failed, or one by
+    /// one as they are slow to answer
+    pub fallback_providers: HashMap<Key, Vec<PeerId>>,
+    pub latency: Latency,
+
+    /// Providers of the records we hold for other peers, and whether they are still around
+    pub provider_health: ProviderHealth,
+
+    /// Relays we hold reservations with
+    pub relays: Relays,
+    /// Names to dial being looked up
+    pub resolver: Resolver,
+
+    /// Bootstrap peers and their health
+    pub bootstrap: Bootstrap,
+
+    /// Whether the initial kademlia bootstrap completed
+    pub bootstrapped: bool,
+
+    /// Why the kademlia bootstrap failed, until it completes
+    pub bootstrap_error: Option<String>,
+
+    /// Per peer request rate limits and bans
+    pub limiter: RateLimiter,
+
+    /// Limits given as flags, kept over the settings file on reload
+    pub overrides: Overrides,
+    pub settings_file: PathBuf,
+
+    /// Outbound pushes waiting for the peer to acknowledge
+    pub pending_push: HashSet<RequestId>,
+
+    /// Gistits being handed to trusted peers to provide, see `gistit send --ttl-peers`
+    pub replications: Replications,
+
+    /// Gistits pushed to us by other peers
+    pub inbox: Inbox,
+
+    /// Peers we agree to serve
+    pub trust: TrustList,
+
+    /// Peers we keep dialing, see `--dial`
+    pub peers: PeerBook,
+
+    /// Addresses we listen on, bound again on the next start
+    pub listening: ListenBook,
+
+    /// Fetched gistits we provide too
+    pub reseed: Reseed,
+    pub burns: Burns,
+    pub pending_reseed: HashSet<QueryId>,
+
+    /// Gistits replaced by newer versions we provide, see `--keep-history`
+    pub superseded: Superseded,
+
+    /// Directory whose files we provide, see `--provide-dir`
+    pub provide_dir: Option<ProvideDir>,
+    pub pending_provide_dir: HashSet<QueryId>,
+
+    /// Peers we trade with other nodes, `None` with `--no-pex`
+    pub pex: Option<PeerExchange>,
+
+    /// History synced with our other nodes, `None` unless `--sync`
+    pub history_sync: Option<HistorySync>,
+
+    /// Bytes received and sent, reported in the status
+    pub bandwidth: Arc<BandwidthSinks>,
+
+    /// Gistits we served to other peers
+    pub audit: AuditLog,
+
+    /// Held while we run, see [`RuntimeLock`]
+    pub runtime_lock: RuntimeLock,
 }
 
 impl Node {
     pub async fn new(config: Config) -> Result<Self> {
+        // Before anything else, binding the ipc socket would take it from a running node
+        let runtime_lock = RuntimeLock::acquire(&config.runtime_path)?;
+
         let (behaviour, client_transport) = Behaviour::new_behaviour_and_transport(&config)?;
+        let bootstrap = Bootstrap::new(config.bootstrap_peers.clone());
+        // Nothing to wait for without bootstrap peers
+        let bootstrapped = config.bootstrap_peers.is_empty();
+        // Kademlia can't even start bootstrapping without a peer id to go with an address
+        let unusable = !bootstrapped
+            && config
+                .bootstrap_peers
+                .iter()
+                .all(|address| split_peer(address).is_none());
+        let bootstrap_error =
+            unusable.then(|| "no bootstrap peer address has a peer id".to_owned());
+        let trust = TrustList::load(&config.trust_file)?;
+        let peers = PeerBook::load(&config.peers_file);
+        let listening = ListenBook::load(&config.listen_file);
 
         let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
             .into_authentic(&config.keypair)
             .expect("Signing libp2p-noise static DH keypair failed.");
 
-        let transport = {
+        let (transport, bandwidth) = {
             let tcp = tcp::TokioTcpConfig::new().nodelay(true);
             let dns_tcp = dns::TokioDnsConfig::system(tcp.clone())?;
             let ws_dns_tcp = websocket::WsConfig::new(tcp.clone());
 
-            tcp.or_transport(client_transport)
+            // Counted before encryption and multiplexing, what actually goes over the wire
+            let (transport, bandwidth) = tcp
+                .or_transport(client_transport)
                 .or_transport(dns_tcp)
                 .or_transport(ws_dns_tcp)
+                .with_bandwidth_logging();
+
+            let transport = transport
                 .upgrade(core::upgrade::Version::V1)
                 .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
                 .multiplex(core::upgrade::SelectUpgrade::new(
@@ -75,44 +258,228 @@ impl Node {
                     mplex::MplexConfig::default(),
                 ))
                 .timeout(std::time::Duration::from_secs(20))
-                .boxed()
+                .boxed();
+            (transport, bandwidth)
         };
 
         let mut swarm = SwarmBuilder::new(transport, behaviour, config.peer_id)
             .executor(Box::new(|fut| {
                 tokio::task::spawn(fut);
             }))
+            .connection_limits(config.limits.connection_limits())
             .build();
-        swarm.listen_on(config.multiaddr)?;
+        // Some interfaces may not be available, e.g. ipv6 being disabled. Only give up if we
+        // can't listen anywhere
+        let mut last_err = None;
+        let mut listeners = 0;
+        let mut rebound = Vec::new();
+        for address in config.listen_addrs {
+            // The port we had last time, if it's still free, so peers can reach us where they
+            // remember us
+            let previous = if config.rebind_port {
+                listening.rebind(&address)
+            } else {
+                None
+            };
+            if let Some(previous) = previous {
+                match swarm.listen_on(previous.clone()) {
+                    Ok(_) => {
+                        listeners += 1;
+                        rebound.push(previous);
+                        continue;
+                    }
+                    Err(err) => warn!(
+                        "Failed to listen on {} again, picking another port: {}",
+                        previous, err
+                    ),
+                }
+            }
+            match swarm.listen_on(address.clone()) {
+                Ok(_) => listeners += 1,
+                Err(err) => {
+                    warn!("Failed to listen on {}: {}", address, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        if let (0, Some(err)) = (listeners, last_err) {
+            return Err(err.into());
+        }
+        for address in listening.external_on(&rebound) {
+            debug!("Advertising {} again", address);
+            swarm.add_external_address(address.clone(), AddressScore::Finite(1));
+        }
 
         let bridge = gistit_ipc::server(&config.runtime_path)?;
+        let partials = Partials::new(&config.runtime_path)?;
 
-        Ok(Self {
+        let mut node = Self {
             swarm,
             bridge,
             pending_dial: HashSet::default(),
             pending_start_providing: HashSet::default(),
-            pending_get_providers: HashSet::default(),
-            pending_request_file: HashSet::default(),
+            reprovide_at: None,
+            provide_batches: ProvideBatches::default(),
+            pending_get_providers: HashMap::default(),
+            pending_get_metadata: HashSet::default(),
+            lookups: Lookups::default(),
+            query_timeout: config.queries.query_timeout,
+            provider_timeout: health::provider_timeout(config.queries.request_timeout),
+            pending_request_file: HashMap::default(),
             pending_receive_file: HashSet::default(),
+            partials,
+            fallback_providers: HashMap::default(),
+            latency: Latency::default(),
+            provider_health: ProviderHealth::default(),
 
-            to_provide: HashMap::default(),
+            to_provide: Store::default(),
+            quota: config.limits.quota(),
             to_request: Vec::default(),
 
-            relays: HashSet::default(),
-        })
+            relays: Relays::default(),
+            resolver: Resolver::default(),
+
+            bootstrap,
+            bootstrapped,
+            bootstrap_error,
+            limiter: RateLimiter::new(config.limits),
+            overrides: config.overrides,
+            settings_file: config.settings_file,
+
+            pending_push: HashSet::default(),
+            replications: Replications::default(),
+            inbox: Inbox::default(),
+            trust,
+            peers,
+            listening,
+
+            reseed: Reseed::new(config.reseed_cap),
+            burns: Burns::default(),
+            pending_reseed: HashSet::default(),
+
+            superseded: Superseded::load(config.supersede_grace, &config.superseded_file),
+
+            provide_dir: config
+                .provide_dir
+                .map(|dir| ProvideDir::new(dir, config.provide_author)),
+            pending_provide_dir: HashSet::default(),
+
+            pex: config.pex_interval.map(PeerExchange::new),
+
+            history_sync: config.history_sync,
+
+            bandwidth,
+
+            audit: AuditLog::new(&config.audit_file),
+
+            runtime_lock,
+        };
+        node.dial_bootstrap();
+        node.redial_peers();
+
+        Ok(node)
     }
 
+    /// Dials bootstrap peers until we reach the target connection count
+    fn dial_bootstrap(&mut self) {
+        for address in self.bootstrap.next_to_dial() {
+            info!("Dialing bootstrap peer {}", address);
+            if let Err(err) = self.swarm.dial(address.clone()) {
+                warn!("Failed to dial bootstrap peer {}: {:?}", address, err);
+                if let Some((peer_id, _)) = split_peer(&address) {
+                    self.bootstrap.on_failure(&peer_id);
+                }
+            }
+        }
+    }
+
+    /// Persists the addresses we listen on and the external ones we learned, if they changed
+    pub fn remember_addresses(&mut self) {
+        let external: Vec<Multiaddr> = self
+            .swarm
+            .external_addresses()
+            .map(|record| record.addr.clone())
+            .collect();
+        if self
+            .listening
+            .update(self.swarm.listeners(), external.iter())
+        {
+            self.listening.save_in_background();
+        }
+    }
+
+    /// Dials persistent peers we aren't connected to, once their backoff elapsed
+    fn redial_peers(&mut self) {
+        for address in self.peers.due(Instant::now()) {
+            info!("Dialing persistent peer {}", address);
+            if let Err(err) = self.swarm.dial(address.clone()) {
+                warn!("Failed to dial persistent peer {}: {:?}", address, err);
+                if let Some((peer_id, _)) = split_peer(&address) {
+                    self.peers.on_failure(&peer_id);
+                }
+            }
+        }
+    }
+
+    /// Addresses with a peer id are remembered and dialed again whenever we lose them, others
+    /// are dialed once. Names like `node@example.com` dial every address their DNS record lists
+    /// once looked up, see [`crate::resolve`]
     pub fn dial_on_init(&mut self, address: &str) -> Result<()> {
-        Ok(self.swarm.dial(address.parse::<Multiaddr>()?)?)
+        match Name::parse(address) {
+            Some(name) => self.resolver.lookup(name, true),
+            None => self.dial_address(address.parse()?, true)?,
+        }
+        Ok(())
     }
 
-    pub fn listen_on_init(&mut self, address: &str) -> Result<()> {
-        self.swarm.listen_on(address.parse::<Multiaddr>()?)?;
+    fn dial_address(&mut self, address: Multiaddr, persistent: bool) -> Result<()> {
+        if persistent && split_peer(&address).is_some() {
+            self.peers.add(address)?;
+            self.peers.save()?;
+            self.redial_peers();
+        } else {
+            self.swarm.dial(address)?;
+        }
         Ok(())
     }
 
+    /// Dials the addresses a name stands for, the ones that aren't multiaddrs are skipped
+    fn dial_resolved(&mut self, resolved: Resolved) {
+        for address in resolved.addresses {
+            let dialed = address
+                .parse()
+                .map_err(Into::into)
+                .and_then(|address| self.dial_address(address, resolved.persistent));
+            if let Err(err) = dialed {
+                warn!("Failed to dial {} from {}: {}", address, resolved.name, err);
+            }
+        }
+    }
+
+    fn unban_expired(&mut self) {
+        self.limiter.prune();
+        for peer_id in self.limiter.expired_bans() {
+            info!("Ban expired for {:?}", peer_id);
+            self.swarm.unban_peer_id(peer_id);
+        }
+    }
+
     pub async fn run(&mut self) -> Result<()> {
+        let mut unban_interval = tokio::time::interval(UNBAN_INTERVAL);
+        let mut rescan_interval = tokio::time::interval(RESCAN_INTERVAL);
+        let mut redial_interval = tokio::time::interval(REDIAL_INTERVAL);
+        let mut retire_interval = tokio::time::interval(RETIRE_INTERVAL);
+        let mut pex_interval = tokio::time::interval(
+            self.pex
+                .as_ref()
+                .map_or(DEFAULT_PEX_INTERVAL, PeerExchange::interval),
+        );
+        let mut sync_interval = tokio::time::interval(SYNC_INTERVAL);
+        let mut health_interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut slow_provider_interval = tokio::time::interval(SLOW_PROVIDER_INTERVAL);
+        let mut lookup_interval = tokio::time::interval(LOOKUP_CHECK_INTERVAL);
+        let mut audit_interval = tokio::time::interval(FLUSH_INTERVAL);
+
         loop {
             tokio::select! {
                 swarm_event = self.swarm.next() => self.handle_swarm_event(
@@ -123,45 +490,612 @@ impl Node {
                 request_event = poll_fn(|_| {
                     self.to_request.pop().map_or(Poll::Pending, Poll::Ready)
                 }) => self.handle_request_event(request_event).await?,
+
+                _ = unban_interval.tick() => self.unban_expired(),
+
+                _ = redial_interval.tick() => self.redial_peers(),
+
+                _ = retire_interval.tick(), if self.superseded.enabled() => {
+                    self.retire_superseded();
+                }
+
+                _ = rescan_interval.tick(), if self.provide_dir.is_some() => {
+                    self.rescan_provide_dir();
+                }
+
+                _ = pex_interval.tick(), if self.pex.is_some() => self.exchange_with_partners(),
+
+                _ = sync_interval.tick(), if self.history_sync.is_some() => self.sync_history(),
+
+                synced = HistorySync::next(self.history_sync.as_mut()) => self.handle_synced(synced),
+
+                _ = health_interval.tick() => self.check_providers(),
+
+                _ = slow_provider_interval.tick(), if !self.pending_receive_file.is_empty() => {
+                    self.ask_next_providers();
+                }
+
+                _ = lookup_interval.tick() => self.finish_overdue_lookups(),
+
+                _ = tokio::time::sleep_until(
+                    self.reprovide_at.unwrap_or_else(Instant::now).into()
+                ), if self.reprovide_at.is_some() => {
+                    self.reprovide_at = None;
+                    self.reprovide();
+                }
+
+                _ = audit_interval.tick() => self.audit.flush(),
+
+                resolved = self.resolver.next() => self.dial_resolved(resolved),
             }
         }
     }
 
-    async fn handle_request_event(&mut self, event: (Key, HashSet<PeerId>)) -> Result<()> {
-        let (key, providers) = event;
+    /// Stores a provider record another peer published through us
+    pub fn store_provider(&mut self, record: ProviderRecord) {
+        self.provider_health.track(&record);
+        if let Err(err) = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .store_mut()
+            .add_provider(record)
+        {
+            warn!("Failed to store provider record: {:?}", err);
+        }
+    }
 
-        self.pending_receive_file.insert(key.clone());
-        for peer in providers {
-            for relay in &self.relays {
-                // Skip if we are trying to relay over the destination peer itself
-                if relay
-                    .iter()
-                    .any(|protocol| protocol == Protocol::P2p(peer.into()))
-                {
-                    continue;
+    /// Dials the providers of the records we hold we aren't connected to, see [`crate::health`]
+    fn check_providers(&mut self) {
+        let store = self.swarm.behaviour_mut().kademlia.store_mut();
+        self.provider_health.retain(|key, peer| {
+            store
+                .providers(key)
+                .iter()
+                .any(|record| record.provider == *peer)
+        });
+
+        let swarm = &self.swarm;
+        let due = self
+            .provider_health
+            .due(|peer| swarm.is_connected(peer), MAX_CHECKS);
+        debug!(
+            "Checking {} of the {} providers we hold records for",
+            due.len(),
+            self.provider_health.len()
+        );
+        for (peer_id, addresses) in due {
+            let dial = DialOpts::peer_id(peer_id).addresses(addresses).build();
+            if let Err(err) = self.swarm.dial(dial) {
+                debug!("Failed to dial provider {:?}: {:?}", peer_id, err);
+                self.drop_dead_provider(&peer_id);
+            }
+        }
+    }
+
+    /// Counts a failed check of `peer_id`, its records go once it's deemed gone
+    fn drop_dead_provider(&mut self, peer_id: &PeerId) {
+        let keys = self.provider_health.failed(peer_id);
+        if keys.is_empty() {
+            return;
+        }
+        info!(
+            "Provider {:?} is gone, dropping its {} records",
+            peer_id,
+            keys.len()
+        );
+        let store = self.swarm.behaviour_mut().kademlia.store_mut();
+        for key in keys {
+            store.remove_provider(&key, peer_id);
+        }
+    }
+
+    /// Asks the next provider of gistits whose providers are slow to answer, the slow ones are
+    /// still waited on
+    fn ask_next_providers(&mut self) {
+        for (request_id, slow) in self.latency.overdue(self.provider_timeout) {
+            let (key, index) = match self.pending_request_file.get(&request_id) {
+                Some((key, index)) if self.pending_receive_file.contains(key) => {
+                    (key.clone(), *index)
                 }
+                _ => continue,
+            };
+            let next = self
+                .fallback_providers
+                .get_mut(&key)
+                .filter(|peers| !peers.is_empty())
+                .map(|peers| peers.remove(0));
+            if let Some(peer) = next {
+                info!(
+                    "Provider {:?} is slow to answer, asking {:?} too",
+                    slow, peer
+                );
+                self.request_index(peer, key, index);
+            }
+        }
+    }
 
-                self.swarm
-                    .behaviour_mut()
-                    .request_response
-                    .add_address(&peer, relay.clone());
-                // let relayed_addr = relay.clone().with(Protocol::P2p(peer.into()));
-                // self.swarm.dial(relayed_addr)?;
+    /// When a lookup gives up, at `--query-timeout` or before the client stops waiting
+    fn lookup_deadline(&self, client_timeout: Option<u32>) -> Instant {
+        let timeout = client_timeout.map_or(self.query_timeout, |secs| {
+            self.query_timeout.min(Duration::from_secs(secs.into()))
+        });
+        Instant::now() + timeout
+    }
+
+    /// Finishes lookups past their deadline with what they found so far
+    fn finish_overdue_lookups(&mut self) {
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        for query_id in self.lookups.overdue(Instant::now()) {
+            if let Some(mut query) = kademlia.query_mut(&query_id) {
+                debug!("Lookup {:?} is overdue, finishing it", query_id);
+                query.finish();
             }
+        }
+    }
 
-            let request_id = self
-                .swarm
+    /// Provider lookups still running for the gistit under `key`
+    pub fn pending_lookups_for(&self, key: &Key) -> Vec<QueryId> {
+        self.pending_get_providers
+            .iter()
+            .filter(|(_, pending)| *pending == key)
+            .map(|(query_id, _)| *query_id)
+            .collect()
+    }
+
+    /// Stops provider lookups nobody waits on anymore, their results are ignored
+    pub fn abandon_lookups(&mut self, query_ids: &[QueryId]) {
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        for query_id in query_ids {
+            self.pending_get_providers.remove(query_id);
+            self.lookups.completed(query_id);
+            if let Some(mut query) = kademlia.query_mut(query_id) {
+                query.finish();
+            }
+        }
+    }
+
+    /// Sends a peer a sample of the ones we are connected to, it answers with its own. Peers we
+    /// don't serve are neither asked nor shared
+    pub fn exchange_peers(&mut self, peer_id: PeerId) {
+        let trust = &self.trust;
+        let sample = match &self.pex {
+            Some(pex) if trust.is_allowed(&peer_id) => {
+                pex.sample(&peer_id, |peer| trust.is_allowed(peer))
+            }
+            _ => return,
+        };
+        if let Some(behaviour) = self.swarm.behaviour_mut().pex.as_mut() {
+            debug!("Trading {} peer addresses with {:?}", sample.len(), peer_id);
+            behaviour.send_request(&peer_id, sample);
+        }
+    }
+
+    fn exchange_with_partners(&mut self) {
+        let partners = self
+            .pex
+            .as_ref()
+            .map(PeerExchange::partners)
+            .unwrap_or_default();
+        for peer_id in partners {
+            self.exchange_peers(peer_id);
+        }
+    }
+
+    /// Provides the record of our sync group again and looks up the other nodes providing it,
+    /// we sync with them once found
+    pub fn sync_history(&mut self) {
+        let group = match &self.history_sync {
+            Some(sync) => sync.group().clone(),
+            None => return,
+        };
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        let provided = kademlia
+            .start_providing(group.clone())
+            .map_err(|err| error!("Failed to provide the sync group record: {:?}", err))
+            .ok();
+        let lookup = kademlia.get_providers(group);
+
+        if let Some(sync) = self.history_sync.as_mut() {
+            for query_id in provided.into_iter().chain(Some(lookup)) {
+                sync.started(query_id);
+            }
+        }
+    }
+
+    /// Sends `peer` our history once it's sealed, it answers with its own
+    pub fn sync_with(&mut self, peer_id: PeerId) {
+        if !self.trust.is_allowed(&peer_id) {
+            return;
+        }
+        if let Some(sync) = &self.history_sync {
+            sync.ask(peer_id);
+        }
+    }
+
+    /// Sends what history sync work done off the event loop produced
+    fn handle_synced(&mut self, synced: Synced) {
+        let behaviour = match self.swarm.behaviour_mut().sync.as_mut() {
+            Some(behaviour) => behaviour,
+            None => return,
+        };
+        match synced {
+            Synced::Answer {
+                peer,
+                channel,
+                response,
+            } => {
+                if behaviour.send_response(channel, response).is_err() {
+                    debug!("History sync with {:?} closed before we answered", peer);
+                }
+            }
+            Synced::Ask { peer, sealed } => {
+                debug!("Syncing history with {:?}", peer);
+                behaviour.send_request(&peer, sealed);
+            }
+        }
+    }
+
+    /// Dials some of the peers `from` told us about while we are short of connections. They get
+    /// to kademlia through identify once connected, not on its word
+    pub fn learn_peers(&mut self, from: &PeerId, addresses: Vec<Multiaddr>) {
+        let wanted = PEX_TARGET.saturating_sub(self.swarm.network_info().num_peers());
+        let local = *self.swarm.local_peer_id();
+        let to_dial = match self.pex.as_mut() {
+            Some(pex) => {
+                let learned = pex::learn(&local, addresses, pex.same_network(from));
+                debug!("Learned {} peer addresses from {:?}", learned.len(), from);
+                pex.to_dial(&learned, wanted, Instant::now())
+            }
+            None => return,
+        };
+        for address in to_dial {
+            info!("Dialing {} learned from {:?}", address, from);
+            if let Err(err) = self.swarm.dial(address.clone()) {
+                warn!("Failed to dial {}: {:?}", address, err);
+            }
+        }
+    }
+
+    /// Makes the peer reachable through the relays we know
+    fn add_relay_addresses(&mut self, peer: &PeerId) {
+        for relay in self.relays.circuits() {
+            // Skip if we are trying to relay over the destination peer itself
+            if relay
+                .iter()
+                .any(|protocol| protocol == Protocol::P2p((*peer).into()))
+            {
+                continue;
+            }
+
+            self.swarm
                 .behaviour_mut()
                 .request_response
-                .send_request(&peer, Request(key.to_vec()));
-            info!("Requesting gistit from {:?}", peer);
+                .add_address(peer, relay.clone());
+            // let relayed_addr = relay.clone().with(Protocol::P2p(peer.into()));
+            // self.swarm.dial(relayed_addr)?;
+        }
+    }
+
+    /// Publishes provider records again, so they carry our current external addresses
+    fn reprovide(&mut self) {
+        let keys: Vec<Key> = self
+            .to_provide
+            .keys()
+            .map(|key| self.to_provide.provider_key(key))
+            .collect();
+        for key in keys {
+            match self.swarm.behaviour_mut().kademlia.start_providing(key) {
+                Ok(query_id) => {
+                    self.pending_start_providing.insert(query_id);
+                }
+                Err(err) => error!("Failed to provide: {:?}", err),
+            }
+        }
+    }
+
+    /// Provides a gistit fetched from another peer, if reseeding is enabled. The least recently
+    /// served reseeded gistits stop being provided to make room for it. Returns whether we
+    /// provide it now
+    pub fn reseed_fetched(&mut self, gistit: &Gistit) -> bool {
+        let key = gistit_key(&gistit.hash);
+        if !self.reseed.enabled() || gistit.burn_after_read || self.to_provide.contains(&key) {
+            return self.to_provide.contains(&key);
+        }
+
+        let evicted = match self.reseed.admit(key.clone(), size_of(gistit)) {
+            Some(evicted) => evicted,
+            None => return false,
+        };
+        for key in evicted {
+            info!("Evicting reseeded gistit {:?}", key);
+            self.unprovide(&key);
+        }
+
+        match self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .start_providing(provider_key(&key, gistit.private))
+        {
+            Ok(query_id) => {
+                info!("Reseeding {}", gistit.hash);
+                self.pending_reseed.insert(query_id);
+                self.to_provide.insert(key, gistit.clone());
+                true
+            }
+            Err(err) => {
+                error!("Failed to reseed: {:?}", err);
+                self.reseed.remove(&key);
+                false
+            }
+        }
+    }
+
+    /// Asks peers in our allowlist, fastest first, to provide a gistit too
+    #[allow(clippy::cast_possible_truncation)]
+    fn start_replicating(&mut self, key: Key, replicas: u32) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        let candidates = self.latency.rank(
+            self.trust
+                .listed()
+                .filter(|peer_id| **peer_id != local_peer_id)
+                .copied(),
+        );
+        let step = self.replications.start(key, replicas as usize, candidates);
+        self.replicate(step);
+    }
+
+    /// Carries on replicating a gistit, see [`Replications`]
+    pub fn replicate(&mut self, step: Option<Step>) {
+        match step {
+            Some(Step::Ask(key, peers)) => {
+                let gistit = if let Some(gistit) = self.to_provide.get(&key) {
+                    gistit
+                } else {
+                    // Burned since
+                    self.replications.forget(&key);
+                    return;
+                };
 
-            self.pending_request_file.insert(request_id);
+                for peer_id in peers {
+                    info!("Asking {:?} to replicate {:?}", peer_id, key);
+                    self.add_relay_addresses(&peer_id);
+                    let request_id = self
+                        .swarm
+                        .behaviour_mut()
+                        .request_response
+                        .send_request(&peer_id, Request::Replicate(gistit.clone()));
+                    self.replications.sent(request_id, key.clone());
+                }
+            }
+            Some(Step::Done {
+                key,
+                replicated,
+                wanted,
+            }) => {
+                if replicated < wanted {
+                    warn!(
+                        "Replicated {:?} to {} out of {} trusted peers",
+                        key, replicated, wanted
+                    );
+                } else {
+                    info!("Replicated {:?} to {} trusted peers", key, replicated);
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Provides files added or changed in `--provide-dir` since the last scan, and stops
+    /// providing the ones changed or removed
+    fn rescan_provide_dir(&mut self) {
+        let changes = match self.provide_dir.as_mut().map(ProvideDir::scan) {
+            Some(Ok(changes)) => changes,
+            Some(Err(err)) => {
+                error!("Failed to scan the provided directory: {:?}", err);
+                return;
+            }
+            None => return,
+        };
+
+        for key in changes.unprovide {
+            info!("Stopped providing {:?}, its file changed or is gone", key);
+            self.unprovide(&key);
+        }
+
+        for gistit in changes.provide {
+            let key = gistit_key(&gistit.hash);
+            if self.to_provide.contains(&key) && !self.reseed.contains(&key) {
+                continue;
+            }
+            if let Err(refusal) = self.check_quota(&gistit) {
+                warn!(
+                    "Not providing {} from {:?}: {}",
+                    gistit.hash, gistit.inner[0].name, refusal
+                );
+                continue;
+            }
+            match self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .start_providing(provider_key(&key, gistit.private))
+            {
+                Ok(query_id) => {
+                    info!("Providing {} from {:?}", gistit.hash, gistit.inner[0].name);
+                    self.pending_provide_dir.insert(query_id);
+                    self.put_metadata(&gistit);
+                    self.reseed.remove(&key);
+                    self.to_provide.insert(key, gistit);
+                }
+                Err(err) => error!("Failed to provide {}: {:?}", gistit.hash, err),
+            }
         }
+    }
+
+    /// Whether `gistit` fits in the hosting quota. Reseeded gistits aren't counted, and neither
+    /// is `gistit` if we provide it already
+    fn check_quota(&self, gistit: &Gistit) -> std::result::Result<(), Refusal> {
+        let key = gistit_key(&gistit.hash);
+        let hosted = self
+            .to_provide
+            .keys()
+            .filter(|hosted| **hosted != key && !self.reseed.contains(hosted))
+            .filter_map(|hosted| self.to_provide.size(hosted));
+        self.quota.check(size_of(gistit), hosted)
+    }
+
+    /// Reads the `node-*` settings and the trust lists again, see [`crate::reload`]. Nothing
+    /// changes if either can't be read
+    fn reload_config(&mut self) -> Result<()> {
+        let settings = settings::load_from(&self.settings_file)?;
+        self.trust.reload()?;
 
+        let limits = self.overrides.apply(self.limiter.limits(), &settings);
+        self.limiter.set_limits(limits);
+        self.quota = limits.quota();
+        info!(
+            "Reloaded config: {} requests a minute, {:?} bans, hosting {} gistits and {} bytes",
+            limits.requests_per_minute,
+            limits.ban_duration,
+            limits.max_hosted,
+            limits.max_hosted_bytes
+        );
         Ok(())
     }
 
+    /// Publishes what a gistit is without its contents, so it can be looked up without fetching
+    /// it. The record is unsigned, peers reading it have to take our word for it. Not for private
+    /// gistits, the record is under their hash
+    fn put_metadata(&mut self, gistit: &Gistit) {
+        if gistit.private {
+            return;
+        }
+        let record = Record::new(
+            metadata_key(gistit.hash.as_bytes()),
+            gistit.metadata().encode_to_vec(),
+        );
+        if let Err(err) = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .put_record(record, Quorum::One)
+        {
+            error!("Failed to publish metadata: {:?}", err);
+        }
+    }
+
+    /// Schedules the gistit `gistit` amends to stop being provided, if we provide it. Reseeded
+    /// ones are left to the reseed cap
+    fn supersede_previous(&mut self, gistit: &Gistit) {
+        let key = gistit_key(&gistit.hash);
+        self.superseded.cancel(&key);
+
+        let previous = match gistit.previous_hash() {
+            Some(previous) => gistit_key(&previous),
+            None => return,
+        };
+        if !self.to_provide.contains(&previous) || self.reseed.contains(&previous) {
+            return;
+        }
+        if self
+            .superseded
+            .supersede(previous.clone(), SystemTime::now())
+        {
+            info!("{} supersedes {:?}", gistit.hash, previous);
+        }
+    }
+
+    /// Stops providing superseded gistits past their grace period
+    fn retire_superseded(&mut self) {
+        for key in self.superseded.due(SystemTime::now()) {
+            // Sent again or burned since
+            if !self.to_provide.contains(&key) || self.reseed.contains(&key) {
+                continue;
+            }
+            info!("Stopped providing {:?}, superseded by a newer version", key);
+            self.unprovide(&key);
+        }
+    }
+
+    /// Takes down the provider and metadata records of a gistit and forgets it
+    fn unprovide(&mut self, key: &Key) {
+        let provider_key = self.to_provide.provider_key(key);
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        kademlia.stop_providing(&provider_key);
+        kademlia.remove_record(&metadata_key(&key.to_vec()));
+        self.to_provide.remove(key);
+        if let Some(provide_dir) = &mut self.provide_dir {
+            provide_dir.release(key);
+        }
+    }
+
+    /// The client sent the gistit under `key`, it's provided until taken down on purpose even if
+    /// a `--provide-dir` file with the same contents goes away
+    fn sent(&mut self, key: &Key) {
+        if let Some(provide_dir) = &mut self.provide_dir {
+            provide_dir.keep(key.clone());
+        }
+    }
+
+    /// Forgets a burn after read gistit that was just served
+    pub fn burn(&mut self, key: &Key) {
+        info!("Burning {:?} after read", key);
+        self.unprovide(key);
+        self.reseed.remove(key);
+    }
+
+    async fn handle_request_event(&mut self, event: (Key, HashSet<PeerId>)) -> Result<()> {
+        let (key, providers) = event;
+
+        self.pending_receive_file.insert(key.clone());
+        let mut providers = self.latency.rank(providers);
+        let fallback = providers.split_off(providers.len().min(PARALLEL_REQUESTS));
+        self.fallback_providers.insert(key.clone(), fallback);
+
+        for peer in providers {
+            if !self.request_chunk(peer, key.clone()) {
+                self.fallback_providers
+                    .entry(key.clone())
+                    .or_default()
+                    .push(peer);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Asks `peer` for a chunk of `key` we are missing and nobody else was asked for, which
+    /// resumes where an interrupted transfer stopped. Returns `false` if every chunk left was
+    /// asked for already
+    pub fn request_chunk(&mut self, peer: PeerId, key: Key) -> bool {
+        match self.partials.pick(&key) {
+            Some(index) => {
+                self.request_index(peer, key, index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Asks `peer` for chunk `index` of `key`
+    fn request_index(&mut self, peer: PeerId, key: Key, index: u32) {
+        self.add_relay_addresses(&peer);
+
+        let request_id = self.swarm.behaviour_mut().request_response.send_request(
+            &peer,
+            Request::FetchChunk {
+                hash: key.to_vec(),
+                index,
+            },
+        );
+        info!("Requesting chunk {} of {:?} from {:?}", index, key, peer);
+
+        self.latency.sent(request_id, peer);
+        self.pending_request_file.insert(request_id, (key, index));
+    }
+
     #[allow(clippy::type_complexity)]
     async fn handle_swarm_event(
         &mut self,
@@ -172,29 +1106,38 @@ impl Node {
                     EitherError<
                         EitherError<
                             EitherError<
-                                EitherError<ProtocolsHandlerUpgrErr<io::Error>, io::Error>,
-                                io::Error,
-                            >,
-                            Either<
-                                ProtocolsHandlerUpgrErr<
+                                EitherError<
                                     EitherError<
-                                        impl std::error::Error + Send,
-                                        impl std::error::Error + Send,
+                                        EitherError<ProtocolsHandlerUpgrErr<io::Error>, io::Error>,
+                                        io::Error,
+                                    >,
+                                    Either<
+                                        ProtocolsHandlerUpgrErr<
+                                            EitherError<
+                                                impl std::error::Error + Send,
+                                                impl std::error::Error + Send,
+                                            >,
+                                        >,
+                                        void::Void,
                                     >,
                                 >,
-                                void::Void,
+                                ProtocolsHandlerUpgrErr<io::Error>,
                             >,
+                            Failure,
+                        >,
+                        Either<
+                            ProtocolsHandlerUpgrErr<
+                                EitherError<
+                                    impl std::error::Error + Send,
+                                    impl std::error::Error + Send,
+                                >,
+                            >,
+                            void::Void,
                         >,
-                        ProtocolsHandlerUpgrErr<io::Error>,
-                    >,
-                    Failure,
-                >,
-                Either<
-                    ProtocolsHandlerUpgrErr<
-                        EitherError<impl std::error::Error + Send, impl std::error::Error + Send>,
                     >,
-                    void::Void,
+                    ProtocolsHandlerUpgrErr<io::Error>,
                 >,
+                ProtocolsHandlerUpgrErr<io::Error>,
             >,
         >,
     ) -> Result<()> {
@@ -205,17 +1148,66 @@ impl Node {
                 handle_request_response(self, event).await?;
             }
 
+            SwarmEvent::Behaviour(Event::Client(event)) => handle_relay_client(self, event),
+            SwarmEvent::Behaviour(Event::Pex(event)) => handle_pex(self, event),
+            SwarmEvent::Behaviour(Event::Sync(event)) => handle_sync(self, event),
+
             SwarmEvent::NewListenAddr { address, .. } => {
                 let peer_id = self.swarm.local_peer_id().to_string();
                 info!("Listening on {:?}, {:?}", address, peer_id);
+
+                // Peers behind NAT are only reachable through the relay, make sure the circuit
+                // address is part of our provider records
+                if is_circuit(&address) {
+                    self.swarm
+                        .add_external_address(address, AddressScore::Infinite);
+                    self.reprovide_at
+                        .get_or_insert_with(|| Instant::now() + REPROVIDE_DELAY);
+                }
+                self.remember_addresses();
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } if is_circuit(&address) => {
+                warn!("Relay address expired {:?}", address);
+                self.swarm.remove_external_address(&address);
             }
             SwarmEvent::ConnectionEstablished {
-                peer_id, endpoint, ..
+                peer_id,
+                endpoint,
+                num_established,
+                ..
             } => {
                 info!("Connection established {:?}", peer_id);
                 if endpoint.is_dialer() {
                     self.pending_dial.remove(&peer_id);
                 }
+                self.provider_health.alive(&peer_id);
+
+                if self.bootstrap.on_connected(&peer_id) {
+                    info!("Connected to bootstrap peer {:?}", peer_id);
+                }
+                // Further connections to a peer change nothing in the book
+                if num_established.get() == 1 && self.peers.on_connected(&peer_id) {
+                    info!("Connected to persistent peer {:?}", peer_id);
+                    if let Err(err) = self.peers.save() {
+                        warn!("Failed to save persistent peers: {:?}", err);
+                    }
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                if let Some(pex) = self.pex.as_mut() {
+                    pex.disconnected(&peer_id);
+                }
+                if self.bootstrap.on_disconnected(&peer_id) {
+                    warn!("Lost bootstrap peer {:?}, rotating", peer_id);
+                    self.dial_bootstrap();
+                }
+                if self.peers.on_disconnected(&peer_id) {
+                    warn!("Lost persistent peer {:?}, dialing it again soon", peer_id);
+                }
             }
             SwarmEvent::OutgoingConnectionError {
                 peer_id: maybe_peer_id,
@@ -225,9 +1217,30 @@ impl Node {
                 error!("Outgoing connection error: {:?}", error);
                 if let Some(peer_id) = maybe_peer_id {
                     self.pending_dial.remove(&peer_id);
+
+                    if self.bootstrap.on_failure(&peer_id) {
+                        self.dial_bootstrap();
+                    }
+                    self.peers.on_failure(&peer_id);
+                    self.drop_dead_provider(&peer_id);
                 }
             }
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error: PendingConnectionError::ConnectionLimit(limit),
+                ..
+            } => {
+                warn!(
+                    "Refused connection from {:?}, limit of {} reached",
+                    send_back_addr, limit.limit
+                );
+                self.limiter.denied_connections += 1;
+            }
             SwarmEvent::Behaviour(Event::Relay(e)) => warn!("{:?}", e),
+            SwarmEvent::Behaviour(Event::Ping(PingEvent {
+                peer,
+                result: Ok(Success::Ping { rtt }),
+            })) => self.latency.record(peer, rtt),
             SwarmEvent::Behaviour(Event::Ping(_)) => {}
             // SwarmEvent::Behaviour(Event::Autonat(e)) => warn!("{:?}", e),
             ev => {
@@ -237,35 +1250,284 @@ impl Node {
         Ok(())
     }
 
+    /// Parses a hash the CLI sent, answering it with an error if it isn't one
+    async fn check_hash(&mut self, hash: &str) -> Result<Option<GistitHash>> {
+        match GistitHash::parse(hash) {
+            Ok(hash) => Ok(Some(hash)),
+            Err(_) => {
+                error!("Refusing invalid hash {:?}", hash);
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_error(Code::InvalidHash, None))
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
     #[allow(clippy::match_wildcard_for_single_variants)]
     #[allow(clippy::cast_possible_truncation)]
     async fn handle_bridge_event(&mut self, instruction: Instruction) -> Result<()> {
-        match instruction.expect_request()? {
+        // Newer clients may send instructions we don't know about, those are refused instead of
+        // taking the whole node down or leaving the client waiting
+        let request = match instruction.expect_request() {
+            Ok(request) => request,
+            Err(err) => {
+                warn!("Refusing instruction: {}", err);
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_error(
+                        Code::Unsupported,
+                        Some(err.to_string()),
+                    ))
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        match request {
             ipc::instruction::Kind::ProvideRequest(ipc::instruction::ProvideRequest {
                 gistit: Some(gistit),
+                replicas,
             }) => {
-                warn!("Instruction: Provide gistit {}", &gistit.hash);
-                let key = Key::new(&gistit.hash);
+                warn!(
+                    "Instruction: Provide gistit {}{}",
+                    &gistit.hash,
+                    if gistit.is_encrypted() {
+                        ", encrypted"
+                    } else {
+                        ""
+                    }
+                );
+                let hash = match gistit
+                    .validate()
+                    .and_then(|()| GistitHash::parse(&gistit.hash))
+                {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        error!("Refusing invalid gistit: {}", err);
+                        self.bridge.connect().await?;
+                        self.bridge
+                            .send(Instruction::respond_error(
+                                Code::InvalidGistit,
+                                Some(err.to_string()),
+                            ))
+                            .await?;
+                        return Ok(());
+                    }
+                };
+                let key = gistit_key(&hash);
+                if self.to_provide.contains(&key) && !self.reseed.contains(&key) {
+                    info!("Already providing {}", hash);
+                    // Sent again on purpose, keep it even if a newer version superseded it
+                    self.superseded.cancel(&key);
+                    self.sent(&key);
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_provide(Status::Duplicate, hash))
+                        .await?;
+                    if replicas > 0 && !gistit.burn_after_read {
+                        self.start_replicating(key, replicas);
+                    }
+                    return Ok(());
+                }
+                if let Err(refusal) = self.check_quota(&gistit) {
+                    warn!("Refusing to provide {}: {}", gistit.hash, refusal);
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_provide_refused(
+                            refusal.status(),
+                            refusal.to_string(),
+                        ))
+                        .await?;
+                    return Ok(());
+                }
 
-                let query_id = self
+                let query_id = match self
                     .swarm
                     .behaviour_mut()
                     .kademlia
-                    .start_providing(key.clone())
-                    .expect("to start providing");
+                    .start_providing(provider_key(&key, gistit.private))
+                {
+                    Ok(query_id) => query_id,
+                    // The record store is full
+                    Err(err) => {
+                        error!("Failed to provide: {:?}", err);
+                        self.bridge.connect().await?;
+                        self.bridge
+                            .send(Instruction::respond_provide_refused(
+                                Status::StoreFailed,
+                                format!("{:?}", err),
+                            ))
+                            .await?;
+                        return Ok(());
+                    }
+                };
 
                 self.pending_start_providing.insert(query_id);
-                self.to_provide.insert(key, gistit);
+                self.put_metadata(&gistit);
+                // Provided on purpose now, never evict it
+                self.reseed.remove(&key);
+                self.sent(&key);
+                self.supersede_previous(&gistit);
+                let burn_after_read = gistit.burn_after_read;
+                self.to_provide.insert(key.clone(), gistit);
+                debug!(
+                    "Hosting {} gistits, {} distinct files",
+                    self.to_provide.len(),
+                    self.to_provide.blob_count()
+                );
+
+                if replicas > 0 && burn_after_read {
+                    warn!("Not replicating {:?}, it burns after read", key);
+                } else if replicas > 0 {
+                    self.start_replicating(key, replicas);
+                }
+            }
+
+            ipc::instruction::Kind::ProvideManyRequest(ipc::instruction::ProvideManyRequest {
+                gistits,
+            }) => {
+                warn!("Instruction: Provide {} gistits", gistits.len());
+                let mut results = Vec::with_capacity(gistits.len());
+                let mut pending = HashMap::new();
+
+                for gistit in gistits {
+                    let key = gistit_key(&gistit.hash);
+                    let mut result = ipc::instruction::ProvideResult {
+                        hash: gistit.hash.clone(),
+                        ..ipc::instruction::ProvideResult::default()
+                    };
+
+                    if let Err(err) = gistit.validate() {
+                        error!("Refusing invalid gistit {}: {}", result.hash, err);
+                        result.error = Some(err.to_string());
+                        results.push(result);
+                        continue;
+                    }
+                    if let Err(refusal) = self.check_quota(&gistit) {
+                        warn!("Refusing to provide {}: {}", result.hash, refusal);
+                        result.error = Some(refusal.to_string());
+                        results.push(result);
+                        continue;
+                    }
+
+                    match self
+                        .swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .start_providing(provider_key(&key, gistit.private))
+                    {
+                        Ok(query_id) => {
+                            pending.insert(query_id, results.len());
+                            self.put_metadata(&gistit);
+                            self.reseed.remove(&key);
+                            self.sent(&key);
+                            self.supersede_previous(&gistit);
+                            self.to_provide.insert(key, gistit);
+                        }
+                        Err(err) => {
+                            error!("Failed to provide {}: {:?}", result.hash, err);
+                            result.error = Some(format!("{:?}", err));
+                        }
+                    }
+                    results.push(result);
+                }
+
+                if let Some(results) = self.provide_batches.start(results, pending) {
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_provide_many(results))
+                        .await?;
+                }
             }
 
-            ipc::instruction::Kind::FetchRequest(ipc::instruction::FetchRequest { hash }) => {
+            ipc::instruction::Kind::FetchRequest(ipc::instruction::FetchRequest {
+                hash,
+                timeout,
+            }) => {
+                let hash = match self.check_hash(&hash).await? {
+                    Some(hash) => hash,
+                    None => return Ok(()),
+                };
+                if let Some(gistit) = self.inbox.get(&hash) {
+                    warn!("Instruction: Fetch {} from inbox", hash);
+                    let gistit = gistit.clone();
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_fetch(Some(gistit)))
+                        .await?;
+                    return Ok(());
+                }
+
                 warn!("Instruction: Get providers for {}", hash);
+                // Whether it's private is up to who sent it, both provider keys are looked up
+                let key = gistit_key(&hash);
+                let deadline = self.lookup_deadline(timeout);
+                for private in [false, true] {
+                    let query_id = self
+                        .swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .get_providers(provider_key(&key, private));
+                    self.pending_get_providers.insert(query_id, key.clone());
+                    self.lookups.start(query_id, deadline);
+                }
+            }
+
+            ipc::instruction::Kind::MetadataRequest(ipc::instruction::MetadataRequest {
+                hash,
+                timeout,
+            }) => {
+                let hash = match self.check_hash(&hash).await? {
+                    Some(hash) => hash,
+                    None => return Ok(()),
+                };
+                let local = self
+                    .to_provide
+                    .get(&gistit_key(&hash))
+                    .or_else(|| self.inbox.get(&hash).cloned());
+                if let Some(gistit) = local {
+                    warn!("Instruction: Metadata of {} we have", hash);
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_metadata(Some(gistit.metadata())))
+                        .await?;
+                    return Ok(());
+                }
+
+                warn!("Instruction: Get metadata record for {}", hash);
                 let query_id = self
                     .swarm
                     .behaviour_mut()
                     .kademlia
-                    .get_providers(Key::new(&hash));
-                self.pending_get_providers.insert(query_id);
+                    .get_record(&metadata_key(hash.as_bytes()), Quorum::One);
+                self.pending_get_metadata.insert(query_id);
+                self.lookups.start(query_id, self.lookup_deadline(timeout));
+            }
+
+            ipc::instruction::Kind::FetchFromPeerRequest(
+                ipc::instruction::FetchFromPeerRequest { hash, peer_id },
+            ) => {
+                let hash = match self.check_hash(&hash).await? {
+                    Some(hash) => hash,
+                    None => return Ok(()),
+                };
+                if let Ok(peer_id) = peer_id.parse::<PeerId>() {
+                    warn!("Instruction: Fetch {} from {:?}", hash, peer_id);
+                    // Same as if the provider lookup found only this peer
+                    self.to_request
+                        .push((gistit_key(&hash), HashSet::from([peer_id])));
+                } else {
+                    error!("Invalid peer id to fetch from: {}", peer_id);
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_error(
+                            Code::Unknown,
+                            Some("invalid peer id".to_owned()),
+                        ))
+                        .await?;
+                }
             }
 
             ipc::instruction::Kind::StatusRequest(ipc::instruction::StatusRequest {}) => {
@@ -277,30 +1539,290 @@ impl Node {
                 let peer_count = network_info.num_peers() as u32;
                 let pending_connections = network_info.connection_counters().num_pending();
                 let hosting = self.to_provide.len() as u32;
+                let listeners = self
+                    .swarm
+                    .listeners()
+                    .chain(self.swarm.external_addresses().map(|record| &record.addr))
+                    .map(ToString::to_string)
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect();
+                let queue = self.bridge.queue_stats();
+
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(
+                        Instruction::respond_status(ipc::instruction::StatusResponse {
+                            peer_id,
+                            peer_count,
+                            pending_connections,
+                            hosting,
+                            bootstrap_peers: self.bootstrap.connected(),
+                            rate_limited: self.limiter.rate_limited,
+                            denied_connections: self.limiter.denied_connections,
+                            banned_peers: self.limiter.banned_count() as u32,
+                            listeners,
+                            relays: self.relays.reserved_count() as u32,
+                            reseeding: self.reseed.len() as u32,
+                            bytes_received: self.bandwidth.total_inbound(),
+                            bytes_sent: self.bandwidth.total_outbound(),
+                            ipc_queue_peak: queue.peak as u32,
+                            ipc_stalls: queue.stalls,
+                            bootstrapped: self.bootstrapped,
+                            bootstrap_error: self.bootstrap_error.clone(),
+                        })
+                        .with_hello(),
+                    )
+                    .await?;
+            }
+
+            ipc::instruction::Kind::SendToPeerRequest(ipc::instruction::SendToPeerRequest {
+                peer_id,
+                gistit: Some(gistit),
+            }) => {
+                warn!("Instruction: Send {} to {}", gistit.hash, peer_id);
+                let peer_id = if let Ok(peer_id) = peer_id.parse::<PeerId>() {
+                    peer_id
+                } else {
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_send_to_peer(
+                            false,
+                            Some("invalid peer id".to_owned()),
+                        ))
+                        .await?;
+                    return Ok(());
+                };
+
+                // Dials the peer if we aren't connected yet
+                self.add_relay_addresses(&peer_id);
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_request(&peer_id, Request::Push(gistit));
+                self.pending_push.insert(request_id);
+            }
+
+            ipc::instruction::Kind::PingRequest(ipc::instruction::PingRequest {}) => {
+                debug!("Instruction: Ping");
+                let listening = self.swarm.listeners().next().is_some();
+
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_pong(
+                        listening,
+                        self.bootstrapped,
+                        self.bootstrap_error.clone(),
+                    ))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::InboxRequest(ipc::instruction::InboxRequest { offset }) => {
+                warn!("Instruction: Inbox");
+                let rest = self.inbox.summary(offset as usize);
+                let total = rest.len();
+                let page = gistit_proto::ipc::split_by_size(rest, gistit_ipc::READBUF_SIZE)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default();
+                let next = (page.len() < total).then(|| offset + page.len() as u32);
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_inbox(page, next))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::HostedRequest(ipc::instruction::HostedRequest { offset }) => {
+                warn!("Instruction: Hosted");
+                let mut keys: Vec<&Key> = self
+                    .to_provide
+                    .keys()
+                    .filter(|key| !self.reseed.contains(key))
+                    .collect();
+                // Same order on every page
+                keys.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+                let rest: Vec<Gistit> = keys
+                    .into_iter()
+                    .skip(offset as usize)
+                    .filter_map(|key| self.to_provide.get(key))
+                    .collect();
+                let total = rest.len();
+                let page = gistit_proto::ipc::split_by_size(rest, gistit_ipc::READBUF_SIZE)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default();
+                let next = (page.len() < total).then(|| offset + page.len() as u32);
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_hosted(page, next))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::ReloadConfigRequest(
+                ipc::instruction::ReloadConfigRequest {},
+            ) => {
+                warn!("Instruction: Reload config");
+                let error = self.reload_config().err().map(|err| {
+                    error!("Failed to reload config: {}", err);
+                    err.to_string()
+                });
+
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_reload_config(error))
+                    .await?;
+            }
 
-                self.bridge.connect_blocking()?;
+            ipc::instruction::Kind::TrustPeerRequest(request) => {
+                warn!("Instruction: Trust peer");
+                let error = match request.peer_id.as_deref().map(str::parse::<PeerId>) {
+                    // Listing only
+                    None => None,
+                    Some(Err(_)) => Some("invalid peer id".to_owned()),
+                    Some(Ok(peer_id)) => {
+                        match request.trust() {
+                            Trust::Allow => self.trust.allow(peer_id),
+                            Trust::Deny => self.trust.deny(peer_id),
+                            Trust::Forget => self.trust.forget(&peer_id),
+                        }
+                        self.trust.save().err().map(|err| err.to_string())
+                    }
+                };
+
+                self.bridge.connect().await?;
                 self.bridge
-                    .send(Instruction::respond_status(
-                        peer_id,
-                        peer_count,
-                        pending_connections,
-                        hosting,
+                    .send(Instruction::respond_trust_peer(
+                        self.trust.allowed().into_iter().collect(),
+                        self.trust.denied().into_iter().collect(),
+                        error,
                     ))
                     .await?;
             }
 
+            ipc::instruction::Kind::PersistentPeerRequest(request) => {
+                warn!("Instruction: Persistent peer");
+                let error = match request.address.as_deref() {
+                    // Listing only
+                    None => None,
+                    Some(address) => match request.change() {
+                        Change::Add => match address.parse::<Multiaddr>() {
+                            Ok(address) => self.peers.add(address).err(),
+                            Err(err) => Some(err.into()),
+                        },
+                        Change::Remove if self.peers.remove(address) == 0 => {
+                            Some(Error::Parse("not a persistent peer"))
+                        }
+                        Change::Remove => None,
+                    }
+                    .or_else(|| self.peers.save().err())
+                    .map(|err| err.to_string()),
+                };
+                self.redial_peers();
+
+                let peers = self
+                    .peers
+                    .peers()
+                    .iter()
+                    .map(|peer| ipc::instruction::PersistentPeer {
+                        address: peer.address.to_string(),
+                        connected: peer.connected,
+                        last_connected: peer.last_connected,
+                        failures: peer.failures,
+                    })
+                    .collect();
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_persistent_peer(peers, error))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::AuditRequest(ipc::instruction::AuditRequest {
+                hash,
+                peer_id,
+            }) => {
+                warn!("Instruction: Audit");
+                let (entries, total, error) =
+                    match self.audit.query(hash.as_deref(), peer_id.as_deref()) {
+                        Ok((entries, total)) => (entries, total, None),
+                        Err(err) => (Vec::new(), 0, Some(err.to_string())),
+                    };
+                let entries = entries
+                    .into_iter()
+                    .map(|entry| ipc::instruction::AuditEntry {
+                        timestamp: entry.timestamp,
+                        peer_id: entry.peer_id,
+                        hash: entry.hash,
+                        bytes: entry.bytes,
+                    })
+                    .collect();
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_audit(entries, total, error))
+                    .await?;
+            }
+
+            ipc::instruction::Kind::ResolveRequest(ipc::instruction::ResolveRequest { prefix }) => {
+                warn!("Instruction: Resolve {}", prefix);
+                if validate::hash_prefix(&prefix).is_err() {
+                    error!("Refusing invalid hash prefix {:?}", prefix);
+                    self.bridge.connect().await?;
+                    self.bridge
+                        .send(Instruction::respond_error(Code::InvalidHash, None))
+                        .await?;
+                    return Ok(());
+                }
+                let prefix = prefix.to_ascii_lowercase();
+                let hashes: Vec<String> = self
+                    .to_provide
+                    .hashes_starting_with(&prefix)
+                    .chain(self.inbox.hashes_starting_with(&prefix))
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .take(MAX_RESOLVED)
+                    .map(ToOwned::to_owned)
+                    .collect();
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_resolve(hashes))
+                    .await?;
+            }
+
             ipc::instruction::Kind::DialRequest(ipc::instruction::DialRequest { address }) => {
                 warn!("Instruction: Dial");
-                let multiaddr: Multiaddr = address.parse()?;
-                self.swarm.dial(multiaddr)?;
+                let dialed = match Name::parse(&address) {
+                    Some(name) => {
+                        self.resolver.lookup(name, false);
+                        Ok(())
+                    }
+                    None => address
+                        .parse()
+                        .map_err(Into::into)
+                        .and_then(|multiaddr| self.dial_address(multiaddr, false)),
+                };
+                if let Err(err) = dialed {
+                    warn!("Failed to dial {}: {}", address, err);
+                }
             }
 
             ipc::instruction::Kind::ShutdownRequest(ipc::instruction::ShutdownRequest {}) => {
                 warn!("Exiting...");
+                if let Err(err) = self.audit.write_pending() {
+                    error!("Failed to write the audit log: {}", err);
+                }
                 std::process::exit(0);
             }
 
-            _ => (),
+            _ => {
+                warn!("Refusing request without its fields");
+                self.bridge.connect().await?;
+                self.bridge
+                    .send(Instruction::respond_error(
+                        Code::Unknown,
+                        Some("request without its fields".to_owned()),
+                    ))
+                    .await?;
+            }
         }
         Ok(())
     }
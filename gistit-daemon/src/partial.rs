@@ -0,0 +1,380 @@
+//! Partial downloads
+//!
+//! Over the chunked exchange protocol a gistit is transferred in [`CHUNK_SIZE`] pieces of its
+//! encoded payload. Received chunks are written to the runtime directory as they arrive, so a
+//! transfer cut by a flaky connection resumes from the first missing chunk the next time the
+//! gistit is fetched, even after a daemon restart. Ones left unfinished for a day are dropped
+//! when the daemon starts.
+//!
+//! Once a provider told how many chunks there are, each provider answering is asked for a chunk
+//! nobody was asked for yet, so they are fetched in parallel. Chunks arriving ahead of the first
+//! missing one are held in memory until it comes in, the file on disk only ever has the chunks
+//! from the start.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use libp2p::kad::record::Key;
+use log::warn;
+
+use gistit_proto::validate;
+
+/// Size of every chunk but the last one
+pub const CHUNK_SIZE: usize = 8192;
+
+/// Chunks a gistit takes at most, providers claiming more are lying
+#[allow(clippy::cast_possible_truncation)]
+const MAX_CHUNKS: u32 = ((validate::ENCODED_SIZE + CHUNK_SIZE - 1) / CHUNK_SIZE) as u32;
+
+/// How long a partial download is kept around to resume
+const PARTIAL_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const PARTIAL_DIR: &str = "partial";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// A chunk we already have, e.g. a duplicate from another provider
+    Stale,
+
+    /// Stored, some chunks are still missing
+    Stored,
+
+    /// The last chunk is in, here is the whole encoded gistit
+    Done(Vec<u8>),
+}
+
+/// Returns chunk `index` of `bytes` along with how many chunks there are. `None` if out of range
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn chunk(bytes: &[u8], index: u32) -> Option<(u32, &[u8])> {
+    // An empty payload is still one (empty) chunk
+    let total = ((bytes.len().max(1) + CHUNK_SIZE - 1) / CHUNK_SIZE) as u32;
+    let start = index as usize * CHUNK_SIZE;
+    (index < total).then(|| (total, &bytes[start..bytes.len().min(start + CHUNK_SIZE)]))
+}
+
+/// A download in progress, once we know how many chunks it takes
+#[derive(Debug, Default)]
+struct Transfer {
+    total: u32,
+    /// Chunks asked for and not answered yet
+    asked: HashSet<u32>,
+    /// Chunks ahead of the first missing one
+    parked: BTreeMap<u32, Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct Partials {
+    dir: PathBuf,
+    transfers: HashMap<Key, Transfer>,
+}
+
+impl Partials {
+    /// Partial downloads kept under `runtime_path`, dropping stale ones
+    ///
+    /// # Errors
+    ///
+    /// Fails if the directory can't be created
+    pub fn new(runtime_path: &Path) -> io::Result<Self> {
+        let dir = runtime_path.join(PARTIAL_DIR);
+        fs::create_dir_all(&dir)?;
+        let partials = Self {
+            dir,
+            transfers: HashMap::new(),
+        };
+        partials.clean(PARTIAL_TTL);
+        Ok(partials)
+    }
+
+    /// Where the chunks of `key` go. `None` if it isn't a gistit hash, plain ascii hex, so
+    /// whatever a peer sends can't name a file elsewhere
+    fn file(&self, key: &Key) -> Option<PathBuf> {
+        let hash = String::from_utf8(key.to_vec()).ok()?;
+        validate::hash(&hash).ok()?;
+        Some(self.dir.join(hash))
+    }
+
+    /// Removes partial downloads untouched for `max_age` and files that aren't ours
+    fn clean(&self, max_age: Duration) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Failed to clean partial downloads: {}", err);
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let ours = entry
+                .file_name()
+                .to_str()
+                .map_or(false, |name| validate::hash(name).is_ok());
+            // A clock going backwards keeps them
+            let stale = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .map_or(true, |modified| {
+                    modified.elapsed().map_or(false, |age| age >= max_age)
+                });
+            if !ours || stale {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Index of the first chunk we don't have yet
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn next_index(&self, key: &Key) -> u32 {
+        self.file(key)
+            .and_then(|file| fs::metadata(file).ok())
+            .map_or(0, |meta| (meta.len() / CHUNK_SIZE as u64) as u32)
+    }
+
+    /// The chunk of `key` to ask a provider for next, `None` if every chunk left was asked for
+    /// already. Until we know how many there are, it's the first missing one for everybody
+    pub fn pick(&mut self, key: &Key) -> Option<u32> {
+        let next = self.next_index(key);
+        let transfer = match self.transfers.get_mut(key) {
+            Some(transfer) => transfer,
+            None => return Some(next),
+        };
+        let index = (next..transfer.total).find(|index| {
+            !transfer.asked.contains(index) && !transfer.parked.contains_key(index)
+        })?;
+        transfer.asked.insert(index);
+        Some(index)
+    }
+
+    /// Chunk `index` of `key` won't come from whoever was asked, someone else may be asked
+    pub fn release(&mut self, key: &Key, index: u32) {
+        if let Some(transfer) = self.transfers.get_mut(key) {
+            transfer.asked.remove(&index);
+        }
+    }
+
+    /// Stores chunk `index` out of `total`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the chunk is malformed, more than a gistit takes or can't be written. The
+    /// partial download is dropped in the first two cases
+    pub fn append(
+        &mut self,
+        key: &Key,
+        index: u32,
+        total: u32,
+        data: &[u8],
+    ) -> io::Result<Progress> {
+        let path = self
+            .file(key)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.release(key, index);
+
+        let last = index + 1 == total;
+        let known_total = self
+            .transfers
+            .get(key)
+            .map_or(total, |transfer| transfer.total);
+        if index >= total
+            || total > MAX_CHUNKS
+            || total != known_total
+            || (!last && data.len() != CHUNK_SIZE)
+            || data.len() > CHUNK_SIZE
+        {
+            self.remove(key);
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let next = self.next_index(key);
+        let transfer = self
+            .transfers
+            .entry(key.clone())
+            .or_insert_with(|| Transfer {
+                total,
+                ..Transfer::default()
+            });
+        if index < next || transfer.parked.contains_key(&index) {
+            return Ok(Progress::Stale);
+        }
+        if index > next {
+            transfer.parked.insert(index, data.to_vec());
+            return Ok(Progress::Stored);
+        }
+
+        let mut file = OpenOptions::new().create(true).write(true).open(&path)?;
+        // Drops whatever an interrupted write left past the last whole chunk
+        let offset = u64::from(index) * CHUNK_SIZE as u64;
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+
+        // Along with the ones that were waiting on it
+        let mut stored = index;
+        while let Some(data) = transfer.parked.remove(&(stored + 1)) {
+            file.write_all(&data)?;
+            stored += 1;
+        }
+
+        if stored + 1 == total {
+            drop(file);
+            let bytes = fs::read(&path)?;
+            self.remove(key);
+            Ok(Progress::Done(bytes))
+        } else {
+            Ok(Progress::Stored)
+        }
+    }
+
+    /// Forgets the chunks of `key` held in memory and who was asked for what, the ones on disk
+    /// are kept to resume later
+    pub fn abandon(&mut self, key: &Key) {
+        self.transfers.remove(key);
+    }
+
+    /// Forgets a partial download, e.g. once it turned out corrupted
+    pub fn remove(&mut self, key: &Key) {
+        self.abandon(key);
+        if let Some(file) = self.file(key) {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn partial_resume_chunks() {
+        let runtime_path = TempDir::new().unwrap();
+        let mut partials = Partials::new(runtime_path.path()).unwrap();
+        let key = Key::new(&"a".repeat(64));
+        let payload: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+
+        assert_eq!(chunk(&[], 0), Some((1, &[][..])));
+        assert_eq!(chunk(&payload, 3), None);
+        let (total, first) = chunk(&payload, 0).unwrap();
+        assert_eq!(total, 3);
+
+        assert_eq!(partials.next_index(&key), 0);
+        assert_eq!(
+            partials.append(&key, 0, total, first).unwrap(),
+            Progress::Stored
+        );
+        assert_eq!(
+            partials.append(&key, 0, total, first).unwrap(),
+            Progress::Stale
+        );
+
+        // A fresh daemon picks up where the last one stopped
+        let mut partials = Partials::new(runtime_path.path()).unwrap();
+        assert_eq!(partials.next_index(&key), 1);
+        let (_, second) = chunk(&payload, 1).unwrap();
+        assert_eq!(
+            partials.append(&key, 1, total, second).unwrap(),
+            Progress::Stored
+        );
+        let (_, third) = chunk(&payload, 2).unwrap();
+        assert_eq!(
+            partials.append(&key, 2, total, third).unwrap(),
+            Progress::Done(payload.clone())
+        );
+        assert_eq!(partials.next_index(&key), 0);
+
+        // Short chunks in the middle are refused
+        assert!(partials.append(&key, 0, total, third).is_err());
+    }
+
+    #[test]
+    fn partial_parallel_chunks() {
+        let runtime_path = TempDir::new().unwrap();
+        let mut partials = Partials::new(runtime_path.path()).unwrap();
+        let key = Key::new(&"c".repeat(64));
+        let payload: Vec<u8> = (0..CHUNK_SIZE * 3 + 10).map(|i| i as u8).collect();
+        let piece = |index| chunk(&payload, index).unwrap().1;
+
+        // Everybody races for the first chunk until we know how many there are
+        assert_eq!(partials.pick(&key), Some(0));
+        assert_eq!(partials.pick(&key), Some(0));
+        assert_eq!(
+            partials.append(&key, 0, 4, piece(0)).unwrap(),
+            Progress::Stored
+        );
+        assert_eq!(
+            partials.append(&key, 0, 4, piece(0)).unwrap(),
+            Progress::Stale
+        );
+
+        // Then each provider gets its own
+        assert_eq!(partials.pick(&key), Some(1));
+        assert_eq!(partials.pick(&key), Some(2));
+        assert_eq!(partials.pick(&key), Some(3));
+        assert_eq!(partials.pick(&key), None);
+
+        // Ahead of the first missing one, held until it comes
+        assert_eq!(
+            partials.append(&key, 3, 4, piece(3)).unwrap(),
+            Progress::Stored
+        );
+        assert_eq!(partials.next_index(&key), 1);
+        partials.release(&key, 1);
+        assert_eq!(partials.pick(&key), Some(1));
+        assert_eq!(
+            partials.append(&key, 1, 4, piece(1)).unwrap(),
+            Progress::Stored
+        );
+        assert_eq!(
+            partials.append(&key, 3, 4, piece(3)).unwrap(),
+            Progress::Stale
+        );
+        assert_eq!(
+            partials.append(&key, 2, 4, piece(2)).unwrap(),
+            Progress::Done(payload.clone())
+        );
+        assert_eq!(partials.next_index(&key), 0);
+        assert_eq!(partials.pick(&key), Some(0));
+
+        // Providers disagreeing on the size are refused
+        partials.append(&key, 0, 4, piece(0)).unwrap();
+        assert!(partials.append(&key, 1, 5, piece(1)).is_err());
+        assert_eq!(partials.next_index(&key), 0);
+    }
+
+    #[test]
+    fn partial_refuse_bogus_chunks() {
+        let runtime_path = TempDir::new().unwrap();
+        let mut partials = Partials::new(runtime_path.path()).unwrap();
+        let key = Key::new(&"b".repeat(64));
+        let data = [0; CHUNK_SIZE];
+
+        // A peer can't make us store more than a gistit takes
+        assert!(partials.append(&key, 0, MAX_CHUNKS + 1, &data).is_err());
+        assert_eq!(partials.next_index(&key), 0);
+        assert_eq!(
+            partials.append(&key, 0, MAX_CHUNKS, &data).unwrap(),
+            Progress::Stored
+        );
+
+        // Nor write outside the partial directory
+        let escape = Key::new(&"../../escape");
+        assert!(partials.append(&escape, 0, 1, b"data").is_err());
+        assert_eq!(partials.next_index(&escape), 0);
+        assert_eq!(fs::read_dir(runtime_path.path()).unwrap().count(), 1);
+
+        fs::write(runtime_path.path().join(PARTIAL_DIR).join("stray"), "").unwrap();
+        partials.clean(PARTIAL_TTL);
+        assert_eq!(partials.next_index(&key), 1);
+        assert_eq!(
+            fs::read_dir(runtime_path.path().join(PARTIAL_DIR))
+                .unwrap()
+                .count(),
+            1
+        );
+        partials.clean(Duration::ZERO);
+        assert_eq!(partials.next_index(&key), 0);
+    }
+}
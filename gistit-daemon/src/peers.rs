@@ -0,0 +1,362 @@
+//! Persistent peers
+//!
+//! Peers given with `--dial` or added with `gistit node --add-peer` are kept in an address book
+//! in the data directory and dialed again whenever we aren't connected to them. Failed dials back
+//! off exponentially. Peers we never managed to reach are given up after [`MAX_FAILURES`] until
+//! the next start, the ones we reached before are retried for as long as they are in the book.
+//! A book that can't be read is set aside as `peers.json.corrupt` and started over.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use libp2p::core::{Multiaddr, PeerId};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use gistit_project::file;
+
+use crate::bootstrap::split_peer;
+use crate::{Error, Result};
+
+pub const PEERS_FILE: &str = "peers.json";
+
+/// How often we look for persistent peers to dial again
+pub const REDIAL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Peers never reached are not dialed again after failing this many times
+pub const MAX_FAILURES: u32 = 5;
+
+const BASE_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// The on disk format
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PeersFile {
+    #[serde(default)]
+    peers: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    address: String,
+
+    /// Unix timestamp in seconds of the last connection
+    #[serde(default)]
+    last_connected: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+    pub last_connected: Option<u64>,
+    pub failures: u32,
+    pub connected: bool,
+    dialing: bool,
+    next_dial: Instant,
+}
+
+impl Peer {
+    fn new(peer_id: PeerId, address: Multiaddr, last_connected: Option<u64>) -> Self {
+        Self {
+            peer_id,
+            address,
+            last_connected,
+            failures: 0,
+            connected: false,
+            dialing: false,
+            next_dial: Instant::now(),
+        }
+    }
+
+    const fn gave_up(&self) -> bool {
+        self.last_connected.is_none() && self.failures >= MAX_FAILURES
+    }
+}
+
+#[derive(Debug)]
+pub struct PeerBook {
+    peers: Vec<Peer>,
+    path: PathBuf,
+}
+
+/// Doubles from [`BASE_BACKOFF`] with every failure, up to [`MAX_BACKOFF`]
+fn backoff(failures: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(2_u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+fn read(path: &Path) -> Result<PeersFile> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn parse_entry(entry: &Entry) -> Result<Peer> {
+    let address: Multiaddr = entry.address.parse()?;
+    let (peer_id, _) =
+        split_peer(&address).ok_or(Error::Parse("persistent peer without peer id"))?;
+    Ok(Peer::new(peer_id, address, entry.last_connected))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+impl PeerBook {
+    /// Loads the book from `path`, empty if the file doesn't exist or can't be read. Entries we
+    /// can't make sense of are skipped
+    #[must_use]
+    pub fn load(path: &Path) -> Self {
+        let file = if fs::metadata(path).is_ok() {
+            read(path).unwrap_or_else(|err| {
+                let aside = path.with_extension("json.corrupt");
+                warn!(
+                    "Starting the persistent peers over, the broken book is kept at {:?}: {}",
+                    aside, err
+                );
+                let _ = fs::rename(path, aside);
+                PeersFile::default()
+            })
+        } else {
+            PeersFile::default()
+        };
+
+        let peers = file
+            .peers
+            .into_iter()
+            .filter_map(|entry| match parse_entry(&entry) {
+                Ok(peer) => Some(peer),
+                Err(err) => {
+                    warn!("Skipping persistent peer {}: {}", entry.address, err);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            peers,
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let file = PeersFile {
+            peers: self
+                .peers
+                .iter()
+                .map(|peer| Entry {
+                    address: peer.address.to_string(),
+                    last_connected: peer.last_connected,
+                })
+                .collect(),
+        };
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        file::replace(&self.path, &serde_json::to_vec_pretty(&file)?)?;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    /// Adds a `/p2p/<peer id>` suffixed address, due to be dialed right away. Returns `false` if
+    /// it was already in the book
+    pub fn add(&mut self, address: Multiaddr) -> Result<bool> {
+        let (peer_id, _) = split_peer(&address).ok_or(Error::Parse(
+            "persistent peer address must end with /p2p/<peer id>",
+        ))?;
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.address == address) {
+            // Asked for again, give it another chance
+            peer.failures = 0;
+            peer.next_dial = Instant::now();
+            return Ok(false);
+        }
+        self.peers.push(Peer::new(peer_id, address, None));
+        Ok(true)
+    }
+
+    /// Removes the peer with this address, or every address of this peer id. Returns how many
+    /// entries were removed
+    pub fn remove(&mut self, address_or_peer_id: &str) -> usize {
+        let before = self.peers.len();
+        match address_or_peer_id.parse::<PeerId>() {
+            Ok(peer_id) => self.peers.retain(|peer| peer.peer_id != peer_id),
+            Err(_) => self
+                .peers
+                .retain(|peer| peer.address.to_string() != address_or_peer_id),
+        }
+        before - self.peers.len()
+    }
+
+    /// Picks the peers due to be dialed again
+    pub fn due(&mut self, now: Instant) -> Vec<Multiaddr> {
+        self.peers
+            .iter_mut()
+            .filter(|peer| {
+                !peer.connected && !peer.dialing && !peer.gave_up() && peer.next_dial <= now
+            })
+            .map(|peer| {
+                peer.dialing = true;
+                peer.address.clone()
+            })
+            .collect()
+    }
+
+    /// Returns `true` if this is a persistent peer
+    pub fn on_connected(&mut self, peer_id: &PeerId) -> bool {
+        let now = now_secs();
+        self.update(peer_id, |peer| {
+            peer.connected = true;
+            peer.dialing = false;
+            peer.failures = 0;
+            peer.last_connected = Some(now);
+        })
+    }
+
+    /// Returns `true` if this is a persistent peer
+    pub fn on_failure(&mut self, peer_id: &PeerId) -> bool {
+        let now = Instant::now();
+        self.update(peer_id, |peer| {
+            peer.dialing = false;
+            peer.failures += 1;
+            peer.next_dial = now + backoff(peer.failures);
+        })
+    }
+
+    /// Returns `true` if this is a persistent peer
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) -> bool {
+        let now = Instant::now();
+        self.update(peer_id, |peer| {
+            peer.connected = false;
+            peer.next_dial = now + BASE_BACKOFF;
+        })
+    }
+
+    fn update(&mut self, peer_id: &PeerId, f: impl Fn(&mut Peer)) -> bool {
+        let mut found = false;
+        for peer in self
+            .peers
+            .iter_mut()
+            .filter(|peer| peer.peer_id == *peer_id)
+        {
+            f(peer);
+            found = true;
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn peer_book_redials_with_backoff() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(PEERS_FILE);
+        let mut book = PeerBook::load(&path);
+
+        let peer_id = PeerId::random();
+        let address: Multiaddr = format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer_id)
+            .parse()
+            .unwrap();
+        assert!(book
+            .add("/ip4/127.0.0.1/tcp/4001".parse().unwrap())
+            .is_err());
+        assert!(book.add(address.clone()).unwrap());
+        assert!(!book.add(address.clone()).unwrap());
+
+        let now = Instant::now();
+        assert_eq!(book.due(now), vec![address.clone()]);
+        // Already dialing
+        assert!(book.due(now).is_empty());
+
+        assert!(book.on_failure(&peer_id));
+        assert!(book.due(now).is_empty());
+        assert_eq!(book.due(now + BASE_BACKOFF * 2), vec![address]);
+        // Waits twice as long after the second failure
+        assert!(book.on_failure(&peer_id));
+        assert!(book.due(now + BASE_BACKOFF * 2).is_empty());
+
+        assert!(book.on_connected(&peer_id));
+        assert!(book.peers()[0].last_connected.is_some());
+        assert!(book.due(now + MAX_BACKOFF).is_empty());
+        assert!(!book.on_connected(&PeerId::random()));
+
+        book.save().unwrap();
+        let mut loaded = PeerBook::load(&path);
+        assert_eq!(loaded.peers().len(), 1);
+        assert_eq!(
+            loaded.peers()[0].last_connected,
+            book.peers()[0].last_connected
+        );
+
+        assert_eq!(loaded.remove(&peer_id.to_base58()), 1);
+        assert!(loaded.peers().is_empty());
+    }
+
+    #[test]
+    fn peer_book_survives_a_broken_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(PEERS_FILE);
+        let peer_id = PeerId::random();
+        let address = format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer_id);
+
+        fs::write(
+            &path,
+            format!(
+                r#"{{"peers": [{{"address": "{}"}}, {{"address": "/ip4/127.0.0.1/tcp/4001"}}, {{"address": "nonsense"}}]}}"#,
+                address
+            ),
+        )
+        .unwrap();
+        let book = PeerBook::load(&path);
+        assert_eq!(book.peers().len(), 1);
+        assert_eq!(book.peers()[0].peer_id, peer_id);
+
+        fs::write(&path, r#"{"peers": [{"addr"#).unwrap();
+        let book = PeerBook::load(&path);
+        assert!(book.peers().is_empty());
+        assert!(fs::metadata(&path).is_err());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("peers.json.corrupt")).unwrap(),
+            r#"{"peers": [{"addr"#
+        );
+
+        book.save().unwrap();
+        assert_eq!(
+            fs::read_dir(dir.path()).unwrap().count(),
+            2,
+            "no temporary file left behind"
+        );
+    }
+
+    #[test]
+    fn peer_book_gives_up_on_unreachable_peers() {
+        assert_eq!(backoff(1), BASE_BACKOFF);
+        assert_eq!(backoff(2), BASE_BACKOFF * 2);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+
+        let mut book = PeerBook::load(Path::new("/nonexistent/peers.json"));
+        let peer_id = PeerId::random();
+        book.add(
+            format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer_id)
+                .parse()
+                .unwrap(),
+        )
+        .unwrap();
+
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(book.due(Instant::now() + MAX_BACKOFF).len(), 1);
+            book.on_failure(&peer_id);
+        }
+        assert!(book.due(Instant::now() + MAX_BACKOFF).is_empty());
+    }
+}
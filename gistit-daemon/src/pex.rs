@@ -0,0 +1,407 @@
+//! Peer exchange
+//!
+//! Without bootstrap connectivity, e.g. on a LAN or behind a firewall blocking the public
+//! bootstrap peers, a node only knows the peers it dials itself. Connected gistit nodes trade a
+//! random sample of the peers they are connected to: right after identifying each other, then
+//! with a few of them every `--pex-interval`. Addresses learned this way are dialed while we are
+//! connected to fewer than [`PEX_TARGET`] peers, kademlia only gets them from identify once we
+//! are connected. Private addresses are only traded with peers on the same network as us, the
+//! ones that see us at a private address. Disabled with `--no-pex`, the protocol isn't
+//! advertised then.
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::core::{Multiaddr, PeerId, ProtocolName};
+use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::RequestResponseCodec;
+
+use crate::address;
+use crate::bootstrap::split_peer;
+
+pub const PEX_PROTOCOL: &[u8] = b"/gistit/pex/1";
+
+/// How often we trade peers when not told otherwise
+pub const DEFAULT_PEX_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Learned peers are only dialed while we have fewer connections than this
+pub const PEX_TARGET: usize = 8;
+
+/// Most addresses sent or taken in one exchange
+const MAX_SAMPLE: usize = 16;
+
+/// Addresses shared per peer, the first ones it listens on
+const MAX_ADDRESSES_PER_PEER: usize = 2;
+
+/// How many connected peers we trade with every interval
+const FANOUT: usize = 3;
+
+/// Learned peers we dialed are not dialed again before this
+const REDIAL_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Big enough for [`MAX_SAMPLE`] addresses, multiaddrs are short
+const MAX_MESSAGE_SIZE: usize = 8 * 1024;
+
+#[derive(Debug)]
+struct Connected {
+    /// Where it listens, `/p2p/<peer id>` suffixed
+    addresses: Vec<Multiaddr>,
+    supports_pex: bool,
+    /// On the same network as us, it sees us at a private address
+    same_network: bool,
+}
+
+#[derive(Debug)]
+pub struct PeerExchange {
+    interval: Duration,
+
+    /// Identified peers we are connected to
+    connected: HashMap<PeerId, Connected>,
+
+    /// Learned peers and when we last dialed them
+    dialed: HashMap<PeerId, Instant>,
+}
+
+impl PeerExchange {
+    #[must_use]
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            connected: HashMap::new(),
+            dialed: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Records where an identified peer listens and where it sees us. Returns `true` the first
+    /// time a peer speaking the protocol is identified since it connected, so we trade with it
+    /// right away
+    pub fn identified(
+        &mut self,
+        peer_id: PeerId,
+        listen_addrs: &[Multiaddr],
+        observed_addr: &Multiaddr,
+        supports_pex: bool,
+    ) -> bool {
+        let addresses = listen_addrs
+            .iter()
+            .filter(|address| shareable(address))
+            .take(MAX_ADDRESSES_PER_PEER)
+            .map(|address| with_peer(address, peer_id))
+            .collect();
+        let first = self
+            .connected
+            .get(&peer_id)
+            .map_or(true, |connected| !connected.supports_pex);
+        self.connected.insert(
+            peer_id,
+            Connected {
+                addresses,
+                supports_pex,
+                same_network: !address::is_global(observed_addr),
+            },
+        );
+        supports_pex && first
+    }
+
+    pub fn disconnected(&mut self, peer_id: &PeerId) {
+        self.connected.remove(peer_id);
+    }
+
+    /// Whether `peer_id` is on the same network as us, so private addresses are fine to trade
+    /// with it
+    #[must_use]
+    pub fn same_network(&self, peer_id: &PeerId) -> bool {
+        self.connected
+            .get(peer_id)
+            .map_or(false, |connected| connected.same_network)
+    }
+
+    /// Up to [`MAX_SAMPLE`] addresses of random peers we are connected to, `to` left out. Private
+    /// ones only if `to` is on our network
+    pub fn sample(&self, to: &PeerId, shared: impl Fn(&PeerId) -> bool) -> Vec<Multiaddr> {
+        let same_network = self.same_network(to);
+        shuffled(
+            self.connected
+                .keys()
+                .filter(|peer_id| *peer_id != to && shared(*peer_id)),
+        )
+        .into_iter()
+        .flat_map(|peer_id| self.connected[peer_id].addresses.iter().cloned())
+        .filter(|address| same_network || address::is_global(address))
+        .take(MAX_SAMPLE)
+        .collect()
+    }
+
+    /// Up to [`FANOUT`] random peers to trade with
+    pub fn partners(&self) -> Vec<PeerId> {
+        shuffled(
+            self.connected
+                .iter()
+                .filter(|(_, connected)| connected.supports_pex)
+                .map(|(peer_id, _)| peer_id),
+        )
+        .into_iter()
+        .take(FANOUT)
+        .copied()
+        .collect()
+    }
+
+    /// Picks up to `wanted` learned peers to dial, skipping the ones we are connected to or
+    /// dialed lately
+    pub fn to_dial(
+        &mut self,
+        learned: &[(PeerId, Multiaddr)],
+        wanted: usize,
+        now: Instant,
+    ) -> Vec<Multiaddr> {
+        self.dialed
+            .retain(|_, dialed| now.saturating_duration_since(*dialed) < REDIAL_AFTER);
+
+        let mut addresses = Vec::new();
+        for (peer_id, address) in learned {
+            if addresses.len() >= wanted {
+                break;
+            }
+            if self.connected.contains_key(peer_id) || self.dialed.contains_key(peer_id) {
+                continue;
+            }
+            self.dialed.insert(*peer_id, now);
+            addresses.push(with_peer(address, *peer_id));
+        }
+        addresses
+    }
+}
+
+/// Splits the addresses a peer sent us, leaving out ours and the malformed ones. Private ones
+/// are left out too unless it's on our network
+pub fn learn(
+    local: &PeerId,
+    addresses: Vec<Multiaddr>,
+    same_network: bool,
+) -> Vec<(PeerId, Multiaddr)> {
+    addresses
+        .into_iter()
+        .take(MAX_SAMPLE)
+        .filter(shareable)
+        .filter(|address| same_network || address::is_global(address))
+        .filter_map(|address| split_peer(&address))
+        .filter(|(peer_id, _)| peer_id != local)
+        .collect()
+}
+
+/// Orders items at random, a different order every call
+fn shuffled<T: Hash>(items: impl Iterator<Item = T>) -> Vec<T> {
+    let state = RandomState::new();
+    let mut items: Vec<T> = items.collect();
+    items.sort_by_cached_key(|item| {
+        let mut hasher = state.build_hasher();
+        item.hash(&mut hasher);
+        hasher.finish()
+    });
+    items
+}
+
+/// Unspecified addresses can't be dialed
+fn shareable(address: &Multiaddr) -> bool {
+    !address.iter().any(|protocol| match protocol {
+        Protocol::Ip4(ip) => ip.is_unspecified(),
+        Protocol::Ip6(ip) => ip.is_unspecified(),
+        _ => false,
+    })
+}
+
+fn with_peer(address: &Multiaddr, peer_id: PeerId) -> Multiaddr {
+    match split_peer(address) {
+        Some(_) => address.clone(),
+        None => address.clone().with(Protocol::P2p(peer_id.into())),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PexProtocol;
+
+impl ProtocolName for PexProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PEX_PROTOCOL
+    }
+}
+
+/// Both sides send a sample, the asking peer first
+#[derive(Clone, Default)]
+pub struct PexCodec;
+
+/// Each address prefixed by its length as two big endian bytes
+fn encode_sample(addresses: &[Multiaddr]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for address in addresses.iter().take(MAX_SAMPLE) {
+        let bytes = address.to_vec();
+        if let Ok(len) = u16::try_from(bytes.len()) {
+            buf.extend(len.to_be_bytes());
+            buf.extend(bytes);
+        }
+    }
+    buf
+}
+
+fn decode_sample(mut bytes: &[u8]) -> io::Result<Vec<Multiaddr>> {
+    let mut addresses = Vec::new();
+    while !bytes.is_empty() {
+        if addresses.len() == MAX_SAMPLE || bytes.len() < 2 {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let (len, rest) = bytes.split_at(2);
+        let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+        if rest.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (address, rest) = rest.split_at(len);
+        addresses.push(
+            Multiaddr::try_from(address.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+        );
+        bytes = rest;
+    }
+    Ok(addresses)
+}
+
+#[async_trait]
+impl RequestResponseCodec for PexCodec {
+    type Protocol = PexProtocol;
+    type Request = Vec<Multiaddr>;
+    type Response = Vec<Multiaddr>;
+
+    async fn read_request<T: Send + Unpin + AsyncRead>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request> {
+        decode_sample(&read_length_prefixed(io, MAX_MESSAGE_SIZE).await?)
+    }
+
+    async fn read_response<T: Send + Unpin + AsyncRead>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response> {
+        decode_sample(&read_length_prefixed(io, MAX_MESSAGE_SIZE).await?)
+    }
+
+    async fn write_request<T: Send + Unpin + AsyncWrite>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()> {
+        write_length_prefixed(io, encode_sample(&request)).await?;
+        io.close().await
+    }
+
+    async fn write_response<T: Send + Unpin + AsyncWrite>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()> {
+        write_length_prefixed(io, encode_sample(&response)).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(peer_id: PeerId, port: u16) -> Multiaddr {
+        format!("/ip4/10.0.0.1/tcp/{}/p2p/{}", port, peer_id)
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn pex_sample_encoding() {
+        let addresses: Vec<Multiaddr> =
+            (0..3).map(|port| address(PeerId::random(), port)).collect();
+        assert_eq!(
+            decode_sample(&encode_sample(&addresses)).unwrap(),
+            addresses
+        );
+        assert_eq!(decode_sample(&[]).unwrap(), Vec::<Multiaddr>::new());
+
+        let encoded = encode_sample(&addresses);
+        assert!(decode_sample(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_sample(&[0, 3, 0xff, 0xff, 0xff]).is_err());
+
+        let many: Vec<Multiaddr> = (0..MAX_SAMPLE as u16 + 4)
+            .map(|port| address(PeerId::random(), port))
+            .collect();
+        let encoded = encode_sample(&many);
+        assert_eq!(decode_sample(&encoded).unwrap().len(), MAX_SAMPLE);
+    }
+
+    #[test]
+    fn pex_trades_connected_peers() {
+        let local = PeerId::random();
+        let (first, second, third) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut pex = PeerExchange::new(DEFAULT_PEX_INTERVAL);
+
+        let listen: Vec<Multiaddr> = vec![
+            "/ip4/0.0.0.0/tcp/4001".parse().unwrap(),
+            "/ip4/10.0.0.1/tcp/4001".parse().unwrap(),
+        ];
+        let (lan, internet): (Multiaddr, Multiaddr) = (
+            "/ip4/10.0.0.9/tcp/4001".parse().unwrap(),
+            "/ip4/1.2.3.4/tcp/4001".parse().unwrap(),
+        );
+        assert!(pex.identified(first, &listen, &lan, true));
+        // Identified again, already traded with
+        assert!(!pex.identified(first, &listen, &lan, true));
+        assert!(!pex.identified(second, &listen, &lan, false));
+
+        // The unspecified address is left out and the peer id appended
+        assert_eq!(pex.sample(&second, |_| true), vec![address(first, 4001)]);
+        assert!(pex.sample(&first, |peer_id| *peer_id != second).is_empty());
+        assert_eq!(pex.partners(), vec![first]);
+
+        // Peers out of our network don't get private addresses
+        assert!(!pex.identified(second, &listen, &internet, false));
+        assert!(!pex.same_network(&second));
+        assert!(pex.sample(&second, |_| true).is_empty());
+
+        let sent = vec![
+            address(local, 1),
+            address(first, 2),
+            address(third, 3),
+            "/ip4/10.0.0.2/tcp/4".parse().unwrap(),
+            "/ip4/127.0.0.1/tcp/5/p2p/12D3KooWJ1TsijH7H5F74hfAD5XishQz3sxrmAtVY37GtNd9CqYf"
+                .parse()
+                .unwrap(),
+        ];
+        assert!(learn(&local, sent.clone(), false).is_empty());
+        let learned = learn(&local, sent, true);
+        assert_eq!(learned.len(), 3);
+
+        let now = Instant::now();
+        let learned = &learned[..2];
+        // Connected to the first one already
+        assert_eq!(pex.to_dial(learned, 8, now), vec![address(third, 3)]);
+        assert!(pex.to_dial(learned, 8, now).is_empty());
+        assert_eq!(
+            pex.to_dial(learned, 8, now + REDIAL_AFTER),
+            vec![address(third, 3)]
+        );
+        assert!(pex.to_dial(learned, 0, now + REDIAL_AFTER * 2).is_empty());
+
+        pex.disconnected(&first);
+        assert!(pex.partners().is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+//! Query tuning
+//!
+//! How many peers kademlia queries ask at once, and how long queries and requests to a single
+//! peer may take. Gistits are small, so lookups are short and a peer that takes long to answer
+//! one is unlikely to ever answer. The defaults give up early instead of letting a fetch look
+//! hung: a kademlia query and the request following it fit in the 30 seconds the CLI waits on
+//! the node.
+//!
+//! Only lookups someone waits on are cut short, see [`Lookups`]. Kademlia itself keeps the long
+//! [`PROVIDE_TIMEOUT`], publishing a provider record or bootstrapping on a large DHT takes
+//! longer and giving up early leaves records on fewer peers.
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use libp2p::kad::{KademliaConfig, QueryId};
+use libp2p::request_response::RequestResponseConfig;
+
+use crate::{Error, Result};
+
+/// How long kademlia queries nobody waits on may take, e.g. providing or bootstrapping
+pub const PROVIDE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How often lookups are checked against their deadline
+pub const LOOKUP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Peers a query may ask at once, past that lookups mostly flood the closest peers
+const PARALLELISM_RANGE: RangeInclusive<usize> = 1..=20;
+
+/// User configurable query settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Queries {
+    /// Peers a kademlia query asks at once
+    pub parallelism: NonZeroUsize,
+
+    /// How long a lookup, e.g. for the providers of a gistit, may take
+    pub query_timeout: Duration,
+
+    /// How long a peer may take to answer a request
+    pub request_timeout: Duration,
+}
+
+impl Default for Queries {
+    fn default() -> Self {
+        Self {
+            // libp2p asks 3 peers at once, lookups of small records finish sooner asking more
+            parallelism: NonZeroUsize::new(5).expect("to be non zero"),
+            query_timeout: Duration::from_secs(15),
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Queries {
+    /// # Errors
+    ///
+    /// Fails if a timeout is under a second or the parallelism is over 20
+    pub fn validate(&self) -> Result<()> {
+        if !PARALLELISM_RANGE.contains(&self.parallelism.get()) {
+            return Err(Error::Parse("--query-parallelism must be between 1 and 20"));
+        }
+        if self.query_timeout < Duration::from_secs(1) {
+            return Err(Error::Parse("--query-timeout must be at least a second"));
+        }
+        if self.request_timeout < Duration::from_secs(1) {
+            return Err(Error::Parse("--request-timeout must be at least a second"));
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn kademlia(&self) -> KademliaConfig {
+        let mut cfg = KademliaConfig::default();
+        cfg.set_parallelism(self.parallelism)
+            .set_query_timeout(PROVIDE_TIMEOUT);
+        cfg
+    }
+
+    #[must_use]
+    pub fn request_response(&self) -> RequestResponseConfig {
+        let mut cfg = RequestResponseConfig::default();
+        cfg.set_request_timeout(self.request_timeout);
+        cfg
+    }
+}
+
+/// Lookups someone waits on and when they give up. Overdue ones are finished with whatever
+/// they found so far
+#[derive(Debug, Default)]
+pub struct Lookups {
+    deadlines: HashMap<QueryId, Instant>,
+    /// Finished before completing on their own
+    cut: Vec<QueryId>,
+}
+
+impl Lookups {
+    pub fn start(&mut self, query_id: QueryId, deadline: Instant) {
+        self.deadlines.insert(query_id, deadline);
+    }
+
+    /// Lookups past their deadline, they are only returned once
+    pub fn overdue(&mut self, now: Instant) -> Vec<QueryId> {
+        let overdue: Vec<QueryId> = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(query_id, _)| *query_id)
+            .collect();
+        for query_id in &overdue {
+            self.deadlines.remove(query_id);
+        }
+        self.cut.extend(overdue.iter().copied());
+        overdue
+    }
+
+    /// Forgets a completed lookup, returns whether it was cut short
+    pub fn completed(&mut self, query_id: &QueryId) -> bool {
+        self.deadlines.remove(query_id);
+        let cut = self.cut.iter().position(|cut| cut == query_id);
+        cut.map(|index| self.cut.swap_remove(index)).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_defaults_fit_client_timeout_and_validate() {
+        let queries = Queries::default();
+        assert!(queries.validate().is_ok());
+        assert!(queries.query_timeout + queries.request_timeout <= Duration::from_secs(30));
+
+        let zero = Queries {
+            query_timeout: Duration::ZERO,
+            ..queries
+        };
+        assert!(zero.validate().is_err());
+        let zero = Queries {
+            request_timeout: Duration::from_millis(500),
+            ..queries
+        };
+        assert!(zero.validate().is_err());
+        let flood = Queries {
+            parallelism: NonZeroUsize::new(21).unwrap(),
+            ..queries
+        };
+        assert!(flood.validate().is_err());
+    }
+}
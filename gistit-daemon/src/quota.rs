@@ -0,0 +1,129 @@
+//! Hosting quota
+//!
+//! Gistits we provide on purpose, from the client or `--provide-dir`, are capped by count and by
+//! size, `--max-hosted` and `--max-hosted-bytes`. Going over refuses the new gistit instead of
+//! dropping an older one, the user decides what to stop hosting. Reseeded gistits share the
+//! store but have a cap of their own, see [`crate::reseed`].
+use gistit_proto::ipc::instruction::provide_response::Status;
+
+/// Default max hosted gistits, under the 1024 provider records kademlia keeps
+pub const DEFAULT_MAX_HOSTED: usize = 1_000;
+
+/// Default max bytes of hosted gistits
+pub const DEFAULT_MAX_HOSTED_BYTES: u64 = 50_000_000;
+
+/// Why a gistit can't be hosted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// Bigger than the whole quota
+    TooLarge {
+        size: u64,
+        max_bytes: u64,
+    },
+    Count {
+        max: usize,
+    },
+    Bytes {
+        used: u64,
+        max_bytes: u64,
+    },
+}
+
+impl Refusal {
+    #[must_use]
+    pub const fn status(&self) -> Status {
+        match self {
+            Self::TooLarge { .. } => Status::TooLarge,
+            Self::Count { .. } | Self::Bytes { .. } => Status::QuotaExceeded,
+        }
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge { size, max_bytes } => write!(
+                f,
+                "gistit takes {} bytes, the node hosts up to {} bytes",
+                size, max_bytes
+            ),
+            Self::Count { max } => write!(f, "the node hosts {} gistits already", max),
+            Self::Bytes { used, max_bytes } => {
+                write!(f, "the node hosts {} of {} bytes already", used, max_bytes)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    max_count: usize,
+    max_bytes: u64,
+}
+
+impl Quota {
+    #[must_use]
+    pub const fn new(max_count: usize, max_bytes: u64) -> Self {
+        Self {
+            max_count,
+            max_bytes,
+        }
+    }
+
+    /// Whether a gistit of `size` bytes fits next to the `hosted` ones, given by their sizes
+    ///
+    /// # Errors
+    ///
+    /// Fails with the first limit it would break
+    pub fn check(&self, size: u64, hosted: impl Iterator<Item = u64>) -> Result<(), Refusal> {
+        if size > self.max_bytes {
+            return Err(Refusal::TooLarge {
+                size,
+                max_bytes: self.max_bytes,
+            });
+        }
+
+        let (count, used) = hosted.fold((0, 0), |(count, used), size| (count + 1, used + size));
+        if count >= self.max_count {
+            Err(Refusal::Count {
+                max: self.max_count,
+            })
+        } else if used + size > self.max_bytes {
+            Err(Refusal::Bytes {
+                used,
+                max_bytes: self.max_bytes,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quota_refuses_with_reason() {
+        let quota = Quota::new(2, 100);
+
+        assert_eq!(quota.check(40, [50].into_iter()), Ok(()));
+        assert_eq!(
+            quota.check(101, std::iter::empty()),
+            Err(Refusal::TooLarge {
+                size: 101,
+                max_bytes: 100
+            })
+        );
+        assert_eq!(
+            quota.check(1, [10, 10].into_iter()),
+            Err(Refusal::Count { max: 2 })
+        );
+        assert_eq!(
+            quota
+                .check(60, [50].into_iter())
+                .map_err(|refusal| refusal.status()),
+            Err(Status::QuotaExceeded)
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Relays we are reachable through
+//!
+//! Peers advertising the circuit relay hop protocol are discovered through identify. We listen on
+//! a circuit address through a handful of them, which makes the relay client reserve a slot. Once
+//! a reservation is accepted the `/p2p-circuit` addresses are advertised as external addresses so
+//! they end up in our provider records.
+use std::collections::HashMap;
+
+use libp2p::core::connection::ListenerId;
+use libp2p::core::{Multiaddr, PeerId};
+use libp2p::multiaddr::Protocol;
+
+/// How many relays we keep reservations with at once
+pub const MAX_RELAYS: usize = 3;
+
+#[derive(Debug, Default)]
+struct Relay {
+    /// `/p2p/<relay>/p2p-circuit` addresses and the listener opened for each
+    circuits: Vec<(Multiaddr, ListenerId)>,
+    reserved: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Relays {
+    relays: HashMap<PeerId, Relay>,
+}
+
+/// Whether the address goes through a relay
+#[must_use]
+pub fn is_circuit(address: &Multiaddr) -> bool {
+    address
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+}
+
+impl Relays {
+    /// The circuit address to listen on through `relay` at `address`. `None` if it's already
+    /// known, is a circuit itself or we have enough relays
+    #[must_use]
+    pub fn candidate(&self, relay: &PeerId, address: &Multiaddr) -> Option<Multiaddr> {
+        // Don't attempt to relay over the relay
+        if is_circuit(address) {
+            return None;
+        }
+
+        let circuit = address
+            .clone()
+            .with(Protocol::P2p((*relay).into()))
+            .with(Protocol::P2pCircuit);
+
+        match self.relays.get(relay) {
+            Some(known) if known.circuits.iter().any(|(addr, _)| *addr == circuit) => None,
+            Some(_) => Some(circuit),
+            None if self.relays.len() < MAX_RELAYS => Some(circuit),
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, relay: PeerId, circuit: Multiaddr, listener: ListenerId) {
+        self.relays
+            .entry(relay)
+            .or_default()
+            .circuits
+            .push((circuit, listener));
+    }
+
+    /// Returns `true` the first time a reservation with this relay is accepted
+    pub fn on_reserved(&mut self, relay: &PeerId) -> bool {
+        self.relays
+            .get_mut(relay)
+            .map_or(false, |relay| !std::mem::replace(&mut relay.reserved, true))
+    }
+
+    /// Drops the relay, returning the listeners that should be closed
+    pub fn on_failed(&mut self, relay: &PeerId) -> Vec<ListenerId> {
+        self.relays.remove(relay).map_or_else(Vec::new, |relay| {
+            relay
+                .circuits
+                .into_iter()
+                .map(|(_, listener)| listener)
+                .collect()
+        })
+    }
+
+    /// Circuit addresses of every relay we know, reserved or not
+    pub fn circuits(&self) -> impl Iterator<Item = &Multiaddr> {
+        self.relays
+            .values()
+            .flat_map(|relay| relay.circuits.iter().map(|(addr, _)| addr))
+    }
+
+    #[must_use]
+    pub fn reserved_count(&self) -> usize {
+        self.relays.values().filter(|relay| relay.reserved).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relays_candidates_and_reservations() {
+        let mut relays = Relays::default();
+        let address: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+
+        for n in 0..MAX_RELAYS {
+            let relay = PeerId::random();
+            let circuit = relays.candidate(&relay, &address).unwrap();
+            assert!(is_circuit(&circuit));
+            relays.insert(relay, circuit.clone(), ListenerId::new(n as u64));
+
+            // Same address twice is ignored, relaying over a circuit too
+            assert!(relays.candidate(&relay, &address).is_none());
+            assert!(relays.candidate(&relay, &circuit).is_none());
+
+            assert!(relays.on_reserved(&relay));
+            assert!(!relays.on_reserved(&relay));
+        }
+
+        let extra = PeerId::random();
+        assert!(relays.candidate(&extra, &address).is_none());
+        assert!(!relays.on_reserved(&extra));
+        assert_eq!(relays.reserved_count(), MAX_RELAYS);
+
+        let relay = *relays.relays.keys().next().unwrap();
+        assert_eq!(relays.on_failed(&relay).len(), 1);
+        assert_eq!(relays.circuits().count(), MAX_RELAYS - 1);
+        assert!(relays.candidate(&extra, &address).is_some());
+    }
+}
@@ -0,0 +1,85 @@
+//! Config reload
+//!
+//! Rate limits, bans and the hosting quota are read from the `node-*` keys of the settings file
+//! too, flags winning over them like they do for the client. A `ReloadConfigRequest` reads the
+//! file again, along with the trust lists, and the running node takes the new values without
+//! dropping connections. Rate limit buckets and bans in place are kept, they refill and expire
+//! as they would have. Connection limits and the largest message size are fixed once the swarm
+//! is built and only change with a restart.
+use std::time::Duration;
+
+use gistit_project::settings::Settings;
+
+use crate::limit::Limits;
+
+/// Limits given as flags, they win over the settings file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Overrides {
+    pub requests_per_minute: Option<u32>,
+    pub ban_duration: Option<Duration>,
+    pub max_hosted: Option<usize>,
+    pub max_hosted_bytes: Option<u64>,
+}
+
+impl Overrides {
+    /// `limits` with the reloadable ones taken from the flags, the settings or the defaults, in
+    /// that order
+    #[must_use]
+    pub fn apply(&self, limits: Limits, settings: &Settings) -> Limits {
+        let defaults = Limits::default();
+        Limits {
+            requests_per_minute: self
+                .requests_per_minute
+                .or(settings.node_rate_limit)
+                .unwrap_or(defaults.requests_per_minute),
+            ban_duration: self
+                .ban_duration
+                .or_else(|| settings.node_ban_duration.map(Duration::from_secs))
+                .unwrap_or(defaults.ban_duration),
+            max_hosted: self
+                .max_hosted
+                .or(settings.node_max_hosted)
+                .unwrap_or(defaults.max_hosted),
+            max_hosted_bytes: self
+                .max_hosted_bytes
+                .or(settings.node_max_hosted_bytes)
+                .unwrap_or(defaults.max_hosted_bytes),
+            ..limits
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_flags_win_over_settings() {
+        let limits = Limits {
+            max_connections: 16,
+            ..Limits::default()
+        };
+        let settings = Settings {
+            node_rate_limit: Some(30),
+            node_ban_duration: Some(60),
+            node_max_hosted: Some(10),
+            ..Settings::default()
+        };
+        let overrides = Overrides {
+            requests_per_minute: Some(120),
+            ..Overrides::default()
+        };
+
+        let applied = overrides.apply(limits, &settings);
+        assert_eq!(applied.requests_per_minute, 120);
+        assert_eq!(applied.ban_duration, Duration::from_secs(60));
+        assert_eq!(applied.max_hosted, 10);
+        assert_eq!(applied.max_hosted_bytes, Limits::default().max_hosted_bytes);
+        // Not reloadable, kept as they are
+        assert_eq!(applied.max_connections, 16);
+
+        // Unset again, back to the default
+        let applied = overrides.apply(applied, &Settings::default());
+        assert_eq!(applied.max_hosted, Limits::default().max_hosted);
+    }
+}
@@ -0,0 +1,172 @@
+//! Replication
+//!
+//! A gistit sent with `gistit send --ttl-peers <n>` is handed to peers in our allowlist, one per
+//! missing copy, until `n` of them agreed to provide it too or there is no one left to ask. Peers
+//! only agree if they run with `--reseed` and have us in their own allowlist.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use libp2p::kad::record::Key;
+use libp2p::request_response::RequestId;
+use libp2p::PeerId;
+
+#[derive(Debug)]
+struct Replication {
+    wanted: usize,
+    replicated: usize,
+    in_flight: usize,
+
+    /// Peers not asked yet
+    candidates: Vec<PeerId>,
+}
+
+/// What to do next for a gistit being replicated
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Ask these peers to provide it
+    Ask(Key, Vec<PeerId>),
+
+    /// Every peer answered, `replicated` out of `wanted` provide it
+    Done {
+        key: Key,
+        replicated: usize,
+        wanted: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct Replications<Id = RequestId> {
+    replications: HashMap<Key, Replication>,
+
+    /// Requests waiting for an answer and the gistit they are for
+    pending: HashMap<Id, Key>,
+}
+
+impl<Id> Default for Replications<Id> {
+    fn default() -> Self {
+        Self {
+            replications: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<Id: Hash + Eq> Replications<Id> {
+    /// Starts replicating a gistit to `wanted` of the `candidates`, asked in order. `None` if it
+    /// is being replicated already
+    pub fn start(&mut self, key: Key, wanted: usize, candidates: Vec<PeerId>) -> Option<Step> {
+        if self.replications.contains_key(&key) {
+            return None;
+        }
+        self.replications.insert(
+            key.clone(),
+            Replication {
+                wanted,
+                replicated: 0,
+                in_flight: 0,
+                candidates,
+            },
+        );
+        self.next(key)
+    }
+
+    pub fn sent(&mut self, request_id: Id, key: Key) {
+        self.pending.insert(request_id, key);
+    }
+
+    #[must_use]
+    pub fn contains(&self, request_id: &Id) -> bool {
+        self.pending.contains_key(request_id)
+    }
+
+    /// Records whether the peer agreed, a failed request counts as a refusal
+    pub fn answered(&mut self, request_id: &Id, accepted: bool) -> Option<Step> {
+        let key = self.pending.remove(request_id)?;
+        let replication = self.replications.get_mut(&key)?;
+        replication.in_flight = replication.in_flight.saturating_sub(1);
+        if accepted {
+            replication.replicated += 1;
+        }
+        self.next(key)
+    }
+
+    /// Gives up on a gistit, answers still coming are ignored
+    pub fn forget(&mut self, key: &Key) {
+        self.replications.remove(key);
+        self.pending.retain(|_, pending| pending != key);
+    }
+
+    fn next(&mut self, key: Key) -> Option<Step> {
+        let replication = self.replications.get_mut(&key)?;
+        let missing = replication
+            .wanted
+            .saturating_sub(replication.replicated + replication.in_flight)
+            .min(replication.candidates.len());
+
+        if missing > 0 {
+            let peers: Vec<PeerId> = replication.candidates.drain(..missing).collect();
+            replication.in_flight += peers.len();
+            return Some(Step::Ask(key, peers));
+        }
+        if replication.in_flight > 0 {
+            return None;
+        }
+
+        let Replication {
+            wanted, replicated, ..
+        } = self.replications.remove(&key)?;
+        Some(Step::Done {
+            key,
+            replicated,
+            wanted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replicate_until_enough_accepted() {
+        let key = Key::new(&"gistit");
+        let peers: Vec<PeerId> = (0..4).map(|_| PeerId::random()).collect();
+        let mut replications = Replications::default();
+
+        assert_eq!(
+            replications.start(key.clone(), 2, peers.clone()),
+            Some(Step::Ask(key.clone(), peers[..2].to_vec()))
+        );
+        assert_eq!(replications.start(key.clone(), 2, peers.clone()), None);
+        replications.sent(1, key.clone());
+        replications.sent(2, key.clone());
+        assert!(replications.contains(&2));
+
+        // A refusal moves on to the next candidate
+        assert_eq!(
+            replications.answered(&1, false),
+            Some(Step::Ask(key.clone(), vec![peers[2]]))
+        );
+        replications.sent(3, key.clone());
+        assert_eq!(replications.answered(&2, true), None);
+        assert_eq!(
+            replications.answered(&3, true),
+            Some(Step::Done {
+                key: key.clone(),
+                replicated: 2,
+                wanted: 2
+            })
+        );
+        assert!(replications.answered(&3, true).is_none());
+
+        // Out of candidates
+        assert_eq!(
+            replications.start(key.clone(), 3, Vec::new()),
+            Some(Step::Done {
+                key,
+                replicated: 0,
+                wanted: 3
+            })
+        );
+    }
+}
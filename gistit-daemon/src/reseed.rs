@@ -0,0 +1,132 @@
+//! Reseeding of fetched gistits
+//!
+//! With `--reseed`, gistits fetched from other peers are provided by us too, so they stay
+//! available after the original provider leaves. Reseeded gistits share the provider store with
+//! the ones we host on purpose, this only keeps track of which are reseeded and how much room
+//! they take. Once over the storage cap the least recently used ones are dropped.
+use std::collections::VecDeque;
+
+use libp2p::kad::record::Key;
+
+use gistit_proto::Gistit;
+
+/// Default storage cap in bytes
+pub const DEFAULT_RESEED_CAP: u64 = 5_000_000;
+
+#[derive(Debug, Default)]
+pub struct Reseed {
+    /// Storage cap in bytes, `None` if reseeding is disabled
+    cap: Option<u64>,
+
+    /// Reseeded keys with their size, least recently used first
+    entries: VecDeque<(Key, u64)>,
+    used: u64,
+}
+
+/// Bytes the files of a gistit take
+#[must_use]
+pub fn size_of(gistit: &Gistit) -> u64 {
+    gistit
+        .inner
+        .iter()
+        .map(|inner| inner.data.len() as u64)
+        .sum()
+}
+
+impl Reseed {
+    #[must_use]
+    pub fn new(cap: Option<u64>) -> Self {
+        Self {
+            cap,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub const fn enabled(&self) -> bool {
+        self.cap.is_some()
+    }
+
+    #[must_use]
+    pub fn contains(&self, key: &Key) -> bool {
+        self.entries.iter().any(|(known, _)| known == key)
+    }
+
+    /// Starts tracking a reseeded gistit. Returns the keys evicted to make room for it, which
+    /// should no longer be provided, or `None` if it can't be reseeded at all
+    pub fn admit(&mut self, key: Key, size: u64) -> Option<Vec<Key>> {
+        let cap = self.cap?;
+        if size > cap || self.contains(&key) {
+            return None;
+        }
+
+        let mut evicted = Vec::new();
+        while self.used + size > cap {
+            let (key, size) = self.entries.pop_front()?;
+            self.used -= size;
+            evicted.push(key);
+        }
+
+        self.used += size;
+        self.entries.push_back((key, size));
+        Some(evicted)
+    }
+
+    /// Marks a reseeded gistit as just served, so it's evicted last
+    pub fn touch(&mut self, key: &Key) {
+        if let Some(index) = self.entries.iter().position(|(known, _)| known == key) {
+            let entry = self.entries.remove(index).expect("index to be valid");
+            self.entries.push_back(entry);
+        }
+    }
+
+    /// Stops tracking a gistit, e.g. once we provide it on purpose. Returns whether it was
+    /// reseeded
+    pub fn remove(&mut self, key: &Key) -> bool {
+        if let Some(index) = self.entries.iter().position(|(known, _)| known == key) {
+            let (_, size) = self.entries.remove(index).expect("index to be valid");
+            self.used -= size;
+            true
+        } else {
+            false
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Bytes taken by reseeded gistits
+    #[must_use]
+    pub const fn used(&self) -> u64 {
+        self.used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reseed_evicts_least_recently_used() {
+        let key = |n: u8| Key::new(&[n]);
+        let mut reseed = Reseed::new(Some(100));
+
+        assert_eq!(reseed.admit(key(1), 40), Some(vec![]));
+        assert_eq!(reseed.admit(key(2), 40), Some(vec![]));
+        // Too large, or already there
+        assert_eq!(reseed.admit(key(3), 101), None);
+        assert_eq!(reseed.admit(key(1), 40), None);
+
+        reseed.touch(&key(1));
+        assert_eq!(reseed.admit(key(3), 40), Some(vec![key(2)]));
+        assert_eq!(reseed.used(), 80);
+
+        assert!(reseed.remove(&key(1)));
+        assert!(!reseed.remove(&key(1)));
+        assert_eq!((reseed.len(), reseed.used()), (1, 40));
+
+        assert_eq!(Reseed::new(None).admit(key(1), 1), None);
+    }
+}
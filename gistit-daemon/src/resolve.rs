@@ -0,0 +1,71 @@
+//! Dialing names
+//!
+//! `--dial` and `gistit node --dial` take names published in DNS too, like `node@example.com`,
+//! see [`gistit_project::dns`]. A resolver may take as long as it likes to answer, so names are
+//! looked up on a task of their own, given up after [`LOOKUP_TIMEOUT`], and the event loop dials
+//! their addresses once they are in.
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio::sync::mpsc;
+
+use gistit_project::dns::{self, Name};
+
+/// How long a name lookup may take
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Addresses a name stands for
+#[derive(Debug)]
+pub struct Resolved {
+    pub name: Name,
+    pub addresses: Vec<String>,
+    /// Whether addresses with a peer id are kept as persistent peers, as `--dial` does
+    pub persistent: bool,
+}
+
+#[derive(Debug)]
+pub struct Resolver {
+    done: mpsc::UnboundedSender<Resolved>,
+    resolved: mpsc::UnboundedReceiver<Resolved>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        let (done, resolved) = mpsc::unbounded_channel();
+        Self { done, resolved }
+    }
+}
+
+impl Resolver {
+    /// Looks `name` up, its addresses come out of [`Self::next`]. Failures are only logged
+    pub fn lookup(&self, name: Name, persistent: bool) {
+        let done = self.done.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(LOOKUP_TIMEOUT, dns::lookup(&name)).await {
+                Ok(Ok(record)) => {
+                    if record.addrs.is_empty() {
+                        warn!("No addresses published under {}", name);
+                    } else {
+                        info!("{} resolved to {:?}", name, record.addrs);
+                    }
+                    let _ = done.send(Resolved {
+                        name,
+                        addresses: record.addrs,
+                        persistent,
+                    });
+                }
+                Ok(Err(err)) => warn!("Failed to look {} up: {}", name, err),
+                Err(_) => warn!("Looking {} up timed out", name),
+            }
+        });
+    }
+
+    /// Waits for a name to be resolved
+    pub async fn next(&mut self) -> Resolved {
+        // We hold a sender, it never ends
+        self.resolved
+            .recv()
+            .await
+            .expect("sender not to be dropped")
+    }
+}
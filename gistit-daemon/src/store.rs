@@ -0,0 +1,235 @@
+//! Storage for the gistits we are providing
+//!
+//! File contents are kept once per inner hash, so hosting many gistits sharing the same files
+//! doesn't duplicate their data in memory.
+//!
+//! Private gistits are only fetched by their full hash, and the peers a provider lookup goes
+//! through learn the key looked up. Their provider records go by a hash of the key instead, see
+//! [`provider_key`], and peers asking for them still send the key itself.
+use std::collections::HashMap;
+
+use libp2p::kad::record::Key;
+use sha2::{Digest, Sha256};
+
+use gistit_proto::payload::inner_hash;
+use gistit_proto::Gistit;
+
+#[derive(Debug)]
+struct Blob {
+    data: String,
+    refs: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Store {
+    /// Gistits with their inner data stripped
+    gistits: HashMap<Key, Gistit>,
+
+    /// Inner file contents by hash
+    blobs: HashMap<String, Blob>,
+
+    /// Provider keys of the private gistits, to the key they are stored under
+    private: HashMap<Key, Key>,
+}
+
+/// Key the provider records of a gistit stored under `key` are published under
+#[must_use]
+pub fn provider_key(key: &Key, private: bool) -> Key {
+    if private {
+        Key::new(&Sha256::digest(key.to_vec()).to_vec())
+    } else {
+        key.clone()
+    }
+}
+
+impl Store {
+    pub fn insert(&mut self, key: Key, mut gistit: Gistit) {
+        self.remove(&key);
+        if gistit.private {
+            self.private.insert(provider_key(&key, true), key.clone());
+        }
+
+        for inner in &mut gistit.inner {
+            // Files sent before per-file hashing get one computed here
+            if inner.hash.is_empty() {
+                inner.hash = inner_hash(&inner.data);
+            }
+
+            let data = std::mem::take(&mut inner.data);
+            self.blobs
+                .entry(inner.hash.clone())
+                .or_insert(Blob { data, refs: 0 })
+                .refs += 1;
+        }
+
+        self.gistits.insert(key, gistit);
+    }
+
+    /// Reassembles the stored gistit with its files contents
+    #[must_use]
+    pub fn get(&self, key: &Key) -> Option<Gistit> {
+        let mut gistit = self.gistits.get(key)?.clone();
+        for inner in &mut gistit.inner {
+            inner.data = self.blobs.get(&inner.hash)?.data.clone();
+        }
+
+        Some(gistit)
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Gistit> {
+        let gistit = self.gistits.remove(key)?;
+        if gistit.private {
+            self.private.remove(&provider_key(key, true));
+        }
+        for inner in &gistit.inner {
+            if let Some(blob) = self.blobs.get_mut(&inner.hash) {
+                blob.refs -= 1;
+                if blob.refs == 0 {
+                    self.blobs.remove(&inner.hash);
+                }
+            }
+        }
+
+        Some(gistit)
+    }
+
+    /// Bytes the files of a stored gistit take, see [`crate::reseed::size_of`]
+    #[must_use]
+    pub fn size(&self, key: &Key) -> Option<u64> {
+        self.gistits
+            .get(key)?
+            .inner
+            .iter()
+            .try_fold(0, |size, inner| {
+                Some(size + self.blobs.get(&inner.hash)?.data.len() as u64)
+            })
+    }
+
+    /// Key the provider records of a stored gistit are published under
+    #[must_use]
+    pub fn provider_key(&self, key: &Key) -> Key {
+        let private = self.gistits.get(key).map_or(false, |gistit| gistit.private);
+        provider_key(key, private)
+    }
+
+    /// Key a gistit is stored under, from the key of its provider records
+    #[must_use]
+    pub fn key_of(&self, provider_key: &Key) -> Key {
+        self.private
+            .get(provider_key)
+            .map_or_else(|| provider_key.clone(), Clone::clone)
+    }
+
+    #[must_use]
+    pub fn contains(&self, key: &Key) -> bool {
+        self.gistits.contains_key(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.gistits.keys()
+    }
+
+    /// Hashes of the stored gistits starting with `prefix`, private ones only go by their full
+    /// hash
+    pub fn hashes_starting_with<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        self.gistits
+            .values()
+            .filter(|gistit| !gistit.private)
+            .map(|gistit| gistit.hash.as_str())
+            .filter(move |hash| hash.starts_with(prefix))
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.gistits.len()
+    }
+
+    /// How many distinct files we are holding in memory
+    #[must_use]
+    pub fn blob_count(&self) -> usize {
+        self.blobs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gistit_proto::payload::gistit::Encryption;
+
+    fn gistit(author: &str, data: &str) -> Gistit {
+        Gistit::new(
+            gistit_proto::payload::hash(author, None, data),
+            author.to_owned(),
+            None,
+            String::new(),
+            vec![Gistit::new_inner(
+                "main.rs".to_owned(),
+                "rust".to_owned(),
+                data.len() as u32,
+                data.to_owned(),
+            )],
+        )
+    }
+
+    #[test]
+    fn store_deduplicates_inners() {
+        let mut store = Store::default();
+        let first = gistit("foo", "fn main() {}");
+        let second = gistit("bar", "fn main() {}");
+
+        store.insert(Key::new(&first.hash), first.clone());
+        store.insert(Key::new(&second.hash), second.clone());
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.blob_count(), 1);
+        assert_eq!(store.get(&Key::new(&first.hash)), Some(first.clone()));
+        assert_eq!(store.get(&Key::new(&second.hash)), Some(second.clone()));
+        assert_eq!(store.size(&Key::new(&first.hash)), Some(12));
+        assert_eq!(
+            store
+                .hashes_starting_with(&first.hash[..8])
+                .collect::<Vec<_>>(),
+            vec![first.hash.as_str()]
+        );
+        assert_eq!(store.hashes_starting_with("").count(), 2);
+
+        // Only fetched by the full hash
+        let mut private = gistit("baz", "fn main() {}");
+        private.private = true;
+        let key = Key::new(&private.hash);
+        store.insert(key.clone(), private.clone());
+        assert_eq!(store.hashes_starting_with("").count(), 2);
+        assert_eq!(store.get(&key), Some(private.clone()));
+
+        // Published under a key that doesn't give the hash away
+        let published = store.provider_key(&key);
+        assert_ne!(published, key);
+        assert_eq!(published, provider_key(&key, true));
+        assert_eq!(store.key_of(&published), key);
+        let public = Key::new(&first.hash);
+        assert_eq!(store.provider_key(&public), public);
+        assert_eq!(store.key_of(&public), public);
+
+        store.remove(&key);
+        assert_eq!(store.key_of(&published), published);
+
+        store.remove(&Key::new(&first.hash));
+        assert_eq!(store.blob_count(), 1);
+        assert_eq!(store.get(&Key::new(&first.hash)), None);
+    }
+
+    #[test]
+    fn store_keeps_encrypted_gistits_opaque() {
+        let mut store = Store::default();
+        let mut sealed = gistit("foo", "c2VhbGVkIGZpbGVzLCBub3QgdGV4dA==");
+        sealed.inner[0].is_binary = true;
+        sealed.encryption = Some(Encryption {
+            scheme: "argon2id-chacha20poly1305".to_owned(),
+            nonce: "AAAAAAAAAAAAAAAA".to_owned(),
+            salt: "AAAAAAAAAAAAAAAAAAAAAA==".to_owned(),
+        });
+
+        store.insert(Key::new(&sealed.hash), sealed.clone());
+        assert_eq!(store.get(&Key::new(&sealed.hash)), Some(sealed));
+    }
+}
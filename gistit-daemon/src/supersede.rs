@@ -0,0 +1,218 @@
+//! Superseded gistits
+//!
+//! `gistit amend` sends a new version of a gistit that points back to the one it replaces with
+//! `previous_hash`. When we provide both, the old one stops being provided after a grace period,
+//! so fetches under way can finish and whoever has the old hash can still get it for a while.
+//! Without this every version ever sent would stay around for as long as we run. With
+//! `--keep-history` they all do.
+//!
+//! The schedule is kept in the data directory, so restarting doesn't start grace periods over
+//! or forget them.
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2p::kad::record::Key;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+pub const SUPERSEDED_FILE: &str = "superseded.json";
+
+/// How long superseded gistits are still provided
+pub const DEFAULT_SUPERSEDE_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// How often we look for superseded gistits past their grace period
+pub const RETIRE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The on disk format
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SupersededFile {
+    /// Hashes and the unix timestamp in seconds they stop being provided at
+    #[serde(default)]
+    due: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Superseded {
+    /// `None` with `--keep-history`
+    grace: Option<Duration>,
+
+    /// Superseded keys and when they stop being provided
+    due: HashMap<Key, SystemTime>,
+
+    path: PathBuf,
+}
+
+fn read(path: &Path) -> Result<SupersededFile> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+impl Superseded {
+    /// The schedule kept at `path`. One that can't be read is started over, it only delays
+    /// retiring old versions. Nothing is kept if we keep history
+    #[must_use]
+    pub fn load(grace: Option<Duration>, path: &Path) -> Self {
+        let mut superseded = Self {
+            grace,
+            path: path.to_path_buf(),
+            ..Self::default()
+        };
+        if grace.is_none() || fs::metadata(path).is_err() {
+            return superseded;
+        }
+
+        match read(path) {
+            Ok(file) => {
+                superseded.due = file
+                    .due
+                    .into_iter()
+                    .map(|(hash, at)| {
+                        (
+                            Key::from(hash.into_bytes()),
+                            UNIX_EPOCH + Duration::from_secs(at),
+                        )
+                    })
+                    .collect();
+            }
+            Err(err) => warn!("Starting the superseded gistits over: {}", err),
+        }
+        superseded
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = SupersededFile {
+            due: self
+                .due
+                .iter()
+                .filter_map(|(key, at)| {
+                    let hash = String::from_utf8(key.to_vec()).ok()?;
+                    Some((hash, to_secs(*at)))
+                })
+                .collect(),
+        };
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        gistit_project::file::replace(&self.path, &serde_json::to_vec(&file)?)?;
+        Ok(())
+    }
+
+    /// Saves the schedule, warning instead of failing
+    fn persist(&self) {
+        if let Err(err) = self.save() {
+            warn!("Failed to save the superseded gistits: {}", err);
+        }
+    }
+
+    #[must_use]
+    pub const fn enabled(&self) -> bool {
+        self.grace.is_some()
+    }
+
+    /// Schedules `previous` to stop being provided once the grace period is over, unless we keep
+    /// history. Superseding it again doesn't push it back. Returns whether it's scheduled
+    pub fn supersede(&mut self, previous: Key, now: SystemTime) -> bool {
+        match self.grace {
+            Some(grace) => {
+                if let Entry::Vacant(entry) = self.due.entry(previous) {
+                    entry.insert(now + grace);
+                    self.persist();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Keeps providing `key`, it was sent again on purpose
+    pub fn cancel(&mut self, key: &Key) {
+        if self.due.remove(key).is_some() {
+            self.persist();
+        }
+    }
+
+    /// Keys whose grace period is over, they are no longer tracked
+    pub fn due(&mut self, now: SystemTime) -> Vec<Key> {
+        let due: Vec<Key> = self
+            .due
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &due {
+            self.due.remove(key);
+        }
+        if !due.is_empty() {
+            self.persist();
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn superseded_retire_after_grace() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join(SUPERSEDED_FILE);
+        let now = SystemTime::now();
+        let grace = Duration::from_secs(10);
+        let mut superseded = Superseded::load(Some(grace), &file);
+        let (old, older) = (Key::new(&"old"), Key::new(&"older"));
+
+        assert!(superseded.supersede(old.clone(), now));
+        assert!(superseded.supersede(older.clone(), now + grace / 2));
+        // Superseded again later, still due when first scheduled
+        assert!(superseded.supersede(old.clone(), now + grace));
+        assert!(superseded.due(now).is_empty());
+        assert_eq!(superseded.due(now + grace), vec![old.clone()]);
+        assert!(superseded.due(now + grace).is_empty());
+
+        superseded.cancel(&older);
+        assert!(superseded.due(now + grace * 2).is_empty());
+
+        let mut keep_history = Superseded::load(None, &file);
+        assert!(!keep_history.enabled());
+        assert!(!keep_history.supersede(old, now));
+        assert!(keep_history.due(now + grace * 2).is_empty());
+    }
+
+    #[test]
+    fn superseded_survive_restart() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join(SUPERSEDED_FILE);
+        let now = SystemTime::now();
+        let grace = Duration::from_secs(10);
+        let old = Key::new(&"old");
+
+        let mut superseded = Superseded::load(Some(grace), &file);
+        assert!(superseded.supersede(old.clone(), now));
+        drop(superseded);
+
+        let mut restarted = Superseded::load(Some(grace), &file);
+        // Still due when first scheduled, to the second
+        assert!(restarted.due(now).is_empty());
+        assert_eq!(restarted.due(now + grace), vec![old]);
+        assert!(Superseded::load(Some(grace), &file)
+            .due(now + grace * 2)
+            .is_empty());
+
+        fs::write(&file, "{").unwrap();
+        assert!(Superseded::load(Some(grace), &file)
+            .due(now + grace * 2)
+            .is_empty());
+    }
+}
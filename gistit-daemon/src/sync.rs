@@ -0,0 +1,455 @@
+//! History sync
+//!
+//! Opt-in with `--sync`. Nodes started with the same secret in `GISTIT_SYNC_SECRET`, say on a
+//! laptop and a desktop, trade the entries of the gistit history so `gistit list` shows what was
+//! sent or fetched on any of them. Every node provides a record under the id of the group the
+//! secret stands for, see [`vault::sync_key`], and looks up the other providers every
+//! [`SYNC_INTERVAL`]. Two nodes sync by sending each other their history sealed with the group
+//! key, the asking node first. Nodes outside the group can't open it, and get nothing back.
+//!
+//! Entries are merged by hash, and a gistit sent from any machine counts as sent, so histories
+//! end up with the same entries whatever order nodes sync in. `gistit amend` takes the gistits
+//! sent from any of our machines for that reason. Nothing is ever removed: an entry cleared on
+//! one machine comes back from the others. Histories sealed by the vault can't be read by the
+//! node and aren't synced.
+//!
+//! Reading, sealing and writing the history happens on blocking threads, the event loop is
+//! handed back what to send, see [`Synced`]. The history file is locked around merges, as
+//! `gistit` does when recording, so neither loses the other's entries.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::core::ProtocolName;
+use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::kad::record::Key;
+use libp2p::kad::QueryId;
+use libp2p::request_response::{RequestResponseCodec, ResponseChannel};
+use libp2p::PeerId;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+
+use gistit_project::file;
+use gistit_project::vault::{self, Vault};
+
+use crate::{Error, Result};
+
+pub const SYNC_PROTOCOL: &[u8] = b"/gistit/sync/1";
+
+/// Gistit history, in the data directory, see `gistit-cli`
+pub const HISTORY_FILE: &str = "history.json";
+
+/// How often we look for the other nodes of the group and sync with them
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Newest entries sent in one sync, older ones are already everywhere
+const MAX_SYNCED: usize = 2000;
+
+/// Usually fits [`MAX_SYNCED`] entries, they don't carry the gistit contents. Fewer are sent
+/// otherwise
+const MAX_MESSAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// What sealing adds to the entries, with room to spare
+const SEAL_OVERHEAD: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Origin {
+    Sent,
+    Fetched,
+}
+
+/// A history entry, only what merging needs. The other fields are kept as they are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    hash: String,
+    origin: Origin,
+    #[serde(flatten)]
+    rest: Map<String, Value>,
+}
+
+/// Sync work done on a blocking thread, for the event loop to finish
+pub enum Synced {
+    /// Our history in answer to the one `peer` sent, empty if it isn't from our group
+    Answer {
+        peer: PeerId,
+        channel: ResponseChannel<Vec<u8>>,
+        response: Vec<u8>,
+    },
+    /// Our history, to send `peer`
+    Ask { peer: PeerId, sealed: Vec<u8> },
+}
+
+pub struct HistorySync {
+    history: Arc<History>,
+    /// Record the nodes of the group provide
+    group: Key,
+    /// Our queries for the group record
+    queries: HashSet<QueryId>,
+    done: mpsc::UnboundedSender<Synced>,
+    synced: mpsc::UnboundedReceiver<Synced>,
+}
+
+impl std::fmt::Debug for HistorySync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HistorySync")
+            .field("file", &self.history.file)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HistorySync {
+    /// # Errors
+    ///
+    /// Fails if `secret` isn't a sync secret, see [`vault::new_sync_secret`]
+    pub fn new(secret: &str, file: PathBuf) -> Result<Self> {
+        let (key, group) = vault::sync_key(secret)?;
+        let (done, synced) = mpsc::unbounded_channel();
+        Ok(Self {
+            history: Arc::new(History { key, file }),
+            group: Key::new(&format!("sync/{}", group)),
+            queries: HashSet::new(),
+            done,
+            synced,
+        })
+    }
+
+    #[must_use]
+    pub const fn group(&self) -> &Key {
+        &self.group
+    }
+
+    pub fn started(&mut self, query_id: QueryId) {
+        self.queries.insert(query_id);
+    }
+
+    /// Whether the query was ours, it's forgotten
+    pub fn finished(&mut self, query_id: &QueryId) -> bool {
+        self.queries.remove(query_id)
+    }
+
+    /// Merges the history `peer` sent and answers with ours, only if it's from our group
+    pub fn answer(&self, peer: PeerId, channel: ResponseChannel<Vec<u8>>, request: Vec<u8>) {
+        let history = Arc::clone(&self.history);
+        let done = self.done.clone();
+        tokio::task::spawn_blocking(move || {
+            let response = if history.merge_from(&peer, &request) {
+                history.sealed().unwrap_or_else(|err| {
+                    error!("Failed to read the history to sync: {}", err);
+                    Vec::new()
+                })
+            } else {
+                Vec::new()
+            };
+            let _ = done.send(Synced::Answer {
+                peer,
+                channel,
+                response,
+            });
+        });
+    }
+
+    /// Seals our history to send `peer`
+    pub fn ask(&self, peer: PeerId) {
+        let history = Arc::clone(&self.history);
+        let done = self.done.clone();
+        tokio::task::spawn_blocking(move || match history.sealed() {
+            Ok(sealed) => {
+                let _ = done.send(Synced::Ask { peer, sealed });
+            }
+            Err(err) => error!("Failed to read the history to sync: {}", err),
+        });
+    }
+
+    /// Merges the history `peer` answered with
+    pub fn merge_answer(&self, peer: PeerId, response: Vec<u8>) {
+        let history = Arc::clone(&self.history);
+        tokio::task::spawn_blocking(move || history.merge_from(&peer, &response));
+    }
+
+    /// Waits for sync work to be done, forever without `--sync`
+    pub async fn next(sync: Option<&mut Self>) -> Synced {
+        match sync {
+            // We hold a sender, it never ends
+            Some(sync) => sync.synced.recv().await.expect("sender not to be dropped"),
+            None => future::pending().await,
+        }
+    }
+}
+
+/// The history file and the key of our group
+struct History {
+    key: Vault,
+    file: PathBuf,
+}
+
+impl History {
+    fn load(&self) -> Result<Vec<Entry>> {
+        let bytes = match fs::read(&self.file) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        if vault::is_sealed(&bytes) {
+            return Err(Error::Sync("history is sealed by the vault"));
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Our newest entries that fit in a message, sealed with the group key
+    fn sealed(&self) -> Result<Vec<u8>> {
+        let entries = self.load()?;
+        let newest = newest_within(&entries, MAX_MESSAGE_SIZE - SEAL_OVERHEAD)?;
+        Ok(self.key.seal(&serde_json::to_vec(newest)?)?)
+    }
+
+    /// Merges entries another node of the group sealed, returns how many changed
+    fn merge(&self, sealed: &[u8]) -> Result<usize> {
+        let remote: Vec<Entry> = serde_json::from_slice(
+            &self
+                .key
+                .open(sealed)
+                .map_err(|_| Error::Sync("history isn't from a node of our group"))?,
+        )?;
+
+        // `gistit` records under the same lock, so its entries aren't lost
+        let _lock = file::Lock::acquire(&self.file)?;
+        let mut entries = self.load()?;
+        let changed = merge(&mut entries, remote);
+        if changed > 0 {
+            file::replace(&self.file, &serde_json::to_vec_pretty(&entries)?)?;
+        }
+        Ok(changed)
+    }
+
+    /// Merges the history `peer` sent, `false` if it isn't from a node of our group
+    fn merge_from(&self, peer: &PeerId, sealed: &[u8]) -> bool {
+        match self.merge(sealed) {
+            Ok(0) => debug!("History in sync with {:?}", peer),
+            Ok(changed) => info!("Synced {} history entries from {:?}", changed, peer),
+            Err(Error::Sync(reason)) => {
+                debug!("History sync from {:?} refused: {}", peer, reason);
+                return false;
+            }
+            Err(err) => error!("Failed to merge the history from {:?}: {}", peer, err),
+        }
+        true
+    }
+}
+
+/// The newest entries, at most [`MAX_SYNCED`] and `budget` bytes encoded
+fn newest_within(entries: &[Entry], budget: usize) -> Result<&[Entry]> {
+    // The brackets of the array
+    let mut size = 2;
+    let mut start = entries.len();
+    for entry in entries.iter().rev().take(MAX_SYNCED) {
+        // And a comma
+        size += serde_json::to_vec(entry)?.len() + 1;
+        if size > budget {
+            break;
+        }
+        start -= 1;
+    }
+    Ok(&entries[start..])
+}
+
+/// Adds the `remote` entries we don't know after ours, and marks the ones sent elsewhere as
+/// sent. Returns how many entries changed
+fn merge(local: &mut Vec<Entry>, remote: Vec<Entry>) -> usize {
+    let mut known: HashMap<String, usize> = local
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.hash.clone(), index))
+        .collect();
+
+    let mut changed = 0;
+    for entry in remote {
+        match known.get(&entry.hash) {
+            Some(&index) => {
+                if entry.origin == Origin::Sent && local[index].origin != Origin::Sent {
+                    local[index].origin = Origin::Sent;
+                    changed += 1;
+                }
+            }
+            None => {
+                known.insert(entry.hash.clone(), local.len());
+                local.push(entry);
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncProtocol;
+
+impl ProtocolName for SyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        SYNC_PROTOCOL
+    }
+}
+
+/// Both sides send their sealed history, the asking node first. An empty one means the other
+/// side couldn't open ours
+#[derive(Clone, Default)]
+pub struct SyncCodec;
+
+#[async_trait]
+impl RequestResponseCodec for SyncCodec {
+    type Protocol = SyncProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T: Send + Unpin + AsyncRead>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request> {
+        read_length_prefixed(io, MAX_MESSAGE_SIZE).await
+    }
+
+    async fn read_response<T: Send + Unpin + AsyncRead>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response> {
+        read_length_prefixed(io, MAX_MESSAGE_SIZE).await
+    }
+
+    async fn write_request<T: Send + Unpin + AsyncWrite>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()> {
+        write_length_prefixed(io, request).await?;
+        io.close().await
+    }
+
+    async fn write_response<T: Send + Unpin + AsyncWrite>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()> {
+        write_length_prefixed(io, response).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    fn entries(json: &str) -> Vec<Entry> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn sync_merge_is_order_independent() {
+        let laptop = entries(
+            r#"[
+                {"hash": "a", "origin": "sent", "author": "fabricio", "files": ["main.rs"]},
+                {"hash": "b", "origin": "fetched", "author": "matthew", "files": []}
+            ]"#,
+        );
+        let desktop = entries(
+            r#"[
+                {"hash": "b", "origin": "sent", "author": "matthew", "files": []},
+                {"hash": "c", "origin": "fetched", "author": "fabricio", "files": ["lib.rs"]}
+            ]"#,
+        );
+
+        let mut on_laptop = laptop.clone();
+        assert_eq!(merge(&mut on_laptop, desktop.clone()), 2);
+        let mut on_desktop = desktop;
+        assert_eq!(merge(&mut on_desktop, laptop), 1);
+
+        let hashes = |entries: &[Entry]| {
+            let mut hashes: Vec<(String, Origin)> = entries
+                .iter()
+                .map(|entry| (entry.hash.clone(), entry.origin))
+                .collect();
+            hashes.sort_by(|a, b| a.0.cmp(&b.0));
+            hashes
+        };
+        assert_eq!(hashes(&on_laptop), hashes(&on_desktop));
+        assert!(on_laptop
+            .iter()
+            .all(|entry| entry.origin == Origin::Sent || entry.hash == "c"));
+        assert_eq!(on_laptop[2].rest["files"], serde_json::json!(["lib.rs"]));
+
+        // Syncing again changes nothing
+        assert_eq!(merge(&mut on_laptop, on_desktop), 0);
+    }
+
+    #[test]
+    fn sync_history_sealed_for_the_group() {
+        let dir = TempDir::new().unwrap();
+        let (ours, theirs) = (dir.path().join("ours.json"), dir.path().join("theirs.json"));
+        fs::write(
+            &ours,
+            r#"[{"hash": "a", "origin": "sent", "author": "fabricio"}]"#,
+        )
+        .unwrap();
+
+        let secret = vault::new_sync_secret();
+        let laptop = HistorySync::new(&secret, ours).unwrap();
+        let desktop = HistorySync::new(&secret, theirs).unwrap();
+        assert_eq!(laptop.group(), desktop.group());
+
+        assert_eq!(
+            desktop
+                .history
+                .merge(&laptop.history.sealed().unwrap())
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            desktop.history.load().unwrap()[0].rest["author"],
+            "fabricio"
+        );
+
+        let stranger =
+            HistorySync::new(&vault::new_sync_secret(), dir.path().join("stranger.json")).unwrap();
+        assert!(stranger
+            .history
+            .merge(&laptop.history.sealed().unwrap())
+            .is_err());
+        assert_ne!(stranger.group(), laptop.group());
+        assert!(HistorySync::new("hunter2", dir.path().join("guessed.json")).is_err());
+    }
+
+    #[test]
+    fn sync_sends_what_fits() {
+        let history: Vec<Entry> = (0..MAX_SYNCED + 10)
+            .map(|n| Entry {
+                hash: format!("{:064}", n),
+                origin: Origin::Fetched,
+                rest: Map::new(),
+            })
+            .collect();
+
+        let newest = newest_within(&history, MAX_MESSAGE_SIZE).unwrap();
+        assert_eq!(newest.len(), MAX_SYNCED);
+        assert_eq!(newest.last(), history.last());
+
+        // Every entry is 95 bytes encoded, with its comma
+        let newest = newest_within(&history, 2 + 95 * 10).unwrap();
+        assert_eq!(newest.len(), 10);
+        assert!(serde_json::to_vec(newest).unwrap().len() <= 2 + 95 * 10);
+        assert_eq!(newest.last(), history.last());
+        assert!(newest_within(&history, 1).unwrap().is_empty());
+    }
+}
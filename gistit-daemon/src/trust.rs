@@ -0,0 +1,149 @@
+//! Trusted peers
+//!
+//! Peers in the denylist are never served. Once the allowlist has entries only those peers are
+//! served, otherwise everyone not denied is. Both lists are persisted as JSON in the config
+//! directory so they survive restarts.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libp2p::core::PeerId;
+use serde::{Deserialize, Serialize};
+
+use gistit_project::file;
+
+use crate::Result;
+
+pub const TRUST_FILE: &str = "trusted-peers.json";
+
+/// The on disk format, peer ids as base58 strings
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TrustFile {
+    #[serde(default)]
+    allow: BTreeSet<String>,
+    #[serde(default)]
+    deny: BTreeSet<String>,
+}
+
+#[derive(Debug)]
+pub struct TrustList {
+    allow: BTreeSet<PeerId>,
+    deny: BTreeSet<PeerId>,
+    path: PathBuf,
+}
+
+fn parse_peers(peers: BTreeSet<String>) -> Result<BTreeSet<PeerId>> {
+    peers
+        .iter()
+        .map(|peer| {
+            peer.parse()
+                .map_err(|_| crate::Error::Parse("invalid trusted peer id"))
+        })
+        .collect()
+}
+
+impl TrustList {
+    /// Loads the lists from `path`, both start empty if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = if fs::metadata(path).is_ok() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            TrustFile::default()
+        };
+
+        Ok(Self {
+            allow: parse_peers(file.allow)?,
+            deny: parse_peers(file.deny)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Reads the lists from the file again, e.g. after it was edited by hand. Nothing changes if
+    /// it can't be read
+    pub fn reload(&mut self) -> Result<()> {
+        *self = Self::load(&self.path)?;
+        Ok(())
+    }
+
+    /// Replaces the file as a whole, a crash halfway through leaves the previous lists
+    pub fn save(&self) -> Result<()> {
+        let file = TrustFile {
+            allow: self.allowed(),
+            deny: self.denied(),
+        };
+        file::replace(&self.path, &serde_json::to_vec_pretty(&file)?)?;
+        Ok(())
+    }
+
+    /// Whether we should serve requests from this peer
+    #[must_use]
+    pub fn is_allowed(&self, peer_id: &PeerId) -> bool {
+        !self.deny.contains(peer_id) && (self.allow.is_empty() || self.allow.contains(peer_id))
+    }
+
+    /// Whether this peer is in the allowlist, rather than allowed because it is empty
+    #[must_use]
+    pub fn is_listed(&self, peer_id: &PeerId) -> bool {
+        self.allow.contains(peer_id)
+    }
+
+    pub fn listed(&self) -> impl Iterator<Item = &PeerId> {
+        self.allow.iter()
+    }
+
+    pub fn allow(&mut self, peer_id: PeerId) {
+        self.deny.remove(&peer_id);
+        self.allow.insert(peer_id);
+    }
+
+    pub fn deny(&mut self, peer_id: PeerId) {
+        self.allow.remove(&peer_id);
+        self.deny.insert(peer_id);
+    }
+
+    pub fn forget(&mut self, peer_id: &PeerId) {
+        self.allow.remove(peer_id);
+        self.deny.remove(peer_id);
+    }
+
+    #[must_use]
+    pub fn allowed(&self) -> BTreeSet<String> {
+        self.allow.iter().map(PeerId::to_base58).collect()
+    }
+
+    #[must_use]
+    pub fn denied(&self) -> BTreeSet<String> {
+        self.deny.iter().map(PeerId::to_base58).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trust_list_allow_and_deny() {
+        let mut trust = TrustList::load(Path::new("/nonexistent/trusted-peers.json")).unwrap();
+        let friend = PeerId::random();
+        let stranger = PeerId::random();
+
+        // Empty allowlist serves everyone
+        assert!(trust.is_allowed(&stranger));
+
+        trust.deny(stranger);
+        assert!(!trust.is_allowed(&stranger));
+        assert!(trust.is_allowed(&friend));
+
+        trust.allow(friend);
+        assert!(trust.is_allowed(&friend));
+        assert!(trust.is_listed(&friend));
+        assert!(!trust.is_allowed(&PeerId::random()));
+
+        trust.allow(stranger);
+        assert!(trust.is_allowed(&stranger));
+
+        trust.forget(&stranger);
+        trust.forget(&friend);
+        assert!(trust.is_allowed(&stranger));
+    }
+}
@@ -0,0 +1,232 @@
+//! Provided directories
+//!
+//! With `--provide-dir`, every file in a directory is provided as a gistit of its own, as long as
+//! `gistit send` would accept it: known extension, UTF-8 and within the size limit. The directory
+//! is scanned again every few seconds, a changed file is provided under its new hash and stops
+//! being provided under the old one, a removed file stops being provided. Gistits the client sent
+//! too stay provided, see [`ProvideDir::keep`].
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2p::kad::record::Key;
+use log::warn;
+
+use gistit_project::lang::EXTENSION_TO_LANG_MAPPING;
+use gistit_proto::validate::{Invalid, FILE_SIZE};
+use gistit_proto::{Gistit, GistitBuilder};
+
+use crate::node::gistit_key;
+use crate::Result;
+
+/// How often the directory is scanned for changes
+pub const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct Snapshot {
+    modified: SystemTime,
+    len: u64,
+
+    /// What the file is provided as, `None` if it can't be
+    key: Option<Key>,
+}
+
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub provide: Vec<Gistit>,
+    pub unprovide: Vec<Key>,
+}
+
+#[derive(Debug)]
+pub struct ProvideDir {
+    path: PathBuf,
+    author: String,
+    files: HashMap<PathBuf, Snapshot>,
+    /// Sent by the client too, they outlive their files
+    kept: HashSet<Key>,
+}
+
+impl ProvideDir {
+    #[must_use]
+    pub fn new(path: PathBuf, author: String) -> Self {
+        Self {
+            path,
+            author,
+            files: HashMap::new(),
+            kept: HashSet::new(),
+        }
+    }
+
+    /// Keeps `key` provided when a file with its contents changes or is removed, the client sent
+    /// it on purpose
+    pub fn keep(&mut self, key: Key) {
+        self.kept.insert(key);
+    }
+
+    /// Forgets about `key` once it's no longer provided
+    pub fn release(&mut self, key: &Key) {
+        self.kept.remove(key);
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Looks for files added, changed or removed since the last scan. Files that can't be
+    /// provided are skipped with a warning, once until they change
+    pub fn scan(&mut self) -> Result<Changes> {
+        let mut changes = Changes::default();
+        let mut seen = HashMap::new();
+
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() && !hidden => metadata,
+                _ => continue,
+            };
+            let modified = metadata.modified()?;
+            let len = metadata.len();
+
+            let snapshot = match self.files.remove(&path) {
+                Some(snapshot) if snapshot.modified == modified && snapshot.len == len => snapshot,
+                previous => {
+                    let gistit = match gistit_from_file(&path, &self.author, modified) {
+                        Ok(gistit) => Some(gistit),
+                        Err(reason) => {
+                            warn!("Not providing {:?}, {}", path, reason);
+                            None
+                        }
+                    };
+                    let key = gistit.as_ref().map(|gistit| gistit_key(&gistit.hash));
+                    if let Some(old) = previous.and_then(|previous| previous.key) {
+                        if Some(&old) != key.as_ref() {
+                            changes.unprovide.push(old);
+                        }
+                    }
+                    changes.provide.extend(gistit);
+                    Snapshot { modified, len, key }
+                }
+            };
+            seen.insert(path, snapshot);
+        }
+
+        // Whatever is left was removed
+        changes
+            .unprovide
+            .extend(self.files.drain().filter_map(|(_, snapshot)| snapshot.key));
+        self.files = seen;
+
+        // Another file may have the same contents, or the client sent it too
+        changes.unprovide.retain(|key| {
+            !self.kept.contains(key)
+                && !self
+                    .files
+                    .values()
+                    .any(|snapshot| snapshot.key.as_ref() == Some(key))
+        });
+        Ok(changes)
+    }
+}
+
+/// Builds the gistit `gistit send` would for this file, or why it can't
+fn gistit_from_file(
+    path: &Path,
+    author: &str,
+    modified: SystemTime,
+) -> std::result::Result<Gistit, String> {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let lang = EXTENSION_TO_LANG_MAPPING
+        .get(ext)
+        .ok_or("file extension not supported")?;
+
+    // Don't read what can't be sent anyway
+    let len = fs::metadata(path).map_err(|_| "failed to read it")?.len();
+    if len > *FILE_SIZE.end() as u64 {
+        return Err(Invalid::Size.to_string());
+    }
+    let data = fs::read(path).map_err(|_| "failed to read it")?;
+    let data = String::from_utf8(data).map_err(|_| "it isn't UTF-8 text")?;
+
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let timestamp = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis())
+        .to_string();
+
+    GistitBuilder::new(author)
+        .file(name, *lang, data)
+        .timestamp(timestamp)
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn provide_dir_follows_changes() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().to_path_buf();
+        fs::write(dir.join("main.rs"), "fn main() { println!(); }").unwrap();
+        fs::write(dir.join("copy.rs"), "fn main() { println!(); }").unwrap();
+        fs::write(dir.join("tiny.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("notes.unknown"), "not a snippet").unwrap();
+        fs::write(dir.join(".hidden.rs"), "fn hidden() {}").unwrap();
+        fs::write(dir.join("big.rs"), "a".repeat(FILE_SIZE.end() + 1)).unwrap();
+
+        let mut provided = ProvideDir::new(dir.clone(), "someone".to_owned());
+        let changes = provided.scan().unwrap();
+        assert_eq!(changes.provide.len(), 2);
+        assert!(changes.unprovide.is_empty());
+        let gistit = &changes.provide[0];
+        assert!(gistit.is_intact());
+        assert_eq!(gistit.inner[0].lang, "rust");
+        assert_eq!(gistit.author, "someone");
+        let old = gistit_key(&gistit.hash);
+
+        // Nothing changed
+        let changes = provided.scan().unwrap();
+        assert!(changes.provide.is_empty() && changes.unprovide.is_empty());
+
+        // The copy still provides the old contents
+        fs::write(dir.join("main.rs"), "fn main() { println!(\"changed\"); }").unwrap();
+        let changes = provided.scan().unwrap();
+        assert_eq!(changes.provide.len(), 1);
+        assert!(changes.unprovide.is_empty());
+
+        fs::remove_file(dir.join("copy.rs")).unwrap();
+        let changes = provided.scan().unwrap();
+        assert!(changes.provide.is_empty());
+        assert_eq!(changes.unprovide, vec![old]);
+    }
+
+    #[test]
+    fn provide_dir_keeps_sent_gistits() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().to_path_buf();
+        fs::write(dir.join("main.rs"), "fn main() { println!(); }").unwrap();
+
+        let mut provided = ProvideDir::new(dir.clone(), "someone".to_owned());
+        let key = gistit_key(&provided.scan().unwrap().provide[0].hash);
+        provided.keep(key.clone());
+
+        fs::remove_file(dir.join("main.rs")).unwrap();
+        assert!(provided.scan().unwrap().unprovide.is_empty());
+
+        // Provided from the file again once the sent one was taken down
+        provided.release(&key);
+        fs::write(dir.join("main.rs"), "fn main() { println!(); }").unwrap();
+        assert_eq!(provided.scan().unwrap().provide.len(), 1);
+        fs::remove_file(dir.join("main.rs")).unwrap();
+        assert_eq!(provided.scan().unwrap().unprovide, vec![key]);
+    }
+}
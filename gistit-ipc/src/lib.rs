@@ -21,26 +21,70 @@
     )
 )]
 //! This is a simple crate to handle the inter process comms for gistit-daemon and gistit-cli
+//!
+//! Unix datagrams between two processes of the same host arrive in order, but once the receiving
+//! end falls behind its queue fills up: linux makes the sender wait, other unixes fail the send
+//! with `ENOBUFS`. Each [`Bridge`] queues its outgoing instructions, sending one at a time in the
+//! order they were given and waiting up to [`SEND_TIMEOUT`] for room on the other end either
+//! way. How deep that queue got is reported by [`Bridge::queue_stats`].
+//!
+//! Windows has no unix datagrams, named pipes stand in for them there, see the `pipe` module.
 //! TODO: Missing TCP socket implementation
 
 use std::fs::{metadata, remove_file};
+#[cfg(unix)]
+use std::fs::{set_permissions, Permissions};
+use std::io;
 use std::marker::PhantomData;
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
-use tokio::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use notify::{RecursiveMode, Watcher};
+#[cfg(unix)]
+use tokio::net::UnixDatagram as Socket;
+use tokio::sync::{Mutex, Notify};
 
 use gistit_proto::bytes::BytesMut;
+use gistit_proto::ipc::instruction;
 use gistit_proto::prost::{self, Message};
+use gistit_proto::validate;
 use gistit_proto::Instruction;
 
+#[cfg(windows)]
+mod pipe;
+#[cfg(windows)]
+use pipe::Datagram as Socket;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 const NAMED_SOCKET_0: &str = "gistit-0";
 const NAMED_SOCKET_1: &str = "gistit-1";
 
-const READBUF_SIZE: usize = 60_000; // A bit bigger than 50kb because encoding
+/// Largest instruction sent or received, bigger ones would be truncated by the receiving end. The
+/// largest gistit fits with room for the rest of the instruction
+pub const READBUF_SIZE: usize = validate::ENCODED_SIZE + 4 * 1024;
 const CONNECT_TIMEOUT_SECS: u64 = 3;
 
+/// How long a send waits for the other end to make room before giving up
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait between attempts to send while the other end is full, doubled each time up to
+/// [`SEND_BACKOFF_MAX`]
+const SEND_BACKOFF_START: Duration = Duration::from_millis(1);
+const SEND_BACKOFF_MAX: Duration = Duration::from_millis(100);
+
+/// Wait before the second connection attempt, doubled after each failed one up to
+/// [`CONNECT_BACKOFF_MAX`]
+const CONNECT_BACKOFF_START: Duration = Duration::from_millis(5);
+const CONNECT_BACKOFF_MAX: Duration = Duration::from_millis(250);
+
+/// Set by [`allow_insecure_runtime_dir`]
+static INSECURE_RUNTIME_DIR: AtomicBool = AtomicBool::new(false);
+
 pub trait SockEnd {}
 
 #[derive(Debug)]
@@ -53,32 +97,157 @@ impl SockEnd for Client {}
 
 #[derive(Debug)]
 pub struct Bridge<T: SockEnd> {
-    pub sock_0: UnixDatagram,
-    pub sock_1: UnixDatagram,
+    pub sock_0: Socket,
+    pub sock_1: Socket,
     base: PathBuf,
+    queue: Queue,
     __marker_t: PhantomData<T>,
 }
 
+/// Outgoing instructions, sent one at a time and in order
+#[derive(Debug, Default)]
+struct Queue {
+    /// Held while sending, waiters get it first come first served
+    sending: Mutex<()>,
+    depth: AtomicUsize,
+    peak: AtomicUsize,
+    stalls: AtomicU64,
+}
+
+/// How the outgoing queue of a [`Bridge`] is doing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Instructions waiting to be sent or being sent
+    pub depth: usize,
+
+    /// Deepest the queue has been
+    pub peak: usize,
+
+    /// Sends that had to wait for the other end to make room
+    pub stalls: u64,
+}
+
+/// Counts a queued send until it's done, cancelled ones included
+struct Queued<'a>(&'a Queue);
+
+impl<'a> Queued<'a> {
+    fn new(queue: &'a Queue) -> Self {
+        let depth = queue.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        queue.peak.fetch_max(depth, Ordering::Relaxed);
+        Self(queue)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Queue {
+    fn stats(&self) -> QueueStats {
+        QueueStats {
+            depth: self.depth.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            stalls: self.stalls.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Sends `instruction` once the ones queued before it are sent
+    async fn send(&self, dgram: &Socket, instruction: &Instruction) -> Result<()> {
+        let mut buf = BytesMut::with_capacity(instruction.encoded_len());
+        instruction.encode(&mut buf)?;
+        if buf.len() > READBUF_SIZE {
+            return Err(Error::TooLarge(buf.len()));
+        }
+
+        let _queued = Queued::new(self);
+        let _sending = self.sending.lock().await;
+
+        let deadline = Instant::now() + SEND_TIMEOUT;
+        let mut backoff = SEND_BACKOFF_START;
+        let mut stalled = false;
+        loop {
+            let full = match dgram.try_send(&buf) {
+                Ok(_) => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => true,
+                Err(err) if out_of_buffers(&err) => false,
+                Err(err) => return Err(err.into()),
+            };
+
+            if !stalled {
+                stalled = true;
+                self.stalls.fetch_add(1, Ordering::Relaxed);
+                log::debug!("The other end is full, waiting to send {} bytes", buf.len());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Backpressure(SEND_TIMEOUT));
+            }
+
+            let wait = backoff.min(deadline - now);
+            if full {
+                // Either way we try again, readiness may be missed for datagrams
+                let _ = tokio::time::timeout(wait, dgram.writable()).await;
+            } else {
+                // Out of buffers rather than full, nothing to wait on
+                tokio::time::sleep(wait).await;
+            }
+            backoff = (backoff * 2).min(SEND_BACKOFF_MAX);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn out_of_buffers(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOBUFS)
+}
+
+#[cfg(windows)]
+const fn out_of_buffers(_: &io::Error) -> bool {
+    false
+}
+
+/// Receives one instruction, refusing ones that didn't fit and were truncated
+async fn __recv(dgram: &Socket) -> Result<Instruction> {
+    let mut buf = vec![0u8; READBUF_SIZE + 1];
+    let read = dgram.recv(&mut buf).await?;
+    if read > READBUF_SIZE {
+        return Err(Error::TooLarge(read));
+    }
+    buf.truncate(read);
+    let instruction = Instruction::decode(&*buf)?;
+    log::debug!("Received {} ({} bytes)", describe(&instruction), read);
+    Ok(instruction)
+}
+
+/// Name of the instruction kind, without its contents which may be whole gistits
+fn describe(instruction: &Instruction) -> &'static str {
+    instruction
+        .kind
+        .as_ref()
+        .map_or("empty instruction", instruction::Kind::name)
+}
+
 /// Recv from [`NAMED_SOCKET_0`] and send to [`NAMED_SOCKET_1`]
 /// The owner of `sock_0`
 ///
 /// # Errors
 ///
-/// Fails if can't spawn a named socket
+/// Fails if can't spawn a named socket or the runtime directory is insecure, see
+/// [`check_runtime_dir`]
 pub fn server(base: &Path) -> Result<Bridge<Server>> {
+    check_runtime_dir(base)?;
     let sockpath_0 = &base.join(NAMED_SOCKET_0);
 
-    if metadata(sockpath_0).is_ok() {
-        remove_file(sockpath_0)?;
-    }
-
     log::trace!("Bind sock_0 (server) at {:?}", sockpath_0);
-    let sock_0 = UnixDatagram::bind(sockpath_0)?;
+    let sock_0 = bind(sockpath_0)?;
 
     Ok(Bridge {
         sock_0,
-        sock_1: UnixDatagram::unbound()?,
+        sock_1: Socket::unbound()?,
         base: base.to_path_buf(),
+        queue: Queue::default(),
         __marker_t: PhantomData,
     })
 }
@@ -88,35 +257,177 @@ pub fn server(base: &Path) -> Result<Bridge<Server>> {
 ///
 /// # Errors
 ///
-/// Fails if can't spawn a named socket
+/// Fails if can't spawn a named socket or the runtime directory is insecure, see
+/// [`check_runtime_dir`]
 pub fn client(base: &Path) -> Result<Bridge<Client>> {
+    check_runtime_dir(base)?;
     let sockpath_1 = &base.join(NAMED_SOCKET_1);
 
-    if metadata(sockpath_1).is_ok() {
-        remove_file(sockpath_1)?;
-    }
-
     log::trace!("Bind sock_1 (client) at {:?}", sockpath_1);
-    let sock_1 = UnixDatagram::bind(sockpath_1)?;
+    let sock_1 = bind(sockpath_1)?;
 
     Ok(Bridge {
-        sock_0: UnixDatagram::unbound()?,
+        sock_0: Socket::unbound()?,
         sock_1,
         base: base.to_path_buf(),
+        queue: Queue::default(),
         __marker_t: PhantomData,
     })
 }
 
-fn __alive(base: &Path, dgram: &UnixDatagram, sock_name: &str) -> bool {
+/// Binds a named socket at `path`, replacing a stale one, that only our user can send to
+/// whatever the umask
+#[cfg(unix)]
+fn bind(path: &Path) -> Result<Socket> {
+    if metadata(path).is_ok() {
+        remove_file(path)?;
+    }
+    let sock = Socket::bind(path)?;
+    set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(sock)
+}
+
+/// Creates the pipe standing for a named socket at `path`, replacing a stale file there
+#[cfg(windows)]
+fn bind(path: &Path) -> Result<Socket> {
+    if metadata(path).is_ok() {
+        remove_file(path)?;
+    }
+    Ok(Socket::bind(path)?)
+}
+
+/// Checks the runtime directory is safe to keep our sockets in
+///
+/// Anyone able to create files in the runtime directory could swap our sockets for their own
+/// and read every instruction, so it must belong to us and not be world writable. On Windows,
+/// where the sockets are named pipes outside of it, it only has to be a directory.
+/// Skipped after [`allow_insecure_runtime_dir`]
+///
+/// # Errors
+///
+/// Fails with [`Error::InsecureRuntimeDir`] if it isn't a directory, is owned by another user
+/// or is world writable
+pub fn check_runtime_dir(base: &Path) -> Result<()> {
+    if INSECURE_RUNTIME_DIR.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let insecure = |reason| Err(Error::InsecureRuntimeDir(base.to_path_buf(), reason));
+
+    let meta = metadata(base)?;
+    if !meta.is_dir() {
+        return insecure("not a directory");
+    }
+    #[cfg(unix)]
+    {
+        check_owner(base, &meta)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn check_owner(base: &Path, meta: &std::fs::Metadata) -> Result<()> {
+    let insecure = |reason| Err(Error::InsecureRuntimeDir(base.to_path_buf(), reason));
+    // SAFETY: `geteuid` is always successful
+    if meta.uid() != unsafe { libc::geteuid() } {
+        return insecure("owned by another user");
+    }
+    if meta.mode() & 0o002 != 0 {
+        return insecure("writable by every user");
+    }
+    Ok(())
+}
+
+/// Skips [`check_runtime_dir`] for the rest of the process, e.g. for a runtime directory on a
+/// filesystem without unix permissions. What `--insecure-runtime-dir` does
+pub fn allow_insecure_runtime_dir() {
+    log::warn!("Runtime directory checks are disabled");
+    INSECURE_RUNTIME_DIR.store(true, Ordering::Relaxed);
+}
+
+/// Whether [`allow_insecure_runtime_dir`] was called
+#[must_use]
+pub fn insecure_runtime_dir_allowed() -> bool {
+    INSECURE_RUNTIME_DIR.load(Ordering::Relaxed)
+}
+
+/// Path of the socket owned by the daemon, which may outlive it if the process was killed
+#[must_use]
+pub fn server_socket(base: &Path) -> PathBuf {
+    base.join(NAMED_SOCKET_0)
+}
+
+fn __alive(base: &Path, dgram: &Socket, sock_name: &str) -> bool {
     !matches!(dgram.connect(base.join(sock_name)), Err(_))
 }
 
-fn __connect_blocking(base: &Path, dgram: &UnixDatagram, sock_name: &str) -> Result<()> {
-    let earlier = Instant::now();
+/// Wakes whoever waits on the returned [`Notify`] when `sock_name` is created in `base`, for as
+/// long as the watcher lives. `None` if the platform can't watch it, backing off still works then
+fn __watch_socket(base: &Path, sock_name: &str) -> Option<(impl Watcher, Arc<Notify>)> {
+    let appeared = Arc::new(Notify::new());
+    let notify = appeared.clone();
+    let sock_name = sock_name.to_owned();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // Paths may come canonicalized, e.g. on macos
+        let created = event
+            .iter()
+            .flat_map(|event| &event.paths)
+            .any(|path| path.file_name() == Some(sock_name.as_ref()));
+        if created {
+            notify.notify_one();
+        }
+    })
+    .map_err(|err| log::debug!("Can't watch the runtime directory: {}", err))
+    .ok()?;
+    watcher
+        .watch(base, RecursiveMode::NonRecursive)
+        .map_err(|err| log::debug!("Can't watch the runtime directory: {}", err))
+        .ok()?;
+
+    Some((watcher, appeared))
+}
+
+async fn __connect(base: &Path, dgram: &Socket, sock_name: &str) -> Result<()> {
+    let sockpath = base.join(sock_name);
+    // The other end is usually up already, don't bother watching then
+    if dgram.connect(&sockpath).is_ok() {
+        log::trace!("Connecting to {:?}", sock_name);
+        return Ok(());
+    }
+
+    let watch = __watch_socket(base, sock_name);
+    let deadline = Instant::now() + Duration::from_secs(CONNECT_TIMEOUT_SECS);
+    let mut backoff = CONNECT_BACKOFF_START;
+    loop {
+        match dgram.connect(&sockpath) {
+            Ok(()) => break,
+            Err(err) if Instant::now() >= deadline => return Err(err.into()),
+            Err(_) => (),
+        }
+
+        let wait = backoff.min(deadline.saturating_duration_since(Instant::now()));
+        if let Some((_, appeared)) = &watch {
+            // Either way we try again, a timeout only means the socket didn't show up
+            let _ = tokio::time::timeout(wait, appeared.notified()).await;
+        } else {
+            tokio::time::sleep(wait).await;
+        }
+        backoff = (backoff * 2).min(CONNECT_BACKOFF_MAX);
+    }
+
+    log::trace!("Connecting to {:?}", sock_name);
+    Ok(())
+}
+
+fn __connect_blocking(base: &Path, dgram: &Socket, sock_name: &str) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(CONNECT_TIMEOUT_SECS);
+    let mut backoff = CONNECT_BACKOFF_START;
     while let Err(err) = dgram.connect(base.join(sock_name)) {
-        if Instant::now().duration_since(earlier).as_secs() > CONNECT_TIMEOUT_SECS {
+        if Instant::now() >= deadline {
             return Err(err.into());
         }
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(CONNECT_BACKOFF_MAX);
     }
 
     log::trace!("Connecting to {:?}", sock_name);
@@ -128,39 +439,52 @@ impl Bridge<Server> {
         __alive(&self.base, &self.sock_1, NAMED_SOCKET_1)
     }
 
-    /// Connect to the other end
+    /// Connect to the other end, waiting up to [`CONNECT_TIMEOUT_SECS`] for it to come up
+    ///
+    /// # Errors
+    ///
+    /// Fails if the other end isn't up by then
+    pub async fn connect(&mut self) -> Result<()> {
+        __connect(&self.base, &self.sock_1, NAMED_SOCKET_1).await
+    }
+
+    /// Connect to the other end, blocking the thread
     ///
     /// # Errors
     ///
     /// Inherits errors of [`__connect_blocking`]
+    #[deprecated(note = "blocks the runtime thread, use `connect` instead")]
     pub fn connect_blocking(&mut self) -> Result<()> {
         __connect_blocking(&self.base, &self.sock_1, NAMED_SOCKET_1)
     }
 
-    /// Send bincode serialized data through the pipe
+    /// Send bincode serialized data through the pipe, after the instructions queued before it
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
+    /// Fails if the socket is not alive, the instruction is over [`READBUF_SIZE`] or the other
+    /// end didn't make room for it in [`SEND_TIMEOUT`]
     pub async fn send(&self, instruction: Instruction) -> Result<()> {
-        let mut buf = BytesMut::with_capacity(READBUF_SIZE);
-        instruction.encode(&mut buf)?;
-        log::trace!("Sending to client {} bytes", buf.len());
-        self.sock_1.send(&buf).await?;
-        Ok(())
+        log::debug!(
+            "Sending {} to client ({} bytes)",
+            describe(&instruction),
+            instruction.encoded_len()
+        );
+        self.queue.send(&self.sock_1, &instruction).await
     }
 
     /// Attempts to receive serialized data from the pipe
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
+    /// Fails if the socket is not alive or the instruction was over [`READBUF_SIZE`]
     pub async fn recv(&self) -> Result<Instruction> {
-        let mut buf = vec![0u8; READBUF_SIZE];
-        let read = self.sock_0.recv(&mut buf).await?;
-        buf.truncate(read);
-        let target = Instruction::decode(&*buf)?;
-        Ok(target)
+        __recv(&self.sock_0).await
+    }
+
+    #[must_use]
+    pub fn queue_stats(&self) -> QueueStats {
+        self.queue.stats()
     }
 }
 
@@ -169,39 +493,52 @@ impl Bridge<Client> {
         __alive(&self.base, &self.sock_0, NAMED_SOCKET_0)
     }
 
-    /// Connect to the other end
+    /// Connect to the other end, waiting up to [`CONNECT_TIMEOUT_SECS`] for it to come up
+    ///
+    /// # Errors
+    ///
+    /// Fails if the other end isn't up by then
+    pub async fn connect(&mut self) -> Result<()> {
+        __connect(&self.base, &self.sock_0, NAMED_SOCKET_0).await
+    }
+
+    /// Connect to the other end, blocking the thread
     ///
     /// # Errors
     ///
     /// Inherits errors of [`__connect_blocking`]
+    #[deprecated(note = "blocks the runtime thread, use `connect` instead")]
     pub fn connect_blocking(&mut self) -> Result<()> {
         __connect_blocking(&self.base, &self.sock_0, NAMED_SOCKET_0)
     }
 
-    /// Send bincode serialized data through the pipe
+    /// Send bincode serialized data through the pipe, after the instructions queued before it
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
+    /// Fails if the socket is not alive, the instruction is over [`READBUF_SIZE`] or the other
+    /// end didn't make room for it in [`SEND_TIMEOUT`]
     pub async fn send(&self, instruction: Instruction) -> Result<()> {
-        let mut buf = BytesMut::with_capacity(READBUF_SIZE);
-        instruction.encode(&mut buf)?;
-        log::trace!("Sending to server {} bytes", buf.len());
-        self.sock_0.send(&*buf).await?;
-        Ok(())
+        log::debug!(
+            "Sending {} to server ({} bytes)",
+            describe(&instruction),
+            instruction.encoded_len()
+        );
+        self.queue.send(&self.sock_0, &instruction).await
     }
 
     /// Attempts to receive serialized data from the pipe
     ///
     /// # Errors
     ///
-    /// Fails if the socket is not alive
+    /// Fails if the socket is not alive or the instruction was over [`READBUF_SIZE`]
     pub async fn recv(&self) -> Result<Instruction> {
-        let mut buf = vec![0u8; READBUF_SIZE];
-        let read = self.sock_1.recv(&mut buf).await?;
-        buf.truncate(read);
-        let target = Instruction::decode(&*buf)?;
-        Ok(target)
+        __recv(&self.sock_1).await
+    }
+
+    #[must_use]
+    pub fn queue_stats(&self) -> QueueStats {
+        self.queue.stats()
     }
 }
 
@@ -215,12 +552,25 @@ pub enum Error {
 
     #[error("encode error {0}")]
     Encode(#[from] prost::EncodeError),
+
+    #[error(
+        "instruction of {0} bytes or more is over the ipc limit of {} bytes",
+        READBUF_SIZE
+    )]
+    TooLarge(usize),
+
+    #[error("the other end didn't make room for an instruction in {0:?}")]
+    Backpressure(Duration),
+
+    #[error("refusing to use runtime directory {0:?}, {1}. Point `GISTIT_RUNTIME` to a private directory or pass `--insecure-runtime-dir`")]
+    InsecureRuntimeDir(PathBuf, &'static str),
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use assert_fs::prelude::*;
+    use gistit_proto::GistitHash;
     use std::sync::Arc;
 
     pub fn test_instruction_1() -> Instruction {
@@ -231,6 +581,10 @@ mod tests {
         Instruction::request_shutdown()
     }
 
+    fn hash(n: usize) -> GistitHash {
+        GistitHash::parse(&format!("{:064x}", n)).unwrap()
+    }
+
     #[tokio::test]
     async fn ipc_named_socket_spawn() {
         let tmp = assert_fs::TempDir::new().unwrap();
@@ -257,7 +611,7 @@ mod tests {
         let server = server(&tmp).unwrap();
         let mut client = client(&tmp).unwrap();
 
-        client.connect_blocking().unwrap();
+        client.connect().await.unwrap();
 
         client.send(test_instruction_1()).await.unwrap();
         client.send(test_instruction_2()).await.unwrap();
@@ -272,7 +626,7 @@ mod tests {
         let mut server = server(&tmp).unwrap();
         let client = client(&tmp).unwrap();
 
-        server.connect_blocking().unwrap();
+        server.connect().await.unwrap();
 
         server.send(test_instruction_1()).await.unwrap();
         server.send(test_instruction_2()).await.unwrap();
@@ -281,14 +635,55 @@ mod tests {
         assert_eq!(client.recv().await.unwrap(), test_instruction_2());
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn ipc_socket_refuses_insecure_runtime_dir() {
+        use std::fs::Permissions;
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = assert_fs::TempDir::new().unwrap();
+        std::fs::set_permissions(&tmp, Permissions::from_mode(0o777)).unwrap();
+        assert!(matches!(
+            server(&tmp).unwrap_err(),
+            Error::InsecureRuntimeDir(_, "writable by every user")
+        ));
+        assert!(matches!(
+            client(&tmp.path().join("nope")).unwrap_err(),
+            Error::IO(_)
+        ));
+
+        std::fs::set_permissions(&tmp, Permissions::from_mode(0o755)).unwrap();
+        let _server = server(&tmp).unwrap();
+        let mode = metadata(server_socket(&tmp)).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn ipc_socket_connect_waits_for_other_end() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let mut client = client(&tmp).unwrap();
+        let base = tmp.path().to_path_buf();
+
+        let started = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            server(&base).unwrap()
+        });
+        client.connect().await.unwrap();
+        client.send(test_instruction_1()).await.unwrap();
+        assert_eq!(
+            started.await.unwrap().recv().await.unwrap(),
+            test_instruction_1()
+        );
+    }
+
     #[tokio::test]
     async fn ipc_socket_alternate_traffic() {
         let tmp = assert_fs::TempDir::new().unwrap();
         let mut server = server(&tmp).unwrap();
         let mut client = client(&tmp).unwrap();
 
-        client.connect_blocking().unwrap();
-        server.connect_blocking().unwrap();
+        client.connect().await.unwrap();
+        server.connect().await.unwrap();
 
         client.send(test_instruction_1()).await.unwrap();
         client.send(test_instruction_2()).await.unwrap();
@@ -308,8 +703,8 @@ mod tests {
         let mut server = server(&tmp).unwrap();
         let mut client = client(&tmp).unwrap();
 
-        client.connect_blocking().unwrap();
-        server.connect_blocking().unwrap();
+        client.connect().await.unwrap();
+        server.connect().await.unwrap();
 
         client.send(test_instruction_1()).await.unwrap();
         client.send(test_instruction_2()).await.unwrap();
@@ -334,36 +729,105 @@ mod tests {
         assert_eq!(server.recv().await.unwrap(), test_instruction_2());
     }
 
+    #[tokio::test]
+    async fn ipc_socket_send_waits_for_room() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let server = server(&tmp).unwrap();
+        let mut client = client(&tmp).unwrap();
+        client.connect().await.unwrap();
+
+        // Way more than the receive queue holds, nobody reads until they are all queued
+        let count = 2_000;
+        let client = Arc::new(client);
+        let sender = client.clone();
+        let sending = tokio::spawn(async move {
+            for n in 0..count {
+                sender
+                    .send(Instruction::request_fetch(hash(n)))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        for n in 0..count {
+            assert_eq!(
+                server.recv().await.unwrap(),
+                Instruction::request_fetch(hash(n))
+            );
+        }
+        sending.await.unwrap();
+
+        let stats = client.queue_stats();
+        assert_eq!(stats.depth, 0);
+        assert!(stats.peak >= 1);
+        assert!(stats.stalls >= 1);
+
+        let huge = Instruction::request_resolve("a".repeat(READBUF_SIZE));
+        assert!(matches!(
+            client.send(huge).await.unwrap_err(),
+            Error::TooLarge(_)
+        ));
+    }
+
     #[tokio::test]
     async fn ipc_socket_traffic_under_load() {
+        const TASKS: usize = 8;
+        const ROUNDS: usize = 200;
+
         let tmp = assert_fs::TempDir::new().unwrap();
         let mut server = server(&tmp).unwrap();
         let mut client = client(&tmp).unwrap();
 
-        client.connect_blocking().unwrap();
-        server.connect_blocking().unwrap();
+        client.connect().await.unwrap();
+        server.connect().await.unwrap();
 
         let server = Arc::new(server);
         let client = Arc::new(client);
 
-        for _ in 0..8 {
-            let s = server.clone();
-            let c = client.clone();
-
-            tokio::spawn(async move {
-                loop {
-                    c.send(test_instruction_1()).await.unwrap();
-                    c.send(test_instruction_2()).await.unwrap();
-
-                    s.send(test_instruction_1()).await.unwrap();
-                    s.send(test_instruction_2()).await.unwrap();
-                }
-            });
+        let senders: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let s = server.clone();
+                let c = client.clone();
+
+                tokio::spawn(async move {
+                    for _ in 0..ROUNDS {
+                        c.send(test_instruction_1()).await.unwrap();
+                        c.send(test_instruction_2()).await.unwrap();
+
+                        s.send(test_instruction_1()).await.unwrap();
+                        s.send(test_instruction_2()).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // The tasks interleave, so only what each end got is checked, not in which order
+        let expected = TASKS * ROUNDS * 2;
+        let receiving = tokio::spawn(async move {
+            let mut received = Vec::with_capacity(expected);
+            for _ in 0..expected {
+                received.push(server.recv().await.unwrap());
+            }
+            received
+        });
+        let mut from_server = Vec::with_capacity(expected);
+        for _ in 0..expected {
+            from_server.push(client.recv().await.unwrap());
+        }
+        let from_client = receiving.await.unwrap();
+        for sender in senders {
+            sender.await.unwrap();
+        }
 
-            assert_eq!(client.recv().await.unwrap(), test_instruction_1());
-            assert_eq!(server.recv().await.unwrap(), test_instruction_1());
-            assert_eq!(client.recv().await.unwrap(), test_instruction_2());
-            assert_eq!(server.recv().await.unwrap(), test_instruction_2());
+        for received in [from_client, from_server] {
+            let count = |instruction: Instruction| {
+                received
+                    .iter()
+                    .filter(|received| **received == instruction)
+                    .count()
+            };
+            assert_eq!(count(test_instruction_1()), TASKS * ROUNDS);
+            assert_eq!(count(test_instruction_2()), TASKS * ROUNDS);
         }
     }
 }
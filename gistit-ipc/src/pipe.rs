@@ -0,0 +1,204 @@
+//! Named pipes standing in for unix datagrams on Windows
+//!
+//! Pipes don't live in the file system, so each named socket of the runtime directory maps to a
+//! pipe named after its path, and an empty file is left at the path for whoever looks for the
+//! socket there. Every instruction is sent over a connection of its own: the sender connects,
+//! writes it and hangs up, the receiver reads until then. That keeps the datagram semantics the
+//! rest of the crate relies on, one instruction per receive, in the order they were sent.
+//!
+//! Pipes created with the default security descriptor can only be written to by our user and
+//! administrators, and remote clients are rejected. Binding fails if the pipe exists already, so
+//! a process that took its name first can't listen in.
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex as SyncMutex;
+
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+use tokio::sync::Mutex;
+
+/// No free instance of the pipe, the receiving end is busy with a previous instruction
+const ERROR_PIPE_BUSY: i32 = 231;
+
+#[derive(Debug)]
+pub struct Datagram {
+    /// Ours to receive on, `None` if unbound
+    bound: Option<Bound>,
+    /// Pipe of the other end, set by [`Self::connect`]
+    peer: SyncMutex<Option<String>>,
+}
+
+#[derive(Debug)]
+struct Bound {
+    name: String,
+    marker: PathBuf,
+    /// The instance the next sender connects to
+    server: Mutex<NamedPipeServer>,
+}
+
+impl Drop for Bound {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.marker);
+    }
+}
+
+/// Pipe standing for the socket at `path`
+fn pipe_name(path: &Path) -> String {
+    let path: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if matches!(c, '\\' | '/' | ':') {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!(r"\\.\pipe\{}", path.trim_start_matches('-'))
+}
+
+fn open(name: &str) -> io::Result<fs::File> {
+    OpenOptions::new()
+        .write(true)
+        .open(name)
+        .map_err(|err| match err.raw_os_error() {
+            Some(ERROR_PIPE_BUSY) => io::Error::new(io::ErrorKind::WouldBlock, err),
+            _ => err,
+        })
+}
+
+impl Datagram {
+    /// Creates the pipe standing for `path`, and the file at `path`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the pipe or the file can't be created, or someone else created the pipe
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        let name = pipe_name(path);
+        let server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&name)?;
+        fs::write(path, b"")?;
+
+        Ok(Self {
+            bound: Some(Bound {
+                name,
+                marker: path.to_path_buf(),
+                server: Mutex::new(server),
+            }),
+            peer: SyncMutex::new(None),
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Never fails, it's fallible like [`tokio::net::UnixDatagram::unbound`]
+    pub fn unbound() -> io::Result<Self> {
+        Ok(Self {
+            bound: None,
+            peer: SyncMutex::new(None),
+        })
+    }
+
+    /// Sends to the socket at `path` from now on, it must be up
+    ///
+    /// # Errors
+    ///
+    /// Fails if nobody is receiving there
+    pub fn connect(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let name = pipe_name(path.as_ref());
+        // The other end skips connections without an instruction
+        match open(&name) {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+            Err(err) => return Err(err),
+        }
+        *self.peer.lock().expect("peer lock to not be poisoned") = Some(name);
+        Ok(())
+    }
+
+    /// Sends `buf` as one instruction
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`io::ErrorKind::WouldBlock`] while the other end is busy, or if we aren't
+    /// connected or it's gone
+    pub fn try_send(&self, buf: &[u8]) -> io::Result<usize> {
+        let peer = self
+            .peer
+            .lock()
+            .expect("peer lock to not be poisoned")
+            .clone()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        open(&peer)?.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    /// Pipes don't tell when the other end makes room, callers bound this with a timeout and
+    /// try again
+    ///
+    /// # Errors
+    ///
+    /// Never fails
+    pub async fn writable(&self) -> io::Result<()> {
+        std::future::pending::<()>().await;
+        Ok(())
+    }
+
+    /// Receives one instruction into `buf`. Ones that don't fit fill it, so callers can tell
+    ///
+    /// # Errors
+    ///
+    /// Fails if we aren't bound or the pipe breaks
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let bound = self
+            .bound
+            .as_ref()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        // Held until the instruction is read, so they are received in order
+        let mut server = bound.server.lock().await;
+
+        loop {
+            server.connect().await?;
+            // Senders wait for a free instance, make one before reading
+            let next = ServerOptions::new().create(&bound.name)?;
+            let connected = std::mem::replace(&mut *server, next);
+
+            let read = read_to_end(&connected, buf).await?;
+            if read > 0 {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+/// Reads until the sender hangs up or `buf` is full
+async fn read_to_end(pipe: &NamedPipeServer, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        pipe.readable().await?;
+        match pipe.try_read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pipe_name_taken_first_is_refused() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let path = tmp.path().join("gistit-0");
+
+        let _squatter = ServerOptions::new().create(pipe_name(&path)).unwrap();
+        assert!(Datagram::bind(&path).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Gistit names
+//!
+//! Organizations can publish stable names for gistits they keep updating, and for their gistit
+//! nodes, in DNS. The name `snippet@example.com` is looked up as TXT records of
+//! `_gistit.snippet.example.com`, each holding either a gistit hash, namespaced or not, or the
+//! multiaddr of a peer:
+//!
+//! ```text
+//! _gistit.snippet.example.com. 300 IN TXT "s:9b74c9897bac770ffc029102a200c5de9b74c9897bac770ffc029102a200c5de"
+//! _gistit.snippet.example.com. 300 IN TXT "/dns4/node.example.com/tcp/4001/p2p/12D3KooW..."
+//! ```
+//!
+//! Updating the record moves the name to another version without anyone changing their links.
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use super::{Error, Result};
+
+/// Label prepended to names, so records don't clash with other uses of the domain
+pub const LABEL: &str = "_gistit";
+
+const MAX_LABEL_LENGTH: usize = 63;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Name {
+    pub name: String,
+    pub domain: String,
+}
+
+/// What a name points to
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Gistit hashes, possibly namespaced, usually just one
+    pub hashes: Vec<String>,
+    /// Multiaddrs of peers
+    pub addrs: Vec<String>,
+}
+
+fn valid_label(label: &str) -> bool {
+    (1..=MAX_LABEL_LENGTH).contains(&label.len())
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Name {
+    /// Reads `name@domain`, `None` if `input` isn't one, e.g. a hash
+    #[must_use]
+    pub fn parse(input: &str) -> Option<Self> {
+        let (name, domain) = input.trim().split_once('@')?;
+        let domain = domain.trim_end_matches('.');
+        let valid = valid_label(name) && domain.contains('.') && domain.split('.').all(valid_label);
+        valid.then(|| Self {
+            name: name.to_ascii_lowercase(),
+            domain: domain.to_ascii_lowercase(),
+        })
+    }
+
+    /// Domain name holding the TXT records, fully qualified
+    #[must_use]
+    pub fn record(&self) -> String {
+        format!("{}.{}.{}.", LABEL, self.name, self.domain)
+    }
+}
+
+impl std::fmt::Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.name, self.domain)
+    }
+}
+
+impl Record {
+    /// Sorts the values of TXT records into hashes and multiaddrs, ignoring anything else
+    pub fn parse<I: IntoIterator<Item = String>>(values: I) -> Self {
+        let mut record = Self::default();
+        for value in values {
+            let value = value.trim();
+            if value.starts_with('/') {
+                record.addrs.push(value.to_owned());
+            } else if is_hash(value) {
+                record.hashes.push(value.to_ascii_lowercase());
+            }
+        }
+        record
+    }
+}
+
+/// A full gistit hash, optionally prefixed by its namespace like `s:` or `p:`
+fn is_hash(value: &str) -> bool {
+    let hash = match value.split_once(':') {
+        Some((namespace, hash)) if namespace.len() == 1 => hash,
+        Some(_) => return false,
+        None => value,
+    };
+    hash.len() == crate::var::GISTIT_HASH_LENGTH && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Looks `name` up with the resolvers configured on the system
+///
+/// # Errors
+///
+/// Fails if the system resolvers can't be read or the lookup fails. A name without records
+/// isn't an error, its record is just empty
+pub async fn lookup(name: &Name) -> Result<Record> {
+    let resolver =
+        TokioAsyncResolver::tokio_from_system_conf().map_err(|err| Error::Dns(err.to_string()))?;
+
+    match resolver.txt_lookup(name.record()).await {
+        Ok(lookup) => Ok(Record::parse(lookup.iter().map(|txt| {
+            txt.txt_data()
+                .iter()
+                .map(|part| String::from_utf8_lossy(part))
+                .collect::<String>()
+        }))),
+        Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+            Ok(Record::default())
+        }
+        Err(err) => Err(Error::Dns(format!("{}: {}", name, err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dns_name_and_record_parse() {
+        let name = Name::parse("Snippet@Example.com").unwrap();
+        assert_eq!(name.to_string(), "snippet@example.com");
+        assert_eq!(name.record(), "_gistit.snippet.example.com.");
+        assert_eq!(Name::parse("a1b2c3"), None);
+        assert_eq!(Name::parse("s:a1b2c3"), None);
+        assert_eq!(Name::parse("snippet@localhost"), None);
+        assert_eq!(Name::parse("-snippet@example.com"), None);
+        assert_eq!(Name::parse("snip pet@example.com"), None);
+
+        let hash = "9B74C9897BAC770FFC029102A200C5DE9B74C9897BAC770FFC029102A200C5DE";
+        let record = Record::parse(vec![
+            format!("s:{}", hash),
+            "/dns4/node.example.com/tcp/4001".to_owned(),
+            "v=spf1 -all".to_owned(),
+            "9b74c9".to_owned(),
+        ]);
+        assert_eq!(
+            record.hashes,
+            vec![format!("s:{}", hash.to_ascii_lowercase())]
+        );
+        assert_eq!(record.addrs, vec!["/dns4/node.example.com/tcp/4001"]);
+    }
+}
@@ -0,0 +1,249 @@
+//! Files shared between processes
+//!
+//! Settings, history and the state files of the gistit node are read and written by the CLI and
+//! the node alike. [`replace`] swaps in new contents at once, so readers see the old or the new
+//! contents and never a mix, and a crash halfway leaves the old ones. A read-modify-write holds a
+//! [`Lock`] so concurrent writers don't undo each other's changes.
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::Result;
+
+/// Tells apart the temporary files of threads writing the same file
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// Replaces `path` with `contents` at once, keeping its permissions if it exists
+///
+/// # Errors
+///
+/// Fails if the temporary file can't be written or renamed over `path`
+pub fn replace(path: &Path, contents: &[u8]) -> Result<()> {
+    let permissions = fs::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions());
+    replace_with(path, contents, permissions)
+}
+
+/// Replaces `path` with `contents` at once, only readable and writable by us. It's never readable
+/// by others, not even in its temporary file
+///
+/// # Errors
+///
+/// Fails if the temporary file can't be written or renamed over `path`
+pub fn replace_private(path: &Path, contents: &[u8]) -> Result<()> {
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(fs::Permissions::from_mode(0o600))
+    };
+    #[cfg(not(unix))]
+    let permissions = None;
+    replace_with(path, contents, permissions)
+}
+
+fn replace_with(path: &Path, contents: &[u8], permissions: Option<fs::Permissions>) -> Result<()> {
+    let partial = sibling(
+        path,
+        &format!(
+            "{}.{}",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+    let written = write_new(&partial, contents, permissions).and_then(|()| {
+        fs::rename(&partial, path)?;
+        sync_parent(path)
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    Ok(written?)
+}
+
+fn write_new(
+    path: &Path,
+    contents: &[u8],
+    permissions: Option<fs::Permissions>,
+) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // Given permissions are only set once it's ours alone, new files get the usual ones
+        options.mode(if permissions.is_some() { 0o600 } else { 0o666 });
+    }
+
+    let mut file = options.open(path)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    file.write_all(contents)?;
+    // On disk before it replaces anything
+    file.sync_all()
+}
+
+/// Makes the rename itself durable, not supported everywhere
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// `path` with `.suffix` appended to its file name
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map_or_else(OsString::new, OsString::from);
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Exclusive use of a file by a process, taken around reading and replacing it
+///
+/// It's a separate `.lock` file next to it, since [`replace`] swaps the file itself. Released when
+/// dropped, or by the OS if the process dies
+#[derive(Debug)]
+pub struct Lock {
+    #[cfg(unix)]
+    _file: fs::File,
+    #[cfg(not(unix))]
+    path: PathBuf,
+}
+
+impl Lock {
+    /// Waits for other processes to release the lock of `path` and takes it
+    ///
+    /// # Errors
+    ///
+    /// Fails if the lock file can't be opened
+    #[cfg(unix)]
+    pub fn acquire(path: &Path) -> Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling(path, "lock"))?;
+        loop {
+            // SAFETY: the descriptor is valid for as long as `file` lives
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                return Ok(Self { _file: file });
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+    }
+
+    /// Creates the lock file, waiting for it to be removed if it exists. One left behind by a
+    /// process that crashed is taken over after a while
+    ///
+    /// # Errors
+    ///
+    /// Fails if the lock file can't be created
+    #[cfg(not(unix))]
+    pub fn acquire(path: &Path) -> Result<Self> {
+        use std::time::{Duration, Instant};
+
+        const STALE: Duration = Duration::from_secs(10);
+
+        let path = sibling(path, "lock");
+        let started = Instant::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Self { path }),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if started.elapsed() > STALE {
+                        fs::remove_file(&path).ok();
+                    } else {
+                        std::thread::sleep(Duration::from_millis(20));
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Drop for Lock {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn replace_is_whole_and_keeps_permissions() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("state.json");
+
+        replace(&file, b"old").unwrap();
+        replace(&file, b"new").unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"new");
+        // No temporary file left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = |file: &Path| fs::metadata(file).unwrap().permissions().mode() & 0o777;
+            let secret = dir.path().join("secret");
+            replace_private(&secret, b"key").unwrap();
+            assert_eq!(mode(&secret), 0o600);
+
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+            replace(&file, b"newer").unwrap();
+            assert_eq!(mode(&file), 0o640);
+        }
+    }
+
+    #[test]
+    fn lock_serializes_updates() {
+        let dir = TempDir::new().unwrap();
+        let file = Arc::new(dir.path().join("counter"));
+        replace(&file, b"0").unwrap();
+
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let file = Arc::clone(&file);
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        let _lock = Lock::acquire(&file).unwrap();
+                        let count: u32 = fs::read_to_string(&*file).unwrap().parse().unwrap();
+                        replace(&file, (count + 1).to_string().as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&*file).unwrap(), "80");
+    }
+}
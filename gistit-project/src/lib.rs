@@ -26,8 +26,19 @@ pub const ORGANIZATION: &str = "demfabris";
 
 pub const QUALIFIER: &str = "io";
 
+pub mod dns;
+
+pub mod file;
+
+pub mod lang;
+
+pub mod settings;
+
+pub mod vault;
+
 pub mod path {
     use std::fs;
+    use std::io::{self, Write};
     use std::path::{Path, PathBuf};
 
     use directories::{BaseDirs, ProjectDirs};
@@ -49,7 +60,12 @@ pub mod path {
 
         let runtime = runtime()?;
         if fs::metadata(&runtime).is_err() {
-            fs::create_dir_all(&runtime)?;
+            create_private_dir(&runtime)?;
+        }
+        if !writable(&runtime) {
+            return Err(Error::Directory(
+                "can't create files in the runtime directory",
+            ));
         }
 
         let data = data()?;
@@ -60,18 +76,142 @@ pub mod path {
         Ok(())
     }
 
-    /// Returns the runtime path of this program
-    /// Fallbacks to a temporary folder
+    /// Only we can list or create files in it, since our ipc sockets live there
+    #[cfg(unix)]
+    fn create_private_dir(path: &Path) -> std::io::Result<()> {
+        use std::os::unix::fs::DirBuilderExt;
+
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(path)
+    }
+
+    #[cfg(not(unix))]
+    fn create_private_dir(path: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    /// Returns the runtime path of this program, where the daemon and the cli meet. Both must
+    /// resolve the same one, so it only depends on the user:
+    ///
+    /// 1. `GISTIT_RUNTIME`, if set
+    /// 2. `XDG_RUNTIME_DIR`
+    /// 3. `/run/user/<uid>`, set up by logind even when `XDG_RUNTIME_DIR` isn't exported, e.g.
+    ///    from cron
+    /// 4. Our cache folder, which is in `%LOCALAPPDATA%` on Windows. Unlike the temporary one
+    ///    it isn't shared with every user
     ///
     /// # Errors
     ///
-    /// Fails if the system doesn't have a HOME directory
+    /// Fails if the system doesn't have a HOME directory or `GISTIT_RUNTIME` isn't an absolute
+    /// path to a directory
     pub fn runtime() -> Result<PathBuf> {
-        let default = BaseDirs::new()
+        if let Some(path) = std::env::var_os(env::GISTIT_RUNTIME_VAR) {
+            let path = PathBuf::from(path);
+            if !path.is_absolute() {
+                return Err(Error::Directory(
+                    "`GISTIT_RUNTIME` must be an absolute path",
+                ));
+            }
+            if path.exists() && !path.is_dir() {
+                return Err(Error::Directory("`GISTIT_RUNTIME` is not a directory"));
+            }
+            return Ok(path);
+        }
+
+        let base = BaseDirs::new().ok_or(Error::Directory("can't open home directory"))?;
+        if let Some(dir) = base.runtime_dir() {
+            return Ok(dir.to_path_buf());
+        }
+
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            // SAFETY: `geteuid` is always successful
+            let run_user = PathBuf::from(format!("/run/user/{}", unsafe { libc::geteuid() }));
+            if run_user.is_dir() {
+                return Ok(run_user);
+            }
+        }
+
+        Ok(ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
             .ok_or(Error::Directory("can't open home directory"))?
-            .runtime_dir()
-            .map_or_else(std::env::temp_dir, Path::to_path_buf);
-        Ok(env::var_or_default(env::GISTIT_RUNTIME_VAR, default))
+            .cache_dir()
+            .join("run"))
+    }
+
+    /// Name of the lock file in the runtime directory
+    pub const RUNTIME_LOCK: &str = "gistit.lock";
+
+    /// Exclusive use of a runtime directory by a gistit node, so a second one can't take over
+    /// the sockets of the first. Released when dropped, or by the OS if the process dies
+    #[derive(Debug)]
+    pub struct RuntimeLock {
+        file: fs::File,
+        #[cfg(not(unix))]
+        path: PathBuf,
+    }
+
+    impl RuntimeLock {
+        /// Locks `runtime` and writes our pid in the lock file
+        ///
+        /// # Errors
+        ///
+        /// Fails with [`Error::Locked`] if another process holds the lock
+        #[cfg(unix)]
+        pub fn acquire(runtime: &Path) -> Result<Self> {
+            use std::os::unix::io::AsRawFd;
+
+            // Not truncated before we hold the lock, it has the pid of the holder
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(runtime.join(RUNTIME_LOCK))?;
+            // SAFETY: the descriptor is valid for as long as `file` lives
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = io::Error::last_os_error();
+                return Err(if err.kind() == io::ErrorKind::WouldBlock {
+                    Error::Locked(runtime.to_path_buf())
+                } else {
+                    err.into()
+                });
+            }
+            Self { file }.write_pid()
+        }
+
+        /// Creates the lock file, which must not exist. A node that crashed leaves it behind and
+        /// it has to be removed by hand
+        ///
+        /// # Errors
+        ///
+        /// Fails with [`Error::Locked`] if the lock file exists
+        #[cfg(not(unix))]
+        pub fn acquire(runtime: &Path) -> Result<Self> {
+            let path = runtime.join(RUNTIME_LOCK);
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(|err| match err.kind() {
+                    io::ErrorKind::AlreadyExists => Error::Locked(runtime.to_path_buf()),
+                    _ => err.into(),
+                })?;
+            Self { file, path }.write_pid()
+        }
+
+        fn write_pid(mut self) -> Result<Self> {
+            self.file.set_len(0)?;
+            write!(self.file, "{}", std::process::id())?;
+            Ok(self)
+        }
+    }
+
+    #[cfg(not(unix))]
+    impl Drop for RuntimeLock {
+        fn drop(&mut self) {
+            fs::remove_file(&self.path).ok();
+        }
     }
 
     /// Returns the config path of this program
@@ -99,6 +239,28 @@ pub mod path {
             .to_path_buf();
         Ok(env::var_or_default(env::GISTIT_DATA_VAR, default))
     }
+
+    /// Returns the home directory of the current user
+    ///
+    /// # Errors
+    ///
+    /// Fails if the system doesn't have a HOME directory
+    pub fn home() -> Result<PathBuf> {
+        Ok(BaseDirs::new()
+            .ok_or(Error::Directory("can't open home directory"))?
+            .home_dir()
+            .to_path_buf())
+    }
+
+    /// Checks a directory exists and we can create files in it
+    #[must_use]
+    pub fn writable(path: &Path) -> bool {
+        let probe = path.join(".gistit-probe");
+        let writable = fs::write(&probe, b"").is_ok();
+        fs::remove_file(&probe).ok();
+
+        writable
+    }
 }
 
 pub mod env {
@@ -113,6 +275,28 @@ pub mod env {
 
     pub const GISTIT_SERVER_URL: &str = "GISTIT_SERVER_URL";
 
+    pub const GISTIT_HTTP_RETRIES: &str = "GISTIT_HTTP_RETRIES";
+
+    /// Unlocks the vault without prompting, see [`crate::vault`]
+    pub const GISTIT_PASSPHRASE: &str = "GISTIT_PASSPHRASE";
+
+    /// Secret shared by the nodes syncing history, see [`crate::vault::sync_key`]
+    pub const GISTIT_SYNC_SECRET: &str = "GISTIT_SYNC_SECRET";
+
+    /// Secret of encrypted gistits, sent or fetched, so scripts aren't prompted for it
+    pub const GISTIT_SECRET: &str = "GISTIT_SECRET";
+
+    /// Pastebin developer api key, needed by `--to pastebin`
+    pub const GISTIT_PASTEBIN_KEY: &str = "GISTIT_PASTEBIN_KEY";
+
+    /// Disables colored output when set to a non-empty value
+    pub const NO_COLOR: &str = "NO_COLOR";
+
+    /// Editor used by `gistit config edit`, preferred over [`EDITOR`]
+    pub const VISUAL: &str = "VISUAL";
+
+    pub const EDITOR: &str = "EDITOR";
+
     #[must_use]
     pub fn var_or_default(var: &str, default: PathBuf) -> PathBuf {
         env::var_os(var)
@@ -122,16 +306,98 @@ pub mod env {
 }
 
 pub mod var {
-    /// Max gistit size allowed in bytes
-    pub const GISTIT_MAX_SIZE: usize = 50_000;
-
-    /// Gistit hash size (sha256)
+    /// Gistit hash size (sha256), `gistit_proto::validate::HASH_LENGTH` for crates depending on
+    /// it
     pub const GISTIT_HASH_LENGTH: usize = 64;
 
     /// Default server base url
     pub const GISTIT_SERVER_URL_BASE: &str = "https://us-central1-gistit-base.cloudfunctions.net/";
 }
 
+pub mod exit {
+    /// Error categories shared by `gistit` and `gistit-daemon`, each exits with its own code
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorKind {
+        Other = 1,
+        Argument = 2,
+        Io = 3,
+        NotFound = 4,
+        Network = 5,
+        Daemon = 6,
+        Integrity = 7,
+        Auth = 8,
+        Config = 9,
+        /// Ctrl-C, 128 + SIGINT as shells report it
+        Interrupted = 130,
+    }
+
+    impl ErrorKind {
+        pub const ALL: [Self; 10] = [
+            Self::Other,
+            Self::Argument,
+            Self::Io,
+            Self::NotFound,
+            Self::Network,
+            Self::Daemon,
+            Self::Integrity,
+            Self::Auth,
+            Self::Config,
+            Self::Interrupted,
+        ];
+
+        #[must_use]
+        pub const fn code(self) -> i32 {
+            self as i32
+        }
+
+        #[must_use]
+        pub const fn describe(self) -> &'static str {
+            match self {
+                Self::Other => "unexpected error",
+                Self::Argument => "invalid argument or input",
+                Self::Io => "file system error",
+                Self::NotFound => "gistit not found",
+                Self::Network => "network or server error",
+                Self::Daemon => "gistit node is not running or failed",
+                Self::Integrity => "content doesn't match its hash",
+                Self::Auth => "authorization failed",
+                Self::Config => "invalid settings or configuration",
+                Self::Interrupted => "interrupted with Ctrl-C",
+            }
+        }
+    }
+}
+
+pub mod verbosity {
+    /// Log filter of `-v` given `verbosity` times, in the `RUST_LOG` syntax. Gistit crates log in
+    /// more detail than the libraries they use, so `gistit -v` and the node it starts match
+    #[must_use]
+    pub const fn filter(verbosity: u8) -> &'static str {
+        match verbosity {
+            0 => "info",
+            1 => "info,gistit=debug",
+            2 => "debug,gistit=trace",
+            _ => "trace",
+        }
+    }
+
+    /// The level `filter` gives records of `target`, the longest matching target prefix wins
+    #[must_use]
+    pub fn level<'a>(filter: &'a str, target: &str) -> &'a str {
+        let mut level = "off";
+        let mut matched = None;
+        for directive in filter.split(',').map(str::trim) {
+            let (prefix, directive_level) = directive.split_once('=').unwrap_or(("", directive));
+            let longer = !matches!(matched, Some(len) if prefix.len() < len);
+            if target.starts_with(prefix) && longer {
+                level = directive_level;
+                matched = Some(prefix.len());
+            }
+        }
+        level
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(thiserror::Error, Debug)]
@@ -141,4 +407,93 @@ pub enum Error {
 
     #[error("io error: {0}")]
     IO(#[from] std::io::Error),
+
+    #[error("another gistit node is running in {0:?}")]
+    Locked(std::path::PathBuf),
+
+    #[error("invalid settings: {0}")]
+    Settings(String),
+
+    #[error("unknown setting `{0}`, expected one of: {}", settings::KEYS.join(", "))]
+    SettingsKey(String),
+
+    #[error("invalid value `{1}` for setting `{0}`")]
+    SettingsValue(String, String),
+
+    #[error("settings error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("vault error: {0}")]
+    Vault(&'static str),
+
+    #[error("dns lookup failed: {0}")]
+    Dns(String),
+}
+
+impl Error {
+    #[must_use]
+    pub const fn kind(&self) -> exit::ErrorKind {
+        match self {
+            Self::IO(_) => exit::ErrorKind::Io,
+            Self::Vault(_) => exit::ErrorKind::Auth,
+            Self::Locked(_) => exit::ErrorKind::Daemon,
+            Self::Dns(_) => exit::ErrorKind::Network,
+            Self::Directory(_)
+            | Self::Settings(_)
+            | Self::SettingsKey(_)
+            | Self::SettingsValue(..)
+            | Self::Yaml(_) => exit::ErrorKind::Config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::path::RuntimeLock;
+    use super::*;
+
+    #[test]
+    fn runtime_lock_is_exclusive() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+
+        let lock = RuntimeLock::acquire(dir).unwrap();
+        assert!(matches!(RuntimeLock::acquire(dir), Err(Error::Locked(_))));
+        let pid = std::fs::read_to_string(dir.join(path::RUNTIME_LOCK)).unwrap();
+        assert_eq!(pid, std::process::id().to_string());
+
+        drop(lock);
+        RuntimeLock::acquire(dir).unwrap();
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let codes: std::collections::HashSet<i32> = exit::ErrorKind::ALL
+            .iter()
+            .map(|kind| kind.code())
+            .collect();
+        assert_eq!(codes.len(), exit::ErrorKind::ALL.len());
+        // 0 is success
+        assert!(!codes.contains(&0));
+        assert_eq!(exit::ErrorKind::Interrupted.code(), 130);
+        assert_eq!(
+            Error::Settings("bad".to_owned()).kind(),
+            exit::ErrorKind::Config
+        );
+    }
+
+    #[test]
+    fn verbosity_filter_levels() {
+        assert_eq!(verbosity::level(verbosity::filter(0), "gistit_ipc"), "info");
+        let filter = verbosity::filter(1);
+        assert_eq!(verbosity::level(filter, "gistit_ipc"), "debug");
+        assert_eq!(verbosity::level(filter, "libp2p_kad::behaviour"), "info");
+        let filter = verbosity::filter(2);
+        assert_eq!(verbosity::level(filter, "gistit_daemon::node"), "trace");
+        assert_eq!(verbosity::level(filter, "reqwest::connect"), "debug");
+        assert_eq!(verbosity::level(verbosity::filter(9), "hyper"), "trace");
+        assert_eq!(verbosity::level("gistit=warn", "hyper"), "off");
+    }
 }
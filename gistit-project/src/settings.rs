@@ -0,0 +1,331 @@
+//! User settings
+//!
+//! Read from `settings.yaml` in the config directory and merged over the built-in defaults.
+//! Command line arguments still take precedence over anything set here. The file is validated
+//! strictly, so a typo in a key or a wrong value type is reported with its line and column
+//! instead of being silently ignored.
+//!
+//! The gistit node reads the `node-*` keys too, and again when asked to while running. The file
+//! is replaced at once when written, so it's never read half written, see [`crate::file`].
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use super::{file, path, Error, Result};
+
+pub const SETTINGS_FILE: &str = "settings.yaml";
+
+/// Every key accepted in the settings file
+pub const KEYS: &[&str] = &[
+    "author",
+    "colorscheme",
+    "clipboard",
+    "no-color",
+    "no-retry",
+    "alias",
+    "default-command",
+    "stdin-limit",
+    "pre-send",
+    "post-fetch",
+    "hook-timeout",
+    "hook-failure",
+    "node-rate-limit",
+    "node-ban-duration",
+    "node-max-hosted",
+    "node-max-hosted-bytes",
+];
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    /// Author of sent gistits, instead of a random generated name
+    pub author: Option<String>,
+
+    /// Colorscheme used to preview gistits
+    pub colorscheme: Option<String>,
+
+    /// Always copy the sent gistit hash to the clipboard
+    pub clipboard: bool,
+
+    /// Disable colors and emoji in the output
+    pub no_color: bool,
+
+    /// Don't retry failed network requests
+    pub no_retry: bool,
+
+    /// Shorthands expanded before parsing the command line, e.g. `s: send --clipboard`
+    pub alias: BTreeMap<String, String>,
+
+    /// Command or alias to run when gistit is called without arguments, instead of sending stdin
+    pub default_command: Option<String>,
+
+    /// Max bytes read from stdin, larger input is refused
+    pub stdin_limit: Option<usize>,
+
+    /// Shell command run before a gistit is sent, e.g. `./lint.sh "$FILE"`
+    pub pre_send: Option<String>,
+
+    /// Shell command run after a gistit is fetched, e.g. `open "$FILE"`
+    pub post_fetch: Option<String>,
+
+    /// Seconds a hook may run before it's killed
+    pub hook_timeout: Option<u64>,
+
+    /// What a failing hook does to the command that ran it
+    pub hook_failure: HookFailure,
+
+    /// Inbound requests the gistit node allows per peer each minute before banning it
+    pub node_rate_limit: Option<u32>,
+
+    /// Seconds misbehaving peers stay banned by the gistit node
+    pub node_ban_duration: Option<u64>,
+
+    /// Max gistits the gistit node provides from the client or `--provide-dir`
+    pub node_max_hosted: Option<usize>,
+
+    /// Bytes the gistits the gistit node provides from the client or `--provide-dir` may take
+    pub node_max_hosted_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookFailure {
+    /// The command fails with the hook
+    #[default]
+    Abort,
+    /// The command goes on, the failure is only reported
+    Warn,
+}
+
+/// Returns the settings file path, which may not exist
+///
+/// # Errors
+///
+/// Fails if the system doesn't have a HOME directory
+pub fn file() -> Result<PathBuf> {
+    Ok(path::config()?.join(SETTINGS_FILE))
+}
+
+/// Loads the settings file, defaults are used if it doesn't exist
+///
+/// # Errors
+///
+/// Fails if the file can't be read or doesn't match the settings schema
+pub fn load() -> Result<Settings> {
+    load_from(&file()?)
+}
+
+/// Loads the settings file at `file`, like [`load`]
+///
+/// # Errors
+///
+/// Fails if the file can't be read or doesn't match the settings schema
+pub fn load_from(file: &Path) -> Result<Settings> {
+    if fs::metadata(file).is_err() {
+        return Ok(Settings::default());
+    }
+
+    parse(&fs::read_to_string(file)?, file)
+}
+
+/// Parses and validates settings, `origin` is only used in error messages
+///
+/// # Errors
+///
+/// Fails on unknown keys, wrong value types or malformed YAML
+pub fn parse(source: &str, origin: &Path) -> Result<Settings> {
+    // An empty document is null, which we treat as an empty file
+    if source.trim().is_empty() {
+        return Ok(Settings::default());
+    }
+
+    serde_yaml::from_str(source).map_err(|err| invalid(&err, origin))
+}
+
+/// Reads a single setting with defaults applied, `None` if it's unset
+///
+/// # Errors
+///
+/// Fails if the key is unknown or the settings file is invalid
+pub fn get(key: &str) -> Result<Option<String>> {
+    check_key(key)?;
+
+    let value = match serde_yaml::to_value(load()?)? {
+        Value::Mapping(mapping) => mapping.get(&Value::from(key)).cloned(),
+        _ => None,
+    };
+
+    Ok(value.and_then(|value| match value {
+        Value::Null => None,
+        Value::String(value) => Some(value),
+        other => serde_yaml::to_string(&other)
+            .ok()
+            .map(|value| value.trim_start_matches("---").trim().to_owned()),
+    }))
+}
+
+/// Writes a single setting into the settings file, keeping the other keys as they are
+///
+/// # Errors
+///
+/// Fails if the key is unknown, the value has the wrong type or the file can't be written
+pub fn set(key: &str, value: &str) -> Result<()> {
+    set_in(&file()?, key, value)
+}
+
+/// Writes a single setting into the settings file at `file`, like [`set`]. Concurrent writers
+/// take turns, so none of their settings are lost
+///
+/// # Errors
+///
+/// Fails if the key is unknown, the value has the wrong type or the file can't be written
+pub fn set_in(file: &Path, key: &str, value: &str) -> Result<()> {
+    check_key(key)?;
+
+    let _lock = file::Lock::acquire(file)?;
+    let mut mapping = if fs::metadata(file).is_ok() {
+        let source = fs::read_to_string(file)?;
+        // Refuse to build on top of a broken file
+        parse(&source, file)?;
+        serde_yaml::from_str::<Option<Mapping>>(&source)
+            .map_err(|err| invalid(&err, file))?
+            .unwrap_or_default()
+    } else {
+        Mapping::new()
+    };
+
+    // `true` should be a boolean but `1234` is still a fine author
+    let parsed: Value = serde_yaml::from_str(value).unwrap_or_else(|_| Value::from(value));
+    for candidate in [parsed, Value::from(value)] {
+        mapping.insert(Value::from(key), candidate);
+        if serde_yaml::from_value::<Settings>(Value::Mapping(mapping.clone())).is_ok() {
+            file::replace(file, serde_yaml::to_string(&mapping)?.as_bytes())?;
+            return Ok(());
+        }
+    }
+
+    Err(Error::SettingsValue(key.to_owned(), value.to_owned()))
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if KEYS.contains(&key) {
+        Ok(())
+    } else {
+        Err(Error::SettingsKey(key.to_owned()))
+    }
+}
+
+fn invalid(err: &serde_yaml::Error, origin: &Path) -> Error {
+    let origin = origin.to_string_lossy();
+    let reason = err.to_string();
+    err.location().map_or_else(
+        || Error::Settings(format!("{}: {}", origin, reason)),
+        |location| {
+            // The location is already part of the reason, move it up front
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            Error::Settings(format!(
+                "{}:{}:{}: {}",
+                origin,
+                location.line(),
+                location.column(),
+                reason.strip_suffix(&suffix).unwrap_or(&reason)
+            ))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn settings_parse_and_validate() {
+        let origin = Path::new("settings.yaml");
+        assert_eq!(parse("", origin).unwrap(), Settings::default());
+
+        let settings = parse("author: foo\nclipboard: true\n", origin).unwrap();
+        assert_eq!(settings.author.as_deref(), Some("foo"));
+        assert!(settings.clipboard);
+        assert!(!settings.no_color);
+
+        let typo = parse("author: foo\ncolorschem: Nord\n", origin).unwrap_err();
+        assert!(typo
+            .to_string()
+            .starts_with("invalid settings: settings.yaml:2:1: unknown field `colorschem`"));
+
+        let alias = parse(
+            "alias:\n  s: send --clipboard\ndefault-command: ls\n",
+            origin,
+        )
+        .unwrap();
+        assert_eq!(alias.alias["s"], "send --clipboard");
+        assert_eq!(alias.default_command.as_deref(), Some("ls"));
+
+        let limit = parse(
+            "stdin-limit: 1000
+",
+            origin,
+        )
+        .unwrap();
+        assert_eq!(limit.stdin_limit, Some(1000));
+
+        let hooks = parse(
+            "pre-send: ./lint.sh \"$FILE\"\nhook-timeout: 5\nhook-failure: warn\n",
+            origin,
+        )
+        .unwrap();
+        assert_eq!(hooks.pre_send.as_deref(), Some("./lint.sh \"$FILE\""));
+        assert_eq!(hooks.post_fetch, None);
+        assert_eq!(hooks.hook_timeout, Some(5));
+        assert_eq!(hooks.hook_failure, HookFailure::Warn);
+        assert_eq!(Settings::default().hook_failure, HookFailure::Abort);
+        assert!(parse("hook-failure: ignore\n", origin).is_err());
+
+        let wrong_type = parse("clipboard: sometimes\n", origin).unwrap_err();
+        assert!(wrong_type
+            .to_string()
+            .starts_with("invalid settings: settings.yaml:1:"));
+
+        let node = parse("node-rate-limit: 30\nnode-max-hosted-bytes: 1000\n", origin).unwrap();
+        assert_eq!(node.node_rate_limit, Some(30));
+        assert_eq!(node.node_max_hosted_bytes, Some(1000));
+        assert_eq!(node.node_max_hosted, None);
+    }
+
+    #[test]
+    fn settings_set_concurrently() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join(SETTINGS_FILE);
+
+        set_in(&file, "author", "foo").unwrap();
+        let writers: Vec<_> = [
+            ("colorscheme", "Nord"),
+            ("clipboard", "true"),
+            ("hook-timeout", "5"),
+        ]
+        .into_iter()
+        .map(|(key, value)| {
+            let file = file.clone();
+            thread::spawn(move || set_in(&file, key, value).unwrap())
+        })
+        .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let settings = load_from(&file).unwrap();
+        assert_eq!(settings.author.as_deref(), Some("foo"));
+        assert_eq!(settings.colorscheme.as_deref(), Some("Nord"));
+        assert!(settings.clipboard);
+        assert_eq!(settings.hook_timeout, Some(5));
+        assert!(set_in(&file, "clipboard", "sometimes").is_err());
+        assert!(set_in(&file, "colorschem", "Nord").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Passphrase sealed storage
+//!
+//! Opt-in for machines shared with other users. The passphrase is stretched with argon2 into an
+//! AES-256-GCM key. The `vault` file in the config directory keeps the salt and a sealed check
+//! value, so a wrong passphrase is caught before anything else is decrypted.
+//!
+//! Files leaving this machine, like node identity bundles, are sealed with a passphrase of their
+//! own instead, see [`seal_with_passphrase`]. History synced between our machines is sealed with
+//! a key derived from a generated sync secret alone, see [`sync_key`].
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::{env, path, Error, Result};
+
+pub const VAULT_FILE: &str = "vault";
+
+/// Prefix of every sealed blob, so sealed and plain files can be told apart
+const MAGIC: &[u8] = b"GVLT";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const CHECK: &[u8] = b"gistit";
+
+/// Fixed, so every machine sharing the sync secret derives the same key and group
+const SYNC_KEY_SALT: &[u8] = b"gistit history sync key";
+const SYNC_GROUP_SALT: &[u8] = b"gistit history sync group";
+const SYNC_GROUP_LEN: usize = 16;
+const SYNC_SECRET_PREFIX: &str = "gistit-sync-";
+/// Random bytes in a sync secret
+const SYNC_SECRET_LEN: usize = 16;
+
+/// An unlocked vault, its key is wiped once dropped
+#[derive(Clone)]
+pub struct Vault {
+    key: Zeroizing<[u8; KEY_LEN]>,
+}
+
+impl std::fmt::Debug for Vault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault").finish_non_exhaustive()
+    }
+}
+
+/// Returns the vault file path
+///
+/// # Errors
+///
+/// Fails if the config directory can't be resolved
+pub fn file() -> Result<PathBuf> {
+    Ok(path::config()?.join(VAULT_FILE))
+}
+
+/// Whether the user opted in to the vault
+#[must_use]
+pub fn enabled() -> bool {
+    matches!(file().map(fs::metadata), Ok(Ok(_)))
+}
+
+/// The passphrase from `GISTIT_PASSPHRASE`, for scripts that can't answer a prompt
+#[must_use]
+pub fn passphrase_from_env() -> Option<String> {
+    std::env::var(env::GISTIT_PASSPHRASE)
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+/// Whether `bytes` were sealed by a vault
+#[must_use]
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Forgets the vault, anything still sealed with it can't be opened anymore
+///
+/// # Errors
+///
+/// Fails if the vault file can't be removed
+pub fn remove() -> Result<()> {
+    let file = file()?;
+    if fs::metadata(&file).is_ok() {
+        fs::remove_file(file)?;
+    }
+    Ok(())
+}
+
+/// Seals `plaintext` with `passphrase` alone, not the vault. The salt goes in front, so it can
+/// be opened anywhere with [`open_with_passphrase`]
+///
+/// # Errors
+///
+/// Fails if the passphrase is empty or encryption fails
+pub fn seal_with_passphrase(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        return Err(Error::Vault("passphrase can't be empty"));
+    }
+
+    let mut salt = [0_u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    let mut sealed = salt.to_vec();
+    sealed.extend(Vault::derive(passphrase, &salt)?.seal(plaintext)?);
+    Ok(sealed)
+}
+
+/// Opens what [`seal_with_passphrase`] produced
+///
+/// # Errors
+///
+/// Fails if the passphrase is wrong or `sealed` was tampered with
+pub fn open_with_passphrase(passphrase: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < SALT_LEN {
+        return Err(Error::Vault("sealed data is corrupted"));
+    }
+
+    let (salt, sealed) = sealed.split_at(SALT_LEN);
+    Vault::derive(passphrase, salt)?
+        .open(sealed)
+        .map_err(|_| Error::Vault("wrong passphrase or corrupted data"))
+}
+
+/// A new random secret for [`sync_key`], copied to each machine syncing history
+#[must_use]
+pub fn new_sync_secret() -> String {
+    let mut secret = [0_u8; SYNC_SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    format!("{}{}", SYNC_SECRET_PREFIX, hex(&secret))
+}
+
+/// Checks `secret` came from [`new_sync_secret`]
+///
+/// # Errors
+///
+/// Fails if it's anything else, like a passphrase
+pub fn check_sync_secret(secret: &str) -> Result<()> {
+    match secret.strip_prefix(SYNC_SECRET_PREFIX) {
+        Some(hex)
+            if hex.len() == SYNC_SECRET_LEN * 2
+                && hex.bytes().all(|byte| byte.is_ascii_hexdigit()) =>
+        {
+            Ok(())
+        }
+        _ => Err(Error::Vault("not a sync secret made by gistit")),
+    }
+}
+
+/// The key sealing history synced between machines sharing `secret`, and the id of their group
+///
+/// The id is published, so anyone can try secrets against it offline. Only generated secrets are
+/// taken for that reason, see [`new_sync_secret`]. It's derived apart from the key, so publishing
+/// it doesn't give the key away either
+///
+/// # Errors
+///
+/// Fails if `secret` isn't a sync secret or the keys can't be derived
+pub fn sync_key(secret: &str) -> Result<(Vault, String)> {
+    check_sync_secret(secret)?;
+
+    let group = hex(&Vault::derive(secret, SYNC_GROUP_SALT)?.key[..SYNC_GROUP_LEN]);
+    Ok((Vault::derive(secret, SYNC_KEY_SALT)?, group))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+impl Vault {
+    fn derive(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        let mut key = Zeroizing::new([0_u8; KEY_LEN]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+            .map_err(|_| Error::Vault("failed to derive key from passphrase"))?;
+
+        Ok(Self { key })
+    }
+
+    /// Creates a new vault, replacing the current one
+    ///
+    /// # Errors
+    ///
+    /// Fails if the passphrase is empty or the vault file can't be written
+    pub fn create(passphrase: &str) -> Result<Self> {
+        if passphrase.is_empty() {
+            return Err(Error::Vault("passphrase can't be empty"));
+        }
+
+        let mut salt = [0_u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let vault = Self::derive(passphrase, &salt)?;
+
+        let mut header = salt.to_vec();
+        header.extend(vault.seal(CHECK)?);
+        fs::write(file()?, header)?;
+
+        Ok(vault)
+    }
+
+    /// Unlocks the existing vault
+    ///
+    /// # Errors
+    ///
+    /// Fails if there is no vault, or the passphrase is wrong
+    pub fn unlock(passphrase: &str) -> Result<Self> {
+        let header = fs::read(file()?).map_err(|_| Error::Vault("vault is not enabled"))?;
+        if header.len() < SALT_LEN {
+            return Err(Error::Vault("vault file is corrupted"));
+        }
+
+        let (salt, check) = header.split_at(SALT_LEN);
+        let vault = Self::derive(passphrase, salt)?;
+        match vault.open(check) {
+            Ok(check) if check == CHECK => Ok(vault),
+            _ => Err(Error::Vault("wrong passphrase")),
+        }
+    }
+
+    /// Encrypts `plaintext`
+    ///
+    /// # Errors
+    ///
+    /// Fails if encryption fails
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0_u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut sealed = MAGIC.to_vec();
+        sealed.extend(nonce);
+        sealed.extend(
+            Aes256Gcm::new(Key::from_slice(self.key.as_ref()))
+                .encrypt(Nonce::from_slice(&nonce), plaintext)
+                .map_err(|_| Error::Vault("failed to encrypt"))?,
+        );
+
+        Ok(sealed)
+    }
+
+    /// Decrypts what [`Self::seal`] produced
+    ///
+    /// # Errors
+    ///
+    /// Fails if `sealed` wasn't sealed with this vault or was tampered with
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let corrupted = || Error::Vault("sealed data is corrupted or from another vault");
+        if !is_sealed(sealed) || sealed.len() < MAGIC.len() + NONCE_LEN {
+            return Err(corrupted());
+        }
+
+        let (nonce, ciphertext) = sealed[MAGIC.len()..].split_at(NONCE_LEN);
+        Aes256Gcm::new(Key::from_slice(self.key.as_ref()))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| corrupted())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vault_seal_and_open() {
+        let salt = [7_u8; SALT_LEN];
+        let vault = Vault::derive("correct horse battery staple", &salt).unwrap();
+
+        let sealed = vault.seal(b"fn main() {}").unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!is_sealed(b"[]"));
+        assert_eq!(vault.open(&sealed).unwrap(), b"fn main() {}");
+
+        let other = Vault::derive("wrong", &salt).unwrap();
+        assert!(other.open(&sealed).is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(vault.open(&tampered).is_err());
+    }
+
+    #[test]
+    fn vault_seal_with_passphrase() {
+        let sealed = seal_with_passphrase("hunter2", b"identity").unwrap();
+        assert_eq!(
+            open_with_passphrase("hunter2", &sealed).unwrap(),
+            b"identity"
+        );
+        assert!(open_with_passphrase("hunter3", &sealed).is_err());
+        assert!(open_with_passphrase("hunter2", &sealed[..4]).is_err());
+        assert!(seal_with_passphrase("", b"identity").is_err());
+    }
+
+    #[test]
+    fn vault_sync_key_is_shared() {
+        let secret = new_sync_secret();
+        let (key, group) = sync_key(&secret).unwrap();
+        let (other_key, other_group) = sync_key(&secret).unwrap();
+        assert_eq!(group, other_group);
+        assert_eq!(group.len(), SYNC_GROUP_LEN * 2);
+        assert_eq!(other_key.open(&key.seal(b"[]").unwrap()).unwrap(), b"[]");
+
+        let (stranger, stranger_group) = sync_key(&new_sync_secret()).unwrap();
+        assert_ne!(group, stranger_group);
+        assert!(stranger.open(&key.seal(b"[]").unwrap()).is_err());
+
+        // People pick guessable passphrases
+        assert!(sync_key("").is_err());
+        assert!(sync_key("hunter2").is_err());
+        assert!(sync_key(&format!("{}xyz", SYNC_SECRET_PREFIX)).is_err());
+        assert!(sync_key(&secret[..secret.len() - 1]).is_err());
+    }
+}
@@ -1,4 +1,23 @@
 fn main() -> std::io::Result<()> {
-    prost_build::compile_protos(&["src/payload.proto", "src/ipc.proto"], &["src"])?;
+    prost_build::Config::new()
+        // Only used by `wire::WireFormat::Json`, names follow the protobuf JSON mapping
+        .type_attribute(
+            ".gistit.payload",
+            "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]",
+        )
+        .type_attribute(
+            ".gistit.payload",
+            "#[cfg_attr(feature = \"serde\", serde(rename_all = \"camelCase\", default))]",
+        )
+        // Only used by `gistit node --format json|yaml`, names are kept as they are in the protos
+        .type_attribute(
+            ".gistit.ipc.Instruction.StatusResponse",
+            "#[cfg_attr(feature = \"serde\", derive(serde::Serialize))]",
+        )
+        .type_attribute(
+            ".gistit.ipc.Instruction.PersistentPeer",
+            "#[cfg_attr(feature = \"serde\", derive(serde::Serialize))]",
+        )
+        .compile_protos(&["src/payload.proto", "src/ipc.proto"], &["src"])?;
     Ok(())
 }
@@ -0,0 +1,374 @@
+//! Building valid gistits
+//!
+//! [`Gistit::new`] takes whatever it's given. [`GistitBuilder`] checks every field against
+//! [`crate::validate`] and computes the hash, languages and timestamp, so a built gistit is one
+//! the server and peers accept.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::payload::gistit::{Encryption, Inner, Snippet};
+use crate::validate::{self, Invalid};
+use crate::{Gistit, Result};
+
+#[derive(Debug, Clone, Default)]
+pub struct GistitBuilder {
+    author: String,
+    description: Option<String>,
+    timestamp: Option<String>,
+    inner: Vec<Inner>,
+    public: bool,
+    private: bool,
+    burn_after_read: bool,
+    encryption: Option<Encryption>,
+    previous_hash: Option<String>,
+}
+
+impl GistitBuilder {
+    #[must_use]
+    pub fn new(author: impl Into<String>) -> Self {
+        Self {
+            author: author.into(),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn description(mut self, description: Option<&str>) -> Self {
+        self.description = description.map(ToOwned::to_owned);
+        self
+    }
+
+    /// Unix timestamp in milliseconds, now unless given
+    #[must_use]
+    pub fn timestamp(mut self, timestamp: impl Into<String>) -> Self {
+        self.timestamp = Some(timestamp.into());
+        self
+    }
+
+    /// Adds a text file
+    #[must_use]
+    pub fn file(mut self, name: impl Into<String>, lang: impl Into<String>, data: String) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let size = data.len() as u32;
+        self.inner
+            .push(Gistit::new_inner(name.into(), lang.into(), size, data));
+        self
+    }
+
+    /// Adds a binary file, base64 encoded in the payload
+    #[must_use]
+    pub fn binary_file(
+        mut self,
+        name: impl Into<String>,
+        lang: impl Into<String>,
+        bytes: &[u8],
+    ) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let size = bytes.len() as u32;
+        let mut inner = Gistit::new_inner(name.into(), lang.into(), size, base64::encode(bytes));
+        inner.is_binary = true;
+        self.inner.push(inner);
+        self
+    }
+
+    /// Adds a markdown description too long for [`Self::description`]. It's kept after the
+    /// other files, whatever the order they were added in
+    #[must_use]
+    pub fn description_file(mut self, name: impl Into<String>, data: String) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let size = data.len() as u32;
+        let mut inner = Gistit::new_inner(name.into(), "markdown".to_owned(), size, data);
+        inner.is_description = true;
+        self.inner.push(inner);
+        self
+    }
+
+    /// Marks the last file added as a snippet of a larger file
+    #[must_use]
+    pub fn snippet(mut self, snippet: Snippet) -> Self {
+        if let Some(inner) = self.inner.last_mut() {
+            inner.snippet = Some(snippet);
+        }
+        self
+    }
+
+    #[must_use]
+    pub const fn public(mut self, public: bool) -> Self {
+        self.public = public;
+        self
+    }
+
+    /// Only fetched by the full hash, can't be [`Self::public`]
+    #[must_use]
+    pub const fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    #[must_use]
+    pub const fn burn_after_read(mut self, burn_after_read: bool) -> Self {
+        self.burn_after_read = burn_after_read;
+        self
+    }
+
+    /// Marks the gistit encrypted, its only file must be the sealed one added with
+    /// [`Self::binary_file`]
+    #[must_use]
+    pub fn encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Links the gistit to the one it amends
+    #[must_use]
+    pub fn previous_hash(mut self, hash: impl Into<String>) -> Self {
+        self.previous_hash = Some(hash.into());
+        self
+    }
+
+    /// # Errors
+    ///
+    /// Fails with the first rule broken, see [`crate::validate`]
+    pub fn build(mut self) -> Result<Gistit> {
+        validate::author(&self.author)?;
+        if let Some(ref previous_hash) = self.previous_hash {
+            validate::hash(previous_hash)?;
+        }
+        if let Some(ref description) = self.description {
+            validate::description(description)?;
+        }
+        if self.inner.iter().all(|inner| inner.is_description) {
+            return Err(Invalid::NoFiles.into());
+        }
+        // Stable, files keep their order. The first one is the gistit's main file
+        self.inner.sort_by_key(|inner| inner.is_description);
+        // Sizes were cast before being checked, binary ones are only known from the field
+        for inner in &self.inner {
+            validate::file(inner)?;
+            if !inner.is_binary && inner.size as usize != inner.data.len() {
+                return Err(Invalid::Size.into());
+            }
+        }
+        validate::total_size(&self.inner)?;
+
+        let timestamp = self.timestamp.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis())
+                .to_string()
+        });
+        let mut gistit = Gistit::new(
+            String::new(),
+            self.author,
+            self.description,
+            timestamp,
+            self.inner,
+        );
+        gistit.private = self.private;
+        gistit.previous_hash = self.previous_hash.unwrap_or_default();
+        gistit.hash = gistit.checksum();
+        gistit.public = self.public;
+        gistit.burn_after_read = self.burn_after_read;
+        gistit.encryption = self.encryption;
+        validate::encryption(&gistit)?;
+        validate::visibility(&gistit)?;
+        Ok(gistit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::hash;
+    use crate::{Error, Visibility};
+
+    #[test]
+    fn builder_validates_and_hashes() {
+        let data = "fn main() { println!(\"hello\"); }";
+        let gistit = GistitBuilder::new("fabricio")
+            .description(Some("prints hello"))
+            .file("main.rs", "rust", data.to_owned())
+            .timestamp("0")
+            .public(true)
+            .build()
+            .unwrap();
+        assert_eq!(gistit.hash, hash("fabricio", Some("prints hello"), data));
+        assert!(gistit.is_intact());
+        assert!(gistit.validate().is_ok());
+        assert!(gistit.public && !gistit.burn_after_read);
+        assert_eq!(gistit.visibility(), Visibility::Public);
+        assert_eq!(gistit.languages[0].lang, "rust");
+
+        let binary = GistitBuilder::new("fabricio")
+            .binary_file("logo.png", "binary", &[0; 32])
+            .build()
+            .unwrap();
+        assert!(binary.inner[0].is_binary);
+        assert_eq!(binary.inner[0].size, 32);
+        assert!(binary.validate().is_ok());
+
+        let described = GistitBuilder::new("fabricio")
+            .description_file("README.md", "# Hello\n\nPrints hello, politely.".to_owned())
+            .file("main.rs", "rust", data.to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(described.inner[0].name, "main.rs");
+        assert!(described.inner[1].is_description);
+        assert_eq!(described.inner[1].lang, "markdown");
+        assert_eq!(described.languages.len(), 1);
+        assert!(described.is_intact());
+
+        let mut amended = GistitBuilder::new("fabricio")
+            .description(Some("prints hello"))
+            .file("main.rs", "rust", data.to_owned())
+            .previous_hash(binary.hash.clone())
+            .build()
+            .unwrap();
+        assert_ne!(amended.hash, gistit.hash);
+        assert!(amended.is_intact());
+        assert_eq!(amended.previous_hash(), Some(binary.hash.as_str()));
+        assert_eq!(gistit.previous_hash(), None);
+        // Linked to another gistit by whoever hosts it
+        amended.previous_hash = described.hash.clone();
+        assert!(!amended.is_intact());
+        amended.previous_hash.clear();
+        assert!(!amended.is_intact());
+
+        let mut private = GistitBuilder::new("fabricio")
+            .description(Some("prints hello"))
+            .file("main.rs", "rust", data.to_owned())
+            .private(true)
+            .build()
+            .unwrap();
+        assert_ne!(private.hash, gistit.hash);
+        assert!(private.is_intact());
+        // Made public by whoever hosts it
+        private.private = false;
+        assert!(!private.is_intact());
+
+        let snippet = Snippet {
+            start_line: 120,
+            end_line: 122,
+            symbol: Some("main".to_owned()),
+        };
+        let cut = GistitBuilder::new("fabricio")
+            .file("main.rs", "rust", data.to_owned())
+            .snippet(snippet.clone())
+            .build()
+            .unwrap();
+        assert_eq!(cut.inner[0].snippet, Some(snippet));
+        assert!(cut.is_intact());
+    }
+
+    #[test]
+    fn builder_refuses_broken_rules() {
+        let file = || "fn main() { println!(\"hello\"); }".to_owned();
+        let invalid = |builder: GistitBuilder| match builder.build() {
+            Err(Error::Invalid(invalid)) => invalid,
+            other => panic!("expected an invalid gistit, got {:?}", other),
+        };
+
+        assert_eq!(
+            invalid(GistitBuilder::new("fa").file("main.rs", "rust", file())),
+            Invalid::Author
+        );
+        assert_eq!(
+            invalid(
+                GistitBuilder::new("fabricio")
+                    .description(Some("short"))
+                    .file("main.rs", "rust", file())
+            ),
+            Invalid::Description
+        );
+        assert_eq!(invalid(GistitBuilder::new("fabricio")), Invalid::NoFiles);
+        assert_eq!(
+            invalid(GistitBuilder::new("fabricio").description_file("README.md", file())),
+            Invalid::NoFiles
+        );
+        assert_eq!(
+            invalid(GistitBuilder::new("fabricio").file("", "rust", file())),
+            Invalid::Name
+        );
+        assert_eq!(
+            invalid(GistitBuilder::new("fabricio").file(
+                "main.rs",
+                "rust",
+                "fn main() {}".to_owned()
+            )),
+            Invalid::Size
+        );
+        assert_eq!(
+            invalid(GistitBuilder::new("fabricio").file("main.rs", "rust", "a".repeat(50_001))),
+            Invalid::Size
+        );
+        assert_eq!(
+            invalid(
+                GistitBuilder::new("fabricio")
+                    .file("main.rs", "rust", "a".repeat(50_000))
+                    .file("lib.rs", "rust", "a".repeat(50_000))
+                    .file("mod.rs", "rust", file())
+            ),
+            Invalid::TotalSize
+        );
+        assert_eq!(
+            invalid(
+                GistitBuilder::new("fabricio")
+                    .file("main.rs", "rust", file())
+                    .previous_hash("3fa9")
+            ),
+            Invalid::Hash
+        );
+        assert_eq!(
+            invalid(
+                GistitBuilder::new("fabricio")
+                    .file("main.rs", "rust", file())
+                    .snippet(Snippet {
+                        start_line: 10,
+                        end_line: 9,
+                        symbol: None,
+                    })
+            ),
+            Invalid::Snippet
+        );
+
+        let encryption = || Encryption {
+            scheme: "argon2id-chacha20poly1305".to_owned(),
+            nonce: "AAAAAAAAAAAAAAAA".to_owned(),
+            salt: "AAAAAAAAAAAAAAAAAAAAAA==".to_owned(),
+        };
+        let sealed = GistitBuilder::new("fabricio")
+            .binary_file("gistit.sealed", "binary", &[7; 64])
+            .encryption(encryption())
+            .build()
+            .unwrap();
+        assert!(sealed.is_encrypted() && sealed.validate().is_ok());
+        assert_eq!(
+            invalid(
+                GistitBuilder::new("fabricio")
+                    .file("main.rs", "rust", file())
+                    .encryption(encryption())
+            ),
+            Invalid::Encryption
+        );
+        assert_eq!(
+            invalid(
+                GistitBuilder::new("fabricio")
+                    .binary_file("gistit.sealed", "binary", &[7; 64])
+                    .encryption(Encryption {
+                        nonce: String::new(),
+                        ..encryption()
+                    })
+            ),
+            Invalid::Encryption
+        );
+
+        assert_eq!(
+            invalid(
+                GistitBuilder::new("fabricio")
+                    .file("main.rs", "rust", file())
+                    .public(true)
+                    .private(true)
+            ),
+            Invalid::Visibility
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Hash namespaces
+//!
+//! Hashes shown to users say where the gistit is hosted: `s:` for the server and `p:` for the
+//! peer network, so fetching one goes straight there. Bare hashes, and the `#` older versions
+//! marked server hashes with, are still understood. Namespaces never reach the payload, the
+//! server or the node, the hash of a gistit is the same wherever it's hosted.
+//!
+//! [`GistitHash`] is a full hash checked once where it enters, so code further in doesn't take
+//! prefixes, namespaced or short hashes for one. Protobuf messages keep hashes as strings, the
+//! wire format doesn't change, they are converted with [`TryFrom`] and [`Into`].
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use crate::validate::{self, Invalid};
+
+/// Marks server hosted hashes
+pub const SERVER_PREFIX: &str = "s:";
+
+/// Marks hashes hosted by the peer network
+pub const PEER_PREFIX: &str = "p:";
+
+/// How server hashes used to be marked
+const LEGACY_SERVER_PREFIX: &str = "#";
+
+/// Characters of a hash shown where the full one doesn't fit, like git short SHAs
+pub const SHORT_LENGTH: usize = 8;
+
+/// Where a gistit is hosted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "lowercase"))]
+pub enum Namespace {
+    Server,
+    Peer,
+}
+
+impl Namespace {
+    #[must_use]
+    pub const fn prefix(self) -> &'static str {
+        match self {
+            Self::Server => SERVER_PREFIX,
+            Self::Peer => PEER_PREFIX,
+        }
+    }
+
+    /// `hash` as shown to users
+    #[must_use]
+    pub fn tag(self, hash: &str) -> String {
+        format!("{}{}", self.prefix(), hash)
+    }
+}
+
+impl std::fmt::Display for Namespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Server => write!(f, "server"),
+            Self::Peer => write!(f, "peer network"),
+        }
+    }
+}
+
+/// Splits the namespace off a hash, or a prefix of one. Bare hashes have none, they may be
+/// hosted anywhere
+#[must_use]
+pub fn split(hash: &str) -> (Option<Namespace>, &str) {
+    let server = hash
+        .strip_prefix(SERVER_PREFIX)
+        .or_else(|| hash.strip_prefix(LEGACY_SERVER_PREFIX));
+    match (server, hash.strip_prefix(PEER_PREFIX)) {
+        (Some(bare), _) => (Some(Namespace::Server), bare),
+        (None, Some(bare)) => (Some(Namespace::Peer), bare),
+        (None, None) => (None, hash),
+    }
+}
+
+/// A full gistit hash, lowercase and without namespace
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct GistitHash(String);
+
+impl GistitHash {
+    /// Reads a full hash, namespaced or not
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Invalid::Hash`] if it isn't 64 hexadecimal characters, prefixes included
+    pub fn parse(hash: &str) -> Result<Self, Invalid> {
+        let (_, bare) = split(hash.trim());
+        validate::hash(bare)?;
+        Ok(Self(bare.to_ascii_lowercase()))
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    #[must_use]
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    /// The first [`SHORT_LENGTH`] characters, only to be shown
+    #[must_use]
+    pub fn short(&self) -> &str {
+        &self.0[..SHORT_LENGTH]
+    }
+
+    /// As shown to users, see [`Namespace::tag`]
+    #[must_use]
+    pub fn tagged(&self, namespace: Namespace) -> String {
+        namespace.tag(&self.0)
+    }
+}
+
+impl fmt::Display for GistitHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for GistitHash {
+    type Err = Invalid;
+
+    fn from_str(hash: &str) -> Result<Self, Self::Err> {
+        Self::parse(hash)
+    }
+}
+
+impl TryFrom<String> for GistitHash {
+    type Error = Invalid;
+
+    fn try_from(hash: String) -> Result<Self, Self::Error> {
+        Self::parse(&hash)
+    }
+}
+
+impl From<GistitHash> for String {
+    fn from(hash: GistitHash) -> Self {
+        hash.0
+    }
+}
+
+impl Deref for GistitHash {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for GistitHash {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for GistitHash {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for GistitHash {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for GistitHash {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for GistitHash {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_namespace_round_trip() {
+        let hash = "3fa9c2".repeat(10);
+
+        for namespace in [Namespace::Server, Namespace::Peer] {
+            let tagged = namespace.tag(&hash);
+            assert_eq!(split(&tagged), (Some(namespace), hash.as_str()));
+        }
+        assert_eq!(split(&hash), (None, hash.as_str()));
+        assert_eq!(split("#3fa9c2"), (Some(Namespace::Server), "3fa9c2"));
+        assert_eq!(split("x:3fa9c2"), (None, "x:3fa9c2"));
+    }
+
+    #[test]
+    fn hash_parse_checks_full_hashes() {
+        let hash = "3FA9C2".repeat(10) + "abcd";
+        let parsed = GistitHash::parse(&hash).unwrap();
+        assert_eq!(parsed, hash.to_ascii_lowercase());
+        assert_eq!(parsed.short(), "3fa9c23f");
+        assert_eq!(parsed.tagged(Namespace::Peer), format!("p:{}", parsed));
+
+        assert_eq!(
+            GistitHash::parse(&Namespace::Server.tag(&hash)),
+            Ok(parsed.clone())
+        );
+        assert_eq!(hash.parse::<GistitHash>(), Ok(parsed.clone()));
+        assert_eq!(GistitHash::try_from(hash.clone()), Ok(parsed.clone()));
+        assert_eq!(String::from(parsed), hash.to_ascii_lowercase());
+
+        assert_eq!(GistitHash::parse("3fa9c2"), Err(Invalid::Hash));
+        assert_eq!(GistitHash::parse(&"g".repeat(64)), Err(Invalid::Hash));
+        assert_eq!(
+            GistitHash::parse(&format!("x:{}", hash)),
+            Err(Invalid::Hash)
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn hash_serde_checks_full_hashes() {
+        let hash = GistitHash::parse(&"a".repeat(64)).unwrap();
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", "a".repeat(64)));
+        assert_eq!(serde_json::from_str::<GistitHash>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<GistitHash>("\"3fa9\"").is_err());
+    }
+}
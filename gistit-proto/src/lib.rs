@@ -23,8 +23,16 @@
 pub use bytes;
 pub use prost;
 
-pub use ipc::Instruction;
-pub use payload::{gistit::Inner, Gistit};
+pub use builder::GistitBuilder;
+pub use hash::GistitHash;
+pub use ipc::{Instruction, Negotiated};
+pub use payload::{gistit::Inner, Annotation, Annotations, Gistit, Visibility};
+pub use wire::WireFormat;
+
+pub mod builder;
+pub mod hash;
+pub mod validate;
+pub mod wire;
 
 pub mod payload {
     use super::prost::Message;
@@ -42,6 +50,61 @@ pub mod payload {
         format!("{:x}", hasher.finalize())
     }
 
+    /// Hash of a single inner file content
+    pub fn inner_hash(data: impl AsRef<[u8]>) -> String {
+        format!("{:x}", Sha256::digest(data.as_ref()))
+    }
+
+    /// Sums file sizes per language, largest first. Description files aren't counted
+    #[must_use]
+    pub fn languages(inner: &[gistit::Inner]) -> Vec<gistit::Language> {
+        let mut languages: Vec<gistit::Language> = Vec::new();
+        for file in inner.iter().filter(|file| !file.is_description) {
+            match languages
+                .iter_mut()
+                .find(|language| language.lang == file.lang)
+            {
+                Some(language) => language.bytes += file.size,
+                None => languages.push(gistit::Language {
+                    lang: file.lang.clone(),
+                    bytes: file.size,
+                }),
+            }
+        }
+        languages.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.lang.cmp(&b.lang)));
+        languages
+    }
+
+    /// Who can find a gistit
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Visibility {
+        /// Fetched by hash or a prefix of it, the default
+        Unlisted,
+        /// Also listed in the public index, see [`Gistit::public`]
+        Public,
+        /// Only fetched by its full hash, see [`Gistit::private`]
+        Private,
+    }
+
+    impl std::fmt::Display for Visibility {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Unlisted => write!(f, "unlisted"),
+                Self::Public => write!(f, "public"),
+                Self::Private => write!(f, "private"),
+            }
+        }
+    }
+
+    impl gistit::Inner {
+        /// Checks that the content matches the inner hash.
+        /// Files sent before per-file hashing have no hash and are taken as is
+        #[must_use]
+        pub fn is_intact(&self) -> bool {
+            self.hash.is_empty() || inner_hash(&self.data) == self.hash
+        }
+    }
+
     impl Gistit {
         #[must_use]
         pub fn new(
@@ -56,23 +119,102 @@ pub mod payload {
                 author,
                 description,
                 timestamp,
+                languages: languages(&inner),
                 inner,
+                public: false,
+                private: false,
+                burn_after_read: false,
+                encryption: None,
+                previous_hash: String::new(),
             }
         }
 
         #[must_use]
-        pub const fn new_inner(
-            name: String,
-            lang: String,
-            size: u32,
-            data: String,
-        ) -> gistit::Inner {
+        pub fn new_inner(name: String, lang: String, size: u32, data: String) -> gistit::Inner {
             gistit::Inner {
                 name,
                 lang,
                 size,
+                hash: inner_hash(&data),
                 data,
+                is_binary: false,
+                is_description: false,
+                snippet: None,
+            }
+        }
+
+        /// Recomputes the hash of this gistit from its author, description, inner data, whether
+        /// it's private and the gistit it amends. Hashes of gistits that are neither are the same
+        /// as before `private` and `previous_hash` existed
+        #[must_use]
+        pub fn checksum(&self) -> String {
+            let mut hasher = Sha256::new();
+            for inner in &self.inner {
+                hasher.update(&inner.data);
+            }
+            hasher.update(&self.author);
+            hasher.update(self.description.as_deref().unwrap_or(""));
+            // Otherwise a host could make it resolvable and listed without breaking the hash
+            if self.private {
+                hasher.update(b"private");
+            }
+            // Or a host could graft it onto the history of any other gistit
+            if let Some(previous_hash) = self.previous_hash() {
+                hasher.update(b"previous");
+                hasher.update(previous_hash);
+            }
+
+            format!("{:x}", hasher.finalize())
+        }
+
+        /// Checks that the content matches the advertised hash, and every file its own hash
+        #[must_use]
+        pub fn is_intact(&self) -> bool {
+            self.checksum() == self.hash && self.inner.iter().all(gistit::Inner::is_intact)
+        }
+
+        /// Whether its files are sealed, see [`gistit::Encryption`]
+        #[must_use]
+        pub const fn is_encrypted(&self) -> bool {
+            self.encryption.is_some()
+        }
+
+        /// Private wins over public, such a gistit doesn't validate anyway
+        #[must_use]
+        pub const fn visibility(&self) -> Visibility {
+            if self.private {
+                Visibility::Private
+            } else if self.public {
+                Visibility::Public
+            } else {
+                Visibility::Unlisted
+            }
+        }
+
+        /// Hash of the gistit this one amends, if any
+        #[must_use]
+        pub fn previous_hash(&self) -> Option<&str> {
+            Some(self.previous_hash.as_str()).filter(|hash| !hash.is_empty())
+        }
+
+        /// Language composition, computed from the files if the payload doesn't carry it
+        #[must_use]
+        pub fn language_stats(&self) -> Vec<gistit::Language> {
+            if self.languages.is_empty() {
+                languages(&self.inner)
+            } else {
+                self.languages.clone()
+            }
+        }
+
+        /// A copy without file contents, sizes and hashes are kept
+        #[must_use]
+        pub fn metadata(&self) -> Self {
+            let mut metadata = self.clone();
+            for inner in &mut metadata.inner {
+                inner.data.clear();
             }
+            metadata
         }
 
         /// Decodes a buffer into [`Self`]
@@ -87,11 +229,158 @@ pub mod payload {
 }
 
 pub mod ipc {
-    use super::Gistit;
     use super::{Error, Result};
+    use super::{Gistit, GistitHash};
 
     include!(concat!(env!("OUT_DIR"), "/gistit.ipc.rs"));
 
+    /// Version of the instruction protocol spoken by this build.
+    /// Bump when changing the meaning of existing instructions
+    pub const PROTOCOL_VERSION: u32 = 1;
+
+    /// Optional features a peer may advertise during the handshake
+    pub mod capability {
+        /// Fetched gistits are checked against their hash before being handed over
+        pub const CHECKSUM: &str = "checksum";
+
+        /// Status responses include connected bootstrap peers
+        pub const BOOTSTRAP_STATUS: &str = "bootstrap-status";
+
+        /// Gistits can be pushed straight to a peer and listed from the inbox
+        pub const DIRECT_SEND: &str = "direct-send";
+
+        /// Peers can be allowed or denied
+        pub const TRUST: &str = "trust";
+
+        /// Gistits can be fetched from a given peer without looking up providers
+        pub const FETCH_FROM_PEER: &str = "fetch-from-peer";
+
+        /// Answers health checks with its readiness
+        pub const PING: &str = "ping";
+
+        /// Answers whether a gistit exists with its metadata, without fetching it
+        pub const METADATA: &str = "metadata";
+
+        /// Provides several gistits with a single instruction
+        pub const PROVIDE_MANY: &str = "provide-many";
+
+        /// Failures are answered with an `ErrorResponse` saying why
+        pub const ERROR_RESPONSE: &str = "error-response";
+
+        /// Asks trusted peers to provide a gistit too
+        pub const REPLICATION: &str = "replication";
+
+        /// Keeps a list of peers to dial again, editable from the client
+        pub const PERSISTENT_PEERS: &str = "persistent-peers";
+
+        /// Logs gistits served to other peers, queryable from the client
+        pub const AUDIT: &str = "audit";
+
+        /// Resolves hash prefixes among the gistits it has
+        pub const RESOLVE: &str = "resolve";
+
+        /// Keeps the encryption metadata of encrypted gistits, older builds drop it
+        pub const ENCRYPTION: &str = "encryption";
+
+        /// Lists the gistits it provides, to move them along with the node identity
+        pub const HOSTED: &str = "hosted";
+
+        /// Reads its settings again while running
+        pub const RELOAD_CONFIG: &str = "reload-config";
+    }
+
+    /// Capabilities supported by this build
+    pub const CAPABILITIES: [&str; 16] = [
+        capability::CHECKSUM,
+        capability::BOOTSTRAP_STATUS,
+        capability::DIRECT_SEND,
+        capability::TRUST,
+        capability::FETCH_FROM_PEER,
+        capability::PING,
+        capability::METADATA,
+        capability::PROVIDE_MANY,
+        capability::ERROR_RESPONSE,
+        capability::REPLICATION,
+        capability::PERSISTENT_PEERS,
+        capability::AUDIT,
+        capability::RESOLVE,
+        capability::ENCRYPTION,
+        capability::HOSTED,
+        capability::RELOAD_CONFIG,
+    ];
+
+    /// What both ends of the bridge agreed on
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Negotiated {
+        pub version: u32,
+        pub capabilities: Vec<String>,
+    }
+
+    impl Negotiated {
+        #[must_use]
+        pub fn supports(&self, capability: &str) -> bool {
+            self.capabilities.iter().any(|c| c == capability)
+        }
+
+        /// The other end predates protocol negotiation
+        #[must_use]
+        pub const fn is_legacy(&self) -> bool {
+            self.version == 0
+        }
+
+        /// The other end speaks an older protocol than ours
+        #[must_use]
+        pub const fn is_outdated(&self) -> bool {
+            self.version < PROTOCOL_VERSION
+        }
+    }
+
+    /// Splits `messages`, gistits or inbox entries, in runs that fit an instruction of at most
+    /// `max_size` bytes encoded, in order. A message too big on its own is alone in its run
+    #[must_use]
+    pub fn split_by_size<M: prost::Message>(messages: Vec<M>, max_size: usize) -> Vec<Vec<M>> {
+        // The instruction and the message holding the runs, generously
+        const ENVELOPE: usize = 16;
+
+        let mut runs = Vec::new();
+        let mut run = Vec::new();
+        let mut size = ENVELOPE;
+        for message in messages {
+            let len = message.encoded_len();
+            // Tag, length and the message itself
+            let field = 1 + prost::encoding::encoded_len_varint(len as u64) + len;
+            if !run.is_empty() && size + field > max_size {
+                runs.push(std::mem::take(&mut run));
+                size = ENVELOPE;
+            }
+            size += field;
+            run.push(message);
+        }
+        if !run.is_empty() {
+            runs.push(run);
+        }
+        runs
+    }
+
+    impl instruction::PongResponse {
+        /// Listening and bootstrapped, ready to provide and fetch
+        #[must_use]
+        pub const fn is_ready(&self) -> bool {
+            self.listening && self.bootstrapped
+        }
+    }
+
+    impl instruction::Hello {
+        /// Version and capabilities of this build
+        #[must_use]
+        pub fn current() -> Self {
+            Self {
+                version: PROTOCOL_VERSION,
+                capabilities: CAPABILITIES.iter().map(ToString::to_string).collect(),
+            }
+        }
+    }
+
     impl Instruction {
         #[must_use]
         pub const fn request_status() -> Self {
@@ -99,35 +388,74 @@ pub mod ipc {
                 kind: Some(instruction::Kind::StatusRequest(
                     instruction::StatusRequest {},
                 )),
+                hello: None,
             }
         }
 
         #[must_use]
-        pub const fn request_fetch(hash: String) -> Self {
+        pub fn request_fetch(hash: GistitHash) -> Self {
             Self {
                 kind: Some(instruction::Kind::FetchRequest(instruction::FetchRequest {
-                    hash,
+                    hash: hash.into(),
+                    timeout: None,
                 })),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub fn request_fetch_from_peer(hash: GistitHash, peer_id: String) -> Self {
+            Self {
+                kind: Some(instruction::Kind::FetchFromPeerRequest(
+                    instruction::FetchFromPeerRequest {
+                        hash: hash.into(),
+                        peer_id,
+                    },
+                )),
+                hello: None,
             }
         }
 
         #[must_use]
-        pub const fn request_provide(gistit: Gistit) -> Self {
+        pub const fn request_provide(gistit: Gistit, replicas: u32) -> Self {
             Self {
                 kind: Some(instruction::Kind::ProvideRequest(
                     instruction::ProvideRequest {
                         gistit: Some(gistit),
+                        replicas,
                     },
                 )),
+                hello: None,
             }
         }
 
+        #[must_use]
+        pub const fn request_provide_many(gistits: Vec<Gistit>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::ProvideManyRequest(
+                    instruction::ProvideManyRequest { gistits },
+                )),
+                hello: None,
+            }
+        }
+
+        /// As many [`Self::request_provide_many`] as it takes for each to be at most `max_size`
+        /// bytes encoded, see [`split_by_size`]
+        #[must_use]
+        pub fn request_provide_many_batches(gistits: Vec<Gistit>, max_size: usize) -> Vec<Self> {
+            split_by_size(gistits, max_size)
+                .into_iter()
+                .map(Self::request_provide_many)
+                .collect()
+        }
+
         #[must_use]
         pub const fn request_shutdown() -> Self {
             Self {
                 kind: Some(instruction::Kind::ShutdownRequest(
                     instruction::ShutdownRequest {},
                 )),
+                hello: None,
             }
         }
 
@@ -137,25 +465,134 @@ pub mod ipc {
                 kind: Some(instruction::Kind::DialRequest(instruction::DialRequest {
                     address,
                 })),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn request_send_to_peer(peer_id: String, gistit: Gistit) -> Self {
+            Self {
+                kind: Some(instruction::Kind::SendToPeerRequest(
+                    instruction::SendToPeerRequest {
+                        peer_id,
+                        gistit: Some(gistit),
+                    },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn request_inbox(offset: u32) -> Self {
+            Self {
+                kind: Some(instruction::Kind::InboxRequest(instruction::InboxRequest {
+                    offset,
+                })),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn request_hosted(offset: u32) -> Self {
+            Self {
+                kind: Some(instruction::Kind::HostedRequest(
+                    instruction::HostedRequest { offset },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn request_reload_config() -> Self {
+            Self {
+                kind: Some(instruction::Kind::ReloadConfigRequest(
+                    instruction::ReloadConfigRequest {},
+                )),
+                hello: None,
             }
         }
 
         #[must_use]
-        pub const fn respond_status(
-            peer_id: String,
-            peer_count: u32,
-            pending_connections: u32,
-            hosting: u32,
+        pub const fn request_ping() -> Self {
+            Self {
+                kind: Some(instruction::Kind::PingRequest(instruction::PingRequest {})),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub fn request_metadata(hash: GistitHash) -> Self {
+            Self {
+                kind: Some(instruction::Kind::MetadataRequest(
+                    instruction::MetadataRequest {
+                        hash: hash.into(),
+                        timeout: None,
+                    },
+                )),
+                hello: None,
+            }
+        }
+
+        /// Without a `peer_id` the daemon only responds with the current lists
+        #[must_use]
+        pub const fn request_trust_peer(
+            peer_id: Option<String>,
+            trust: instruction::trust_peer_request::Trust,
         ) -> Self {
             Self {
-                kind: Some(instruction::Kind::StatusResponse(
-                    instruction::StatusResponse {
+                kind: Some(instruction::Kind::TrustPeerRequest(
+                    instruction::TrustPeerRequest {
                         peer_id,
-                        peer_count,
-                        pending_connections,
-                        hosting,
+                        trust: trust as i32,
                     },
                 )),
+                hello: None,
+            }
+        }
+
+        /// Without an `address` the daemon only responds with the current list
+        #[must_use]
+        pub const fn request_persistent_peer(
+            address: Option<String>,
+            change: instruction::persistent_peer_request::Change,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::PersistentPeerRequest(
+                    instruction::PersistentPeerRequest {
+                        address,
+                        change: change as i32,
+                    },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub fn request_audit(hash: Option<GistitHash>, peer_id: Option<String>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::AuditRequest(instruction::AuditRequest {
+                    hash: hash.map(Into::into),
+                    peer_id,
+                })),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn request_resolve(prefix: String) -> Self {
+            Self {
+                kind: Some(instruction::Kind::ResolveRequest(
+                    instruction::ResolveRequest { prefix },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_status(status: instruction::StatusResponse) -> Self {
+            Self {
+                kind: Some(instruction::Kind::StatusResponse(status)),
+                hello: None,
             }
         }
 
@@ -165,18 +602,251 @@ pub mod ipc {
                 kind: Some(instruction::Kind::FetchResponse(
                     instruction::FetchResponse { gistit },
                 )),
+                hello: None,
             }
         }
 
+        /// The gistit is provided, `status` says whether it was already
         #[must_use]
-        pub const fn respond_provide(maybe_hash: Option<String>) -> Self {
+        pub fn respond_provide(
+            status: instruction::provide_response::Status,
+            hash: GistitHash,
+        ) -> Self {
             Self {
                 kind: Some(instruction::Kind::ProvideResponse(
-                    instruction::ProvideResponse { hash: maybe_hash },
+                    instruction::ProvideResponse {
+                        hash: Some(hash.into()),
+                        status: status as i32,
+                        reason: None,
+                    },
                 )),
+                hello: None,
             }
         }
 
+        /// The gistit wasn't provided, with why
+        #[must_use]
+        pub const fn respond_provide_refused(
+            status: instruction::provide_response::Status,
+            reason: String,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::ProvideResponse(
+                    instruction::ProvideResponse {
+                        hash: None,
+                        status: status as i32,
+                        reason: Some(reason),
+                    },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_provide_many(results: Vec<instruction::ProvideResult>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::ProvideManyResponse(
+                    instruction::ProvideManyResponse { results },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_send_to_peer(delivered: bool, error: Option<String>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::SendToPeerResponse(
+                    instruction::SendToPeerResponse { delivered, error },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_inbox(
+            entries: Vec<instruction::InboxEntry>,
+            next: Option<u32>,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::InboxResponse(
+                    instruction::InboxResponse { entries, next },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_hosted(gistits: Vec<Gistit>, next: Option<u32>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::HostedResponse(
+                    instruction::HostedResponse { gistits, next },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_reload_config(error: Option<String>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::ReloadConfigResponse(
+                    instruction::ReloadConfigResponse { error },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_pong(
+            listening: bool,
+            bootstrapped: bool,
+            bootstrap_error: Option<String>,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::PongResponse(instruction::PongResponse {
+                    listening,
+                    bootstrapped,
+                    bootstrap_error,
+                })),
+                hello: None,
+            }
+        }
+
+        /// `gistit` is expected without file contents, see [`Gistit::metadata`]
+        #[must_use]
+        pub const fn respond_metadata(gistit: Option<Gistit>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::MetadataResponse(
+                    instruction::MetadataResponse { gistit },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_error(
+            code: instruction::error_response::Code,
+            message: Option<String>,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::ErrorResponse(
+                    instruction::ErrorResponse {
+                        code: code as i32,
+                        message,
+                    },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_trust_peer(
+            allowed: Vec<String>,
+            denied: Vec<String>,
+            error: Option<String>,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::TrustPeerResponse(
+                    instruction::TrustPeerResponse {
+                        allowed,
+                        denied,
+                        error,
+                    },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_persistent_peer(
+            peers: Vec<instruction::PersistentPeer>,
+            error: Option<String>,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::PersistentPeerResponse(
+                    instruction::PersistentPeerResponse { peers, error },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_audit(
+            entries: Vec<instruction::AuditEntry>,
+            total: u64,
+            error: Option<String>,
+        ) -> Self {
+            Self {
+                kind: Some(instruction::Kind::AuditResponse(
+                    instruction::AuditResponse {
+                        entries,
+                        total,
+                        error,
+                    },
+                )),
+                hello: None,
+            }
+        }
+
+        #[must_use]
+        pub const fn respond_resolve(hashes: Vec<String>) -> Self {
+            Self {
+                kind: Some(instruction::Kind::ResolveResponse(
+                    instruction::ResolveResponse { hashes },
+                )),
+                hello: None,
+            }
+        }
+
+        /// A status request carrying our version and capabilities.
+        /// Daemons predating negotiation answer it as a plain status request
+        #[must_use]
+        pub fn hello() -> Self {
+            Self::request_status().with_hello()
+        }
+
+        /// Attaches our version and capabilities to this instruction
+        #[must_use]
+        pub fn with_hello(self) -> Self {
+            Self {
+                hello: Some(instruction::Hello::current()),
+                ..self
+            }
+        }
+
+        /// Tells the daemon how long we wait on a fetch or metadata request, other instructions
+        /// are left as they are
+        #[must_use]
+        #[allow(clippy::cast_possible_truncation)]
+        pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+            let secs = Some(timeout.as_secs().min(u64::from(u32::MAX)) as u32);
+            match &mut self.kind {
+                Some(instruction::Kind::FetchRequest(request)) => request.timeout = secs,
+                Some(instruction::Kind::MetadataRequest(request)) => request.timeout = secs,
+                _ => (),
+            }
+            self
+        }
+
+        /// Negotiates a common version and capabilities set with the sender of this instruction
+        #[must_use]
+        pub fn negotiate(&self) -> Negotiated {
+            self.hello.as_ref().map_or(
+                Negotiated {
+                    version: 0,
+                    capabilities: Vec::new(),
+                },
+                |hello| Negotiated {
+                    version: hello.version.min(PROTOCOL_VERSION),
+                    capabilities: hello
+                        .capabilities
+                        .iter()
+                        .filter(|c| CAPABILITIES.contains(&c.as_str()))
+                        .cloned()
+                        .collect(),
+                },
+            )
+        }
+
         /// Unwraps [`Self`] expecting a request kind
         ///
         /// # Errors
@@ -190,12 +860,26 @@ pub mod ipc {
                         Some(
                             instruction::Kind::FetchResponse(_)
                             | instruction::Kind::ProvideResponse(_)
-                            | instruction::Kind::StatusResponse(_),
+                            | instruction::Kind::StatusResponse(_)
+                            | instruction::Kind::SendToPeerResponse(_)
+                            | instruction::Kind::InboxResponse(_)
+                            | instruction::Kind::TrustPeerResponse(_)
+                            | instruction::Kind::PongResponse(_)
+                            | instruction::Kind::MetadataResponse(_)
+                            | instruction::Kind::ProvideManyResponse(_)
+                            | instruction::Kind::ErrorResponse(_)
+                            | instruction::Kind::PersistentPeerResponse(_)
+                            | instruction::Kind::AuditResponse(_)
+                            | instruction::Kind::ResolveResponse(_)
+                            | instruction::Kind::HostedResponse(_)
+                            | instruction::Kind::ReloadConfigResponse(_),
                         )
                         | None,
+                    ..
                 } => Err(Error::Other("instruction is not a request")),
                 Self {
                     kind: Some(request),
+                    ..
                 } => Ok(request),
             }
         }
@@ -214,16 +898,71 @@ pub mod ipc {
                             instruction::Kind::FetchRequest(_)
                             | instruction::Kind::StatusRequest(_)
                             | instruction::Kind::ShutdownRequest(_)
-                            | instruction::Kind::ProvideRequest(_),
+                            | instruction::Kind::ProvideRequest(_)
+                            | instruction::Kind::SendToPeerRequest(_)
+                            | instruction::Kind::InboxRequest(_)
+                            | instruction::Kind::TrustPeerRequest(_)
+                            | instruction::Kind::FetchFromPeerRequest(_)
+                            | instruction::Kind::PingRequest(_)
+                            | instruction::Kind::MetadataRequest(_)
+                            | instruction::Kind::ProvideManyRequest(_)
+                            | instruction::Kind::PersistentPeerRequest(_)
+                            | instruction::Kind::AuditRequest(_)
+                            | instruction::Kind::ResolveRequest(_)
+                            | instruction::Kind::HostedRequest(_)
+                            | instruction::Kind::ReloadConfigRequest(_),
                         )
                         | None,
+                    ..
                 } => Err(Error::Other("instruction is not a response")),
                 Self {
                     kind: Some(response),
+                    ..
                 } => Ok(response),
             }
         }
     }
+
+    impl instruction::Kind {
+        /// Name of the instruction, for logs
+        #[must_use]
+        pub const fn name(&self) -> &'static str {
+            match self {
+                Self::ProvideRequest(_) => "ProvideRequest",
+                Self::FetchRequest(_) => "FetchRequest",
+                Self::StatusRequest(_) => "StatusRequest",
+                Self::ShutdownRequest(_) => "ShutdownRequest",
+                Self::DialRequest(_) => "DialRequest",
+                Self::ProvideResponse(_) => "ProvideResponse",
+                Self::FetchResponse(_) => "FetchResponse",
+                Self::StatusResponse(_) => "StatusResponse",
+                Self::SendToPeerRequest(_) => "SendToPeerRequest",
+                Self::InboxRequest(_) => "InboxRequest",
+                Self::SendToPeerResponse(_) => "SendToPeerResponse",
+                Self::InboxResponse(_) => "InboxResponse",
+                Self::TrustPeerRequest(_) => "TrustPeerRequest",
+                Self::TrustPeerResponse(_) => "TrustPeerResponse",
+                Self::FetchFromPeerRequest(_) => "FetchFromPeerRequest",
+                Self::PingRequest(_) => "PingRequest",
+                Self::PongResponse(_) => "PongResponse",
+                Self::MetadataRequest(_) => "MetadataRequest",
+                Self::MetadataResponse(_) => "MetadataResponse",
+                Self::ProvideManyRequest(_) => "ProvideManyRequest",
+                Self::ProvideManyResponse(_) => "ProvideManyResponse",
+                Self::ErrorResponse(_) => "ErrorResponse",
+                Self::PersistentPeerRequest(_) => "PersistentPeerRequest",
+                Self::PersistentPeerResponse(_) => "PersistentPeerResponse",
+                Self::AuditRequest(_) => "AuditRequest",
+                Self::AuditResponse(_) => "AuditResponse",
+                Self::ResolveRequest(_) => "ResolveRequest",
+                Self::ResolveResponse(_) => "ResolveResponse",
+                Self::HostedRequest(_) => "HostedRequest",
+                Self::HostedResponse(_) => "HostedResponse",
+                Self::ReloadConfigRequest(_) => "ReloadConfigRequest",
+                Self::ReloadConfigResponse(_) => "ReloadConfigResponse",
+            }
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -235,6 +974,13 @@ pub enum Error {
 
     #[error("other error {0}")]
     Other(&'static str),
+
+    #[error("invalid gistit, {0}")]
+    Invalid(#[from] validate::Invalid),
+
+    #[cfg(feature = "json")]
+    #[error("json error {0}")]
+    Json(#[from] serde_json::Error),
 }
 
 #[cfg(test)]
@@ -252,6 +998,71 @@ mod tests {
         assert_eq!(Gistit::decode(&*bytes).unwrap(), payload);
     }
 
+    #[test]
+    fn test_payload_checksum() {
+        let data = "fn main() {}";
+        let inner = Gistit::new_inner("main.rs".to_owned(), "rust".to_owned(), 12, data.to_owned());
+        let mut payload = Gistit::new(
+            payload::hash("Matthew McCaunaghey", Some("foo bar baz"), data),
+            "Matthew McCaunaghey".to_owned(),
+            Some("foo bar baz".to_owned()),
+            String::new(),
+            vec![inner],
+        );
+        assert!(payload.is_intact());
+
+        payload.inner[0].data.push_str("// tampered");
+        assert!(!payload.is_intact());
+    }
+
+    #[test]
+    fn test_payload_inner_hash() {
+        let mut inner = Gistit::new_inner(
+            "main.rs".to_owned(),
+            "rust".to_owned(),
+            12,
+            "fn main() {}".to_owned(),
+        );
+        assert_eq!(inner.hash, payload::inner_hash("fn main() {}"));
+        assert!(inner.is_intact());
+
+        inner.data.push_str("// tampered");
+        assert!(!inner.is_intact());
+
+        // Legacy files without a hash are accepted
+        inner.hash.clear();
+        assert!(inner.is_intact());
+    }
+
+    #[test]
+    fn test_payload_languages() {
+        let file = |name: &str, lang: &str, size| {
+            Gistit::new_inner(name.to_owned(), lang.to_owned(), size, String::new())
+        };
+        let mut payload = Gistit::new(
+            String::new(),
+            String::new(),
+            None,
+            String::new(),
+            vec![
+                file("Cargo.toml", "toml", 30),
+                file("main.rs", "rust", 50),
+                file("lib.rs", "rust", 20),
+            ],
+        );
+
+        let languages: Vec<_> = payload
+            .languages
+            .iter()
+            .map(|language| (language.lang.as_str(), language.bytes))
+            .collect();
+        assert_eq!(languages, vec![("rust", 70), ("toml", 30)]);
+
+        // Older payloads don't carry it
+        payload.languages.clear();
+        assert_eq!(payload.language_stats().len(), 2);
+    }
+
     #[test]
     fn test_ipc_encode_decode() {
         let instruction = Instruction::request_shutdown();
@@ -261,25 +1072,218 @@ mod tests {
 
     #[test]
     fn test_ipc_unwrap_methods() {
+        let hash = GistitHash::parse(&"a".repeat(64)).unwrap();
         let req1 = Instruction::request_shutdown().expect_request().unwrap();
-        let req2 = Instruction::request_provide(Gistit::default())
+        let req2 = Instruction::request_provide(Gistit::default(), 0)
             .expect_request()
             .unwrap();
         let req3 = Instruction::request_status().expect_request().unwrap();
-        let req4 = Instruction::request_fetch(String::new())
+        let req4 = Instruction::request_fetch(hash.clone())
+            .expect_request()
+            .unwrap();
+        let req5 = Instruction::request_send_to_peer(String::new(), Gistit::default())
+            .expect_request()
+            .unwrap();
+        let req6 = Instruction::request_fetch_from_peer(hash.clone(), String::new())
+            .expect_request()
+            .unwrap();
+        let req7 = Instruction::request_ping().expect_request().unwrap();
+        let req8 = Instruction::request_metadata(hash.clone())
+            .expect_request()
+            .unwrap();
+        let req9 = Instruction::request_provide_many(vec![Gistit::default()])
+            .expect_request()
+            .unwrap();
+        let req10 = Instruction::request_persistent_peer(
+            None,
+            ipc::instruction::persistent_peer_request::Change::Add,
+        )
+        .expect_request()
+        .unwrap();
+        let req11 = Instruction::request_audit(None, None)
+            .expect_request()
+            .unwrap();
+        let req12 = Instruction::request_resolve(String::new())
+            .expect_request()
+            .unwrap();
+        let req13 = Instruction::request_hosted(0).expect_request().unwrap();
+        let req14 = Instruction::request_reload_config()
             .expect_request()
             .unwrap();
 
         let res1 = Instruction::respond_fetch(Some(Gistit::default()))
             .expect_response()
             .unwrap();
-        let res2 = Instruction::respond_provide(None)
+        let res2 = Instruction::respond_provide(
+            ipc::instruction::provide_response::Status::Accepted,
+            hash,
+        )
+        .expect_response()
+        .unwrap();
+        let res3 = Instruction::respond_status(ipc::instruction::StatusResponse::default())
+            .expect_response()
+            .unwrap();
+        let res4 = Instruction::respond_send_to_peer(true, None)
+            .expect_response()
+            .unwrap();
+        let res5 = Instruction::respond_inbox(Vec::new(), None)
+            .expect_response()
+            .unwrap();
+
+        let res6 = Instruction::respond_trust_peer(Vec::new(), Vec::new(), None)
+            .expect_response()
+            .unwrap();
+        let res7 = Instruction::respond_pong(true, false, Some("no known peers".to_owned()))
+            .expect_response()
+            .unwrap();
+        assert!(matches!(
+            res7,
+            ipc::instruction::Kind::PongResponse(pong) if !pong.is_ready()
+        ));
+        let res8 = Instruction::respond_metadata(None)
             .expect_response()
             .unwrap();
-        let res3 = Instruction::respond_status(String::new(), 0, 0, 0)
+        let res9 = Instruction::respond_provide_many(Vec::new())
             .expect_response()
             .unwrap();
+        let res10 = Instruction::respond_error(
+            ipc::instruction::error_response::Code::NoProviders,
+            Some("timeout".to_owned()),
+        )
+        .expect_response()
+        .unwrap();
+        assert!(matches!(
+            res10,
+            ipc::instruction::Kind::ErrorResponse(err)
+                if err.code() == ipc::instruction::error_response::Code::NoProviders
+        ));
+
+        let res11 = Instruction::respond_persistent_peer(Vec::new(), None)
+            .expect_response()
+            .unwrap();
+        let res12 = Instruction::respond_audit(Vec::new(), 0, None)
+            .expect_response()
+            .unwrap();
+        let res13 = Instruction::respond_resolve(Vec::new())
+            .expect_response()
+            .unwrap();
+        let res14 = Instruction::respond_hosted(vec![Gistit::default()], None)
+            .expect_response()
+            .unwrap();
+        let res15 = Instruction::respond_reload_config(None)
+            .expect_response()
+            .unwrap();
+
+        assert!(Instruction::request_inbox(0).expect_response().is_err());
+        assert!(Instruction::respond_inbox(Vec::new(), None)
+            .expect_request()
+            .is_err());
 
         assert!(true);
     }
+
+    #[test]
+    fn test_ipc_negotiate() {
+        let legacy = Instruction::request_status().negotiate();
+        assert!(legacy.is_legacy());
+        assert!(!legacy.supports(ipc::capability::CHECKSUM));
+
+        let current = Instruction::hello().negotiate();
+        assert_eq!(current.version, ipc::PROTOCOL_VERSION);
+        assert!(current.supports(ipc::capability::CHECKSUM));
+
+        let mut newer = Instruction::hello();
+        newer.hello = Some(ipc::instruction::Hello {
+            version: ipc::PROTOCOL_VERSION + 1,
+            capabilities: vec!["from-the-future".to_owned(), "checksum".to_owned()],
+        });
+        let negotiated = newer.negotiate();
+        assert_eq!(negotiated.version, ipc::PROTOCOL_VERSION);
+        assert_eq!(negotiated.capabilities, vec!["checksum".to_owned()]);
+
+        let bytes = Instruction::hello().encode_to_vec();
+        assert_eq!(
+            Instruction::decode(&*bytes).unwrap().negotiate(),
+            Instruction::hello().negotiate()
+        );
+    }
+
+    #[test]
+    fn test_ipc_with_timeout() {
+        let hash = GistitHash::parse(&"a".repeat(64)).unwrap();
+        let wait = std::time::Duration::from_secs(5);
+        match Instruction::request_fetch(hash.clone())
+            .with_timeout(wait)
+            .kind
+        {
+            Some(ipc::instruction::Kind::FetchRequest(request)) => {
+                assert_eq!(request.timeout, Some(5));
+            }
+            _ => unreachable!(),
+        }
+        match Instruction::request_metadata(hash).with_timeout(wait).kind {
+            Some(ipc::instruction::Kind::MetadataRequest(request)) => {
+                assert_eq!(request.timeout, Some(5));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            Instruction::request_status().with_timeout(wait),
+            Instruction::request_status()
+        );
+    }
+
+    #[test]
+    fn test_ipc_provide_many_batches() {
+        let gistit = |n: usize| {
+            let data = n.to_string().repeat(20_000);
+            Gistit::new(
+                payload::hash("fabricio", None, &data),
+                "fabricio".to_owned(),
+                None,
+                String::new(),
+                vec![Gistit::new_inner(
+                    "main.rs".to_owned(),
+                    "rust".to_owned(),
+                    20_000,
+                    data,
+                )],
+            )
+        };
+        let gistits: Vec<Gistit> = (1..=7).map(gistit).collect();
+
+        let batches = Instruction::request_provide_many_batches(gistits.clone(), 60_000);
+        assert_eq!(batches.len(), 4);
+        let mut sent = Vec::new();
+        for batch in batches {
+            assert!(batch.encoded_len() <= 60_000);
+            match batch.kind {
+                Some(ipc::instruction::Kind::ProvideManyRequest(request)) => {
+                    sent.extend(request.gistits);
+                }
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(sent, gistits);
+
+        // Alone when too big, sending it fails instead
+        assert_eq!(ipc::split_by_size(gistits.clone(), 1000).len(), 7);
+        assert!(ipc::split_by_size(Vec::<Gistit>::new(), 1000).is_empty());
+
+        // Inbox entries are paged the same way
+        let entries: Vec<ipc::instruction::InboxEntry> = gistits
+            .iter()
+            .map(|gistit| ipc::instruction::InboxEntry {
+                peer_id: "peer".to_owned(),
+                gistit: Some(gistit.metadata()),
+                received_at: 0,
+            })
+            .collect();
+        let pages = ipc::split_by_size(entries.clone(), 200);
+        assert!(pages.len() > 1);
+        for page in &pages {
+            assert!(Instruction::respond_inbox(page.clone(), Some(0)).encoded_len() <= 200);
+        }
+        assert_eq!(pages.concat(), entries);
+    }
 }
@@ -0,0 +1,313 @@
+//! Rules a gistit payload follows
+//!
+//! The server refuses payloads breaking them, checking early gives a clear error instead. Used by
+//! [`crate::GistitBuilder`] and on their own to check user input.
+use std::ops::RangeInclusive;
+
+use crate::payload::gistit::Inner;
+use crate::payload::Annotation;
+use crate::Gistit;
+
+/// Allowed author length, in bytes
+pub const AUTHOR_LENGTH: RangeInclusive<usize> = 3..=30;
+
+/// Allowed description length, in bytes
+pub const DESCRIPTION_LENGTH: RangeInclusive<usize> = 10..=100;
+
+/// Allowed file size, in bytes. Binary files are measured before encoding
+pub const FILE_SIZE: RangeInclusive<usize> = 20..=50_000;
+
+/// Allowed size of all files of a gistit together, in bytes. Room for a file as large as it gets
+/// and its description file
+pub const GISTIT_SIZE: usize = 2 * *FILE_SIZE.end();
+
+/// Largest a valid gistit gets encoded: files of [`GISTIT_SIZE`] together, base64 encoded if
+/// they're binary, with room for the rest of the payload
+pub const ENCODED_SIZE: usize = (GISTIT_SIZE + 2) / 3 * 4 + 16 * 1024;
+
+/// Allowed annotation message length, in bytes
+pub const ANNOTATION_LENGTH: RangeInclusive<usize> = 1..=500;
+
+/// Hashes are hex encoded sha256 digests
+pub const HASH_LENGTH: usize = 64;
+
+/// Allowed length of a hash prefix, like git short SHAs
+pub const HASH_PREFIX_LENGTH: RangeInclusive<usize> = 4..=HASH_LENGTH;
+
+/// Which rule was broken
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalid {
+    #[error("author must be 3 to 30 characters long")]
+    Author,
+
+    #[error("description must be 10 to 100 characters long")]
+    Description,
+
+    #[error("files must be {} to {} bytes", FILE_SIZE.start(), FILE_SIZE.end())]
+    Size,
+
+    #[error("files must be {} bytes together at most", GISTIT_SIZE)]
+    TotalSize,
+
+    #[error("hash must be 64 hexadecimal characters")]
+    Hash,
+
+    #[error("hash prefix must be 4 to 64 hexadecimal characters")]
+    HashPrefix,
+
+    #[error("file name can't be empty")]
+    Name,
+
+    #[error("a gistit needs at least one file")]
+    NoFiles,
+
+    #[error("snippets start at line 1 and end after they start")]
+    Snippet,
+
+    #[error("encrypted gistits need a scheme, nonce, salt and a single binary file")]
+    Encryption,
+
+    #[error("annotations need a file, a line from 1 and a 1 to 500 characters long message")]
+    Annotation,
+
+    #[error("a gistit can't be both public and private")]
+    Visibility,
+}
+
+/// # Errors
+///
+/// Fails with [`Invalid::Author`]
+pub fn author(author: &str) -> Result<(), Invalid> {
+    if AUTHOR_LENGTH.contains(&author.len()) {
+        Ok(())
+    } else {
+        Err(Invalid::Author)
+    }
+}
+
+/// # Errors
+///
+/// Fails with [`Invalid::Description`]
+pub fn description(description: &str) -> Result<(), Invalid> {
+    if DESCRIPTION_LENGTH.contains(&description.len()) {
+        Ok(())
+    } else {
+        Err(Invalid::Description)
+    }
+}
+
+/// # Errors
+///
+/// Fails with [`Invalid::Size`]
+pub fn size(size: usize) -> Result<(), Invalid> {
+    if FILE_SIZE.contains(&size) {
+        Ok(())
+    } else {
+        Err(Invalid::Size)
+    }
+}
+
+/// # Errors
+///
+/// Fails with [`Invalid::Hash`]
+pub fn hash(hash: &str) -> Result<(), Invalid> {
+    if hash.len() == HASH_LENGTH && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(Invalid::Hash)
+    }
+}
+
+/// A full hash or the start of one
+///
+/// # Errors
+///
+/// Fails with [`Invalid::HashPrefix`]
+pub fn hash_prefix(prefix: &str) -> Result<(), Invalid> {
+    if HASH_PREFIX_LENGTH.contains(&prefix.len()) && prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(Invalid::HashPrefix)
+    }
+}
+
+/// Size of a file, binary ones are measured decoded
+fn file_size(inner: &Inner) -> usize {
+    if inner.is_binary {
+        inner.size as usize
+    } else {
+        inner.data.len()
+    }
+}
+
+/// # Errors
+///
+/// Fails with [`Invalid::TotalSize`] if the files are over [`GISTIT_SIZE`] together
+pub fn total_size(inner: &[Inner]) -> Result<(), Invalid> {
+    if inner.iter().map(file_size).sum::<usize>() <= GISTIT_SIZE {
+        Ok(())
+    } else {
+        Err(Invalid::TotalSize)
+    }
+}
+
+/// # Errors
+///
+/// Fails if the name is empty, the size is out of [`FILE_SIZE`] or the snippet lines are off
+pub fn file(inner: &Inner) -> Result<(), Invalid> {
+    if inner.name.is_empty() {
+        return Err(Invalid::Name);
+    }
+    if let Some(ref snippet) = inner.snippet {
+        if snippet.start_line == 0 || snippet.end_line < snippet.start_line {
+            return Err(Invalid::Snippet);
+        }
+    }
+    size(file_size(inner))
+}
+
+/// Encrypted gistits are opaque, only their shape is checked. Unknown schemes are fine, whoever
+/// fetches it may know them
+///
+/// # Errors
+///
+/// Fails with [`Invalid::Encryption`]
+pub fn encryption(gistit: &Gistit) -> Result<(), Invalid> {
+    match (&gistit.encryption, gistit.inner.as_slice()) {
+        (None, _) => Ok(()),
+        (Some(encryption), [sealed])
+            if !encryption.scheme.is_empty()
+                && !encryption.nonce.is_empty()
+                && !encryption.salt.is_empty()
+                && sealed.is_binary
+                && !sealed.is_description =>
+        {
+            Ok(())
+        }
+        _ => Err(Invalid::Encryption),
+    }
+}
+
+/// # Errors
+///
+/// Fails with [`Invalid::Visibility`] if the gistit is both public and private
+pub const fn visibility(gistit: &Gistit) -> Result<(), Invalid> {
+    if gistit.public && gistit.private {
+        return Err(Invalid::Visibility);
+    }
+    Ok(())
+}
+
+impl Annotation {
+    /// Checks the annotation follows the rules. Whether its file and line exist is up to the
+    /// gistit, which may not be at hand
+    ///
+    /// # Errors
+    ///
+    /// Fails with the first rule broken
+    pub fn validate(&self) -> Result<(), Invalid> {
+        hash(&self.hash)?;
+        author(&self.author)?;
+        if self.file.is_empty()
+            || self.line == 0
+            || !ANNOTATION_LENGTH.contains(&self.message.len())
+        {
+            return Err(Invalid::Annotation);
+        }
+        Ok(())
+    }
+}
+
+impl Gistit {
+    /// Checks the payload follows the rules, not that its contents match the hash, see
+    /// [`Self::is_intact`]. Files are expected with their contents
+    ///
+    /// # Errors
+    ///
+    /// Fails with the first rule broken
+    pub fn validate(&self) -> Result<(), Invalid> {
+        author(&self.author)?;
+        if let Some(ref value) = self.description {
+            description(value)?;
+        }
+        hash(&self.hash)?;
+        if let Some(previous_hash) = self.previous_hash() {
+            hash(previous_hash)?;
+        }
+        if self.inner.iter().all(|inner| inner.is_description) {
+            return Err(Invalid::NoFiles);
+        }
+        encryption(self)?;
+        visibility(self)?;
+        self.inner.iter().try_for_each(file)?;
+        total_size(&self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rules() {
+        assert_eq!(author("fa"), Err(Invalid::Author));
+        assert_eq!(author("fabricio"), Ok(()));
+        assert_eq!(description("short"), Err(Invalid::Description));
+        assert_eq!(description(&"a".repeat(101)), Err(Invalid::Description));
+        assert_eq!(size(19), Err(Invalid::Size));
+        assert_eq!(size(50_000), Ok(()));
+        assert_eq!(hash(&"a".repeat(64)), Ok(()));
+        assert_eq!(hash(&"g".repeat(64)), Err(Invalid::Hash));
+        assert_eq!(hash("abc"), Err(Invalid::Hash));
+        assert_eq!(hash_prefix("3fa9"), Ok(()));
+        assert_eq!(hash_prefix(&"a".repeat(64)), Ok(()));
+        assert_eq!(hash_prefix("3fa"), Err(Invalid::HashPrefix));
+        assert_eq!(hash_prefix("3fa9g"), Err(Invalid::HashPrefix));
+    }
+
+    #[test]
+    fn validate_encoded_size() {
+        use crate::prost::Message;
+        use crate::GistitBuilder;
+
+        let largest = GistitBuilder::new("a".repeat(*AUTHOR_LENGTH.end()))
+            .description(Some(&"a".repeat(*DESCRIPTION_LENGTH.end())))
+            .binary_file("a".repeat(255), "binary", &[0xff; *FILE_SIZE.end()])
+            .previous_hash("a".repeat(HASH_LENGTH))
+            .build()
+            .unwrap();
+        assert!(largest.encoded_len() <= ENCODED_SIZE);
+
+        let described = GistitBuilder::new("a".repeat(*AUTHOR_LENGTH.end()))
+            .description(Some(&"a".repeat(*DESCRIPTION_LENGTH.end())))
+            .binary_file("a".repeat(255), "binary", &[0xff; *FILE_SIZE.end()])
+            .description_file("a".repeat(255), "a".repeat(*FILE_SIZE.end()))
+            .previous_hash("a".repeat(HASH_LENGTH))
+            .build()
+            .unwrap();
+        assert!(described.encoded_len() <= ENCODED_SIZE);
+    }
+
+    #[test]
+    fn validate_annotation() {
+        let mut annotation = Annotation {
+            hash: "a".repeat(64),
+            file: "main.rs".to_owned(),
+            line: 42,
+            message: "this can panic".to_owned(),
+            author: "fabricio".to_owned(),
+            timestamp: "0".to_owned(),
+        };
+        assert_eq!(annotation.validate(), Ok(()));
+
+        annotation.line = 0;
+        assert_eq!(annotation.validate(), Err(Invalid::Annotation));
+        annotation.line = 1;
+        annotation.message = "a".repeat(501);
+        assert_eq!(annotation.validate(), Err(Invalid::Annotation));
+        annotation.message.clear();
+        assert_eq!(annotation.validate(), Err(Invalid::Annotation));
+        annotation.hash = "abc".to_owned();
+        assert_eq!(annotation.validate(), Err(Invalid::Hash));
+    }
+}
@@ -0,0 +1,178 @@
+//! Encodings of the payload over http
+//!
+//! The server and the daemon speak protobuf. With the `json` feature gistits can also be sent and
+//! fetched as JSON, for integrators that can't easily consume protobuf. Fields are in lower camel
+//! case like the protobuf JSON mapping, which is what the server's protobuf library produces.
+//! Annotations travel the same way.
+use std::str::FromStr;
+
+use prost::Message;
+
+use crate::{Annotation, Annotations, Error, Gistit, Result};
+
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// A message that goes over http, in either format
+#[cfg(feature = "json")]
+pub trait Payload: Message + Default + serde::Serialize + serde::de::DeserializeOwned {}
+
+/// A message that goes over http, in either format
+#[cfg(not(feature = "json"))]
+pub trait Payload: Message + Default {}
+
+impl Payload for Gistit {}
+impl Payload for Annotation {}
+impl Payload for Annotations {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    Proto,
+
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl WireFormat {
+    #[must_use]
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Proto => PROTOBUF_CONTENT_TYPE,
+            #[cfg(feature = "json")]
+            Self::Json => JSON_CONTENT_TYPE,
+        }
+    }
+
+    /// The format a `Content-Type` header names, parameters like `charset` aside
+    #[must_use]
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type.split(';').next().unwrap_or_default().trim() {
+            PROTOBUF_CONTENT_TYPE => Some(Self::Proto),
+            #[cfg(feature = "json")]
+            JSON_CONTENT_TYPE => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the gistit can't be serialized
+    pub fn encode(self, gistit: &Gistit) -> Result<Vec<u8>> {
+        self.encode_payload(gistit)
+    }
+
+    /// # Errors
+    ///
+    /// Fails if `bytes` aren't a gistit in this format
+    pub fn decode(self, bytes: &[u8]) -> Result<Gistit> {
+        self.decode_payload(bytes)
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the payload can't be serialized
+    pub fn encode_payload<T: Payload>(self, payload: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Proto => Ok(payload.encode_to_vec()),
+            #[cfg(feature = "json")]
+            Self::Json => Ok(serde_json::to_vec(payload)?),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Fails if `bytes` aren't a `T` in this format
+    pub fn decode_payload<T: Payload>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Self::Proto => Ok(T::decode(bytes)?),
+            #[cfg(feature = "json")]
+            Self::Json => Ok(serde_json::from_slice(bytes)?),
+        }
+    }
+}
+
+impl FromStr for WireFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "proto" => Ok(Self::Proto),
+            #[cfg(feature = "json")]
+            "json" => Ok(Self::Json),
+            _ => Err(Error::Other("unknown wire format")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GistitBuilder;
+
+    fn gistit() -> Gistit {
+        GistitBuilder::new("someone")
+            .description(Some("a wire format test"))
+            .file(
+                "main.rs",
+                "rust",
+                "fn main() { println!(\"wire\"); }".to_owned(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn wire_format_roundtrip() {
+        let gistit = gistit();
+        let bytes = WireFormat::Proto.encode(&gistit).unwrap();
+        assert_eq!(WireFormat::Proto.decode(&bytes).unwrap(), gistit);
+
+        let annotations = Annotations {
+            annotations: vec![Annotation {
+                hash: gistit.hash.clone(),
+                file: "main.rs".to_owned(),
+                line: 1,
+                message: "prints wire".to_owned(),
+                ..Annotation::default()
+            }],
+        };
+        let bytes = WireFormat::Proto.encode_payload(&annotations).unwrap();
+        assert_eq!(
+            WireFormat::Proto
+                .decode_payload::<Annotations>(&bytes)
+                .unwrap(),
+            annotations
+        );
+
+        assert_eq!(
+            WireFormat::from_content_type("application/x-protobuf"),
+            Some(WireFormat::Proto)
+        );
+        assert_eq!(WireFormat::from_content_type("text/html"), None);
+        assert!("xml".parse::<WireFormat>().is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn wire_format_json() {
+        let gistit = gistit();
+        let bytes = WireFormat::Json.encode(&gistit).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["inner"][0]["isBinary"], false);
+        assert_eq!(WireFormat::Json.decode(&bytes).unwrap(), gistit);
+
+        // Fields left out are defaults, like protobuf
+        let partial = WireFormat::Json
+            .decode(br#"{"hash": "abc", "burnAfterRead": true}"#)
+            .unwrap();
+        assert_eq!(partial.hash, "abc");
+        assert!(partial.burn_after_read);
+        assert!(partial.inner.is_empty());
+
+        assert_eq!(
+            WireFormat::from_content_type("application/json; charset=utf-8"),
+            Some(WireFormat::Json)
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//
+//   ________.__          __  .__  __
+//  /  _____/|__| _______/  |_|__|/  |_
+// /   \  ___|  |/  ___/\   __\  \   __\
+// \    \_\  \  |\___ \  |  | |  ||  |
+//  \______  /__/____  > |__| |__||__|
+//         \/        \/
+//
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![allow(clippy::module_name_repetitions)]
+//! Ephemeral gistit nodes for end to end tests
+//!
+//! [`Network::spawn`] starts nodes inside the test process, each on its own thread, listening on
+//! a random localhost port and living in temporary directories. Every node bootstraps from the
+//! first one. Tests then drive them through the ipc bridge, like `gistit-cli` does:
+//!
+//! ```no_run
+//! # async fn run() -> gistit_testkit::Result<()> {
+//! let mut network = gistit_testkit::Network::spawn(2).await?;
+//! let gistit = gistit_testkit::gistit("fn main() { println!(\"hello\"); }");
+//!
+//! let hash = network.nodes[0].provide(gistit).await?;
+//! let fetched = network.nodes[1].fetch(&hash).await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+use tokio::sync::oneshot;
+
+use gistit_daemon::{Config, Limits, Node, Overrides, Queries};
+use gistit_ipc::{Bridge, Client};
+use gistit_proto::ipc::instruction::{Kind, PongResponse, StatusResponse};
+use gistit_proto::{Gistit, GistitBuilder, GistitHash, Instruction};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How long a request may take, fetches included
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a node may take to listen and bootstrap
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Nodes started together, stopped and cleaned up when dropped
+#[derive(Debug)]
+pub struct Network {
+    pub nodes: Vec<TestNode>,
+    /// Declared last, so it's removed once every node stopped
+    _root: TempDir,
+}
+
+impl Network {
+    /// Starts `count` nodes and waits for all of them to bootstrap
+    ///
+    /// # Errors
+    ///
+    /// Fails if a node doesn't start, listen or bootstrap in time
+    pub async fn spawn(count: usize) -> Result<Self> {
+        let root = tempfile::tempdir()?;
+        let mut nodes: Vec<TestNode> = Vec::with_capacity(count);
+        for index in 0..count {
+            let bootstrap_peers = nodes
+                .first()
+                .map(|first| vec![first.address.clone()])
+                .unwrap_or_default();
+            let dir = root.path().join(format!("node-{}", index));
+            nodes.push(TestNode::spawn(&dir, bootstrap_peers).await?);
+        }
+        for node in &mut nodes {
+            node.wait_for(|pong| pong.bootstrapped).await?;
+        }
+
+        Ok(Self { nodes, _root: root })
+    }
+}
+
+/// A node running on its own thread and the ipc bridge to it
+#[derive(Debug)]
+pub struct TestNode {
+    pub peer_id: String,
+    /// `/p2p/<peer id>` suffixed listen address
+    pub address: String,
+    bridge: Bridge<Client>,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestNode {
+    async fn spawn(dir: &Path, bootstrap_peers: Vec<String>) -> Result<Self> {
+        let runtime_path = dir.join("run");
+        let config_path = dir.join("config");
+        let data_path = dir.join("data");
+        fs::create_dir_all(&runtime_path)?;
+        fs::create_dir_all(&config_path)?;
+        fs::create_dir_all(&data_path)?;
+
+        // Every directory is given, so nodes stay out of the user's ones and out of each other's
+        let config = Config::from_args(
+            Some(runtime_path.clone()),
+            Some(config_path),
+            Some(data_path),
+            None,
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            Some(0),
+            false,
+            Vec::new(),
+            false,
+            bootstrap_peers,
+            Limits::default(),
+            Overrides::default(),
+            Queries::default(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+        )?;
+
+        let (ready, started) = oneshot::channel();
+        let (stop, stopped) = oneshot::channel();
+        // A runtime of its own, like the daemon has, so the node keeps going while the test
+        // blocks
+        let thread = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("runtime to build");
+            runtime.block_on(async move {
+                let mut node = match Node::new(config).await {
+                    Ok(node) => {
+                        let _ = ready.send(Ok(()));
+                        node
+                    }
+                    Err(err) => {
+                        let _ = ready.send(Err(err.to_string()));
+                        return;
+                    }
+                };
+                tokio::select! {
+                    result = node.run() => {
+                        if let Err(err) = result {
+                            log::error!("Test node stopped: {}", err);
+                        }
+                    }
+                    _ = stopped => (),
+                }
+            });
+        });
+        started
+            .await
+            .map_err(|_| Error::Start("node thread panicked".to_owned()))?
+            .map_err(Error::Start)?;
+
+        let mut node = Self {
+            peer_id: String::new(),
+            address: String::new(),
+            bridge: gistit_ipc::client(&runtime_path)?,
+            stop: Some(stop),
+            thread: Some(thread),
+        };
+        node.wait_for(|pong| pong.listening).await?;
+
+        let status = node.status().await?;
+        let listener = status
+            .listeners
+            .first()
+            .ok_or_else(|| Error::Unexpected("node has no listeners".to_owned()))?;
+        node.address = format!("{}/p2p/{}", listener, status.peer_id);
+        node.peer_id = status.peer_id;
+        Ok(node)
+    }
+
+    /// Sends `instruction` and waits for the response, up to [`REQUEST_TIMEOUT`]
+    ///
+    /// # Errors
+    ///
+    /// Fails if the node doesn't answer in time or answers with something else than a response
+    pub async fn request(&mut self, instruction: Instruction) -> Result<Kind> {
+        self.bridge.connect().await?;
+        self.bridge.send(instruction).await?;
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, self.bridge.recv())
+            .await
+            .map_err(|_| Error::Timeout)??;
+        Ok(response.expect_response()?)
+    }
+
+    /// Pings the node until `ready` holds for its answer, up to a few seconds
+    async fn wait_for(&mut self, ready: impl Fn(&PongResponse) -> bool) -> Result<()> {
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            match self.request(Instruction::request_ping()).await? {
+                Kind::PongResponse(pong) if ready(&pong) => return Ok(()),
+                Kind::PongResponse(_) => (),
+                other => return Err(unexpected(&other)),
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the node doesn't answer with its status
+    pub async fn status(&mut self) -> Result<StatusResponse> {
+        match self.request(Instruction::request_status()).await? {
+            Kind::StatusResponse(status) => Ok(status),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    /// Provides `gistit` and returns its hash
+    ///
+    /// # Errors
+    ///
+    /// Fails if the node refuses or fails to provide it
+    pub async fn provide(&mut self, gistit: Gistit) -> Result<GistitHash> {
+        match self
+            .request(Instruction::request_provide(gistit, 0))
+            .await?
+        {
+            Kind::ProvideResponse(response) => {
+                let hash = response.hash.ok_or_else(|| {
+                    Error::Refused(
+                        response
+                            .reason
+                            .unwrap_or_else(|| "failed to provide".to_owned()),
+                    )
+                })?;
+                Ok(GistitHash::try_from(hash).map_err(gistit_proto::Error::from)?)
+            }
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    /// Fetches a gistit by its hash, from whichever node provides it
+    ///
+    /// # Errors
+    ///
+    /// Fails if nobody provides it or the node refuses the request
+    pub async fn fetch(&mut self, hash: &GistitHash) -> Result<Gistit> {
+        match self
+            .request(Instruction::request_fetch(hash.clone()))
+            .await?
+        {
+            Kind::FetchResponse(response) => response
+                .gistit
+                .ok_or_else(|| Error::Refused("not found".to_owned())),
+            other => Err(unexpected(&other)),
+        }
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// An error response is a refusal, anything else is unexpected
+fn unexpected(response: &Kind) -> Error {
+    match response {
+        Kind::ErrorResponse(error) => Error::Refused(format!(
+            "{:?} {}",
+            error.code(),
+            error.message.as_deref().unwrap_or_default()
+        )),
+        other => Error::Unexpected(format!("{:?}", other)),
+    }
+}
+
+/// A valid gistit holding `contents` in a single rust file. Contents must be at least 20 bytes
+///
+/// # Panics
+///
+/// If `contents` break the payload rules, see [`gistit_proto::validate`]
+#[must_use]
+pub fn gistit(contents: &str) -> Gistit {
+    GistitBuilder::new("testkit")
+        .file("main.rs", "rust", contents.to_owned())
+        .build()
+        .expect("test gistit to be valid")
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io error {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("node error {0}")]
+    Daemon(#[from] gistit_daemon::Error),
+
+    #[error("node failed to start, {0}")]
+    Start(String),
+
+    #[error("ipc error {0}")]
+    Ipc(#[from] gistit_ipc::Error),
+
+    #[error("protocol error {0}")]
+    Proto(#[from] gistit_proto::Error),
+
+    #[error("node didn't answer in time")]
+    Timeout,
+
+    #[error("request refused, {0}")]
+    Refused(String),
+
+    #[error("unexpected response {0}")]
+    Unexpected(String),
+}
@@ -0,0 +1,64 @@
+use gistit_proto::ipc::instruction::error_response::Code;
+use gistit_proto::ipc::instruction::Kind;
+use gistit_proto::payload::gistit::Encryption;
+use gistit_proto::{GistitBuilder, Instruction};
+use gistit_testkit::{gistit, Network};
+
+#[tokio::test]
+async fn nodes_bootstrap_from_the_first() {
+    let mut network = Network::spawn(3).await.unwrap();
+
+    let status = network.nodes[0].status().await.unwrap();
+    assert_eq!(status.peer_id, network.nodes[0].peer_id);
+    assert_eq!(status.peer_count, 2);
+    for node in &mut network.nodes[1..] {
+        assert!(node.status().await.unwrap().peer_count >= 1);
+    }
+}
+
+#[tokio::test]
+async fn provide_then_fetch_from_another_node() {
+    let mut network = Network::spawn(3).await.unwrap();
+    let gistit = gistit("fn main() {\n    println!(\"hello from the testkit\");\n}\n");
+
+    let hash = network.nodes[1].provide(gistit.clone()).await.unwrap();
+    assert_eq!(hash, gistit.hash);
+
+    let fetched = network.nodes[2].fetch(&hash).await.unwrap();
+    assert_eq!(fetched.inner, gistit.inner);
+    assert_eq!(fetched.author, gistit.author);
+}
+
+#[tokio::test]
+async fn encrypted_gistits_pass_through_untouched() {
+    let mut network = Network::spawn(2).await.unwrap();
+    let sealed = GistitBuilder::new("testkit")
+        .binary_file("gistit.sealed", "binary", &[0x5a; 96])
+        .encryption(Encryption {
+            scheme: "argon2id-chacha20poly1305".to_owned(),
+            nonce: "AAAAAAAAAAAAAAAA".to_owned(),
+            salt: "AAAAAAAAAAAAAAAAAAAAAA==".to_owned(),
+        })
+        .build()
+        .unwrap();
+
+    let hash = network.nodes[0].provide(sealed.clone()).await.unwrap();
+    let fetched = network.nodes[1].fetch(&hash).await.unwrap();
+    assert_eq!(fetched.inner, sealed.inner);
+    assert_eq!(fetched.encryption, sealed.encryption);
+}
+
+#[tokio::test]
+async fn unknown_instructions_are_refused() {
+    let mut network = Network::spawn(1).await.unwrap();
+    // What an instruction added after this build decodes to
+    let unknown = Instruction {
+        kind: None,
+        hello: None,
+    };
+
+    match network.nodes[0].request(unknown).await.unwrap() {
+        Kind::ErrorResponse(err) => assert_eq!(err.code(), Code::Unsupported),
+        other => panic!("unexpected response {:?}", other),
+    }
+}
@@ -0,0 +1,39 @@
+import * as assert from "assert";
+
+import {
+  ANNOTATION_MAX_CHAR_LENGTH,
+  ANNOTATION_RATE_LIMIT,
+  ANNOTATION_RATE_WINDOW_MS,
+  takeQuota,
+  validAnnotation,
+} from "./annotate";
+
+const note = { file: "main.rs", line: 3, message: "this can panic" };
+assert.ok(validAnnotation(note));
+assert.ok(!validAnnotation({ ...note, line: 0 }));
+assert.ok(!validAnnotation({ ...note, line: 1.5 }));
+assert.ok(!validAnnotation({ ...note, file: "" }));
+assert.ok(
+  !validAnnotation({
+    ...note,
+    message: "a".repeat(ANNOTATION_MAX_CHAR_LENGTH + 1),
+  })
+);
+assert.ok(!validAnnotation({ ...note, message: "fine\u001b[2Jgone" }));
+assert.ok(!validAnnotation({ ...note, file: "main.rs\r" }));
+
+let quota = takeQuota(undefined, 1000);
+assert.deepStrictEqual(quota, { windowStart: 1000, count: 1 });
+for (let i = 1; i < ANNOTATION_RATE_LIMIT; i++) {
+  quota = takeQuota(quota, 2000);
+}
+assert.deepStrictEqual(quota, {
+  windowStart: 1000,
+  count: ANNOTATION_RATE_LIMIT,
+});
+assert.strictEqual(takeQuota(quota, 3000), undefined);
+// A new window starts over
+assert.deepStrictEqual(takeQuota(quota, 1000 + ANNOTATION_RATE_WINDOW_MS), {
+  windowStart: 1000 + ANNOTATION_RATE_WINDOW_MS,
+  count: 1,
+});
@@ -0,0 +1,53 @@
+// Longest note on a line
+export const ANNOTATION_MAX_CHAR_LENGTH = 500;
+
+// Longest file name an annotation can point at
+export const ANNOTATION_FILE_MAX_CHAR_LENGTH = 255;
+
+// Annotations a gistit takes, newer ones are refused
+export const ANNOTATION_MAX_PER_GISTIT = 200;
+
+// Annotations a GitHub user can leave in a window
+export const ANNOTATION_RATE_LIMIT = 30;
+export const ANNOTATION_RATE_WINDOW_MS = 60 * 60 * 1000;
+
+// What's checked of an annotation, the author is whoever the token belongs to
+export type AnnotationFields = {
+  file?: string;
+  line?: number;
+  message?: string;
+};
+
+// Kept per GitHub user
+export type AnnotationQuota = {
+  windowStart: number;
+  count: number;
+};
+
+// eslint-disable-next-line no-control-regex
+const CONTROL_CHARS = /[\u0000-\u001f\u007f-\u009f]/;
+
+// Notes show in terminals, control characters could rewrite what's around them
+export const validAnnotation = ({ file, line, message }: AnnotationFields) =>
+  !!file &&
+  file.length <= ANNOTATION_FILE_MAX_CHAR_LENGTH &&
+  !CONTROL_CHARS.test(file) &&
+  Number.isInteger(line) &&
+  (line as number) > 0 &&
+  !!message &&
+  message.length <= ANNOTATION_MAX_CHAR_LENGTH &&
+  !CONTROL_CHARS.test(message);
+
+// The quota after one more annotation at `now`, undefined if it's used up
+export const takeQuota = (
+  quota: AnnotationQuota | undefined,
+  now: number
+): AnnotationQuota | undefined => {
+  if (!quota || now - quota.windowStart >= ANNOTATION_RATE_WINDOW_MS) {
+    return { windowStart: now, count: 1 };
+  }
+  if (quota.count >= ANNOTATION_RATE_LIMIT) {
+    return undefined;
+  }
+  return { ...quota, count: quota.count + 1 };
+};
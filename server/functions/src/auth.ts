@@ -26,6 +26,50 @@ export const token = functions.https.onRequest(async (req, res) => {
   }
 });
 
+interface RevokePayload {
+  access_token: string;
+}
+
+export const revoke = functions.https.onRequest(async (req, res) => {
+  try {
+    const { access_token: accessToken } = req.body as RevokePayload;
+
+    if (!accessToken) {
+      res.status(400).send({ error: "unexpected request" });
+      return;
+    }
+
+    const clientSecret = functions.config().github.secret;
+    const clientId = functions.config().github.id;
+    const credentials = Buffer.from(`${clientId}:${clientSecret}`).toString(
+      "base64"
+    );
+
+    const response = await fetch(
+      `https://api.github.com/applications/${clientId}/token`,
+      {
+        method: "DELETE",
+        headers: {
+          "Content-Type": "application/json",
+          Accept: "application/vnd.github.v3+json",
+          Authorization: `Basic ${credentials}`,
+        },
+        body: JSON.stringify({ access_token: accessToken }),
+      }
+    );
+
+    // 404 means the token was already revoked or expired
+    if (response.status === 204 || response.status === 404) {
+      res.status(200).send({ success: "revoked" });
+      return;
+    }
+
+    res.status(502).send({ error: "github refused to revoke" });
+  } catch (err) {
+    res.status(500).end();
+  }
+});
+
 export const auth = functions.https.onRequest(async (req, res) => {
   res
     .setHeader("Access-Control-Allow-Origin", "https://gistit.vercel.app")
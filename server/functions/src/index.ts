@@ -1,13 +1,22 @@
 import * as functions from "firebase-functions";
 import * as admin from "firebase-admin";
 import protobuf from "protobufjs";
+import * as zlib from "zlib";
+import fetch from "cross-fetch";
 
-export { auth, token, tokenScheduledCleanup } from "./auth";
+export { auth, revoke, token, tokenScheduledCleanup } from "./auth";
 export {
   createReservedData,
   updateReservedData,
   gistitScheduledCleanup,
 } from "./reserved";
+import { resolvableHashes } from "./resolve";
+import {
+  ANNOTATION_MAX_PER_GISTIT,
+  AnnotationQuota,
+  takeQuota,
+  validAnnotation,
+} from "./annotate";
 
 admin.initializeApp();
 
@@ -23,38 +32,106 @@ const GISTIT_DESCRIPTION_MIN_CHAR_LENGTH = 10;
 
 const GISTIT_FILE_MAX_SIZE = 50_000_000; // 50kb
 const GISTIT_FILE_MIN_SIZE = 20; // 20 bytes
+const GISTIT_TOTAL_MAX_SIZE = 100_000; // 100kb, a file and its description file
+
+const GISTIT_LIST_MAX_PAGE_SIZE = 50;
+
+const GISTIT_HASH_PREFIX_MIN_LENGTH = 4;
+const GISTIT_RESOLVE_MAX_HASHES = 20;
+
+const PROTOBUF_CONTENT_TYPE = "application/x-protobuf";
+const JSON_CONTENT_TYPE = "application/json";
+
+// Smaller bodies aren't worth compressing
+const COMPRESS_MIN_SIZE = 1024;
+
+// Payloads are protobuf unless sent as JSON (`gistit --wire-format json`), with
+// the protobuf field names in lower camel case
+const decodePayload = (
+  req: functions.https.Request,
+  Gistit: protobuf.Type,
+  body: Buffer = req.body
+) =>
+  req.is(JSON_CONTENT_TYPE) ? Gistit.fromObject(req.body) : Gistit.decode(body);
+
+// Answers in whichever format the client accepts, protobuf if it doesn't say.
+// Deflated if the client takes it, zstd isn't in node's zlib yet
+const sendPayload = (
+  req: functions.https.Request,
+  res: functions.Response,
+  Gistit: protobuf.Type,
+  payload: Record<string, unknown>
+) => {
+  const message = Gistit.fromObject(payload);
+  const accepted = req.accepts([PROTOBUF_CONTENT_TYPE, JSON_CONTENT_TYPE]);
+  const [type, body] =
+    accepted === JSON_CONTENT_TYPE
+      ? [
+          JSON_CONTENT_TYPE,
+          Buffer.from(
+            JSON.stringify(Gistit.toObject(message, { defaults: true }))
+          ),
+        ]
+      : [PROTOBUF_CONTENT_TYPE, Buffer.from(Gistit.encode(message).finish())];
+
+  res.status(200).type(type).setHeader("Vary", "Accept, Accept-Encoding");
+  if (body.length >= COMPRESS_MIN_SIZE && req.acceptsEncodings("deflate")) {
+    res.setHeader("Content-Encoding", "deflate").send(zlib.deflateSync(body));
+  } else {
+    res.send(body);
+  }
+};
 
 export type GistitPayload = {
   hash: string;
   author: string;
   description: string;
   timestamp: string;
+  public: boolean;
+  private?: boolean;
+  burnAfterRead?: boolean;
   inner: {
     name: string;
     lang: string;
     data: string;
     size: number;
+    hash?: string;
+    isBinary?: boolean;
+    isDescription?: boolean;
+  }[];
+  languages?: {
+    lang: string;
+    bytes: number;
   }[];
 };
 
 export const load = functions.https.onRequest(async (req, res) => {
   const proto = await protobuf.load("payload.proto");
   const Gistit = proto.lookupType("gistit.payload.Gistit");
-  const payload = Gistit.decode(req.body);
 
   try {
+    const payload = decodePayload(req, Gistit);
     const {
       hash,
       author,
       description,
       timestamp,
-      inner: [{ name, lang, size, data }],
+      public: isPublic,
+      private: isPrivate,
+      burnAfterRead,
+      inner: [{ name, lang, size, data, hash: innerHash, isBinary }, ...rest],
+      languages,
     } = payload as unknown as GistitPayload;
+    // A markdown description sent along (`--description-file`), kept after the file
+    const descriptionFile = rest.find((inner) => inner.isDescription);
     functions.logger.log(payload);
 
     if (hash?.length !== GISTIT_HASH_LENGTH)
       throw Error("Invalid gistit hash format");
 
+    if (isPublic && isPrivate)
+      throw Error("A gistit can't be both public and private");
+
     if (
       author &&
       (author.length > GISTIT_AUTHOR_MAX_CHAR_LENGTH ||
@@ -78,6 +155,21 @@ export const load = functions.https.onRequest(async (req, res) => {
       throw Error("File size is not allowed");
     }
 
+    if (
+      descriptionFile &&
+      (descriptionFile.data.length > GISTIT_FILE_MAX_SIZE ||
+        descriptionFile.data.length < GISTIT_FILE_MIN_SIZE)
+    ) {
+      throw Error("Description file size is not allowed");
+    }
+
+    // Binary files count decoded, as the client measures them
+    const totalSize =
+      (isBinary ? size : data.length) + (descriptionFile?.data.length ?? 0);
+    if (totalSize > GISTIT_TOTAL_MAX_SIZE) {
+      throw Error("Files are too large together");
+    }
+
     await db
       .collection("gistits")
       .doc(hash)
@@ -85,19 +177,44 @@ export const load = functions.https.onRequest(async (req, res) => {
         author,
         description,
         timestamp: timestamp.toString(),
-        inner: [{ name, lang, data, size }],
+        public: (isPublic ?? false) && !burnAfterRead,
+        private: isPrivate ?? false,
+        burnAfterRead: burnAfterRead ?? false,
+        inner: [
+          {
+            name,
+            lang,
+            data,
+            size,
+            hash: innerHash ?? "",
+            isBinary: isBinary ?? false,
+          },
+          ...(descriptionFile
+            ? [
+                {
+                  name: descriptionFile.name,
+                  lang: descriptionFile.lang,
+                  data: descriptionFile.data,
+                  size: descriptionFile.size,
+                  hash: descriptionFile.hash ?? "",
+                  isBinary: false,
+                  isDescription: true,
+                },
+              ]
+            : []),
+        ],
+        languages: languages ?? [],
       });
 
     functions.logger.info("added gistit: ", hash);
-    const response = Gistit.encode({
+    sendPayload(req, res, Gistit, {
       hash,
       author,
       description,
       timestamp,
       inner: [{ name, lang, data: "", size }],
-    }).finish();
-
-    res.send(response);
+      languages,
+    });
   } catch (err) {
     functions.logger.error(err);
     res.status(400).end();
@@ -126,9 +243,9 @@ export const get = functions.https.onRequest(async (req, res) => {
   } else {
     data = req.body;
   }
-  const payload = Gistit.decode(data);
 
   try {
+    const payload = decodePayload(req, Gistit, data);
     const { hash } = payload as unknown as GistitPayload;
 
     functions.logger.debug(hash);
@@ -136,18 +253,445 @@ export const get = functions.https.onRequest(async (req, res) => {
     if (hash?.length !== GISTIT_HASH_LENGTH)
       throw Error("Invalid gistit hash format");
 
-    const gistitRef = await db.collection("gistits").doc(hash).get();
+    const docRef = db.collection("gistits").doc(hash);
+    // Read and burn in one go, so a burn after read gistit is served once
+    const gistit = await db.runTransaction(async (transaction) => {
+      const gistitRef = await transaction.get(docRef);
+      const data = gistitRef.data();
+      if (data?.burnAfterRead) {
+        transaction.delete(docRef);
+      }
+      return data;
+    });
 
-    if (!gistitRef.exists) {
+    if (!gistit) {
       res.status(404).end();
       return;
     }
 
-    const gistit = gistitRef.data();
     console.log(gistit);
-    const response = Gistit.encode({ ...gistit, hash }).finish();
-    res.status(200).send(response);
+    sendPayload(req, res, Gistit, { ...gistit, hash });
+  } catch (err) {
+    res.status(400).end();
+  }
+});
+
+// Like `get` but without file contents, and never burns. Tells whether a gistit exists
+export const head = functions.https.onRequest(async (req, res) => {
+  const proto = await protobuf.load("payload.proto");
+  const Gistit = proto.lookupType("gistit.payload.Gistit");
+
+  try {
+    const payload = decodePayload(req, Gistit);
+    const { hash } = payload as unknown as GistitPayload;
+
+    if (hash?.length !== GISTIT_HASH_LENGTH)
+      throw Error("Invalid gistit hash format");
+
+    const gistitRef = await db.collection("gistits").doc(hash).get();
+    const gistit = gistitRef.data() as GistitPayload | undefined;
+
+    if (!gistit) {
+      res.status(404).end();
+      return;
+    }
+
+    sendPayload(req, res, Gistit, {
+      ...gistit,
+      hash,
+      inner: gistit.inner.map((inner) => ({ ...inner, data: "" })),
+    });
+  } catch (err) {
+    res.status(400).end();
+  }
+});
+
+export type AnnotationPayload = {
+  hash: string;
+  file: string;
+  line: number;
+  message: string;
+  author: string;
+  timestamp: string;
+};
+
+// Annotations live under their gistit, oldest first
+const annotationsOf = async (hash: string) => {
+  const snapshot = await db
+    .collection("gistits")
+    .doc(hash)
+    .collection("annotations")
+    .orderBy("timestamp")
+    .get();
+
+  return snapshot.docs.map((doc) => ({
+    ...(doc.data() as AnnotationPayload),
+    hash,
+  }));
+};
+
+// The GitHub user the request's token (`Authorization: token <token>`) belongs
+// to, undefined without a valid one
+const githubLogin = async (
+  req: functions.https.Request
+): Promise<string | undefined> => {
+  const authorization = req.get("authorization");
+  if (!authorization?.startsWith("token ")) return undefined;
+
+  const response = await fetch("https://api.github.com/user", {
+    headers: { Authorization: authorization, "User-Agent": "gistit" },
+  });
+  if (!response.ok) return undefined;
+  const { login } = (await response.json()) as { login?: string };
+  return login;
+};
+
+// Leaves a note on a line of a gistit (`gistit annotate`), answers with every
+// annotation of the gistit. Needs a GitHub token, the note is signed with its
+// user whatever author it claims. Users leave a limited number of notes an
+// hour and gistits take a limited number of them
+export const annotate = functions.https.onRequest(async (req, res) => {
+  const proto = await protobuf.load("payload.proto");
+  const Annotation = proto.lookupType("gistit.payload.Annotation");
+  const Annotations = proto.lookupType("gistit.payload.Annotations");
+
+  try {
+    const payload = decodePayload(req, Annotation);
+    const { hash, file, line, message, timestamp } =
+      payload as unknown as AnnotationPayload;
+
+    if (hash?.length !== GISTIT_HASH_LENGTH)
+      throw Error("Invalid gistit hash format");
+
+    if (!validAnnotation({ file, line, message }))
+      throw Error("Invalid annotation");
+
+    const author = await githubLogin(req);
+    if (!author) {
+      res.status(401).end();
+      return;
+    }
+
+    const gistitRef = db.collection("gistits").doc(hash);
+    const quotaRef = db.collection("annotationQuota").doc(author);
+    const status = await db.runTransaction(async (transaction) => {
+      const gistit = await transaction.get(gistitRef);
+      if (!gistit.exists) return 404;
+      if ((gistit.data()?.annotationCount ?? 0) >= ANNOTATION_MAX_PER_GISTIT)
+        return 403;
+
+      const quota = takeQuota(
+        (await transaction.get(quotaRef)).data() as AnnotationQuota | undefined,
+        Date.now()
+      );
+      if (!quota) return 429;
+
+      transaction.set(quotaRef, quota);
+      transaction.update(gistitRef, {
+        annotationCount: admin.firestore.FieldValue.increment(1),
+      });
+      transaction.create(gistitRef.collection("annotations").doc(), {
+        file,
+        line,
+        message,
+        author,
+        timestamp: timestamp.toString(),
+      });
+      return 200;
+    });
+    if (status !== 200) {
+      res.status(status).end();
+      return;
+    }
+
+    functions.logger.info("annotated gistit: ", hash);
+    sendPayload(req, res, Annotations, {
+      annotations: await annotationsOf(hash),
+    });
   } catch (err) {
+    functions.logger.error(err);
+    res.status(400).end();
+  }
+});
+
+// Every annotation of a gistit, asked for with an annotation carrying only the hash
+export const annotations = functions.https.onRequest(async (req, res) => {
+  const proto = await protobuf.load("payload.proto");
+  const Annotation = proto.lookupType("gistit.payload.Annotation");
+  const Annotations = proto.lookupType("gistit.payload.Annotations");
+
+  try {
+    const payload = decodePayload(req, Annotation);
+    const { hash } = payload as unknown as AnnotationPayload;
+
+    if (hash?.length !== GISTIT_HASH_LENGTH)
+      throw Error("Invalid gistit hash format");
+
+    if (!(await db.collection("gistits").doc(hash).get()).exists) {
+      res.status(404).end();
+      return;
+    }
+
+    sendPayload(req, res, Annotations, {
+      annotations: await annotationsOf(hash),
+    });
+  } catch (err) {
+    res.status(400).end();
+  }
+});
+
+type ResolveQuery = {
+  prefix?: string;
+};
+
+// Hashes starting with a prefix, like git short SHAs. Sorted and capped, more
+// than one tells the client the prefix is ambiguous. Private gistits never
+// match, see `resolvableHashes`
+export const resolve = functions.https.onRequest(async (req, res) => {
+  try {
+    const { prefix } = req.body as ResolveQuery;
+
+    if (
+      !prefix ||
+      prefix.length < GISTIT_HASH_PREFIX_MIN_LENGTH ||
+      prefix.length > GISTIT_HASH_LENGTH ||
+      !/^[0-9a-f]+$/i.test(prefix)
+    )
+      throw Error("Invalid gistit hash prefix format");
+
+    const start = prefix.toLowerCase();
+    // Hex digits sort before `g`, so this ends the range right after the last match
+    const end = `${start}g`;
+    const snapshot = await db
+      .collection("gistits")
+      .orderBy(admin.firestore.FieldPath.documentId())
+      .startAt(start)
+      .endBefore(end)
+      .limit(GISTIT_RESOLVE_MAX_HASHES)
+      .select("private")
+      .get();
+
+    res.status(200).send({
+      hashes: resolvableHashes(
+        snapshot.docs.map((doc) => ({
+          id: doc.id,
+          private: doc.get("private"),
+        }))
+      ),
+    });
+  } catch (err) {
+    functions.logger.error(err);
+    res.status(400).end();
+  }
+});
+
+type ListQuery = {
+  limit?: number;
+  cursor?: string;
+};
+
+type ListAuthorQuery = ListQuery & {
+  author?: string;
+};
+
+// One page of public gistits, newest first. `next` is the cursor of the
+// following page, null on the last one
+const listPage = async (
+  query: FirebaseFirestore.Query,
+  { limit, cursor }: ListQuery
+) => {
+  const pageSize = Math.min(
+    Math.max(limit ?? GISTIT_LIST_MAX_PAGE_SIZE, 1),
+    GISTIT_LIST_MAX_PAGE_SIZE
+  );
+
+  let page = query
+    .where("public", "==", true)
+    .orderBy("timestamp", "desc")
+    .limit(pageSize);
+
+  if (cursor) {
+    page = page.startAfter(cursor);
+  }
+
+  const snapshot = await page.get();
+  const gistits = snapshot.docs.map((doc) => {
+    const { author, description, timestamp, inner, languages } =
+      doc.data() as GistitPayload;
+    const [{ name, lang, size }] = inner;
+
+    return {
+      hash: doc.id,
+      author,
+      description,
+      timestamp,
+      name,
+      lang,
+      size,
+      languages: languages ?? [],
+    };
+  });
+
+  const last = gistits[gistits.length - 1];
+  const next = gistits.length === pageSize ? last.timestamp : null;
+
+  return { gistits, next };
+};
+
+export const list = functions.https.onRequest(async (req, res) => {
+  try {
+    const page = await listPage(
+      db.collection("gistits"),
+      req.body as ListQuery
+    );
+
+    res.status(200).send(page);
+  } catch (err) {
+    functions.logger.error(err);
+    res.status(400).end();
+  }
+});
+
+// Public gistits sent under an author name (`gistit fetch --author`)
+export const listAuthor = functions.https.onRequest(async (req, res) => {
+  try {
+    const query = req.body as ListAuthorQuery;
+    const { author } = query;
+
+    if (
+      !author ||
+      author.length > GISTIT_AUTHOR_MAX_CHAR_LENGTH ||
+      author.length < GISTIT_AUTHOR_MIN_CHAR_LENGTH
+    )
+      throw Error("Invalid author length");
+
+    const page = await listPage(
+      db.collection("gistits").where("author", "==", author),
+      query
+    );
+
+    res.status(200).send(page);
+  } catch (err) {
+    functions.logger.error(err);
+    res.status(400).end();
+  }
+});
+
+const GISTIT_SEARCH_MAX_QUERY_LENGTH = 100;
+// How many of the newest public gistits a search ranks, Firestore has no full
+// text search
+const GISTIT_SEARCH_SCAN_LIMIT = 500;
+const GISTIT_SEARCH_PREVIEW_LINES = 3;
+const GISTIT_SEARCH_PREVIEW_MAX_CHAR_LENGTH = 240;
+
+type SearchQuery = ListQuery & {
+  query?: string;
+  author?: string;
+  lang?: string;
+};
+
+// How well a gistit matches the search terms. Description words count the
+// most, then the author, the file name and the language
+const searchScore = (terms: string[], gistit: GistitPayload) => {
+  const [{ name, lang }] = gistit.inner;
+  const fields: [string, number][] = [
+    [gistit.description ?? "", 3],
+    [gistit.author, 2],
+    [name, 2],
+    [lang, 1],
+  ];
+
+  return terms.reduce((score, term) => {
+    const matched = fields
+      .filter(([field]) => field.toLowerCase().includes(term))
+      .reduce((sum, [, weight]) => sum + weight, 0);
+    // Every term has to match somewhere
+    return matched === 0 || score < 0 ? -1 : score + matched;
+  }, 0);
+};
+
+// The first lines of the main file, binary files have none
+const searchPreview = ({ inner }: GistitPayload) => {
+  const file = inner.find((inner) => !inner.isDescription) ?? inner[0];
+  if (file.isBinary) return "";
+
+  return file.data
+    .split("\n")
+    .slice(0, GISTIT_SEARCH_PREVIEW_LINES)
+    .join("\n")
+    .slice(0, GISTIT_SEARCH_PREVIEW_MAX_CHAR_LENGTH);
+};
+
+// Public gistits matching a query by description, author, file name or
+// language, best match first. The cursor is the offset of the following page
+export const search = functions.https.onRequest(async (req, res) => {
+  try {
+    const { query, author, lang, limit, cursor } = req.body as SearchQuery;
+
+    if (
+      typeof query !== "string" ||
+      query.trim().length === 0 ||
+      query.length > GISTIT_SEARCH_MAX_QUERY_LENGTH
+    )
+      throw Error("Invalid search query");
+
+    const pageSize = Math.min(
+      Math.max(limit ?? GISTIT_LIST_MAX_PAGE_SIZE, 1),
+      GISTIT_LIST_MAX_PAGE_SIZE
+    );
+    const offset = Math.max(parseInt(cursor ?? "0", 10) || 0, 0);
+    const terms = query.toLowerCase().split(/\s+/).filter(Boolean);
+
+    let scan = db.collection("gistits").where("public", "==", true);
+    if (author) {
+      scan = scan.where("author", "==", author);
+    }
+    const snapshot = await scan
+      .orderBy("timestamp", "desc")
+      .limit(GISTIT_SEARCH_SCAN_LIMIT)
+      .get();
+
+    const ranked = snapshot.docs
+      .map((doc) => {
+        const gistit = doc.data() as GistitPayload;
+        return { hash: doc.id, gistit, score: searchScore(terms, gistit) };
+      })
+      .filter(
+        ({ gistit, score }) =>
+          score > 0 &&
+          (!lang ||
+            gistit.inner.some(
+              (inner) => inner.lang.toLowerCase() === lang.toLowerCase()
+            ))
+      )
+      // Newest first among equal scores, the scan is already in that order
+      .sort((a, b) => b.score - a.score);
+
+    const results = ranked
+      .slice(offset, offset + pageSize)
+      .map(({ hash, gistit, score }) => {
+        const { author, description, timestamp, inner } = gistit;
+        const [{ name, lang, size }] = inner;
+
+        return {
+          hash,
+          author,
+          description,
+          timestamp,
+          name,
+          lang,
+          size,
+          score,
+          preview: searchPreview(gistit),
+        };
+      });
+
+    const next =
+      offset + pageSize < ranked.length ? String(offset + pageSize) : null;
+
+    res.status(200).send({ results, next, total: ranked.length });
+  } catch (err) {
+    functions.logger.error(err);
     res.status(400).end();
   }
 });
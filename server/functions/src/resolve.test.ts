@@ -0,0 +1,13 @@
+import * as assert from "assert";
+
+import { resolvableHashes } from "./resolve";
+
+assert.deepStrictEqual(
+  resolvableHashes([
+    { id: "abc1" },
+    { id: "abc2", private: true },
+    { id: "abc3", private: false },
+  ]),
+  ["abc1", "abc3"]
+);
+assert.deepStrictEqual(resolvableHashes([{ id: "abc2", private: true }]), []);
@@ -0,0 +1,10 @@
+// What `resolve` reads of a gistit matching the prefix
+export type ResolveCandidate = {
+  id: string;
+  private?: boolean;
+};
+
+// Private gistits are only fetched by their full hash, they never resolve from
+// a prefix
+export const resolvableHashes = (candidates: ResolveCandidate[]): string[] =>
+  candidates.filter((candidate) => !candidate.private).map(({ id }) => id);